@@ -0,0 +1,1825 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::Context;
+use candle_core::DType;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::core::fim::FimTemplate;
+use crate::core::guardrails::GuardrailSettings;
+use crate::core::prompts::PromptTemplate;
+use crate::core::quantize::Quantization;
+use crate::core::stop_tokens::StopToken;
+
+/// Runtime configuration of the server.
+///
+/// The configuration is read from the JSON file pointed to by the `SYNAP_CONFIG`
+/// environment variable. Every section is optional and falls back to its defaults,
+/// so the server keeps working without any configuration file at all.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub model: ModelSource,
+    /// Sampling defaults and limits by model id.
+    pub models: HashMap<String, ModelSettings>,
+    pub files: FileSettings,
+    pub storage: StorageSettings,
+    pub audio: AudioSettings,
+    pub guardrails: GuardrailSettings,
+    pub streaming: StreamingSettings,
+    pub queue: QueueSettings,
+    pub generations: GenerationJobSettings,
+    pub webhooks: WebhookSettings,
+    pub prefix_cache: PrefixCacheSettings,
+    pub circuit_breaker: CircuitBreakerSettings,
+    pub retries: RetrySettings,
+    pub workers: WorkerSettings,
+    pub gateway: GatewaySettings,
+    pub placement: PlacementSettings,
+    pub hub: HubSettings,
+    pub admin: AdminSettings,
+    pub azure: AzureSettings,
+    pub conversations: ConversationSettings,
+    pub assistants: AssistantSettings,
+    pub embeddings: EmbeddingSettings,
+    pub rag: RagSettings,
+    pub vector_stores: VectorStoreSettings,
+    pub tools: ToolSettings,
+    pub mcp: McpSettings,
+    pub agents: AgentSettings,
+    pub limits: LimitSettings,
+    pub model_access: ModelAccessSettings,
+    pub access_log: AccessLogSettings,
+    pub audit: AuditSettings,
+    pub network: NetworkSettings,
+    /// Named system prompt presets, referenced by requests through `prompt_template`.
+    pub prompts: HashMap<String, PromptTemplate>,
+    /// The fill-in-the-middle tokens of the served model, detected from the tokenizer when unset.
+    pub fim: Option<FimTemplate>,
+    /// The tracing filter of the logs, e.g. `synap_forge_llm=debug`. `RUST_LOG` takes
+    /// precedence at startup.
+    pub log_level: Option<String>,
+    /// Whether the request bodies may hold fields the server does not know.
+    pub request_parsing: RequestParsing,
+    /// Other names of the served models, e.g. `gpt-4o-mini`, by alias, so that
+    /// applications requesting OpenAI models by name are served without code changes.
+    pub model_aliases: HashMap<String, String>,
+}
+
+/// The sections of the configuration applied again when it is reloaded, the others are
+/// only read at startup.
+pub const RELOADABLE_SECTIONS: &[&str] = &[
+    "models",
+    "limits",
+    "model_access",
+    "tools",
+    "admin",
+    "prompts",
+    "log_level",
+    "request_parsing",
+    "model_aliases",
+];
+
+impl ServerConfig {
+    /// Loads the configuration from the file referenced by `SYNAP_CONFIG`.
+    ///
+    /// # Returns
+    ///
+    /// The parsed configuration, or the default configuration when the
+    /// environment variable is not set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or is not valid JSON.
+    pub fn load() -> anyhow::Result<Self> {
+        match std::env::var("SYNAP_CONFIG") {
+            Ok(path) => Self::from_file(path),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Returns the sampling settings of a model, or the built-in defaults if it has none.
+    ///
+    /// The defaults the served model publishes in `generation_config.json` fill in the
+    /// values its settings leave unset.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_id` - The id of the model.
+    pub fn model_settings(&self, model_id: &str) -> ModelSettings {
+        let mut settings = self.models.get(model_id).cloned().unwrap_or_default();
+        if model_id == self.model.id {
+            let published = &self.model.published_defaults;
+            let defaults = &mut settings.defaults;
+            defaults.temperature = defaults.temperature.or(published.temperature);
+            defaults.top_p = defaults.top_p.or(published.top_p);
+            defaults.top_k = defaults.top_k.or(published.top_k);
+        }
+        settings
+    }
+
+    /// Resolves the model a request names, which may be an alias.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The `model` of the request.
+    ///
+    /// # Returns
+    ///
+    /// The id of the aliased model, or `model` itself when it is not an alias.
+    pub fn resolve_model<'a>(&'a self, model: &'a str) -> &'a str {
+        self.model_aliases.get(model).map_or(model, String::as_str)
+    }
+
+    /// Reads the configuration from a JSON file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the JSON configuration file.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Error opening config file {}", path.display()))?;
+
+        serde_json::from_reader(file)
+            .with_context(|| format!("Error parsing config file {}", path.display()))
+    }
+
+    /// Reads the configuration file again and applies its runtime-tunable sections.
+    ///
+    /// The sampling defaults and limits, the request limits and the models by API key,
+    /// the server tools, the admin key, the prompt presets, the log level, the request parsing mode and the model
+    /// aliases are taken from the file. The other sections,
+    /// such as the served model or the storage directories, set up the server at startup
+    /// and are kept as they are, as are `limits.max_body_bytes` and the stop tokens, which
+    /// were resolved against the tokenizer at startup.
+    ///
+    /// # Returns
+    ///
+    /// A copy of this configuration with the reloaded sections.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `SYNAP_CONFIG` is not set, or the file cannot be opened or is
+    /// not valid JSON.
+    pub fn reload(&self) -> anyhow::Result<Self> {
+        let path = std::env::var("SYNAP_CONFIG")
+            .context("SYNAP_CONFIG is not set, there is no configuration file to reload")?;
+        let file = Self::from_file(path)?;
+
+        Ok(Self {
+            models: file.models,
+            limits: LimitSettings {
+                max_body_bytes: self.limits.max_body_bytes,
+                ..file.limits
+            },
+            model_access: file.model_access,
+            tools: file.tools,
+            admin: file.admin,
+            prompts: file.prompts,
+            log_level: file.log_level.or_else(|| self.log_level.clone()),
+            request_parsing: file.request_parsing,
+            model_aliases: file.model_aliases,
+            ..self.clone()
+        })
+    }
+}
+
+/// The configuration of the running server, which can be replaced while requests are served.
+///
+/// Requests take a snapshot of the configuration when they need it, so a reload never
+/// changes the settings in the middle of a request.
+#[derive(Debug)]
+pub struct SettingsHandle {
+    current: RwLock<Arc<ServerConfig>>,
+}
+
+impl SettingsHandle {
+    /// Wraps the configuration the server started with.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The configuration at startup.
+    pub fn new(settings: ServerConfig) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(settings)),
+        }
+    }
+
+    /// The current configuration.
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Replaces the configuration for the requests received from now on.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The new configuration.
+    ///
+    /// # Returns
+    ///
+    /// The new configuration.
+    pub fn replace(&self, settings: ServerConfig) -> Arc<ServerConfig> {
+        let settings = Arc::new(settings);
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = settings.clone();
+        settings
+    }
+}
+
+/// The Hugging Face Hub model served by the server.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ModelSource {
+    /// The Hub id of the model.
+    pub id: String,
+    /// The commit, branch or tag of the model repository to load. Branches and tags are
+    /// resolved to their commit when the model is loaded.
+    pub revision: String,
+    /// Refuses to start unless `revision` is a full commit SHA, so that a server never
+    /// loads weights pushed to a branch after they were reviewed.
+    pub require_pinned_revision: bool,
+    /// A local directory holding `config.json`, `tokenizer.json` and the SafeTensors
+    /// weights, loaded instead of downloading `id` from the Hub, which is then only the
+    /// name the model is served under.
+    pub path: Option<PathBuf>,
+    /// An `s3://bucket/path` or `gs://bucket/path` folder laid out like a Hub repository,
+    /// downloaded to the local cache and loaded instead of `id`, overridden by
+    /// `--model-uri`.
+    pub uri: Option<String>,
+    /// How the checkpoint of `uri` is downloaded.
+    pub object_store: ObjectStoreSettings,
+    /// A bundle written by `bundle create`, verified and unpacked to the local cache and
+    /// loaded instead of `id`, overridden by `--bundle`. A quantized bundle is served
+    /// with the quantization it was created with.
+    pub bundle: Option<PathBuf>,
+    /// How the digests of the weights are checked before they are loaded.
+    pub verification: VerificationSettings,
+    /// Unloads the weights after this many minutes without requests; they are
+    /// reloaded from the local cache by the next request.
+    pub idle_unload_minutes: Option<u64>,
+    /// Quantizes full-precision weights at load time to lower the memory footprint.
+    pub quantize: Option<Quantization>,
+    /// The data type full-precision weights are loaded in.
+    pub dtype: WeightDType,
+    /// The number of weight shards read in parallel before loading, `0` to disable.
+    pub prefetch_threads: usize,
+    /// Checks the model repository for a new commit on `update_branch` every this
+    /// many minutes, and swaps in the new weights once they are loaded.
+    pub update_check_minutes: Option<u64>,
+    /// The branch followed by the update checks.
+    pub update_branch: String,
+    /// The sampling defaults published with the model in `generation_config.json`, read
+    /// when it is loaded. The `defaults` of `models` take precedence over them.
+    #[serde(skip)]
+    pub published_defaults: PublishedDefaults,
+}
+
+/// The sampling defaults the authors of a model publish in `generation_config.json`.
+#[derive(Clone, Debug, Default)]
+pub struct PublishedDefaults {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+}
+
+/// The data type of full-precision weights.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WeightDType {
+    #[default]
+    F32,
+    F16,
+    BF16,
+}
+
+impl From<WeightDType> for DType {
+    fn from(dtype: WeightDType) -> Self {
+        match dtype {
+            WeightDType::F32 => DType::F32,
+            WeightDType::F16 => DType::F16,
+            WeightDType::BF16 => DType::BF16,
+        }
+    }
+}
+
+impl Default for ModelSource {
+    fn default() -> Self {
+        Self {
+            // "meta-llama/Llama-3.2-3B-Instruct"
+            // "45026b798cd537efe6a1abcb93040ad21d416c43"
+            id: "meta-llama/Llama-3.1-8B-Instruct".to_string(),
+            revision: "0e9e39f249a16976918f6564b8830bc894c89659".to_string(),
+            require_pinned_revision: false,
+            path: None,
+            uri: None,
+            object_store: ObjectStoreSettings::default(),
+            bundle: None,
+            verification: VerificationSettings::default(),
+            idle_unload_minutes: None,
+            quantize: None,
+            dtype: WeightDType::default(),
+            prefetch_threads: 4,
+            update_check_minutes: None,
+            update_branch: "main".to_string(),
+            published_defaults: PublishedDefaults::default(),
+        }
+    }
+}
+
+/// How the weights are checked against their expected SHA-256 digests before they are
+/// loaded. Weights whose digest differs are never served.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct VerificationSettings {
+    /// Checks the weights of Hub models against the digests the Hub lists for the
+    /// revision.
+    pub hub_checksums: bool,
+    /// A JSON manifest mapping the names of the weight files to their hex digests, e.g.
+    /// `{"files": {"model.safetensors": "9f86d0…"}}`, which must list every weight file.
+    pub manifest: Option<PathBuf>,
+    /// The base64 Ed25519 public key the manifest is signed with. When set, the base64
+    /// signature of the manifest is read from `manifest_signature` and must be valid.
+    pub public_key: Option<String>,
+    /// The signature of the manifest, `<manifest>.sig` by default.
+    pub manifest_signature: Option<PathBuf>,
+    /// Refuses to load weights that cannot be checked against any digest, e.g. when the
+    /// Hub cannot be reached, instead of serving them as unverified.
+    pub require: bool,
+}
+
+impl Default for VerificationSettings {
+    fn default() -> Self {
+        Self {
+            hub_checksums: true,
+            manifest: None,
+            public_key: None,
+            manifest_signature: None,
+            require: false,
+        }
+    }
+}
+
+/// How a checkpoint is downloaded from an S3 or GCS bucket.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ObjectStoreSettings {
+    /// The URL of the object storage, by default AWS S3 in `region` for `s3://` URIs and
+    /// the interoperability API of Google Cloud Storage for `gs://` URIs. Set it for
+    /// S3-compatible storages such as MinIO.
+    pub endpoint: Option<String>,
+    pub region: String,
+    /// The credentials, read from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` when
+    /// unset. Google Cloud Storage takes HMAC keys.
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// The number of byte ranges of a file downloaded at once.
+    pub download_threads: usize,
+    /// The size of the byte ranges, in megabytes.
+    pub chunk_mb: u64,
+    /// How long in seconds to wait for a request to the object storage.
+    pub timeout_secs: u64,
+}
+
+impl Default for ObjectStoreSettings {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            region: "us-east-1".to_string(),
+            access_key_id: None,
+            secret_access_key: None,
+            download_threads: 8,
+            chunk_mb: 64,
+            timeout_secs: 300,
+        }
+    }
+}
+
+/// Sampling defaults and limits of a model.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ModelSettings {
+    /// Values used when the request omits a parameter.
+    pub defaults: SamplingDefaults,
+    /// Ceilings clamping the values provided by clients.
+    pub limits: SamplingLimits,
+    /// Tokens ending the generation besides the end of sequence tokens of `config.json`,
+    /// as ids or vocabulary entries. When unset, the end of sequence tokens published in
+    /// `generation_config.json` and `tokenizer_config.json` are used, or else the end of
+    /// turn tokens of the known model families found in the vocabulary.
+    pub stop_tokens: Option<Vec<StopToken>>,
+    /// How the vectors of an embedding model are computed.
+    pub embedding: EmbeddingOutput,
+    /// How the reasoning trace of a thinking model is returned.
+    pub reasoning: ReasoningSettings,
+    /// When generations stuck repeating themselves are stopped.
+    pub degeneration: DegenerationSettings,
+}
+
+/// The guard stopping a generation that collapsed into a confident loop, ending it with
+/// the `repetition` finish reason.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DegenerationSettings {
+    pub enabled: bool,
+    /// The number of last generated tokens inspected.
+    pub window: usize,
+    /// The length of the longest cycle of tokens that counts as a repetition.
+    pub max_period: usize,
+    /// The mean entropy of the next-token distributions over the window, in nats, below
+    /// which the model is considered stuck.
+    pub max_entropy: f32,
+}
+
+impl Default for DegenerationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window: 64,
+            max_period: 16,
+            max_entropy: 0.5,
+        }
+    }
+}
+
+/// The tags around the reasoning trace of a thinking model, and how it is returned.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ReasoningSettings {
+    pub mode: ReasoningMode,
+    pub start: String,
+    pub end: String,
+}
+
+impl Default for ReasoningSettings {
+    fn default() -> Self {
+        Self {
+            mode: ReasoningMode::Separate,
+            start: "<think>".to_string(),
+            end: "</think>".to_string(),
+        }
+    }
+}
+
+/// How the reasoning trace of a chat completion is returned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningMode {
+    /// In the `reasoning_content` of the message, out of its content.
+    #[default]
+    Separate,
+    /// Not at all.
+    Strip,
+    /// In the content, as generated.
+    Keep,
+}
+
+/// How an embedding model turns the states of the tokens of a text into its vector.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingOutput {
+    pub pooling: Pooling,
+    /// Whether the vectors are normalized to unit length, so that their dot product is
+    /// their cosine similarity.
+    pub normalize: bool,
+}
+
+impl Default for EmbeddingOutput {
+    fn default() -> Self {
+        Self {
+            pooling: Pooling::Mean,
+            normalize: true,
+        }
+    }
+}
+
+/// Which token states make the vector of a text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pooling {
+    /// The state of the first token, `[CLS]` for BERT models.
+    Cls,
+    /// The mean of the states of all the tokens.
+    #[default]
+    Mean,
+    /// The state of the last token, for decoder-based embedding models.
+    LastToken,
+}
+
+impl Pooling {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cls => "cls",
+            Self::Mean => "mean",
+            Self::LastToken => "last_token",
+        }
+    }
+}
+
+impl ModelSettings {
+    /// Resolves the sampling temperature of a request.
+    pub fn temperature(&self, requested: Option<f64>) -> Option<f64> {
+        clamp_max(
+            requested.or(self.defaults.temperature),
+            self.limits.max_temperature,
+        )
+    }
+
+    /// Resolves the nucleus sampling probability of a request.
+    pub fn top_p(&self, requested: Option<f64>) -> Option<f64> {
+        clamp_max(requested.or(self.defaults.top_p), self.limits.max_top_p)
+    }
+
+    /// Resolves the top-k sampling value of a request.
+    pub fn top_k(&self, requested: Option<usize>) -> Option<usize> {
+        clamp_max(requested.or(self.defaults.top_k), self.limits.max_top_k)
+    }
+
+    /// Resolves the repeat penalty of a request.
+    pub fn repeat_penalty(&self, requested: Option<f32>) -> f32 {
+        let requested = requested.unwrap_or(self.defaults.repeat_penalty);
+        clamp_max(Some(requested), self.limits.max_repeat_penalty).unwrap_or(requested)
+    }
+
+    /// Resolves the maximum number of tokens to generate for a request.
+    pub fn max_tokens(&self, requested: Option<i32>) -> usize {
+        let requested = requested.map_or(self.defaults.max_tokens, |t| t.max(0) as usize);
+        clamp_max(Some(requested), self.limits.max_tokens).unwrap_or(requested)
+    }
+}
+
+fn clamp_max<T: PartialOrd>(value: Option<T>, max: Option<T>) -> Option<T> {
+    match (value, max) {
+        (Some(value), Some(max)) if value > max => Some(max),
+        (value, _) => value,
+    }
+}
+
+/// Sampling values applied when a request omits them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SamplingDefaults {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    /// The penalty dividing the logits of the tokens of the last `repeat_last_n` tokens,
+    /// `1` disables it unless a request sets `repetition_penalty`.
+    pub repeat_penalty: f32,
+    /// The number of last tokens considered by the repeat penalty, `0` disables it.
+    pub repeat_last_n: usize,
+    pub max_tokens: usize,
+    /// The seed of the sampling RNG.
+    pub seed: u64,
+}
+
+impl Default for SamplingDefaults {
+    fn default() -> Self {
+        Self {
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+            max_tokens: 64,
+            seed: 299792458,
+        }
+    }
+}
+
+/// Hard ceilings on the sampling values of a request.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SamplingLimits {
+    pub max_temperature: Option<f64>,
+    pub max_top_p: Option<f64>,
+    pub max_top_k: Option<usize>,
+    pub max_repeat_penalty: Option<f32>,
+    pub max_tokens: Option<usize>,
+}
+
+/// Settings of streamed (`stream: true`) responses.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct StreamingSettings {
+    /// Interval in seconds of the keep-alive comments sent while no token is produced.
+    pub keep_alive_secs: u64,
+    /// How long in seconds a finished stream can still be resumed with `Last-Event-ID`.
+    pub resume_window_secs: u64,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        Self {
+            keep_alive_secs: 15,
+            resume_window_secs: 60,
+        }
+    }
+}
+
+/// Settings of the queue of the generations.
+///
+/// By default every request generates as soon as it arrives. With `max_running` set,
+/// at most that many generations run at once and the others wait in the order they
+/// arrived, which keeps the latency of the running ones predictable under load.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct QueueSettings {
+    /// The number of generations running at once, `0` for no limit.
+    pub max_running: usize,
+    /// Whether streamed responses of queued requests are sent right away, with their
+    /// position and estimated wait in headers and SSE comments, instead of once their
+    /// generation starts.
+    pub report_position: bool,
+}
+
+/// Settings of the asynchronous generations of `/v1/generations`, for clients that
+/// cannot hold a connection open for a whole generation.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct GenerationJobSettings {
+    /// How long in seconds an ended generation can still be read.
+    pub retention_secs: u64,
+    /// The longest a poll may wait for a generation to end, in seconds.
+    pub max_wait_secs: u64,
+}
+
+impl Default for GenerationJobSettings {
+    fn default() -> Self {
+        Self {
+            retention_secs: 3600,
+            max_wait_secs: 60,
+        }
+    }
+}
+
+/// Settings of the webhooks notified when a job, such as an asynchronous generation or
+/// a vector store file batch, completes or fails.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WebhookSettings {
+    /// The webhooks notified of every job.
+    pub endpoints: Vec<WebhookEndpoint>,
+    /// The URL prefixes the `webhook_url` of a request must start with, such as
+    /// `https://hooks.example.com/`. Requests may not name a webhook when empty, so that
+    /// they cannot make the server call its internal network.
+    pub allowed_url_prefixes: Vec<String>,
+    /// The secret the events are signed with in the `webhook-signature` header, unsigned
+    /// when unset.
+    pub secret: Option<String>,
+    /// How long in seconds to wait for a webhook to answer.
+    pub timeout_secs: u64,
+    /// The number of times an event is sent before giving up on a webhook.
+    pub max_attempts: u32,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            endpoints: Vec::new(),
+            allowed_url_prefixes: Vec::new(),
+            secret: None,
+            timeout_secs: 10,
+            max_attempts: 3,
+        }
+    }
+}
+
+impl WebhookSettings {
+    /// Whether a request may name a webhook URL, which must start with one of
+    /// `allowed_url_prefixes` at a boundary of its path, so that `https://hooks.example.com`
+    /// does not allow `https://hooks.example.com.attacker.net`.
+    pub fn allows_request_url(&self, url: &str) -> bool {
+        self.allowed_url_prefixes.iter().any(|prefix| {
+            url.strip_prefix(prefix.as_str()).is_some_and(|rest| {
+                prefix.ends_with('/') || rest.is_empty() || rest.starts_with(['/', '?', '#'])
+            })
+        })
+    }
+}
+
+/// A registered webhook.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhookEndpoint {
+    pub url: String,
+    /// The kinds of events sent to the webhook, e.g. `generation.failed`, all of them when
+    /// empty.
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+impl WebhookEndpoint {
+    /// Whether the webhook receives the events of a kind.
+    pub fn subscribes_to(&self, kind: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|event| event == kind)
+    }
+}
+
+/// Settings of the multi-process worker mode.
+///
+/// With workers, the server process does not load the model: it spawns `count` worker
+/// processes, one per GPU, and forwards every request to one of them over loopback
+/// HTTP. A worker that crashes or runs out of memory is restarted without taking the
+/// API down.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WorkerSettings {
+    /// The number of worker processes, `0` to serve from a single process.
+    pub count: usize,
+    /// The loopback port of the first worker, the next workers use the following ports.
+    pub base_port: u16,
+    /// The GPU of every worker, GPU `i` for worker `i` when unset.
+    pub devices: Vec<usize>,
+    /// How long in seconds to wait before restarting a worker that exited.
+    pub restart_delay_secs: u64,
+    /// The interval in seconds between two health checks of a worker.
+    pub health_check_secs: u64,
+}
+
+impl Default for WorkerSettings {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            base_port: 8100,
+            devices: Vec::new(),
+            restart_delay_secs: 5,
+            health_check_secs: 2,
+        }
+    }
+}
+
+/// Settings of the gateway mode.
+///
+/// With upstreams, the server does not load a model: it forwards every request to one
+/// of the upstreams, other instances of this server or any OpenAI-compatible server,
+/// so that one endpoint fronts a fleet serving different models.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct GatewaySettings {
+    pub upstreams: Vec<UpstreamSettings>,
+    /// How many other upstreams a request is sent to when its upstream cannot be
+    /// reached or answers `429`, `502`, `503` or `504`.
+    pub retries: usize,
+    /// The interval in seconds between two health checks of an upstream.
+    pub health_check_secs: u64,
+    /// Model names whose requests are spread over several models by weight, to compare
+    /// a candidate checkpoint with the production one on live traffic.
+    pub splits: HashMap<String, Vec<SplitTargetSettings>>,
+    /// Model names whose requests are also sent, for a sample of them, to a shadow
+    /// model whose output is logged but never returned.
+    pub shadows: HashMap<String, ShadowSettings>,
+    /// The log the outputs of both models are written to for every shadowed request,
+    /// required with `shadows`.
+    pub shadow_log: AccessLogSettings,
+}
+
+impl Default for GatewaySettings {
+    fn default() -> Self {
+        Self {
+            upstreams: Vec::new(),
+            retries: 2,
+            health_check_secs: 10,
+            splits: HashMap::new(),
+            shadows: HashMap::new(),
+            shadow_log: AccessLogSettings::default(),
+        }
+    }
+}
+
+/// A model receiving part of the requests of a split.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SplitTargetSettings {
+    /// The model the requests are forwarded to, served by one of the upstreams.
+    pub model: String,
+    /// The share of the requests of the target, relative to the weights of the other
+    /// targets of the split.
+    pub weight: f64,
+}
+
+/// A model receiving a copy of a sample of the requests for another model.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ShadowSettings {
+    /// The shadow model, served by one of the upstreams.
+    pub model: String,
+    /// The fraction of the requests copied to the shadow model, between `0` and `1`.
+    pub sample_rate: f64,
+}
+
+/// A server the gateway forwards requests to.
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpstreamSettings {
+    /// The base URL of the server, such as `http://10.0.0.2:8000`.
+    pub url: String,
+    /// The key sent as the bearer token of the forwarded requests, instead of the key of
+    /// the client.
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// The models the upstream serves. When empty, they are read from its `/v1/models`.
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// The path probed by the health checks, `/v1/models` when the models are read from
+    /// the upstream and `/v1/health` otherwise.
+    #[serde(default)]
+    pub health_path: Option<String>,
+}
+
+/// Settings of the KV caches kept between requests.
+///
+/// The KV cache of a finished generation is retained for a while, and a request whose
+/// prompt starts with the same tokens, such as the next turn of a chat, only runs the
+/// model over the tokens that follow them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PrefixCacheSettings {
+    pub enabled: bool,
+    /// The number of retained caches. Every generation retains the cache of its prompt
+    /// and the cache of its prompt followed by the output.
+    pub max_entries: usize,
+    /// How long in seconds an unused cache is retained.
+    pub ttl_secs: u64,
+    /// The most prompt tokens left after a retained cache for the request to resume from
+    /// it. They are run one at a time, so a longer rest is processed with the whole prompt.
+    pub max_resume_tokens: usize,
+}
+
+impl Default for PrefixCacheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_entries: 8,
+            ttl_secs: 300,
+            max_resume_tokens: 64,
+        }
+    }
+}
+
+/// When to stop serving a model that keeps failing and reload it.
+///
+/// Consecutive backend failures, such as CUDA or out of memory errors, open the
+/// circuit: requests fail right away with `503 Service Unavailable` while the weights
+/// are reloaded in the background, and the circuit closes once a warmup generation
+/// on the reloaded model succeeds.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerSettings {
+    pub enabled: bool,
+    /// The number of consecutive failed generations opening the circuit.
+    pub failure_threshold: u32,
+    /// How long in seconds to wait before retrying a failed reload or warmup.
+    pub retry_delay_secs: u64,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            failure_threshold: 3,
+            retry_delay_secs: 10,
+        }
+    }
+}
+
+/// How the generation steps failing with a transient device error are retried.
+///
+/// A step whose forward pass or sampling fails with an error that may not happen again,
+/// such as a kernel launch timeout or an out of memory error, is run again after a
+/// backoff, processing the whole context again from a fresh KV cache. The generation
+/// fails, and counts towards the circuit breaker, once `max_retries` retries failed.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RetrySettings {
+    /// The number of retries of a step, `0` fails the generation on the first error.
+    pub max_retries: u32,
+    /// The wait in milliseconds before the first retry, doubled for every other retry.
+    pub backoff_ms: u64,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            backoff_ms: 50,
+        }
+    }
+}
+
+impl RetrySettings {
+    /// The wait before a retry.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The number of the retry, from `1`.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let doublings = retry.saturating_sub(1).min(16);
+        Duration::from_millis(self.backoff_ms.saturating_mul(1 << doublings))
+    }
+}
+
+/// How the layers of the model are spread over the devices.
+///
+/// By default the whole model is loaded on the first accelerator. Listing several
+/// devices, or allowing CPU offload, splits the layers over them in order, which is
+/// needed for models larger than a single GPU such as 70B-class checkpoints.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PlacementSettings {
+    /// Ordinals of the accelerators to place layers on, in order.
+    pub devices: Vec<usize>,
+    /// Places the layers that fit on no accelerator in CPU memory.
+    pub cpu_offload: bool,
+    /// Fraction of the free memory of each accelerator kept for activations.
+    pub reserve_fraction: f64,
+    /// The number of tokens the KV cache is sized for in the memory estimate.
+    pub kv_cache_tokens: usize,
+    /// When the model does not fit, lowers the dtype or quantization level and enables
+    /// CPU offload as needed instead of refusing to start.
+    pub auto_fit: bool,
+}
+
+impl Default for PlacementSettings {
+    fn default() -> Self {
+        Self {
+            devices: vec![0],
+            cpu_offload: false,
+            reserve_fraction: 0.1,
+            kv_cache_tokens: 8192,
+            auto_fit: false,
+        }
+    }
+}
+
+/// Settings of the Hugging Face Hub client.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct HubSettings {
+    /// Directory where downloaded models are cached, overridden by `--cache-dir`.
+    /// Defaults to the `HF_HOME` cache, `~/.cache/huggingface/hub`.
+    pub cache_dir: Option<PathBuf>,
+    /// How many times a download failing with a transient error is retried.
+    pub max_retries: u32,
+    /// The delay in milliseconds before the first retry, doubled at every attempt.
+    pub retry_base_delay_ms: u64,
+    /// The maximum delay in milliseconds between two attempts.
+    pub retry_max_delay_ms: u64,
+    /// How long `/v1/health/dependencies` waits for the Hub to answer, in seconds.
+    pub probe_timeout_secs: u64,
+    /// The free space in megabytes below which the disk of the cache is reported as full
+    /// by `/v1/health/dependencies`.
+    pub min_free_disk_mb: u64,
+}
+
+impl Default for HubSettings {
+    fn default() -> Self {
+        Self {
+            cache_dir: None,
+            max_retries: 5,
+            retry_base_delay_ms: 500,
+            retry_max_delay_ms: 30_000,
+            probe_timeout_secs: 5,
+            min_free_disk_mb: 10_240,
+        }
+    }
+}
+
+impl HubSettings {
+    /// Returns the directory of the Hub cache, configured or default.
+    pub fn cache_dir(&self) -> PathBuf {
+        self.cache_dir
+            .clone()
+            .unwrap_or_else(|| hf_hub::Cache::default().path().clone())
+    }
+}
+
+/// Limits protecting the server from pathological requests.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LimitSettings {
+    /// Maximum size in bytes of a request body, except file and audio uploads which have
+    /// their own limits.
+    pub max_body_bytes: usize,
+    /// The limits of requests whose API key has no entry in `keys`.
+    #[serde(flatten)]
+    pub defaults: PromptLimits,
+    /// Limits by API key, the bearer token of the `Authorization` header. Limits a key
+    /// does not set fall back to the defaults.
+    pub keys: HashMap<String, PromptLimits>,
+    /// Refusing long requests while the accelerator is short of memory.
+    pub memory_pressure: MemoryPressureSettings,
+}
+
+impl Default for LimitSettings {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 4 * 1024 * 1024,
+            defaults: PromptLimits::default(),
+            keys: HashMap::new(),
+            memory_pressure: MemoryPressureSettings::default(),
+        }
+    }
+}
+
+/// Admission control by the free memory of the accelerator.
+///
+/// While the free memory is below `min_free_mb`, requests whose prompt and requested
+/// output exceed `long_request_tokens` are refused with `503 Service Unavailable`,
+/// since their KV cache is what runs the device out of memory, while shorter requests
+/// are still served.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MemoryPressureSettings {
+    /// The free memory in MiB below which long requests are refused, disabled when
+    /// unset. The CPU, which reports no memory, is never under pressure.
+    pub min_free_mb: Option<u64>,
+    /// The number of prompt and output tokens above which a request is long.
+    pub long_request_tokens: usize,
+    /// The delay in seconds clients are told to wait before retrying, in `Retry-After`.
+    pub retry_after_secs: u64,
+}
+
+impl Default for MemoryPressureSettings {
+    fn default() -> Self {
+        Self {
+            min_free_mb: None,
+            long_request_tokens: 4096,
+            retry_after_secs: 5,
+        }
+    }
+}
+
+impl LimitSettings {
+    /// Returns the limits of an API key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The bearer token of the request, if it has one.
+    pub fn for_key(&self, key: Option<&str>) -> PromptLimits {
+        match key.and_then(|key| self.keys.get(key)) {
+            Some(limits) => PromptLimits {
+                max_messages: limits.max_messages.or(self.defaults.max_messages),
+                max_prompt_tokens: limits.max_prompt_tokens.or(self.defaults.max_prompt_tokens),
+            },
+            None => self.defaults.clone(),
+        }
+    }
+}
+
+/// Network access control, by the address of the client.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// The rules of every request.
+    #[serde(flatten)]
+    pub defaults: NetworkRules,
+    /// Rules by path prefix, e.g. `/admin`, checked in addition to the defaults. The
+    /// longest prefix matching the path of a request at a segment boundary applies, so
+    /// `/admin` covers `/admin/reload` but not `/administrator`.
+    pub routes: HashMap<String, NetworkRules>,
+}
+
+impl NetworkSettings {
+    /// Whether any rule is configured.
+    pub fn is_empty(&self) -> bool {
+        self.defaults.is_empty() && self.routes.values().all(NetworkRules::is_empty)
+    }
+
+    /// Whether a client may send a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `ip` - The address of the client.
+    /// * `path` - The path of the request.
+    pub fn allows(&self, ip: IpAddr, path: &str) -> bool {
+        let ip = ip.to_canonical();
+        let route = self
+            .routes
+            .iter()
+            .filter(|(prefix, _)| {
+                path.strip_prefix(prefix.as_str()).is_some_and(|rest| {
+                    prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/')
+                })
+            })
+            .max_by_key(|(prefix, _)| prefix.len());
+
+        self.defaults.allows(ip) && route.map_or(true, |(_, rules)| rules.allows(ip))
+    }
+}
+
+/// Networks allowed and denied to send requests.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct NetworkRules {
+    /// The networks allowed, e.g. `10.0.0.0/8`. Every address is allowed when empty.
+    pub allow: Vec<IpNet>,
+    /// The networks denied, which take precedence over `allow`.
+    pub deny: Vec<IpNet>,
+}
+
+impl NetworkRules {
+    fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    fn allows(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+/// The tools the server runs itself when the model calls them in `/v1/responses`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ToolSettings {
+    /// The registered tools, by the name the model calls them with.
+    pub registry: HashMap<String, ToolDefinition>,
+    /// The tools each API key may have the server run.
+    pub access: ToolAccessSettings,
+    /// The maximum number of rounds of tool calls run for one response, after which the
+    /// model answers with the results it has.
+    pub max_iterations: usize,
+    /// The maximum size in bytes of a tool result fed back to the model, longer results
+    /// are truncated.
+    pub max_output_bytes: usize,
+}
+
+impl Default for ToolSettings {
+    fn default() -> Self {
+        Self {
+            registry: HashMap::new(),
+            access: ToolAccessSettings::default(),
+            max_iterations: 4,
+            max_output_bytes: 16 * 1024,
+        }
+    }
+}
+
+/// A tool the server runs, described to the model by its `description` and the JSON
+/// schema of its `parameters`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ToolDefinition {
+    /// How the tool is run.
+    #[serde(rename = "type")]
+    pub kind: ToolKind,
+    pub description: String,
+    pub parameters: serde_json::Value,
+    /// The endpoint of an `http` tool.
+    pub url: Option<String>,
+    /// The method of an `http` tool, `POST` or `GET`.
+    pub method: String,
+    /// The headers sent by an `http` tool, such as its credentials.
+    pub headers: HashMap<String, String>,
+    /// The program and arguments of a `command` tool.
+    pub command: Vec<String>,
+    /// A command prefix isolating a `command` tool, such as
+    /// `["bwrap", "--ro-bind", "/", "/", "--unshare-all", "--"]`.
+    pub sandbox: Vec<String>,
+    /// Whether a `command` tool without `sandbox` may run with the rights of the server,
+    /// which is refused otherwise.
+    pub unsandboxed: bool,
+    /// The environment of a `command` tool, which inherits none of the server's.
+    pub env: HashMap<String, String>,
+    /// The working directory of a `command` tool.
+    pub working_dir: Option<PathBuf>,
+    /// How long in seconds an `http` or `command` tool may run.
+    pub timeout_secs: u64,
+    /// The vector store a `search` tool searches, the RAG documents when unset.
+    pub vector_store_id: Option<String>,
+    /// The number of chunks a `search` tool returns.
+    pub max_results: usize,
+    /// The MCP server of an `mcp` tool, a key of `mcp.servers`.
+    pub server: Option<String>,
+    /// The name of an `mcp` tool on its server, the registered name when unset.
+    pub tool: Option<String>,
+}
+
+impl Default for ToolDefinition {
+    fn default() -> Self {
+        Self {
+            kind: ToolKind::Calculator,
+            description: String::new(),
+            parameters: serde_json::json!({ "type": "object", "properties": {} }),
+            url: None,
+            method: "POST".to_string(),
+            headers: HashMap::new(),
+            command: Vec::new(),
+            sandbox: Vec::new(),
+            unsandboxed: false,
+            env: HashMap::new(),
+            working_dir: None,
+            timeout_secs: 30,
+            vector_store_id: None,
+            max_results: 5,
+            server: None,
+            tool: None,
+        }
+    }
+}
+
+/// How a server tool is run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolKind {
+    /// Sends the arguments to `url`, as a JSON body or as the query string of a `GET`, and
+    /// returns the body of the response.
+    Http,
+    /// Runs `command` with the JSON arguments on its standard input and returns its
+    /// standard output, killed after `timeout_secs`.
+    Command,
+    /// Evaluates the arithmetic `expression` argument.
+    Calculator,
+    /// Searches a vector store or the RAG documents for the `query` argument.
+    Search,
+    /// Calls `tool` on the MCP server `server`.
+    Mcp,
+}
+
+/// Settings of `/v1/agents/run`, which runs the loop of generations and tool calls on the
+/// server.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AgentSettings {
+    /// Whether the endpoint is served.
+    pub enabled: bool,
+    /// The default and maximum number of generations of a run.
+    pub max_iterations: usize,
+    /// The default and maximum duration of a run, in seconds.
+    pub max_duration_secs: u64,
+}
+
+impl Default for AgentSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_iterations: 8,
+            max_duration_secs: 300,
+        }
+    }
+}
+
+/// The Model Context Protocol servers whose tools the server offers to the model and calls.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct McpSettings {
+    /// The MCP servers, by the name prefixing their tools.
+    pub servers: HashMap<String, McpServerSettings>,
+}
+
+/// An MCP server, reached over standard input and output when `command` is set, or over
+/// the Streamable HTTP transport at `url`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct McpServerSettings {
+    /// The program and arguments of a local server, started on first use.
+    pub command: Vec<String>,
+    /// Variables added to the environment of the local server.
+    pub env: HashMap<String, String>,
+    /// The working directory of the local server.
+    pub working_dir: Option<PathBuf>,
+    /// The endpoint of a remote server.
+    pub url: Option<String>,
+    /// The headers sent to the remote server, such as its credentials.
+    pub headers: HashMap<String, String>,
+    /// How long in seconds a request to the server may take.
+    pub timeout_secs: u64,
+    /// The tools of the server offered to the model, every tool when unset.
+    pub tools: Option<Vec<String>>,
+}
+
+impl Default for McpServerSettings {
+    fn default() -> Self {
+        Self {
+            command: Vec::new(),
+            env: HashMap::new(),
+            working_dir: None,
+            url: None,
+            headers: HashMap::new(),
+            timeout_secs: 30,
+            tools: None,
+        }
+    }
+}
+
+/// The server tools each API key may use.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ToolAccessSettings {
+    /// The tool names each API key may use, by the bearer token of the `Authorization` header.
+    pub keys: HashMap<String, Vec<String>>,
+    /// The tool names of the requests whose key has no entry in `keys` or that have no
+    /// key. When unset, every registered tool without `keys`, and none with them.
+    pub default: Option<Vec<String>>,
+}
+
+impl ToolAccessSettings {
+    /// Whether an API key may have the server run a tool.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The bearer token of the request, if it has one.
+    /// * `tool` - The name of the tool.
+    pub fn allows(&self, key: Option<&str>, tool: &str) -> bool {
+        allowed_by(&self.keys, self.default.as_ref(), key, tool)
+    }
+}
+
+/// The models each API key may use, so a shared server can give teams different models.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ModelAccessSettings {
+    /// The model ids each API key may use, by the bearer token of the `Authorization` header.
+    pub keys: HashMap<String, Vec<String>>,
+    /// The model ids of the requests whose key has no entry in `keys` or that have no key.
+    /// When unset, every model without `keys`, and none with them.
+    pub default: Option<Vec<String>>,
+}
+
+impl ModelAccessSettings {
+    /// Whether an API key may use a model.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The bearer token of the request, if it has one.
+    /// * `model_id` - The id of the model.
+    pub fn allows(&self, key: Option<&str>, model_id: &str) -> bool {
+        allowed_by(&self.keys, self.default.as_ref(), key, model_id)
+    }
+}
+
+/// Whether the entry of a key, or else the default, lists a name.
+///
+/// Listing keys restricts the server: a request whose key is not listed, or that has no
+/// key, only gets the default names, and none when there is no default, so that a
+/// restricted key cannot be bypassed by changing or dropping the token.
+fn allowed_by(
+    keys: &HashMap<String, Vec<String>>,
+    default: Option<&Vec<String>>,
+    key: Option<&str>,
+    name: &str,
+) -> bool {
+    let allowed = match key.and_then(|key| keys.get(key)).or(default) {
+        Some(allowed) => allowed,
+        None => return keys.is_empty(),
+    };
+    allowed.iter().any(|allowed| allowed == name)
+}
+
+/// Limits on the size of the prompt of a generation request.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PromptLimits {
+    /// Maximum number of messages or input items of a request.
+    pub max_messages: Option<usize>,
+    /// Maximum number of tokens of the prompt, system prompt and history included.
+    pub max_prompt_tokens: Option<usize>,
+}
+
+/// Settings of the access log, a file with one JSON line per request.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AccessLogSettings {
+    /// The file requests are logged to. The access log is disabled when unset.
+    pub path: Option<PathBuf>,
+    /// When the file is rotated.
+    pub rotation: LogRotation,
+    /// The size in bytes the file is rotated at, with the `size` rotation.
+    pub max_bytes: u64,
+    /// The number of rotated files kept, the oldest ones are removed.
+    pub max_files: usize,
+    /// What is logged of the request bodies, which hold the prompts.
+    pub content: ContentPolicy,
+}
+
+impl Default for AccessLogSettings {
+    fn default() -> Self {
+        Self {
+            path: None,
+            rotation: LogRotation::Daily,
+            max_bytes: 100 * 1024 * 1024,
+            max_files: 7,
+            content: ContentPolicy::Omit,
+        }
+    }
+}
+
+/// When the access log file is rotated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    /// At the first request of every day, in UTC.
+    #[default]
+    Daily,
+    /// Once the file reaches `max_bytes`.
+    Size,
+}
+
+/// How the JSON bodies of the requests are parsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestParsing {
+    /// Unknown fields are ignored, like the fields of other OpenAI-compatible servers.
+    #[default]
+    Permissive,
+    /// Unknown fields are rejected with a `400`, which catches misspelled parameters that
+    /// would otherwise be silently dropped.
+    Strict,
+}
+
+/// What the access log keeps of the request bodies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentPolicy {
+    /// The bodies are never logged.
+    #[default]
+    Omit,
+    /// The SHA-256 digest of the bodies is logged, to match requests without their content.
+    Hash,
+    /// The bodies are logged in full.
+    Full,
+}
+
+/// Settings of the `/admin` endpoints.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AdminSettings {
+    /// The bearer token required by the admin endpoints, which are disabled when unset.
+    pub api_key: Option<String>,
+}
+
+/// Settings of the audit log of the admin actions.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AuditSettings {
+    /// The SQLite database the admin actions are appended to. Auditing is disabled when unset.
+    pub database: Option<PathBuf>,
+}
+
+/// Settings of the Azure OpenAI compatible `/openai/deployments` routes.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AzureSettings {
+    /// The model id served under each deployment name.
+    pub deployments: HashMap<String, String>,
+}
+
+/// Settings of the server-side chat transcripts.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ConversationSettings {
+    /// The SQLite database the transcripts are kept in. Persistence is disabled when unset.
+    pub database: Option<PathBuf>,
+}
+
+/// Settings of the Assistants API subset.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AssistantSettings {
+    /// The SQLite database the assistants, threads, messages and runs are kept in. The
+    /// Assistants API is disabled when unset.
+    pub database: Option<PathBuf>,
+}
+
+/// Settings of the `/v1/embeddings` endpoint.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingSettings {
+    /// Hub id of the BERT model used for embeddings, e.g. `sentence-transformers/all-MiniLM-L6-v2`.
+    /// RAG and the vector stores need it, the embeddings endpoint is disabled when neither
+    /// it nor `image_model` is set.
+    pub model: Option<String>,
+    /// Hub id of a SigLIP model embedding images and texts in a shared space, e.g.
+    /// `google/siglip-base-patch16-224`, served by the embeddings endpoint as well.
+    pub image_model: Option<String>,
+    /// The device the model runs on, such as `cpu` or `cuda:1`, so that embedding batches
+    /// do not compete with the served model. Defaults to the device of the served model.
+    pub device: Option<String>,
+    /// The number of threads dedicated to running the model. With `0`, texts are embedded
+    /// on the threads of the requests.
+    pub workers: usize,
+    /// What to do with the texts longer than the context of the model.
+    pub long_inputs: LongInputPolicy,
+    pub heads: EmbeddingHeads,
+    pub cache: EmbeddingCacheSettings,
+}
+
+/// The projection heads of an embedding model, files of its repository such as the
+/// `sparse_linear.pt` and `colbert_linear.pt` of `BAAI/bge-m3`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingHeads {
+    /// The head computing the lexical weight of every token, for sparse vectors.
+    pub sparse: Option<String>,
+    /// The head projecting the state of every token, for ColBERT-style multi-vectors.
+    pub multi_vector: Option<String>,
+}
+
+/// What to do with the embedding inputs longer than the context of the model.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LongInputPolicy {
+    /// Only the first tokens are embedded, and the item is reported as `truncated`.
+    #[default]
+    Truncate,
+    /// The text is split into chunks the model reads in full, and the vector is the mean
+    /// of their vectors.
+    Mean,
+    /// The request is refused.
+    Error,
+}
+
+/// The cache of the vectors of the texts already embedded, by the digest of their content.
+///
+/// Re-embedding identical chunks, e.g. when the same documents are ingested again, then
+/// skips the model.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingCacheSettings {
+    pub enabled: bool,
+    /// The number of vectors kept in memory, the least recently used are evicted first.
+    pub max_entries: usize,
+    /// The SQLite database the vectors are also kept in, so they survive restarts. The
+    /// cache is in memory only when unset.
+    pub database: Option<PathBuf>,
+}
+
+impl Default for EmbeddingCacheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: 10_000,
+            database: None,
+        }
+    }
+}
+
+/// Settings of the local retrieval-augmented generation endpoints under `/v1/rag`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RagSettings {
+    /// Whether the endpoints are served. They also need an embedding model.
+    pub enabled: bool,
+    /// The size in characters of the chunks documents are split into.
+    pub chunk_chars: usize,
+    /// The number of characters consecutive chunks share.
+    pub chunk_overlap: usize,
+    /// The number of chunks retrieved for a query when the request does not say.
+    pub top_k: usize,
+}
+
+impl Default for RagSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            chunk_chars: 1000,
+            chunk_overlap: 200,
+            top_k: 4,
+        }
+    }
+}
+
+/// Settings of the `/v1/vector_stores` endpoints, which also need an embedding model.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct VectorStoreSettings {
+    /// Directory where the chunks and vectors of every vector store are kept.
+    pub directory: PathBuf,
+}
+
+impl Default for VectorStoreSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("data/vector_stores"),
+        }
+    }
+}
+
+/// Settings of the disk-backed store behind the `/v1/files` endpoints.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FileSettings {
+    /// Directory where uploaded files and their metadata are kept.
+    pub directory: PathBuf,
+    /// Maximum size in bytes of a single uploaded file.
+    pub max_file_bytes: u64,
+    /// Optional limit in bytes on the size of all stored files together.
+    pub max_total_bytes: Option<u64>,
+}
+
+impl Default for FileSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("data/files"),
+            max_file_bytes: 512 * 1024 * 1024,
+            max_total_bytes: None,
+        }
+    }
+}
+
+/// Where the artifacts of the server, such as the uploaded files, are kept.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+    /// The bucket of the `s3` backend.
+    pub s3: S3Settings,
+}
+
+/// The backends of the artifact storage.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// The directories of the subsystems on the local disk, e.g. `files.directory`.
+    #[default]
+    Local,
+    /// A bucket of an S3-compatible object storage, one folder per subsystem.
+    S3,
+}
+
+/// An S3-compatible bucket, such as AWS S3, MinIO or Cloudflare R2.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct S3Settings {
+    /// The URL of the object storage, e.g. `https://s3.eu-west-1.amazonaws.com`.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    /// The folder of the server in the bucket, under which every subsystem has its own.
+    pub prefix: String,
+    /// The credentials, read from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` when
+    /// unset.
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// How long in seconds to wait for a request to the object storage.
+    pub timeout_secs: u64,
+}
+
+impl Default for S3Settings {
+    fn default() -> Self {
+        Self {
+            endpoint: "https://s3.amazonaws.com".to_string(),
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            prefix: String::new(),
+            access_key_id: None,
+            secret_access_key: None,
+            timeout_secs: 30,
+        }
+    }
+}
+
+/// Settings of the `/v1/audio` endpoints.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    /// Hub id of the Whisper model used for transcriptions, e.g. `openai/whisper-base`.
+    /// The transcription endpoint is disabled when unset.
+    pub transcription_model: Option<String>,
+    /// Hub id of the Parler-TTS model used for speech, e.g. `parler-tts/parler-tts-mini-v1`.
+    /// The speech endpoint is disabled when unset.
+    pub speech_model: Option<String>,
+    /// Speaker descriptions by voice name, overriding or extending the built-in voices.
+    pub voices: HashMap<String, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model_access(default: Option<&[&str]>) -> ModelAccessSettings {
+        ModelAccessSettings {
+            keys: HashMap::from([("sk-team".to_string(), vec!["small".to_string()])]),
+            default: default.map(|models| models.iter().map(|model| model.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn listed_keys_get_their_models() {
+        let access = model_access(None);
+        assert!(access.allows(Some("sk-team"), "small"));
+        assert!(!access.allows(Some("sk-team"), "large"));
+    }
+
+    #[test]
+    fn unlisted_and_missing_keys_are_denied_without_default() {
+        let access = model_access(None);
+        assert!(!access.allows(Some("sk-other"), "small"));
+        assert!(!access.allows(Some("sk-other"), "large"));
+        assert!(!access.allows(None, "small"));
+    }
+
+    #[test]
+    fn unlisted_and_missing_keys_get_the_default() {
+        let access = model_access(Some(&["large"]));
+        assert!(access.allows(Some("sk-other"), "large"));
+        assert!(access.allows(None, "large"));
+        assert!(!access.allows(None, "small"));
+        assert!(!access.allows(Some("sk-team"), "large"));
+    }
+
+    #[test]
+    fn no_restriction_allows_everything() {
+        let access = ModelAccessSettings::default();
+        assert!(access.allows(None, "large"));
+        assert!(access.allows(Some("sk-any"), "large"));
+
+        let tools = ToolAccessSettings::default();
+        assert!(tools.allows(None, "calculator"));
+    }
+
+    #[test]
+    fn request_webhooks_must_match_a_prefix() {
+        let webhooks = WebhookSettings {
+            allowed_url_prefixes: vec![
+                "https://hooks.example.com".to_string(),
+                "https://example.org/hooks/".to_string(),
+            ],
+            ..WebhookSettings::default()
+        };
+        assert!(webhooks.allows_request_url("https://hooks.example.com/jobs"));
+        assert!(webhooks.allows_request_url("https://example.org/hooks/jobs"));
+        assert!(!webhooks.allows_request_url("https://hooks.example.com.attacker.net/"));
+        assert!(!webhooks.allows_request_url("https://hooks.example.com@10.0.0.1/"));
+        assert!(!webhooks.allows_request_url("http://169.254.169.254/latest/meta-data"));
+        assert!(!WebhookSettings::default().allows_request_url("https://hooks.example.com/"));
+    }
+
+    fn network(routes: &[(&str, NetworkRules)]) -> NetworkSettings {
+        NetworkSettings {
+            defaults: NetworkRules::default(),
+            routes: routes
+                .iter()
+                .map(|(prefix, rules)| (prefix.to_string(), rules.clone()))
+                .collect(),
+        }
+    }
+
+    fn rules(allow: &[&str], deny: &[&str]) -> NetworkRules {
+        let nets = |nets: &[&str]| nets.iter().map(|net| net.parse().unwrap()).collect();
+        NetworkRules {
+            allow: nets(allow),
+            deny: nets(deny),
+        }
+    }
+
+    #[test]
+    fn network_routes_match_whole_segments() {
+        let network = network(&[("/admin", rules(&["127.0.0.1/32"], &[]))]);
+        let remote: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(!network.allows(remote, "/admin"));
+        assert!(!network.allows(remote, "/admin/reload"));
+        assert!(network.allows(remote, "/administrator"));
+        assert!(network.allows(remote, "/v1/models"));
+    }
+
+    #[test]
+    fn network_deny_takes_precedence_over_allow() {
+        let network = network(&[("/v1", rules(&["10.0.0.0/8"], &["10.0.0.7/32"]))]);
+        assert!(network.allows("10.0.0.1".parse().unwrap(), "/v1/models"));
+        assert!(!network.allows("10.0.0.7".parse().unwrap(), "/v1/models"));
+        assert!(!network.allows("192.168.0.1".parse().unwrap(), "/v1/models"));
+    }
+
+    #[test]
+    fn network_rules_apply_to_ipv4_mapped_addresses() {
+        let network = network(&[
+            ("/admin", rules(&["127.0.0.1/32"], &[])),
+            ("/v1", rules(&[], &["10.0.0.0/8"])),
+        ]);
+        assert!(network.allows("::ffff:127.0.0.1".parse().unwrap(), "/admin"));
+        assert!(!network.allows("::ffff:192.168.0.1".parse().unwrap(), "/admin"));
+        assert!(!network.allows("::ffff:10.0.0.1".parse().unwrap(), "/v1/models"));
+        assert!(network.allows("::1".parse().unwrap(), "/v1/models"));
+    }
+
+    #[test]
+    fn the_longest_network_route_applies() {
+        let network = network(&[
+            ("/v1", rules(&["10.0.0.0/8"], &[])),
+            ("/v1/files", rules(&["192.168.0.0/16"], &[])),
+        ]);
+        let private: IpAddr = "10.0.0.1".parse().unwrap();
+        let home: IpAddr = "192.168.0.1".parse().unwrap();
+        assert!(network.allows(private, "/v1/models"));
+        assert!(!network.allows(home, "/v1/models"));
+        assert!(network.allows(home, "/v1/files/file-1"));
+        assert!(!network.allows(private, "/v1/files"));
+        // `/v1/filesystem` is not under `/v1/files`
+        assert!(network.allows(private, "/v1/filesystem"));
+    }
+
+    #[test]
+    fn tool_access_denies_unlisted_keys() {
+        let tools = ToolAccessSettings {
+            keys: HashMap::from([("sk-data".to_string(), vec!["python".to_string()])]),
+            default: None,
+        };
+        assert!(tools.allows(Some("sk-data"), "python"));
+        assert!(!tools.allows(Some("sk-other"), "python"));
+        assert!(!tools.allows(None, "calculator"));
+    }
+}
@@ -0,0 +1,343 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::config::{AccessLogSettings, ContentPolicy, LogRotation};
+
+/// A request of the access log.
+#[derive(Serialize)]
+pub struct AccessLogEntry {
+    /// When the request was received, in RFC 3339.
+    pub timestamp: String,
+    pub method: String,
+    /// The path of the request, without the query string.
+    pub path: String,
+    pub status: u16,
+    /// The time until the response headers were sent. Streamed responses continue after it.
+    pub latency_ms: u64,
+    /// The digest of the API key of the request, which is never logged itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// The body of the request, or its digest, depending on the content policy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<serde_json::Value>,
+}
+
+/// Writes one JSON line per request to a rotated file, separately from the tracing logs.
+///
+/// The lines are written by a background thread, so requests never wait for the disk.
+pub struct AccessLog {
+    content: ContentPolicy,
+    lines: mpsc::Sender<String>,
+}
+
+impl AccessLog {
+    /// Opens the access log file and starts its writer thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file requests are logged to, rotated files are written next to it.
+    /// * `settings` - The rotation and content policy of the access log.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub fn open(path: &Path, settings: &AccessLogSettings) -> anyhow::Result<Self> {
+        let mut file = RotatingFile::open(path.to_path_buf(), settings)
+            .with_context(|| format!("Error opening the access log {}", path.display()))?;
+        let (lines, received) = mpsc::channel::<String>();
+
+        std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                for line in received {
+                    if let Err(err) = file.write_line(&line) {
+                        error!("Error writing the access log: {err}");
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            content: settings.content,
+            lines,
+        })
+    }
+
+    /// Whether request bodies are logged in any form.
+    pub fn logs_content(&self) -> bool {
+        self.content != ContentPolicy::Omit
+    }
+
+    /// Applies the content policy to a request body.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The raw request body.
+    ///
+    /// # Returns
+    ///
+    /// The value logged for the body, `None` when bodies are omitted.
+    pub fn redact(&self, body: &[u8]) -> Option<serde_json::Value> {
+        match self.content {
+            ContentPolicy::Omit => None,
+            ContentPolicy::Hash => Some(digest(body).into()),
+            ContentPolicy::Full => Some(
+                serde_json::from_slice(body)
+                    .unwrap_or_else(|_| String::from_utf8_lossy(body).into_owned().into()),
+            ),
+        }
+    }
+
+    /// Queues an entry for the writer thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The request to log, or any other entry written to the same kind of log.
+    pub fn record(&self, entry: &impl Serialize) {
+        match serde_json::to_string(entry) {
+            Ok(line) => {
+                // The writer thread only stops with the process
+                let _ = self.lines.send(line);
+            }
+            Err(err) => error!("Error serializing an access log entry: {err}"),
+        }
+    }
+}
+
+/// The SHA-256 digest of some bytes, e.g. `sha256:9f86d0…`.
+///
+/// # Arguments
+///
+/// * `bytes` - The bytes to digest.
+pub fn digest(bytes: &[u8]) -> String {
+    let hash = Sha256::digest(bytes);
+    let hex: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("sha256:{hex}")
+}
+
+/// The access log file, renamed with a timestamp suffix when it is rotated.
+struct RotatingFile {
+    path: PathBuf,
+    rotation: LogRotation,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+    opened: NaiveDate,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, settings: &AccessLogSettings) -> io::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        let opened = metadata
+            .modified()
+            .map(|modified| DateTime::<Utc>::from(modified).date_naive())
+            .unwrap_or_else(|_| Utc::now().date_naive());
+
+        Ok(Self {
+            path,
+            rotation: settings.rotation,
+            max_bytes: settings.max_bytes,
+            max_files: settings.max_files,
+            file,
+            size: metadata.len(),
+            opened,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let rotate = match self.rotation {
+            LogRotation::Daily => Utc::now().date_naive() != self.opened,
+            LogRotation::Size => self.size > 0 && self.size + len > self.max_bytes,
+        };
+        if rotate {
+            self.rotate()?;
+        }
+
+        self.file.write_all(format!("{line}\n").as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Renames the current file after the day it covers, or the time it was rotated, and
+    /// removes the oldest rotated files.
+    fn rotate(&mut self) -> io::Result<()> {
+        let suffix = match self.rotation {
+            LogRotation::Daily => self.opened.format("%Y-%m-%d").to_string(),
+            LogRotation::Size => Utc::now().format("%Y-%m-%dT%H-%M-%S%.6f").to_string(),
+        };
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(format!(".{suffix}"));
+        std::fs::rename(&self.path, &rotated)?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened = Utc::now().date_naive();
+
+        self.remove_oldest()
+    }
+
+    fn remove_oldest(&self) -> io::Result<()> {
+        let Some(name) = self.path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(());
+        };
+        let prefix = format!("{name}.");
+        let dir = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        // The suffixes are timestamps, so the names sort from the oldest to the newest
+        let mut rotated: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|entry| entry.path())
+            .collect();
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for path in &rotated[..excess] {
+            std::fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "synap-forge-access-log-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn rotated_files(dir: &Path) -> Vec<PathBuf> {
+        let mut rotated: Vec<PathBuf> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.file_name().unwrap() != "access.log")
+            .collect();
+        rotated.sort();
+        rotated
+    }
+
+    #[test]
+    fn size_rotation_keeps_max_files() {
+        let dir = temp_dir("size");
+        let settings = AccessLogSettings {
+            rotation: LogRotation::Size,
+            max_bytes: 16,
+            max_files: 2,
+            ..AccessLogSettings::default()
+        };
+        let mut file = RotatingFile::open(dir.join("access.log"), &settings).unwrap();
+        for line in [
+            "first line",
+            "second line",
+            "third line",
+            "fourth line",
+            "fifth line",
+        ] {
+            file.write_line(line).unwrap();
+        }
+
+        let rotated = rotated_files(&dir);
+        assert_eq!(rotated.len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&rotated[0]).unwrap(),
+            "third line\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&rotated[1]).unwrap(),
+            "fourth line\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("access.log")).unwrap(),
+            "fifth line\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn daily_rotation_names_the_file_after_its_day() {
+        let dir = temp_dir("daily");
+        let settings = AccessLogSettings {
+            max_files: 1,
+            ..AccessLogSettings::default()
+        };
+        let mut file = RotatingFile::open(dir.join("access.log"), &settings).unwrap();
+        file.write_line("today").unwrap();
+        assert!(rotated_files(&dir).is_empty());
+
+        let yesterday = Utc::now().date_naive().pred_opt().unwrap();
+        let older = yesterday.pred_opt().unwrap();
+        std::fs::write(
+            dir.join(format!("access.log.{}", older.format("%Y-%m-%d"))),
+            "older\n",
+        )
+        .unwrap();
+        file.opened = yesterday;
+        file.write_line("tomorrow").unwrap();
+
+        // The older file was pruned, the one of yesterday holds its lines
+        let rotated = rotated_files(&dir);
+        assert_eq!(
+            rotated,
+            vec![dir.join(format!("access.log.{}", yesterday.format("%Y-%m-%d")))]
+        );
+        assert_eq!(std::fs::read_to_string(&rotated[0]).unwrap(), "today\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("access.log")).unwrap(),
+            "tomorrow\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn redacts_bodies_with_the_content_policy() {
+        let log = |content| AccessLog {
+            content,
+            lines: mpsc::channel().0,
+        };
+        let body = br#"{"prompt": "a secret"}"#;
+
+        assert!(!log(ContentPolicy::Omit).logs_content());
+        assert_eq!(log(ContentPolicy::Omit).redact(body), None);
+        assert_eq!(
+            log(ContentPolicy::Hash).redact(body),
+            Some(digest(body).into())
+        );
+        assert!(digest(body).starts_with("sha256:"));
+        assert_eq!(
+            log(ContentPolicy::Full).redact(body),
+            Some(serde_json::json!({ "prompt": "a secret" }))
+        );
+        assert_eq!(
+            log(ContentPolicy::Full).redact(b"not json"),
+            Some("not json".into())
+        );
+    }
+}
@@ -0,0 +1,484 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+use serde::de::DeserializeOwned;
+use tracing::warn;
+
+use crate::openai::models::{
+    AssistantObject, RunError, RunObject, ThreadMessageObject, ThreadObject,
+};
+
+/// Errors returned by the [`AssistantStore`].
+#[derive(Debug)]
+pub enum AssistantStoreError {
+    /// No object of this kind, e.g. `thread`, has this id.
+    NotFound {
+        kind: &'static str,
+        id: String,
+    },
+    Database(rusqlite::Error),
+    Serialization(serde_json::Error),
+}
+
+impl fmt::Display for AssistantStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { kind, id } => write!(f, "No {kind} found with id '{id}'"),
+            Self::Database(err) => write!(f, "Assistant database error: {err}"),
+            Self::Serialization(err) => write!(f, "Assistant serialization error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for AssistantStoreError {}
+
+impl From<rusqlite::Error> for AssistantStoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+impl From<serde_json::Error> for AssistantStoreError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Serialization(err)
+    }
+}
+
+/// A SQLite store of the objects of the Assistants API: assistants, threads, the messages
+/// of the threads and their runs.
+///
+/// Every row holds the JSON of its object, as returned by the API, with the columns its
+/// lookups need. Objects are listed in the order they were created.
+///
+/// Assistants and threads are owned by the digest of the API key that created them, and
+/// the messages and runs of a thread by its owner; objects of another key are reported
+/// as not found. The queries block on the database, so async handlers run them with
+/// `spawn_blocking`.
+pub struct AssistantStore {
+    connection: Mutex<Connection>,
+}
+
+impl AssistantStore {
+    /// Opens the database, creating it and its tables if needed.
+    ///
+    /// The runs the server was executing when it stopped are marked as failed, since
+    /// nothing will end them.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the SQLite database file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened or initialised.
+    pub fn open(path: &Path) -> Result<Self, AssistantStoreError> {
+        if let Some(parent) = path.parent() {
+            // A missing directory surfaces as an open error just below
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA foreign_keys = ON;
+             PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS assistants (
+                 seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                 id TEXT NOT NULL UNIQUE,
+                 owner TEXT,
+                 body TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS threads (
+                 seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                 id TEXT NOT NULL UNIQUE,
+                 owner TEXT,
+                 body TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS messages (
+                 seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                 id TEXT NOT NULL UNIQUE,
+                 thread_id TEXT NOT NULL REFERENCES threads (id) ON DELETE CASCADE,
+                 body TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS messages_thread ON messages (thread_id);
+             CREATE TABLE IF NOT EXISTS runs (
+                 seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                 id TEXT NOT NULL UNIQUE,
+                 thread_id TEXT NOT NULL REFERENCES threads (id) ON DELETE CASCADE,
+                 body TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS runs_thread ON runs (thread_id);",
+        )?;
+        // Databases created before the objects were owned by API keys lack the column,
+        // their objects stay visible to requests without a key
+        for table in ["assistants", "threads"] {
+            if connection
+                .prepare(&format!("SELECT owner FROM {table} LIMIT 0"))
+                .is_err()
+            {
+                connection.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN owner TEXT"))?;
+            }
+        }
+
+        let store = Self {
+            connection: Mutex::new(connection),
+        };
+        store.fail_interrupted_runs()?;
+
+        Ok(store)
+    }
+
+    /// Stores an assistant of an API key, replacing the one with the same id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an assistant of another key has the id or the database fails.
+    pub fn save_assistant(
+        &self,
+        assistant: &AssistantObject,
+        owner: Option<&str>,
+    ) -> Result<(), AssistantStoreError> {
+        let body = serde_json::to_string(assistant)?;
+        let saved = self.lock().execute(
+            "INSERT INTO assistants (id, owner, body) VALUES (?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET body = ?3 WHERE owner IS ?2",
+            params![assistant.id, owner, body],
+        )?;
+        if saved == 0 {
+            return Err(not_found("assistant", &assistant.id));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an assistant of an API key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key has no such assistant or the database fails.
+    pub fn assistant(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+    ) -> Result<AssistantObject, AssistantStoreError> {
+        let connection = self.lock();
+        find(
+            &connection,
+            "SELECT body FROM assistants WHERE id = ?1 AND owner IS ?2",
+            &[&id, &owner],
+        )?
+        .ok_or_else(|| not_found("assistant", id))
+    }
+
+    /// Lists the assistants of an API key in the order they were created.
+    pub fn assistants(
+        &self,
+        owner: Option<&str>,
+    ) -> Result<Vec<AssistantObject>, AssistantStoreError> {
+        let connection = self.lock();
+        list(
+            &connection,
+            "SELECT body FROM assistants WHERE owner IS ?1 ORDER BY seq",
+            &[&owner],
+        )
+    }
+
+    /// Deletes an assistant of an API key. The runs it made are kept with their threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key has no such assistant or the database fails.
+    pub fn delete_assistant(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+    ) -> Result<(), AssistantStoreError> {
+        let deleted = self.lock().execute(
+            "DELETE FROM assistants WHERE id = ?1 AND owner IS ?2",
+            params![id, owner],
+        )?;
+        if deleted == 0 {
+            return Err(not_found("assistant", id));
+        }
+
+        Ok(())
+    }
+
+    /// Stores a new thread of an API key with the messages it starts with.
+    pub fn create_thread(
+        &self,
+        thread: &ThreadObject,
+        messages: &[ThreadMessageObject],
+        owner: Option<&str>,
+    ) -> Result<(), AssistantStoreError> {
+        let mut connection = self.lock();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO threads (id, owner, body) VALUES (?1, ?2, ?3)",
+            params![thread.id, owner, serde_json::to_string(thread)?],
+        )?;
+        for message in messages {
+            transaction.execute(
+                "INSERT INTO messages (id, thread_id, body) VALUES (?1, ?2, ?3)",
+                params![message.id, thread.id, serde_json::to_string(message)?],
+            )?;
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Returns a thread of an API key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key has no such thread or the database fails.
+    pub fn thread(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+    ) -> Result<ThreadObject, AssistantStoreError> {
+        let connection = self.lock();
+        find(
+            &connection,
+            "SELECT body FROM threads WHERE id = ?1 AND owner IS ?2",
+            &[&id, &owner],
+        )?
+        .ok_or_else(|| not_found("thread", id))
+    }
+
+    /// Deletes a thread of an API key with its messages and runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key has no such thread or the database fails.
+    pub fn delete_thread(&self, id: &str, owner: Option<&str>) -> Result<(), AssistantStoreError> {
+        let deleted = self.lock().execute(
+            "DELETE FROM threads WHERE id = ?1 AND owner IS ?2",
+            params![id, owner],
+        )?;
+        if deleted == 0 {
+            return Err(not_found("thread", id));
+        }
+
+        Ok(())
+    }
+
+    /// Stores a message, replacing the one with the same id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the API key has no thread of the message or the database fails.
+    pub fn save_message(
+        &self,
+        message: &ThreadMessageObject,
+        owner: Option<&str>,
+    ) -> Result<(), AssistantStoreError> {
+        let connection = self.lock();
+        check_thread(&connection, &message.thread_id, owner)?;
+        connection.execute(
+            "INSERT INTO messages (id, thread_id, body) VALUES (?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET body = ?3",
+            params![
+                message.id,
+                message.thread_id,
+                serde_json::to_string(message)?
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Lists the messages of a thread in the order they were added.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the API key has no such thread or the database fails.
+    pub fn messages(
+        &self,
+        thread_id: &str,
+        owner: Option<&str>,
+    ) -> Result<Vec<ThreadMessageObject>, AssistantStoreError> {
+        let connection = self.lock();
+        check_thread(&connection, thread_id, owner)?;
+        list(
+            &connection,
+            "SELECT body FROM messages WHERE thread_id = ?1 ORDER BY seq",
+            &[&thread_id],
+        )
+    }
+
+    /// Stores a run, replacing the one with the same id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the API key has no thread of the run or the database fails.
+    pub fn save_run(
+        &self,
+        run: &RunObject,
+        owner: Option<&str>,
+    ) -> Result<(), AssistantStoreError> {
+        let connection = self.lock();
+        check_thread(&connection, &run.thread_id, owner)?;
+        connection.execute(
+            "INSERT INTO runs (id, thread_id, body) VALUES (?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET body = ?3",
+            params![run.id, run.thread_id, serde_json::to_string(run)?],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns a run of a thread.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the run does not exist in this thread of the API key or the
+    /// database fails.
+    pub fn run(
+        &self,
+        thread_id: &str,
+        id: &str,
+        owner: Option<&str>,
+    ) -> Result<RunObject, AssistantStoreError> {
+        let connection = self.lock();
+        check_thread(&connection, thread_id, owner)?;
+        find(
+            &connection,
+            "SELECT body FROM runs WHERE id = ?1 AND thread_id = ?2",
+            &[&id, &thread_id],
+        )?
+        .ok_or_else(|| not_found("run", id))
+    }
+
+    /// Lists the runs of a thread in the order they were created.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the API key has no such thread or the database fails.
+    pub fn runs(
+        &self,
+        thread_id: &str,
+        owner: Option<&str>,
+    ) -> Result<Vec<RunObject>, AssistantStoreError> {
+        let connection = self.lock();
+        check_thread(&connection, thread_id, owner)?;
+        list(
+            &connection,
+            "SELECT body FROM runs WHERE thread_id = ?1 ORDER BY seq",
+            &[&thread_id],
+        )
+    }
+
+    /// Marks the runs left queued or in progress by a previous process as failed.
+    fn fail_interrupted_runs(&self) -> Result<(), AssistantStoreError> {
+        let connection = self.lock();
+        let runs: Vec<RunObject> = list(&connection, "SELECT body FROM runs", &[])?;
+        let now = Utc::now().timestamp();
+        for mut run in runs.into_iter().filter(RunObject::is_active) {
+            warn!("Run {} was interrupted by a restart", run.id);
+            run.status = "failed".to_string();
+            run.failed_at = Some(now);
+            run.last_error = Some(RunError {
+                code: "server_error".to_string(),
+                message: "The server stopped during the run".to_string(),
+            });
+            connection.execute(
+                "UPDATE runs SET body = ?2 WHERE id = ?1",
+                params![run.id, serde_json::to_string(&run)?],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn not_found(kind: &'static str, id: &str) -> AssistantStoreError {
+    AssistantStoreError::NotFound {
+        kind,
+        id: id.to_string(),
+    }
+}
+
+/// Checks that a thread exists and belongs to an API key.
+fn check_thread(
+    connection: &Connection,
+    id: &str,
+    owner: Option<&str>,
+) -> Result<(), AssistantStoreError> {
+    connection
+        .query_row(
+            "SELECT 1 FROM threads WHERE id = ?1 AND owner IS ?2",
+            params![id, owner],
+            |_| Ok(()),
+        )
+        .optional()?
+        .ok_or_else(|| not_found("thread", id))
+}
+
+/// Returns the object of the first row of a query, if any.
+fn find<T: DeserializeOwned>(
+    connection: &Connection,
+    sql: &str,
+    params: &[&dyn ToSql],
+) -> Result<Option<T>, AssistantStoreError> {
+    let body: Option<String> = connection
+        .query_row(sql, params, |row| row.get(0))
+        .optional()?;
+
+    Ok(body.map(|body| serde_json::from_str(&body)).transpose()?)
+}
+
+/// Returns the objects of the rows of a query.
+fn list<T: DeserializeOwned>(
+    connection: &Connection,
+    sql: &str,
+    params: &[&dyn ToSql],
+) -> Result<Vec<T>, AssistantStoreError> {
+    let mut statement = connection.prepare(sql)?;
+    let bodies = statement
+        .query_map(params, |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    bodies
+        .iter()
+        .map(|body| Ok(serde_json::from_str(body)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threads_are_invisible_to_other_keys() {
+        let path = std::env::temp_dir().join(format!(
+            "synap-forge-assistants-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let store = AssistantStore::open(&path).unwrap();
+        let thread = ThreadObject {
+            id: "thread_1".to_string(),
+            object: "thread".to_string(),
+            created_at: 0,
+            metadata: Default::default(),
+        };
+        store.create_thread(&thread, &[], Some("sha256:a")).unwrap();
+
+        assert!(store.thread("thread_1", Some("sha256:a")).is_ok());
+        assert!(store.thread("thread_1", Some("sha256:b")).is_err());
+        assert!(store.thread("thread_1", None).is_err());
+        assert!(store.messages("thread_1", Some("sha256:b")).is_err());
+        assert!(store.delete_thread("thread_1", Some("sha256:b")).is_err());
+        assert!(store.delete_thread("thread_1", Some("sha256:a")).is_ok());
+
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use tracing::error;
+
+/// The actor of the actions triggered by a signal instead of a request.
+pub const SIGNAL_ACTOR: &str = "signal";
+
+/// An admin action of the audit log.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// When the action was performed, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// The digest of the admin key the action was requested with, or how it was triggered.
+    pub actor: String,
+    /// What was done, e.g. `POST /admin/reload` or `keys.change`.
+    pub action: String,
+    /// `success`, or why the action failed or was refused.
+    pub outcome: String,
+    pub detail: Option<String>,
+}
+
+/// An append-only SQLite log of the admin actions.
+///
+/// The database refuses to update or delete entries, so the log can only grow.
+pub struct AuditLog {
+    connection: Mutex<Connection>,
+}
+
+impl AuditLog {
+    /// Opens the database, creating it and its table if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the SQLite database file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened or initialised.
+    pub fn open(path: &Path) -> Result<Self, rusqlite::Error> {
+        if let Some(parent) = path.parent() {
+            // A missing directory surfaces as an open error just below
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS audit (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 timestamp INTEGER NOT NULL,
+                 actor TEXT NOT NULL,
+                 action TEXT NOT NULL,
+                 outcome TEXT NOT NULL,
+                 detail TEXT
+             );
+             CREATE INDEX IF NOT EXISTS audit_action ON audit (action);
+             CREATE TRIGGER IF NOT EXISTS audit_no_update BEFORE UPDATE ON audit
+             BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;
+             CREATE TRIGGER IF NOT EXISTS audit_no_delete BEFORE DELETE ON audit
+             BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;",
+        )?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Appends an action to the log.
+    ///
+    /// A failure to write is logged rather than returned, the action itself already
+    /// happened.
+    ///
+    /// # Arguments
+    ///
+    /// * `actor` - Who performed the action.
+    /// * `action` - What was done.
+    /// * `outcome` - `success`, or why the action failed or was refused.
+    /// * `detail` - Additional information about the action.
+    pub fn record(&self, actor: &str, action: &str, outcome: &str, detail: Option<&str>) {
+        let inserted = self.lock().execute(
+            "INSERT INTO audit (timestamp, actor, action, outcome, detail)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![Utc::now().timestamp(), actor, action, outcome, detail],
+        );
+        if let Err(err) = inserted {
+            error!("Error writing the audit log, {action} by {actor} ({outcome}) is not recorded: {err}");
+        }
+    }
+
+    /// Returns the most recent actions first.
+    ///
+    /// # Arguments
+    ///
+    /// * `action` - Only returns the actions with this name.
+    /// * `before` - Only returns the actions with a lower id, to page through the log.
+    /// * `limit` - The maximum number of actions returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database fails.
+    pub fn list(
+        &self,
+        action: Option<&str>,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>, rusqlite::Error> {
+        let connection = self.lock();
+        let mut statement = connection.prepare(
+            "SELECT id, timestamp, actor, action, outcome, detail FROM audit
+             WHERE (?1 IS NULL OR action = ?1) AND (?2 IS NULL OR id < ?2)
+             ORDER BY id DESC LIMIT ?3",
+        )?;
+        let entries = statement
+            .query_map(params![action, before, limit as i64], |row| {
+                Ok(AuditEntry {
+                    id: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    actor: row.get(2)?,
+                    action: row.get(3)?,
+                    outcome: row.get(4)?,
+                    detail: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(entries)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
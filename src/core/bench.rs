@@ -0,0 +1,430 @@
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::core::device_memory::{device_memory, device_name};
+use crate::core::events::GenerationEvent;
+use crate::core::generator::TextGeneration;
+use crate::core::quantization_check::CheckPrompt;
+use crate::core::sampling::SamplingParams;
+use crate::openai::http_entities::AppState;
+
+/// The prompt benchmarked when no prompts file is given.
+const BENCH_PROMPT: &str = "Write a short story about a lighthouse keeper.";
+
+/// The version of the format of the reports, increased when a field changes meaning.
+const REPORT_FORMAT: u32 = 1;
+
+/// The latencies of the generations of a benchmark, in milliseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl LatencySummary {
+    /// Summarizes some durations, all zero without any.
+    fn of(durations: &[Duration]) -> Self {
+        if durations.is_empty() {
+            return Self::default();
+        }
+        let mut millis: Vec<f64> = durations
+            .iter()
+            .map(|duration| duration.as_secs_f64() * 1000.)
+            .collect();
+        millis.sort_by(f64::total_cmp);
+        // The nearest-rank percentile
+        let percentile = |p: f64| millis[((p * millis.len() as f64).ceil() as usize).max(1) - 1];
+
+        Self {
+            mean: millis.iter().sum::<f64>() / millis.len() as f64,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            max: millis[millis.len() - 1],
+        }
+    }
+}
+
+/// The machine-readable results of a benchmark, written as JSON so that a later run can
+/// be compared with it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub format: u32,
+    pub model: String,
+    pub revision: String,
+    pub device: String,
+    /// When the benchmark ran, in seconds since the epoch.
+    pub created_at: i64,
+    pub prompts: usize,
+    /// The number of times every prompt was generated.
+    pub runs: usize,
+    pub max_tokens: i32,
+    pub prompt_tokens: usize,
+    pub generated_tokens: usize,
+    /// The generated tokens per second of generation, prompt processing excluded.
+    pub tokens_per_sec: f64,
+    pub time_to_first_token_ms: LatencySummary,
+    /// The duration of the whole generations.
+    pub latency_ms: LatencySummary,
+    /// The most device memory in use after a generation, `None` on the CPU.
+    pub peak_memory_bytes: Option<u64>,
+}
+
+/// Benchmarks the loaded model.
+///
+/// Every prompt, `{"prompt": ...}` per line of the prompts file, is generated greedily
+/// `runs` times with up to `max_tokens` tokens, after a warmup generation that is not
+/// measured. The time to the first token, the duration of the generations, the decoding
+/// throughput and the peak device memory are reported.
+///
+/// # Arguments
+///
+/// * `state` - The application state holding the loaded model.
+/// * `prompts` - The path of the JSONL prompts, a canned prompt when `None`.
+/// * `runs` - The number of generations of every prompt.
+/// * `max_tokens` - The number of tokens generated at most for every prompt.
+///
+/// # Returns
+///
+/// The report of the benchmark.
+///
+/// # Errors
+///
+/// Returns an error naming the line if the prompts cannot be read or parsed, or an
+/// error if a generation fails.
+pub fn run_bench(
+    state: &AppState,
+    prompts: Option<&Path>,
+    runs: usize,
+    max_tokens: i32,
+) -> anyhow::Result<BenchReport> {
+    let prompts = match prompts {
+        Some(path) => read_prompts(path)?,
+        None => vec![BENCH_PROMPT.to_string()],
+    };
+    let runs = runs.max(1);
+    let params = SamplingParams::default()
+        .with_temperature(Some(0.))
+        .with_max_tokens(Some(max_tokens));
+
+    // The first generation pays for lazy initialisations, such as the kernels
+    measure(state, &params, &prompts[0])?;
+
+    let mut first_tokens = Vec::new();
+    let mut latencies = Vec::new();
+    let mut prompt_tokens = 0;
+    let mut generated_tokens = 0;
+    let mut decoding = Duration::ZERO;
+    let mut peak_memory_bytes = None;
+    for run in 0..runs {
+        for prompt in &prompts {
+            let sample = measure(state, &params, prompt)?;
+            prompt_tokens += sample.prompt_tokens;
+            generated_tokens += sample.generated_tokens;
+            if let Some(first_token) = sample.first_token {
+                first_tokens.push(first_token);
+                decoding += sample.latency.saturating_sub(first_token);
+            }
+            latencies.push(sample.latency);
+            if let Some(memory) = device_memory(&state.device) {
+                peak_memory_bytes = peak_memory_bytes.max(Some(memory.used_bytes));
+            }
+        }
+        info!("Benchmark run {} of {runs} done", run + 1);
+    }
+
+    // The first token of every generation comes with the prompt processing
+    let decoded_tokens = generated_tokens.saturating_sub(first_tokens.len());
+    let settings = state.settings.current();
+    Ok(BenchReport {
+        format: REPORT_FORMAT,
+        model: settings.model.id.clone(),
+        revision: state.model.revision(),
+        device: device_name(&state.device),
+        created_at: Utc::now().timestamp(),
+        prompts: prompts.len(),
+        runs,
+        max_tokens,
+        prompt_tokens,
+        generated_tokens,
+        tokens_per_sec: match decoding.as_secs_f64() {
+            secs if secs > 0. => decoded_tokens as f64 / secs,
+            _ => 0.,
+        },
+        time_to_first_token_ms: LatencySummary::of(&first_tokens),
+        latency_ms: LatencySummary::of(&latencies),
+        peak_memory_bytes,
+    })
+}
+
+/// The measures of one generation.
+struct Sample {
+    prompt_tokens: usize,
+    generated_tokens: usize,
+    /// The time to the first generated text, `None` when nothing was generated.
+    first_token: Option<Duration>,
+    latency: Duration,
+}
+
+/// Generates a prompt and measures the generation.
+fn measure(state: &AppState, params: &SamplingParams, prompt: &str) -> anyhow::Result<Sample> {
+    let text_gen = TextGeneration::from_state(state.clone(), params)?;
+    let start = Instant::now();
+    let mut first_token = None;
+    let mut usage = None;
+    text_gen.generate_streaming(prompt.to_string(), |event| {
+        match event {
+            GenerationEvent::TokenDelta(_) if first_token.is_none() => {
+                first_token = Some(start.elapsed())
+            }
+            GenerationEvent::UsageUpdate(update) => usage = Some(update),
+            _ => {}
+        }
+        Ok(())
+    })?;
+    let usage = usage.unwrap_or_default();
+
+    Ok(Sample {
+        prompt_tokens: usage.prompt_tokens,
+        generated_tokens: usage.completion_tokens,
+        first_token,
+        latency: start.elapsed(),
+    })
+}
+
+/// Reads the prompts of a JSONL file.
+fn read_prompts(path: &Path) -> anyhow::Result<Vec<String>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Error opening prompts {}", path.display()))?;
+    let mut prompts = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Error reading {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let prompt: CheckPrompt = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid prompt on line {}", index + 1))?;
+        prompts.push(prompt.prompt);
+    }
+    if prompts.is_empty() {
+        anyhow::bail!("{} holds no prompt", path.display());
+    }
+
+    Ok(prompts)
+}
+
+/// Reads a report written by an earlier benchmark.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not a report of this format.
+pub fn read_report(path: &Path) -> anyhow::Result<BenchReport> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Error reading the report {}", path.display()))?;
+    let report: BenchReport = serde_json::from_str(&json)
+        .with_context(|| format!("Invalid report {}", path.display()))?;
+    if report.format != REPORT_FORMAT {
+        anyhow::bail!(
+            "{} is a report of format {}, expected {REPORT_FORMAT}",
+            path.display(),
+            report.format
+        );
+    }
+
+    Ok(report)
+}
+
+/// The change of one metric between a baseline and a new benchmark.
+#[derive(Debug, PartialEq)]
+pub struct MetricChange {
+    pub metric: &'static str,
+    pub baseline: f64,
+    pub current: f64,
+    /// How much worse the metric got, in percent of the baseline, negative when it
+    /// improved.
+    pub regression_percent: f64,
+    /// Whether the regression exceeds the threshold.
+    pub regressed: bool,
+}
+
+/// The comparison of a benchmark with a baseline.
+#[derive(Debug)]
+pub struct BenchComparison {
+    pub threshold_percent: f64,
+    pub changes: Vec<MetricChange>,
+}
+
+impl BenchComparison {
+    /// Whether no metric regressed beyond the threshold.
+    pub fn passed(&self) -> bool {
+        !self.changes.iter().any(|change| change.regressed)
+    }
+}
+
+impl fmt::Display for BenchComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(
+                f,
+                "{:<28} {:>14.2} {:>14.2} {:>+8.1}%  {}",
+                change.metric,
+                change.baseline,
+                change.current,
+                change.regression_percent,
+                if change.regressed { "REGRESSED" } else { "ok" }
+            )?;
+        }
+        writeln!(
+            f,
+            "bench compare {} (threshold {}%)",
+            if self.passed() { "passed" } else { "FAILED" },
+            self.threshold_percent
+        )
+    }
+}
+
+/// Compares a benchmark with a baseline.
+///
+/// Lower throughput and higher latencies or memory count as regressions. Metrics that
+/// were not measured in both reports, such as the memory on the CPU, are left out.
+///
+/// # Arguments
+///
+/// * `baseline` - The report of the reference benchmark.
+/// * `current` - The report of the new benchmark.
+/// * `threshold_percent` - How much worse, in percent, a metric may get.
+pub fn compare_reports(
+    baseline: &BenchReport,
+    current: &BenchReport,
+    threshold_percent: f64,
+) -> BenchComparison {
+    let mut metrics = vec![
+        (
+            "tokens_per_sec",
+            baseline.tokens_per_sec,
+            current.tokens_per_sec,
+            false,
+        ),
+        (
+            "time_to_first_token_ms.p50",
+            baseline.time_to_first_token_ms.p50,
+            current.time_to_first_token_ms.p50,
+            true,
+        ),
+        (
+            "time_to_first_token_ms.p95",
+            baseline.time_to_first_token_ms.p95,
+            current.time_to_first_token_ms.p95,
+            true,
+        ),
+        (
+            "latency_ms.p50",
+            baseline.latency_ms.p50,
+            current.latency_ms.p50,
+            true,
+        ),
+        (
+            "latency_ms.p95",
+            baseline.latency_ms.p95,
+            current.latency_ms.p95,
+            true,
+        ),
+    ];
+    if let (Some(baseline), Some(current)) = (baseline.peak_memory_bytes, current.peak_memory_bytes)
+    {
+        metrics.push(("peak_memory_bytes", baseline as f64, current as f64, true));
+    }
+
+    let changes = metrics
+        .into_iter()
+        .filter(|&(_, baseline, _, _)| baseline > 0.)
+        .map(|(metric, baseline, current, lower_is_better)| {
+            let change = (current - baseline) / baseline * 100.;
+            let regression_percent = if lower_is_better { change } else { -change };
+            MetricChange {
+                metric,
+                baseline,
+                current,
+                regression_percent,
+                regressed: regression_percent > threshold_percent,
+            }
+        })
+        .collect();
+
+    BenchComparison {
+        threshold_percent,
+        changes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(tokens_per_sec: f64, latency_p95: f64, memory: Option<u64>) -> BenchReport {
+        let latency = LatencySummary {
+            mean: 100.,
+            p50: 100.,
+            p95: latency_p95,
+            max: latency_p95,
+        };
+        BenchReport {
+            format: REPORT_FORMAT,
+            model: "model".to_string(),
+            revision: "main".to_string(),
+            device: "cpu".to_string(),
+            created_at: 0,
+            prompts: 1,
+            runs: 1,
+            max_tokens: 16,
+            prompt_tokens: 8,
+            generated_tokens: 16,
+            tokens_per_sec,
+            time_to_first_token_ms: latency,
+            latency_ms: latency,
+            peak_memory_bytes: memory,
+        }
+    }
+
+    #[test]
+    fn summarizes_latencies() {
+        let durations: Vec<Duration> = (1..=20).map(Duration::from_millis).collect();
+        let summary = LatencySummary::of(&durations);
+        assert_eq!((summary.p50, summary.p95, summary.max), (10., 19., 20.));
+        assert!((summary.mean - 10.5).abs() < 1e-9);
+        assert_eq!(LatencySummary::of(&[]), LatencySummary::default());
+    }
+
+    #[test]
+    fn flags_regressions_beyond_the_threshold() {
+        let baseline = report(50., 200., None);
+
+        let comparison = compare_reports(&baseline, &report(47., 210., Some(1)), 10.);
+        assert!(comparison.passed());
+        assert!(comparison
+            .changes
+            .iter()
+            .all(|change| change.metric != "peak_memory_bytes"));
+
+        let comparison = compare_reports(&baseline, &report(40., 200., None), 10.);
+        let regressed: Vec<_> = comparison
+            .changes
+            .iter()
+            .filter(|change| change.regressed)
+            .map(|change| change.metric)
+            .collect();
+        assert_eq!(regressed, vec!["tokens_per_sec"]);
+
+        let comparison = compare_reports(&baseline, &report(60., 250., None), 10.);
+        assert!(!comparison.passed());
+        assert!(comparison.changes[0].regression_percent < 0.);
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::config::{HubSettings, ServerConfig};
+use crate::core::access_log::digest;
+use crate::core::load_model::{checkpoint_files, QUANTIZED_WEIGHTS, WEIGHT_INDEX};
+use crate::core::quantize::{write_quantized, Quantization};
+
+/// The version of the layout of the bundles, bumped when it changes.
+const BUNDLE_FORMAT: u32 = 1;
+
+/// The name of the manifest, the first entry of every bundle.
+const MANIFEST: &str = "manifest.json";
+
+/// What a bundle holds, written as its first entry so that it is read before the files
+/// it describes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BundleManifest {
+    /// The version of the layout of the bundle.
+    pub format: u32,
+    /// The Hub id of the model, the name it is served under.
+    pub model_id: String,
+    /// The commit of the model the bundle was created from, or `local`.
+    pub revision: String,
+    /// The quantization of `model.gguf`, `None` for full-precision weights.
+    pub quantize: Option<Quantization>,
+    pub created_at: i64,
+    pub files: Vec<BundleFile>,
+}
+
+/// A file of a bundle with the digest it is verified against when the bundle is unpacked.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BundleFile {
+    pub name: String,
+    pub bytes: u64,
+    /// The hex SHA-256 digest of the content of the file.
+    pub sha256: String,
+}
+
+/// Packages the configuration, the tokenizer and the weights of the configured model into
+/// a single archive that can be carried to a machine without network access.
+///
+/// The archive is a tar file whose first entry is `manifest.json`, listing the size and
+/// SHA-256 digest of every other entry. With `quantize`, the full-precision weights are
+/// quantized once into `model.gguf`, which is loaded as is, instead of being quantized
+/// every time the server starts.
+///
+/// # Arguments
+///
+/// * `token` - The Hugging Face token, unused when the model is not read from the Hub.
+/// * `settings` - The server configuration naming the model.
+/// * `quantize` - The quantization to apply to the weights, if any.
+/// * `output` - The archive to write.
+///
+/// # Returns
+///
+/// The manifest of the bundle.
+///
+/// # Errors
+///
+/// Returns an error if the files of the model cannot be fetched, a quantization is asked
+/// for weights that are already quantized, or the archive cannot be written.
+pub fn create_bundle(
+    token: String,
+    settings: ServerConfig,
+    quantize: Option<Quantization>,
+    output: &Path,
+) -> anyhow::Result<BundleManifest> {
+    let checkpoint = checkpoint_files(token, settings)?;
+    let mut files = checkpoint.metadata;
+
+    // Kept until the archive is written, then removed
+    let quantized = output.with_extension("gguf.partial");
+    match quantize {
+        Some(_)
+            if checkpoint.prequantized
+                || checkpoint
+                    .weights
+                    .iter()
+                    .any(|(name, _)| name == QUANTIZED_WEIGHTS) =>
+        {
+            bail!(
+                "The weights of {} are already quantized",
+                checkpoint.model_id
+            );
+        }
+        Some(quantization) => {
+            let shards: Vec<PathBuf> = checkpoint
+                .weights
+                .into_iter()
+                .filter(|(name, _)| name != WEIGHT_INDEX)
+                .map(|(_, path)| path)
+                .collect();
+            info!(
+                "Quantizing the weights of {} to {}",
+                checkpoint.model_id,
+                quantization.as_str()
+            );
+            write_quantized(&shards, &checkpoint.config, quantization, &quantized)?;
+            files.push((QUANTIZED_WEIGHTS.to_string(), quantized.clone()));
+        }
+        None => files.extend(checkpoint.weights),
+    }
+
+    let result = (|| -> anyhow::Result<BundleManifest> {
+        let mut manifest = BundleManifest {
+            format: BUNDLE_FORMAT,
+            model_id: checkpoint.model_id,
+            revision: checkpoint.revision,
+            quantize,
+            created_at: Utc::now().timestamp(),
+            files: Vec::with_capacity(files.len()),
+        };
+        for (name, path) in &files {
+            let (bytes, sha256) = hash_file(path)?;
+            manifest.files.push(BundleFile {
+                name: name.clone(),
+                bytes,
+                sha256,
+            });
+        }
+        write_archive(&manifest, &files, output)?;
+
+        Ok(manifest)
+    })();
+    let _ = fs::remove_file(&quantized);
+
+    result
+}
+
+/// Verifies a bundle and unpacks it into `<cache>/bundles/<digest>`, the digest being the
+/// one of its manifest.
+///
+/// Every entry is checked against the size and SHA-256 digest the manifest lists while it
+/// is unpacked, and the directory is only renamed to its final name once all of them
+/// matched, so that a bundle unpacked before is reused without being read again.
+///
+/// # Arguments
+///
+/// * `path` - The archive written by `create_bundle`.
+/// * `hub` - The Hub settings holding the cache directory.
+///
+/// # Returns
+///
+/// The directory holding the files of the model, laid out like a Hub repository, with the
+/// manifest of the bundle.
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be read, does not start with a manifest of a
+/// supported format, or holds a file that is not in the manifest, is missing or does not
+/// match its size or digest.
+pub fn open_bundle(path: &Path, hub: &HubSettings) -> anyhow::Result<(PathBuf, BundleManifest)> {
+    let file = File::open(path).with_context(|| format!("Error opening {}", path.display()))?;
+    let mut archive = tar::Archive::new(BufReader::new(file));
+    let mut entries = archive.entries()?;
+
+    let (manifest, manifest_digest) = match entries.next() {
+        Some(entry) => {
+            let mut entry = entry?;
+            if entry.path()?.as_ref() != Path::new(MANIFEST) {
+                bail!("{} does not start with {MANIFEST}", path.display());
+            }
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            let manifest: BundleManifest = serde_json::from_slice(&content)
+                .with_context(|| format!("Invalid {MANIFEST} in {}", path.display()))?;
+            (manifest, digest(&content))
+        }
+        None => bail!("{} is empty", path.display()),
+    };
+    if manifest.format != BUNDLE_FORMAT {
+        bail!(
+            "{} has format {}, expected {BUNDLE_FORMAT}",
+            path.display(),
+            manifest.format
+        );
+    }
+    // The files are unpacked under their name, which must not lead out of the directory
+    if let Some(file) = manifest.files.iter().find(|file| {
+        file.name.is_empty() || file.name.starts_with('.') || file.name.contains(['/', '\\'])
+    }) {
+        bail!("Invalid file name '{}' in {MANIFEST}", file.name);
+    }
+
+    let directory = hub
+        .cache_dir()
+        .join("bundles")
+        .join(&manifest_digest["sha256:".len()..][..16]);
+    if directory.is_dir() {
+        return Ok((directory, manifest));
+    }
+
+    let mut partial = directory.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let _ = fs::remove_dir_all(&partial);
+    fs::create_dir_all(&partial)
+        .with_context(|| format!("Error creating {}", partial.display()))?;
+
+    let result = (|| -> anyhow::Result<()> {
+        let mut unpacked = HashSet::new();
+        for entry in entries {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let Some(expected) = manifest.files.iter().find(|file| file.name == name) else {
+                bail!("{name} is not listed in {MANIFEST}");
+            };
+            if !unpacked.insert(name.clone()) {
+                bail!("{name} appears twice in the bundle");
+            }
+
+            let mut writer = HashingWriter::new(BufWriter::new(File::create(partial.join(&name))?));
+            io::copy(&mut entry, &mut writer)?;
+            let (bytes, sha256) = writer.finish()?;
+            if bytes != expected.bytes || sha256 != expected.sha256 {
+                bail!("{name} does not match its digest in {MANIFEST}, the bundle is corrupted or was tampered with");
+            }
+        }
+        if let Some(missing) = manifest
+            .files
+            .iter()
+            .find(|file| !unpacked.contains(&file.name))
+        {
+            bail!("{} is missing from the bundle", missing.name);
+        }
+
+        Ok(())
+    })();
+    if let Err(err) = result {
+        let _ = fs::remove_dir_all(&partial);
+        return Err(err.context(format!("Error unpacking {}", path.display())));
+    }
+    fs::rename(&partial, &directory)?;
+    info!(
+        "Unpacked the bundle of {} to {}",
+        manifest.model_id,
+        directory.display()
+    );
+
+    Ok((directory, manifest))
+}
+
+/// Writes the manifest then the files to a tar archive, through a partial file renamed
+/// once complete.
+fn write_archive(
+    manifest: &BundleManifest,
+    files: &[(String, PathBuf)],
+    output: &Path,
+) -> anyhow::Result<()> {
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    let result = (|| -> anyhow::Result<()> {
+        let file = File::create(&partial)
+            .with_context(|| format!("Error creating {}", partial.display()))?;
+        let mut archive = tar::Builder::new(BufWriter::new(file));
+
+        let content = serde_json::to_vec_pretty(manifest)?;
+        archive.append_data(
+            &mut entry_header(content.len() as u64, manifest.created_at),
+            MANIFEST,
+            content.as_slice(),
+        )?;
+        for (name, path) in files {
+            let file = File::open(path)?;
+            let bytes = file.metadata()?.len();
+            archive.append_data(
+                &mut entry_header(bytes, manifest.created_at),
+                name,
+                BufReader::new(file),
+            )?;
+        }
+        archive.into_inner()?.flush()?;
+
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+        return result;
+    }
+    fs::rename(&partial, output)?;
+
+    Ok(())
+}
+
+/// The header of a regular file entry.
+fn entry_header(bytes: u64, created_at: i64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes);
+    header.set_mode(0o644);
+    header.set_mtime(created_at.max(0) as u64);
+    header
+}
+
+/// The size and hex SHA-256 digest of a file, read as a stream.
+pub(crate) fn hash_file(path: &Path) -> anyhow::Result<(u64, String)> {
+    let file = File::open(path).with_context(|| format!("Error opening {}", path.display()))?;
+    let mut writer = HashingWriter::new(io::sink());
+    io::copy(&mut BufReader::new(file), &mut writer)?;
+
+    Ok(writer.finish()?)
+}
+
+/// A writer counting and hashing the bytes it forwards.
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Flushes the inner writer and returns the number of bytes and their hex digest.
+    fn finish(mut self) -> io::Result<(u64, String)> {
+        self.inner.flush()?;
+        let hex = self
+            .hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        Ok((self.bytes, hex))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,252 @@
+use std::fmt;
+use std::fmt::Write;
+
+use chrono::Local;
+use minijinja::{context, Environment, Error, ErrorKind};
+use minijinja_contrib::pycompat::unknown_method_callback;
+use serde::Serialize;
+
+/// The template of the prompt when neither the request nor the model has one: the
+/// messages as `role:content`, separated by spaces.
+pub const DEFAULT_PRESET: &str = "plain";
+
+/// The chat templates built into the server, by name, for the common model families.
+const PRESETS: &[(&str, &str)] = &[
+    (
+        "plain",
+        "{% for message in messages %}{% if not loop.first %} {% endif %}\
+         {{ message.role }}:{{ message.content }}{% endfor %}",
+    ),
+    (
+        "chatml",
+        "{% for message in messages %}<|im_start|>{{ message.role }}\n{{ message.content }}\
+         <|im_end|>\n{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}",
+    ),
+    (
+        "llama3",
+        "{% for message in messages %}<|start_header_id|>{{ message.role }}<|end_header_id|>\
+         \n\n{{ message.content | trim }}<|eot_id|>{% endfor %}{% if add_generation_prompt %}\
+         <|start_header_id|>assistant<|end_header_id|>\n\n{% endif %}",
+    ),
+    (
+        "mistral",
+        "{% if messages and messages[0].role == 'system' %}\
+         {% set system = messages[0].content ~ '\\n\\n' %}{% set turns = messages[1:] %}\
+         {% else %}{% set system = '' %}{% set turns = messages %}{% endif %}\
+         {% for message in turns %}{% if message.role == 'user' %}[INST] \
+         {% if loop.first %}{{ system }}{% endif %}{{ message.content }} [/INST]\
+         {% else %}{{ message.content }}{{ eos_token }}{% endif %}{% endfor %}",
+    ),
+    (
+        "gemma",
+        "{% for message in messages %}<start_of_turn>\
+         {{ 'model' if message.role == 'assistant' else message.role }}\n\
+         {{ message.content | trim }}<end_of_turn>\n{% endfor %}\
+         {% if add_generation_prompt %}<start_of_turn>model\n{% endif %}",
+    ),
+    (
+        "phi3",
+        "{% for message in messages %}<|{{ message.role }}|>\n{{ message.content }}<|end|>\n\
+         {% endfor %}{% if add_generation_prompt %}<|assistant|>\n{% endif %}",
+    ),
+];
+
+/// Errors returned when resolving or rendering a chat template.
+#[derive(Debug)]
+pub enum ChatTemplateError {
+    /// The name is neither a preset nor a template.
+    UnknownPreset(String),
+    /// The template does not parse.
+    Syntax(String),
+    /// The template failed on the messages, e.g. with `raise_exception`.
+    Render(String),
+}
+
+impl fmt::Display for ChatTemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownPreset(name) => write!(
+                f,
+                "Unknown chat template preset '{name}', expected a template or one of: {}",
+                PRESETS
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::Syntax(reason) => write!(f, "Invalid chat template: {reason}"),
+            Self::Render(reason) => write!(f, "The chat template failed: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ChatTemplateError {}
+
+/// The special tokens a template may refer to, from `tokenizer_config.json`.
+#[derive(Clone, Debug, Default)]
+pub struct TemplateTokens {
+    pub bos_token: String,
+    pub eos_token: String,
+    pub pad_token: String,
+    /// Whether the tokenizer adds `bos_token` when it encodes the prompt, in which case
+    /// the one written by the template is removed so that the prompt does not start with
+    /// two.
+    pub bos_added: bool,
+}
+
+#[derive(Serialize)]
+struct TemplateMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+/// A Jinja chat template, turning the messages of a chat completion into the prompt.
+///
+/// Templates follow the conventions of the `chat_template` of Hugging Face tokenizers:
+/// they read `messages`, `bos_token`, `eos_token` and `add_generation_prompt`, may call
+/// `raise_exception` and `strftime_now` and the Python string and dict methods, and are
+/// rendered with `trim_blocks` and `lstrip_blocks`. The templates bundled with models
+/// therefore render as they do with `transformers`.
+#[derive(Clone, Debug)]
+pub struct ChatTemplate {
+    source: String,
+}
+
+impl ChatTemplate {
+    /// Returns a template built into the server.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the preset, e.g. `chatml` or `llama3`.
+    pub fn preset(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|(_, source)| Self {
+                source: source.to_string(),
+            })
+    }
+
+    /// Parses a template.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The Jinja source of the template.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Syntax` error if the template does not parse.
+    pub fn parse(source: &str) -> Result<Self, ChatTemplateError> {
+        environment()
+            .template_from_str(source)
+            .map_err(|err| ChatTemplateError::Syntax(describe(&err)))?;
+
+        Ok(Self {
+            source: source.to_string(),
+        })
+    }
+
+    /// Resolves the `chat_template` of a request: a preset name, or else a template.
+    ///
+    /// # Arguments
+    ///
+    /// * `spec` - The name of a preset or the Jinja source of a template.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is not a preset and not a valid template. A value
+    /// without any `{{` or `{%` is taken as a misspelled preset.
+    pub fn resolve(spec: &str) -> Result<Self, ChatTemplateError> {
+        if let Some(preset) = Self::preset(spec.trim()) {
+            return Ok(preset);
+        }
+        if !spec.contains("{{") && !spec.contains("{%") {
+            return Err(ChatTemplateError::UnknownPreset(spec.trim().to_string()));
+        }
+
+        Self::parse(spec)
+    }
+
+    /// Renders the prompt of a conversation, ending with the start of the assistant turn.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The roles and contents of the messages, in order.
+    /// * `tokens` - The special tokens the template may refer to.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `Render` error if the template fails on the messages.
+    pub fn render(
+        &self,
+        messages: &[(String, String)],
+        tokens: &TemplateTokens,
+    ) -> Result<String, ChatTemplateError> {
+        let messages: Vec<_> = messages
+            .iter()
+            .map(|(role, content)| TemplateMessage { role, content })
+            .collect();
+
+        let prompt = environment()
+            .render_str(
+                &self.source,
+                context! {
+                    messages => messages,
+                    bos_token => tokens.bos_token.as_str(),
+                    eos_token => tokens.eos_token.as_str(),
+                    pad_token => tokens.pad_token.as_str(),
+                    add_generation_prompt => true,
+                },
+            )
+            .map_err(|err| ChatTemplateError::Render(describe(&err)))?;
+
+        Ok(match prompt.strip_prefix(tokens.bos_token.as_str()) {
+            Some(rest) if tokens.bos_added && !tokens.bos_token.is_empty() => rest.to_string(),
+            _ => prompt,
+        })
+    }
+}
+
+impl Default for ChatTemplate {
+    fn default() -> Self {
+        Self::preset(DEFAULT_PRESET).expect("the default preset is built in")
+    }
+}
+
+/// The environment of the templates, with the settings and functions of Hugging Face.
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_unknown_method_callback(unknown_method_callback);
+    env.add_function(
+        "raise_exception",
+        |message: String| -> Result<String, Error> {
+            Err(Error::new(ErrorKind::InvalidOperation, message))
+        },
+    );
+    // Llama 3.1 and later templates write the current date into the system prompt
+    env.add_function("strftime_now", |format: String| -> Result<String, Error> {
+        let mut formatted = String::new();
+        write!(formatted, "{}", Local::now().format(&format)).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("invalid date format '{format}'"),
+            )
+        })?;
+        Ok(formatted)
+    });
+    env
+}
+
+/// The message of a template error, with its detail and line when known.
+fn describe(err: &Error) -> String {
+    let mut message = match err.detail() {
+        Some(detail) => detail.to_string(),
+        None => err.kind().to_string(),
+    };
+    if let Some(line) = err.line() {
+        message.push_str(&format!(" (line {line})"));
+    }
+    message
+}
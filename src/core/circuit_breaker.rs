@@ -0,0 +1,264 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::core::generator::TextGeneration;
+use crate::core::sampling::SamplingParams;
+use crate::openai::http_entities::AppState;
+
+/// The prompt of the generation checking the reloaded model before requests are served again.
+const WARMUP_PROMPT: &str = "Hello";
+
+/// The number of tokens generated by the warmup generation.
+const WARMUP_TOKENS: i32 = 4;
+
+/// Whether requests are sent to the model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests are served.
+    Closed,
+    /// The model failed repeatedly and is being reloaded, requests fail right away.
+    Open,
+}
+
+impl CircuitState {
+    /// The name of the state reported by the health endpoint.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+        }
+    }
+}
+
+/// The error returned for requests received while the circuit is open.
+#[derive(Debug, Clone)]
+pub struct CircuitOpen {
+    /// How long the circuit has been open.
+    pub open_for: Duration,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The model is recovering from repeated failures since {} s, retry later",
+            self.open_for.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+/// Stops sending requests to a model that keeps failing until it was reloaded.
+///
+/// Generations report whether the model ran, and `failure_threshold` consecutive
+/// failures of the backend, such as CUDA errors or out of memory errors, open the
+/// circuit. While it is open, requests fail right away instead of queueing behind a
+/// broken model, and the recovery task reloads the weights. The circuit closes again
+/// once a warmup generation on the reloaded model succeeds.
+pub struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+    failure_threshold: u32,
+    tripped: Notify,
+}
+
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { since: Instant },
+}
+
+impl CircuitBreaker {
+    /// Creates a closed circuit breaker.
+    ///
+    /// # Arguments
+    ///
+    /// * `failure_threshold` - The number of consecutive failures opening the circuit.
+    pub fn new(failure_threshold: u32) -> Self {
+        Self {
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+            failure_threshold: failure_threshold.max(1),
+            tripped: Notify::new(),
+        }
+    }
+
+    /// Whether requests are currently sent to the model.
+    pub fn state(&self) -> CircuitState {
+        match *self.lock() {
+            BreakerState::Closed { .. } => CircuitState::Closed,
+            BreakerState::Open { .. } => CircuitState::Open,
+        }
+    }
+
+    /// Fails if the circuit is open.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CircuitOpen` while the model is being recovered.
+    pub fn check(&self) -> Result<(), CircuitOpen> {
+        match *self.lock() {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { since } => Err(CircuitOpen {
+                open_for: since.elapsed(),
+            }),
+        }
+    }
+
+    /// Records a generation the model completed, resetting the failure count.
+    pub fn record_success(&self) {
+        if let BreakerState::Closed { failures } = &mut *self.lock() {
+            *failures = 0;
+        }
+    }
+
+    /// Records a generation the backend failed, opening the circuit at the threshold.
+    ///
+    /// # Returns
+    ///
+    /// `true` if this failure opened the circuit.
+    pub fn record_failure(&self) -> bool {
+        let mut state = self.lock();
+        let BreakerState::Closed { failures } = &mut *state else {
+            return false;
+        };
+        *failures += 1;
+        if *failures < self.failure_threshold {
+            return false;
+        }
+
+        warn!(
+            "Opening the circuit after {} consecutive backend failures",
+            failures
+        );
+        *state = BreakerState::Open {
+            since: Instant::now(),
+        };
+        self.tripped.notify_one();
+        true
+    }
+
+    /// Closes the circuit once the model was recovered.
+    pub fn close(&self) {
+        let mut state = self.lock();
+        if let BreakerState::Open { since } = *state {
+            info!("Closing the circuit after {:.2?}", since.elapsed());
+        }
+        *state = BreakerState::Closed { failures: 0 };
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether a generation error comes from the backend running the model.
+///
+/// Rejected prompts, deadlines and dropped clients are not failures of the model, only
+/// errors raised by the tensor operations are.
+///
+/// # Arguments
+///
+/// * `err` - The error of the generation.
+pub fn is_backend_failure(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<candle_core::Error>().is_some()
+}
+
+/// Parts of the messages of the device errors that may not happen again when the step
+/// is retried, such as a kernel launch timing out or memory held by another generation.
+const TRANSIENT_MARKERS: &[&str] = &[
+    "out of memory",
+    "launch_timeout",
+    "launch_out_of_resources",
+    "not_ready",
+    "busy",
+    "cublas_status_alloc_failed",
+    "cublas_status_execution_failed",
+    "command buffer",
+];
+
+/// The number of generation steps retried after a transient device error since the start.
+static STEP_RETRIES: AtomicU64 = AtomicU64::new(0);
+
+/// Whether a generation error is a backend failure that may succeed when retried.
+///
+/// Shape or dtype mismatches fail the same way on every attempt and are not retried.
+///
+/// # Arguments
+///
+/// * `err` - The error of the generation step.
+pub fn is_transient_failure(err: &anyhow::Error) -> bool {
+    is_backend_failure(err)
+        && err.chain().any(|cause| {
+            let message = cause.to_string().to_lowercase();
+            TRANSIENT_MARKERS
+                .iter()
+                .any(|marker| message.contains(marker))
+        })
+}
+
+/// Counts a generation step retried after a transient device error.
+pub fn record_step_retry() {
+    STEP_RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of generation steps retried since the start, reported by `/metrics`.
+pub fn step_retries() -> u64 {
+    STEP_RETRIES.load(Ordering::Relaxed)
+}
+
+/// Recovers the model every time the circuit opens.
+///
+/// The weights are reloaded, which also frees the device buffers held by the failed
+/// model, and a short warmup generation runs on the reloaded model. The circuit
+/// closes once the warmup succeeds, otherwise the recovery is retried after
+/// `retry_delay`.
+///
+/// # Arguments
+///
+/// * `state` - The application state holding the model.
+/// * `breaker` - The circuit breaker of the model.
+/// * `retry_delay` - How long to wait before retrying a failed recovery.
+///
+/// # Returns
+///
+/// The handle of the background task.
+pub fn spawn_recovery(
+    state: AppState,
+    breaker: Arc<CircuitBreaker>,
+    retry_delay: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            breaker.tripped.notified().await;
+
+            loop {
+                let state = state.clone();
+                let recovered = tokio::task::spawn_blocking(move || recover(state)).await;
+                match recovered {
+                    Ok(Ok(())) => {
+                        breaker.close();
+                        break;
+                    }
+                    Ok(Err(err)) => error!("The model could not be recovered: {err}"),
+                    Err(err) => error!("The model recovery panicked: {err}"),
+                }
+                tokio::time::sleep(retry_delay).await;
+            }
+        }
+    })
+}
+
+fn recover(state: AppState) -> anyhow::Result<()> {
+    state.model.reload()?;
+
+    let params = SamplingParams::default().with_max_tokens(Some(WARMUP_TOKENS));
+    TextGeneration::from_state_unchecked(state, &params)?.generate(WARMUP_PROMPT.to_string())?;
+    info!("Warmup generation succeeded on the reloaded model");
+
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+
+use serde::Serialize;
+use tokenizers::Tokenizer;
+
+/// The instructions placed before the text when the request has none.
+const DEFAULT_INSTRUCTIONS: &str = "Classify the text into one of the labels.";
+
+/// Errors returned when preparing the labels of a classification.
+#[derive(Debug)]
+pub enum ClassificationError {
+    /// The label at this index has no token.
+    EmptyLabel(usize),
+    /// Two labels start with the same token, so a single token cannot tell them apart.
+    AmbiguousLabels {
+        first: String,
+        second: String,
+    },
+    Tokenizer(String),
+}
+
+impl fmt::Display for ClassificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyLabel(index) => write!(f, "Label {index} is empty"),
+            Self::AmbiguousLabels { first, second } => write!(
+                f,
+                "Labels '{first}' and '{second}' start with the same token, use labels whose first word differs"
+            ),
+            Self::Tokenizer(err) => write!(f, "Error tokenizing the labels: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ClassificationError {}
+
+/// The probability of one of the candidate labels.
+#[derive(Clone, Debug, Serialize)]
+pub struct LabelScore {
+    pub label: String,
+    pub probability: f32,
+}
+
+/// The labels of a classification, each identified by its first token.
+///
+/// Classification is constrained single-token decoding: the model sees the text and
+/// the labels, and the logits of its next token are compared over the first tokens of
+/// the labels only. One forward pass ranks every label, whatever their number.
+pub struct LabelSet {
+    labels: Vec<String>,
+    tokens: Vec<u32>,
+}
+
+impl LabelSet {
+    /// Tokenizes the candidate labels.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokenizer` - The tokenizer of the served model.
+    /// * `labels` - The candidate labels.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a label is empty or two labels start with the same token.
+    pub fn new(tokenizer: &Tokenizer, labels: &[String]) -> Result<Self, ClassificationError> {
+        let mut tokens: Vec<u32> = Vec::with_capacity(labels.len());
+        for (index, label) in labels.iter().enumerate() {
+            // The label follows "Label:" in the prompt, so it is tokenized after a space
+            let encoding = tokenizer
+                .encode(format!(" {}", label.trim()), false)
+                .map_err(|e| ClassificationError::Tokenizer(e.to_string()))?;
+            let Some(&token) = encoding
+                .get_ids()
+                .first()
+                .filter(|_| !label.trim().is_empty())
+            else {
+                return Err(ClassificationError::EmptyLabel(index));
+            };
+            if let Some(other) = tokens.iter().position(|&t| t == token) {
+                return Err(ClassificationError::AmbiguousLabels {
+                    first: labels[other].clone(),
+                    second: label.clone(),
+                });
+            }
+            tokens.push(token);
+        }
+
+        Ok(Self {
+            labels: labels.to_vec(),
+            tokens,
+        })
+    }
+
+    /// Builds the prompt asking the model for the label of a text.
+    ///
+    /// # Arguments
+    ///
+    /// * `instructions` - What the labels mean, replacing the default instructions.
+    /// * `text` - The text to classify.
+    pub fn prompt(&self, instructions: Option<&str>, text: &str) -> String {
+        format!(
+            "{}\nLabels: {}\n\nText: {}\nLabel:",
+            instructions.unwrap_or(DEFAULT_INSTRUCTIONS),
+            self.labels.join(", "),
+            text
+        )
+    }
+
+    /// Normalizes the logits of the label tokens into probabilities.
+    ///
+    /// # Arguments
+    ///
+    /// * `logits` - The logits of the next token over the vocabulary.
+    ///
+    /// # Returns
+    ///
+    /// The probability of every label, most likely first, summing to one.
+    pub fn probabilities(&self, logits: &[f32]) -> Vec<LabelScore> {
+        let label_logits: Vec<f32> = self
+            .tokens
+            .iter()
+            .map(|&token| {
+                logits
+                    .get(token as usize)
+                    .copied()
+                    .unwrap_or(f32::NEG_INFINITY)
+            })
+            .collect();
+        let max = label_logits
+            .iter()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max);
+        let exps: Vec<f32> = label_logits.iter().map(|l| (l - max).exp()).collect();
+        let sum: f32 = exps.iter().sum();
+
+        let mut scores: Vec<LabelScore> = self
+            .labels
+            .iter()
+            .zip(exps)
+            .map(|(label, exp)| LabelScore {
+                label: label.clone(),
+                probability: if sum > 0.0 { exp / sum } else { 0.0 },
+            })
+            .collect();
+        scores.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+        scores
+    }
+}
@@ -0,0 +1,332 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+/// Errors returned by the [`ConversationStore`].
+#[derive(Debug)]
+pub enum ConversationStoreError {
+    /// The conversation does not exist or belongs to another API key or user.
+    NotFound(String),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for ConversationStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "No conversation found with id '{id}'"),
+            Self::Database(err) => write!(f, "Conversation database error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ConversationStoreError {}
+
+impl From<rusqlite::Error> for ConversationStoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+/// A message of a stored conversation.
+#[derive(Clone, Debug, Serialize)]
+pub struct StoredMessage {
+    pub role: String,
+    pub content: String,
+    pub created_at: i64,
+}
+
+/// The metadata of a stored conversation.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub user: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub message_count: usize,
+}
+
+/// A SQLite store of chat transcripts.
+///
+/// Conversations are identified by the id chosen by the client and owned by the digest
+/// of the API key of the request that created them, and optionally by its `user`; a
+/// conversation is invisible to requests made with another key or for another user.
+///
+/// The queries block on the database, so async handlers run them with `spawn_blocking`.
+pub struct ConversationStore {
+    connection: Mutex<Connection>,
+}
+
+impl ConversationStore {
+    /// Opens the database, creating it and its tables if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the SQLite database file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened or initialised.
+    pub fn open(path: &Path) -> Result<Self, ConversationStoreError> {
+        if let Some(parent) = path.parent() {
+            // A missing directory surfaces as an open error just below
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA foreign_keys = ON;
+             PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS conversations (
+                 id TEXT PRIMARY KEY,
+                 owner TEXT,
+                 user TEXT,
+                 created_at INTEGER NOT NULL,
+                 updated_at INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS conversations_user ON conversations (user);
+             CREATE TABLE IF NOT EXISTS messages (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 conversation_id TEXT NOT NULL REFERENCES conversations (id) ON DELETE CASCADE,
+                 role TEXT NOT NULL,
+                 content TEXT NOT NULL,
+                 created_at INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS messages_conversation ON messages (conversation_id);",
+        )?;
+        // Databases created before conversations were owned by API keys lack the column,
+        // their conversations stay visible to requests without a key
+        if connection
+            .prepare("SELECT owner FROM conversations LIMIT 0")
+            .is_err()
+        {
+            connection.execute_batch("ALTER TABLE conversations ADD COLUMN owner TEXT")?;
+        }
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Returns the messages of a conversation in order, empty if it does not exist yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the conversation.
+    /// * `owner` - The digest of the API key of the request.
+    /// * `user` - The user the request is made for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation belongs to another key or user or the database
+    /// fails.
+    pub fn history(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+        user: Option<&str>,
+    ) -> Result<Vec<StoredMessage>, ConversationStoreError> {
+        let connection = self.lock();
+        if !owned_by(&connection, id, owner, user)?.unwrap_or(true) {
+            return Err(ConversationStoreError::NotFound(id.to_string()));
+        }
+
+        let mut statement = connection.prepare(
+            "SELECT role, content, created_at FROM messages
+             WHERE conversation_id = ?1 ORDER BY id",
+        )?;
+        let messages = statement
+            .query_map(params![id], |row| {
+                Ok(StoredMessage {
+                    role: row.get(0)?,
+                    content: row.get(1)?,
+                    created_at: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(messages)
+    }
+
+    /// Appends messages to a conversation, creating it if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the conversation.
+    /// * `owner` - The digest of the API key of the request, which owns a new conversation.
+    /// * `user` - The user the request is made for, who owns a new conversation.
+    /// * `messages` - The roles and contents of the new messages.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation belongs to another key or user or the database
+    /// fails.
+    pub fn append(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+        user: Option<&str>,
+        messages: &[(String, String)],
+    ) -> Result<(), ConversationStoreError> {
+        let mut connection = self.lock();
+        if !owned_by(&connection, id, owner, user)?.unwrap_or(true) {
+            return Err(ConversationStoreError::NotFound(id.to_string()));
+        }
+
+        let now = Utc::now().timestamp();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO conversations (id, owner, user, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?4)
+             ON CONFLICT (id) DO UPDATE SET updated_at = ?4",
+            params![id, owner, user, now],
+        )?;
+        for (role, content) in messages {
+            transaction.execute(
+                "INSERT INTO messages (conversation_id, role, content, created_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![id, role, content, now],
+            )?;
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Lists the conversations of an API key and a user, most recently updated first.
+    ///
+    /// # Arguments
+    ///
+    /// * `owner` - The digest of the API key, or `None` for the conversations without one.
+    /// * `user` - The user, or `None` for the conversations without a user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database fails.
+    pub fn list(
+        &self,
+        owner: Option<&str>,
+        user: Option<&str>,
+    ) -> Result<Vec<ConversationSummary>, ConversationStoreError> {
+        let connection = self.lock();
+        let mut statement = connection.prepare(
+            "SELECT c.id, c.user, c.created_at, c.updated_at, COUNT(m.id)
+             FROM conversations c LEFT JOIN messages m ON m.conversation_id = c.id
+             WHERE c.owner IS ?1 AND c.user IS ?2
+             GROUP BY c.id ORDER BY c.updated_at DESC",
+        )?;
+        let conversations = statement
+            .query_map(params![owner, user], |row| {
+                Ok(ConversationSummary {
+                    id: row.get(0)?,
+                    user: row.get(1)?,
+                    created_at: row.get(2)?,
+                    updated_at: row.get(3)?,
+                    message_count: row.get::<_, i64>(4)? as usize,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(conversations)
+    }
+
+    /// Deletes a conversation and its messages.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the conversation.
+    /// * `owner` - The digest of the API key of the request.
+    /// * `user` - The user the request is made for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation does not exist, belongs to another key or
+    /// user or the database fails.
+    pub fn delete(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+        user: Option<&str>,
+    ) -> Result<(), ConversationStoreError> {
+        let connection = self.lock();
+        if !owned_by(&connection, id, owner, user)?.unwrap_or(false) {
+            return Err(ConversationStoreError::NotFound(id.to_string()));
+        }
+        connection.execute("DELETE FROM conversations WHERE id = ?1", params![id])?;
+
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether a conversation belongs to the API key `owner` and to `user`, or `None` if it
+/// does not exist.
+fn owned_by(
+    connection: &Connection,
+    id: &str,
+    owner: Option<&str>,
+    user: Option<&str>,
+) -> Result<Option<bool>, rusqlite::Error> {
+    let owners: Option<(Option<String>, Option<String>)> = connection
+        .query_row(
+            "SELECT owner, user FROM conversations WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    Ok(owners.map(|(key, name)| key.as_deref() == owner && name.as_deref() == user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversations_are_invisible_to_other_keys() {
+        let path = std::env::temp_dir().join(format!(
+            "synap-forge-conversations-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let store = ConversationStore::open(&path).unwrap();
+        let messages = [("user".to_string(), "hello".to_string())];
+        store
+            .append("chat", Some("sha256:a"), Some("alice"), &messages)
+            .unwrap();
+
+        assert_eq!(
+            store
+                .history("chat", Some("sha256:a"), Some("alice"))
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(store
+            .history("chat", Some("sha256:b"), Some("alice"))
+            .is_err());
+        assert!(store.history("chat", None, Some("alice")).is_err());
+        assert!(store
+            .append("chat", Some("sha256:b"), Some("alice"), &messages)
+            .is_err());
+        assert!(store
+            .list(Some("sha256:b"), Some("alice"))
+            .unwrap()
+            .is_empty());
+        assert!(store
+            .delete("chat", Some("sha256:b"), Some("alice"))
+            .is_err());
+        assert_eq!(
+            store.list(Some("sha256:a"), Some("alice")).unwrap().len(),
+            1
+        );
+
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+}
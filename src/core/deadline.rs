@@ -0,0 +1,64 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The time by which a client needs the answer of a request.
+///
+/// Requests whose deadline passed while they waited for the model fail without
+/// generating anything, and a running generation stops early enough to return its
+/// partial output before the deadline.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    at: Instant,
+    timeout: Duration,
+}
+
+/// The error returned when a request reaches its deadline before generation starts.
+#[derive(Debug, Clone)]
+pub struct DeadlineExceeded {
+    pub timeout: Duration,
+}
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The request timed out after {} ms before generation started",
+            self.timeout.as_millis()
+        )
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+impl Deadline {
+    /// Creates the deadline of a request received now.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The time the client waits for the answer.
+    pub fn after(timeout: Duration) -> Self {
+        Self {
+            at: Instant::now() + timeout,
+            timeout,
+        }
+    }
+
+    /// The time left before the deadline, zero once it passed.
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    /// Fails if the deadline already passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DeadlineExceeded` once the deadline passed.
+    pub fn check(&self) -> Result<(), DeadlineExceeded> {
+        if self.remaining().is_zero() {
+            return Err(DeadlineExceeded {
+                timeout: self.timeout,
+            });
+        }
+        Ok(())
+    }
+}
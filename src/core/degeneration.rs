@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+
+use candle_core::{DType, Tensor, D};
+
+use crate::config::DegenerationSettings;
+
+/// Detects a generation collapsing into degenerate repetition, so that it stops instead
+/// of spending the rest of its `max_tokens` on the same few tokens.
+///
+/// A generation degenerates when its last `window` tokens repeat a cycle of at most
+/// `max_period` tokens and the model was confident about them, with a mean entropy of
+/// its next-token distribution at most `max_entropy`. Requiring both keeps legitimate
+/// repetitions, such as a table or a list the model is unsure how to continue, running.
+pub(crate) struct DegenerationGuard {
+    window: usize,
+    max_period: usize,
+    max_entropy: f32,
+    /// The last generated tokens with the entropy of the distribution they were drawn
+    /// from, in nats.
+    recent: VecDeque<(u32, f32)>,
+}
+
+impl DegenerationGuard {
+    /// Creates the guard of a generation.
+    ///
+    /// # Returns
+    ///
+    /// The guard, `None` when the guard is disabled for the model.
+    pub(crate) fn new(settings: &DegenerationSettings) -> Option<Self> {
+        if !settings.enabled || settings.window < 2 || settings.max_period == 0 {
+            return None;
+        }
+
+        Some(Self {
+            window: settings.window,
+            // The cycle must be seen at least twice to be a repetition
+            max_period: settings.max_period.min(settings.window / 2),
+            max_entropy: settings.max_entropy,
+            recent: VecDeque::with_capacity(settings.window),
+        })
+    }
+
+    /// Records a generated token.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The generated token.
+    /// * `entropy` - The entropy of the distribution the token was drawn from, in nats.
+    ///
+    /// # Returns
+    ///
+    /// Whether the generation degenerated and should stop.
+    pub(crate) fn push(&mut self, token: u32, entropy: f32) -> bool {
+        if self.recent.len() == self.window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((token, entropy));
+        if self.recent.len() < self.window {
+            return false;
+        }
+
+        let mean_entropy =
+            self.recent.iter().map(|&(_, entropy)| entropy).sum::<f32>() / self.window as f32;
+        mean_entropy <= self.max_entropy && self.is_periodic()
+    }
+
+    /// Whether the tokens of the window repeat a cycle of at most `max_period` tokens.
+    fn is_periodic(&self) -> bool {
+        (1..=self.max_period).any(|period| {
+            (period..self.recent.len()).all(|i| self.recent[i].0 == self.recent[i - period].0)
+        })
+    }
+}
+
+/// The entropy of the next-token distribution of some logits, in nats.
+///
+/// It is computed from the logits of the model, before the temperature and the penalties,
+/// so that it measures the confidence of the model rather than the sampling settings.
+///
+/// # Arguments
+///
+/// * `logits` - The finite logits over the vocabulary, of shape `(vocab)`.
+pub(crate) fn entropy(logits: &Tensor) -> candle_core::Result<f32> {
+    let log_probs = candle_nn::ops::log_softmax(&logits.to_dtype(DType::F32)?, D::Minus1)?;
+    let entropy = (log_probs.exp()? * &log_probs)?.sum_all()?.neg()?;
+    entropy.to_scalar::<f32>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Device;
+
+    fn enabled_guard() -> DegenerationGuard {
+        let settings = DegenerationSettings {
+            enabled: true,
+            window: 8,
+            max_period: 3,
+            max_entropy: 0.5,
+        };
+        DegenerationGuard::new(&settings).unwrap()
+    }
+
+    #[test]
+    fn stops_a_confident_loop() {
+        let mut guard = enabled_guard();
+        let stopped: Vec<bool> = [1, 2, 3, 4, 5, 4, 5, 4, 5, 4, 5, 4]
+            .into_iter()
+            .map(|token| guard.push(token, 0.1))
+            .collect();
+        assert_eq!(stopped.iter().position(|&stop| stop), Some(10));
+    }
+
+    #[test]
+    fn keeps_uncertain_or_varied_text() {
+        let mut guard = enabled_guard();
+        assert!((0..32).all(|_| !guard.push(7, 2.0)));
+
+        let mut guard = enabled_guard();
+        assert!((0..32).all(|token| !guard.push(token, 0.)));
+
+        let disabled = DegenerationSettings::default();
+        assert!(DegenerationGuard::new(&disabled).is_none());
+    }
+
+    #[test]
+    fn entropy_of_a_uniform_distribution() {
+        let logits = Tensor::new(&[1f32, 1., 1., 1.], &Device::Cpu).unwrap();
+        assert!((entropy(&logits).unwrap() - 4f32.ln()).abs() < 1e-5);
+
+        let logits = Tensor::new(&[100f32, 0., 0., 0.], &Device::Cpu).unwrap();
+        assert!(entropy(&logits).unwrap() < 1e-5);
+    }
+}
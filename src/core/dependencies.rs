@@ -0,0 +1,139 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::HubSettings;
+
+/// The Hub the models are downloaded from, unless `HF_ENDPOINT` names a mirror.
+const DEFAULT_HUB_ENDPOINT: &str = "https://huggingface.co";
+
+/// The state of what the server needs to load new models, returned by
+/// `/v1/health/dependencies`.
+#[derive(Debug, Serialize)]
+pub struct DependencyReport {
+    /// `ok`, or `degraded` when the server keeps serving the loaded models but could not
+    /// download or cache new ones.
+    pub status: String,
+    pub hub: HubStatus,
+    pub cache: CacheStatus,
+    pub disk: DiskStatus,
+}
+
+/// Whether the Hugging Face Hub answers.
+#[derive(Debug, Serialize)]
+pub struct HubStatus {
+    pub endpoint: String,
+    /// Whether the Hub answered the probe, even with a client error such as `401` for a
+    /// gated model.
+    pub reachable: bool,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Whether downloads can be written to the Hub cache.
+#[derive(Debug, Serialize)]
+pub struct CacheStatus {
+    pub path: String,
+    pub writable: bool,
+    pub error: Option<String>,
+}
+
+/// The space left on the disk of the Hub cache.
+#[derive(Debug, Serialize)]
+pub struct DiskStatus {
+    pub available_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    /// The free space below which the disk is reported as full, `hub.min_free_disk_mb`.
+    pub min_free_bytes: u64,
+    pub sufficient: bool,
+    pub error: Option<String>,
+}
+
+/// Checks the Hub, the Hub cache and its disk.
+///
+/// Blocks for up to `hub.probe_timeout_secs` while the Hub is probed.
+///
+/// # Arguments
+///
+/// * `hub` - The Hub settings holding the cache directory and the thresholds.
+/// * `model_id` - The served model, whose metadata the Hub is asked for.
+///
+/// # Returns
+///
+/// The report, `degraded` when any of the checks fails.
+pub fn check_dependencies(hub: &HubSettings, model_id: &str) -> DependencyReport {
+    let cache_dir = hub.cache_dir();
+    let hub_status = probe_hub(model_id, Duration::from_secs(hub.probe_timeout_secs.max(1)));
+    let cache = check_writable(&cache_dir);
+    let disk = check_disk(&cache_dir, hub.min_free_disk_mb.saturating_mul(1024 * 1024));
+
+    let healthy = hub_status.reachable && cache.writable && disk.sufficient;
+    DependencyReport {
+        status: if healthy { "ok" } else { "degraded" }.to_string(),
+        hub: hub_status,
+        cache,
+        disk,
+    }
+}
+
+/// Asks the Hub for the metadata of a model.
+fn probe_hub(model_id: &str, timeout: Duration) -> HubStatus {
+    let endpoint = std::env::var("HF_ENDPOINT")
+        .unwrap_or_else(|_| DEFAULT_HUB_ENDPOINT.to_string())
+        .trim_end_matches('/')
+        .to_string();
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+
+    let started = Instant::now();
+    let (reachable, error) = match agent
+        .head(&format!("{endpoint}/api/models/{model_id}"))
+        .call()
+    {
+        Ok(_) => (true, None),
+        Err(ureq::Error::Status(status, _)) if status < 500 => (true, None),
+        Err(ureq::Error::Status(status, _)) => (false, Some(format!("HTTP {status}"))),
+        Err(err) => (false, Some(err.to_string())),
+    };
+
+    HubStatus {
+        endpoint,
+        reachable,
+        latency_ms: reachable.then(|| started.elapsed().as_millis() as u64),
+        error,
+    }
+}
+
+/// Writes and removes a file in a directory, creating the directory if needed.
+fn check_writable(dir: &Path) -> CacheStatus {
+    let probe = dir.join(format!(".write-probe-{}", std::process::id()));
+    let written = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(&probe, b"probe"))
+        .and_then(|_| std::fs::remove_file(&probe));
+
+    CacheStatus {
+        path: dir.display().to_string(),
+        writable: written.is_ok(),
+        error: written.err().map(|err| err.to_string()),
+    }
+}
+
+/// Reads the free space of the disk holding a directory.
+fn check_disk(dir: &Path, min_free_bytes: u64) -> DiskStatus {
+    match fs2::available_space(dir).and_then(|available| Ok((available, fs2::total_space(dir)?))) {
+        Ok((available, total)) => DiskStatus {
+            available_bytes: Some(available),
+            total_bytes: Some(total),
+            min_free_bytes,
+            sufficient: available >= min_free_bytes,
+            error: None,
+        },
+        Err(err) => DiskStatus {
+            available_bytes: None,
+            total_bytes: None,
+            min_free_bytes,
+            sufficient: false,
+            error: Some(err.to_string()),
+        },
+    }
+}
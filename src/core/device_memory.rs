@@ -0,0 +1,113 @@
+use candle_core::Device;
+use serde::Serialize;
+
+/// The memory of an accelerator, in bytes.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct DeviceMemory {
+    pub used_bytes: u64,
+    pub free_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// Queries the memory usage of a device.
+///
+/// On CUDA this is the memory of the whole GPU, including other processes. On Metal
+/// the total is the recommended working set size of the device and the used memory
+/// is what this process has allocated.
+///
+/// # Arguments
+///
+/// * `device` - The device to query.
+///
+/// # Returns
+///
+/// The memory usage, or `None` for the CPU and when the backend cannot report it.
+pub fn device_memory(device: &Device) -> Option<DeviceMemory> {
+    match device {
+        #[cfg(feature = "cuda")]
+        Device::Cuda(cuda) => {
+            cuda.cuda_device().bind_to_thread().ok()?;
+            let (free, total) = cudarc::driver::result::mem_get_info().ok()?;
+
+            Some(DeviceMemory {
+                used_bytes: (total - free) as u64,
+                free_bytes: free as u64,
+                total_bytes: total as u64,
+            })
+        }
+        #[cfg(feature = "metal")]
+        Device::Metal(metal) => {
+            let total = metal.device().recommended_max_working_set_size();
+            let used = metal.device().current_allocated_size();
+
+            Some(DeviceMemory {
+                used_bytes: used,
+                free_bytes: total.saturating_sub(used),
+                total_bytes: total,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// A short name of the device, such as `cuda:0`.
+pub fn device_name(device: &Device) -> String {
+    match device.location() {
+        candle_core::DeviceLocation::Cpu => "cpu".to_string(),
+        candle_core::DeviceLocation::Cuda { gpu_id } => format!("cuda:{gpu_id}"),
+        candle_core::DeviceLocation::Metal { gpu_id } => format!("metal:{gpu_id}"),
+    }
+}
+
+/// Opens a device from its short name, such as `cpu`, `cuda:1` or `metal:0`.
+///
+/// # Arguments
+///
+/// * `name` - The name of the device, the ordinal defaults to `0`.
+///
+/// # Errors
+///
+/// Returns an error if the name is not a device name, or the device cannot be opened,
+/// e.g. when its backend is not compiled into the server.
+pub fn open_device(name: &str) -> anyhow::Result<Device> {
+    let (backend, ordinal) = match name.split_once(':') {
+        Some((backend, ordinal)) => (
+            backend,
+            ordinal
+                .parse::<usize>()
+                .map_err(|_| anyhow::anyhow!("Invalid device ordinal in '{name}'"))?,
+        ),
+        None => (name, 0),
+    };
+
+    Ok(match backend {
+        "cpu" => Device::Cpu,
+        "cuda" => Device::new_cuda(ordinal)?,
+        "metal" => Device::new_metal(ordinal)?,
+        _ => anyhow::bail!("Unknown device '{name}', expected cpu, cuda:N or metal:N"),
+    })
+}
+
+/// The compute backends compiled into the server, the CPU first.
+///
+/// The GPU backends are Cargo features, so a build without them runs on machines
+/// without the CUDA or Metal toolchains.
+pub fn compiled_backends() -> Vec<&'static str> {
+    let mut backends = vec!["cpu"];
+    if cfg!(feature = "accelerate") {
+        backends.push("accelerate");
+    }
+    if cfg!(feature = "mkl") {
+        backends.push("mkl");
+    }
+    if cfg!(feature = "cuda") {
+        backends.push("cuda");
+    }
+    if cfg!(feature = "cudnn") {
+        backends.push("cudnn");
+    }
+    if cfg!(feature = "metal") {
+        backends.push("metal");
+    }
+    backends
+}
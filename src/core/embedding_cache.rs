@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::config::EmbeddingCacheSettings;
+
+/// The SHA-256 digest of the model, its pooling and the text a vector was computed from.
+type CacheKey = [u8; 32];
+
+/// A vector of the cache, with the number of tokens its text was made of.
+pub struct CachedEmbedding {
+    pub vector: Vec<f32>,
+    pub tokens: usize,
+}
+
+/// The hit counters of the embedding cache, reported by the health endpoint.
+#[derive(Clone, Debug, Serialize)]
+pub struct EmbeddingCacheStats {
+    /// The texts whose vector was found in memory or in the database.
+    pub hits: u64,
+    /// The texts that had to be embedded by the model.
+    pub misses: u64,
+    /// The number of vectors held in memory.
+    pub entries: usize,
+}
+
+/// The vectors of the texts already embedded, by the digest of the model, its pooling and
+/// the text.
+///
+/// The most recently used vectors are kept in memory, and all of them in an optional
+/// SQLite database, which is read when a vector is not in memory.
+pub struct EmbeddingCache {
+    memory: Mutex<RecentVectors>,
+    max_entries: usize,
+    database: Option<Mutex<Connection>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// The in-memory vectors, evicted least recently used first.
+#[derive(Default)]
+struct RecentVectors {
+    entries: HashMap<CacheKey, (CachedEmbedding, u64)>,
+    /// The keys by the tick they were last used at.
+    by_use: BTreeMap<u64, CacheKey>,
+    tick: u64,
+}
+
+impl EmbeddingCache {
+    /// Creates the cache, opening its database when one is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The size of the cache and its database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened or initialised.
+    pub fn open(settings: &EmbeddingCacheSettings) -> Result<Self, rusqlite::Error> {
+        let database = match &settings.database {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    // A missing directory surfaces as an open error just below
+                    let _ = std::fs::create_dir_all(parent);
+                }
+                let connection = Connection::open(path)?;
+                connection.execute_batch(
+                    "PRAGMA journal_mode = WAL;
+                     CREATE TABLE IF NOT EXISTS embeddings (
+                         key BLOB PRIMARY KEY,
+                         tokens INTEGER NOT NULL,
+                         vector BLOB NOT NULL
+                     );",
+                )?;
+                Some(Mutex::new(connection))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            memory: Mutex::new(RecentVectors::default()),
+            max_entries: settings.max_entries,
+            database,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Looks up the vector of a text.
+    ///
+    /// # Arguments
+    ///
+    /// * `variant` - The embedding model and the pooling the vector was computed with.
+    /// * `text` - The embedded text.
+    ///
+    /// # Returns
+    ///
+    /// The cached vector, or `None` if the text was not embedded with this model yet.
+    pub fn get(&self, variant: &str, text: &str) -> Option<CachedEmbedding> {
+        let key = cache_key(variant, text);
+        let in_memory = self.lock_memory().touch(&key);
+        let found =
+            in_memory.or_else(|| self.read(&key).inspect(|cached| self.remember(key, cached)));
+
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        found
+    }
+
+    /// Caches the vector of a text.
+    ///
+    /// A failure to write the database is logged rather than returned, the vector is
+    /// still cached in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `variant` - The embedding model and the pooling the vector was computed with.
+    /// * `text` - The embedded text.
+    /// * `cached` - The vector and the number of tokens of the text.
+    pub fn insert(&self, variant: &str, text: &str, cached: &CachedEmbedding) {
+        let key = cache_key(variant, text);
+        self.remember(key, cached);
+
+        if let Some(database) = &self.database {
+            let vector: Vec<u8> = cached
+                .vector
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            let written = lock(database).execute(
+                "INSERT OR REPLACE INTO embeddings (key, tokens, vector) VALUES (?1, ?2, ?3)",
+                params![key.as_slice(), cached.tokens as i64, vector],
+            );
+            if let Err(err) = written {
+                error!("Error writing the embedding cache: {err}");
+            }
+        }
+    }
+
+    /// The hit counters of the cache since the server started.
+    pub fn stats(&self) -> EmbeddingCacheStats {
+        EmbeddingCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.lock_memory().entries.len(),
+        }
+    }
+
+    /// Keeps a vector in memory, evicting the least recently used one if the cache is full.
+    fn remember(&self, key: CacheKey, cached: &CachedEmbedding) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut memory = self.lock_memory();
+        if memory.touch(&key).is_none() {
+            while memory.entries.len() >= self.max_entries && memory.evict_oldest() {}
+            memory.insert(
+                key,
+                CachedEmbedding {
+                    vector: cached.vector.clone(),
+                    tokens: cached.tokens,
+                },
+            );
+        }
+    }
+
+    /// Reads a vector from the database, if there is one.
+    fn read(&self, key: &CacheKey) -> Option<CachedEmbedding> {
+        let database = self.database.as_ref()?;
+        let row = lock(database)
+            .query_row(
+                "SELECT tokens, vector FROM embeddings WHERE key = ?1",
+                params![key.as_slice()],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)),
+            )
+            .optional();
+
+        match row {
+            Ok(row) => row.map(|(tokens, vector)| CachedEmbedding {
+                vector: vector
+                    .chunks_exact(4)
+                    .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .collect(),
+                tokens: tokens as usize,
+            }),
+            Err(err) => {
+                error!("Error reading the embedding cache: {err}");
+                None
+            }
+        }
+    }
+
+    fn lock_memory(&self) -> MutexGuard<'_, RecentVectors> {
+        lock(&self.memory)
+    }
+}
+
+impl RecentVectors {
+    /// Marks a vector as just used and returns a copy of it.
+    fn touch(&mut self, key: &CacheKey) -> Option<CachedEmbedding> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (cached, used) = self.entries.get_mut(key)?;
+        self.by_use.remove(used);
+        *used = tick;
+        self.by_use.insert(tick, *key);
+
+        Some(CachedEmbedding {
+            vector: cached.vector.clone(),
+            tokens: cached.tokens,
+        })
+    }
+
+    fn insert(&mut self, key: CacheKey, cached: CachedEmbedding) {
+        self.tick += 1;
+        self.by_use.insert(self.tick, key);
+        self.entries.insert(key, (cached, self.tick));
+    }
+
+    /// Evicts the least recently used vector, returns whether there was one.
+    fn evict_oldest(&mut self) -> bool {
+        let Some((_, key)) = self.by_use.pop_first() else {
+            return false;
+        };
+        self.entries.remove(&key);
+        true
+    }
+}
+
+fn cache_key(variant: &str, text: &str) -> CacheKey {
+    let mut hasher = Sha256::new();
+    hasher.update(variant.as_bytes());
+    hasher.update([0]);
+    hasher.update(text.as_bytes());
+    hasher.finalize().into()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,726 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+
+use anyhow::Error as E;
+use candle_core::{DType, Device, IndexOp, Tensor};
+use candle_nn::{Linear, Module, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use hf_hub::api::sync::{Api, ApiRepo};
+use hf_hub::{Repo, RepoType};
+use serde::{Deserialize, Serialize};
+use tokenizers::{PaddingParams, PostProcessor, Tokenizer, TruncationParams};
+use tracing::info;
+
+use crate::config::{EmbeddingHeads, EmbeddingOutput, HubSettings, LongInputPolicy, Pooling};
+use crate::core::device_memory::device_name;
+use crate::core::embedding_cache::{CachedEmbedding, EmbeddingCache, EmbeddingCacheStats};
+use crate::core::hub_fetch::fetch_with_retry;
+
+/// The texts embedded in one forward pass.
+const BATCH_SIZE: usize = 32;
+
+/// The vectors of a batch of texts, with the number of tokens they were made of.
+pub struct Embeddings {
+    pub vectors: Vec<Vec<f32>>,
+    pub prompt_tokens: usize,
+    /// Whether each text was longer than the context of the model and only its first
+    /// tokens were embedded.
+    pub truncated: Vec<bool>,
+    /// The lexical weights of each text, when they were requested.
+    pub sparse: Option<Vec<SparseVector>>,
+    /// The vectors of the tokens of each text, when they were requested.
+    pub multi_vectors: Option<Vec<Vec<Vec<f32>>>>,
+}
+
+/// The weights of the tokens of a text by token id, for lexical matching in hybrid search.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SparseVector {
+    /// The ids of the tokens of the text, in increasing order.
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+/// How texts are embedded.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EmbeddingOptions {
+    pub output: EmbeddingOutput,
+    /// What to do with the texts longer than the context of the model.
+    pub long_inputs: LongInputPolicy,
+    /// Whether the lexical weights of the texts are computed, which needs a sparse head.
+    pub sparse: bool,
+    /// Whether the vectors of the tokens are returned, which needs a multi-vector head.
+    pub multi_vector: bool,
+}
+
+/// The outputs of the model for a text.
+struct EncodedText {
+    vector: Vec<f32>,
+    tokens: usize,
+    sparse: Option<SparseVector>,
+    multi_vector: Option<Vec<Vec<f32>>>,
+}
+
+impl From<CachedEmbedding> for EncodedText {
+    fn from(cached: CachedEmbedding) -> Self {
+        Self {
+            vector: cached.vector,
+            tokens: cached.tokens,
+            sparse: None,
+            multi_vector: None,
+        }
+    }
+}
+
+/// A text longer than the context of the embedding model, refused by the `error` policy.
+#[derive(Debug, Clone)]
+pub struct InputTooLong {
+    /// The position of the text in the input.
+    pub index: usize,
+    pub tokens: usize,
+    pub max_tokens: usize,
+}
+
+impl fmt::Display for InputTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Input {} is {} tokens long, more than the {} tokens the embedding model reads",
+            self.index, self.tokens, self.max_tokens
+        )
+    }
+}
+
+impl std::error::Error for InputTooLong {}
+
+/// Sentence embeddings with a BERT model, such as `sentence-transformers/all-MiniLM-L6-v2`.
+///
+/// By default the vector of a text is the mean of its token states, normalized to unit
+/// length so that the dot product of two vectors is their cosine similarity.
+pub struct Embedder {
+    model_id: String,
+    encoder: Arc<BertEncoder>,
+    /// The tokenizer without padding nor truncation, to count and split the texts.
+    plain_tokenizer: Tokenizer,
+    /// The number of tokens of a text the model reads, special tokens excluded.
+    max_tokens: usize,
+    defaults: EmbeddingOptions,
+    dims: usize,
+    workers: Option<EmbeddingWorkers>,
+    cache: Option<EmbeddingCache>,
+}
+
+/// The BERT model with its tokenizer, shared with the embedding workers.
+struct BertEncoder {
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+    /// Projects the state of every token to its lexical weight.
+    sparse_head: Option<Linear>,
+    /// Projects the state of every token to its vector.
+    multi_vector_head: Option<Linear>,
+}
+
+impl Embedder {
+    /// Loads a BERT embedding model from the Hugging Face Hub.
+    ///
+    /// # Arguments
+    ///
+    /// * `api` - The Hub API client.
+    /// * `hub` - The Hub settings holding the download retry policy.
+    /// * `model_id` - The Hub id of the model.
+    /// * `heads` - The files of the sparse and multi-vector heads of the model, if any.
+    /// * `device` - The device to run the model on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the model files cannot be fetched or loaded.
+    pub fn load(
+        api: &Api,
+        hub: &HubSettings,
+        model_id: &str,
+        heads: &EmbeddingHeads,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        let repo = api.repo(Repo::new(model_id.to_string(), RepoType::Model));
+
+        let config: Config = serde_json::from_slice(&std::fs::read(fetch_with_retry(
+            &repo,
+            "config.json",
+            hub,
+        )?)?)?;
+        let mut tokenizer = Tokenizer::from_file(fetch_with_retry(&repo, "tokenizer.json", hub)?)
+            .map_err(E::msg)?;
+        let mut plain_tokenizer = tokenizer.clone();
+        plain_tokenizer.with_padding(None);
+        plain_tokenizer.with_truncation(None).map_err(E::msg)?;
+        let special_tokens = tokenizer
+            .get_post_processor()
+            .map_or(0, |processor| processor.added_tokens(false));
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                ..TruncationParams::default()
+            }))
+            .map_err(E::msg)?;
+        let weights = fetch_with_retry(&repo, "model.safetensors", hub)?;
+
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, device)? };
+        let model = BertModel::load(vb, &config)?;
+        let sparse_head = match &heads.sparse {
+            Some(filename) => Some(load_head(&repo, filename, hub, device)?),
+            None => None,
+        };
+        let multi_vector_head = match &heads.multi_vector {
+            Some(filename) => Some(load_head(&repo, filename, hub, device)?),
+            None => None,
+        };
+
+        info!(
+            "Embedding model {} loaded on {}",
+            model_id,
+            device_name(device)
+        );
+
+        Ok(Self {
+            model_id: model_id.to_string(),
+            encoder: Arc::new(BertEncoder {
+                model,
+                tokenizer,
+                device: device.clone(),
+                sparse_head,
+                multi_vector_head,
+            }),
+            plain_tokenizer,
+            max_tokens: config
+                .max_position_embeddings
+                .saturating_sub(special_tokens)
+                .max(1),
+            defaults: EmbeddingOptions::default(),
+            dims: config.hidden_size,
+            workers: None,
+            cache: None,
+        })
+    }
+
+    /// Sets how texts are embedded when the caller does not say.
+    ///
+    /// # Arguments
+    ///
+    /// * `defaults` - The pooling and the policy applied to the long texts.
+    pub fn with_defaults(mut self, defaults: EmbeddingOptions) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    /// Runs the model on dedicated threads instead of the threads of the requests.
+    ///
+    /// Texts are embedded by at most `count` batches at a time, whatever the number of
+    /// requests embedding texts.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - The number of threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a thread cannot be spawned.
+    pub fn with_workers(mut self, count: usize) -> anyhow::Result<Self> {
+        self.workers = Some(EmbeddingWorkers::spawn(count)?);
+        Ok(self)
+    }
+
+    /// Reuses the vectors of the texts already embedded instead of running the model.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache` - The cache the vectors are looked up in and added to.
+    pub fn with_cache(mut self, cache: EmbeddingCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// The hit counters of the cache, `None` when the vectors are not cached.
+    pub fn cache_stats(&self) -> Option<EmbeddingCacheStats> {
+        self.cache.as_ref().map(EmbeddingCache::stats)
+    }
+
+    /// The Hub id of the model.
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    /// The size of the vectors.
+    pub fn dims(&self) -> usize {
+        self.dims
+    }
+
+    /// Whether the model has a head computing the lexical weights of the tokens.
+    pub fn supports_sparse(&self) -> bool {
+        self.encoder.sparse_head.is_some()
+    }
+
+    /// Whether the model has a head computing the vectors of the tokens.
+    pub fn supports_multi_vector(&self) -> bool {
+        self.encoder.multi_vector_head.is_some()
+    }
+
+    /// Splits a text into chunks of tokens of this model's tokenizer.
+    ///
+    /// Chunks longer than the context of the model are truncated when they are embedded,
+    /// so only their first tokens count for retrieval.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to split.
+    /// * `max_tokens` - The maximum number of tokens of a chunk.
+    /// * `overlap_tokens` - The number of tokens consecutive chunks share, less than `max_tokens`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the text cannot be tokenized.
+    pub fn chunk(
+        &self,
+        text: &str,
+        max_tokens: usize,
+        overlap_tokens: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        let encoding = self.plain_tokenizer.encode(text, false).map_err(E::msg)?;
+        let offsets = encoding.get_offsets();
+
+        let max_tokens = max_tokens.max(1);
+        let step = max_tokens.saturating_sub(overlap_tokens).max(1);
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < offsets.len() {
+            let end = (start + max_tokens).min(offsets.len());
+            if let Some(chunk) = text.get(offsets[start].0..offsets[end - 1].1) {
+                chunks.push(chunk.to_string());
+            }
+            if end == offsets.len() {
+                break;
+            }
+            start += step;
+        }
+
+        Ok(chunks)
+    }
+
+    /// Embeds texts with the default pooling and policy for the long texts.
+    ///
+    /// # Arguments
+    ///
+    /// * `texts` - The texts to embed.
+    ///
+    /// # Returns
+    ///
+    /// One vector per text, in order, and the number of tokens embedded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a text cannot be tokenized or the forward pass fails.
+    pub fn embed(&self, texts: &[String]) -> anyhow::Result<Embeddings> {
+        self.embed_with(texts, self.defaults)
+    }
+
+    /// Embeds texts.
+    ///
+    /// A text longer than the context of the model is truncated, split into chunks whose
+    /// vectors are averaged, or refused, depending on the policy; the lexical weights of the
+    /// chunks are merged and their token vectors concatenated. Unless sparse or token
+    /// vectors are requested, texts whose vector is cached are not run through the model,
+    /// and count for the same number of tokens as when they were embedded.
+    ///
+    /// # Arguments
+    ///
+    /// * `texts` - The texts to embed.
+    /// * `options` - The pooling, what to do with the texts longer than the context of the
+    ///   model, and the additional outputs.
+    ///
+    /// # Returns
+    ///
+    /// One vector per text, in order, the number of tokens embedded, which texts were
+    /// truncated, and the requested sparse and token vectors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InputTooLong` error for a long text with the `error` policy, or an
+    /// error if an output the model has no head for is requested, a text cannot be
+    /// tokenized or the forward pass fails.
+    pub fn embed_with(
+        &self,
+        texts: &[String],
+        options: EmbeddingOptions,
+    ) -> anyhow::Result<Embeddings> {
+        if options.sparse && !self.supports_sparse() {
+            return Err(E::msg("The embedding model has no sparse head"));
+        }
+        if options.multi_vector && !self.supports_multi_vector() {
+            return Err(E::msg("The embedding model has no multi-vector head"));
+        }
+
+        let long_inputs = options.long_inputs;
+        let mut pieces = Vec::with_capacity(texts.len());
+        let mut spans: Vec<Range<usize>> = Vec::with_capacity(texts.len());
+        let mut truncated = Vec::with_capacity(texts.len());
+        for (index, text) in texts.iter().enumerate() {
+            let tokens = self
+                .plain_tokenizer
+                .encode(text.as_str(), false)
+                .map_err(E::msg)?
+                .len();
+            let long = tokens > self.max_tokens;
+            let start = pieces.len();
+            match long_inputs {
+                LongInputPolicy::Mean if long => {
+                    pieces.extend(self.chunk(text, self.max_tokens, 0)?);
+                }
+                LongInputPolicy::Error if long => {
+                    return Err(InputTooLong {
+                        index,
+                        tokens,
+                        max_tokens: self.max_tokens,
+                    }
+                    .into());
+                }
+                _ => pieces.push(text.clone()),
+            }
+            if pieces.len() == start {
+                pieces.push(text.clone());
+            }
+            spans.push(start..pieces.len());
+            truncated.push(long && long_inputs == LongInputPolicy::Truncate);
+        }
+
+        let embedded = self.embed_pieces(&pieces, options)?;
+        let mut vectors = Vec::with_capacity(texts.len());
+        let mut sparse = options.sparse.then(Vec::new);
+        let mut multi_vectors = options.multi_vector.then(Vec::new);
+        for span in spans {
+            let chunks = &embedded[span];
+            vectors.push(match chunks {
+                [single] => single.vector.clone(),
+                chunks => mean_of(chunks, options.output.normalize),
+            });
+            if let Some(sparse) = &mut sparse {
+                sparse.push(merge_sparse(chunks));
+            }
+            if let Some(multi_vectors) = &mut multi_vectors {
+                multi_vectors.push(
+                    chunks
+                        .iter()
+                        .flat_map(|chunk| chunk.multi_vector.iter().flatten().cloned())
+                        .collect(),
+                );
+            }
+        }
+
+        Ok(Embeddings {
+            vectors,
+            prompt_tokens: embedded.iter().map(|e| e.tokens).sum(),
+            truncated,
+            sparse,
+            multi_vectors,
+        })
+    }
+
+    /// Embeds the texts as they are, reading their vectors from the cache when possible.
+    fn embed_pieces(
+        &self,
+        texts: &[String],
+        options: EmbeddingOptions,
+    ) -> anyhow::Result<Vec<EncodedText>> {
+        let output = options.output;
+        let cache = match &self.cache {
+            Some(cache) if !options.sparse && !options.multi_vector => cache,
+            _ => return self.forward(texts, options),
+        };
+
+        let variant = format!(
+            "{}|{}|{}",
+            self.model_id,
+            output.pooling.as_str(),
+            if output.normalize { "unit" } else { "raw" }
+        );
+        let mut found: Vec<Option<EncodedText>> = texts
+            .iter()
+            .map(|text| cache.get(&variant, text).map(EncodedText::from))
+            .collect();
+        let (missing, missing_texts): (Vec<usize>, Vec<String>) = found
+            .iter()
+            .enumerate()
+            .filter(|(_, cached)| cached.is_none())
+            .map(|(i, _)| (i, texts[i].clone()))
+            .unzip();
+        if !missing.is_empty() {
+            for (i, embedded) in missing
+                .into_iter()
+                .zip(self.forward(&missing_texts, options)?)
+            {
+                let cached = CachedEmbedding {
+                    vector: embedded.vector.clone(),
+                    tokens: embedded.tokens,
+                };
+                cache.insert(&variant, &texts[i], &cached);
+                found[i] = Some(embedded);
+            }
+        }
+
+        Ok(found.into_iter().flatten().collect())
+    }
+
+    /// Runs the model over texts, on the workers if there are some.
+    fn forward(
+        &self,
+        texts: &[String],
+        options: EmbeddingOptions,
+    ) -> anyhow::Result<Vec<EncodedText>> {
+        match &self.workers {
+            Some(workers) => {
+                let encoder = self.encoder.clone();
+                let texts = texts.to_vec();
+                workers.run(move || encoder.encode(&texts, options))?
+            }
+            None => self.encoder.encode(texts, options),
+        }
+    }
+}
+
+impl BertEncoder {
+    /// Runs the model over texts, returning the outputs and the number of tokens of each.
+    fn encode(
+        &self,
+        texts: &[String],
+        options: EmbeddingOptions,
+    ) -> anyhow::Result<Vec<EncodedText>> {
+        let output = options.output;
+        let mut embedded = Vec::with_capacity(texts.len());
+
+        for batch in texts.chunks(BATCH_SIZE) {
+            let encodings = self
+                .tokenizer
+                .encode_batch(batch.to_vec(), true)
+                .map_err(E::msg)?;
+
+            let ids = encodings
+                .iter()
+                .map(|e| Tensor::new(e.get_ids(), &self.device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            let masks = encodings
+                .iter()
+                .map(|e| Tensor::new(e.get_attention_mask(), &self.device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            let tokens: Vec<usize> = encodings
+                .iter()
+                .map(|e| e.get_attention_mask().iter().filter(|&&m| m == 1).count())
+                .collect();
+
+            let ids = Tensor::stack(&ids, 0)?;
+            let mask = Tensor::stack(&masks, 0)?;
+            let token_type_ids = ids.zeros_like()?;
+            let states = self.model.forward(&ids, &token_type_ids, Some(&mask))?;
+
+            let states = states.to_dtype(DType::F32)?;
+            let pooled = match output.pooling {
+                Pooling::Cls => states.i((.., 0))?,
+                Pooling::Mean => {
+                    // Mean of the states of the real tokens, ignoring the padding
+                    let mask = mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+                    let summed = states.broadcast_mul(&mask)?.sum(1)?;
+                    summed.broadcast_div(&mask.sum(1)?)?
+                }
+                Pooling::LastToken => {
+                    // The padding is on the right, so the last real token is at `tokens - 1`
+                    let last = tokens
+                        .iter()
+                        .enumerate()
+                        .map(|(row, &count)| states.i((row, count.saturating_sub(1))))
+                        .collect::<candle_core::Result<Vec<_>>>()?;
+                    Tensor::stack(&last, 0)?
+                }
+            };
+            let vectors = if output.normalize {
+                let norms = pooled.sqr()?.sum_keepdim(1)?.sqrt()?;
+                pooled.broadcast_div(&norms)?
+            } else {
+                pooled
+            };
+
+            let weights = match (&self.sparse_head, options.sparse) {
+                (Some(head), true) => Some(
+                    head.forward(&states)?
+                        .relu()?
+                        .squeeze(2)?
+                        .to_vec2::<f32>()?,
+                ),
+                _ => None,
+            };
+            // The token vectors leave out the first token, `[CLS]`, as in ColBERT
+            let token_vectors = match (&self.multi_vector_head, options.multi_vector) {
+                (Some(head), true) => {
+                    let projected = head.forward(&states.i((.., 1..))?)?;
+                    let norms = projected.sqr()?.sum_keepdim(2)?.sqrt()?;
+                    Some(projected.broadcast_div(&norms)?.to_vec3::<f32>()?)
+                }
+                _ => None,
+            };
+
+            for (row, (vector, encoding)) in vectors
+                .to_vec2::<f32>()?
+                .into_iter()
+                .zip(&encodings)
+                .enumerate()
+            {
+                let count = tokens[row];
+                embedded.push(EncodedText {
+                    vector,
+                    tokens: count,
+                    sparse: weights.as_ref().map(|weights| {
+                        lexical_weights(
+                            &encoding.get_ids()[..count],
+                            &encoding.get_special_tokens_mask()[..count],
+                            &weights[row][..count],
+                        )
+                    }),
+                    multi_vector: token_vectors.as_ref().map(|token_vectors| {
+                        token_vectors[row][..count.saturating_sub(1)].to_vec()
+                    }),
+                });
+            }
+        }
+
+        Ok(embedded)
+    }
+}
+
+/// Loads a linear projection head from a file of the repository of the model, in the
+/// safetensors or PyTorch format.
+fn load_head(
+    repo: &ApiRepo,
+    filename: &str,
+    hub: &HubSettings,
+    device: &Device,
+) -> anyhow::Result<Linear> {
+    let path = fetch_with_retry(repo, filename, hub)?;
+    let tensors: HashMap<String, Tensor> = if filename.ends_with(".safetensors") {
+        candle_core::safetensors::load(&path, &Device::Cpu)?
+    } else {
+        candle_core::pickle::read_all(&path)?.into_iter().collect()
+    };
+    let tensor = |name: &str| {
+        tensors
+            .get(name)
+            .map(|tensor| tensor.to_dtype(DType::F32)?.to_device(device))
+            .transpose()
+    };
+    let weight = tensor("weight")?
+        .ok_or_else(|| E::msg(format!("The embedding head {filename} has no `weight`")))?;
+
+    Ok(Linear::new(weight, tensor("bias")?))
+}
+
+/// The highest weight of every token of a text, special tokens and zero weights left out.
+fn lexical_weights(ids: &[u32], special: &[u32], weights: &[f32]) -> SparseVector {
+    let mut by_token: HashMap<u32, f32> = HashMap::new();
+    for ((&id, &special), &weight) in ids.iter().zip(special).zip(weights) {
+        if special == 0 && weight > 0.0 {
+            let max = by_token.entry(id).or_insert(0.0);
+            *max = max.max(weight);
+        }
+    }
+
+    sparse_vector(by_token)
+}
+
+/// Merges the lexical weights of the chunks of a text, keeping the highest of every token.
+fn merge_sparse(chunks: &[EncodedText]) -> SparseVector {
+    let mut by_token: HashMap<u32, f32> = HashMap::new();
+    for sparse in chunks.iter().filter_map(|chunk| chunk.sparse.as_ref()) {
+        for (&id, &weight) in sparse.indices.iter().zip(&sparse.values) {
+            let max = by_token.entry(id).or_insert(0.0);
+            *max = max.max(weight);
+        }
+    }
+
+    sparse_vector(by_token)
+}
+
+fn sparse_vector(by_token: HashMap<u32, f32>) -> SparseVector {
+    let mut weights: Vec<(u32, f32)> = by_token.into_iter().collect();
+    weights.sort_unstable_by_key(|(id, _)| *id);
+
+    SparseVector {
+        indices: weights.iter().map(|(id, _)| *id).collect(),
+        values: weights.iter().map(|(_, weight)| *weight).collect(),
+    }
+}
+
+/// The mean of the vectors of the chunks of a text, weighted by their number of tokens
+/// and normalized to unit length again if the vectors are.
+fn mean_of(chunks: &[EncodedText], normalize: bool) -> Vec<f32> {
+    let dims = chunks.first().map_or(0, |chunk| chunk.vector.len());
+    let mut mean = vec![0.0f32; dims];
+    for chunk in chunks {
+        for (sum, value) in mean.iter_mut().zip(&chunk.vector) {
+            *sum += value * chunk.tokens.max(1) as f32;
+        }
+    }
+    let norm = mean.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if normalize && norm > 0.0 {
+        mean.iter_mut().for_each(|value| *value /= norm);
+    }
+
+    mean
+}
+
+/// A job of the embedding workers.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Threads dedicated to the embedding model, taking jobs from a shared queue.
+struct EmbeddingWorkers {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl EmbeddingWorkers {
+    fn spawn(count: usize) -> std::io::Result<Self> {
+        let (jobs, queued) = mpsc::channel::<Job>();
+        let queued = Arc::new(Mutex::new(queued));
+
+        for index in 0..count.max(1) {
+            let queued = queued.clone();
+            std::thread::Builder::new()
+                .name(format!("embedding-{index}"))
+                .spawn(move || loop {
+                    let job = match queued.lock().unwrap_or_else(|e| e.into_inner()).recv() {
+                        Ok(job) => job,
+                        // The embedder was dropped
+                        Err(_) => break,
+                    };
+                    // A panic fails its own job only, the thread keeps serving the queue
+                    let _ = catch_unwind(AssertUnwindSafe(job));
+                })?;
+        }
+
+        Ok(Self { jobs })
+    }
+
+    /// Runs a job on the first free worker and waits for its result.
+    fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> T + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let (reply, result) = mpsc::sync_channel(1);
+        self.jobs
+            .send(Box::new(move || {
+                let _ = reply.send(job());
+            }))
+            .map_err(|_| E::msg("The embedding workers have stopped"))?;
+
+        result
+            .recv()
+            .map_err(|_| E::msg("The embedding worker panicked"))
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::core::generator::TextGeneration;
+use crate::core::sampling::SamplingParams;
+use crate::openai::http_entities::AppState;
+
+/// An example of an evaluation dataset, one JSON object per line.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum EvalExample {
+    /// A multiple-choice question: the example is correct when the choice with the
+    /// highest log-probability after the context is the answer.
+    Choice {
+        context: String,
+        choices: Vec<String>,
+        /// The index of the correct choice.
+        answer: usize,
+    },
+    /// A text whose perplexity is measured.
+    Text { text: String },
+}
+
+/// The results of an evaluation.
+#[derive(Debug, Default)]
+pub struct EvalReport {
+    pub texts: usize,
+    pub text_tokens: usize,
+    text_logprob: f64,
+    pub questions: usize,
+    /// Questions answered correctly by the total log-probability of the choices.
+    pub correct: usize,
+    /// Questions answered correctly by the log-probability of the choices per byte, which
+    /// does not favour short choices.
+    pub correct_normalized: usize,
+}
+
+impl EvalReport {
+    /// The perplexity over the tokens of all the texts, `None` without texts.
+    pub fn perplexity(&self) -> Option<f64> {
+        (self.text_tokens > 0).then(|| (-self.text_logprob / self.text_tokens as f64).exp())
+    }
+
+    /// The share of questions answered correctly, `None` without questions.
+    pub fn accuracy(&self) -> Option<f64> {
+        (self.questions > 0).then(|| self.correct as f64 / self.questions as f64)
+    }
+
+    /// The share of questions answered correctly by length-normalized log-probability.
+    pub fn accuracy_normalized(&self) -> Option<f64> {
+        (self.questions > 0).then(|| self.correct_normalized as f64 / self.questions as f64)
+    }
+}
+
+impl fmt::Display for EvalReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(perplexity) = self.perplexity() {
+            writeln!(
+                f,
+                "texts: {}, tokens: {}, perplexity: {:.4}",
+                self.texts, self.text_tokens, perplexity
+            )?;
+        }
+        if let (Some(accuracy), Some(normalized)) = (self.accuracy(), self.accuracy_normalized()) {
+            writeln!(
+                f,
+                "questions: {}, accuracy: {:.4}, normalized accuracy: {:.4}",
+                self.questions, accuracy, normalized
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Evaluates the loaded model over a JSONL dataset.
+///
+/// Every line is either a text, `{"text": ...}`, whose tokens count towards the
+/// perplexity, or a multiple-choice question, `{"context": ..., "choices": [...],
+/// "answer": 0}`, whose choices are scored as continuations of the context. This uses
+/// the same scoring path as `/v1/score`.
+///
+/// # Arguments
+///
+/// * `state` - The application state holding the loaded model.
+/// * `dataset` - The path of the JSONL dataset.
+///
+/// # Returns
+///
+/// The perplexity and accuracy over the dataset.
+///
+/// # Errors
+///
+/// Returns an error naming the line if the dataset cannot be read or parsed, or if the
+/// model fails to score an example.
+pub fn evaluate(state: &AppState, dataset: &Path) -> anyhow::Result<EvalReport> {
+    let file = std::fs::File::open(dataset)
+        .with_context(|| format!("Error opening dataset {}", dataset.display()))?;
+    let mut report = EvalReport::default();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Error reading {}", dataset.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let example: EvalExample = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid example on line {}", index + 1))?;
+
+        evaluate_example(state, &mut report, example)
+            .with_context(|| format!("Error evaluating line {}", index + 1))?;
+        if (report.texts + report.questions) % 100 == 0 {
+            info!("Evaluated {} examples", report.texts + report.questions);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Scores one example and adds it to the report.
+fn evaluate_example(
+    state: &AppState,
+    report: &mut EvalReport,
+    example: EvalExample,
+) -> anyhow::Result<()> {
+    let params = SamplingParams::default();
+    match example {
+        EvalExample::Text { text } => {
+            let score = TextGeneration::from_state(state.clone(), &params)?.score(text, None, 0)?;
+            report.texts += 1;
+            report.text_tokens += score.scored().len();
+            report.text_logprob += f64::from(score.total_logprob());
+        }
+        EvalExample::Choice {
+            context,
+            choices,
+            answer,
+        } => {
+            if answer >= choices.len() {
+                bail!(
+                    "The answer {answer} is not one of the {} choices",
+                    choices.len()
+                );
+            }
+
+            let mut best = (f32::NEG_INFINITY, 0);
+            let mut best_normalized = (f32::NEG_INFINITY, 0);
+            for (index, choice) in choices.iter().enumerate() {
+                let score = TextGeneration::from_state(state.clone(), &params)?.score(
+                    context.clone(),
+                    Some(choice),
+                    0,
+                )?;
+                let logprob = score.total_logprob();
+                let normalized = logprob / choice.len().max(1) as f32;
+                if logprob > best.0 {
+                    best = (logprob, index);
+                }
+                if normalized > best_normalized.0 {
+                    best_normalized = (normalized, index);
+                }
+            }
+
+            report.questions += 1;
+            report.correct += usize::from(best.1 == answer);
+            report.correct_normalized += usize::from(best_normalized.1 == answer);
+        }
+    }
+    Ok(())
+}
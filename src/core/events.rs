@@ -0,0 +1,85 @@
+use std::fmt;
+
+use tokio_stream::wrappers::ReceiverStream;
+
+/// An event emitted by the engine while it generates a completion.
+///
+/// Both the SSE layer and library consumers read generations as a stream of these
+/// events, so there is a single event model from the engine to the wire.
+#[derive(Debug)]
+pub enum GenerationEvent {
+    /// A newly decoded piece of text.
+    TokenDelta(String),
+    /// A piece of a tool call being generated, for models that emit tool calls.
+    ToolCallDelta(ToolCallDelta),
+    /// The token counts of the generation so far.
+    UsageUpdate(TokenUsage),
+    /// The generation is complete, no more events follow.
+    Done { finish_reason: FinishReason },
+    /// The generation failed, no more events follow.
+    Error(anyhow::Error),
+}
+
+/// A fragment of a tool call, to be concatenated with the other fragments of the same `index`.
+#[derive(Clone, Debug)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub arguments: String,
+}
+
+/// The number of tokens consumed and produced by a generation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+impl TokenUsage {
+    /// The number of prompt and completion tokens combined.
+    pub fn total_tokens(&self) -> usize {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// Why a generation stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FinishReason {
+    /// The model produced an end-of-sequence token.
+    Stop,
+    /// The maximum number of tokens was reached.
+    Length,
+    /// The server lowered the `max_tokens` of the request, to its limits or to the room
+    /// left in the context, and the lowered maximum was reached.
+    LengthCapped,
+    /// An output guardrail rejected the generated text.
+    ContentFilter,
+    /// The deadline of the request was about to pass, the output is partial.
+    Timeout,
+    /// The model was stuck repeating itself and the degeneration guard stopped it.
+    Repetition,
+}
+
+impl FinishReason {
+    /// The name of the finish reason in the OpenAI API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Stop => "stop",
+            Self::Length => "length",
+            Self::LengthCapped => "length_capped",
+            Self::ContentFilter => "content_filter",
+            Self::Timeout => "timeout",
+            Self::Repetition => "repetition",
+        }
+    }
+}
+
+impl fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The asynchronous stream of events of one generation.
+pub type GenerationStream = ReceiverStream<GenerationEvent>;
@@ -0,0 +1,232 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::config::{FileSettings, StorageSettings};
+use crate::core::storage::{open_storage, Storage};
+use crate::openai::models::FileObject;
+
+/// Errors returned by the [`FileStore`].
+#[derive(Debug)]
+pub enum FileStoreError {
+    /// The uploaded file is bigger than `max_file_bytes`.
+    FileTooLarge { size: u64, limit: u64 },
+    /// Storing the file would exceed `max_total_bytes`.
+    StorageFull { limit: u64 },
+    /// The file id is not a valid id issued by this store.
+    InvalidId(String),
+    Io(std::io::Error),
+    Metadata(serde_json::Error),
+}
+
+impl fmt::Display for FileStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FileTooLarge { size, limit } => write!(
+                f,
+                "File of {size} bytes exceeds the maximum file size of {limit} bytes"
+            ),
+            Self::StorageFull { limit } => {
+                write!(f, "File storage limit of {limit} bytes has been reached")
+            }
+            Self::InvalidId(id) => write!(f, "Invalid file id '{id}'"),
+            Self::Io(err) => write!(f, "File storage I/O error: {err}"),
+            Self::Metadata(err) => write!(f, "File metadata error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for FileStoreError {}
+
+impl From<std::io::Error> for FileStoreError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for FileStoreError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Metadata(err)
+    }
+}
+
+/// A store for files uploaded through the `/v1/files` endpoints.
+///
+/// Every file is kept as two artifacts of the [`Storage`]: `<id>` holding the raw
+/// content and `<id>.json` holding its [`FileObject`] metadata. Writes and deletions are
+/// serialized so the total size limit is enforced consistently.
+pub struct FileStore {
+    settings: FileSettings,
+    storage: Arc<dyn Storage>,
+    write_lock: Mutex<()>,
+}
+
+impl FileStore {
+    /// Opens the store in the configured storage, the `directory` of the settings on the
+    /// local disk or the `files` folder of the object storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The storage directory and size limits.
+    /// * `storage` - The configured storage backend.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage directory cannot be created or the object storage
+    /// is misconfigured.
+    pub fn open(settings: FileSettings, storage: &StorageSettings) -> Result<Self, FileStoreError> {
+        let storage = open_storage(storage, settings.directory.clone(), "files")?;
+        Ok(Self::with_storage(settings, storage))
+    }
+
+    /// Creates the store on a storage of the caller, such as a custom backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The size limits, whose directory is unused.
+    /// * `storage` - Where the files are kept.
+    pub fn with_storage(settings: FileSettings, storage: Arc<dyn Storage>) -> Self {
+        Self {
+            settings,
+            storage,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Returns the maximum size in bytes of a single file.
+    pub fn max_file_bytes(&self) -> u64 {
+        self.settings.max_file_bytes
+    }
+
+    /// Stores a new file and returns its metadata.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - The original name of the uploaded file.
+    /// * `purpose` - The intended purpose of the file, e.g. `batch`.
+    /// * `content` - The raw content of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a size limit is exceeded or the file cannot be written.
+    pub fn create(
+        &self,
+        filename: &str,
+        purpose: &str,
+        content: &[u8],
+    ) -> Result<FileObject, FileStoreError> {
+        let size = content.len() as u64;
+        if size > self.settings.max_file_bytes {
+            return Err(FileStoreError::FileTooLarge {
+                size,
+                limit: self.settings.max_file_bytes,
+            });
+        }
+
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(limit) = self.settings.max_total_bytes {
+            // The contents' sizes come with the listing, without reading any metadata
+            let used: u64 = self
+                .storage
+                .list("file-")?
+                .iter()
+                .filter(|entry| !entry.key.ends_with(".json"))
+                .map(|entry| entry.size)
+                .sum();
+            if used + size > limit {
+                return Err(FileStoreError::StorageFull { limit });
+            }
+        }
+
+        let file = FileObject {
+            id: format!("file-{}", Uuid::new_v4().simple()),
+            object: "file".to_string(),
+            bytes: size,
+            created_at: Utc::now().timestamp(),
+            filename: filename.to_string(),
+            purpose: purpose.to_string(),
+        };
+
+        self.storage.put(&self.content_key(&file.id)?, content)?;
+        self.storage
+            .put(&self.metadata_key(&file.id)?, &serde_json::to_vec(&file)?)?;
+
+        Ok(file)
+    }
+
+    /// Lists the stored files, newest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `purpose` - When set, only files with this purpose are returned.
+    pub fn list(&self, purpose: Option<&str>) -> Result<Vec<FileObject>, FileStoreError> {
+        let mut files = Vec::new();
+        for entry in self.storage.list("file-")? {
+            if !entry.key.ends_with(".json") {
+                continue;
+            }
+            // A file deleted while listing
+            let Some(bytes) = self.storage.get(&entry.key)? else {
+                continue;
+            };
+            let file: FileObject = serde_json::from_slice(&bytes)?;
+            if purpose.map_or(true, |p| p == file.purpose) {
+                files.push(file);
+            }
+        }
+        files.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        Ok(files)
+    }
+
+    /// Returns the metadata of a file, or `None` if it does not exist.
+    pub fn get(&self, id: &str) -> Result<Option<FileObject>, FileStoreError> {
+        match self.storage.get(&self.metadata_key(id)?)? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the content of a file, or `None` if it does not exist.
+    pub fn content(&self, id: &str) -> Result<Option<Vec<u8>>, FileStoreError> {
+        Ok(self.storage.get(&self.content_key(id)?)?)
+    }
+
+    /// Deletes a file.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the file existed and was deleted, `false` if it did not exist.
+    pub fn delete(&self, id: &str) -> Result<bool, FileStoreError> {
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        if !self.storage.delete(&self.metadata_key(id)?)? {
+            return Ok(false);
+        }
+        self.storage.delete(&self.content_key(id)?)?;
+        Ok(true)
+    }
+
+    /// Returns the key of the content of a file.
+    ///
+    /// Ids come straight from request paths, so anything but the `file-<hex>` form
+    /// issued by [`FileStore::create`] is rejected to keep lookups inside the storage.
+    fn content_key(&self, id: &str) -> Result<String, FileStoreError> {
+        let valid = id
+            .strip_prefix("file-")
+            .is_some_and(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()));
+        if !valid {
+            return Err(FileStoreError::InvalidId(id.to_string()));
+        }
+
+        Ok(id.to_string())
+    }
+
+    /// Returns the key of the metadata of a file.
+    fn metadata_key(&self, id: &str) -> Result<String, FileStoreError> {
+        Ok(format!("{}.json", self.content_key(id)?))
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::bail;
+use serde::Deserialize;
+use tokenizers::Tokenizer;
+
+/// The special tokens code models are trained with for fill-in-the-middle.
+///
+/// A fill-in-the-middle prompt gives the model the code before and after the cursor,
+/// in prefix-suffix-middle order, and the model generates the code in between:
+/// `{prefix}<code before>{suffix}<code after>{middle}`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FimTemplate {
+    /// The token placed before the code preceding the cursor.
+    pub prefix: String,
+    /// The token placed before the code following the cursor.
+    pub suffix: String,
+    /// The token after which the model generates the missing code.
+    pub middle: String,
+    /// The token the model ends the missing code with, when it is not the end of sequence.
+    #[serde(default)]
+    pub end: Option<String>,
+}
+
+/// The templates of the code model families, tried in order on the tokenizer vocabulary.
+const KNOWN_TEMPLATES: [(&str, &str, &str, Option<&str>); 4] = [
+    // StarCoder, StarCoder2 and their fine-tunes
+    ("<fim_prefix>", "<fim_suffix>", "<fim_middle>", None),
+    // Qwen2.5-Coder
+    (
+        "<|fim_prefix|>",
+        "<|fim_suffix|>",
+        "<|fim_middle|>",
+        Some("<|endoftext|>"),
+    ),
+    // DeepSeek-Coder
+    ("<｜fim▁begin｜>", "<｜fim▁hole｜>", "<｜fim▁end｜>", None),
+    // Code Llama
+    ("▁<PRE>", "▁<SUF>", "▁<MID>", Some("▁<EOT>")),
+];
+
+impl FimTemplate {
+    /// Finds the fill-in-the-middle tokens of a known code model family in a tokenizer.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokenizer` - The tokenizer of the served model.
+    ///
+    /// # Returns
+    ///
+    /// The template whose tokens are all in the vocabulary, or `None` if the model was
+    /// not trained for fill-in-the-middle.
+    pub fn detect(tokenizer: &Tokenizer) -> Option<Self> {
+        KNOWN_TEMPLATES
+            .iter()
+            .map(|&(prefix, suffix, middle, end)| Self {
+                prefix: prefix.to_string(),
+                suffix: suffix.to_string(),
+                middle: middle.to_string(),
+                end: end.map(str::to_string),
+            })
+            .find(|template| template.check(tokenizer).is_ok())
+    }
+
+    /// Checks that every token of the template is a single token of the vocabulary.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first token missing from the vocabulary.
+    pub fn check(&self, tokenizer: &Tokenizer) -> anyhow::Result<()> {
+        let tokens = [&self.prefix, &self.suffix, &self.middle]
+            .into_iter()
+            .chain(self.end.as_ref());
+        for token in tokens {
+            if tokenizer.token_to_id(token).is_none() {
+                bail!("The fill-in-the-middle token '{token}' is not in the vocabulary");
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the prompt asking the model for the code between a prefix and a suffix.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The code before the cursor.
+    /// * `suffix` - The code after the cursor.
+    pub fn format(&self, prefix: &str, suffix: &str) -> String {
+        format!(
+            "{}{}{}{}{}",
+            self.prefix, prefix, self.suffix, suffix, self.middle
+        )
+    }
+
+    /// Returns the id of the token ending the missing code, if the template has one.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokenizer` - The tokenizer of the served model.
+    pub fn end_token(&self, tokenizer: &Tokenizer) -> Option<u32> {
+        self.end
+            .as_deref()
+            .and_then(|token| tokenizer.token_to_id(token))
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::openai::errors::ErrorBody;
+
+/// Where an asynchronous generation stands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    InProgress,
+    Completed,
+    Failed,
+}
+
+/// An asynchronous generation, returned by `/v1/generations`.
+#[derive(Clone, Debug, Serialize)]
+pub struct GenerationJob {
+    pub id: String,
+    pub object: String,
+    pub created_at: i64,
+    /// The endpoint the request is run on, e.g. `/v1/chat/completions`.
+    pub url: String,
+    pub status: JobStatus,
+    pub completed_at: Option<i64>,
+    /// The body the endpoint answered, once the generation completed.
+    pub response: Option<Value>,
+    /// Why the generation failed.
+    pub error: Option<ErrorBody>,
+}
+
+impl GenerationJob {
+    /// Whether the generation ended, successfully or not.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status, JobStatus::Completed | JobStatus::Failed)
+    }
+}
+
+/// A registered generation, readable by the API key that created it only.
+struct JobEntry {
+    /// The digest of the API key of the request, `None` without a key.
+    owner: Option<String>,
+    job: watch::Sender<GenerationJob>,
+}
+
+/// The handle through which a running generation reports its progress.
+pub struct JobHandle {
+    entry: Arc<JobEntry>,
+}
+
+impl JobHandle {
+    /// The generation as it currently stands.
+    pub fn snapshot(&self) -> GenerationJob {
+        self.entry.job.borrow().clone()
+    }
+
+    /// Marks the generation as running.
+    pub fn start(&self) {
+        self.entry
+            .job
+            .send_modify(|job| job.status = JobStatus::InProgress);
+    }
+
+    /// Marks the generation as completed and wakes up the clients polling it.
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The body the endpoint answered.
+    pub fn complete(&self, response: Value) {
+        self.entry.job.send_modify(|job| {
+            job.status = JobStatus::Completed;
+            job.completed_at = Some(Utc::now().timestamp());
+            job.response = Some(response);
+        });
+    }
+
+    /// Marks the generation as failed and wakes up the clients polling it.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The error the endpoint answered.
+    pub fn fail(&self, error: ErrorBody) {
+        self.entry.job.send_modify(|job| {
+            job.status = JobStatus::Failed;
+            job.completed_at = Some(Utc::now().timestamp());
+            job.error = Some(error);
+        });
+    }
+}
+
+/// The asynchronous generations, by id.
+///
+/// A generation stays readable for `retention` after it ended, so that a client whose
+/// poll timed out can come back for the result without regenerating it.
+pub struct GenerationJobs {
+    jobs: Mutex<HashMap<String, Arc<JobEntry>>>,
+    retention: Duration,
+}
+
+impl GenerationJobs {
+    /// Creates an empty registry.
+    ///
+    /// # Arguments
+    ///
+    /// * `retention` - How long an ended generation can still be read.
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            retention,
+        }
+    }
+
+    /// Registers a new queued generation and drops the generations whose retention has
+    /// passed.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The endpoint the request is run on.
+    /// * `owner` - The digest of the API key of the request.
+    ///
+    /// # Returns
+    ///
+    /// The handle through which the generation reports its progress.
+    pub fn create(&self, url: &str, owner: Option<String>) -> JobHandle {
+        let job = GenerationJob {
+            id: format!("gen_{}", Uuid::new_v4().simple()),
+            object: "generation".to_string(),
+            created_at: Utc::now().timestamp(),
+            url: url.to_string(),
+            status: JobStatus::Queued,
+            completed_at: None,
+            response: None,
+            error: None,
+        };
+        let entry = Arc::new(JobEntry {
+            owner,
+            job: watch::Sender::new(job.clone()),
+        });
+
+        let mut jobs = self.lock();
+        jobs.retain(|_, entry| !self.expired(entry));
+        jobs.insert(job.id, entry.clone());
+
+        JobHandle { entry }
+    }
+
+    /// Returns a generation, waiting for it to end if it is still running.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the generation.
+    /// * `owner` - The digest of the API key of the request.
+    /// * `wait` - How long to wait for the generation to end, zero to return right away.
+    ///
+    /// # Returns
+    ///
+    /// The generation as it stands when it ended or the wait ran out, `None` if it does
+    /// not exist, expired or belongs to another key.
+    pub async fn wait(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+        wait: Duration,
+    ) -> Option<GenerationJob> {
+        let entry = self
+            .lock()
+            .get(id)
+            .filter(|entry| entry.owner.as_deref() == owner && !self.expired(entry))
+            .cloned()?;
+
+        let mut job = entry.job.subscribe();
+        if !wait.is_zero() {
+            let _ = tokio::time::timeout(wait, job.wait_for(GenerationJob::is_finished)).await;
+        }
+        let job = job.borrow().clone();
+        Some(job)
+    }
+
+    fn expired(&self, entry: &JobEntry) -> bool {
+        let deadline = Utc::now().timestamp() - self.retention.as_secs() as i64;
+        entry
+            .job
+            .borrow()
+            .completed_at
+            .is_some_and(|at| at < deadline)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Arc<JobEntry>>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The weight of the latest generation in the mean duration of the generations.
+const DURATION_SMOOTHING: f64 = 0.2;
+
+/// Where a waiting generation stands in the queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueuePosition {
+    /// The generations waiting ahead of this one.
+    pub ahead: usize,
+    /// The estimated time until this generation starts, from the mean duration of the
+    /// recent generations.
+    pub eta: Duration,
+}
+
+/// Limits the number of generations running at once, the others waiting in the order
+/// they arrived.
+pub struct GenerationQueue {
+    max_running: usize,
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+struct QueueState {
+    running: usize,
+    /// The ticket of the next generation to arrive.
+    next_ticket: u64,
+    /// The ticket of the next generation to start.
+    next_admitted: u64,
+    /// The mean duration of the recent generations, in seconds.
+    mean_secs: f64,
+}
+
+/// A running generation, whose slot is given to the next waiting one once dropped.
+pub struct QueueSlot {
+    queue: Arc<GenerationQueue>,
+    started_at: Instant,
+}
+
+impl GenerationQueue {
+    /// Creates a queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_running` - The number of generations running at once, at least one.
+    pub fn new(max_running: usize) -> Self {
+        Self {
+            max_running: max_running.max(1),
+            state: Mutex::new(QueueState {
+                running: 0,
+                next_ticket: 0,
+                next_admitted: 0,
+                mean_secs: 0.0,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Waits, blocking the thread, until the generation may run.
+    ///
+    /// # Arguments
+    ///
+    /// * `on_wait` - Called with the position of the generation whenever it changes while
+    ///   it waits, and not at all when it starts right away.
+    ///
+    /// # Returns
+    ///
+    /// The slot of the generation, held until it ends.
+    pub fn enter(self: &Arc<Self>, mut on_wait: impl FnMut(QueuePosition)) -> QueueSlot {
+        let mut state = self.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+
+        let mut reported = None;
+        while ticket != state.next_admitted || state.running >= self.max_running {
+            let position = self.position(&state, ticket);
+            if reported != Some(position.ahead) {
+                reported = Some(position.ahead);
+                on_wait(position);
+            }
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.next_admitted += 1;
+        state.running += 1;
+        drop(state);
+        // The next ticket may start too if there is room
+        self.changed.notify_all();
+
+        QueueSlot {
+            queue: self.clone(),
+            started_at: Instant::now(),
+        }
+    }
+
+    /// The number of generations waiting to start.
+    pub fn waiting(&self) -> usize {
+        let state = self.lock();
+        (state.next_ticket - state.next_admitted) as usize
+    }
+
+    fn position(&self, state: &QueueState, ticket: u64) -> QueuePosition {
+        let ahead = (ticket - state.next_admitted) as usize;
+        // The generations ahead start by batches of `max_running`
+        let rounds = ahead / self.max_running + 1;
+        QueuePosition {
+            ahead,
+            eta: Duration::from_secs_f64(state.mean_secs * rounds as f64),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        let elapsed = self.started_at.elapsed().as_secs_f64();
+        let mut state = self.queue.lock();
+        state.running -= 1;
+        state.mean_secs = if state.mean_secs == 0.0 {
+            elapsed
+        } else {
+            state.mean_secs + DURATION_SMOOTHING * (elapsed - state.mean_secs)
+        };
+        drop(state);
+        self.queue.changed.notify_all();
+    }
+}
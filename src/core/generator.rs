@@ -1,26 +1,85 @@
+use std::sync::Arc;
+
+use crate::config::RetrySettings;
+use crate::core::circuit_breaker::{
+    is_backend_failure, is_transient_failure, record_step_retry, CircuitBreaker,
+};
+use crate::core::deadline::Deadline;
+use crate::core::degeneration::{entropy, DegenerationGuard};
+use crate::core::events::{FinishReason, GenerationEvent, GenerationStream, TokenUsage};
+use crate::core::generation_queue::GenerationQueue;
+use crate::core::guardrails::{GuardrailStage, GuardrailViolation, Guardrails};
 use crate::core::output_stream::TokenOutputStream;
+use crate::core::prediction::{PredictedOutput, SPECULATION_WINDOW};
+use crate::core::prefix_cache::PrefixCache;
+use crate::core::sampling::{Penalties, Sampler, SamplingParams};
+use crate::core::scoring::{PromptScore, TokenScore};
+use crate::core::stats::EngineStats;
+use crate::core::text_model::TextModel;
+use crate::core::usage_meter::UsageMeter;
 use crate::openai::http_entities::AppState;
 use anyhow::Error;
 use candle_core::{DType, Device, Tensor};
-use candle_transformers::generation::{LogitsProcessor, Sampling};
-use candle_transformers::models::llama::{Cache, Config, Llama as Llama3, LlamaEosToks};
+use candle_transformers::models::llama::{Cache, Config, LlamaEosToks};
 use tokenizers::Tokenizer;
-use tracing::info;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, trace, warn};
+
+/// The prompt of a generation, as text or as the token ids of the served model's tokenizer.
+///
+/// Token prompts are fed to the model as they are, for clients that tokenize themselves
+/// and need exact control of the prompt.
+#[derive(Clone, Debug)]
+pub enum PromptInput {
+    Text(String),
+    Tokens(Vec<u32>),
+}
+
+impl From<String> for PromptInput {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
 
 /// A struct representing text generation using the Llama3 model.
 ///
 /// The `TextGeneration` struct contains fields for the Llama3 model, device,
-/// tokenizer, logits processor, repeat penalty, repeat last n, and configuration.
+/// tokenizer, sampler, repeat penalty, repeat last n, configuration and
+/// the guardrail hooks run around generation.
 /// It provides methods to create a new `TextGeneration` instance and generate
 /// text based on a given prompt.
 pub struct TextGeneration {
-    model: Llama3,
+    model: TextModel,
     device: Device,
     tokenizer: TokenOutputStream,
-    logits_processor: LogitsProcessor,
+    sampler: Sampler,
     repeat_penalty: f32,
     repeat_last_n: usize,
+    frequency_penalty: f32,
+    presence_penalty: f32,
     pub(crate) config: Config,
+    dtype: DType,
+    guardrails: Arc<Guardrails>,
+    max_tokens: usize,
+    /// The `max_tokens` sent by the client, to tell it when the server lowered it.
+    requested_max_tokens: Option<usize>,
+    stop_tokens: Vec<u32>,
+    /// Stops the generation once it is stuck in a confident loop, when enabled.
+    degeneration: Option<DegenerationGuard>,
+    /// How the steps failing with a transient device error are retried.
+    retries: RetrySettings,
+    deadline: Option<Deadline>,
+    /// Counts the tokens and the queue time of the request, for its usage headers.
+    meter: Option<Arc<UsageMeter>>,
+    prediction: Option<PredictedOutput>,
+    /// The retained KV caches, with the revision of the weights of `model`.
+    prefix_cache: Option<(Arc<PrefixCache>, String)>,
+    /// Told whether the model ran, to stop serving a model that keeps failing.
+    breaker: Option<Arc<CircuitBreaker>>,
+    /// Where the generation waits for its turn when the running generations are limited.
+    queue: Option<Arc<GenerationQueue>>,
+    stats: Arc<EngineStats>,
 }
 
 impl TextGeneration {
@@ -28,7 +87,7 @@ impl TextGeneration {
     ///
     /// # Arguments
     ///
-    /// * `model` - The Llama3 model to use for text generation.
+    /// * `model` - The language model to use for text generation.
     /// * `tokenizer` - The tokenizer to use for encoding and decoding text.
     /// * `seed` - The seed value for the random number generator.
     /// * `temperature` - Optional temperature value for sampling.
@@ -38,13 +97,17 @@ impl TextGeneration {
     /// * `repeat_last_n` - The number of last tokens to consider for repeat penalty.
     /// * `device` - The device to use for computations.
     /// * `config` - The configuration settings.
+    /// * `dtype` - The data type of the model weights.
+    /// * `guardrails` - The hooks run over the prompt and the generated text.
+    /// * `max_tokens` - The maximum number of tokens to generate.
+    /// * `stats` - The engine counters updated while generating.
     ///
     /// # Returns
     ///
     /// A new `TextGeneration` instance with the specified parameters.
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
-        model: Llama3,
+        model: TextModel,
         tokenizer: Tokenizer,
         seed: u64,
         temperature: Option<f64>,
@@ -54,140 +117,831 @@ impl TextGeneration {
         repeat_last_n: usize,
         device: &Device,
         config: Config,
+        dtype: DType,
+        guardrails: Arc<Guardrails>,
+        max_tokens: usize,
+        stats: Arc<EngineStats>,
     ) -> Self {
-        let logits_processor = {
-            let temperature = temperature.unwrap_or_else(|| 0f64);
-
-            let sampling = if temperature <= 0. {
-                Sampling::ArgMax
-            } else {
-                match (top_k, top_p) {
-                    (None, None) => Sampling::All { temperature },
-                    (Some(k), None) => Sampling::TopK { k, temperature },
-                    (None, Some(p)) => Sampling::TopP { p, temperature },
-                    (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
-                }
-            };
-            LogitsProcessor::from_sampling(seed, sampling)
+        let stop_tokens = match &config.eos_token_id {
+            Some(LlamaEosToks::Single(id)) => vec![*id],
+            Some(LlamaEosToks::Multiple(ids)) => ids.clone(),
+            None => Vec::new(),
         };
 
         Self {
             model,
             tokenizer: TokenOutputStream::new(tokenizer),
-            logits_processor,
+            sampler: Sampler::new(seed, temperature, top_p, top_k),
             repeat_penalty,
             repeat_last_n,
+            frequency_penalty: 0.,
+            presence_penalty: 0.,
             device: device.clone(),
             config,
+            dtype,
+            guardrails,
+            max_tokens,
+            requested_max_tokens: None,
+            stop_tokens,
+            degeneration: None,
+            retries: RetrySettings::default(),
+            deadline: None,
+            meter: None,
+            prediction: None,
+            prefix_cache: None,
+            breaker: None,
+            queue: None,
+            stats,
+        }
+    }
+
+    /// Adds tokens that end the generation like the end of sequence token.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens` - The ids of the tokens, which are not part of the generated text.
+    pub(crate) fn with_stop_tokens(mut self, tokens: impl IntoIterator<Item = u32>) -> Self {
+        self.stop_tokens.extend(tokens);
+        self
+    }
+
+    /// Keeps generating past the end-of-sequence tokens of the model, up to the maximum
+    /// number of tokens. Stop tokens added afterwards still end the generation.
+    pub(crate) fn ignoring_eos(mut self) -> Self {
+        self.stop_tokens.clear();
+        self
+    }
+
+    /// Sets the minimum probability of a token relative to the most likely one.
+    fn with_min_p(mut self, min_p: Option<f64>) -> Self {
+        self.sampler = self.sampler.with_min_p(min_p);
+        self
+    }
+
+    /// Sets the OpenAI frequency and presence penalties, which only count generated tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `frequency_penalty` - Subtracted from a logit for every time its token was generated.
+    /// * `presence_penalty` - Subtracted from a logit once its token was generated.
+    pub(crate) fn with_output_penalties(
+        mut self,
+        frequency_penalty: f32,
+        presence_penalty: f32,
+    ) -> Self {
+        self.frequency_penalty = frequency_penalty;
+        self.presence_penalty = presence_penalty;
+        self
+    }
+
+    /// Resumes the generation from the retained KV cache of an earlier one when possible,
+    /// and retains its own KV cache once done.
+    ///
+    /// Only the single-device full-precision model keeps its KV cache outside of the
+    /// weights, other models ignore the prefix cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix_cache` - The retained KV caches.
+    /// * `revision` - The revision of the weights of the model of this generation.
+    pub(crate) fn with_prefix_cache(
+        mut self,
+        prefix_cache: Arc<PrefixCache>,
+        revision: String,
+    ) -> Self {
+        if self.model.has_external_cache() {
+            self.prefix_cache = Some((prefix_cache, revision));
+        }
+        self
+    }
+
+    /// Runs the model over the prompt and retains its KV cache, so the generations of the
+    /// same prompt resume from it.
+    ///
+    /// The cache covers every token of the prompt but the last one, which the
+    /// generations run themselves to get the logits of their first token. Nothing is done
+    /// without a prefix cache, or when the prompt is already cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The prompt text or tokens shared by the generations.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `GuardrailViolation` if a guardrail rejects the prompt, or an error if the
+    /// forward pass fails.
+    pub(crate) fn prefill(&mut self, prompt: impl Into<PromptInput>) -> anyhow::Result<()> {
+        let Some((prefix_cache, revision)) = self.prefix_cache.clone() else {
+            return Ok(());
+        };
+        let tokens = self.prompt_tokens(prompt.into())?;
+        let shared = &tokens[..tokens.len().saturating_sub(1)];
+        if shared.is_empty() {
+            return Ok(());
         }
+        if let Some((cached, _)) = prefix_cache.lookup(&revision, &tokens) {
+            if cached == shared.len() {
+                return Ok(());
+            }
+        }
+
+        let mut cache = Cache::new(true, self.dtype, &self.config, &self.device)?;
+        let input = Tensor::from_slice(shared, (1, shared.len()), &self.device)?;
+        self.model.forward(&input, 0, &mut cache)?;
+        info!("Prefilled {} shared prompt tokens", shared.len());
+        prefix_cache.store(revision, shared.to_vec(), cache);
+
+        Ok(())
     }
 
-    /// Generates text based on the given prompt and maximum number of tokens.
+    /// Creates a new `TextGeneration` instance for a request.
+    ///
+    /// Parameters missing from `params` take the defaults configured for the served
+    /// model, and all values are clamped to its configured limits. If the model was
+    /// unloaded while idle, its weights are reloaded first.
     ///
     /// # Arguments
     ///
-    /// * `prompt` - The prompt string to use for text generation.
-    /// * `max_tokens` - Optional maximum number of tokens to generate.
+    /// * `app_state` - The application state holding the model and its settings.
+    /// * `params` - The sampling parameters of the request.
     ///
     /// # Returns
     ///
-    /// The generated text as a string.
-    pub(crate) fn generate(mut self, prompt: String, max_tokens: Option<i32>) -> String {
-        self.tokenizer.clear();
-        let mut tokens = self
+    /// A new `TextGeneration` instance with the resolved parameters.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CircuitOpen` while the model is recovering from repeated failures, or an
+    /// error if the model weights cannot be reloaded.
+    pub fn from_state(app_state: AppState, params: &SamplingParams) -> anyhow::Result<Self> {
+        let breaker = app_state.breaker.clone();
+        if let Some(breaker) = &breaker {
+            breaker.check()?;
+        }
+
+        let mut text_gen = Self::from_state_unchecked(app_state, params)?;
+        text_gen.breaker = breaker;
+        Ok(text_gen)
+    }
+
+    /// Creates a new `TextGeneration` instance like [`TextGeneration::from_state`], even
+    /// while the circuit is open, and without reporting its outcome to the circuit breaker.
+    ///
+    /// Used by the warmup generation checking a recovered model.
+    ///
+    /// # Arguments
+    ///
+    /// * `app_state` - The application state holding the model and its settings.
+    /// * `params` - The sampling parameters of the request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the model weights cannot be reloaded.
+    pub(crate) fn from_state_unchecked(
+        app_state: AppState,
+        params: &SamplingParams,
+    ) -> anyhow::Result<Self> {
+        let settings = app_state.settings.current();
+        let model_settings = settings.model_settings(&settings.model.id);
+        let model = app_state.model.acquire()?;
+        let revision = app_state.model.revision();
+
+        let text_gen = Self::new(
+            model,
+            app_state.tokenizer,
+            params.seed.unwrap_or(model_settings.defaults.seed),
+            model_settings.temperature(params.temperature),
+            model_settings.top_p(params.top_p),
+            model_settings.top_k(params.top_k),
+            model_settings.repeat_penalty(params.repeat_penalty),
+            model_settings.defaults.repeat_last_n,
+            &app_state.device,
+            app_state.config,
+            app_state.dtype,
+            app_state.guardrails,
+            model_settings.max_tokens(params.max_tokens),
+            app_state.stats,
+        )
+        .with_stop_tokens(app_state.stop_tokens.iter().copied())
+        .with_min_p(params.min_p)
+        .with_output_penalties(
+            params.frequency_penalty.unwrap_or(0.),
+            params.presence_penalty.unwrap_or(0.),
+        );
+        let mut text_gen = match app_state.prefix_cache {
+            Some(prefix_cache) => text_gen.with_prefix_cache(prefix_cache, revision),
+            None => text_gen,
+        };
+        text_gen.queue = app_state.queue;
+        text_gen.requested_max_tokens = params.max_tokens.map(|t| t.max(0) as usize);
+        text_gen.degeneration = DegenerationGuard::new(&model_settings.degeneration);
+        text_gen.retries = settings.retries.clone();
+        Ok(text_gen)
+    }
+
+    /// Generates text based on the given prompt, up to the maximum number of tokens.
+    ///
+    /// The prompt guardrails run before the prompt is tokenized and the output
+    /// guardrails run over the generated text before it is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The prompt text or tokens to use for text generation.
+    ///
+    /// # Returns
+    ///
+    /// The generated text as a string, and why the generation stopped.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `GuardrailViolation` if a guardrail rejects the prompt or the output, or
+    /// `DeadlineExceeded` if the deadline passed before generation started.
+    pub(crate) fn generate(
+        self,
+        prompt: impl Into<PromptInput>,
+    ) -> anyhow::Result<(String, FinishReason)> {
+        let mut finish_reason = FinishReason::Stop;
+        let text = self.generate_streaming(prompt, |event| {
+            if let GenerationEvent::Done {
+                finish_reason: reason,
+            } = event
+            {
+                finish_reason = reason;
+            }
+            Ok(())
+        })?;
+
+        Ok((text, finish_reason))
+    }
+
+    /// Computes the logits of the token following the prompt, without sampling it.
+    ///
+    /// The prompt guardrails run before the prompt is tokenized. A single forward pass
+    /// is made, which is what constrained decoding over a closed set of tokens needs.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The prompt string.
+    ///
+    /// # Returns
+    ///
+    /// The logits over the vocabulary, and the number of tokens of the prompt.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `GuardrailViolation` if a guardrail rejects the prompt, or an error if the
+    /// prompt cannot be tokenized or the forward pass fails.
+    pub(crate) fn next_token_logits(mut self, prompt: String) -> anyhow::Result<(Vec<f32>, usize)> {
+        let mut generation = self.stats.start_generation();
+        let prompt = self.guardrails.check_prompt(prompt)?;
+
+        let tokens = self
             .tokenizer
             .tokenizer()
             .encode(prompt, true)
-            .unwrap()
+            .map_err(Error::msg)?
             .get_ids()
             .to_vec();
+        if let Some(meter) = &self.meter {
+            meter.record_start(tokens.len());
+        }
+        let mut cache = Cache::new(true, self.dtype, &self.config, &self.device)?;
+        generation.set_kv_cache_tokens(tokens.len());
 
-        info!("Got tokens!");
+        let input = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
+        let logits = self
+            .model
+            .forward(&input, 0, &mut cache)?
+            .squeeze(0)?
+            .to_dtype(DType::F32)?
+            .to_vec1::<f32>()?;
 
-        let origin_config = self.config.clone();
+        Ok((logits, tokens.len()))
+    }
 
-        let eos_token = self.config.eos_token_id.or_else(|| {
-            let option = self.tokenizer.tokenizer().token_to_id("</s>").unwrap();
-            let toks = LlamaEosToks::Single(option);
-            Some(toks)
-        });
+    /// Scores a text: computes the log-probability of each of its tokens given the tokens
+    /// before it, without generating anything.
+    ///
+    /// The prompt guardrails run over the prompt. Models returning the logits of every
+    /// position score the text in one forward pass; the others read it one token at a time
+    /// with their KV cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The text or tokens to score, or the context of the continuation.
+    /// * `continuation` - Text appended to the prompt whose tokens alone are scored, e.g.
+    ///   one of the answers of a multiple-choice question.
+    /// * `top_logprobs` - The number of most likely tokens to return at every position.
+    ///
+    /// # Returns
+    ///
+    /// The score of every token of the prompt and the continuation.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `GuardrailViolation` if a guardrail rejects the prompt, or an error if the
+    /// text cannot be tokenized or the forward pass fails.
+    pub(crate) fn score(
+        mut self,
+        prompt: impl Into<PromptInput>,
+        continuation: Option<&str>,
+        top_logprobs: usize,
+    ) -> anyhow::Result<PromptScore> {
+        if let Some(deadline) = &self.deadline {
+            deadline.check()?;
+        }
+        let mut generation = self.stats.start_generation();
+
+        let mut tokens = self.prompt_tokens(prompt.into())?;
+        let mut scored_from = 1;
+        if let Some(continuation) = continuation {
+            scored_from = tokens.len();
+            let encoding = self
+                .tokenizer
+                .tokenizer()
+                .encode(continuation, false)
+                .map_err(Error::msg)?;
+            tokens.extend(encoding.get_ids());
+        }
+        if let Some(meter) = &self.meter {
+            meter.record_start(tokens.len());
+        }
+
+        let mut positions = Vec::with_capacity(tokens.len().saturating_sub(1));
+        let input = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
+        if let Some(logits) = self.model.forward_all(&input)? {
+            for (position, &token) in tokens.iter().enumerate().skip(1) {
+                let logits = logits.get(position - 1)?;
+                positions.push(TokenScore::from_logits(&logits, token, top_logprobs)?);
+            }
+        } else {
+            let mut cache = Cache::new(true, self.dtype, &self.config, &self.device)?;
+            for (position, window) in tokens.windows(2).enumerate() {
+                let input = Tensor::new(&window[..1], &self.device)?.unsqueeze(0)?;
+                let logits = self
+                    .model
+                    .forward(&input, position, &mut cache)?
+                    .squeeze(0)?
+                    .to_dtype(DType::F32)?;
+                generation.set_kv_cache_tokens(position + 1);
+                positions.push(TokenScore::from_logits(&logits, window[1], top_logprobs)?);
+            }
+        }
+
+        Ok(PromptScore {
+            tokens,
+            scored_from,
+            positions,
+        })
+    }
+
+    /// Sets the time by which the generation must return.
+    ///
+    /// A generation starting after its deadline fails, and a running one stops with the
+    /// `Timeout` finish reason when the next token would not be ready in time.
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - The deadline of the request, if the client sent one.
+    pub(crate) fn with_deadline(mut self, deadline: Option<Deadline>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Sets the meter counting the usage of the request this generation belongs to.
+    ///
+    /// The meter records when the prompt starts being processed, and the number of
+    /// tokens of the prompt and of the completion.
+    ///
+    /// # Arguments
+    ///
+    /// * `meter` - The meter shared by the generations of the request.
+    pub(crate) fn with_meter(mut self, meter: Arc<UsageMeter>) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// Sets the output the completion is expected to be close to, speeding up rewrites.
+    ///
+    /// The predicted tokens are verified several at a time on models that return the
+    /// logits of every position, the sharded model. The other models decode one token
+    /// at a time as without a prediction. The prediction never changes the generated text.
+    ///
+    /// # Arguments
+    ///
+    /// * `prediction` - The predicted text.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the prediction cannot be tokenized.
+    pub(crate) fn with_prediction(mut self, prediction: &str) -> anyhow::Result<Self> {
+        let tokens = self
+            .tokenizer
+            .tokenizer()
+            .encode(prediction, false)
+            .map_err(Error::msg)?
+            .get_ids()
+            .to_vec();
+        self.prediction = Some(PredictedOutput::new(tokens));
+        Ok(self)
+    }
+
+    /// Runs the model over the context and samples the next tokens, verifying the draft
+    /// of the predicted output when there is one.
+    ///
+    /// # Returns
+    ///
+    /// The sampled tokens and, with a degeneration guard, their entropies.
+    fn step(
+        &mut self,
+        tokens: &mut Vec<u32>,
+        draft: &[u32],
+        penalty: &Penalties,
+        cache: &mut Cache,
+        index_pos: &mut usize,
+    ) -> anyhow::Result<(Vec<u32>, Vec<f32>)> {
+        if let Some(sampled) = self.verify_draft(tokens, draft, penalty)? {
+            return Ok(sampled);
+        }
+
+        let (context_size, context_index) = if cache.use_kv_cache {
+            (tokens.len() - *index_pos, *index_pos)
+        } else {
+            (tokens.len(), 0)
+        };
+        let ctxt = &tokens[tokens.len().saturating_sub(context_size)..];
+        let input = Tensor::from_slice(ctxt, (1, ctxt.len()), &self.device)?;
+
+        let logits = self
+            .model
+            .forward(&input, context_index, cache)?
+            .squeeze(0)?;
+        // Sampling is the last step that may fail, so a retried step draws the same token
+        let entropy = self.entropy(&logits)?;
+        let token = self.sampler.sample(logits, penalty, tokens.len() - 1)?;
+        *index_pos += ctxt.len();
+
+        Ok((vec![token], entropy.into_iter().collect()))
+    }
+
+    /// Verifies drafted tokens in a single forward pass.
+    ///
+    /// The model reads the tokens followed by the draft, and a token is sampled at every
+    /// drafted position in turn, as it would have been without the draft, until one
+    /// differs from the draft.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens` - The tokens so far. The draft is appended to them for the forward pass
+    ///   and removed again, so that no input buffer is allocated.
+    /// * `draft` - The drafted tokens.
+    /// * `penalty` - The penalties of the tokens seen before the draft.
+    ///
+    /// # Returns
+    ///
+    /// The sampled tokens: the accepted draft tokens followed by the token sampled where
+    /// the draft was rejected or ended. `None` if the draft is empty or the model only
+    /// returns the logits of the last position.
+    fn verify_draft(
+        &mut self,
+        tokens: &mut Vec<u32>,
+        draft: &[u32],
+        penalty: &Penalties,
+    ) -> anyhow::Result<Option<(Vec<u32>, Vec<f32>)>> {
+        if draft.is_empty() {
+            return Ok(None);
+        }
+        let context_len = tokens.len();
+        tokens.extend_from_slice(draft);
+        let input = Tensor::from_slice(tokens.as_slice(), (1, tokens.len()), &self.device);
+        tokens.truncate(context_len);
+        let Some(logits) = self.model.forward_all(&input?)? else {
+            return Ok(None);
+        };
+
+        let mut penalty = penalty.clone();
+        let mut sampled = Vec::with_capacity(draft.len() + 1);
+        let mut entropies = Vec::new();
+        for position in 0..=draft.len() {
+            let logits = logits.get(context_len - 1 + position)?;
+            entropies.extend(self.entropy(&logits)?);
+            let token = self
+                .sampler
+                .sample(logits, &penalty, context_len - 1 + position)?;
+            sampled.push(token);
+            penalty.push(token);
+            if draft.get(position) != Some(&token) {
+                break;
+            }
+        }
+
+        Ok(Some((sampled, entropies)))
+    }
+
+    /// The entropy of the next-token distribution, `None` without a degeneration guard.
+    ///
+    /// Non-finite logits give a NaN entropy, but the sampler rejects them right after.
+    fn entropy(&self, logits: &Tensor) -> anyhow::Result<Option<f32>> {
+        if self.degeneration.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(entropy(logits)?))
+    }
 
-        let eos_token_value = match eos_token.clone().unwrap() {
-            LlamaEosToks::Single(value) => value,
-            _ => 0, // Handle other cases if necessary
+    /// Runs the prompt guardrails and returns the tokens of a prompt.
+    fn prompt_tokens(&self, prompt: PromptInput) -> anyhow::Result<Vec<u32>> {
+        let tokenizer = self.tokenizer.tokenizer();
+        let text = match prompt {
+            PromptInput::Text(text) => self.guardrails.check_prompt(text)?,
+            PromptInput::Tokens(tokens) => {
+                let text = tokenizer.decode(&tokens, false).map_err(Error::msg)?;
+                let checked = self.guardrails.check_prompt(text.clone())?;
+                if checked == text {
+                    return Ok(tokens);
+                }
+                checked
+            }
         };
 
-        info!("End of S {:?} token", eos_token_value);
+        Ok(tokenizer
+            .encode(text, true)
+            .map_err(Error::msg)?
+            .get_ids()
+            .to_vec())
+    }
+
+    /// Generates text in the background and returns its events as an asynchronous stream.
+    ///
+    /// The stream yields a [`GenerationEvent::TokenDelta`] for every decoded piece of text,
+    /// a [`GenerationEvent::UsageUpdate`] once generation is done, and ends with either
+    /// [`GenerationEvent::Done`] or [`GenerationEvent::Error`]. An output guardrail
+    /// violation ends the stream with the `ContentFilter` finish reason. Dropping the
+    /// stream stops the generation.
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The prompt text or tokens to use for text generation.
+    ///
+    /// # Returns
+    ///
+    /// The stream of generation events.
+    pub fn stream(self, prompt: impl Into<PromptInput>) -> GenerationStream {
+        let (tx, rx) = mpsc::channel(64);
+        let prompt = prompt.into();
+
+        tokio::task::spawn_blocking(move || {
+            let result = self.generate_streaming(prompt, |event| {
+                tx.blocking_send(event)
+                    .map_err(|_| Error::msg("The generation stream was dropped"))
+            });
+
+            if let Err(err) = result {
+                let event = match err.downcast_ref::<GuardrailViolation>() {
+                    Some(violation) if violation.stage == GuardrailStage::Output => {
+                        GenerationEvent::Done {
+                            finish_reason: FinishReason::ContentFilter,
+                        }
+                    }
+                    _ => GenerationEvent::Error(err),
+                };
+                // Nothing left to do if the stream was dropped
+                let _ = tx.blocking_send(event);
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+
+    /// Generates text like [`TextGeneration::generate`], handing the generation events to
+    /// `on_event` as soon as they are available.
+    ///
+    /// The output guardrails only run over the complete text once generation is done, so
+    /// when output hooks are registered the text is held back and passed to `on_event` as
+    /// a single token delta of the filtered output, and nothing is passed when the output
+    /// is rejected. `Done` is only emitted when the output passes the guardrails.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The prompt text or tokens to use for text generation. The prompt
+    ///   guardrails check token prompts on their decoded text, and a token prompt they
+    ///   rewrite is tokenized again from the rewritten text.
+    /// * `on_event` - Called with each event, an error aborts the generation.
+    ///
+    /// # Returns
+    ///
+    /// The generated text as a string.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `GuardrailViolation` if a guardrail rejects the prompt or the output,
+    /// or the error returned by `on_event`.
+    pub(crate) fn generate_streaming(
+        mut self,
+        prompt: impl Into<PromptInput>,
+        on_event: impl FnMut(GenerationEvent) -> anyhow::Result<()>,
+    ) -> anyhow::Result<String> {
+        let breaker = self.breaker.take();
+        let result = self.decode(prompt.into(), on_event);
+
+        if let Some(breaker) = breaker {
+            match &result {
+                Ok(_) => breaker.record_success(),
+                Err(err) if is_backend_failure(err) => {
+                    breaker.record_failure();
+                }
+                Err(_) => {}
+            }
+        }
+        result
+    }
+
+    fn decode(
+        mut self,
+        prompt: PromptInput,
+        mut on_event: impl FnMut(GenerationEvent) -> anyhow::Result<()>,
+    ) -> anyhow::Result<String> {
+        let queue = self.queue.take();
+        let _slot = queue.map(|queue| {
+            queue.enter(|position| {
+                if let Some(meter) = &self.meter {
+                    meter.record_queued(position);
+                }
+            })
+        });
+        // The request may have waited for a blocking thread, its turn in the queue or a
+        // reload of the weights
+        if let Some(deadline) = &self.deadline {
+            deadline.check()?;
+        }
+        let mut generation = self.stats.start_generation();
+
+        self.tokenizer.clear();
+        let mut tokens = self.prompt_tokens(prompt)?;
+
+        let prompt_tokens = tokens.len();
+        // Whatever the limits allow, the generation ends with the context of the model
+        let room = self
+            .config
+            .max_position_embeddings
+            .saturating_sub(prompt_tokens);
+        self.max_tokens = self.max_tokens.min(room);
+        let capped = self
+            .requested_max_tokens
+            .is_some_and(|requested| requested > self.max_tokens);
+        // Recorded before the start, which sends the headers of a stream
+        if let Some(meter) = &self.meter {
+            if capped {
+                meter.record_max_tokens_applied(self.max_tokens);
+            }
+            meter.record_start(prompt_tokens);
+        }
+        // Room for every generated token and a draft, so the context never reallocates
+        tokens.reserve(self.max_tokens + SPECULATION_WINDOW);
+        let mut penalty = Penalties::new(
+            self.repeat_penalty,
+            self.repeat_last_n,
+            self.frequency_penalty,
+            self.presence_penalty,
+        );
+        penalty.extend(&tokens);
+
+        info!("Got tokens!");
+
+        let origin_config = self.config.clone();
+
+        info!("Stop tokens {:?}", self.stop_tokens);
 
         let mut string = String::new();
+        // The text can't be streamed before the output guardrails have seen all of it
+        let hold_back = self.guardrails.filters_output();
 
-        let mut cache = Cache::new(false, DType::F32, &origin_config, &self.device).unwrap();
+        let resumed = self
+            .prefix_cache
+            .as_ref()
+            .and_then(|(prefix_cache, revision)| prefix_cache.lookup(revision, &tokens));
+        let (mut cache, mut index_pos) = match resumed {
+            Some((cached, cache)) => {
+                info!("Resuming from {cached} cached prompt tokens");
+                (cache, cached)
+            }
+            None => {
+                let use_kv_cache = self.prefix_cache.is_some();
+                (
+                    Cache::new(use_kv_cache, self.dtype, &origin_config, &self.device)?,
+                    0,
+                )
+            }
+        };
+        // The model only masks a sequence run from the first position, so a resumed cache
+        // is extended one token at a time up to the last token of the prompt
+        if index_pos > 0 {
+            for position in index_pos..tokens.len() - 1 {
+                let input = Tensor::from_slice(&tokens[position..=position], (1, 1), &self.device)?;
+                self.model.forward(&input, position, &mut cache)?;
+            }
+            index_pos = tokens.len() - 1;
+        }
+        let mut prompt_cache = None;
 
         let mut start_gen = std::time::Instant::now();
-        let mut index_pos = 0;
         let mut token_generated = 0;
+        let mut finish_reason = if capped {
+            FinishReason::LengthCapped
+        } else {
+            FinishReason::Length
+        };
 
-        for index in 0..max_tokens.unwrap_or_else(|| 064) {
-            let (context_size, context_index) = if cache.use_kv_cache && index > 0 {
-                (1, index_pos)
-            } else {
-                (tokens.len(), 0)
-            };
+        let mut prediction = self.prediction.take();
+        let mut step_time = std::time::Duration::ZERO;
+
+        'generation: while token_generated < self.max_tokens {
+            let index = token_generated;
             if index == 1 {
                 start_gen = std::time::Instant::now()
             }
-            let ctxt = &tokens[tokens.len().saturating_sub(context_size)..];
-            let input = Tensor::new(ctxt, &self.device)
-                .unwrap()
-                .unsqueeze(0)
-                .unwrap();
-
-            let logits = self
-                .model
-                .forward(&input, context_index, &mut cache)
-                .unwrap()
-                .squeeze(0)
-                .unwrap();
-
-            let logits = if self.repeat_penalty == 1. {
-                logits
-            } else {
-                let start_at = tokens.len().saturating_sub(self.repeat_last_n);
-                candle_transformers::utils::apply_repeat_penalty(
-                    &logits,
-                    self.repeat_penalty,
-                    &tokens[start_at..],
-                )
-                .unwrap()
+            // Stop while the partial output can still be returned in time
+            if let Some(deadline) = &self.deadline {
+                if deadline.remaining() <= step_time {
+                    finish_reason = FinishReason::Timeout;
+                    break;
+                }
+            }
+            let step_start = std::time::Instant::now();
+
+            let remaining = self.max_tokens - token_generated;
+            let draft = match &prediction {
+                Some(prediction) if !cache.use_kv_cache => prediction
+                    .draft(SPECULATION_WINDOW.min(remaining - 1))
+                    .to_vec(),
+                _ => Vec::new(),
             };
-            index_pos += ctxt.len();
+            let mut retries = 0;
+            // The entropies of the sampled tokens, only computed for the degeneration guard
+            let (sampled, entropies) = loop {
+                match self.step(&mut tokens, &draft, &penalty, &mut cache, &mut index_pos) {
+                    Ok(step) => break step,
+                    Err(err)
+                        if retries < self.retries.max_retries && is_transient_failure(&err) =>
+                    {
+                        retries += 1;
+                        record_step_retry();
+                        warn!(
+                            "Retrying a generation step after a transient device error ({retries}/{}): {err}",
+                            self.retries.max_retries
+                        );
+                        std::thread::sleep(self.retries.backoff(retries));
+                        // The failed step may have written part of its keys and values, so
+                        // the whole context is processed again from a fresh cache
+                        if cache.use_kv_cache {
+                            cache = Cache::new(true, self.dtype, &origin_config, &self.device)?;
+                            index_pos = 0;
+                        }
+                    }
+                    Err(err) => return Err(err),
+                }
+            };
+            if cache.use_kv_cache {
+                generation.set_kv_cache_tokens(index_pos);
+                if index == 0 && self.prefix_cache.is_some() {
+                    prompt_cache = Some(cache.clone());
+                }
+            }
+            step_time = step_start.elapsed();
 
-            let next_token = self.logits_processor.sample(&logits).unwrap();
-            token_generated += 1;
-            tokens.push(next_token);
+            for (position, &next_token) in sampled.iter().enumerate() {
+                token_generated += 1;
+                tokens.push(next_token);
+                penalty.push(next_token);
 
-            //Diff
-            match eos_token {
-                Some(LlamaEosToks::Single(eos_tok_id)) if next_token == eos_tok_id => {
-                    break;
+                if self.stop_tokens.contains(&next_token) {
+                    finish_reason = FinishReason::Stop;
+                    break 'generation;
                 }
-                Some(LlamaEosToks::Multiple(ref eos_ids)) if eos_ids.contains(&next_token) => {
-                    break;
+                if let (Some(guard), Some(&entropy)) =
+                    (&mut self.degeneration, entropies.get(position))
+                {
+                    if guard.push(next_token, entropy) {
+                        info!("Stopping a degenerate generation after {token_generated} tokens");
+                        finish_reason = FinishReason::Repetition;
+                        break 'generation;
+                    }
                 }
-                _ => (),
-            }
-            if Some(next_token) == Some(eos_token_value) {
-                break;
-            }
 
-            if let Some(t) = self.tokenizer.next_token(next_token).unwrap() {
-                info!("Found a token! {}", t);
-                string.push_str(&t);
+                if let Some(t) = self.tokenizer.next_token(next_token)? {
+                    trace!("Found a token! {}", t);
+                    string.push_str(&t);
+                    if !hold_back {
+                        on_event(GenerationEvent::TokenDelta(t))?;
+                    }
+                }
             }
 
-            if let Some(rest) = self.tokenizer.decode_rest().map_err(Error::msg).unwrap() {
-                print!("{rest}");
+            if let Some(prediction) = &mut prediction {
+                prediction.advance(&sampled, &tokens[prompt_tokens..]);
             }
+
             let dt = start_gen.elapsed();
             info!(
                 "{} tokens generated ({} token/s)",
@@ -196,35 +950,49 @@ impl TextGeneration {
             )
         }
 
-        string
-    }
-}
+        if let Some((prefix_cache, revision)) = &self.prefix_cache {
+            if let Some(cache) = prompt_cache {
+                prefix_cache.store(revision.clone(), tokens[..prompt_tokens].to_vec(), cache);
+            }
+            if index_pos > prompt_tokens {
+                prefix_cache.store(revision.clone(), tokens[..index_pos].to_vec(), cache);
+            }
+        }
 
-impl From<(AppState, Option<f64>, Option<f64>, Option<usize>)> for TextGeneration {
-    /// Creates a new `TextGeneration` instance from an `AppState` tuple.
-    ///
-    /// # Arguments
-    ///
-    /// * `tuple` - A tuple containing the `AppState`, optional temperature,
-    ///             optional top-p, and optional top-k values.
-    ///
-    /// # Returns
-    ///
-    /// A new `TextGeneration` instance with the specified parameters.
-    fn from(tuple: (AppState, Option<f64>, Option<f64>, Option<usize>)) -> Self {
-        let (app_state, temperature, top_p, top_k) = tuple;
+        // Text held back waiting for a complete character is only decoded once, at the end
+        if let Some(rest) = self.tokenizer.decode_rest()? {
+            string.push_str(&rest);
+            if !hold_back {
+                on_event(GenerationEvent::TokenDelta(rest))?;
+            }
+        }
 
-        Self::new(
-            app_state.model,
-            app_state.tokenizer,
-            299792458,   // seed RNG
-            temperature, // temperature
-            top_p,       // top_p - Nucleus sampling probability stuff
-            top_k,       // top_k - Nucleus sampling probability stuff
-            1.1,         // repeat penalty
-            64,          // context size to consider for the repeat penalty
-            &app_state.device,
-            app_state.config,
-        )
+        if let Some(prediction) = &prediction {
+            info!(
+                "Predicted output: {} tokens accepted, {} rejected",
+                prediction.accepted(),
+                prediction.rejected()
+            );
+        }
+
+        let filtered = self.guardrails.filter_output(string);
+        if let (true, Ok(text)) = (hold_back, &filtered) {
+            if !text.is_empty() {
+                on_event(GenerationEvent::TokenDelta(text.clone()))?;
+            }
+        }
+
+        if let Some(meter) = &self.meter {
+            meter.record_completion(token_generated);
+        }
+        on_event(GenerationEvent::UsageUpdate(TokenUsage {
+            prompt_tokens,
+            completion_tokens: token_generated,
+        }))?;
+
+        let string = filtered?;
+        on_event(GenerationEvent::Done { finish_reason })?;
+
+        Ok(string)
     }
 }
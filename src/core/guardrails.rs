@@ -0,0 +1,265 @@
+use std::fmt;
+use std::sync::Arc;
+
+use regex::Regex;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+/// A hook inspecting or rewriting text around generation.
+///
+/// Hooks run in registration order; each receives the output of the previous one.
+/// Returning `Err` with a reason rejects the text and stops the request.
+pub trait Guardrail: Send + Sync {
+    /// A short name identifying the hook in logs and errors.
+    fn name(&self) -> &str;
+
+    /// Inspects `text`, returning the (possibly rewritten) text or the reason it is rejected.
+    fn apply(&self, text: String) -> Result<String, String>;
+}
+
+/// The point of the request at which a guardrail runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuardrailStage {
+    /// Before the prompt is tokenized.
+    Prompt,
+    /// Before the generated text is returned.
+    Output,
+}
+
+impl fmt::Display for GuardrailStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Prompt => write!(f, "prompt"),
+            Self::Output => write!(f, "output"),
+        }
+    }
+}
+
+/// The error returned when a guardrail rejects a prompt or an output.
+#[derive(Debug, Clone)]
+pub struct GuardrailViolation {
+    pub stage: GuardrailStage,
+    pub hook: String,
+    pub reason: String,
+}
+
+impl fmt::Display for GuardrailViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The {} was rejected by the '{}' guardrail: {}",
+            self.stage, self.hook, self.reason
+        )
+    }
+}
+
+impl std::error::Error for GuardrailViolation {}
+
+/// The configuration of a built-in guardrail.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GuardrailConfig {
+    /// Rejects text matching any of the regular expressions.
+    Blocklist { patterns: Vec<String> },
+    /// Replaces e-mail addresses, phone, card and social security numbers.
+    PiiScrub {
+        #[serde(default = "default_replacement")]
+        replacement: String,
+    },
+    /// Trims text to at most `max_chars` characters.
+    MaxLength { max_chars: usize },
+}
+
+fn default_replacement() -> String {
+    "[REDACTED]".to_string()
+}
+
+/// Guardrails configuration, with the hooks applied to prompts and to outputs.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct GuardrailSettings {
+    pub prompt: Vec<GuardrailConfig>,
+    pub output: Vec<GuardrailConfig>,
+}
+
+/// The ordered prompt and output hooks of a deployment.
+#[derive(Clone, Default)]
+pub struct Guardrails {
+    prompt: Vec<Arc<dyn Guardrail>>,
+    output: Vec<Arc<dyn Guardrail>>,
+}
+
+impl Guardrails {
+    /// Builds the built-in hooks described by the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a blocklist pattern is not a valid regular expression.
+    pub fn from_settings(settings: &GuardrailSettings) -> anyhow::Result<Self> {
+        let build = |configs: &[GuardrailConfig]| -> anyhow::Result<Vec<Arc<dyn Guardrail>>> {
+            configs
+                .iter()
+                .map(|config| -> anyhow::Result<Arc<dyn Guardrail>> {
+                    Ok(match config {
+                        GuardrailConfig::Blocklist { patterns } => {
+                            Arc::new(Blocklist::new(patterns)?)
+                        }
+                        GuardrailConfig::PiiScrub { replacement } => {
+                            Arc::new(PiiScrubber::new(replacement.clone()))
+                        }
+                        GuardrailConfig::MaxLength { max_chars } => Arc::new(MaxLength {
+                            max_chars: *max_chars,
+                        }),
+                    })
+                })
+                .collect()
+        };
+
+        let guardrails = Self {
+            prompt: build(&settings.prompt)?,
+            output: build(&settings.output)?,
+        };
+        info!(
+            "Guardrails configured with {} prompt and {} output hooks",
+            guardrails.prompt.len(),
+            guardrails.output.len()
+        );
+
+        Ok(guardrails)
+    }
+
+    /// Appends a hook to the given stage.
+    pub fn register(&mut self, stage: GuardrailStage, hook: Arc<dyn Guardrail>) {
+        match stage {
+            GuardrailStage::Prompt => self.prompt.push(hook),
+            GuardrailStage::Output => self.output.push(hook),
+        }
+    }
+
+    /// Runs the prompt hooks over `prompt`.
+    pub fn check_prompt(&self, prompt: String) -> Result<String, GuardrailViolation> {
+        Self::run(&self.prompt, GuardrailStage::Prompt, prompt)
+    }
+
+    /// Whether any output hook is registered, in which case streamed text is held back
+    /// until the complete output passed them.
+    pub fn filters_output(&self) -> bool {
+        !self.output.is_empty()
+    }
+
+    /// Runs the output hooks over `output`.
+    pub fn filter_output(&self, output: String) -> Result<String, GuardrailViolation> {
+        Self::run(&self.output, GuardrailStage::Output, output)
+    }
+
+    fn run(
+        hooks: &[Arc<dyn Guardrail>],
+        stage: GuardrailStage,
+        text: String,
+    ) -> Result<String, GuardrailViolation> {
+        hooks.iter().try_fold(text, |text, hook| {
+            hook.apply(text).map_err(|reason| {
+                warn!(
+                    "Guardrail '{}' rejected the {}: {}",
+                    hook.name(),
+                    stage,
+                    reason
+                );
+                GuardrailViolation {
+                    stage,
+                    hook: hook.name().to_string(),
+                    reason,
+                }
+            })
+        })
+    }
+}
+
+/// Rejects text matching any of a set of regular expressions.
+pub struct Blocklist {
+    patterns: Vec<Regex>,
+}
+
+impl Blocklist {
+    /// Compiles the blocklist patterns.
+    pub fn new(patterns: &[String]) -> anyhow::Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|p| Regex::new(p))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { patterns })
+    }
+}
+
+impl Guardrail for Blocklist {
+    fn name(&self) -> &str {
+        "blocklist"
+    }
+
+    fn apply(&self, text: String) -> Result<String, String> {
+        match self.patterns.iter().find(|p| p.is_match(&text)) {
+            Some(pattern) => Err(format!("matched blocked pattern '{}'", pattern.as_str())),
+            None => Ok(text),
+        }
+    }
+}
+
+/// Replaces personally identifiable information with a placeholder.
+pub struct PiiScrubber {
+    patterns: Vec<Regex>,
+    replacement: String,
+}
+
+impl PiiScrubber {
+    /// Creates a scrubber for e-mail addresses, card, social security and phone numbers.
+    pub fn new(replacement: String) -> Self {
+        // Cards go before phone numbers, which would otherwise match parts of them
+        let patterns = [
+            r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+            r"\b(?:\d[ -]?){12,15}\d\b",
+            r"\b\d{3}-\d{2}-\d{4}\b",
+            r"(?:\+\d{1,3}[\s.-]?)?\(?\b\d{3}\)?[\s.-]?\d{3}[\s.-]?\d{4}\b",
+        ]
+        .iter()
+        .map(|p| Regex::new(p).expect("built-in PII pattern is valid"))
+        .collect();
+
+        Self {
+            patterns,
+            replacement,
+        }
+    }
+}
+
+impl Guardrail for PiiScrubber {
+    fn name(&self) -> &str {
+        "pii_scrub"
+    }
+
+    fn apply(&self, text: String) -> Result<String, String> {
+        Ok(self.patterns.iter().fold(text, |text, pattern| {
+            pattern
+                .replace_all(&text, self.replacement.as_str())
+                .into_owned()
+        }))
+    }
+}
+
+/// Trims text to a maximum number of characters.
+pub struct MaxLength {
+    pub max_chars: usize,
+}
+
+impl Guardrail for MaxLength {
+    fn name(&self) -> &str {
+        "max_length"
+    }
+
+    fn apply(&self, text: String) -> Result<String, String> {
+        match text.char_indices().nth(self.max_chars) {
+            Some((end, _)) => Ok(text[..end].to_string()),
+            None => Ok(text),
+        }
+    }
+}
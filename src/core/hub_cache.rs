@@ -0,0 +1,292 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tracing::info;
+
+/// A model repository in the Hugging Face Hub cache.
+#[derive(Clone, Debug, Serialize)]
+pub struct CachedModel {
+    pub id: String,
+    /// The size of all the files of the repository, shared files counted once.
+    pub size_bytes: u64,
+    pub revisions: Vec<CachedRevision>,
+}
+
+/// A downloaded revision of a cached model.
+#[derive(Clone, Debug, Serialize)]
+pub struct CachedRevision {
+    pub revision: String,
+    /// The branches and tags pointing to this revision, such as `main`.
+    pub refs: Vec<String>,
+    /// The size of the files of this revision.
+    pub size_bytes: u64,
+    /// Whether the server loads this revision.
+    pub in_use: bool,
+}
+
+/// A revision used by the server, which is never purged.
+#[derive(Clone, Debug)]
+pub struct RevisionInUse {
+    pub model_id: String,
+    /// The commit, branch or tag, or `None` for the revision `main` points to.
+    pub revision: Option<String>,
+}
+
+/// The result of purging the cache.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PurgeReport {
+    /// The removed revisions, as `model_id@revision`.
+    pub removed: Vec<String>,
+    pub freed_bytes: u64,
+    pub dry_run: bool,
+}
+
+/// Lists the models in a Hub cache directory.
+///
+/// # Arguments
+///
+/// * `cache_dir` - The root of the Hub cache.
+/// * `in_use` - The revisions loaded by the server.
+///
+/// # Errors
+///
+/// Returns an error if the cache directory cannot be read.
+pub fn list_cached_models(
+    cache_dir: &Path,
+    in_use: &[RevisionInUse],
+) -> io::Result<Vec<CachedModel>> {
+    let mut models = Vec::new();
+    if !cache_dir.exists() {
+        return Ok(models);
+    }
+
+    for entry in fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        let Some(id) = model_id(&path) else {
+            continue;
+        };
+
+        let refs = read_refs(&path)?;
+        let mut revisions = Vec::new();
+        for (revision, blobs) in snapshots(&path)? {
+            revisions.push(CachedRevision {
+                in_use: is_in_use(&id, &revision, &refs, in_use),
+                refs: refs
+                    .iter()
+                    .filter(|(_, target)| **target == revision)
+                    .map(|(name, _)| name.clone())
+                    .collect(),
+                size_bytes: blobs.values().sum(),
+                revision,
+            });
+        }
+
+        models.push(CachedModel {
+            size_bytes: directory_size(&path.join("blobs"))?,
+            id,
+            revisions,
+        });
+    }
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(models)
+}
+
+/// Removes the revisions the server does not use from a Hub cache directory.
+///
+/// The snapshots of unused revisions and the refs pointing to them are deleted,
+/// then the files no remaining snapshot refers to. Repositories left without any
+/// revision are removed entirely.
+///
+/// # Arguments
+///
+/// * `cache_dir` - The root of the Hub cache.
+/// * `in_use` - The revisions loaded by the server.
+/// * `dry_run` - Only reports what would be removed.
+///
+/// # Errors
+///
+/// Returns an error if the cache cannot be read or a file cannot be removed.
+pub fn purge_unused_revisions(
+    cache_dir: &Path,
+    in_use: &[RevisionInUse],
+    dry_run: bool,
+) -> io::Result<PurgeReport> {
+    let mut report = PurgeReport {
+        dry_run,
+        ..PurgeReport::default()
+    };
+    if !cache_dir.exists() {
+        return Ok(report);
+    }
+
+    for entry in fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        let Some(id) = model_id(&path) else {
+            continue;
+        };
+
+        let refs = read_refs(&path)?;
+        let snapshots = snapshots(&path)?;
+        let (kept, removed): (Vec<_>, Vec<_>) = snapshots
+            .into_iter()
+            .partition(|(revision, _)| is_in_use(&id, revision, &refs, in_use));
+        if removed.is_empty() {
+            continue;
+        }
+
+        let kept_blobs: HashSet<&PathBuf> =
+            kept.iter().flat_map(|(_, blobs)| blobs.keys()).collect();
+        let removed_blobs: HashMap<&PathBuf, u64> = removed
+            .iter()
+            .flat_map(|(_, blobs)| blobs.iter())
+            .filter(|(blob, _)| !kept_blobs.contains(blob))
+            .map(|(blob, size)| (blob, *size))
+            .collect();
+        report.freed_bytes += removed_blobs.values().sum::<u64>();
+        report.removed.extend(
+            removed
+                .iter()
+                .map(|(revision, _)| format!("{id}@{revision}")),
+        );
+
+        if dry_run {
+            continue;
+        }
+        if kept.is_empty() {
+            fs::remove_dir_all(&path)?;
+            info!("Removed {} from the Hub cache", id);
+            continue;
+        }
+        for (revision, _) in &removed {
+            fs::remove_dir_all(path.join("snapshots").join(revision))?;
+            for (name, target) in &refs {
+                if target == revision {
+                    fs::remove_file(path.join("refs").join(name))?;
+                }
+            }
+            info!("Removed {}@{} from the Hub cache", id, revision);
+        }
+        for blob in removed_blobs.keys() {
+            fs::remove_file(blob)?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// The model id of a cache entry such as `models--meta-llama--Llama-3.1-8B-Instruct`.
+fn model_id(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let repo = name.strip_prefix("models--")?;
+
+    Some(repo.replace("--", "/"))
+}
+
+/// Whether a revision of a model is loaded by the server.
+fn is_in_use(
+    id: &str,
+    revision: &str,
+    refs: &HashMap<String, String>,
+    in_use: &[RevisionInUse],
+) -> bool {
+    in_use.iter().any(|used| {
+        let name = used.revision.as_deref().unwrap_or("main");
+        // The configured revision is either a commit or a branch or tag name
+        used.model_id == id
+            && (name == revision || refs.get(name).is_some_and(|target| target == revision))
+    })
+}
+
+/// Reads the commit a branch or tag of a model pointed to when it was last downloaded.
+///
+/// # Arguments
+///
+/// * `cache_dir` - The Hub cache directory.
+/// * `model_id` - The Hub id of the model.
+/// * `revision` - The branch or tag.
+///
+/// # Returns
+///
+/// The commit, or `None` if the revision was never downloaded.
+pub fn cached_commit(cache_dir: &Path, model_id: &str, revision: &str) -> Option<String> {
+    let repo = cache_dir.join(format!("models--{}", model_id.replace('/', "--")));
+    let commit = fs::read_to_string(repo.join("refs").join(revision)).ok()?;
+
+    Some(commit.trim().to_string())
+}
+
+/// Reads the refs of a cached repository, by name, with the revision they point to.
+fn read_refs(repo: &Path) -> io::Result<HashMap<String, String>> {
+    let refs_dir = repo.join("refs");
+    let mut refs = HashMap::new();
+    if !refs_dir.exists() {
+        return Ok(refs);
+    }
+
+    for entry in fs::read_dir(refs_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            let target = fs::read_to_string(entry.path())?;
+            refs.insert(
+                entry.file_name().to_string_lossy().to_string(),
+                target.trim().to_string(),
+            );
+        }
+    }
+
+    Ok(refs)
+}
+
+/// Lists the snapshots of a cached repository with the blobs they refer to and their sizes.
+fn snapshots(repo: &Path) -> io::Result<Vec<(String, HashMap<PathBuf, u64>)>> {
+    let snapshots_dir = repo.join("snapshots");
+    let mut snapshots = Vec::new();
+    if !snapshots_dir.exists() {
+        return Ok(snapshots);
+    }
+
+    for entry in fs::read_dir(snapshots_dir)? {
+        let entry = entry?;
+        let mut blobs = HashMap::new();
+        collect_blobs(&entry.path(), &mut blobs)?;
+        snapshots.push((entry.file_name().to_string_lossy().to_string(), blobs));
+    }
+
+    Ok(snapshots)
+}
+
+/// Resolves the files of a snapshot directory to the blobs they link to.
+fn collect_blobs(dir: &Path, blobs: &mut HashMap<PathBuf, u64>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_blobs(&path, blobs)?;
+        } else if let Ok(blob) = fs::canonicalize(&path) {
+            let size = fs::metadata(&blob)?.len();
+            blobs.insert(blob, size);
+        }
+    }
+
+    Ok(())
+}
+
+/// The size of the files of a directory, not following links.
+fn directory_size(dir: &Path) -> io::Result<u64> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    fs::read_dir(dir)?.try_fold(0, |size, entry| {
+        let metadata = entry?.metadata()?;
+        Ok(size
+            + if metadata.is_file() {
+                metadata.len()
+            } else {
+                0
+            })
+    })
+}
@@ -0,0 +1,81 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use hf_hub::api::sync::{ApiError, ApiRepo};
+use tracing::warn;
+
+use crate::config::HubSettings;
+
+/// Fetches a file of a Hub repository, retrying transient failures.
+///
+/// The file is served from the local cache when it was already downloaded. Network
+/// errors, `429 Too Many Requests` and server errors are retried up to
+/// `hub.max_retries` times, waiting a random delay of up to
+/// `retry_base_delay_ms * 2^attempt`, capped at `retry_max_delay_ms`, between
+/// attempts so that restarting replicas don't hit the Hub in lockstep. Client errors
+/// such as a missing file or a denied access fail immediately.
+///
+/// # Arguments
+///
+/// * `repo` - The Hub repository.
+/// * `filename` - The path of the file in the repository.
+/// * `hub` - The Hub settings holding the retry policy.
+///
+/// # Returns
+///
+/// The path of the file in the local cache.
+///
+/// # Errors
+///
+/// Returns the last error if the file cannot be fetched after all the retries, or
+/// the first one if it is not transient.
+pub fn fetch_with_retry(
+    repo: &ApiRepo,
+    filename: &str,
+    hub: &HubSettings,
+) -> anyhow::Result<PathBuf> {
+    let mut attempt = 0;
+    loop {
+        match repo.get(filename) {
+            Ok(path) => return Ok(path),
+            Err(err) if attempt < hub.max_retries && is_transient(&err) => {
+                let delay = backoff_delay(hub, attempt);
+                attempt += 1;
+                warn!(
+                    "Fetching {} failed: {}. Retrying in {:.2?} ({}/{})",
+                    filename, err, delay, attempt, hub.max_retries
+                );
+                std::thread::sleep(delay);
+            }
+            Err(err) => {
+                return Err(anyhow::Error::new(err)
+                    .context(format!("Error fetching {filename} from the Hub")))
+            }
+        }
+    }
+}
+
+/// Whether a Hub error may succeed when the request is repeated.
+fn is_transient(err: &ApiError) -> bool {
+    match err {
+        ApiError::RequestError(err) => match err.as_ref() {
+            ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+            ureq::Error::Transport(_) => true,
+        },
+        ApiError::IoError(_) | ApiError::TooManyRetries(_) => true,
+        _ => false,
+    }
+}
+
+/// The delay before a retry: exponential backoff with full jitter.
+pub(crate) fn backoff_delay(hub: &HubSettings, attempt: u32) -> Duration {
+    let ceiling = hub
+        .retry_base_delay_ms
+        .saturating_mul(1 << attempt.min(20))
+        .min(hub.retry_max_delay_ms);
+    let jitter = RandomState::new().build_hasher().finish();
+
+    Duration::from_millis(jitter % (ceiling + 1))
+}
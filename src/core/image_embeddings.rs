@@ -0,0 +1,213 @@
+use std::fmt;
+
+use anyhow::Error as E;
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::siglip::{Config, Model};
+use hf_hub::api::sync::Api;
+use hf_hub::{Repo, RepoType};
+use image::imageops::FilterType;
+use tokenizers::Tokenizer;
+use tracing::info;
+
+use crate::config::HubSettings;
+use crate::core::device_memory::device_name;
+use crate::core::embeddings::Embeddings;
+use crate::core::hub_fetch::fetch_with_retry;
+
+/// A text or an image to embed with an image embedding model.
+pub enum EmbeddingItem {
+    Text(String),
+    /// The encoded image, in any format the `image` crate decodes, such as PNG or JPEG.
+    Image(Vec<u8>),
+}
+
+/// An image that cannot be decoded.
+#[derive(Debug, Clone)]
+pub struct InvalidImage {
+    /// The position of the image in the input.
+    pub index: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Input {} is not a valid image: {}",
+            self.index, self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidImage {}
+
+/// Image and text embeddings in a shared space with a SigLIP model, such as
+/// `google/siglip-base-patch16-224`, for multimodal retrieval.
+///
+/// Texts go through the text tower and images through the vision tower, so the vector of
+/// a caption is close to the vector of the image it describes.
+pub struct ImageEmbedder {
+    model_id: String,
+    model: Model,
+    tokenizer: Tokenizer,
+    /// The number of tokens of the text tower, texts are padded or truncated to it.
+    max_text_tokens: usize,
+    pad_id: u32,
+    /// The side in pixels of the square images the vision tower reads.
+    image_size: usize,
+    dims: usize,
+    device: Device,
+}
+
+impl ImageEmbedder {
+    /// Loads a SigLIP model from the Hugging Face Hub.
+    ///
+    /// # Arguments
+    ///
+    /// * `api` - The Hub API client.
+    /// * `hub` - The Hub settings holding the download retry policy.
+    /// * `model_id` - The Hub id of the model.
+    /// * `device` - The device to run the model on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the model files cannot be fetched or loaded.
+    pub fn load(
+        api: &Api,
+        hub: &HubSettings,
+        model_id: &str,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        let repo = api.repo(Repo::new(model_id.to_string(), RepoType::Model));
+
+        let config: Config = serde_json::from_slice(&std::fs::read(fetch_with_retry(
+            &repo,
+            "config.json",
+            hub,
+        )?)?)?;
+        let tokenizer = Tokenizer::from_file(fetch_with_retry(&repo, "tokenizer.json", hub)?)
+            .map_err(E::msg)?;
+        let weights = fetch_with_retry(&repo, "model.safetensors", hub)?;
+
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DType::F32, device)? };
+        let model = Model::new(&config, vb)?;
+
+        info!(
+            "Image embedding model {} loaded on {}",
+            model_id,
+            device_name(device)
+        );
+
+        Ok(Self {
+            model_id: model_id.to_string(),
+            model,
+            tokenizer,
+            max_text_tokens: config.text_config.max_position_embeddings,
+            pad_id: config.text_config.pad_token_id,
+            image_size: config.vision_config.image_size,
+            dims: config.vision_config.hidden_size,
+            device: device.clone(),
+        })
+    }
+
+    /// The Hub id of the model.
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    /// The size of the vectors.
+    pub fn dims(&self) -> usize {
+        self.dims
+    }
+
+    /// Embeds texts and images.
+    ///
+    /// Images are resized to the square the model reads, without keeping their aspect
+    /// ratio. Texts longer than the context of the text tower are truncated.
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The texts and images to embed.
+    /// * `normalize` - Whether the vectors are normalized to unit length.
+    ///
+    /// # Returns
+    ///
+    /// One vector per item, in order, the number of text tokens embedded and which texts
+    /// were truncated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidImage` error if an image cannot be decoded, or an error if a
+    /// text cannot be tokenized or the forward pass fails.
+    pub fn embed(&self, items: &[EmbeddingItem], normalize: bool) -> anyhow::Result<Embeddings> {
+        let mut vectors = Vec::with_capacity(items.len());
+        let mut truncated = Vec::with_capacity(items.len());
+        let mut prompt_tokens = 0;
+
+        for (index, item) in items.iter().enumerate() {
+            let features = match item {
+                EmbeddingItem::Text(text) => {
+                    let mut ids = self
+                        .tokenizer
+                        .encode(text.as_str(), true)
+                        .map_err(E::msg)?
+                        .get_ids()
+                        .to_vec();
+                    truncated.push(ids.len() > self.max_text_tokens);
+                    ids.truncate(self.max_text_tokens);
+                    prompt_tokens += ids.len();
+                    // The text tower reads a fixed number of tokens, padding included
+                    ids.resize(self.max_text_tokens, self.pad_id);
+
+                    let ids = Tensor::new(ids.as_slice(), &self.device)?.unsqueeze(0)?;
+                    self.model.get_text_features(&ids)?
+                }
+                EmbeddingItem::Image(bytes) => {
+                    truncated.push(false);
+                    let pixels = self.pixel_values(bytes).map_err(|reason| InvalidImage {
+                        index,
+                        reason: reason.to_string(),
+                    })?;
+                    self.model
+                        .get_image_features(&pixels.to_device(&self.device)?)?
+                }
+            };
+
+            let features = features.to_dtype(DType::F32)?;
+            let features = if normalize {
+                let norms = features.sqr()?.sum_keepdim(1)?.sqrt()?;
+                features.broadcast_div(&norms)?
+            } else {
+                features
+            };
+            vectors.push(features.squeeze(0)?.to_vec1::<f32>()?);
+        }
+
+        Ok(Embeddings {
+            vectors,
+            prompt_tokens,
+            truncated,
+            sparse: None,
+            multi_vectors: None,
+        })
+    }
+
+    /// Decodes an image into the pixel values of the vision tower, scaled to `[-1, 1]`.
+    fn pixel_values(&self, bytes: &[u8]) -> anyhow::Result<Tensor> {
+        let size = self.image_size as u32;
+        let image = image::load_from_memory(bytes)?
+            .resize_exact(size, size, FilterType::Triangle)
+            .to_rgb8();
+
+        let pixels = Tensor::from_vec(
+            image.into_raw(),
+            (self.image_size, self.image_size, 3),
+            &Device::Cpu,
+        )?
+        .permute((2, 0, 1))?
+        .to_dtype(DType::F32)?;
+
+        Ok(((pixels * (2.0 / 255.0))? - 1.0)?.unsqueeze(0)?)
+    }
+}
@@ -1,17 +1,66 @@
 use std::collections::HashSet;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
+use crate::config::{HubSettings, ModelSource, PublishedDefaults, ServerConfig};
+use crate::core::bundle::open_bundle;
+use crate::core::chat_template::{ChatTemplate, TemplateTokens};
+use crate::core::device_memory::{compiled_backends, open_device};
+use crate::core::embedding_cache::EmbeddingCache;
+use crate::core::embeddings::{Embedder, EmbeddingOptions};
+use crate::core::hub_cache::cached_commit;
+use crate::core::hub_fetch::fetch_with_retry;
+use crate::core::image_embeddings::ImageEmbedder;
+use crate::core::model_handle::{ModelHandle, ModelLoader};
+use crate::core::model_updates::ModelUpdater;
+use crate::core::object_weights::download_checkpoint;
 use crate::core::output_stream::WeightMaps;
+use crate::core::placement::{preflight, PlacementPlan};
+use crate::core::prequantized::{load_prequantized, CheckpointQuantization};
+use crate::core::quantize::{load_quantized, quantize_llama, Quantization};
+use crate::core::rag::RagStore;
+use crate::core::sharded_llama::ShardedLlama;
+use crate::core::speech::SpeechSynthesizer;
+use crate::core::stop_tokens::StopToken;
+use crate::core::text_model::TextModel;
+use crate::core::transcription::Transcriber;
+use crate::core::vector_stores::VectorStores;
+use crate::core::weight_verification::{
+    bundle_digests, hub_digests, manifest_digests, verify_weights, WeightVerification,
+};
 use crate::openai::http_entities::AppState;
-use anyhow::Error as E;
+use anyhow::{Context, Error as E};
 use candle_core::{DType, Device};
 use candle_nn::VarBuilder;
 use candle_transformers::models::llama::{Config, Llama as Llama3, LlamaConfig};
-use hf_hub::api::sync::{ApiBuilder, ApiRepo};
+use hf_hub::api::sync::{Api, ApiBuilder, ApiRepo};
 use hf_hub::{Repo, RepoType};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Deserializer};
 use serde_json::from_reader;
 use tokenizers::Tokenizer;
-use tracing::info;
+use tracing::{info, warn};
+
+/// Bytes in a MiB, for the load timings.
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// The revision reported for a model loaded from a local directory.
+const LOCAL_REVISION: &str = "local";
+
+/// The revision standing for the last commit of the `main` branch.
+const LATEST_REVISION: &str = "latest";
+
+/// The index of sharded SafeTensors checkpoints.
+pub(crate) const WEIGHT_INDEX: &str = "model.safetensors.index.json";
+
+/// The weights of single-file SafeTensors checkpoints.
+const SINGLE_WEIGHTS: &str = "model.safetensors";
+
+/// The quantized weights of bundles created with `bundle create --quantize`.
+pub(crate) const QUANTIZED_WEIGHTS: &str = "model.gguf";
 
 /// Loads SafeTensors weight files from a Hugging Face repository based on a JSON configuration.
 ///
@@ -22,6 +71,7 @@ use tracing::info;
 ///
 /// * `repo` - A reference to an `ApiRepo` instance representing the Hugging Face repository
 /// * `json_file` - A string slice containing the path to the JSON configuration file within the repository
+/// * `hub` - The Hub settings holding the download retry policy
 ///
 /// # Returns
 ///
@@ -31,18 +81,20 @@ use tracing::info;
 /// # Errors
 ///
 /// This function will return an error if:
-/// * The JSON file cannot be found in the repository
+/// * The JSON file cannot be found in the repository, or fetching it keeps failing
 /// * The JSON file cannot be opened
 /// * The JSON file contains invalid data that cannot be deserialized
 /// * Any of the weight files specified in the JSON cannot be retrieved from the repository
+///   after the configured retries
 ///
 /// # Example
 ///
 /// ```rust,no_run
 /// use hf_hub::api::sync::{ApiBuilder, ApiRepo};
+/// use synap_forge_llm::config::HubSettings;
 ///
 /// let repo : ApiRepo;
-/// let weight_files = synap_forge_llm::core::load_model::hub_load_safe_tensors(&repo, "model.safetensors.index.json")?;
+/// let weight_files = synap_forge_llm::core::load_model::hub_load_safe_tensors(&repo, "model.safetensors.index.json", &HubSettings::default())?;
 /// ```
 ///
 /// # Notes
@@ -53,20 +105,318 @@ use tracing::info;
 pub fn hub_load_safe_tensors(
     repo: &ApiRepo,
     json_file: &str,
+    hub: &HubSettings,
 ) -> anyhow::Result<Vec<std::path::PathBuf>> {
-    let json_file = repo.get(json_file).map_err(candle_core::Error::wrap)?;
+    let json_file = fetch_with_retry(repo, json_file, hub)?;
     let json_file = std::fs::File::open(json_file)?;
     let json: WeightMaps = from_reader(&json_file).map_err(candle_core::Error::wrap)?;
 
-    let pathbufs: Vec<std::path::PathBuf> = json
+    let pathbufs = json
         .weight_map
         .iter()
-        .map(|f| repo.get(f).unwrap())
-        .collect();
+        .map(|f| fetch_with_retry(repo, f, hub))
+        .collect::<anyhow::Result<Vec<_>>>()?;
 
     Ok(pathbufs)
 }
 
+/// Reads the SafeTensors shards in parallel so that they are in the page cache before
+/// they are memory-mapped.
+///
+/// Mapping the shards and reading the tensors one after the other is slow on network
+/// filesystems, where every page fault is a round trip. Reading the files sequentially
+/// from several threads first turns those faults into cache hits. The load time of
+/// every shard is logged.
+///
+/// # Parameters
+///
+/// - `filenames`: The paths of the shards.
+/// - `threads`: The number of shards read at the same time, `0` to skip the prefetch.
+///
+/// # Returns
+///
+/// Returns a result containing either:
+/// - `Ok(())`: All the shards were read.
+/// - `Err(anyhow::Error)`: An error if a shard cannot be read.
+pub fn prefetch_shards(filenames: &[PathBuf], threads: usize) -> anyhow::Result<()> {
+    if threads == 0 || filenames.is_empty() {
+        return Ok(());
+    }
+
+    let before = Instant::now();
+    let next = AtomicUsize::new(0);
+    let bytes = AtomicU64::new(0);
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(filenames.len()))
+            .map(|_| {
+                scope.spawn(|| -> anyhow::Result<()> {
+                    let mut buffer = vec![0u8; 8 * 1024 * 1024];
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(filename) = filenames.get(index) else {
+                            return Ok(());
+                        };
+
+                        let start = Instant::now();
+                        let mut file = std::fs::File::open(filename)?;
+                        let mut size = 0;
+                        loop {
+                            let read = file.read(&mut buffer)?;
+                            if read == 0 {
+                                break;
+                            }
+                            size += read as u64;
+                        }
+                        bytes.fetch_add(size, Ordering::Relaxed);
+
+                        let elapsed = start.elapsed();
+                        info!(
+                            "Loaded shard {} ({:.1} MiB) in {:.2?} ({:.0} MiB/s)",
+                            filename.display(),
+                            size as f64 / MIB,
+                            elapsed,
+                            size as f64 / MIB / elapsed.as_secs_f64()
+                        );
+                    }
+                })
+            })
+            .collect();
+
+        workers.into_iter().try_for_each(|worker| {
+            worker
+                .join()
+                .map_err(|_| E::msg("A prefetch thread panicked"))?
+        })
+    })?;
+
+    let total = bytes.load(Ordering::Relaxed) as f64 / MIB;
+    info!(
+        "Loaded {} shards ({:.1} MiB) with {} threads in {:.2?}",
+        filenames.len(),
+        total,
+        threads,
+        before.elapsed()
+    );
+
+    Ok(())
+}
+
+/// How the SafeTensors weights of a checkpoint are stored.
+#[derive(Debug, PartialEq, Eq)]
+enum WeightLayout {
+    /// Shards listed by `model.safetensors.index.json`.
+    Sharded,
+    /// A single `model.safetensors` file without an index, as small checkpoints come.
+    Single,
+    /// A quantized `model.gguf`, as quantized bundles come.
+    Quantized,
+}
+
+/// Tells how the weights of a checkpoint are stored from the names of its files.
+///
+/// # Parameters
+///
+/// - `filenames`: The paths of the files of the repository or directory.
+///
+/// # Returns
+///
+/// Returns a result containing either:
+/// - `Ok(WeightLayout)`: The layout of the SafeTensors weights.
+/// - `Err(anyhow::Error)`: An error if there are no SafeTensors weights, explaining that
+///   PyTorch weights are not loaded when the checkpoint only has those.
+fn weight_layout<'a>(filenames: impl IntoIterator<Item = &'a str>) -> anyhow::Result<WeightLayout> {
+    let filenames: Vec<_> = filenames.into_iter().collect();
+    if filenames.contains(&WEIGHT_INDEX) {
+        return Ok(WeightLayout::Sharded);
+    }
+    if filenames.contains(&SINGLE_WEIGHTS) {
+        return Ok(WeightLayout::Single);
+    }
+    if filenames.contains(&QUANTIZED_WEIGHTS) {
+        return Ok(WeightLayout::Quantized);
+    }
+
+    let pickles: Vec<_> = filenames
+        .iter()
+        .copied()
+        .filter(|name| name.ends_with(".bin") || name.ends_with(".pt") || name.ends_with(".pth"))
+        .collect();
+    if !pickles.is_empty() {
+        anyhow::bail!(
+            "The model only has PyTorch weights ({}), which are not loaded since unpickling \
+             them can run arbitrary code. Use a SafeTensors version of the model, or convert \
+             the checkpoint with `safetensors.torch.save_file`",
+            pickles.join(", ")
+        );
+    }
+    anyhow::bail!("The model has neither {WEIGHT_INDEX}, {SINGLE_WEIGHTS} nor {QUANTIZED_WEIGHTS}")
+}
+
+/// Fetches the SafeTensors shards of a model repository.
+///
+/// The files of the repository tell whether the weights are sharded with an index or,
+/// as for small checkpoints, a single `model.safetensors` file. When the Hub cannot list
+/// them, e.g. offline with the model in the local cache, the index is tried first.
+///
+/// # Parameters
+///
+/// - `repo`: The model repository.
+/// - `hub`: The Hub settings holding the download retry policy.
+///
+/// # Returns
+///
+/// Returns a result containing either:
+/// - `Ok(Vec<PathBuf>)`: The paths of the shards in the local cache.
+/// - `Err(anyhow::Error)`: An error if the shards cannot be downloaded, or the
+///   repository has no SafeTensors weights.
+pub(crate) fn fetch_weight_files(
+    repo: &ApiRepo,
+    hub: &HubSettings,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let layout = match repo.info() {
+        Ok(info) => weight_layout(info.siblings.iter().map(|file| file.rfilename.as_str()))?,
+        Err(err) => {
+            warn!("Cannot list the files of the model, looking for its weights: {err}");
+            return hub_load_safe_tensors(repo, WEIGHT_INDEX, hub)
+                .or_else(|_| Ok(vec![fetch_with_retry(repo, SINGLE_WEIGHTS, hub)?]));
+        }
+    };
+
+    match layout {
+        WeightLayout::Sharded => hub_load_safe_tensors(repo, WEIGHT_INDEX, hub),
+        WeightLayout::Single => Ok(vec![fetch_with_retry(repo, SINGLE_WEIGHTS, hub)?]),
+        WeightLayout::Quantized => Ok(vec![fetch_with_retry(repo, QUANTIZED_WEIGHTS, hub)?]),
+    }
+}
+
+/// Where the files of the served model are read from.
+enum ModelFiles {
+    /// A repository of the Hub, downloaded to the local cache.
+    Hub(ApiRepo),
+    /// A local directory laid out like a Hub repository.
+    Local(PathBuf),
+}
+
+impl ModelFiles {
+    /// Returns the local path of a file of the model, downloading it first from the Hub.
+    ///
+    /// # Parameters
+    ///
+    /// - `filename`: The path of the file in the repository or directory.
+    /// - `hub`: The Hub settings holding the download retry policy.
+    fn get(&self, filename: &str, hub: &HubSettings) -> anyhow::Result<PathBuf> {
+        match self {
+            Self::Hub(repo) => fetch_with_retry(repo, filename, hub),
+            Self::Local(directory) => {
+                let path = directory.join(filename);
+                if !path.is_file() {
+                    anyhow::bail!("{} not found", path.display());
+                }
+                Ok(path)
+            }
+        }
+    }
+
+    /// Returns the local paths of the SafeTensors shards of the model, from
+    /// `model.safetensors.index.json` or `model.safetensors` otherwise.
+    ///
+    /// # Parameters
+    ///
+    /// - `hub`: The Hub settings holding the download retry policy.
+    fn weight_files(&self, hub: &HubSettings) -> anyhow::Result<Vec<PathBuf>> {
+        match self {
+            Self::Hub(repo) => fetch_weight_files(repo, hub),
+            Self::Local(directory) => {
+                let filenames = std::fs::read_dir(directory)
+                    .with_context(|| format!("Cannot read {}", directory.display()))?
+                    .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                    .collect::<Vec<_>>();
+
+                match weight_layout(filenames.iter().map(String::as_str))? {
+                    WeightLayout::Sharded => {
+                        let index = self.get(WEIGHT_INDEX, hub)?;
+                        let json: WeightMaps = from_reader(std::fs::File::open(index)?)?;
+                        json.weight_map
+                            .iter()
+                            .map(|shard| self.get(shard, hub))
+                            .collect()
+                    }
+                    WeightLayout::Single => Ok(vec![self.get(SINGLE_WEIGHTS, hub)?]),
+                    WeightLayout::Quantized => Ok(vec![self.get(QUANTIZED_WEIGHTS, hub)?]),
+                }
+            }
+        }
+    }
+}
+
+/// How the weights of the served model are loaded, shared by all its revisions.
+#[derive(Clone)]
+pub(crate) struct ModelRecipe {
+    pub(crate) config: Config,
+    /// The quantization of an AWQ or GPTQ checkpoint.
+    pub(crate) checkpoint: Option<CheckpointQuantization>,
+    pub(crate) quantize: Option<Quantization>,
+    pub(crate) dtype: DType,
+    pub(crate) prefetch_threads: usize,
+    pub(crate) device: Device,
+    /// The placement of a model spread over several devices.
+    pub(crate) plan: Option<Arc<PlacementPlan>>,
+}
+
+impl ModelRecipe {
+    /// Builds the loader of the weights stored in `filenames`.
+    ///
+    /// # Parameters
+    ///
+    /// - `filenames`: The SafeTensors shards of a revision of the model.
+    ///
+    /// # Returns
+    ///
+    /// Returns a loader reading the shards from the local cache.
+    pub(crate) fn loader(&self, filenames: Vec<PathBuf>) -> ModelLoader {
+        let recipe = self.clone();
+
+        Box::new(move || {
+            let ModelRecipe {
+                config,
+                checkpoint,
+                quantize,
+                dtype,
+                prefetch_threads,
+                device,
+                plan,
+            } = &recipe;
+            prefetch_shards(&filenames, *prefetch_threads)?;
+
+            if let [path] = filenames.as_slice() {
+                if path.file_name().and_then(|name| name.to_str()) == Some(QUANTIZED_WEIGHTS) {
+                    return Ok(TextModel::Quantized(load_quantized(path, device)?));
+                }
+            }
+            match (checkpoint, quantize, plan) {
+                (Some(checkpoint), Some(quantization), _) => Ok(TextModel::Quantized(
+                    load_prequantized(&filenames, config, checkpoint, *quantization, device)?,
+                )),
+                (_, Some(quantization), _) => Ok(TextModel::Quantized(quantize_llama(
+                    &filenames,
+                    config,
+                    *quantization,
+                    device,
+                )?)),
+                (_, None, Some(plan)) => Ok(TextModel::Sharded(ShardedLlama::load(
+                    &filenames, config, *dtype, plan,
+                )?)),
+                (_, None, None) => {
+                    let vb =
+                        unsafe { VarBuilder::from_mmaped_safetensors(&filenames, *dtype, device)? };
+                    Ok(TextModel::Llama(Llama3::load(vb, config)?))
+                }
+            }
+        })
+    }
+}
+
 /// Deserializes a JSON object into a `HashSet<String>`.
 ///
 /// This function takes a deserializer and attempts to deserialize it into a
@@ -106,16 +456,16 @@ where
     }
 }
 
-/// Retrieves a `Tokenizer` from a specified repository.
+/// Retrieves a `Tokenizer` from the files of the model.
 ///
 /// This function attempts to load a `Tokenizer` by first fetching the filename
-/// of the tokenizer configuration from the provided `ApiRepo`. It then reads
-/// the tokenizer data from the specified file.
+/// of the tokenizer configuration from the Hub repository or local directory of the
+/// model. It then reads the tokenizer data from the specified file.
 ///
 /// # Parameters
 ///
-/// - `repo`: A reference to an `ApiRepo` instance, which is used to access
-///   the tokenizer configuration file.
+/// - `files`: The Hub repository or local directory of the model.
+/// - `hub`: The Hub settings holding the download retry policy.
 ///
 /// # Returns
 ///
@@ -129,23 +479,238 @@ where
 /// This function may return an error if:
 /// - The tokenizer filename cannot be obtained from the repository.
 /// - There is an issue reading the tokenizer data from the file.
-fn get_tokenizer(repo: &ApiRepo) -> anyhow::Result<Tokenizer> {
-    let tokenizer_filename = repo.get("tokenizer.json")?;
+fn get_tokenizer(files: &ModelFiles, hub: &HubSettings) -> anyhow::Result<Tokenizer> {
+    let tokenizer_filename = files.get("tokenizer.json", hub)?;
 
     Tokenizer::from_file(tokenizer_filename).map_err(E::msg)
 }
 
-/// Retrieves a `Config` from a specified repository.
+/// The fields of `tokenizer_config.json` read by the server.
+#[derive(Deserialize, Default)]
+struct TokenizerConfig {
+    #[serde(default)]
+    chat_template: Option<BundledChatTemplate>,
+    #[serde(default)]
+    bos_token: Option<ConfigToken>,
+    #[serde(default)]
+    eos_token: Option<ConfigToken>,
+    #[serde(default)]
+    pad_token: Option<ConfigToken>,
+}
+
+/// A special token of `tokenizer_config.json`, as its text or as an added token object.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConfigToken {
+    Text(String),
+    Added { content: String },
+}
+
+impl ConfigToken {
+    fn into_text(self) -> String {
+        match self {
+            Self::Text(text) | Self::Added { content: text } => text,
+        }
+    }
+}
+
+/// The fields of `generation_config.json` read by the server.
+#[derive(Deserialize, Default)]
+struct GenerationConfig {
+    #[serde(default)]
+    eos_token_id: Option<TokenIds>,
+    #[serde(default)]
+    do_sample: Option<bool>,
+    #[serde(default)]
+    temperature: Option<f64>,
+    #[serde(default)]
+    top_p: Option<f64>,
+    #[serde(default)]
+    top_k: Option<usize>,
+}
+
+/// One token id or several.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenIds {
+    Single(u32),
+    Multiple(Vec<u32>),
+}
+
+/// Reads a JSON file of the model that may be missing, such as `tokenizer_config.json`.
+///
+/// # Parameters
+///
+/// - `files`: The Hub repository or local directory of the model.
+/// - `filename`: The path of the file in the repository or directory.
+/// - `hub`: The Hub settings holding the download retry policy.
+///
+/// # Returns
+///
+/// Returns the parsed file, or the defaults when it is missing or unreadable.
+fn get_optional_json<T: DeserializeOwned + Default>(
+    files: &ModelFiles,
+    filename: &str,
+    hub: &HubSettings,
+) -> T {
+    let path = match files.get(filename, hub) {
+        Ok(path) => path,
+        Err(err) => {
+            info!("No {filename}: {err}");
+            return T::default();
+        }
+    };
+    let parsed = std::fs::read(path)
+        .map_err(E::from)
+        .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?));
+
+    parsed.unwrap_or_else(|err| {
+        warn!("Ignoring the unreadable {filename}: {err}");
+        T::default()
+    })
+}
+
+/// Applies what the model publishes about its tokens and sampling to the settings.
+///
+/// The end of sequence tokens of `generation_config.json` and `tokenizer_config.json`
+/// become the stop tokens of the model unless `stop_tokens` is configured, so that the
+/// known end tokens are only guessed for models that publish none. The sampling values of
+/// `generation_config.json` become the defaults of the model, below the configured ones;
+/// a model published with `do_sample: false` is greedy by default.
+///
+/// # Parameters
+///
+/// - `settings`: The server configuration.
+/// - `tokenizer`: The tokenizer of the model.
+/// - `eos_token`: The eos token of `tokenizer_config.json`, if any.
+/// - `generation`: The parsed `generation_config.json`.
+fn apply_published_defaults(
+    settings: &mut ServerConfig,
+    tokenizer: &Tokenizer,
+    eos_token: Option<&str>,
+    generation: GenerationConfig,
+) {
+    let mut eos_ids = match generation.eos_token_id {
+        Some(TokenIds::Single(id)) => vec![id],
+        Some(TokenIds::Multiple(ids)) => ids,
+        None => Vec::new(),
+    };
+    eos_ids.extend(eos_token.and_then(|token| tokenizer.token_to_id(token)));
+    if !eos_ids.is_empty() {
+        let model_settings = settings
+            .models
+            .entry(settings.model.id.clone())
+            .or_default();
+        if model_settings.stop_tokens.is_none() {
+            info!("End of sequence tokens published by the model: {eos_ids:?}");
+            model_settings.stop_tokens = Some(eos_ids.into_iter().map(StopToken::Id).collect());
+        }
+    }
+
+    settings.model.published_defaults = PublishedDefaults {
+        temperature: match generation.do_sample {
+            Some(false) => Some(0.0),
+            _ => generation.temperature,
+        },
+        top_p: generation.top_p,
+        top_k: generation.top_k,
+    };
+}
+
+/// Resolves the special tokens the chat templates refer to.
+///
+/// # Parameters
+///
+/// - `tokenizer`: The tokenizer of the model.
+/// - `bos_token`, `eos_token`, `pad_token`: The tokens of `tokenizer_config.json`.
+///
+/// # Returns
+///
+/// Returns the tokens, noting whether the tokenizer adds the bos token itself.
+fn template_tokens(
+    tokenizer: &Tokenizer,
+    bos_token: Option<String>,
+    eos_token: Option<String>,
+    pad_token: Option<String>,
+) -> TemplateTokens {
+    let bos_id = bos_token
+        .as_deref()
+        .and_then(|token| tokenizer.token_to_id(token));
+    let bos_added = match (bos_id, tokenizer.encode("", true)) {
+        (Some(bos_id), Ok(encoding)) => encoding.get_ids().first() == Some(&bos_id),
+        _ => false,
+    };
+
+    TemplateTokens {
+        bos_token: bos_token.unwrap_or_default(),
+        eos_token: eos_token.unwrap_or_default(),
+        pad_token: pad_token.unwrap_or_default(),
+        bos_added,
+    }
+}
+
+/// The `chat_template` of `tokenizer_config.json`: a single template, or named templates
+/// such as `default` and `tool_use`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BundledChatTemplate {
+    Single(String),
+    Named(Vec<NamedChatTemplate>),
+}
+
+#[derive(Deserialize)]
+struct NamedChatTemplate {
+    name: String,
+    template: String,
+}
+
+/// Parses the chat template bundled with the model in `tokenizer_config.json`.
+///
+/// Of named templates, the one named `default` is used. A model without a template, or
+/// whose template does not parse, has none, and its prompts fall back to the `plain`
+/// preset.
+///
+/// # Parameters
+///
+/// - `bundled`: The `chat_template` of `tokenizer_config.json`.
+///
+/// # Returns
+///
+/// Returns the bundled template, if the model has a valid one.
+fn get_chat_template(bundled: Option<BundledChatTemplate>) -> Option<ChatTemplate> {
+    let template = match bundled? {
+        BundledChatTemplate::Single(template) => template,
+        BundledChatTemplate::Named(templates) => {
+            templates
+                .into_iter()
+                .find(|template| template.name == "default")?
+                .template
+        }
+    };
+
+    match ChatTemplate::parse(&template) {
+        Ok(template) => {
+            info!("Using the chat template bundled with the model");
+            Some(template)
+        }
+        Err(err) => {
+            warn!("Ignoring the chat template bundled with the model: {err}");
+            None
+        }
+    }
+}
+
+/// Retrieves a `Config` from the files of the model.
 ///
 /// This function attempts to load a configuration by first fetching the filename
-/// of the configuration file from the provided `ApiRepo`. It then reads the
-/// configuration data from the specified file and converts it into a `Config`
-/// instance.
+/// of the configuration file from the Hub repository or local directory of the model.
+/// It then reads the configuration data from the specified file and converts it into
+/// a `Config` instance.
 ///
 /// # Parameters
 ///
-/// - `repo`: A reference to an `ApiRepo` instance, which is used to access
-///   the configuration file.
+/// - `files`: The Hub repository or local directory of the model.
+/// - `hub`: The Hub settings holding the download retry policy.
 ///
 /// # Returns
 ///
@@ -160,8 +725,8 @@ fn get_tokenizer(repo: &ApiRepo) -> anyhow::Result<Tokenizer> {
 /// - The configuration filename cannot be obtained from the repository.
 /// - There is an issue reading the configuration data from the file.
 /// - Deserialization of the configuration data fails.
-fn get_config(repo: &ApiRepo) -> anyhow::Result<Config> {
-    let config_filename = repo.get("config.json")?;
+fn get_config(files: &ModelFiles, hub: &HubSettings) -> anyhow::Result<Config> {
+    let config_filename = files.get("config.json", hub)?;
 
     let config: LlamaConfig = serde_json::from_slice(&std::fs::read(config_filename)?)?;
     let config = config.into_config(false);
@@ -169,65 +734,345 @@ fn get_config(repo: &ApiRepo) -> anyhow::Result<Config> {
     Ok(config)
 }
 
+/// Retrieves the quantization of a pre-quantized checkpoint from the files of the model.
+///
+/// # Parameters
+///
+/// - `files`: The Hub repository or local directory of the model.
+/// - `hub`: The Hub settings holding the download retry policy.
+///
+/// # Returns
+///
+/// Returns a result containing either:
+/// - `Ok(Some(CheckpointQuantization))`: The quantization of an AWQ or GPTQ checkpoint.
+/// - `Ok(None)`: The checkpoint has full-precision weights.
+/// - `Err(anyhow::Error)`: An error if the configuration file cannot be read.
+fn get_checkpoint_quantization(
+    files: &ModelFiles,
+    hub: &HubSettings,
+) -> anyhow::Result<Option<CheckpointQuantization>> {
+    let config_filename = files.get("config.json", hub)?;
+    let config: serde_json::Value = serde_json::from_slice(&std::fs::read(config_filename)?)?;
+
+    Ok(CheckpointQuantization::from_model_config(&config))
+}
+
 /// Retrieves the preferred computational device.
 ///
-/// This function attempts to create a computational device by first trying to
-/// initialize a CUDA device. If that fails, it then tries to initialize a
-/// Metal device. If both CUDA and Metal devices are unavailable, it defaults
-/// to using a CPU device.
+/// Only the backends compiled into the server are tried: the first CUDA device, then
+/// the Metal device, and the CPU when neither is compiled in or available.
 ///
 /// # Returns
 ///
 /// Returns a `Device` instance representing the selected computational device.
-/// The function will return:
-/// - A CUDA device if available.
-/// - A Metal device if CUDA is not available.
-/// - A CPU device if neither CUDA nor Metal devices are available.
 fn get_device() -> Device {
-    let device_cuda = Device::new_cuda(0);
-    let device_metal = Device::new_metal(0);
+    info!("Compiled backends: {}", compiled_backends().join(", "));
 
-    let device = device_metal.or(device_cuda).unwrap_or(Device::Cpu);
-
-    info!("Device Info {:?}", device);
+    #[cfg(feature = "cuda")]
+    match Device::new_cuda(0) {
+        Ok(device) => return device,
+        Err(err) => warn!("No usable CUDA device, {err}"),
+    }
+    #[cfg(feature = "metal")]
+    match Device::new_metal(0) {
+        Ok(device) => return device,
+        Err(err) => warn!("No usable Metal device, {err}"),
+    }
 
-    device
+    info!("Running on the CPU");
+    Device::Cpu
 }
 
-/// Retrieves an `ApiRepo` instance using the provided authentication token.
-///
-/// This function initializes an API client with the specified token and
-/// constructs a repository for a specific model. It uses the `ApiBuilder`
-/// to create the API client and sets up the model ID and revision for the
-/// repository.
+/// Builds a Hugging Face Hub API client using the provided authentication token.
 ///
 /// # Parameters
 ///
 /// - `token`: A `String` representing the authentication token used to
 ///   access the API.
+/// - `hub`: The Hub settings, holding the directory downloads are cached in.
 ///
 /// # Returns
 ///
 /// Returns a result containing either:
-/// - `Ok(ApiRepo)`: The constructed `ApiRepo` instance if successful.
-/// - `Err(anyhow::Error)`: An error if the API client cannot be built or
-///   if any other issue occurs during the process.
+/// - `Ok(Api)`: The API client if successful.
+/// - `Err(anyhow::Error)`: An error if the API client cannot be built.
+fn get_api(token: String, hub: &HubSettings) -> anyhow::Result<Api> {
+    Ok(ApiBuilder::new()
+        .with_token(Some(token))
+        .with_cache_dir(hub.cache_dir())
+        .build()?)
+}
+
+/// Retrieves an `ApiRepo` instance for the served model.
 ///
-/// # Errors
+/// This function constructs a repository for a specific model from the given
+/// API client, setting up the model ID and revision for the repository.
 ///
-/// This function may return an error if:
-/// - The API client fails to initialize with the provided token.
-/// - There is an issue creating the repository for the specified model.
-fn get_repo(token: String) -> anyhow::Result<ApiRepo> {
-    let api = ApiBuilder::new().with_token(Some(token)).build()?;
-    // "meta-llama/Llama-3.2-3B-Instruct"
-    // "45026b798cd537efe6a1abcb93040ad21d416c43"
-    let model_id = "meta-llama/Llama-3.1-8B-Instruct".to_string();
-    Ok(api.repo(Repo::with_revision(
-        model_id,
+/// # Parameters
+///
+/// - `api`: The Hub API client.
+/// - `source`: The model ID and revision to load.
+///
+/// # Returns
+///
+/// Returns the constructed `ApiRepo` instance.
+fn get_repo(api: &Api, source: &ModelSource) -> ApiRepo {
+    api.repo(Repo::with_revision(
+        source.id.clone(),
         RepoType::Model,
-        "0e9e39f249a16976918f6564b8830bc894c89659".to_string(),
-    )))
+        source.revision.clone(),
+    ))
+}
+
+/// Whether a revision is a full commit SHA rather than a branch or a tag.
+fn is_commit(revision: &str) -> bool {
+    revision.len() == 40 && revision.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Resolves the revision of the served model to the commit it points to.
+///
+/// `latest` stands for the `main` branch.
+///
+/// The commit is asked to the Hub, or read from the local cache when the Hub cannot be
+/// reached and the revision was downloaded before. Loading every file from the resolved
+/// commit keeps them consistent if the branch moves during the download, and the commit
+/// is what `/v1/health` and the `system_fingerprint` of the responses report.
+///
+/// # Parameters
+///
+/// - `api`: The Hub API client.
+/// - `source`: The served model, whose `revision` is replaced by its commit.
+/// - `hub`: The Hub settings holding the cache directory.
+///
+/// # Errors
+///
+/// Returns an error if the revision is not a commit and `require_pinned_revision` is set,
+/// or if it cannot be resolved.
+fn resolve_revision(api: &Api, source: &mut ModelSource, hub: &HubSettings) -> anyhow::Result<()> {
+    if is_commit(&source.revision) {
+        return Ok(());
+    }
+    if source.revision == LATEST_REVISION {
+        source.revision = "main".to_string();
+    }
+    if source.require_pinned_revision {
+        return Err(E::msg(format!(
+            "`model.revision` is '{}', but `model.require_pinned_revision` requires a full commit SHA",
+            source.revision
+        )));
+    }
+
+    let commit = match get_repo(api, source).info() {
+        Ok(info) => info.sha,
+        Err(err) => {
+            cached_commit(&hub.cache_dir(), &source.id, &source.revision).with_context(|| {
+                format!(
+                    "Cannot resolve the revision {} of {}: {err}",
+                    source.revision, source.id
+                )
+            })?
+        }
+    };
+    info!(
+        "Revision {} of {} resolved to {commit}",
+        source.revision, source.id
+    );
+    source.revision = commit;
+
+    Ok(())
+}
+
+/// Finds where the files of the served model are read from: the Hub, a local directory,
+/// or a checkpoint in object storage or a bundle, which are downloaded or unpacked to the
+/// local cache first.
+///
+/// # Parameters
+///
+/// - `api`: The Hub client.
+/// - `settings`: The server configuration. The revision of a Hub model is resolved to
+///   a commit, and the quantization of a quantized bundle is applied.
+///
+/// # Returns
+///
+/// Returns a result containing either:
+/// - `Ok(ModelFiles)`: The repository or directory of the model.
+/// - `Err(anyhow::Error)`: An error if several sources are configured, updates are
+///   configured for a model that is not read from the Hub, or the checkpoint cannot be
+///   fetched.
+fn resolve_model_files(api: &Api, settings: &mut ServerConfig) -> anyhow::Result<ModelFiles> {
+    let sources = [
+        settings.model.path.is_some(),
+        settings.model.uri.is_some(),
+        settings.model.bundle.is_some(),
+    ];
+    if sources.iter().filter(|set| **set).count() > 1 {
+        return Err(E::msg(
+            "`model.path`, `model.uri` and `model.bundle` are different sources of the weights, set only one",
+        ));
+    }
+    if let Some(uri) = &settings.model.uri {
+        settings.model.path = Some(download_checkpoint(
+            uri,
+            &settings.model.object_store,
+            &settings.hub,
+        )?);
+    }
+    if let Some(bundle) = &settings.model.bundle {
+        let (directory, manifest) = open_bundle(bundle, &settings.hub)?;
+        info!(
+            "Loading the bundle of {} at revision {}",
+            manifest.model_id, manifest.revision
+        );
+        if manifest.quantize.is_some() {
+            settings.model.quantize = manifest.quantize;
+        }
+        settings.model.path = Some(directory);
+    }
+
+    match &settings.model.path {
+        Some(_) if settings.model.update_check_minutes.is_some() => Err(E::msg(
+            "`model.update_check_minutes` requires the model to be loaded from the Hub",
+        )),
+        Some(directory) => Ok(ModelFiles::Local(directory.clone())),
+        None if settings.model.require_pinned_revision
+            && settings.model.update_check_minutes.is_some() =>
+        {
+            Err(E::msg(
+                "`model.update_check_minutes` follows a branch and cannot be combined with `model.require_pinned_revision`",
+            ))
+        }
+        None => {
+            resolve_revision(api, &mut settings.model, &settings.hub)?;
+            Ok(ModelFiles::Hub(get_repo(api, &settings.model)))
+        }
+    }
+}
+
+/// The files of the checkpoint of the served model in the local cache, from which
+/// bundles are created.
+pub struct CheckpointFiles {
+    pub model_id: String,
+    /// The commit of the model, or `local` for a model read from a directory.
+    pub revision: String,
+    pub config: Config,
+    /// Whether the checkpoint is quantized itself, as AWQ and GPTQ checkpoints are.
+    pub prequantized: bool,
+    /// The configuration, tokenizer and generation files, by name.
+    pub metadata: Vec<(String, PathBuf)>,
+    /// The weight files by name, with the index of sharded checkpoints.
+    pub weights: Vec<(String, PathBuf)>,
+}
+
+/// Fetches the files of the checkpoint of the configured model without loading it.
+///
+/// # Parameters
+///
+/// - `token`: The Hugging Face token, unused when the model is not read from the Hub.
+/// - `settings`: The server configuration naming the model.
+///
+/// # Returns
+///
+/// Returns a result containing either:
+/// - `Ok(CheckpointFiles)`: The local paths of the files of the checkpoint.
+/// - `Err(anyhow::Error)`: An error if the configuration, the tokenizer or the weights
+///   cannot be fetched.
+pub fn checkpoint_files(
+    token: String,
+    mut settings: ServerConfig,
+) -> anyhow::Result<CheckpointFiles> {
+    let api = get_api(token, &settings.hub)?;
+    let files = resolve_model_files(&api, &mut settings)?;
+
+    let mut metadata = Vec::new();
+    for name in ["config.json", "tokenizer.json"] {
+        metadata.push((name.to_string(), files.get(name, &settings.hub)?));
+    }
+    for name in [
+        "tokenizer_config.json",
+        "generation_config.json",
+        "special_tokens_map.json",
+    ] {
+        if let Ok(path) = files.get(name, &settings.hub) {
+            metadata.push((name.to_string(), path));
+        }
+    }
+
+    let mut weights = Vec::new();
+    for path in files.weight_files(&settings.hub)? {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| E::msg(format!("Invalid weight file {}", path.display())))?
+            .to_string();
+        weights.push((name, path));
+    }
+    if weights
+        .iter()
+        .any(|(name, _)| name != SINGLE_WEIGHTS && name != QUANTIZED_WEIGHTS)
+    {
+        weights.push((
+            WEIGHT_INDEX.to_string(),
+            files.get(WEIGHT_INDEX, &settings.hub)?,
+        ));
+    }
+
+    let revision = match &files {
+        ModelFiles::Hub(_) => settings.model.revision.clone(),
+        ModelFiles::Local(_) => LOCAL_REVISION.to_string(),
+    };
+    Ok(CheckpointFiles {
+        model_id: settings.model.id.clone(),
+        revision,
+        config: get_config(&files, &settings.hub)?,
+        prequantized: get_checkpoint_quantization(&files, &settings.hub)?.is_some(),
+        metadata,
+        weights,
+    })
+}
+
+/// Checks the weights of the served model against the digests the Hub lists for its
+/// revision, the digests of its bundle and the configured manifest.
+///
+/// # Parameters
+///
+/// - `files`: The Hub repository or local directory of the model.
+/// - `filenames`: The weight files.
+/// - `settings`: The server configuration holding the verification settings.
+///
+/// # Returns
+///
+/// Returns a result containing either:
+/// - `Ok(WeightVerification)`: How the weights were verified.
+/// - `Err(anyhow::Error)`: An error if a weight file does not match its digest, the
+///   manifest or its signature is invalid, or the weights cannot be verified while
+///   `model.verification.require` is set.
+fn verify_model_weights(
+    files: &ModelFiles,
+    filenames: &[PathBuf],
+    settings: &ServerConfig,
+) -> anyhow::Result<WeightVerification> {
+    let verification = &settings.model.verification;
+    let mut sources = Vec::new();
+    if let (ModelFiles::Hub(repo), true) = (files, verification.hub_checksums) {
+        match hub_digests(repo) {
+            Ok(digests) => sources.push(digests),
+            Err(err) if verification.require => return Err(err),
+            Err(err) => warn!("{err:#}, the weights are not verified against the Hub"),
+        }
+    }
+    if let Some(bundle) = &settings.model.bundle {
+        // Unpacked already, only its manifest is read
+        let (_, manifest) = open_bundle(bundle, &settings.hub)?;
+        sources.push(bundle_digests(&manifest));
+    }
+    sources.extend(manifest_digests(verification)?);
+
+    verify_weights(
+        filenames,
+        &sources,
+        verification,
+        settings.model.prefetch_threads,
+    )
 }
 
 /// Initializes a machine learning model and its associated components.
@@ -240,7 +1085,9 @@ fn get_repo(token: String) -> anyhow::Result<ApiRepo> {
 /// # Parameters
 ///
 /// - `token`: A `String` representing the authentication token used to
-///   access the model repository.
+///   access the model repository, unused when the model is loaded from `model.path`,
+///   `model.uri` or `model.bundle`.
+/// - `settings`: The server configuration used to set up the other subsystems.
 ///
 /// # Returns
 ///
@@ -253,26 +1100,184 @@ fn get_repo(token: String) -> anyhow::Result<ApiRepo> {
 ///
 /// This function may return an error if:
 /// - The repository cannot be retrieved using the provided token.
+/// - The checkpoint of `model.uri` cannot be downloaded from object storage.
+/// - The bundle of `model.bundle` cannot be unpacked or fails its verification.
+/// - The weights do not match the digests of the Hub, the bundle or the manifest.
 /// - The tokenizer cannot be loaded from the repository.
 /// - The device initialization fails.
 /// - There is an issue loading the safe tensor files.
 /// - The configuration cannot be retrieved from the repository.
 /// - The model fails to load from the safe tensor files.
-pub fn initialise_model(token: String) -> anyhow::Result<AppState> {
-    let repo = get_repo(token)?;
-    let tokenizer = get_tokenizer(&repo)?;
+/// - The configured transcription, speech, embedding or image embedding model fails to load.
+/// - RAG is enabled without an embedding model.
+/// - The vector stores cannot be read.
+/// - One of the configured subsystems cannot be initialised.
+pub fn initialise_model(token: String, mut settings: ServerConfig) -> anyhow::Result<AppState> {
+    let api = get_api(token, &settings.hub)?;
+    let files = resolve_model_files(&api, &mut settings)?;
+    let tokenizer = get_tokenizer(&files, &settings.hub)?;
+    let tokenizer_config: TokenizerConfig =
+        get_optional_json(&files, "tokenizer_config.json", &settings.hub);
+    let chat_template = get_chat_template(tokenizer_config.chat_template);
+    let eos_token = tokenizer_config.eos_token.map(ConfigToken::into_text);
+    apply_published_defaults(
+        &mut settings,
+        &tokenizer,
+        eos_token.as_deref(),
+        get_optional_json(&files, "generation_config.json", &settings.hub),
+    );
+    let special_tokens = template_tokens(
+        &tokenizer,
+        tokenizer_config.bos_token.map(ConfigToken::into_text),
+        eos_token,
+        tokenizer_config.pad_token.map(ConfigToken::into_text),
+    );
 
     let device = get_device();
 
-    let filenames = hub_load_safe_tensors(&repo, "model.safetensors.index.json")?;
+    let config = get_config(&files, &settings.hub)?;
+    let checkpoint = get_checkpoint_quantization(&files, &settings.hub)?;
+    if let Some(checkpoint) = &checkpoint {
+        info!(
+            "Loading a {}-bit {:?} checkpoint",
+            checkpoint.bits, checkpoint.quant_method
+        );
+        settings.model.quantize = settings
+            .model
+            .quantize
+            .or(Some(checkpoint.default_quantization()));
+    }
+
+    let plan = preflight(&config, &mut settings.model, &settings.placement, &device)?;
+    info!("Model placement:\n{plan}");
+
+    let dtype: DType = settings.model.dtype.into();
+    let device = if plan.is_single_device() {
+        plan.embeddings_device().clone()
+    } else {
+        device
+    };
+    let plan = (!plan.is_single_device()).then_some(plan);
+
+    let filenames = files.weight_files(&settings.hub)?;
+    let verification = verify_model_weights(&files, &filenames, &settings)?;
+    let recipe = ModelRecipe {
+        config: config.clone(),
+        checkpoint,
+        quantize: settings.model.quantize,
+        dtype,
+        prefetch_threads: settings.model.prefetch_threads,
+        device: device.clone(),
+        plan: plan.map(Arc::new),
+    };
+    let revision = match &files {
+        ModelFiles::Hub(_) => settings.model.revision.clone(),
+        ModelFiles::Local(_) => LOCAL_REVISION.to_string(),
+    };
+    let loader = recipe.loader(filenames);
+    let model = Arc::new(ModelHandle::new(loader()?, loader, revision, verification));
 
-    let config = get_config(&repo)?;
+    let updater = settings.model.update_check_minutes.map(|_| {
+        Arc::new(ModelUpdater::new(
+            api.clone(),
+            &settings.model,
+            settings.hub.clone(),
+            recipe,
+            model.clone(),
+        ))
+    });
 
-    let model = {
-        let dtype = DType::F32;
-        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
-        Llama3::load(vb, &config)?
+    let transcriber = match &settings.audio.transcription_model {
+        Some(model_id) => Some(Arc::new(Transcriber::load(
+            &api,
+            &settings.hub,
+            model_id,
+            &device,
+        )?)),
+        None => None,
+    };
+    let synthesizer = match &settings.audio.speech_model {
+        Some(model_id) => Some(Arc::new(SpeechSynthesizer::load(
+            &api,
+            &settings.hub,
+            model_id,
+            &device,
+        )?)),
+        None => None,
+    };
+    let embedder = match &settings.embeddings.model {
+        Some(model_id) => {
+            let embedding_device = match &settings.embeddings.device {
+                Some(name) => open_device(name)?,
+                None => device.clone(),
+            };
+            let mut embedder = Embedder::load(
+                &api,
+                &settings.hub,
+                model_id,
+                &settings.embeddings.heads,
+                &embedding_device,
+            )?
+            .with_defaults(EmbeddingOptions {
+                output: settings.model_settings(model_id).embedding,
+                long_inputs: settings.embeddings.long_inputs,
+                ..EmbeddingOptions::default()
+            });
+            if settings.embeddings.workers > 0 {
+                embedder = embedder.with_workers(settings.embeddings.workers)?;
+            }
+            if settings.embeddings.cache.enabled {
+                embedder = embedder.with_cache(EmbeddingCache::open(&settings.embeddings.cache)?);
+            }
+            Some(Arc::new(embedder))
+        }
+        None => None,
+    };
+    let image_embedder = match &settings.embeddings.image_model {
+        Some(model_id) => {
+            let image_device = match &settings.embeddings.device {
+                Some(name) => open_device(name)?,
+                None => device.clone(),
+            };
+            Some(Arc::new(ImageEmbedder::load(
+                &api,
+                &settings.hub,
+                model_id,
+                &image_device,
+            )?))
+        }
+        None => None,
     };
+    let rag = match (&embedder, settings.rag.enabled) {
+        (Some(embedder), true) => Some(Arc::new(RagStore::new(
+            embedder.clone(),
+            settings.rag.clone(),
+        ))),
+        (None, true) => {
+            return Err(E::msg(
+                "`rag.enabled` requires `embeddings.model` to be set",
+            ))
+        }
+        (_, false) => None,
+    };
+    let vector_stores = match &embedder {
+        Some(embedder) => Some(Arc::new(VectorStores::open(
+            settings.vector_stores.clone(),
+            embedder.clone(),
+        )?)),
+        None => None,
+    };
+
+    let mut state = AppState::new(model, device, tokenizer, config, dtype, settings)?;
+    state.transcriber = transcriber;
+    state.synthesizer = synthesizer;
+    state.embedder = embedder;
+    state.image_embedder = image_embedder;
+    state.rag = rag;
+    state.vector_stores = vector_stores;
+    state.updater = updater;
+    state.chat_template = chat_template.map(Arc::new);
+    state.special_tokens = special_tokens;
 
-    Ok((model, device, tokenizer, config).into())
+    Ok(state)
 }
@@ -0,0 +1,68 @@
+use anyhow::Context;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// The filter of the logs, which can be changed while the server runs.
+///
+/// Changing the log level does not need a restart, which would unload the model.
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogFilter {
+    /// Installs the global tracing subscriber, logging to stderr.
+    ///
+    /// The filter is read from `RUST_LOG`, then from `configured`, and falls back to
+    /// `default`.
+    ///
+    /// # Arguments
+    ///
+    /// * `configured` - The `log_level` of the configuration, if it sets one.
+    /// * `default` - The filter used when neither `RUST_LOG` nor the configuration set one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configured filter is invalid.
+    pub fn init(configured: Option<&str>, default: &str) -> anyhow::Result<Self> {
+        let filter = match (EnvFilter::try_from_default_env(), configured) {
+            (Ok(filter), _) => filter,
+            (Err(_), Some(configured)) => parse(configured)?,
+            (Err(_), None) => EnvFilter::new(default),
+        };
+        let (filter, handle) = reload::Layer::new(filter);
+
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(tracing_subscriber::fmt::layer())
+            .init();
+
+        Ok(Self { handle })
+    }
+
+    /// The directives of the current filter.
+    pub fn current(&self) -> String {
+        self.handle
+            .with_current(ToString::to_string)
+            .unwrap_or_default()
+    }
+
+    /// Replaces the filter of the logs.
+    ///
+    /// # Arguments
+    ///
+    /// * `directives` - The new filter, e.g. `synap_forge_llm=debug,tower_http=info`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directives are invalid, the filter is then unchanged.
+    pub fn set(&self, directives: &str) -> anyhow::Result<()> {
+        self.handle
+            .reload(parse(directives)?)
+            .context("Error replacing the log filter")
+    }
+}
+
+fn parse(directives: &str) -> anyhow::Result<EnvFilter> {
+    EnvFilter::try_new(directives).with_context(|| format!("Invalid log filter `{directives}`"))
+}
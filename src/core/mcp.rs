@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::config::{McpServerSettings, McpSettings, ToolDefinition, ToolKind};
+
+/// The version of the protocol asked for when connecting.
+const PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// The header carrying the session of the Streamable HTTP transport.
+const SESSION_HEADER: &str = "Mcp-Session-Id";
+
+/// The wait before connecting again to a server that could not be reached, doubled on
+/// every failure up to [`MAX_RECONNECT_BACKOFF`].
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(300);
+
+/// A tool as listed by `tools/list`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub input_schema: Option<Value>,
+}
+
+/// The clients of the configured MCP servers.
+///
+/// A server is connected on first use and its tools are listed once per connection, then
+/// offered from that list without waiting for the session, which a long tool call may hold.
+/// A server that cannot be reached is skipped, and only connected again after a backoff
+/// growing with its failures, so that it does not slow every request down.
+pub struct McpClients {
+    servers: HashMap<String, McpServer>,
+}
+
+struct McpServer {
+    settings: McpServerSettings,
+    session: Mutex<Option<Session>>,
+    listing: std::sync::Mutex<Listing>,
+}
+
+/// The tools of a server as of its last connection, and when it may be connected again.
+#[derive(Default)]
+struct Listing {
+    tools: Option<Vec<McpTool>>,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+/// A connection to an MCP server, after the initialization handshake.
+struct Session {
+    transport: Transport,
+    next_id: u64,
+}
+
+enum Transport {
+    Stdio {
+        // Kept so that the server is killed with the session
+        _child: Child,
+        stdin: ChildStdin,
+        stdout: Lines<BufReader<ChildStdout>>,
+    },
+    Http {
+        url: String,
+        session_id: Option<String>,
+    },
+}
+
+impl McpClients {
+    /// Creates the clients of the configured servers, without connecting them.
+    pub fn new(settings: &McpSettings) -> Self {
+        Self {
+            servers: settings
+                .servers
+                .iter()
+                .map(|(name, settings)| {
+                    let server = McpServer {
+                        settings: settings.clone(),
+                        session: Mutex::new(None),
+                        listing: std::sync::Mutex::new(Listing::default()),
+                    };
+                    (name.clone(), server)
+                })
+                .collect(),
+        }
+    }
+
+    /// The tools of every reachable server, as server tools named `<server>__<tool>`.
+    ///
+    /// Servers that were never listed are connected first, unless another request is
+    /// already connecting them; those that fail to are logged and left out.
+    pub async fn tools(&self) -> Vec<(String, ToolDefinition)> {
+        let mut tools = Vec::new();
+        for (name, server) in &self.servers {
+            let Some(listed) = server.tools(name).await else {
+                continue;
+            };
+
+            for tool in &listed {
+                let offered = server
+                    .settings
+                    .tools
+                    .as_ref()
+                    .map_or(true, |offered| offered.contains(&tool.name));
+                if !offered {
+                    continue;
+                }
+                let definition = ToolDefinition {
+                    kind: ToolKind::Mcp,
+                    description: tool.description.clone().unwrap_or_default(),
+                    parameters: tool
+                        .input_schema
+                        .clone()
+                        .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+                    server: Some(name.clone()),
+                    tool: Some(tool.name.clone()),
+                    ..ToolDefinition::default()
+                };
+                tools.push((format!("{name}__{}", tool.name), definition));
+            }
+        }
+
+        tools
+    }
+
+    /// Calls a tool of a server.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The name of the server.
+    /// * `tool` - The name of the tool on the server.
+    /// * `arguments` - The arguments of the call.
+    ///
+    /// # Returns
+    ///
+    /// The text content of the result.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is unknown or unreachable, or the tool reports an
+    /// error. A server failing to answer is disconnected, so the next call reconnects it.
+    pub async fn call(
+        &self,
+        server: &str,
+        tool: &str,
+        arguments: &Value,
+    ) -> anyhow::Result<String> {
+        let Some(client) = self.servers.get(server) else {
+            bail!("Unknown MCP server {server}");
+        };
+        let mut session = client.session.lock().await;
+        client.connect(server, &mut session).await?;
+        let Some(connected) = session.as_mut() else {
+            bail!("The MCP server {server} is not connected");
+        };
+
+        let params = json!({ "name": tool, "arguments": arguments });
+        let result = match connected
+            .request("tools/call", params, &client.settings)
+            .await
+        {
+            Ok(result) => result,
+            Err(err) => {
+                *session = None;
+                return Err(err);
+            }
+        };
+
+        let text = result
+            .get("content")
+            .and_then(Value::as_array)
+            .map(|content| {
+                content
+                    .iter()
+                    .map(|part| match part.get("text").and_then(Value::as_str) {
+                        Some(text) => text.to_string(),
+                        None => part.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .unwrap_or_default();
+        if result.get("isError").and_then(Value::as_bool) == Some(true) {
+            bail!("{text}");
+        }
+
+        Ok(text)
+    }
+}
+
+impl McpServer {
+    /// The tools of the server as of its last connection, connecting it when it was never
+    /// listed.
+    ///
+    /// # Returns
+    ///
+    /// The tools, `None` while the server is unreachable or connected by another request.
+    async fn tools(&self, name: &str) -> Option<Vec<McpTool>> {
+        if let Some(tools) = &self.listing().tools {
+            return Some(tools.clone());
+        }
+        // A server being connected by another request is left out rather than waited for
+        let mut session = self.session.try_lock().ok()?;
+        if let Err(err) = self.connect(name, &mut session).await {
+            warn!("The MCP server {name} is unavailable: {err:#}");
+            return None;
+        }
+
+        self.listing().tools.clone()
+    }
+
+    fn listing(&self) -> std::sync::MutexGuard<'_, Listing> {
+        self.listing.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Connects the server and lists its tools, unless the session is already open.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server cannot be reached, or was not reached recently and
+    /// its backoff has not elapsed yet.
+    async fn connect(&self, name: &str, session: &mut Option<Session>) -> anyhow::Result<()> {
+        if session.is_some() {
+            return Ok(());
+        }
+        if let Some(retry_at) = self.listing().retry_at {
+            let now = Instant::now();
+            if now < retry_at {
+                bail!("Not connecting again before {:?}", retry_at - now);
+            }
+        }
+
+        match self.open(name).await {
+            Ok((connected, tools)) => {
+                *self.listing() = Listing {
+                    tools: Some(tools),
+                    failures: 0,
+                    retry_at: None,
+                };
+                *session = Some(connected);
+                Ok(())
+            }
+            Err(err) => {
+                let mut listing = self.listing();
+                let backoff = RECONNECT_BACKOFF
+                    .saturating_mul(1 << listing.failures.min(16))
+                    .min(MAX_RECONNECT_BACKOFF);
+                listing.failures += 1;
+                listing.retry_at = Some(Instant::now() + backoff);
+                Err(err)
+            }
+        }
+    }
+
+    /// Opens a session with the server and lists its tools.
+    async fn open(&self, name: &str) -> anyhow::Result<(Session, Vec<McpTool>)> {
+        let transport = match (&self.settings.url, self.settings.command.split_first()) {
+            (_, Some((program, args))) => {
+                let mut command = tokio::process::Command::new(program);
+                command
+                    .args(args)
+                    .envs(&self.settings.env)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::inherit())
+                    .kill_on_drop(true);
+                if let Some(directory) = &self.settings.working_dir {
+                    command.current_dir(directory);
+                }
+                let mut child = command
+                    .spawn()
+                    .with_context(|| format!("Error starting {program}"))?;
+                let stdin = child.stdin.take().context("No standard input")?;
+                let stdout = child.stdout.take().context("No standard output")?;
+                Transport::Stdio {
+                    _child: child,
+                    stdin,
+                    stdout: BufReader::new(stdout).lines(),
+                }
+            }
+            (Some(url), None) => Transport::Http {
+                url: url.clone(),
+                session_id: None,
+            },
+            (None, None) => bail!("The MCP server {name} has neither a command nor a url"),
+        };
+
+        let mut connected = Session {
+            transport,
+            next_id: 0,
+        };
+        let params = json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+        });
+        connected
+            .request("initialize", params, &self.settings)
+            .await?;
+        connected
+            .notify("notifications/initialized", &self.settings)
+            .await?;
+
+        let mut listed = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let page = connected
+                .request("tools/list", params, &self.settings)
+                .await?;
+            let tools: Vec<McpTool> =
+                serde_json::from_value(page.get("tools").cloned().unwrap_or_default())
+                    .context("Invalid tools/list result")?;
+            listed.extend(tools);
+            cursor = page
+                .get("nextCursor")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            if cursor.is_none() {
+                break;
+            }
+        }
+        info!(
+            "Connected to the MCP server {name}, offering {} tools",
+            listed.len()
+        );
+
+        Ok((connected, listed))
+    }
+}
+
+impl Session {
+    /// Sends a JSON-RPC request and waits for its result.
+    async fn request(
+        &mut self,
+        method: &str,
+        params: Value,
+        settings: &McpServerSettings,
+    ) -> anyhow::Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let timeout = Duration::from_secs(settings.timeout_secs);
+
+        let response = tokio::time::timeout(timeout, self.exchange(message, Some(id), settings))
+            .await
+            .map_err(|_| anyhow::anyhow!("{method} timed out after {timeout:?}"))??
+            .with_context(|| format!("No response to {method}"))?;
+        if let Some(error) = response.get("error") {
+            let message = error
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown error");
+            bail!("{method} failed: {message}");
+        }
+
+        Ok(response.get("result").cloned().unwrap_or_default())
+    }
+
+    /// Sends a JSON-RPC notification, which has no response.
+    async fn notify(&mut self, method: &str, settings: &McpServerSettings) -> anyhow::Result<()> {
+        let message = json!({ "jsonrpc": "2.0", "method": method });
+        let timeout = Duration::from_secs(settings.timeout_secs);
+        tokio::time::timeout(timeout, self.exchange(message, None, settings))
+            .await
+            .map_err(|_| anyhow::anyhow!("{method} timed out after {timeout:?}"))??;
+        Ok(())
+    }
+
+    /// Sends a message and, for a request, reads messages until the response to `id`,
+    /// skipping the notifications and requests of the server.
+    async fn exchange(
+        &mut self,
+        message: Value,
+        id: Option<u64>,
+        settings: &McpServerSettings,
+    ) -> anyhow::Result<Option<Value>> {
+        match &mut self.transport {
+            Transport::Stdio { stdin, stdout, .. } => {
+                let mut line = message.to_string();
+                line.push('\n');
+                stdin.write_all(line.as_bytes()).await?;
+                stdin.flush().await?;
+                let Some(id) = id else {
+                    return Ok(None);
+                };
+
+                while let Some(line) = stdout.next_line().await? {
+                    let Ok(response) = serde_json::from_str::<Value>(&line) else {
+                        continue;
+                    };
+                    if response.get("id").and_then(Value::as_u64) == Some(id)
+                        && response.get("method").is_none()
+                    {
+                        return Ok(Some(response));
+                    }
+                }
+                bail!("The MCP server exited")
+            }
+            Transport::Http { url, session_id } => {
+                let url = url.clone();
+                let headers = settings.headers.clone();
+                let session = session_id.clone();
+                let timeout = Duration::from_secs(settings.timeout_secs);
+
+                let (response, new_session) = tokio::task::spawn_blocking(move || {
+                    post_message(&url, &headers, session.as_deref(), &message, id, timeout)
+                })
+                .await??;
+                if new_session.is_some() {
+                    *session_id = new_session;
+                }
+                Ok(response)
+            }
+        }
+    }
+}
+
+/// Posts a message with the Streamable HTTP transport and reads the response to `id`,
+/// from a JSON body or from the events of a `text/event-stream` body.
+///
+/// # Returns
+///
+/// The response, and the session id the server assigned, if any.
+fn post_message(
+    url: &str,
+    headers: &HashMap<String, String>,
+    session_id: Option<&str>,
+    message: &Value,
+    id: Option<u64>,
+    timeout: Duration,
+) -> anyhow::Result<(Option<Value>, Option<String>)> {
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+    let mut request = agent
+        .post(url)
+        .set("Content-Type", "application/json")
+        .set("Accept", "application/json, text/event-stream");
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    if let Some(session_id) = session_id {
+        request = request.set(SESSION_HEADER, session_id);
+    }
+
+    let response = request
+        .send_string(&message.to_string())
+        .with_context(|| format!("Error calling {url}"))?;
+    let session_id = response.header(SESSION_HEADER).map(str::to_string);
+    let Some(id) = id else {
+        return Ok((None, session_id));
+    };
+    let is_stream = response
+        .header("Content-Type")
+        .is_some_and(|content_type| content_type.starts_with("text/event-stream"));
+
+    let matches = |message: &Value| {
+        message.get("id").and_then(Value::as_u64) == Some(id) && message.get("method").is_none()
+    };
+    if !is_stream {
+        let body: Value = serde_json::from_reader(response.into_reader())?;
+        // A server may answer with a batch holding the response
+        let response = match body {
+            Value::Array(messages) => messages.into_iter().find(|message| matches(message)),
+            body => Some(body),
+        };
+        return Ok((response, session_id));
+    }
+
+    let reader = std::io::BufReader::new(response.into_reader());
+    let mut data = String::new();
+    for line in std::io::BufRead::lines(reader) {
+        let line = line?;
+        if let Some(chunk) = line.strip_prefix("data:") {
+            data.push_str(chunk.trim_start());
+        } else if line.is_empty() && !data.is_empty() {
+            if let Ok(message) = serde_json::from_str::<Value>(&data) {
+                if matches(&message) {
+                    return Ok((Some(message), session_id));
+                }
+            }
+            data.clear();
+        }
+    }
+
+    Ok((None, session_id))
+}
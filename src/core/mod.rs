@@ -1,3 +1,61 @@
+pub mod access_log;
+pub mod assistants;
+pub mod audit;
+pub mod bench;
+pub mod bundle;
+pub mod chat_template;
+pub mod circuit_breaker;
+pub mod classification;
+pub mod conversations;
+pub mod deadline;
+pub mod degeneration;
+pub mod dependencies;
+pub mod device_memory;
+pub mod embedding_cache;
+pub mod embeddings;
+pub mod evaluation;
+pub mod events;
+pub mod files;
+pub mod fim;
+pub mod generation_jobs;
+pub mod generation_queue;
 pub mod generator;
+pub mod guardrails;
+pub mod hub_cache;
+pub mod hub_fetch;
+pub mod image_embeddings;
 pub mod load_model;
+pub mod logging;
+pub mod mcp;
+pub mod model_handle;
+pub mod model_updates;
+pub mod object_weights;
 pub mod output_stream;
+pub mod placement;
+pub mod prediction;
+pub mod prefix_cache;
+pub mod prequantized;
+pub mod prompts;
+pub mod quantization_check;
+pub mod quantize;
+pub mod rag;
+pub mod reasoning;
+pub mod sampling;
+pub mod scoring;
+pub mod self_test;
+pub mod settings_reload;
+pub mod sharded_llama;
+pub mod speech;
+pub mod stats;
+pub mod stop_tokens;
+pub mod storage;
+pub mod streams;
+pub mod text_model;
+pub mod tools;
+pub mod transcription;
+pub mod usage_meter;
+pub mod vector_index;
+pub mod vector_stores;
+pub mod webhooks;
+pub mod weight_verification;
+pub mod workers;
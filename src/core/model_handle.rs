@@ -0,0 +1,285 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+
+use crate::core::stats::EngineStats;
+use crate::core::text_model::TextModel;
+use crate::core::weight_verification::WeightVerification;
+use anyhow::Context;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::task::JoinHandle;
+use tracing::info;
+
+/// Loads the model weights, from the local Hub cache once they were downloaded.
+pub type ModelLoader = Box<dyn Fn() -> anyhow::Result<TextModel> + Send + Sync>;
+
+/// Whether the model weights are in memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Readiness {
+    /// The weights are loaded and requests are served right away.
+    Ready,
+    /// The weights are being reloaded, requests wait for them.
+    Cold,
+    /// The weights were unloaded, the next request reloads them.
+    Unloaded,
+}
+
+impl Readiness {
+    /// The name of the readiness reported by the health endpoint.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ready => "ready",
+            Self::Cold => "cold",
+            Self::Unloaded => "unloaded",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Ready,
+            1 => Self::Cold,
+            _ => Self::Unloaded,
+        }
+    }
+}
+
+/// The served model, which can be unloaded while idle and reloaded on demand.
+///
+/// Generations work on clones of the model sharing its weights, so unloading or
+/// swapping in another revision only frees the memory once the running generations
+/// are done.
+pub struct ModelHandle {
+    slot: Mutex<Slot>,
+    /// Held while the weights are reloaded, so that concurrent callers wait for the running
+    /// reload instead of loading the weights twice, while `slot` stays available.
+    loading: Mutex<()>,
+    /// Kept apart from `slot` so that the health endpoint never waits for a reload.
+    served: RwLock<Served>,
+    readiness: AtomicU8,
+}
+
+struct Slot {
+    model: Option<TextModel>,
+    loader: Arc<ModelLoader>,
+    last_used: Instant,
+}
+
+/// Where the served weights come from.
+struct Served {
+    /// The commit of the model repository the weights come from.
+    revision: String,
+    /// How the weights were checked against their digests.
+    verification: WeightVerification,
+}
+
+impl ModelHandle {
+    /// Wraps a loaded model.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The loaded model.
+    /// * `loader` - Loads the model again after it was unloaded.
+    /// * `revision` - The commit of the model repository the weights come from.
+    /// * `verification` - How the weights were checked against their digests.
+    pub fn new(
+        model: TextModel,
+        loader: ModelLoader,
+        revision: String,
+        verification: WeightVerification,
+    ) -> Self {
+        Self {
+            slot: Mutex::new(Slot {
+                model: Some(model),
+                loader: Arc::new(loader),
+                last_used: Instant::now(),
+            }),
+            loading: Mutex::new(()),
+            served: RwLock::new(Served {
+                revision,
+                verification,
+            }),
+            readiness: AtomicU8::new(Readiness::Ready as u8),
+        }
+    }
+
+    /// Returns the model for a generation, reloading it first if it was unloaded.
+    ///
+    /// Concurrent callers wait for a running reload instead of loading the weights twice.
+    /// A reload called from an async handler runs in place of the worker thread, whose
+    /// other tasks the runtime hands over to the other workers meanwhile.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the weights cannot be reloaded.
+    pub fn acquire(&self) -> anyhow::Result<TextModel> {
+        {
+            let mut slot = self.lock();
+            slot.last_used = Instant::now();
+            if let Some(model) = &slot.model {
+                return Ok(model.clone());
+            }
+        }
+
+        match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| self.load())
+            }
+            _ => self.load(),
+        }
+    }
+
+    /// Reloads the weights, without holding `slot` while they load.
+    fn load(&self) -> anyhow::Result<TextModel> {
+        let _loading = self.loading.lock().unwrap_or_else(|e| e.into_inner());
+        let loader = {
+            let slot = self.lock();
+            // Another caller reloaded the weights while this one waited
+            if let Some(model) = &slot.model {
+                return Ok(model.clone());
+            }
+            slot.loader.clone()
+        };
+
+        info!("Reloading the model weights");
+        self.set_readiness(Readiness::Cold);
+        let before = Instant::now();
+
+        match loader() {
+            Ok(model) => {
+                info!("Model reloaded in {:.2?}", before.elapsed());
+                let mut slot = self.lock();
+                // Another revision was swapped in during the reload and is served instead
+                if !Arc::ptr_eq(&slot.loader, &loader) {
+                    return slot
+                        .model
+                        .clone()
+                        .context("The swapped in model was unloaded during the reload");
+                }
+                slot.model = Some(model.clone());
+                self.set_readiness(Readiness::Ready);
+                Ok(model)
+            }
+            Err(err) => {
+                self.set_readiness(Readiness::Unloaded);
+                Err(err)
+            }
+        }
+    }
+
+    /// Drops the loaded weights and loads them again, to recover a model that keeps failing.
+    ///
+    /// Running generations keep the previous weights until they are done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the weights cannot be reloaded, the model is then unloaded.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        self.lock().model = None;
+        self.set_readiness(Readiness::Unloaded);
+        self.acquire().map(|_| ())
+    }
+
+    /// Replaces the served model with another revision.
+    ///
+    /// Generations started from now on use the new model, running ones finish with
+    /// the previous one.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The loaded model of the new revision.
+    /// * `loader` - Loads the new revision again after it was unloaded.
+    /// * `revision` - The commit of the new revision.
+    /// * `verification` - How the weights of the new revision were checked.
+    pub fn swap(
+        &self,
+        model: TextModel,
+        loader: ModelLoader,
+        revision: String,
+        verification: WeightVerification,
+    ) {
+        let mut slot = self.lock();
+        slot.model = Some(model);
+        slot.loader = Arc::new(loader);
+        *self.served.write().unwrap_or_else(|e| e.into_inner()) = Served {
+            revision,
+            verification,
+        };
+        self.set_readiness(Readiness::Ready);
+    }
+
+    /// The commit of the model repository the served weights come from.
+    pub fn revision(&self) -> String {
+        self.served().revision.clone()
+    }
+
+    /// How the served weights were checked against their digests.
+    pub fn verification(&self) -> WeightVerification {
+        self.served().verification.clone()
+    }
+
+    /// Whether the weights are currently in memory.
+    pub fn readiness(&self) -> Readiness {
+        Readiness::from_u8(self.readiness.load(Ordering::Relaxed))
+    }
+
+    /// Unloads the weights if the model was not used for `ttl`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the weights were unloaded.
+    pub fn unload_if_idle(&self, ttl: Duration) -> bool {
+        let mut slot = self.lock();
+        if slot.model.is_none() || slot.last_used.elapsed() < ttl {
+            return false;
+        }
+
+        slot.model = None;
+        self.set_readiness(Readiness::Unloaded);
+        info!("Model unloaded after {:?} without requests", ttl);
+
+        true
+    }
+
+    fn set_readiness(&self, readiness: Readiness) {
+        self.readiness.store(readiness as u8, Ordering::Relaxed);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Slot> {
+        self.slot.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn served(&self) -> RwLockReadGuard<'_, Served> {
+        self.served.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Periodically unloads the model once it has been idle for `ttl`.
+///
+/// The model is never unloaded while generations are in flight.
+///
+/// # Arguments
+///
+/// * `model` - The model to unload.
+/// * `stats` - The engine counters, used to detect running generations.
+/// * `ttl` - How long the model must be idle before it is unloaded.
+///
+/// # Returns
+///
+/// The handle of the background task.
+pub fn spawn_idle_unloader(
+    model: Arc<ModelHandle>,
+    stats: Arc<EngineStats>,
+    ttl: Duration,
+) -> JoinHandle<()> {
+    let period = (ttl / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if stats.in_flight() == 0 {
+                model.unload_if_idle(ttl);
+            }
+        }
+    })
+}
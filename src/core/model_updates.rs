@@ -0,0 +1,191 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use hf_hub::api::sync::Api;
+use hf_hub::{Repo, RepoType};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::{HubSettings, ModelSource, VerificationSettings};
+use crate::core::hub_fetch::fetch_with_retry;
+use crate::core::load_model::{fetch_weight_files, ModelRecipe};
+use crate::core::model_handle::ModelHandle;
+use crate::core::weight_verification::{
+    hub_digests, manifest_digests, verify_weights, WeightVerification,
+};
+
+/// The files that must not change between two revisions swapped at runtime, because
+/// the server keeps the tokenizer and the architecture loaded at startup.
+const PINNED_FILES: [&str; 2] = ["config.json", "tokenizer.json"];
+
+/// Follows a branch of the model repository and swaps in its new commits.
+///
+/// A new revision is downloaded and loaded next to the served one, then replaces it
+/// atomically, so requests keep being served during the update. This needs enough
+/// memory for two copies of the weights while the new one loads.
+pub struct ModelUpdater {
+    api: Api,
+    model_id: String,
+    branch: String,
+    hub: HubSettings,
+    verification: VerificationSettings,
+    recipe: ModelRecipe,
+    model: Arc<ModelHandle>,
+    /// A revision that cannot be swapped in, not downloaded again.
+    rejected: Mutex<Option<String>>,
+}
+
+impl ModelUpdater {
+    /// Creates an updater for the served model.
+    ///
+    /// # Arguments
+    ///
+    /// * `api` - The Hub API client.
+    /// * `source` - The served model and the branch to follow.
+    /// * `hub` - The Hub settings holding the download retry policy.
+    /// * `recipe` - How the weights of the served model are loaded.
+    /// * `model` - The handle of the served model.
+    pub(crate) fn new(
+        api: Api,
+        source: &ModelSource,
+        hub: HubSettings,
+        recipe: ModelRecipe,
+        model: Arc<ModelHandle>,
+    ) -> Self {
+        Self {
+            api,
+            model_id: source.id.clone(),
+            branch: source.update_branch.clone(),
+            hub,
+            verification: source.verification.clone(),
+            recipe,
+            model,
+            rejected: Mutex::new(None),
+        }
+    }
+
+    /// Swaps in the latest commit of the followed branch if it is not served yet.
+    ///
+    /// Blocks while the new revision is downloaded and loaded.
+    ///
+    /// # Returns
+    ///
+    /// The new revision, or `None` when the served one is up to date.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the branch cannot be resolved, the new revision changes the
+    /// model configuration or tokenizer, or its weights cannot be downloaded, fail their
+    /// verification or cannot be loaded.
+    /// The served model is kept in all these cases.
+    pub fn check_for_update(&self) -> anyhow::Result<Option<String>> {
+        let current = self.model.revision();
+        let latest = self.repo(&self.branch).info()?.sha;
+        if latest == current || self.is_rejected(&latest) {
+            return Ok(None);
+        }
+
+        info!(
+            "New revision {} of {} found on {}, loading it",
+            latest, self.model_id, self.branch
+        );
+        let before = Instant::now();
+
+        let (served, candidate) = (self.repo(&current), self.repo(&latest));
+        for file in PINNED_FILES {
+            let served = std::fs::read(fetch_with_retry(&served, file, &self.hub)?)?;
+            let candidate = std::fs::read(fetch_with_retry(&candidate, file, &self.hub)?)?;
+            if served != candidate {
+                *self.rejected.lock().unwrap_or_else(|e| e.into_inner()) = Some(latest.clone());
+                bail!("{file} changed in revision {latest}, restart the server to load it");
+            }
+        }
+
+        let filenames = fetch_weight_files(&candidate, &self.hub)?;
+        let verification = self.verify(&candidate, &latest, &filenames)?;
+        let loader = self.recipe.loader(filenames);
+        let model = loader()?;
+        self.model.swap(model, loader, latest.clone(), verification);
+
+        info!(
+            "Now serving revision {} of {}, swapped in {:.2?}",
+            latest,
+            self.model_id,
+            before.elapsed()
+        );
+
+        Ok(Some(latest))
+    }
+
+    /// Checks the weights of a revision against the digests of the Hub and the manifest,
+    /// rejecting the revision if they do not match.
+    fn verify(
+        &self,
+        repo: &hf_hub::api::sync::ApiRepo,
+        revision: &str,
+        filenames: &[std::path::PathBuf],
+    ) -> anyhow::Result<WeightVerification> {
+        let mut sources = Vec::new();
+        if self.verification.hub_checksums {
+            match hub_digests(repo) {
+                Ok(digests) => sources.push(digests),
+                Err(err) if self.verification.require => return Err(err),
+                Err(err) => warn!("{err:#}, the new revision is not verified against the Hub"),
+            }
+        }
+        sources.extend(manifest_digests(&self.verification)?);
+
+        verify_weights(
+            filenames,
+            &sources,
+            &self.verification,
+            self.recipe.prefetch_threads,
+        )
+        .inspect_err(|_| {
+            *self.rejected.lock().unwrap_or_else(|e| e.into_inner()) = Some(revision.to_string());
+        })
+    }
+
+    fn repo(&self, revision: &str) -> hf_hub::api::sync::ApiRepo {
+        self.api.repo(Repo::with_revision(
+            self.model_id.clone(),
+            RepoType::Model,
+            revision.to_string(),
+        ))
+    }
+
+    fn is_rejected(&self, revision: &str) -> bool {
+        let rejected = self.rejected.lock().unwrap_or_else(|e| e.into_inner());
+
+        rejected.as_deref() == Some(revision)
+    }
+}
+
+/// Periodically checks the model repository for a new revision and swaps it in.
+///
+/// # Arguments
+///
+/// * `updater` - The updater of the served model.
+/// * `period` - The time between two checks.
+///
+/// # Returns
+///
+/// The handle of the background task.
+pub fn spawn_update_checker(updater: Arc<ModelUpdater>, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        // The first tick completes immediately, right after the startup load
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+
+            let updater = updater.clone();
+            match tokio::task::spawn_blocking(move || updater.check_for_update()).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => warn!("Model update check failed: {:#}", err),
+                Err(err) => warn!("Model update check panicked: {}", err),
+            }
+        }
+    })
+}
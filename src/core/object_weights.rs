@@ -0,0 +1,249 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use tracing::{info, warn};
+
+use crate::config::{HubSettings, ObjectStoreSettings};
+use crate::core::hub_fetch::backoff_delay;
+use crate::core::storage::{s3_error, ObjectEntry, S3Client};
+
+/// The endpoint of Google Cloud Storage, reached through its S3 interoperability API.
+const GCS_ENDPOINT: &str = "https://storage.googleapis.com";
+
+/// Checkpoint formats that are never loaded, so never downloaded either.
+const SKIPPED_EXTENSIONS: [&str; 4] = [".bin", ".pt", ".pth", ".ckpt"];
+
+/// A folder of a bucket holding a checkpoint, e.g. `s3://models/llama-3-8b/`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectUri {
+    /// `s3` or `gs`.
+    pub scheme: String,
+    pub bucket: String,
+    /// The folder of the checkpoint in the bucket, empty or ending with `/`.
+    pub prefix: String,
+}
+
+impl ObjectUri {
+    /// Parses an `s3://bucket/path` or `gs://bucket/path` URI.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scheme is neither `s3` nor `gs`, or the bucket is missing.
+    pub fn parse(uri: &str) -> anyhow::Result<Self> {
+        let Some((scheme, rest)) = uri.split_once("://") else {
+            bail!("Invalid model URI '{uri}', expected s3://bucket/path or gs://bucket/path");
+        };
+        if scheme != "s3" && scheme != "gs" {
+            bail!("Unsupported model URI scheme '{scheme}', expected s3 or gs");
+        }
+        let (bucket, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            bail!("The model URI '{uri}' names no bucket");
+        }
+        let path = path.trim_matches('/');
+
+        Ok(Self {
+            scheme: scheme.to_string(),
+            bucket: bucket.to_string(),
+            prefix: if path.is_empty() {
+                String::new()
+            } else {
+                format!("{path}/")
+            },
+        })
+    }
+}
+
+/// Downloads a checkpoint from object storage into the local cache.
+///
+/// Every file of the folder of the URI is downloaded, except PyTorch checkpoints which
+/// are never loaded, to `<cache>/object-store/<scheme>/<bucket>/<path>`. Large files are
+/// fetched as byte ranges by several threads at once, and a file already in the cache
+/// with the size of its object is not downloaded again.
+///
+/// # Arguments
+///
+/// * `uri` - The `s3://` or `gs://` URI of the folder holding the checkpoint.
+/// * `settings` - The endpoint, credentials and parallelism of the downloads.
+/// * `hub` - The Hub settings, holding the cache directory and the retry policy.
+///
+/// # Returns
+///
+/// The local directory holding the checkpoint, laid out like a Hub repository.
+///
+/// # Errors
+///
+/// Returns an error if the URI is invalid, the folder cannot be listed or holds no
+/// files, or a file cannot be downloaded after all the retries.
+pub fn download_checkpoint(
+    uri: &str,
+    settings: &ObjectStoreSettings,
+    hub: &HubSettings,
+) -> anyhow::Result<PathBuf> {
+    let location = ObjectUri::parse(uri)?;
+    let endpoint = match (&settings.endpoint, location.scheme.as_str()) {
+        (Some(endpoint), _) => endpoint.clone(),
+        (None, "gs") => GCS_ENDPOINT.to_string(),
+        (None, _) => format!("https://s3.{}.amazonaws.com", settings.region),
+    };
+    let client = S3Client::new(
+        &endpoint,
+        &settings.region,
+        settings.access_key_id.clone(),
+        settings.secret_access_key.clone(),
+        Duration::from_secs(settings.timeout_secs),
+    )?;
+
+    let objects: Vec<ObjectEntry> = client
+        .list(&location.bucket, &location.prefix)
+        .with_context(|| format!("Error listing {uri}"))?
+        .into_iter()
+        .filter(|object| {
+            let name = &object.key[location.prefix.len()..];
+            !name.is_empty()
+                && !name.contains('/')
+                && !SKIPPED_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+        })
+        .collect();
+    if objects.is_empty() {
+        bail!("No checkpoint files found at {uri}");
+    }
+
+    let directory = hub
+        .cache_dir()
+        .join("object-store")
+        .join(&location.scheme)
+        .join(&location.bucket)
+        .join(location.prefix.trim_end_matches('/'));
+    fs::create_dir_all(&directory)
+        .with_context(|| format!("Error creating {}", directory.display()))?;
+
+    let started = Instant::now();
+    let mut downloaded = 0;
+    for object in &objects {
+        let name = &object.key[location.prefix.len()..];
+        let path = directory.join(name);
+        if fs::metadata(&path).is_ok_and(|metadata| metadata.len() == object.size) {
+            continue;
+        }
+        download_object(&client, &location.bucket, object, &path, settings, hub)
+            .with_context(|| format!("Error downloading {}{name}", uri_folder(&location)))?;
+        downloaded += object.size;
+    }
+    if downloaded > 0 {
+        info!(
+            "Downloaded {:.1} MB of {uri} in {:.1?}",
+            downloaded as f64 / 1e6,
+            started.elapsed()
+        );
+    }
+
+    Ok(directory)
+}
+
+/// The URI of the folder of a checkpoint, ending with `/`.
+fn uri_folder(location: &ObjectUri) -> String {
+    format!(
+        "{}://{}/{}",
+        location.scheme, location.bucket, location.prefix
+    )
+}
+
+/// Downloads an object to a file, as ranges of `chunk_mb` megabytes fetched by
+/// `download_threads` threads.
+fn download_object(
+    client: &S3Client,
+    bucket: &str,
+    object: &ObjectEntry,
+    path: &Path,
+    settings: &ObjectStoreSettings,
+    hub: &HubSettings,
+) -> anyhow::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    File::create(&partial)?.set_len(object.size)?;
+
+    let chunk = (settings.chunk_mb.max(1) * 1024 * 1024).min(object.size.max(1));
+    let chunks = object.size.div_ceil(chunk) as usize;
+    let next = AtomicUsize::new(0);
+    let failure = Mutex::new(None);
+
+    std::thread::scope(|scope| {
+        for _ in 0..settings.download_threads.clamp(1, chunks.max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= chunks || failure.lock().unwrap_or_else(|e| e.into_inner()).is_some() {
+                    return;
+                }
+                let start = index as u64 * chunk;
+                let end = (start + chunk).min(object.size) - 1;
+                if let Err(err) =
+                    fetch_range(client, bucket, &object.key, &partial, start, end, hub)
+                {
+                    *failure.lock().unwrap_or_else(|e| e.into_inner()) = Some(err);
+                    return;
+                }
+            });
+        }
+    });
+
+    if let Some(err) = failure.into_inner().unwrap_or_else(|e| e.into_inner()) {
+        let _ = fs::remove_file(&partial);
+        return Err(err);
+    }
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Writes the bytes `start..=end` of an object at the same offset of a file, retrying
+/// failures with the backoff of the Hub downloads.
+fn fetch_range(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    start: u64,
+    end: u64,
+    hub: &HubSettings,
+) -> anyhow::Result<()> {
+    let mut attempt = 0;
+    loop {
+        let result = client
+            .request("GET", bucket, Some(key), &[], &[])
+            .set("Range", &format!("bytes={start}-{end}"))
+            .call()
+            .map_err(s3_error)
+            .and_then(|response| {
+                let mut file = OpenOptions::new().write(true).open(path)?;
+                file.seek(SeekFrom::Start(start))?;
+                let written = io::copy(&mut response.into_reader(), &mut file)?;
+                if written != end - start + 1 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("received {written} of {} bytes", end - start + 1),
+                    ));
+                }
+                Ok(())
+            });
+
+        match result {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt < hub.max_retries => {
+                let delay = backoff_delay(hub, attempt);
+                attempt += 1;
+                warn!(
+                    "Fetching bytes {start}-{end} of {key} failed: {err}. Retrying in {delay:.2?} ({attempt}/{})",
+                    hub.max_retries
+                );
+                std::thread::sleep(delay);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
@@ -9,11 +9,21 @@ use std::collections::HashSet;
 /// methods to decode them into human-readable strings. It keeps track of
 /// the current position in the token stream and allows for incremental
 /// decoding of tokens as they are received.
+///
+/// Only the tokens since the last returned text are decoded for each new token, and the
+/// text already returned is not decoded again, so the cost of a token does not grow
+/// with the length of the generation.
+///
+/// The text is returned as soon as it decodes to complete characters. A character
+/// split over several byte tokens decodes to a replacement character until its last
+/// byte was generated, and is held back until then.
 pub struct TokenOutputStream {
     tokenizer: tokenizers::Tokenizer,
     tokens: Vec<u32>,
     prev_index: usize,
     current_index: usize,
+    /// The length of the decoded text of `tokens[prev_index..current_index]`, once known.
+    prev_len: Option<usize>,
 }
 
 impl TokenOutputStream {
@@ -33,6 +43,7 @@ impl TokenOutputStream {
             tokens: Vec::new(),
             prev_index: 0,
             current_index: 0,
+            prev_len: None,
         }
     }
 
@@ -76,21 +87,19 @@ impl TokenOutputStream {
     ///
     /// Returns a `Result<Option<String>>`, where `Some(String)` contains
     /// the newly generated text if applicable, or `None` if no new text
-    /// was generated.
+    /// was generated or it ends with an incomplete character.
     pub fn next_token(&mut self, token: u32) -> Result<Option<String>> {
-        let prev_text = if self.tokens.is_empty() {
-            String::new()
-        } else {
-            let tokens = &self.tokens[self.prev_index..self.current_index];
-            self.decode(tokens)?
-        };
+        let prev_len = self.prev_len()?;
         self.tokens.push(token);
         let text = self.decode(&self.tokens[self.prev_index..])?;
-        if text.len() > prev_text.len() && text.chars().last().unwrap().is_alphanumeric() {
-            let text = text.split_at(prev_text.len());
+        if text.len() > prev_len
+            && text.is_char_boundary(prev_len)
+            && !text.ends_with(char::REPLACEMENT_CHARACTER)
+        {
             self.prev_index = self.current_index;
             self.current_index = self.tokens.len();
-            Ok(Some(text.1.to_string()))
+            self.prev_len = None;
+            Ok(Some(text[prev_len..].to_string()))
         } else {
             Ok(None)
         }
@@ -106,22 +115,34 @@ impl TokenOutputStream {
     /// Returns a `Result<Option<String>>`, where `Some(String)` contains
     /// the newly generated text if applicable, or `None` if no new text
     /// was generated.
-    pub fn decode_rest(&self) -> Result<Option<String>> {
-        let prev_text = if self.tokens.is_empty() {
-            String::new()
-        } else {
-            let tokens = &self.tokens[self.prev_index..self.current_index];
-            self.decode(tokens)?
-        };
+    pub fn decode_rest(&mut self) -> Result<Option<String>> {
+        let prev_len = self.prev_len()?;
         let text = self.decode(&self.tokens[self.prev_index..])?;
-        if text.len() > prev_text.len() {
-            let text = text.split_at(prev_text.len());
-            Ok(Some(text.1.to_string()))
+        // The bytes of an incomplete character decode to replacement characters, which
+        // may not line up with the text already returned
+        let start = (prev_len..text.len())
+            .find(|&index| text.is_char_boundary(index))
+            .unwrap_or(text.len());
+        if text.len() > start {
+            Ok(Some(text[start..].to_string()))
         } else {
             Ok(None)
         }
     }
 
+    /// Returns the length of the decoded text of the tokens between the previous and the
+    /// current index, decoding them the first time only.
+    fn prev_len(&mut self) -> Result<usize> {
+        if let Some(len) = self.prev_len {
+            return Ok(len);
+        }
+        let len = self
+            .decode(&self.tokens[self.prev_index..self.current_index])?
+            .len();
+        self.prev_len = Some(len);
+        Ok(len)
+    }
+
     /// Decodes all tokens in the stream into a single string.
     ///
     /// # Returns
@@ -163,6 +184,7 @@ impl TokenOutputStream {
         self.tokens.clear();
         self.prev_index = 0;
         self.current_index = 0;
+        self.prev_len = None;
     }
 }
 
@@ -186,3 +208,150 @@ pub(crate) struct WeightMaps {
     #[serde(deserialize_with = "deserialize_weight_map")]
     pub(crate) weight_map: HashSet<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::str::FromStr;
+
+    /// The pieces of the vocabulary after the byte tokens, most characters of the tested
+    /// text such as `ü`, `好` or `😀` are only covered by the byte tokens.
+    const PIECES: &[&str] = &[
+        "▁", "a", "b", "c", "d", "e", "h", "l", "o", "r", "w", ",", ".", "!", "é", "你", "he",
+        "ll", "▁he",
+    ];
+
+    /// The id of the first piece, after `<unk>` and the 256 byte tokens.
+    const FIRST_PIECE: u32 = 257;
+
+    /// A tokenizer decoding like the Llama tokenizers, with the bytes of the characters
+    /// missing from its vocabulary split into one token each.
+    fn tokenizer() -> tokenizers::Tokenizer {
+        let mut vocab = serde_json::Map::new();
+        vocab.insert("<unk>".to_string(), 0.into());
+        for byte in 0..=255u32 {
+            vocab.insert(format!("<0x{byte:02X}>"), (byte + 1).into());
+        }
+        for (id, piece) in (FIRST_PIECE..).zip(PIECES) {
+            vocab.insert(piece.to_string(), id.into());
+        }
+        let json = serde_json::json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": {
+                "type": "Sequence",
+                "normalizers": [
+                    { "type": "Prepend", "prepend": "▁" },
+                    { "type": "Replace", "pattern": { "String": " " }, "content": "▁" }
+                ]
+            },
+            "pre_tokenizer": null,
+            "post_processor": null,
+            "decoder": {
+                "type": "Sequence",
+                "decoders": [
+                    { "type": "Replace", "pattern": { "String": "▁" }, "content": " " },
+                    { "type": "ByteFallback" },
+                    { "type": "Fuse" },
+                    { "type": "Strip", "content": " ", "start": 1, "stop": 0 }
+                ]
+            },
+            "model": {
+                "type": "BPE",
+                "dropout": null,
+                "unk_token": "<unk>",
+                "continuing_subword_prefix": null,
+                "end_of_word_suffix": null,
+                "fuse_unk": true,
+                "byte_fallback": true,
+                "vocab": vocab,
+                "merges": ["h e", "l l", "▁ he"]
+            }
+        });
+        tokenizers::Tokenizer::from_str(&json.to_string()).expect("the tokenizer is valid")
+    }
+
+    /// Streams `tokens` and returns the concatenated text and the full decode.
+    fn stream(tokens: &[u32]) -> (String, String) {
+        let mut stream = TokenOutputStream::new(tokenizer());
+        let mut streamed = String::new();
+        for &token in tokens {
+            if let Some(text) = stream.next_token(token).unwrap() {
+                streamed.push_str(&text);
+            }
+        }
+        if let Some(rest) = stream.decode_rest().unwrap() {
+            streamed.push_str(&rest);
+        }
+        (streamed, stream.decode_all().unwrap())
+    }
+
+    fn encode(text: &str) -> Vec<u32> {
+        tokenizer().encode(text, false).unwrap().get_ids().to_vec()
+    }
+
+    #[test]
+    fn split_characters_are_held_back_until_complete() {
+        let mut stream = TokenOutputStream::new(tokenizer());
+        let tokens = encode("a😀");
+        assert_eq!(tokens.len(), 6);
+
+        // The leading space is stripped by the decoder
+        assert_eq!(stream.next_token(tokens[0]).unwrap(), None);
+        assert_eq!(stream.next_token(tokens[1]).unwrap().as_deref(), Some("a"));
+        for &token in &tokens[2..5] {
+            assert_eq!(stream.next_token(token).unwrap(), None);
+        }
+        assert_eq!(stream.next_token(tokens[5]).unwrap().as_deref(), Some("😀"));
+        assert_eq!(stream.decode_rest().unwrap(), None);
+    }
+
+    #[test]
+    fn punctuation_and_spaces_are_not_held_back() {
+        let mut stream = TokenOutputStream::new(tokenizer());
+        let mut streamed = Vec::new();
+        for token in encode("he, ") {
+            streamed.push(stream.next_token(token).unwrap());
+        }
+        assert_eq!(streamed.last().unwrap().as_deref(), Some(" "));
+    }
+
+    #[test]
+    fn an_incomplete_character_is_returned_at_the_end() {
+        let tokens = encode("你好");
+        let (streamed, decoded) = stream(&tokens[..tokens.len() - 1]);
+        assert_eq!(streamed, decoded);
+        assert!(streamed.ends_with(char::REPLACEMENT_CHARACTER));
+    }
+
+    proptest! {
+        #[test]
+        fn streamed_text_matches_the_full_decode(text in "[a-z ,.!éü你好😀]{0,48}") {
+            let (streamed, decoded) = stream(&encode(&text));
+            prop_assert_eq!(&streamed, &decoded);
+            prop_assert_eq!(streamed, text);
+        }
+
+        #[test]
+        fn streamed_pieces_match_the_full_decode(
+            tokens in proptest::collection::vec(FIRST_PIECE..FIRST_PIECE + PIECES.len() as u32, 0..48)
+        ) {
+            let (streamed, decoded) = stream(&tokens);
+            prop_assert_eq!(streamed, decoded);
+        }
+
+        // Only panics are checked: the byte fallback decodes an invalid byte sequence as
+        // one replacement character per byte, so a byte token breaking a character that
+        // was already streamed turns it into replacement characters in the full decode
+        // only. The streamed text matches the full decode for valid text, checked above.
+        #[test]
+        fn arbitrary_tokens_never_panic(
+            tokens in proptest::collection::vec(0..FIRST_PIECE + PIECES.len() as u32, 0..48)
+        ) {
+            stream(&tokens);
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use std::fmt;
+
+use anyhow::bail;
+use candle_core::{DType, Device};
+use candle_transformers::models::llama::Config;
+
+use crate::config::{ModelSource, PlacementSettings, WeightDType};
+use crate::core::device_memory::{device_memory, device_name};
+use crate::core::quantize::Quantization;
+use tracing::warn;
+
+/// The format the weights of the model are loaded in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeightFormat {
+    Full(DType),
+    Quantized(Quantization),
+}
+
+impl WeightFormat {
+    /// The format described by the model settings.
+    pub fn of(model: &ModelSource) -> Self {
+        match model.quantize {
+            Some(quantization) => Self::Quantized(quantization),
+            None => Self::Full(model.dtype.into()),
+        }
+    }
+
+    /// The average memory of one weight of a linear layer.
+    fn bytes_per_weight(&self) -> f64 {
+        match self {
+            Self::Full(dtype) => dtype.size_in_bytes() as f64,
+            // Blocks of 32 weights with an f16 scale
+            Self::Quantized(Quantization::Int8) => 34.0 / 32.0,
+            // Super-blocks of 256 weights in 144 bytes
+            Self::Quantized(Quantization::Int4) => 144.0 / 256.0,
+        }
+    }
+
+    /// The memory of one embedding weight, which the quantized model keeps in `f32`.
+    fn embedding_bytes_per_weight(&self) -> f64 {
+        match self {
+            Self::Full(dtype) => dtype.size_in_bytes() as f64,
+            Self::Quantized(_) => 4.0,
+        }
+    }
+
+    /// The memory of one KV cache entry.
+    fn kv_bytes(&self) -> u64 {
+        match self {
+            Self::Full(dtype) => dtype.size_in_bytes() as u64,
+            Self::Quantized(_) => 4,
+        }
+    }
+}
+
+/// The estimated memory of a Llama model, in bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct ModelFootprint {
+    pub embeddings_bytes: u64,
+    /// The weights and the KV cache of one decoder layer.
+    pub layer_bytes: u64,
+    pub layers: usize,
+    /// The final norm and the output projection.
+    pub head_bytes: u64,
+}
+
+impl ModelFootprint {
+    /// Estimates the memory of a model from its configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration of the model.
+    /// * `weights` - The format of the weights.
+    /// * `kv_cache_tokens` - The number of tokens the KV cache of every layer is sized for.
+    pub fn estimate(config: &Config, weights: WeightFormat, kv_cache_tokens: usize) -> Self {
+        let hidden = config.hidden_size as f64;
+        let kv =
+            (config.num_key_value_heads * (config.hidden_size / config.num_attention_heads)) as f64;
+        let intermediate = config.intermediate_size as f64;
+        let vocab = config.vocab_size as f64;
+        let size = weights.bytes_per_weight();
+
+        let attention = 2.0 * hidden * hidden + 2.0 * hidden * kv;
+        let mlp = 3.0 * hidden * intermediate;
+        let kv_cache = 2 * kv_cache_tokens as u64 * kv as u64 * weights.kv_bytes();
+
+        Self {
+            embeddings_bytes: (vocab * hidden * weights.embedding_bytes_per_weight()) as u64,
+            layer_bytes: ((attention + mlp) * size + 2.0 * hidden * 4.0) as u64 + kv_cache,
+            layers: config.num_hidden_layers,
+            head_bytes: (vocab * hidden * size + hidden * 4.0) as u64,
+        }
+    }
+
+    /// The memory of the whole model.
+    pub fn total_bytes(&self) -> u64 {
+        self.embeddings_bytes + self.layer_bytes * self.layers as u64 + self.head_bytes
+    }
+}
+
+/// Checks that the model fits on the devices before any weight is loaded.
+///
+/// Full-precision models are planned over the configured devices; quantized models
+/// are always loaded on the first one. When the model does not fit and
+/// `auto_fit` is enabled, the dtype is lowered to 16 bits, then CPU offload is
+/// enabled, or the quantization is lowered from `int8` to `int4`, and the
+/// model settings are updated with the format that fits.
+///
+/// # Arguments
+///
+/// * `config` - The configuration of the model.
+/// * `model` - The model settings, updated when the format is downgraded.
+/// * `placement` - The placement settings.
+/// * `primary` - The accelerator selected at startup.
+///
+/// # Returns
+///
+/// The placement of the model.
+///
+/// # Errors
+///
+/// Returns an error explaining how much memory is missing and how to make the model
+/// fit, if it does not fit even after the allowed downgrades.
+pub fn preflight(
+    config: &Config,
+    model: &mut ModelSource,
+    placement: &PlacementSettings,
+    primary: &Device,
+) -> anyhow::Result<PlacementPlan> {
+    let mut placement = placement.clone();
+
+    loop {
+        if model.quantize.is_some() {
+            placement.devices.truncate(1);
+            placement.cpu_offload = false;
+        }
+
+        let err = match plan_placement(config, WeightFormat::of(model), &placement, primary) {
+            Ok(plan) => return Ok(plan),
+            Err(err) if placement.auto_fit => err,
+            Err(err) => return Err(err),
+        };
+
+        match (model.quantize, model.dtype) {
+            (None, WeightDType::F32) => {
+                model.dtype = if primary.is_cuda() {
+                    WeightDType::BF16
+                } else {
+                    WeightDType::F16
+                };
+                warn!("{err}. Retrying with {:?} weights", model.dtype);
+            }
+            (None, _) if !placement.cpu_offload => {
+                placement.cpu_offload = true;
+                warn!("{err}. Retrying with CPU offload");
+            }
+            (Some(Quantization::Int8), _) => {
+                model.quantize = Some(Quantization::Int4);
+                warn!("{err}. Retrying with int4 quantization");
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+/// The devices the parts of the model are loaded on.
+pub struct PlacementPlan {
+    /// The candidate devices, in placement order.
+    pub devices: Vec<Device>,
+    /// The memory available for weights on each device, `None` when unknown.
+    pub budgets: Vec<Option<u64>>,
+    /// The memory of the weights placed on each device.
+    pub used_bytes: Vec<u64>,
+    /// The index in `devices` of the embeddings.
+    pub embeddings: usize,
+    /// The index in `devices` of each decoder layer.
+    pub layers: Vec<usize>,
+    /// The index in `devices` of the final norm and output projection.
+    pub head: usize,
+}
+
+impl PlacementPlan {
+    /// Whether the whole model is placed on a single device.
+    pub fn is_single_device(&self) -> bool {
+        self.layers
+            .iter()
+            .chain([&self.embeddings, &self.head])
+            .all(|&device| device == self.embeddings)
+    }
+
+    /// The device the embeddings are placed on.
+    pub fn embeddings_device(&self) -> &Device {
+        &self.devices[self.embeddings]
+    }
+
+    /// The device a decoder layer is placed on.
+    pub fn layer_device(&self, layer: usize) -> &Device {
+        &self.devices[self.layers[layer]]
+    }
+
+    /// The device the final norm and output projection are placed on.
+    pub fn head_device(&self) -> &Device {
+        &self.devices[self.head]
+    }
+}
+
+impl fmt::Display for PlacementPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, device) in self.devices.iter().enumerate() {
+            let mut parts = Vec::new();
+            if self.embeddings == index {
+                parts.push("embeddings".to_string());
+            }
+            let layers: Vec<usize> = (0..self.layers.len())
+                .filter(|&layer| self.layers[layer] == index)
+                .collect();
+            if let (Some(first), Some(last)) = (layers.first(), layers.last()) {
+                parts.push(format!("layers {first}-{last}"));
+            }
+            if self.head == index {
+                parts.push("head".to_string());
+            }
+            if parts.is_empty() {
+                continue;
+            }
+
+            let budget = match self.budgets[index] {
+                Some(budget) => format!("{:.1} GiB", gib(budget)),
+                None => "unknown".to_string(),
+            };
+            writeln!(
+                f,
+                "{}: {} ({:.1} GiB, available {})",
+                device_name(device),
+                parts.join(", "),
+                gib(self.used_bytes[index]),
+                budget
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Plans how to spread the model over the configured devices.
+///
+/// The embeddings, the decoder layers and the head are placed in order, each on
+/// the first device from the current one that still has room for it, so the
+/// hidden states only move forward through the devices. The room of an accelerator
+/// is its free memory minus the configured reserve; the CPU, used when offload is
+/// enabled, is not limited.
+///
+/// # Arguments
+///
+/// * `config` - The configuration of the model.
+/// * `weights` - The format of the weights.
+/// * `settings` - The placement settings.
+/// * `primary` - The accelerator selected at startup, which decides the backend.
+///
+/// # Returns
+///
+/// The placement of every part of the model.
+///
+/// # Errors
+///
+/// Returns an error if a configured device cannot be opened or the model does not
+/// fit on the devices.
+pub fn plan_placement(
+    config: &Config,
+    weights: WeightFormat,
+    settings: &PlacementSettings,
+    primary: &Device,
+) -> anyhow::Result<PlacementPlan> {
+    let mut devices = Vec::new();
+    for &ordinal in &settings.devices {
+        let device = match primary {
+            Device::Cpu => continue,
+            Device::Cuda(_) => Device::new_cuda(ordinal)?,
+            // Metal exposes a single device
+            Device::Metal(_) if ordinal == 0 => primary.clone(),
+            Device::Metal(_) => bail!("Metal only supports device 0, got {ordinal}"),
+        };
+        devices.push(device);
+    }
+    if devices.is_empty() || primary.is_cpu() || settings.cpu_offload {
+        devices.push(Device::Cpu);
+    }
+
+    let budgets: Vec<Option<u64>> = devices
+        .iter()
+        .map(|device| {
+            device_memory(device)
+                .map(|memory| (memory.free_bytes as f64 * (1.0 - settings.reserve_fraction)) as u64)
+        })
+        .collect();
+
+    let footprint = ModelFootprint::estimate(config, weights, settings.kv_cache_tokens);
+    let mut placer = Placer {
+        budgets: &budgets,
+        used_bytes: vec![0; devices.len()],
+        current: 0,
+    };
+
+    let embeddings = placer.place(footprint.embeddings_bytes);
+    let layers: Option<Vec<usize>> = (0..footprint.layers)
+        .map(|_| placer.place(footprint.layer_bytes))
+        .collect();
+    let head = placer.place(footprint.head_bytes);
+
+    let (Some(embeddings), Some(layers), Some(head)) = (embeddings, layers, head) else {
+        let available: u64 = budgets.iter().flatten().sum();
+        bail!(
+            "The model needs {:.1} GiB for its weights and KV cache but only {:.1} GiB are available on {}. \
+             List more devices in `placement.devices`, enable `placement.cpu_offload`, \
+             load a smaller `model.dtype` or `model.quantize` level, \
+             or enable `placement.auto_fit`",
+            gib(footprint.total_bytes()),
+            gib(available),
+            devices
+                .iter()
+                .map(device_name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    };
+
+    Ok(PlacementPlan {
+        devices,
+        used_bytes: placer.used_bytes,
+        budgets,
+        embeddings,
+        layers,
+        head,
+    })
+}
+
+/// Assigns parts of the model to devices, never going back to a previous device.
+struct Placer<'a> {
+    budgets: &'a [Option<u64>],
+    used_bytes: Vec<u64>,
+    current: usize,
+}
+
+impl Placer<'_> {
+    fn place(&mut self, bytes: u64) -> Option<usize> {
+        while self.current < self.budgets.len() {
+            let fits = self.budgets[self.current].map_or(true, |budget| {
+                self.used_bytes[self.current] + bytes <= budget
+            });
+            if fits {
+                self.used_bytes[self.current] += bytes;
+                return Some(self.current);
+            }
+            self.current += 1;
+        }
+
+        None
+    }
+}
+
+/// Converts bytes to GiB.
+pub(crate) fn gib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0 * 1024.0)
+}
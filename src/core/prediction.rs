@@ -0,0 +1,96 @@
+/// The maximum number of predicted tokens verified in one forward pass.
+pub const SPECULATION_WINDOW: usize = 16;
+
+/// The number of generated tokens matched against the prediction to find where the
+/// generation rejoins it after a divergence.
+const ALIGNMENT_TOKENS: usize = 3;
+
+/// A predicted output, the text the completion is expected to be close to.
+///
+/// Rewrites such as code edits mostly repeat their input. The tokens of the prediction
+/// that follow the generated text are drafted and verified in a single forward pass,
+/// so every run of accepted tokens costs one pass instead of one pass per token.
+/// Once the generation diverges, the prediction is only drafted from again after the
+/// last generated tokens are found in it, e.g. past an edited line.
+pub struct PredictedOutput {
+    tokens: Vec<u32>,
+    /// The position in `tokens` of the next expected token, `None` while diverged.
+    cursor: Option<usize>,
+    accepted: usize,
+}
+
+impl PredictedOutput {
+    /// Creates a predicted output from the tokens of the prediction.
+    pub fn new(tokens: Vec<u32>) -> Self {
+        Self {
+            tokens,
+            cursor: Some(0),
+            accepted: 0,
+        }
+    }
+
+    /// Returns the predicted tokens to verify next, at most `max` of them.
+    pub fn draft(&self, max: usize) -> &[u32] {
+        match self.cursor {
+            Some(cursor) => {
+                let end = self.tokens.len().min(cursor + max);
+                &self.tokens[cursor.min(end)..end]
+            }
+            None => &[],
+        }
+    }
+
+    /// Records the tokens sampled at the positions of the last draft.
+    ///
+    /// # Arguments
+    ///
+    /// * `sampled` - The tokens the model sampled, one more than the accepted draft tokens
+    ///   unless the generation ended.
+    /// * `generated` - Every token generated so far, `sampled` included.
+    pub fn advance(&mut self, sampled: &[u32], generated: &[u32]) {
+        let Some(cursor) = self.cursor else {
+            self.cursor = self.align(generated);
+            return;
+        };
+
+        let matched = self.tokens[cursor.min(self.tokens.len())..]
+            .iter()
+            .zip(sampled)
+            .take_while(|(predicted, sampled)| predicted == sampled)
+            .count();
+        self.accepted += matched;
+        self.cursor = if matched == sampled.len() {
+            Some(cursor + matched)
+        } else {
+            self.align(generated)
+        };
+    }
+
+    /// The number of predicted tokens that were part of the completion.
+    pub fn accepted(&self) -> usize {
+        self.accepted
+    }
+
+    /// The number of predicted tokens that were not part of the completion.
+    pub fn rejected(&self) -> usize {
+        self.tokens.len().saturating_sub(self.accepted)
+    }
+
+    /// Finds the position following the last generated tokens in the prediction.
+    fn align(&self, generated: &[u32]) -> Option<usize> {
+        let n = ALIGNMENT_TOKENS.min(generated.len());
+        if n == 0 {
+            return None;
+        }
+        let tail = &generated[generated.len() - n..];
+        let from = self.cursor.unwrap_or(0);
+
+        let position = |start: usize| {
+            self.tokens[start.min(self.tokens.len())..]
+                .windows(n)
+                .position(|window| window == tail)
+                .map(|position| start + position + n)
+        };
+        position(from).or_else(|| position(0))
+    }
+}
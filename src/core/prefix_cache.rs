@@ -0,0 +1,144 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use candle_transformers::models::llama::Cache;
+
+/// The KV caches of recent generations, by the tokens they hold.
+///
+/// A cache can only be reused as a whole, so a request resumes from a retained cache when
+/// all of its tokens are a prefix of the prompt of the request. Caches are evicted once
+/// unused for the configured time, or least recently used first when there are too many.
+pub struct PrefixCache {
+    entries: Mutex<Vec<PrefixEntry>>,
+    max_entries: usize,
+    ttl: Duration,
+    /// The most prompt tokens a resumed cache may leave uncached.
+    max_resume_tokens: usize,
+}
+
+struct PrefixEntry {
+    /// The revision of the weights the cache was computed with.
+    revision: String,
+    tokens: Vec<u32>,
+    cache: Cache,
+    used_at: Instant,
+}
+
+impl PrefixCache {
+    /// Creates an empty prefix cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_entries` - The number of caches retained at most.
+    /// * `ttl` - How long an unused cache is retained.
+    /// * `max_resume_tokens` - The most prompt tokens a resumed cache may leave uncached.
+    pub fn new(max_entries: usize, ttl: Duration, max_resume_tokens: usize) -> Self {
+        Self {
+            entries: Mutex::new(Vec::new()),
+            max_entries,
+            ttl,
+            max_resume_tokens,
+        }
+    }
+
+    /// Finds the longest retained cache whose tokens are a prefix of a prompt.
+    ///
+    /// The cache must leave at least one token of the prompt to run the model over, as
+    /// the logits of the last token are not retained, and at most `max_resume_tokens`:
+    /// the rest of the prompt is run one token at a time after a resumed cache, which is
+    /// slower than processing the whole prompt at once past a few dozen tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `revision` - The revision of the served weights.
+    /// * `tokens` - The tokens of the prompt.
+    ///
+    /// # Returns
+    ///
+    /// The number of tokens the cache holds and a copy of the cache, or `None` if no
+    /// retained cache matches.
+    pub fn lookup(&self, revision: &str, tokens: &[u32]) -> Option<(usize, Cache)> {
+        let mut entries = self.lock();
+        let entry = entries
+            .iter_mut()
+            .filter(|entry| {
+                entry.revision == revision
+                    && entry.tokens.len() < tokens.len()
+                    && tokens.len() - entry.tokens.len() <= self.max_resume_tokens
+                    && tokens.starts_with(&entry.tokens)
+            })
+            .max_by_key(|entry| entry.tokens.len())?;
+        entry.used_at = Instant::now();
+        Some((entry.tokens.len(), entry.cache.clone()))
+    }
+
+    /// Retains the cache of a sequence of tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `revision` - The revision of the weights the cache was computed with.
+    /// * `tokens` - The tokens the cache holds, in order.
+    /// * `cache` - The KV cache.
+    pub fn store(&self, revision: String, tokens: Vec<u32>, cache: Cache) {
+        if self.max_entries == 0 || tokens.is_empty() {
+            return;
+        }
+        let mut entries = self.lock();
+        let now = Instant::now();
+        entries.retain(|entry| {
+            now.duration_since(entry.used_at) < self.ttl
+                && !(entry.revision == revision && entry.tokens == tokens)
+        });
+        if entries.len() >= self.max_entries {
+            entries.sort_by_key(|entry| std::cmp::Reverse(entry.used_at));
+            entries.truncate(self.max_entries - 1);
+        }
+        entries.push(PrefixEntry {
+            revision,
+            tokens,
+            cache,
+            used_at: now,
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<PrefixEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::{DType, Device};
+    use candle_transformers::models::llama::LlamaConfig;
+
+    fn cache() -> Cache {
+        let config: LlamaConfig = serde_json::from_value(serde_json::json!({
+            "hidden_size": 8,
+            "intermediate_size": 16,
+            "vocab_size": 32,
+            "num_hidden_layers": 1,
+            "num_attention_heads": 2,
+            "num_key_value_heads": 1,
+            "rms_norm_eps": 1e-5,
+            "rope_theta": 10000.0,
+            "max_position_embeddings": 64,
+        }))
+        .unwrap();
+        Cache::new(true, DType::F32, &config.into_config(false), &Device::Cpu).unwrap()
+    }
+
+    #[test]
+    fn resumes_only_short_suffixes() {
+        let prefix_cache = PrefixCache::new(4, Duration::from_secs(60), 3);
+        prefix_cache.store("main".to_string(), vec![1, 2, 3], cache());
+
+        let resumed = prefix_cache.lookup("main", &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(resumed.map(|(cached, _)| cached), Some(3));
+        assert!(prefix_cache
+            .lookup("main", &[1, 2, 3, 4, 5, 6, 7])
+            .is_none());
+        assert!(prefix_cache.lookup("main", &[1, 2, 3]).is_none());
+        assert!(prefix_cache.lookup("other", &[1, 2, 3, 4]).is_none());
+    }
+}
@@ -0,0 +1,218 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use candle_core::safetensors::MmapedSafetensors;
+use candle_core::{DType, Device, Tensor};
+use candle_transformers::models::llama::Config;
+use candle_transformers::models::quantized_llama::ModelWeights as QuantizedLlama3;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::core::quantize::{Quantization, QuantizedWeights};
+
+/// The order in which AWQ packs eight 4-bit values into an `i32`, reversed.
+const AWQ_REVERSE_ORDER: [usize; 8] = [0, 4, 1, 5, 2, 6, 3, 7];
+
+/// The `quantization_config` section of the `config.json` of a pre-quantized checkpoint.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CheckpointQuantization {
+    pub quant_method: QuantMethod,
+    pub bits: usize,
+    /// The number of input features sharing a scale and zero point, `-1` for all of them.
+    pub group_size: i64,
+    /// `gptq_v2` checkpoints store the zero points without the offset of one.
+    #[serde(default)]
+    pub checkpoint_format: Option<String>,
+}
+
+/// The method a checkpoint was quantized with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantMethod {
+    Awq,
+    Gptq,
+}
+
+impl CheckpointQuantization {
+    /// Reads the quantization of a checkpoint from its `config.json`.
+    ///
+    /// # Returns
+    ///
+    /// The quantization, or `None` when the checkpoint has full-precision weights or
+    /// uses a method other than AWQ or GPTQ.
+    pub fn from_model_config(config: &serde_json::Value) -> Option<Self> {
+        let quantization = config.get("quantization_config")?;
+
+        serde_json::from_value(quantization.clone()).ok()
+    }
+
+    /// The quantization applied when loading the checkpoint with the same number of bits.
+    pub fn default_quantization(&self) -> Quantization {
+        if self.bits > 4 {
+            Quantization::Int8
+        } else {
+            Quantization::Int4
+        }
+    }
+}
+
+/// Loads an AWQ or GPTQ quantized Llama checkpoint as a quantized model.
+///
+/// candle has no AWQ or GPTQ kernels, so every packed linear layer is unpacked to
+/// `f32` on the CPU and immediately quantized again to the GGML block format of
+/// `quantization`. The other tensors are quantized like full-precision weights.
+///
+/// # Arguments
+///
+/// * `filenames` - The safetensors shards of the checkpoint.
+/// * `config` - The configuration of the model.
+/// * `checkpoint` - The quantization of the checkpoint.
+/// * `quantization` - The quantization of the loaded model.
+/// * `device` - The device to load the quantized model on.
+///
+/// # Returns
+///
+/// The quantized model.
+///
+/// # Errors
+///
+/// Returns an error if the checkpoint uses a bit width other than 4 or 8 (AWQ only
+/// supports 4), a packed tensor is missing or has an unexpected shape, or the
+/// quantized model cannot be built.
+pub fn load_prequantized(
+    filenames: &[PathBuf],
+    config: &Config,
+    checkpoint: &CheckpointQuantization,
+    quantization: Quantization,
+    device: &Device,
+) -> anyhow::Result<QuantizedLlama3> {
+    match (checkpoint.quant_method, checkpoint.bits) {
+        (QuantMethod::Awq, 4) | (QuantMethod::Gptq, 4 | 8) => {}
+        (method, bits) => bail!("{bits}-bit {method:?} checkpoints are not supported"),
+    }
+
+    let before = std::time::Instant::now();
+    let safetensors = unsafe { MmapedSafetensors::multi(filenames)? };
+    let mut weights = QuantizedWeights::new(config, quantization);
+
+    for (name, _) in safetensors.tensors() {
+        if let Some(prefix) = name.strip_suffix(".qweight") {
+            let weight = dequantize_linear(&safetensors, prefix, checkpoint)
+                .with_context(|| format!("Failed to unpack {prefix}"))?;
+            weights.push(&format!("{prefix}.weight"), weight)?;
+        } else if !is_packed_tensor(&name) {
+            weights.push(&name, safetensors.load(&name, &Device::Cpu)?)?;
+        }
+    }
+    let model = weights.build(device)?;
+
+    info!(
+        "Converted the {:?} checkpoint to {} in {:.2?}",
+        checkpoint.quant_method,
+        quantization.as_str(),
+        before.elapsed()
+    );
+
+    Ok(model)
+}
+
+/// Whether a tensor holds the quantization data of a packed linear layer.
+fn is_packed_tensor(name: &str) -> bool {
+    [".qweight", ".qzeros", ".scales", ".g_idx"]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+}
+
+/// Unpacks the weight of a quantized linear layer to an `f32` tensor of shape `(out, in)`.
+fn dequantize_linear(
+    safetensors: &MmapedSafetensors,
+    prefix: &str,
+    checkpoint: &CheckpointQuantization,
+) -> anyhow::Result<Tensor> {
+    let bits = checkpoint.bits;
+    let pack = 32 / bits;
+    let mask = (1u32 << bits) - 1;
+
+    let (qweight, qweight_shape) = load_u32(safetensors, &format!("{prefix}.qweight"))?;
+    let (qzeros, _) = load_u32(safetensors, &format!("{prefix}.qzeros"))?;
+    let scales = safetensors
+        .load(&format!("{prefix}.scales"), &Device::Cpu)?
+        .to_dtype(DType::F32)?;
+    let (groups, out_features) = scales.dims2()?;
+    let scales = scales.flatten_all()?.to_vec1::<f32>()?;
+
+    let in_features = match checkpoint.quant_method {
+        // GPTQ packs the input features, AWQ the output features
+        QuantMethod::Gptq => qweight_shape[0] * pack,
+        QuantMethod::Awq => qweight_shape[0],
+    };
+    let group_size = match checkpoint.group_size {
+        size if size > 0 => size as usize,
+        _ => in_features,
+    };
+    if in_features.div_ceil(group_size) != groups {
+        bail!("{groups} scale groups do not match {in_features} input features");
+    }
+
+    let mut weight = vec![0f32; out_features * in_features];
+    match checkpoint.quant_method {
+        QuantMethod::Gptq => {
+            let g_idx = match load_u32(safetensors, &format!("{prefix}.g_idx")) {
+                Ok((g_idx, _)) => Some(g_idx),
+                Err(_) => None,
+            };
+            let zero_offset = match checkpoint.checkpoint_format.as_deref() {
+                Some("gptq_v2") => 0,
+                _ => 1,
+            };
+            let packed_out = out_features / pack;
+
+            for i in 0..in_features {
+                let group = g_idx
+                    .as_ref()
+                    .map_or(i / group_size, |g_idx| g_idx[i] as usize);
+                let shift = bits * (i % pack);
+                for j in 0..out_features {
+                    let q = (qweight[(i / pack) * out_features + j] >> shift) & mask;
+                    let zero =
+                        (qzeros[group * packed_out + j / pack] >> (bits * (j % pack))) & mask;
+                    let zero = zero + zero_offset;
+                    weight[j * in_features + i] =
+                        scales[group * out_features + j] * (q as f32 - zero as f32);
+                }
+            }
+        }
+        QuantMethod::Awq => {
+            let packed_out = out_features / pack;
+
+            for i in 0..in_features {
+                let group = i / group_size;
+                for j in 0..out_features {
+                    let shift = bits * AWQ_REVERSE_ORDER[j % pack];
+                    let q = (qweight[i * packed_out + j / pack] >> shift) & mask;
+                    let zero = (qzeros[group * packed_out + j / pack] >> shift) & mask;
+                    weight[j * in_features + i] =
+                        scales[group * out_features + j] * (q as f32 - zero as f32);
+                }
+            }
+        }
+    }
+
+    Ok(Tensor::from_vec(
+        weight,
+        (out_features, in_features),
+        &Device::Cpu,
+    )?)
+}
+
+/// Reads a packed `i32` tensor as unsigned words, with its shape.
+fn load_u32(safetensors: &MmapedSafetensors, name: &str) -> anyhow::Result<(Vec<u32>, Vec<usize>)> {
+    let view = safetensors.get(name)?;
+    let words = view
+        .data()
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+
+    Ok((words, view.shape().to_vec()))
+}
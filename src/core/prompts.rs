@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Deserialize;
+
+/// A named system prompt preset defined by the operator.
+///
+/// The `system` text may contain `{{variable}}` placeholders, filled from the
+/// variables of the request and then from the defaults of the template.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PromptTemplate {
+    pub system: String,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+/// Errors returned when resolving a prompt template.
+#[derive(Debug)]
+pub enum PromptTemplateError {
+    /// No template with this name is configured.
+    UnknownTemplate(String),
+    /// A placeholder has neither a request value nor a default.
+    MissingVariable { template: String, variable: String },
+}
+
+impl fmt::Display for PromptTemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownTemplate(name) => write!(f, "Unknown prompt template '{name}'"),
+            Self::MissingVariable { template, variable } => write!(
+                f,
+                "Prompt template '{template}' requires the variable '{variable}'"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PromptTemplateError {}
+
+impl PromptTemplate {
+    /// Renders the template, replacing every `{{variable}}` placeholder.
+    ///
+    /// # Arguments
+    ///
+    /// * `variables` - The variables supplied by the request, taking precedence over the
+    ///   defaults of the template.
+    ///
+    /// # Returns
+    ///
+    /// The rendered text, or the name of the first variable without a value.
+    pub fn render(&self, variables: &HashMap<String, String>) -> Result<String, String> {
+        let mut rendered = String::with_capacity(self.system.len());
+        let mut rest = self.system.as_str();
+
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start + 2..].find("}}") else {
+                break;
+            };
+            let name = rest[start + 2..start + 2 + end].trim();
+            let value = variables
+                .get(name)
+                .or_else(|| self.variables.get(name))
+                .ok_or_else(|| name.to_string())?;
+
+            rendered.push_str(&rest[..start]);
+            rendered.push_str(value);
+            rest = &rest[start + 2 + end + 2..];
+        }
+        rendered.push_str(rest);
+
+        Ok(rendered)
+    }
+}
+
+/// Looks up and renders a configured prompt template.
+///
+/// # Arguments
+///
+/// * `templates` - The configured templates by name.
+/// * `name` - The name of the template to render.
+/// * `variables` - The variables supplied by the request.
+///
+/// # Errors
+///
+/// Returns an error if the template does not exist or a variable is missing.
+pub fn render_template(
+    templates: &HashMap<String, PromptTemplate>,
+    name: &str,
+    variables: &HashMap<String, String>,
+) -> Result<String, PromptTemplateError> {
+    let template = templates
+        .get(name)
+        .ok_or_else(|| PromptTemplateError::UnknownTemplate(name.to_string()))?;
+
+    template
+        .render(variables)
+        .map_err(|variable| PromptTemplateError::MissingVariable {
+            template: name.to_string(),
+            variable,
+        })
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::core::generator::TextGeneration;
+use crate::core::sampling::SamplingParams;
+use crate::openai::http_entities::AppState;
+
+/// A prompt of a quantization check, one JSON object per line.
+#[derive(Debug, Deserialize)]
+pub struct CheckPrompt {
+    pub prompt: String,
+}
+
+/// How far the quantized model drifts from the reference model over a set of prompts.
+#[derive(Debug, Default)]
+pub struct QuantizationReport {
+    pub prompts: usize,
+    /// The tokens generated by the reference model, over which the models are compared.
+    pub tokens: usize,
+    /// The tokens the quantized model predicts as its most likely token too.
+    pub top1_matches: usize,
+    abs_logprob_diff: f64,
+    /// The tokens the quantized model generates greedily before its first divergence,
+    /// summed over the prompts.
+    pub matching_prefix_tokens: usize,
+    /// The prompts for which the quantized model generates the same tokens.
+    pub identical_outputs: usize,
+}
+
+impl QuantizationReport {
+    /// The share of the tokens on which both models agree on the most likely token,
+    /// `None` without tokens.
+    pub fn top1_agreement(&self) -> Option<f64> {
+        (self.tokens > 0).then(|| self.top1_matches as f64 / self.tokens as f64)
+    }
+
+    /// The mean absolute difference between the log-probabilities the two models assign
+    /// to the tokens of the reference output.
+    pub fn mean_abs_logprob_diff(&self) -> Option<f64> {
+        (self.tokens > 0).then(|| self.abs_logprob_diff / self.tokens as f64)
+    }
+
+    /// The mean number of tokens generated greedily before the outputs diverge.
+    pub fn mean_matching_prefix(&self) -> Option<f64> {
+        (self.prompts > 0).then(|| self.matching_prefix_tokens as f64 / self.prompts as f64)
+    }
+}
+
+impl fmt::Display for QuantizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "prompts: {}, tokens: {}", self.prompts, self.tokens)?;
+        if let (Some(agreement), Some(diff), Some(prefix)) = (
+            self.top1_agreement(),
+            self.mean_abs_logprob_diff(),
+            self.mean_matching_prefix(),
+        ) {
+            writeln!(
+                f,
+                "top-1 agreement: {agreement:.4}, mean |logprob diff|: {diff:.4}, \
+                 mean tokens before divergence: {prefix:.1}, identical outputs: {}/{}",
+                self.identical_outputs, self.prompts
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares a quantized model with its reference model over a JSONL file of prompts.
+///
+/// For every prompt, `{"prompt": ...}`, the reference model generates up to
+/// `max_tokens` tokens greedily. Both models then score that output, which tells at
+/// every token whether the quantized model predicts the same most likely token, how far
+/// its log-probability of the token is from the reference one, and where a greedy
+/// generation of the quantized model would first diverge.
+///
+/// # Arguments
+///
+/// * `reference` - The application state holding the reference model.
+/// * `quantized` - The application state holding the quantized model.
+/// * `prompts` - The path of the JSONL prompts.
+/// * `max_tokens` - The number of tokens generated for every prompt.
+///
+/// # Returns
+///
+/// The token-level divergence of the two models over the prompts.
+///
+/// # Errors
+///
+/// Returns an error if the reference model is quantized itself, such as an AWQ or GPTQ
+/// checkpoint, or an error naming the line if the prompts cannot be read or parsed, or
+/// if a model fails to generate or score.
+pub fn compare_quantization(
+    reference: &AppState,
+    quantized: &AppState,
+    prompts: &Path,
+    max_tokens: i32,
+) -> anyhow::Result<QuantizationReport> {
+    if reference.settings.current().model.quantize.is_some() {
+        bail!("The checkpoint is quantized, it has no full-precision version to compare with");
+    }
+    let file = std::fs::File::open(prompts)
+        .with_context(|| format!("Error opening prompts {}", prompts.display()))?;
+    let mut report = QuantizationReport::default();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Error reading {}", prompts.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let prompt: CheckPrompt = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid prompt on line {}", index + 1))?;
+
+        compare_prompt(reference, quantized, &mut report, prompt.prompt, max_tokens)
+            .with_context(|| format!("Error comparing line {}", index + 1))?;
+        if report.prompts % 10 == 0 {
+            info!("Compared {} prompts", report.prompts);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Generates with the reference model, scores the output with both models and adds
+/// the comparison to the report.
+fn compare_prompt(
+    reference: &AppState,
+    quantized: &AppState,
+    report: &mut QuantizationReport,
+    prompt: String,
+    max_tokens: i32,
+) -> anyhow::Result<()> {
+    let params = SamplingParams::default()
+        .with_temperature(Some(0.0))
+        .with_max_tokens(Some(max_tokens));
+    let (output, _) =
+        TextGeneration::from_state(reference.clone(), &params)?.generate(prompt.clone())?;
+
+    let params = SamplingParams::default();
+    let expected = TextGeneration::from_state(reference.clone(), &params)?.score(
+        prompt.clone(),
+        Some(&output),
+        1,
+    )?;
+    let actual =
+        TextGeneration::from_state(quantized.clone(), &params)?.score(prompt, Some(&output), 1)?;
+    if expected.tokens != actual.tokens {
+        bail!("The models tokenize the prompt differently");
+    }
+
+    let mut diverged = false;
+    for (expected, actual) in expected.scored().iter().zip(actual.scored()) {
+        let expected_top = expected.top_logprobs.first().map(|(id, _)| *id);
+        let matches = expected_top.is_some()
+            && expected_top == actual.top_logprobs.first().map(|(id, _)| *id);
+
+        report.tokens += 1;
+        report.top1_matches += usize::from(matches);
+        report.abs_logprob_diff += f64::from((expected.logprob - actual.logprob).abs());
+        diverged |= !matches;
+        if !diverged {
+            report.matching_prefix_tokens += 1;
+        }
+    }
+    report.prompts += 1;
+    report.identical_outputs += usize::from(!diverged);
+    Ok(())
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::{DType, Device, Tensor};
+use candle_transformers::models::llama::Config;
+use candle_transformers::models::quantized_llama::ModelWeights as QuantizedLlama3;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Quantization applied to full-precision weights at load time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    /// 8-bit blocks (`q8_0`), close to full-precision quality.
+    Int8,
+    /// 4-bit k-quant blocks (`q4k`), the smallest footprint.
+    Int4,
+}
+
+impl Quantization {
+    /// The name of the quantization in the configuration.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Int8 => "int8",
+            Self::Int4 => "int4",
+        }
+    }
+
+    fn ggml_dtype(&self) -> GgmlDType {
+        match self {
+            Self::Int8 => GgmlDType::Q8_0,
+            Self::Int4 => GgmlDType::Q4K,
+        }
+    }
+}
+
+/// Quantizes full-precision Llama safetensors weights and loads them as a quantized model.
+///
+/// The shards are read one at a time on the CPU, the linear layers are quantized to
+/// the requested format and the norms are kept in `f32`. The tensors are renamed to
+/// the GGUF layout and the query and key projections permuted for the interleaved
+/// rotary embedding of the quantized implementation.
+///
+/// # Arguments
+///
+/// * `filenames` - The safetensors shards of the model.
+/// * `config` - The configuration of the model.
+/// * `quantization` - The quantization to apply.
+/// * `device` - The device to load the quantized model on.
+///
+/// # Returns
+///
+/// The quantized model.
+///
+/// # Errors
+///
+/// Returns an error if a shard cannot be read, a tensor has an unexpected shape or
+/// the quantized model cannot be built.
+///
+/// # Notes
+///
+/// The quantized implementation does not apply the Llama 3.1 rope scaling, which
+/// only matters for prompts longer than the original 8k context.
+pub fn quantize_llama(
+    filenames: &[PathBuf],
+    config: &Config,
+    quantization: Quantization,
+    device: &Device,
+) -> anyhow::Result<QuantizedLlama3> {
+    let before = std::time::Instant::now();
+    let mut weights = QuantizedWeights::new(config, quantization);
+
+    for filename in filenames {
+        for (name, tensor) in candle_core::safetensors::load(filename, &Device::Cpu)? {
+            weights.push(&name, tensor)?;
+        }
+    }
+    let model = weights.build(device)?;
+
+    info!(
+        "Quantized the weights to {} in {:.2?}",
+        quantization.as_str(),
+        before.elapsed()
+    );
+
+    Ok(model)
+}
+
+/// Quantizes full-precision Llama safetensors weights into a GGUF file, which
+/// `load_quantized` loads without quantizing them again.
+///
+/// # Arguments
+///
+/// * `filenames` - The safetensors shards of the model.
+/// * `config` - The configuration of the model.
+/// * `quantization` - The quantization to apply.
+/// * `output` - The GGUF file to write.
+///
+/// # Errors
+///
+/// Returns an error if a shard cannot be read, a tensor has an unexpected shape or
+/// the file cannot be written.
+pub fn write_quantized(
+    filenames: &[PathBuf],
+    config: &Config,
+    quantization: Quantization,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let mut weights = QuantizedWeights::new(config, quantization);
+    for filename in filenames {
+        for (name, tensor) in candle_core::safetensors::load(filename, &Device::Cpu)? {
+            weights.push(&name, tensor)?;
+        }
+    }
+    let mut file = BufWriter::new(File::create(output)?);
+    weights.write(&mut file)?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Loads a quantized Llama model from a GGUF file, such as one written by
+/// `write_quantized`.
+///
+/// # Arguments
+///
+/// * `path` - The GGUF file.
+/// * `device` - The device to load the model on.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not a Llama GGUF model.
+pub fn load_quantized(path: &Path, device: &Device) -> anyhow::Result<QuantizedLlama3> {
+    let mut file = File::open(path)?;
+    let content = gguf_file::Content::read(&mut file)?;
+
+    Ok(QuantizedLlama3::from_gguf(content, &mut file, device)?)
+}
+
+/// Quantized tensors in the GGUF layout, from which the quantized model is built.
+pub(crate) struct QuantizedWeights<'a> {
+    config: &'a Config,
+    quantization: Quantization,
+    tensors: Vec<(String, QTensor)>,
+}
+
+impl<'a> QuantizedWeights<'a> {
+    pub(crate) fn new(config: &'a Config, quantization: Quantization) -> Self {
+        Self {
+            config,
+            quantization,
+            tensors: Vec::new(),
+        }
+    }
+
+    /// Quantizes a tensor of the Hugging Face checkpoint, skipping tensors the model does not use.
+    pub(crate) fn push(&mut self, name: &str, tensor: Tensor) -> anyhow::Result<()> {
+        let Some(gguf_name) = gguf_name(name) else {
+            return Ok(());
+        };
+        let tensor = tensor.to_dtype(DType::F32)?;
+        let tensor = match gguf_name.rsplit('.').nth(1) {
+            Some("attn_q") => permute_rotary(&tensor, self.config.num_attention_heads)?,
+            Some("attn_k") => permute_rotary(&tensor, self.config.num_key_value_heads)?,
+            _ => tensor,
+        };
+        let dtype = if tensor.rank() == 1 {
+            GgmlDType::F32
+        } else {
+            self.quantization.ggml_dtype()
+        };
+        self.tensors
+            .push((gguf_name, QTensor::quantize(&tensor, dtype)?));
+
+        Ok(())
+    }
+
+    /// Builds the quantized model on `device`.
+    pub(crate) fn build(self, device: &Device) -> anyhow::Result<QuantizedLlama3> {
+        let mut gguf = Cursor::new(Vec::new());
+        self.write(&mut gguf)?;
+        gguf.set_position(0);
+
+        let content = gguf_file::Content::read(&mut gguf)?;
+
+        Ok(QuantizedLlama3::from_gguf(content, &mut gguf, device)?)
+    }
+
+    /// Writes the model in the GGUF format.
+    fn write(mut self, writer: &mut (impl Write + Seek)) -> anyhow::Result<()> {
+        if !self.tensors.iter().any(|(name, _)| name == "output.weight") {
+            // Tied embeddings, the output projection shares the token embeddings
+            let embeddings = self
+                .tensors
+                .iter()
+                .find(|(name, _)| name == "token_embd.weight")
+                .map(|(_, tensor)| tensor.dequantize(&Device::Cpu))
+                .ok_or_else(|| anyhow::anyhow!("The model has no token embeddings"))??;
+            let output = QTensor::quantize(&embeddings, self.quantization.ggml_dtype())?;
+            self.tensors.push(("output.weight".to_string(), output));
+        }
+
+        let metadata = metadata(self.config);
+        let metadata: Vec<(&str, &gguf_file::Value)> =
+            metadata.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        let tensors: Vec<(&str, &QTensor)> =
+            self.tensors.iter().map(|(k, v)| (k.as_str(), v)).collect();
+
+        gguf_file::write(writer, &metadata, &tensors)?;
+
+        Ok(())
+    }
+}
+
+/// Maps a Hugging Face Llama tensor name to its GGUF name.
+fn gguf_name(name: &str) -> Option<String> {
+    let name = match name {
+        "model.embed_tokens.weight" => "token_embd.weight".to_string(),
+        "model.norm.weight" => "output_norm.weight".to_string(),
+        "lm_head.weight" => "output.weight".to_string(),
+        _ => {
+            let rest = name.strip_prefix("model.layers.")?;
+            let (layer, tensor) = rest.split_once('.')?;
+            let tensor = match tensor {
+                "self_attn.q_proj.weight" => "attn_q.weight",
+                "self_attn.k_proj.weight" => "attn_k.weight",
+                "self_attn.v_proj.weight" => "attn_v.weight",
+                "self_attn.o_proj.weight" => "attn_output.weight",
+                "mlp.gate_proj.weight" => "ffn_gate.weight",
+                "mlp.up_proj.weight" => "ffn_up.weight",
+                "mlp.down_proj.weight" => "ffn_down.weight",
+                "input_layernorm.weight" => "attn_norm.weight",
+                "post_attention_layernorm.weight" => "ffn_norm.weight",
+                _ => return None,
+            };
+            format!("blk.{layer}.{tensor}")
+        }
+    };
+
+    Some(name)
+}
+
+/// Reorders the rows of a query or key projection from the half-split rotary layout
+/// of Hugging Face checkpoints to the interleaved layout of GGUF.
+fn permute_rotary(weight: &Tensor, n_head: usize) -> candle_core::Result<Tensor> {
+    let (rows, columns) = weight.dims2()?;
+
+    weight
+        .reshape((n_head, 2, rows / n_head / 2, columns))?
+        .transpose(1, 2)?
+        .contiguous()?
+        .reshape((rows, columns))
+}
+
+/// Builds the GGUF metadata read by the quantized Llama implementation.
+fn metadata(config: &Config) -> HashMap<String, gguf_file::Value> {
+    let head_dim = config.hidden_size / config.num_attention_heads;
+
+    HashMap::from([
+        (
+            "general.architecture".to_string(),
+            gguf_file::Value::String("llama".to_string()),
+        ),
+        (
+            "llama.attention.head_count".to_string(),
+            gguf_file::Value::U32(config.num_attention_heads as u32),
+        ),
+        (
+            "llama.attention.head_count_kv".to_string(),
+            gguf_file::Value::U32(config.num_key_value_heads as u32),
+        ),
+        (
+            "llama.block_count".to_string(),
+            gguf_file::Value::U32(config.num_hidden_layers as u32),
+        ),
+        (
+            "llama.embedding_length".to_string(),
+            gguf_file::Value::U32(config.hidden_size as u32),
+        ),
+        (
+            "llama.rope.dimension_count".to_string(),
+            gguf_file::Value::U32(head_dim as u32),
+        ),
+        (
+            "llama.attention.layer_norm_rms_epsilon".to_string(),
+            gguf_file::Value::F32(config.rms_norm_eps as f32),
+        ),
+        (
+            "llama.rope.freq_base".to_string(),
+            gguf_file::Value::F32(config.rope_theta),
+        ),
+    ])
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use chrono::Utc;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::config::RagSettings;
+use crate::core::embeddings::Embedder;
+use crate::core::vector_index::VectorIndex;
+
+/// Errors returned by the [`RagStore`].
+#[derive(Debug)]
+pub enum RagError {
+    /// No document has this id.
+    NotFound(String),
+    /// A document with this id was already ingested.
+    Duplicate(String),
+    /// The document has no text to index.
+    EmptyDocument,
+    Embedding(anyhow::Error),
+}
+
+impl fmt::Display for RagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "No document found with id '{id}'"),
+            Self::Duplicate(id) => write!(f, "A document with id '{id}' already exists"),
+            Self::EmptyDocument => write!(f, "The document has no text to index"),
+            Self::Embedding(err) => write!(f, "Error embedding text: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for RagError {}
+
+/// A document ingested into the [`RagStore`].
+#[derive(Clone, Debug, Serialize)]
+pub struct RagDocument {
+    pub id: String,
+    pub created_at: i64,
+    pub chunks: usize,
+    pub characters: usize,
+    /// Arbitrary client data returned with the chunks of the document.
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// A chunk of a document retrieved for a query.
+#[derive(Clone, Debug, Serialize)]
+pub struct RetrievedChunk {
+    pub document_id: String,
+    pub chunk_index: usize,
+    pub text: String,
+    /// The cosine similarity between the chunk and the query.
+    pub score: f32,
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// A chunk of a document, by the id of its vector in the index.
+struct Chunk {
+    document_id: String,
+    index: usize,
+    text: String,
+}
+
+struct Indexed {
+    index: VectorIndex,
+    chunks: HashMap<usize, Chunk>,
+    documents: HashMap<String, (RagDocument, Vec<usize>)>,
+}
+
+/// An in-memory document store for retrieval-augmented generation.
+///
+/// Documents are split into overlapping chunks of characters, each chunk is embedded
+/// and added to an HNSW index, and queries retrieve the chunks closest to their own
+/// embedding. The store lives in memory and is empty after a restart.
+pub struct RagStore {
+    embedder: Arc<Embedder>,
+    settings: RagSettings,
+    indexed: RwLock<Indexed>,
+}
+
+impl RagStore {
+    /// Creates an empty store.
+    ///
+    /// # Arguments
+    ///
+    /// * `embedder` - The model embedding the chunks and the queries.
+    /// * `settings` - The chunking and retrieval settings.
+    pub fn new(embedder: Arc<Embedder>, settings: RagSettings) -> Self {
+        let index = VectorIndex::new(embedder.dims());
+
+        Self {
+            embedder,
+            settings,
+            indexed: RwLock::new(Indexed {
+                index,
+                chunks: HashMap::new(),
+                documents: HashMap::new(),
+            }),
+        }
+    }
+
+    /// The number of chunks retrieved when a query does not say.
+    pub fn default_top_k(&self) -> usize {
+        self.settings.top_k
+    }
+
+    /// Chunks, embeds and indexes a document.
+    ///
+    /// Embedding is compute bound and should run on a blocking thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the document, generated when `None`.
+    /// * `text` - The text of the document.
+    /// * `metadata` - Client data returned with the chunks of the document.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the id is taken, the text is blank or embedding fails.
+    pub fn add_document(
+        &self,
+        id: Option<String>,
+        text: &str,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<RagDocument, RagError> {
+        let id = id.unwrap_or_else(|| format!("doc_{}", Uuid::new_v4().simple()));
+        if self.read().documents.contains_key(&id) {
+            return Err(RagError::Duplicate(id));
+        }
+
+        let chunks = chunk_text(text, self.settings.chunk_chars, self.settings.chunk_overlap);
+        if chunks.is_empty() {
+            return Err(RagError::EmptyDocument);
+        }
+        let embeddings = self.embedder.embed(&chunks).map_err(RagError::Embedding)?;
+
+        let mut indexed = self.write();
+        // Checked again as another request may have ingested the id while embedding
+        if indexed.documents.contains_key(&id) {
+            return Err(RagError::Duplicate(id));
+        }
+        let mut vector_ids = Vec::with_capacity(chunks.len());
+        for (index, (text, vector)) in chunks.into_iter().zip(embeddings.vectors).enumerate() {
+            let vector_id = indexed.index.insert(vector);
+            indexed.chunks.insert(
+                vector_id,
+                Chunk {
+                    document_id: id.clone(),
+                    index,
+                    text,
+                },
+            );
+            vector_ids.push(vector_id);
+        }
+
+        let document = RagDocument {
+            id: id.clone(),
+            created_at: Utc::now().timestamp(),
+            chunks: vector_ids.len(),
+            characters: text.chars().count(),
+            metadata,
+        };
+        indexed.documents.insert(id, (document.clone(), vector_ids));
+
+        Ok(document)
+    }
+
+    /// Lists the ingested documents, most recent first.
+    pub fn documents(&self) -> Vec<RagDocument> {
+        let mut documents: Vec<RagDocument> = self
+            .read()
+            .documents
+            .values()
+            .map(|(document, _)| document.clone())
+            .collect();
+        documents.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        documents
+    }
+
+    /// Removes a document and its chunks from the store.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the document.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no document has this id.
+    pub fn delete_document(&self, id: &str) -> Result<(), RagError> {
+        let mut indexed = self.write();
+        let Some((_, vector_ids)) = indexed.documents.remove(id) else {
+            return Err(RagError::NotFound(id.to_string()));
+        };
+        for vector_id in vector_ids {
+            indexed.index.remove(vector_id);
+            indexed.chunks.remove(&vector_id);
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves the chunks most similar to a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The text of the query.
+    /// * `top_k` - The maximum number of chunks to return.
+    ///
+    /// # Returns
+    ///
+    /// The chunks, most similar first, and the number of tokens of the query.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query cannot be embedded.
+    pub fn retrieve(
+        &self,
+        query: &str,
+        top_k: usize,
+    ) -> Result<(Vec<RetrievedChunk>, usize), RagError> {
+        let embeddings = self
+            .embedder
+            .embed(&[query.to_string()])
+            .map_err(RagError::Embedding)?;
+        let Some(vector) = embeddings.vectors.first() else {
+            return Ok((Vec::new(), embeddings.prompt_tokens));
+        };
+
+        let indexed = self.read();
+        let chunks = indexed
+            .index
+            .search(vector, top_k)
+            .into_iter()
+            .filter_map(|neighbour| {
+                let chunk = indexed.chunks.get(&neighbour.id)?;
+                let (document, _) = indexed.documents.get(&chunk.document_id)?;
+                Some(RetrievedChunk {
+                    document_id: chunk.document_id.clone(),
+                    chunk_index: chunk.index,
+                    text: chunk.text.clone(),
+                    score: neighbour.score,
+                    metadata: document.metadata.clone(),
+                })
+            })
+            .collect();
+
+        Ok((chunks, embeddings.prompt_tokens))
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Indexed> {
+        self.indexed.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Indexed> {
+        self.indexed.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Splits a text into chunks of up to `size` characters, consecutive chunks sharing
+/// `overlap` characters.
+///
+/// Chunks end at the last whitespace of their window when there is one in its second
+/// half, so that words are not cut in two.
+fn chunk_text(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let size = size.max(1);
+    let overlap = overlap.min(size / 2);
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = (start + size).min(chars.len());
+        if end < chars.len() {
+            if let Some(space) = chars[start + size / 2..end]
+                .iter()
+                .rposition(|c| c.is_whitespace())
+            {
+                end = start + size / 2 + space + 1;
+            }
+        }
+
+        let chunk: String = chars[start..end].iter().collect();
+        let chunk = chunk.trim();
+        if !chunk.is_empty() {
+            chunks.push(chunk.to_string());
+        }
+        if end == chars.len() {
+            break;
+        }
+        start = (end - overlap).max(start + 1);
+    }
+
+    chunks
+}
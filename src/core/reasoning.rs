@@ -0,0 +1,196 @@
+use crate::config::{ReasoningMode, ReasoningSettings};
+
+/// The text of a generation split into its reasoning and its content.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReasoningDelta {
+    pub reasoning: String,
+    pub content: String,
+}
+
+/// Separates the reasoning trace of a thinking model, such as `<think>...</think>`, from
+/// its answer as the text is generated.
+///
+/// A tag split over several deltas is held back until it is complete, so that neither
+/// the reasoning nor the content ever shows part of a tag.
+pub struct ReasoningParser {
+    start: String,
+    end: String,
+    strip: bool,
+    in_reasoning: bool,
+    /// Whether the whitespace following the end tag is still to be skipped.
+    trim_content: bool,
+    /// The text that may be the beginning of a tag.
+    pending: String,
+}
+
+impl ReasoningParser {
+    /// Creates the parser of a generation.
+    ///
+    /// Models whose chat template opens the reasoning in the prompt, as DeepSeek-R1 does,
+    /// generate the reasoning without its start tag, so the parser starts in the reasoning
+    /// when the prompt ends with the start tag.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The tags of the reasoning of the model and how it is returned.
+    /// * `prompt` - The rendered prompt of the generation.
+    ///
+    /// # Returns
+    ///
+    /// The parser, `None` when the reasoning is kept in the content.
+    pub fn new(settings: &ReasoningSettings, prompt: &str) -> Option<Self> {
+        if settings.mode == ReasoningMode::Keep
+            || settings.start.is_empty()
+            || settings.end.is_empty()
+        {
+            return None;
+        }
+
+        Some(Self {
+            start: settings.start.clone(),
+            end: settings.end.clone(),
+            strip: settings.mode == ReasoningMode::Strip,
+            in_reasoning: prompt.trim_end().ends_with(&settings.start),
+            trim_content: false,
+            pending: String::new(),
+        })
+    }
+
+    /// Splits the next delta of the generated text.
+    pub fn push(&mut self, delta: &str) -> ReasoningDelta {
+        self.pending.push_str(delta);
+        let mut split = ReasoningDelta::default();
+
+        loop {
+            let tag = if self.in_reasoning {
+                &self.end
+            } else {
+                &self.start
+            };
+            match self.pending.find(tag.as_str()) {
+                Some(position) => {
+                    let text: String = self.pending.drain(..position + tag.len()).collect();
+                    self.emit(&mut split, &text[..position]);
+                    if self.in_reasoning {
+                        self.trim_content = true;
+                    }
+                    self.in_reasoning = !self.in_reasoning;
+                }
+                None => {
+                    let held = partial_tag(&self.pending, tag);
+                    let text: String = self.pending.drain(..self.pending.len() - held).collect();
+                    self.emit(&mut split, &text);
+                    return split;
+                }
+            }
+        }
+    }
+
+    /// Returns the text held back at the end of the generation.
+    pub fn finish(&mut self) -> ReasoningDelta {
+        let mut split = ReasoningDelta::default();
+        let text = std::mem::take(&mut self.pending);
+        self.emit(&mut split, &text);
+        split
+    }
+
+    /// Splits a whole generated text.
+    ///
+    /// # Returns
+    ///
+    /// The reasoning, `None` when there is none or it is stripped, and the content.
+    pub fn split(mut self, text: &str) -> (Option<String>, String) {
+        let mut split = self.push(text);
+        let rest = self.finish();
+        split.reasoning.push_str(&rest.reasoning);
+        split.content.push_str(&rest.content);
+
+        let reasoning = split.reasoning.trim();
+        let reasoning = (!reasoning.is_empty()).then(|| reasoning.to_string());
+        (reasoning, split.content)
+    }
+
+    fn emit(&mut self, split: &mut ReasoningDelta, mut text: &str) {
+        if self.in_reasoning {
+            if !self.strip {
+                split.reasoning.push_str(text);
+            }
+            return;
+        }
+        if self.trim_content {
+            text = text.trim_start();
+            self.trim_content = text.is_empty();
+        }
+        split.content.push_str(text);
+    }
+}
+
+/// The length of the longest end of `text` that is the beginning of `tag`.
+fn partial_tag(text: &str, tag: &str) -> usize {
+    (1..tag.len().min(text.len() + 1))
+        .rev()
+        .find(|&len| {
+            text.is_char_boundary(text.len() - len) && tag.starts_with(&text[text.len() - len..])
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parser(mode: ReasoningMode, prompt: &str) -> ReasoningParser {
+        let settings = ReasoningSettings {
+            mode,
+            ..ReasoningSettings::default()
+        };
+        ReasoningParser::new(&settings, prompt).unwrap()
+    }
+
+    #[test]
+    fn separates_split_tags() {
+        let mut parser = parser(ReasoningMode::Separate, "user: hi");
+        let mut reasoning = String::new();
+        let mut content = String::new();
+        for delta in [
+            "<th",
+            "ink>Let me",
+            " think.</",
+            "think>\n\nHello",
+            " <",
+            "b>",
+        ] {
+            let split = parser.push(delta);
+            reasoning.push_str(&split.reasoning);
+            content.push_str(&split.content);
+        }
+        content.push_str(&parser.finish().content);
+
+        assert_eq!(reasoning, "Let me think.");
+        assert_eq!(content, "Hello <b>");
+    }
+
+    #[test]
+    fn starts_in_reasoning_opened_by_the_prompt() {
+        let parser = parser(ReasoningMode::Separate, "<|Assistant|><think>\n");
+        assert_eq!(
+            parser.split("Two plus two.</think>4"),
+            (Some("Two plus two.".to_string()), "4".to_string())
+        );
+    }
+
+    #[test]
+    fn strips_reasoning() {
+        let parser = parser(ReasoningMode::Strip, "");
+        assert_eq!(
+            parser.split("<think>secret</think> answer"),
+            (None, "answer".to_string())
+        );
+        assert!(ReasoningParser::new(&ReasoningSettings::default(), "").is_some());
+        let keep = ReasoningSettings {
+            mode: ReasoningMode::Keep,
+            ..ReasoningSettings::default()
+        };
+        assert!(ReasoningParser::new(&keep, "").is_none());
+    }
+}
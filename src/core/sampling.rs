@@ -0,0 +1,663 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use candle_core::{DType, Device, Tensor, D};
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+use tracing::error;
+
+/// The sampling parameters of a generation request.
+///
+/// Every parameter is optional: values left unset fall back to the defaults configured
+/// for the served model, and all values are clamped to its configured limits when the
+/// `TextGeneration` is created.
+///
+/// # Example
+///
+/// ```rust
+/// use synap_forge_llm::core::sampling::SamplingParams;
+///
+/// let params = SamplingParams::default()
+///     .with_temperature(Some(0.7))
+///     .with_max_tokens(Some(256));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SamplingParams {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    /// The probability relative to the most likely token below which tokens are never
+    /// sampled.
+    pub min_p: Option<f64>,
+    pub max_tokens: Option<i32>,
+    pub seed: Option<u64>,
+    pub repeat_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+}
+
+impl SamplingParams {
+    /// Sets the sampling temperature, `0` selects greedy decoding.
+    pub fn with_temperature(mut self, temperature: Option<f64>) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Sets the nucleus sampling probability.
+    pub fn with_top_p(mut self, top_p: Option<f64>) -> Self {
+        self.top_p = top_p;
+        self
+    }
+
+    /// Sets the number of most likely tokens to sample from.
+    pub fn with_top_k(mut self, top_k: Option<usize>) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Sets the minimum probability of a token relative to the most likely one.
+    pub fn with_min_p(mut self, min_p: Option<f64>) -> Self {
+        self.min_p = min_p;
+        self
+    }
+
+    /// Sets the maximum number of tokens to generate.
+    pub fn with_max_tokens(mut self, max_tokens: Option<i32>) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Sets the seed of the sampling RNG.
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets the penalty of the recently generated tokens, `1` disables it.
+    pub fn with_repeat_penalty(mut self, repeat_penalty: Option<f32>) -> Self {
+        self.repeat_penalty = repeat_penalty;
+        self
+    }
+
+    /// Sets the penalty of a token proportional to the number of times it was generated.
+    pub fn with_frequency_penalty(mut self, frequency_penalty: Option<f32>) -> Self {
+        self.frequency_penalty = frequency_penalty;
+        self
+    }
+
+    /// Sets the penalty of a token once it was generated.
+    pub fn with_presence_penalty(mut self, presence_penalty: Option<f32>) -> Self {
+        self.presence_penalty = presence_penalty;
+        self
+    }
+}
+
+/// Picks the tokens of a generation from the logits of the model.
+///
+/// A temperature of `0` or none selects greedy decoding, otherwise the token is drawn
+/// with the seeded RNG among the `top_k` most likely tokens, then among the smallest set
+/// of them whose probability exceeds `top_p`. With `min_p`, the tokens less likely than
+/// `min_p` times the most likely one are dropped first.
+///
+/// Every generation owns its sampler, and so the state of its RNG, which is neither
+/// shared nor cloned: seeded requests draw the same tokens whether they run alone or
+/// concurrently with others. The RNG only advances once the logits were checked and a
+/// token is drawn, so a failed draw leaves it as it was for the retried step.
+pub(crate) struct Sampler {
+    logits_processor: LogitsProcessor,
+    /// Whether the sampling is greedy, so tokens are picked by an argmax on the device.
+    greedy: bool,
+    temperature: f64,
+    /// The number of most likely tokens sampled from, truncated on the device when set.
+    top_k: Option<usize>,
+    min_p: Option<f64>,
+}
+
+impl Sampler {
+    /// Creates a sampler.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed value for the random number generator.
+    /// * `temperature` - Optional temperature value for sampling.
+    /// * `top_p` - Optional top-p value for nucleus sampling.
+    /// * `top_k` - Optional top-k value for nucleus sampling.
+    pub(crate) fn new(
+        seed: u64,
+        temperature: Option<f64>,
+        top_p: Option<f64>,
+        top_k: Option<usize>,
+    ) -> Self {
+        let temperature = temperature.unwrap_or(0.);
+        let greedy = temperature <= 0.;
+        let sampling = if greedy {
+            Sampling::ArgMax
+        } else {
+            match (top_k, top_p) {
+                (None, None) => Sampling::All { temperature },
+                (Some(k), None) => Sampling::TopK { k, temperature },
+                (None, Some(p)) => Sampling::TopP { p, temperature },
+                (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+            }
+        };
+
+        Self {
+            logits_processor: LogitsProcessor::from_sampling(seed, sampling),
+            greedy,
+            temperature,
+            top_k: top_k.filter(|&k| !greedy && k <= MAX_DEVICE_TOP_K),
+            min_p: None,
+        }
+    }
+
+    /// Drops the tokens less likely than `min_p` times the most likely one, `0` or
+    /// `None` keeps them all. Greedy sampling ignores it.
+    pub(crate) fn with_min_p(mut self, min_p: Option<f64>) -> Self {
+        self.min_p = min_p.filter(|&p| p > 0. && !self.greedy);
+        self
+    }
+
+    /// Picks the next token from the logits of the last position.
+    ///
+    /// Greedy sampling without penalized tokens takes the argmax on the device, so only
+    /// the token id is copied to the host instead of the logits over the vocabulary.
+    /// Likewise top-k sampling without penalized tokens only copies the `top_k` largest
+    /// logits, truncated on the device.
+    ///
+    /// # Arguments
+    ///
+    /// * `logits` - The logits over the vocabulary, of shape `(vocab)`.
+    /// * `penalties` - The penalties of the tokens seen so far.
+    /// * `position` - The position in the context of the token the logits were computed for.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NonFiniteLogits` if a logit is NaN or infinite.
+    pub(crate) fn sample(
+        &mut self,
+        logits: Tensor,
+        penalties: &Penalties,
+        position: usize,
+    ) -> anyhow::Result<u32> {
+        check_logits(&logits, position)?;
+        if self.greedy && penalties.is_empty() {
+            return Ok(logits.argmax(D::Minus1)?.to_scalar::<u32>()?);
+        }
+        if let Some(k) = self.top_k.filter(|_| penalties.is_empty()) {
+            let (values, ids) = top_k_on_device(&logits, k)?;
+            let candidates = Tensor::from_vec(values, ids.len(), &Device::Cpu)?;
+            let candidates = self.drop_unlikely(candidates)?;
+            let index = self.logits_processor.sample(&candidates)?;
+            return Ok(ids[index as usize]);
+        }
+        let logits = self.drop_unlikely(penalties.apply(logits)?)?;
+        Ok(self.logits_processor.sample(&logits)?)
+    }
+
+    /// Masks the logits of the tokens below the `min_p` threshold.
+    ///
+    /// A token is `p_i / p_max = exp((l_i - l_max) / T)` times as likely as the most likely
+    /// one, so it is dropped when its logit is below `l_max + T * ln(min_p)`.
+    fn drop_unlikely(&self, logits: Tensor) -> candle_core::Result<Tensor> {
+        let Some(min_p) = self.min_p else {
+            return Ok(logits);
+        };
+        let mut values = logits.to_dtype(DType::F32)?.to_vec1::<f32>()?;
+        let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let threshold = max + (self.temperature * min_p.ln()) as f32;
+        for value in values.iter_mut().filter(|value| **value < threshold) {
+            *value = f32::NEG_INFINITY;
+        }
+        Tensor::from_vec(values, logits.shape(), logits.device())
+    }
+}
+
+/// The penalties lowering the logits of the tokens a generation already saw.
+///
+/// The repeat penalty divides the logits of the last tokens of the context, prompt
+/// included. The frequency and presence penalties follow the OpenAI semantics and only
+/// count generated tokens: the logit of a token is lowered by the frequency penalty
+/// times the number of times it was generated, plus the presence penalty once it was
+/// generated.
+///
+/// The counts are updated as tokens are generated rather than rebuilt from the whole
+/// context at every step, so applying the penalties only costs a pass over the distinct
+/// penalized tokens.
+#[derive(Clone, Debug)]
+pub(crate) struct Penalties {
+    repeat_penalty: f32,
+    size: usize,
+    window: VecDeque<u32>,
+    /// The number of occurrences of every token of the repeat window.
+    window_counts: HashMap<u32, usize>,
+    frequency_penalty: f32,
+    presence_penalty: f32,
+    /// The number of times every token was generated.
+    generated: HashMap<u32, usize>,
+}
+
+impl Penalties {
+    /// Creates the penalties of a generation that did not see any token yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `repeat_penalty` - The repeat penalty, `1` disables it.
+    /// * `size` - The number of last tokens that the repeat penalty applies to.
+    /// * `frequency_penalty` - Subtracted from a logit for every time its token was generated.
+    /// * `presence_penalty` - Subtracted from a logit once its token was generated.
+    pub(crate) fn new(
+        repeat_penalty: f32,
+        size: usize,
+        frequency_penalty: f32,
+        presence_penalty: f32,
+    ) -> Self {
+        Self {
+            repeat_penalty,
+            size,
+            window: VecDeque::with_capacity(size),
+            window_counts: HashMap::with_capacity(size),
+            frequency_penalty,
+            presence_penalty,
+            generated: HashMap::new(),
+        }
+    }
+
+    /// Adds the tokens of the prompt to the repeat window, dropping the oldest ones beyond
+    /// its size.
+    pub(crate) fn extend(&mut self, tokens: &[u32]) {
+        let start = tokens.len().saturating_sub(self.size);
+        for &token in &tokens[start..] {
+            self.push_window(token);
+        }
+    }
+
+    /// Adds a generated token.
+    pub(crate) fn push(&mut self, token: u32) {
+        self.push_window(token);
+        if self.frequency_penalty != 0. || self.presence_penalty != 0. {
+            *self.generated.entry(token).or_default() += 1;
+        }
+    }
+
+    /// Adds a token to the repeat window, dropping the oldest one if it is full.
+    fn push_window(&mut self, token: u32) {
+        if self.size == 0 || self.repeat_penalty == 1. {
+            return;
+        }
+        if self.window.len() == self.size {
+            if let Some(oldest) = self.window.pop_front() {
+                if let Some(count) = self.window_counts.get_mut(&oldest) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.window_counts.remove(&oldest);
+                    }
+                }
+            }
+        }
+        self.window.push_back(token);
+        *self.window_counts.entry(token).or_default() += 1;
+    }
+
+    /// Whether no logit is currently penalized.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.window_counts.is_empty() && self.generated.is_empty()
+    }
+
+    /// Penalizes the logits of the tokens already seen.
+    ///
+    /// # Arguments
+    ///
+    /// * `logits` - The logits over the vocabulary, of shape `(vocab)`.
+    ///
+    /// # Returns
+    ///
+    /// The penalized logits in `f32`, or `logits` untouched when there is nothing to
+    /// penalize.
+    pub(crate) fn apply(&self, logits: Tensor) -> candle_core::Result<Tensor> {
+        if self.is_empty() {
+            return Ok(logits);
+        }
+        let mut values = logits.to_dtype(DType::F32)?.to_vec1::<f32>()?;
+        for &token in self.window_counts.keys() {
+            if let Some(value) = values.get_mut(token as usize) {
+                if *value >= 0. {
+                    *value /= self.repeat_penalty;
+                } else {
+                    *value *= self.repeat_penalty;
+                }
+            }
+        }
+        for (&token, &count) in &self.generated {
+            if let Some(value) = values.get_mut(token as usize) {
+                *value -= count as f32 * self.frequency_penalty + self.presence_penalty;
+            }
+        }
+        Tensor::from_vec(values, logits.shape(), logits.device())
+    }
+}
+
+/// The number of logits sorted together by [`top_k_on_device`], small enough for the
+/// sort kernels of every backend.
+const TOP_K_CHUNK: usize = 1024;
+
+/// The largest `top_k` truncated on the device. Every pass over the chunks keeps at most
+/// half of each chunk, so the candidates shrink until they fit in a single chunk.
+pub(crate) const MAX_DEVICE_TOP_K: usize = TOP_K_CHUNK / 2;
+
+/// Keeps the `k` largest logits without copying the whole vocabulary from the device.
+///
+/// The logits are split in chunks sorted on the device, the `k` largest logits of every
+/// chunk are kept, and the pass is repeated over the kept logits until they fit in a
+/// single chunk. Only the `k` largest logits and their token ids are then copied to
+/// the host.
+///
+/// # Arguments
+///
+/// * `logits` - The logits over the vocabulary, of shape `(vocab)`.
+/// * `k` - The number of logits to keep, at most [`MAX_DEVICE_TOP_K`].
+///
+/// # Returns
+///
+/// The `k` largest logits in decreasing order, with their token ids.
+pub(crate) fn top_k_on_device(
+    logits: &Tensor,
+    k: usize,
+) -> candle_core::Result<(Vec<f32>, Vec<u32>)> {
+    let values = logits.to_dtype(DType::F32)?;
+    let ids = Tensor::arange(0u32, values.dim(0)? as u32, values.device())?;
+    let (values, ids) = top_k_chunks(values, ids, k.min(MAX_DEVICE_TOP_K))?;
+
+    Ok((values.to_vec1::<f32>()?, ids.to_vec1::<u32>()?))
+}
+
+fn top_k_chunks(values: Tensor, ids: Tensor, k: usize) -> candle_core::Result<(Tensor, Tensor)> {
+    let len = values.dim(0)?;
+    let rows = len.div_ceil(TOP_K_CHUNK);
+    let (values, ids) = if rows == 1 {
+        (values.unsqueeze(0)?, ids.unsqueeze(0)?)
+    } else {
+        // The padding logits are never among the largest ones
+        let padding = rows * TOP_K_CHUNK - len;
+        let (values, ids) = if padding > 0 {
+            let device = values.device().clone();
+            (
+                Tensor::cat(
+                    &[values, Tensor::full(f32::NEG_INFINITY, padding, &device)?],
+                    0,
+                )?,
+                Tensor::cat(&[ids, Tensor::zeros(padding, DType::U32, &device)?], 0)?,
+            )
+        } else {
+            (values, ids)
+        };
+        (
+            values.reshape((rows, TOP_K_CHUNK))?,
+            ids.reshape((rows, TOP_K_CHUNK))?,
+        )
+    };
+
+    let kept = k.min(values.dim(1)?);
+    let order = values
+        .arg_sort_last_dim(false)?
+        .narrow(1, 0, kept)?
+        .contiguous()?;
+    let values = values.gather(&order, 1)?.flatten_all()?;
+    let ids = ids.gather(&order, 1)?.flatten_all()?;
+    if rows == 1 {
+        Ok((values, ids))
+    } else {
+        top_k_chunks(values, ids, k)
+    }
+}
+
+/// The error returned when the model produces NaN or infinite logits.
+///
+/// This happens when activations overflow the range of the dtype the weights were
+/// loaded in, typically `f16` on some prompts. Sampling from such logits only yields
+/// garbage tokens, so the generation is aborted instead.
+#[derive(Debug, Clone)]
+pub struct NonFiniteLogits {
+    /// The position in the context of the token the logits were computed for.
+    pub position: usize,
+    pub nan: usize,
+    pub infinite: usize,
+}
+
+impl fmt::Display for NonFiniteLogits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The model produced {} NaN and {} infinite logits at position {}, the activations \
+             overflowed the model dtype; serving the model in bf16 or f32 avoids this",
+            self.nan, self.infinite, self.position
+        )
+    }
+}
+
+impl std::error::Error for NonFiniteLogits {}
+
+/// Fails if some of the logits are NaN or infinite.
+///
+/// Only the sum of the logits is copied from the device on every step, as any NaN or
+/// infinite logit makes it non-finite. The logits are only inspected in full to log
+/// diagnostics once a problem was detected.
+///
+/// # Arguments
+///
+/// * `logits` - The logits over the vocabulary, of shape `(vocab)`.
+/// * `position` - The position in the context of the token the logits were computed for.
+///
+/// # Errors
+///
+/// Returns `NonFiniteLogits` if a logit is NaN or infinite, or an error if the logits
+/// cannot be read from the device.
+pub(crate) fn check_logits(logits: &Tensor, position: usize) -> anyhow::Result<()> {
+    let logits = logits.to_dtype(DType::F32)?;
+    if logits.sum_all()?.to_scalar::<f32>()?.is_finite() {
+        return Ok(());
+    }
+
+    let values = logits.to_vec1::<f32>()?;
+    let nan = values.iter().filter(|value| value.is_nan()).count();
+    let infinite = values.iter().filter(|value| value.is_infinite()).count();
+    if nan == 0 && infinite == 0 {
+        // The finite logits only overflowed when summed
+        return Ok(());
+    }
+    let finite = values.iter().filter(|value| value.is_finite());
+    let max = finite.clone().fold(f32::MIN, |max, &value| max.max(value));
+    let min = finite.fold(f32::MAX, |min, &value| min.min(value));
+    error!(
+        "Non-finite logits at position {position}: {nan} NaN, {infinite} infinite, \
+         {} finite in [{min}, {max}] out of {}",
+        values.len() - nan - infinite,
+        values.len()
+    );
+
+    Err(NonFiniteLogits {
+        position,
+        nan,
+        infinite,
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGITS: [f32; 6] = [1.0, 3.0, -2.0, 2.5, 0.5, 2.9];
+
+    fn logits(values: &[f32]) -> Tensor {
+        Tensor::new(values, &Device::Cpu).expect("the logits tensor is created")
+    }
+
+    fn no_penalties() -> Penalties {
+        Penalties::new(1., 0, 0., 0.)
+    }
+
+    fn draws(sampler: &mut Sampler, count: usize) -> Vec<u32> {
+        (0..count)
+            .map(|_| {
+                sampler
+                    .sample(logits(&LOGITS), &no_penalties(), 0)
+                    .expect("a token is sampled")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn greedy_sampling_picks_the_largest_logit() {
+        for temperature in [None, Some(0.0)] {
+            let mut sampler = Sampler::new(7, temperature, Some(0.5), Some(3));
+            assert_eq!(draws(&mut sampler, 4), vec![1, 1, 1, 1]);
+        }
+    }
+
+    #[test]
+    fn sampling_is_reproducible_for_a_seed() {
+        let mut first = Sampler::new(42, Some(1.0), None, None);
+        let mut second = Sampler::new(42, Some(1.0), None, None);
+        assert_eq!(draws(&mut first, 64), draws(&mut second, 64));
+    }
+
+    #[test]
+    fn low_temperature_converges_to_greedy_sampling() {
+        let mut sampler = Sampler::new(42, Some(1e-3), None, None);
+        assert!(draws(&mut sampler, 64).iter().all(|&token| token == 1));
+    }
+
+    #[test]
+    fn top_k_only_samples_the_most_likely_tokens() {
+        let mut sampler = Sampler::new(42, Some(2.0), None, Some(2));
+        let tokens = draws(&mut sampler, 200);
+        assert!(tokens.iter().all(|&token| token == 1 || token == 5));
+        assert!(tokens.contains(&1) && tokens.contains(&5));
+    }
+
+    #[test]
+    fn top_p_only_samples_the_nucleus() {
+        // Token 1 alone holds more than 10% of the probability
+        let mut sampler = Sampler::new(42, Some(1.0), Some(0.1), None);
+        assert!(draws(&mut sampler, 64).iter().all(|&token| token == 1));
+    }
+
+    #[test]
+    fn min_p_drops_the_unlikely_tokens() {
+        // Only token 5 is at least 80% as likely as token 1 at temperature 1, token 3 is 61%
+        let mut sampler = Sampler::new(42, Some(1.0), None, None).with_min_p(Some(0.8));
+        let tokens = draws(&mut sampler, 200);
+        assert!(tokens.iter().all(|&token| token == 1 || token == 5));
+        assert!(tokens.contains(&1) && tokens.contains(&5));
+    }
+
+    #[test]
+    fn penalties_lower_the_seen_tokens() {
+        let mut penalties = Penalties::new(2.0, 3, 0.5, 0.25);
+        penalties.extend(&[0, 1]);
+        penalties.push(3);
+        penalties.push(3);
+
+        // Token 0 left the repeat window, tokens 1 and 3 are divided by the repeat penalty
+        // and token 3, generated twice, also loses 2 * 0.5 + 0.25
+        let penalized = penalties.apply(logits(&LOGITS)).unwrap();
+        assert_eq!(
+            penalized.to_vec1::<f32>().unwrap(),
+            vec![1.0, 1.5, -2.0, 0.0, 0.5, 2.9]
+        );
+    }
+
+    #[test]
+    fn repeat_penalty_multiplies_negative_logits() {
+        let mut penalties = Penalties::new(2.0, 4, 0., 0.);
+        penalties.extend(&[2]);
+
+        let penalized = penalties.apply(logits(&LOGITS)).unwrap();
+        assert_eq!(penalized.to_vec1::<f32>().unwrap()[2], -4.0);
+    }
+
+    #[test]
+    fn penalties_steer_greedy_sampling() {
+        let mut sampler = Sampler::new(7, None, None, None);
+        let mut penalties = Penalties::new(2.0, 8, 0., 0.);
+        penalties.push(1);
+
+        let token = sampler.sample(logits(&LOGITS), &penalties, 0).unwrap();
+        assert_eq!(token, 5);
+    }
+
+    #[test]
+    fn disabled_penalties_penalize_nothing() {
+        let mut penalties = Penalties::new(1.0, 64, 0., 0.);
+        penalties.extend(&[1, 2, 3]);
+        penalties.push(1);
+        assert!(penalties.is_empty());
+    }
+
+    #[test]
+    fn device_top_k_matches_a_full_sort() {
+        // A permutation of the values, spread over several chunks
+        let values: Vec<f32> = (0..5000u32)
+            .map(|i| ((i * 7919) % 5000) as f32 / 10.0)
+            .collect();
+        let (top, ids) = top_k_on_device(&logits(&values), 40).unwrap();
+
+        let mut sorted = values.clone();
+        sorted.sort_by(|a, b| b.total_cmp(a));
+        assert_eq!(top, sorted[..40]);
+        for (value, id) in top.iter().zip(&ids) {
+            assert_eq!(values[*id as usize], *value);
+        }
+    }
+
+    #[test]
+    fn concurrent_samplers_do_not_perturb_each_other() {
+        let seeds = [1, 2, 3, 42, 42, 7];
+        let sampler = |seed| Sampler::new(seed, Some(1.0), Some(0.95), None);
+        let alone: Vec<Vec<u32>> = seeds
+            .iter()
+            .map(|&seed| draws(&mut sampler(seed), 256))
+            .collect();
+
+        let barrier = std::sync::Barrier::new(seeds.len());
+        let concurrent: Vec<Vec<u32>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = seeds
+                .iter()
+                .map(|&seed| {
+                    let barrier = &barrier;
+                    scope.spawn(move || {
+                        let mut sampler = sampler(seed);
+                        barrier.wait();
+                        draws(&mut sampler, 256)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("the sampling thread ends"))
+                .collect()
+        });
+
+        assert_eq!(concurrent, alone);
+    }
+
+    #[test]
+    fn failed_sampling_leaves_the_rng_untouched() {
+        let mut retried = Sampler::new(42, Some(1.0), None, None);
+        let non_finite = logits(&[1.0, f32::NAN, 2.0, 0.5, 0.1, 3.0]);
+        assert!(retried.sample(non_finite, &no_penalties(), 0).is_err());
+
+        let mut fresh = Sampler::new(42, Some(1.0), None, None);
+        assert_eq!(draws(&mut retried, 64), draws(&mut fresh, 64));
+    }
+
+    #[test]
+    fn non_finite_logits_are_rejected() {
+        assert!(check_logits(&logits(&LOGITS), 3).is_ok());
+
+        let err = check_logits(&logits(&[1.0, f32::NAN, f32::INFINITY, 2.0]), 3).unwrap_err();
+        let err = err
+            .downcast_ref::<NonFiniteLogits>()
+            .expect("the logits are reported as non-finite");
+        assert_eq!((err.position, err.nan, err.infinite), (3, 1, 1));
+    }
+}
@@ -0,0 +1,78 @@
+use candle_core::{Tensor, D};
+
+/// The log-probabilities the model assigns to the tokens of a text.
+pub struct PromptScore {
+    /// The tokens of the text, the continuation included.
+    pub tokens: Vec<u32>,
+    /// The index in `tokens` of the first scored token: the first token of the continuation,
+    /// or the second token without one.
+    pub scored_from: usize,
+    /// The score of every token but the first, which has no context to be predicted from.
+    pub positions: Vec<TokenScore>,
+}
+
+/// The log-probability of one token given the tokens before it.
+pub struct TokenScore {
+    pub logprob: f32,
+    /// The most likely tokens at this position with their log-probabilities, most likely first.
+    pub top_logprobs: Vec<(u32, f32)>,
+}
+
+impl TokenScore {
+    /// Scores a token from the logits predicted at the position before it.
+    ///
+    /// # Arguments
+    ///
+    /// * `logits` - The logits over the vocabulary, of shape `(vocab)`.
+    /// * `token` - The actual token at this position.
+    /// * `top_logprobs` - The number of most likely tokens to return.
+    pub fn from_logits(
+        logits: &Tensor,
+        token: u32,
+        top_logprobs: usize,
+    ) -> candle_core::Result<Self> {
+        let logprobs = candle_nn::ops::log_softmax(logits, D::Minus1)?.to_vec1::<f32>()?;
+        let logprob = logprobs
+            .get(token as usize)
+            .copied()
+            .unwrap_or(f32::NEG_INFINITY);
+
+        let mut top: Vec<(u32, f32)> = Vec::with_capacity(top_logprobs + 1);
+        if top_logprobs > 0 {
+            for (id, &lp) in logprobs.iter().enumerate() {
+                if top.len() < top_logprobs || lp > top[top.len() - 1].1 {
+                    let at = top.partition_point(|&(_, other)| other >= lp);
+                    top.insert(at, (id as u32, lp));
+                    top.truncate(top_logprobs);
+                }
+            }
+        }
+
+        Ok(Self {
+            logprob,
+            top_logprobs: top,
+        })
+    }
+}
+
+impl PromptScore {
+    /// The scores of the continuation tokens, or of the whole text without a continuation.
+    pub fn scored(&self) -> &[TokenScore] {
+        let start = self.scored_from.saturating_sub(1);
+        &self.positions[start.min(self.positions.len())..]
+    }
+
+    /// The log-probability of the scored tokens together.
+    pub fn total_logprob(&self) -> f32 {
+        self.scored().iter().map(|score| score.logprob).sum()
+    }
+
+    /// The perplexity of the model over the scored tokens.
+    pub fn perplexity(&self) -> f32 {
+        let scored = self.scored();
+        if scored.is_empty() {
+            return 1.0;
+        }
+        (-self.total_logprob() / scored.len() as f32).exp()
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use tracing::info;
+
+use crate::core::generator::TextGeneration;
+use crate::core::image_embeddings::EmbeddingItem;
+use crate::core::sampling::SamplingParams;
+use crate::openai::http_entities::AppState;
+
+/// The prompt of the canned generation.
+const GENERATION_PROMPT: &str = "The capital of France is";
+
+/// The number of tokens generated by the canned generation.
+const GENERATION_TOKENS: i32 = 16;
+
+/// The text of the canned embeddings.
+const EMBEDDING_TEXT: &str = "The quick brown fox jumps over the lazy dog.";
+
+/// The outcome of one check of the self-test.
+#[derive(Debug)]
+pub enum CheckOutcome {
+    Passed(String),
+    Failed(String),
+    /// The check does not apply, e.g. embeddings are not enabled.
+    Skipped(String),
+}
+
+/// A check of the self-test.
+#[derive(Debug)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+    pub elapsed: Duration,
+}
+
+/// The results of the self-test.
+#[derive(Debug, Default)]
+pub struct SelfTestReport {
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    /// Whether no check failed.
+    pub fn passed(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|check| matches!(check.outcome, CheckOutcome::Failed(_)))
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let (status, detail) = match &check.outcome {
+                CheckOutcome::Passed(detail) => ("ok", detail),
+                CheckOutcome::Failed(detail) => ("FAILED", detail),
+                CheckOutcome::Skipped(detail) => ("skipped", detail),
+            };
+            writeln!(
+                f,
+                "{:<16} {:<8} {:>8.2?}  {}",
+                check.name, status, check.elapsed, detail
+            )?;
+        }
+        writeln!(
+            f,
+            "self-test {}",
+            if self.passed() { "passed" } else { "FAILED" }
+        )
+    }
+}
+
+/// Checks that the loaded models produce sensible outputs.
+///
+/// Runs a short greedy generation, scores it, and embeds a text with the embedding
+/// models that are enabled. The outputs must be non-empty and every number finite, which
+/// catches corrupted weights, a wrong dtype for the device or a broken tokenizer before
+/// the server takes traffic.
+///
+/// # Arguments
+///
+/// * `state` - The application state holding the loaded models.
+///
+/// # Returns
+///
+/// The outcome of every check, failed or not.
+pub fn run_self_test(state: &AppState) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+
+    let (generation, generated) = timed("generation", || {
+        let params = SamplingParams::default()
+            .with_temperature(Some(0.0))
+            .with_max_tokens(Some(GENERATION_TOKENS));
+        let (text, _) = TextGeneration::from_state(state.clone(), &params)?
+            .generate(GENERATION_PROMPT.to_string())?;
+        if text.trim().is_empty() {
+            bail!("the model generated no text");
+        }
+        Ok((format!("{text:?}"), Some(text)))
+    });
+    report.checks.push(generation);
+
+    let logprobs = match generated {
+        Some(text) => {
+            timed("logprobs", || {
+                let score = TextGeneration::from_state(state.clone(), &SamplingParams::default())?
+                    .score(GENERATION_PROMPT.to_string(), Some(&text), 0)?;
+                let logprob = score.total_logprob();
+                if score.scored().is_empty() || !logprob.is_finite() {
+                    bail!("the log-probability of the generated text is {logprob}");
+                }
+                Ok((format!("total log-probability {logprob:.4}"), ()))
+            })
+            .0
+        }
+        None => skipped("logprobs", "no generated text to score"),
+    };
+    report.checks.push(logprobs);
+
+    let embedding = match &state.embedder {
+        Some(embedder) => {
+            timed("embedding", || {
+                let embeddings = embedder.embed(&[EMBEDDING_TEXT.to_string()])?;
+                check_vectors(&embeddings.vectors).map(|detail| (detail, ()))
+            })
+            .0
+        }
+        None => skipped("embedding", "embeddings.model is not set"),
+    };
+    report.checks.push(embedding);
+
+    let image_embedding = match &state.image_embedder {
+        Some(embedder) => {
+            timed("image embedding", || {
+                let items = [EmbeddingItem::Text(EMBEDDING_TEXT.to_string())];
+                let embeddings = embedder.embed(&items, true)?;
+                check_vectors(&embeddings.vectors).map(|detail| (detail, ()))
+            })
+            .0
+        }
+        None => skipped("image embedding", "embeddings.image_model is not set"),
+    };
+    report.checks.push(image_embedding);
+
+    report
+}
+
+/// Runs a check, turning its error into a failed outcome.
+fn timed<T: Default>(
+    name: &'static str,
+    check: impl FnOnce() -> anyhow::Result<(String, T)>,
+) -> (SelfTestCheck, T) {
+    info!("Self-test: {name}");
+    let started = Instant::now();
+    let (outcome, value) = match check() {
+        Ok((detail, value)) => (CheckOutcome::Passed(detail), value),
+        Err(err) => (CheckOutcome::Failed(format!("{err:#}")), T::default()),
+    };
+
+    (
+        SelfTestCheck {
+            name,
+            outcome,
+            elapsed: started.elapsed(),
+        },
+        value,
+    )
+}
+
+fn skipped(name: &'static str, reason: &str) -> SelfTestCheck {
+    SelfTestCheck {
+        name,
+        outcome: CheckOutcome::Skipped(reason.to_string()),
+        elapsed: Duration::ZERO,
+    }
+}
+
+/// Checks that there are vectors, none of them empty, with finite values only.
+fn check_vectors(vectors: &[Vec<f32>]) -> anyhow::Result<String> {
+    let Some(vector) = vectors.first() else {
+        bail!("the model returned no vector");
+    };
+    if vector.is_empty() {
+        bail!("the model returned an empty vector");
+    }
+    if let Some(value) = vector.iter().find(|value| !value.is_finite()) {
+        bail!("the vector holds a non-finite value, {value}");
+    }
+
+    Ok(format!("{} dimensions", vector.len()))
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::config::{ServerConfig, RELOADABLE_SECTIONS};
+use crate::core::audit::SIGNAL_ACTOR;
+use crate::openai::http_entities::AppState;
+
+/// Reloads the runtime-tunable sections of the configuration file.
+///
+/// The new log level is applied first, so an invalid filter leaves the whole
+/// configuration unchanged. Requests already running finish with the previous settings.
+/// Changes of the admin key or of the API keys with their own limits are recorded in
+/// the audit log.
+///
+/// # Arguments
+///
+/// * `state` - The application state holding the configuration.
+/// * `actor` - Who requested the reload, recorded in the audit log.
+///
+/// # Returns
+///
+/// The configuration in effect after the reload.
+///
+/// # Errors
+///
+/// Returns an error if the configuration file cannot be read or its log level is invalid.
+pub fn reload_settings(state: &AppState, actor: &str) -> anyhow::Result<Arc<ServerConfig>> {
+    let previous = state.settings.current();
+    let reloaded = previous.reload()?;
+    if let (Some(filter), Some(directives)) = (&state.log_filter, &reloaded.log_level) {
+        filter.set(directives)?;
+    }
+
+    let reloaded = state.settings.replace(reloaded);
+    if let (Some(audit), Some(changes)) = (&state.audit, key_changes(&previous, &reloaded)) {
+        audit.record(actor, "keys.change", "success", Some(&changes));
+    }
+    info!(
+        "Configuration reloaded, sections applied: {}",
+        RELOADABLE_SECTIONS.join(", ")
+    );
+
+    Ok(reloaded)
+}
+
+/// Describes how the keys changed between two configurations, `None` if they did not.
+fn key_changes(previous: &ServerConfig, reloaded: &ServerConfig) -> Option<String> {
+    let mut changes = Vec::new();
+    if previous.admin.api_key != reloaded.admin.api_key {
+        changes.push("admin key changed".to_string());
+    }
+    let added = reloaded
+        .limits
+        .keys
+        .keys()
+        .filter(|key| !previous.limits.keys.contains_key(*key))
+        .count();
+    let removed = previous
+        .limits
+        .keys
+        .keys()
+        .filter(|key| !reloaded.limits.keys.contains_key(*key))
+        .count();
+    if added > 0 || removed > 0 {
+        changes.push(format!("{added} API keys added, {removed} removed"));
+    }
+
+    (!changes.is_empty()).then(|| changes.join(", "))
+}
+
+/// Reloads the configuration every time the process receives `SIGHUP`.
+///
+/// A failed reload is logged and the previous configuration stays in effect. Every
+/// reload is recorded in the audit log.
+///
+/// # Arguments
+///
+/// * `state` - The application state holding the configuration.
+///
+/// # Returns
+///
+/// The handle of the background task.
+///
+/// # Errors
+///
+/// Returns an error if the signal handler cannot be registered.
+#[cfg(unix)]
+pub fn spawn_hangup_handler(state: AppState) -> anyhow::Result<JoinHandle<()>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+
+    Ok(tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            info!("SIGHUP received, reloading the configuration");
+            let reloading = state.clone();
+            let outcome = match tokio::task::spawn_blocking(move || {
+                reload_settings(&reloading, SIGNAL_ACTOR)
+            })
+            .await
+            {
+                Ok(Ok(_)) => "success".to_string(),
+                Ok(Err(err)) => {
+                    error!("The configuration could not be reloaded: {err:#}");
+                    format!("failed: {err:#}")
+                }
+                Err(err) => {
+                    error!("The configuration reload panicked: {err}");
+                    "failed: panicked".to_string()
+                }
+            };
+            if let Some(audit) = &state.audit {
+                audit.record(SIGNAL_ACTOR, "SIGHUP", &outcome, None);
+            }
+        }
+    }))
+}
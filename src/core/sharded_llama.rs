@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use candle_core::{DType, Device, IndexOp, Module, Tensor, D};
+use candle_nn::{
+    embedding, linear_no_bias as linear, rms_norm, Embedding, Linear, RmsNorm, VarBuilder,
+};
+use candle_transformers::models::llama::Config;
+
+use crate::core::placement::PlacementPlan;
+
+/// A Llama model whose layers are spread over several devices.
+///
+/// This mirrors the candle Llama implementation without a KV cache, but loads every
+/// decoder layer on the device chosen by the [`PlacementPlan`] and moves the hidden
+/// states from one device to the next during the forward pass. Layers placed on the
+/// CPU run there, which is slow but lets models larger than the GPU memory run at all.
+#[derive(Clone)]
+pub struct ShardedLlama {
+    embeddings: Embedding,
+    blocks: Vec<Block>,
+    norm: RmsNorm,
+    lm_head: Linear,
+    head_device: Device,
+}
+
+#[derive(Clone)]
+struct Block {
+    input_norm: RmsNorm,
+    attention: Attention,
+    post_attention_norm: RmsNorm,
+    mlp: Mlp,
+    device: Device,
+    rotary: Rotary,
+}
+
+#[derive(Clone)]
+struct Attention {
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    o_proj: Linear,
+    num_attention_heads: usize,
+    num_key_value_heads: usize,
+    head_dim: usize,
+}
+
+#[derive(Clone)]
+struct Mlp {
+    gate_proj: Linear,
+    up_proj: Linear,
+    down_proj: Linear,
+}
+
+/// The rotary embedding tables of one device.
+#[derive(Clone)]
+struct Rotary {
+    cos: Tensor,
+    sin: Tensor,
+}
+
+impl ShardedLlama {
+    /// Loads the model following a placement plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `filenames` - The safetensors shards of the model.
+    /// * `config` - The configuration of the model.
+    /// * `dtype` - The data type of the weights.
+    /// * `plan` - The device of every part of the model.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a shard cannot be mapped or a tensor is missing.
+    pub fn load(
+        filenames: &[PathBuf],
+        config: &Config,
+        dtype: DType,
+        plan: &PlacementPlan,
+    ) -> anyhow::Result<Self> {
+        let mut builders: HashMap<usize, VarBuilder> = HashMap::new();
+        let mut rotaries: HashMap<usize, Rotary> = HashMap::new();
+        for (index, device) in plan.devices.iter().enumerate() {
+            let vb = unsafe { VarBuilder::from_mmaped_safetensors(filenames, dtype, device)? };
+            builders.insert(index, vb);
+            rotaries.insert(index, Rotary::new(config, dtype, device)?);
+        }
+
+        let vb = &builders[&plan.embeddings];
+        let embeddings = embedding(
+            config.vocab_size,
+            config.hidden_size,
+            vb.pp("model.embed_tokens"),
+        )?;
+
+        let blocks = (0..config.num_hidden_layers)
+            .map(|layer| {
+                let index = plan.layers[layer];
+                let vb = builders[&index].pp(format!("model.layers.{layer}"));
+                Block::load(vb, config, plan.layer_device(layer), &rotaries[&index])
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let vb = &builders[&plan.head];
+        let norm = rms_norm(config.hidden_size, config.rms_norm_eps, vb.pp("model.norm"))?;
+        let lm_head = if config.tie_word_embeddings {
+            Linear::new(
+                vb.get(
+                    (config.vocab_size, config.hidden_size),
+                    "model.embed_tokens.weight",
+                )?,
+                None,
+            )
+        } else {
+            linear(config.hidden_size, config.vocab_size, vb.pp("lm_head"))?
+        };
+
+        Ok(Self {
+            embeddings,
+            blocks,
+            norm,
+            lm_head,
+            head_device: plan.head_device().clone(),
+        })
+    }
+
+    /// Runs the model over `input` and returns the `f32` logits of the last position.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The token ids, of shape `(batch, sequence)`.
+    /// * `index_pos` - The position of the first token of `input` in the sequence.
+    pub fn forward(&self, input: &Tensor, index_pos: usize) -> candle_core::Result<Tensor> {
+        let (_, seq_len) = input.dims2()?;
+        let x = self.hidden_states(input, index_pos)?;
+        let x = x.i((.., seq_len - 1, ..))?.contiguous()?;
+
+        self.lm_head.forward(&x)?.to_dtype(DType::F32)
+    }
+
+    /// Runs the model over `input` and returns the `f32` logits of every position, of
+    /// shape `(batch, sequence, vocab)`.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The token ids, of shape `(batch, sequence)`.
+    /// * `index_pos` - The position of the first token of `input` in the sequence.
+    pub fn forward_all(&self, input: &Tensor, index_pos: usize) -> candle_core::Result<Tensor> {
+        let x = self.hidden_states(input, index_pos)?;
+
+        self.lm_head.forward(&x)?.to_dtype(DType::F32)
+    }
+
+    /// Returns the normalized hidden states of every position, on the device of the head.
+    fn hidden_states(&self, input: &Tensor, index_pos: usize) -> candle_core::Result<Tensor> {
+        let input = input.to_device(self.embeddings.embeddings().device())?;
+
+        let mut x = self.embeddings.forward(&input)?;
+        for block in &self.blocks {
+            x = block.forward(&x.to_device(&block.device)?, index_pos)?;
+        }
+
+        self.norm.forward(&x.to_device(&self.head_device)?)
+    }
+}
+
+impl Block {
+    fn load(
+        vb: VarBuilder,
+        config: &Config,
+        device: &Device,
+        rotary: &Rotary,
+    ) -> anyhow::Result<Self> {
+        let hidden = config.hidden_size;
+        let head_dim = hidden / config.num_attention_heads;
+        let kv = config.num_key_value_heads * head_dim;
+
+        let attn = vb.pp("self_attn");
+        let attention = Attention {
+            q_proj: linear(hidden, hidden, attn.pp("q_proj"))?,
+            k_proj: linear(hidden, kv, attn.pp("k_proj"))?,
+            v_proj: linear(hidden, kv, attn.pp("v_proj"))?,
+            o_proj: linear(hidden, hidden, attn.pp("o_proj"))?,
+            num_attention_heads: config.num_attention_heads,
+            num_key_value_heads: config.num_key_value_heads,
+            head_dim,
+        };
+
+        let mlp = vb.pp("mlp");
+        let mlp = Mlp {
+            gate_proj: linear(hidden, config.intermediate_size, mlp.pp("gate_proj"))?,
+            up_proj: linear(hidden, config.intermediate_size, mlp.pp("up_proj"))?,
+            down_proj: linear(config.intermediate_size, hidden, mlp.pp("down_proj"))?,
+        };
+
+        Ok(Self {
+            input_norm: rms_norm(hidden, config.rms_norm_eps, vb.pp("input_layernorm"))?,
+            attention,
+            post_attention_norm: rms_norm(
+                hidden,
+                config.rms_norm_eps,
+                vb.pp("post_attention_layernorm"),
+            )?,
+            mlp,
+            device: device.clone(),
+            rotary: rotary.clone(),
+        })
+    }
+
+    fn forward(&self, x: &Tensor, index_pos: usize) -> candle_core::Result<Tensor> {
+        let residual = x;
+        let x = self.input_norm.forward(x)?;
+        let x = (self.attention.forward(&x, index_pos, &self.rotary)? + residual)?;
+
+        let residual = &x;
+        let x = self.post_attention_norm.forward(&x)?;
+
+        self.mlp.forward(&x)? + residual
+    }
+}
+
+impl Attention {
+    fn forward(
+        &self,
+        x: &Tensor,
+        index_pos: usize,
+        rotary: &Rotary,
+    ) -> candle_core::Result<Tensor> {
+        let (b_sz, seq_len, hidden_size) = x.dims3()?;
+
+        let q = self
+            .q_proj
+            .forward(x)?
+            .reshape((b_sz, seq_len, self.num_attention_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()?;
+        let k = self
+            .k_proj
+            .forward(x)?
+            .reshape((b_sz, seq_len, self.num_key_value_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()?;
+        let v = self
+            .v_proj
+            .forward(x)?
+            .reshape((b_sz, seq_len, self.num_key_value_heads, self.head_dim))?
+            .transpose(1, 2)?;
+
+        let q = rotary.apply(&q, index_pos)?;
+        let k = rotary.apply(&k, index_pos)?;
+
+        let n_rep = self.num_attention_heads / self.num_key_value_heads;
+        let k = candle_transformers::utils::repeat_kv(k, n_rep)?;
+        let v = candle_transformers::utils::repeat_kv(v, n_rep)?;
+
+        let in_dtype = q.dtype();
+        let q = q.to_dtype(DType::F32)?;
+        let k = k.to_dtype(DType::F32)?;
+        let v = v.to_dtype(DType::F32)?;
+
+        let att = (q.matmul(&k.t()?)? / (self.head_dim as f64).sqrt())?;
+        let att = if seq_len == 1 {
+            att
+        } else {
+            let mask: Vec<u8> = (0..seq_len)
+                .flat_map(|i| (0..seq_len).map(move |j| u8::from(j > i)))
+                .collect();
+            let mask = Tensor::from_slice(&mask, (seq_len, seq_len), x.device())?
+                .broadcast_as(att.shape())?;
+            let neg_inf = Tensor::new(f32::NEG_INFINITY, x.device())?.broadcast_as(att.shape())?;
+            mask.where_cond(&neg_inf, &att)?
+        };
+        let att = candle_nn::ops::softmax_last_dim(&att)?;
+
+        let y = att.matmul(&v.contiguous()?)?.to_dtype(in_dtype)?;
+        let y = y.transpose(1, 2)?.reshape((b_sz, seq_len, hidden_size))?;
+
+        self.o_proj.forward(&y)
+    }
+}
+
+impl Mlp {
+    fn forward(&self, x: &Tensor) -> candle_core::Result<Tensor> {
+        let gate = candle_nn::ops::silu(&self.gate_proj.forward(x)?)?;
+
+        self.down_proj.forward(&(gate * self.up_proj.forward(x)?)?)
+    }
+}
+
+impl Rotary {
+    /// Computes the tables for all positions, with the Llama 3 frequency scaling if configured.
+    fn new(config: &Config, dtype: DType, device: &Device) -> candle_core::Result<Self> {
+        let head_dim = config.hidden_size / config.num_attention_heads;
+        let inv_freq: Vec<f32> = (0..head_dim)
+            .step_by(2)
+            .map(|i| 1f32 / config.rope_theta.powf(i as f32 / head_dim as f32))
+            .collect();
+
+        let inv_freq = match &config.rope_scaling {
+            None => inv_freq,
+            Some(scaling) => {
+                let original = scaling.original_max_position_embeddings as f32;
+                let low_freq_wavelen = original / scaling.low_freq_factor;
+                let high_freq_wavelen = original / scaling.high_freq_factor;
+
+                inv_freq
+                    .into_iter()
+                    .map(|freq| {
+                        let wavelen = 2. * std::f32::consts::PI / freq;
+                        if wavelen < high_freq_wavelen {
+                            freq
+                        } else if wavelen > low_freq_wavelen {
+                            freq / scaling.factor
+                        } else {
+                            let smooth = (original / wavelen - scaling.low_freq_factor)
+                                / (scaling.high_freq_factor - scaling.low_freq_factor);
+                            (1. - smooth) * freq / scaling.factor + smooth * freq
+                        }
+                    })
+                    .collect()
+            }
+        };
+
+        let positions = config.max_position_embeddings;
+        let inv_freq_len = inv_freq.len();
+        let inv_freq = Tensor::from_vec(inv_freq, (1, inv_freq_len), device)?;
+        let angles = Tensor::arange(0, positions as u32, device)?
+            .to_dtype(DType::F32)?
+            .reshape((positions, 1))?
+            .matmul(&inv_freq)?;
+
+        Ok(Self {
+            cos: angles.cos()?.to_dtype(dtype)?,
+            sin: angles.sin()?.to_dtype(dtype)?,
+        })
+    }
+
+    fn apply(&self, x: &Tensor, index_pos: usize) -> candle_core::Result<Tensor> {
+        let seq_len = x.dim(D::Minus2)?;
+        let cos = self.cos.narrow(0, index_pos, seq_len)?;
+        let sin = self.sin.narrow(0, index_pos, seq_len)?;
+
+        candle_nn::rotary_emb::rope(x, &cos, &sin)
+    }
+}
@@ -0,0 +1,234 @@
+use std::sync::Mutex;
+
+use anyhow::Error as E;
+use candle_core::{DType, Device, IndexOp, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::generation::LogitsProcessor;
+use candle_transformers::models::parler_tts::{Config, Model};
+use hf_hub::api::sync::Api;
+use hf_hub::{Repo, RepoType};
+use tokenizers::Tokenizer;
+use tracing::info;
+
+use crate::config::HubSettings;
+use crate::core::hub_fetch::fetch_with_retry;
+use crate::core::transcription::resample;
+
+/// Upper bound of audio codec steps generated for one request.
+const MAX_STEPS: usize = 2048;
+
+/// Text-to-speech using a Parler-TTS model.
+///
+/// Parler-TTS conditions the generated voice on a natural language description, so
+/// every OpenAI voice name maps to a description of the speaker.
+pub struct SpeechSynthesizer {
+    model: Mutex<Model>,
+    tokenizer: Tokenizer,
+    sample_rate: u32,
+    device: Device,
+}
+
+impl SpeechSynthesizer {
+    /// Loads a Parler-TTS model from the Hugging Face Hub.
+    ///
+    /// # Arguments
+    ///
+    /// * `api` - The Hub API client.
+    /// * `hub` - The Hub settings holding the download retry policy.
+    /// * `model_id` - The Hub id of the model, e.g. `parler-tts/parler-tts-mini-v1`.
+    /// * `device` - The device to run the model on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the model files cannot be fetched or loaded.
+    pub fn load(
+        api: &Api,
+        hub: &HubSettings,
+        model_id: &str,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        let repo = api.repo(Repo::new(model_id.to_string(), RepoType::Model));
+
+        let config: Config = serde_json::from_slice(&std::fs::read(fetch_with_retry(
+            &repo,
+            "config.json",
+            hub,
+        )?)?)?;
+        let tokenizer = Tokenizer::from_file(fetch_with_retry(&repo, "tokenizer.json", hub)?)
+            .map_err(E::msg)?;
+        let weights = fetch_with_retry(&repo, "model.safetensors", hub)?;
+
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DType::F32, device)? };
+        let model = Model::new(&config, vb)?;
+
+        info!("Speech model {} loaded", model_id);
+
+        Ok(Self {
+            model: Mutex::new(model),
+            tokenizer,
+            sample_rate: config.audio_encoder.sampling_rate as u32,
+            device: device.clone(),
+        })
+    }
+
+    /// Returns the sample rate of the generated audio.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Synthesizes speech.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The text to speak.
+    /// * `description` - The description of the speaker's voice.
+    /// * `speed` - The playback speed, `1.0` keeps the generated speed. Other speeds
+    ///   resample the audio rather than time-stretch it, so the voice is also pitched up
+    ///   when faster and down when slower, like a tape played at another speed.
+    ///
+    /// # Returns
+    ///
+    /// Mono `f32` samples at [`SpeechSynthesizer::sample_rate`].
+    pub fn synthesize(
+        &self,
+        input: &str,
+        description: &str,
+        speed: f64,
+    ) -> anyhow::Result<Vec<f32>> {
+        let prompt_tokens = self.encode(input)?;
+        let description_tokens = self.encode(description)?;
+
+        let mut model = self.model.lock().unwrap_or_else(|e| e.into_inner());
+        let lp = LogitsProcessor::new(299792458, Some(1.0), None);
+        let codes = model
+            .generate(&prompt_tokens, &description_tokens, lp, MAX_STEPS)?
+            .to_dtype(DType::I64)?
+            .unsqueeze(0)?;
+        let pcm = model
+            .audio_encoder
+            .decode_codes(&codes.to_device(&self.device)?)?
+            .i((0, 0))?
+            .to_dtype(DType::F32)?
+            .to_vec1::<f32>()?;
+        drop(model);
+
+        let pcm = normalize_peak(pcm);
+        if (speed - 1.0).abs() < f64::EPSILON {
+            return Ok(pcm);
+        }
+        let stretched_rate = (self.sample_rate as f64 * speed).round() as u32;
+
+        Ok(resample(&pcm, stretched_rate, self.sample_rate))
+    }
+
+    fn encode(&self, text: &str) -> anyhow::Result<Tensor> {
+        let ids = self
+            .tokenizer
+            .encode(text, true)
+            .map_err(E::msg)?
+            .get_ids()
+            .to_vec();
+
+        Ok(Tensor::new(ids, &self.device)?.unsqueeze(0)?)
+    }
+}
+
+/// Scales the samples so the loudest one peaks just below full scale.
+fn normalize_peak(pcm: Vec<f32>) -> Vec<f32> {
+    let peak = pcm.iter().fold(0f32, |max, s| max.max(s.abs()));
+    if peak <= f32::EPSILON {
+        return pcm;
+    }
+
+    let gain = 0.95 / peak;
+    pcm.into_iter().map(|s| s * gain).collect()
+}
+
+/// Splits the input of a speech request into the sentences synthesized one at a time.
+///
+/// Each sentence keeps its terminating punctuation, so that the model reads it with the
+/// right intonation, and a sentence too long for the codec steps of one generation is
+/// not cut short by the others.
+///
+/// # Returns
+///
+/// The non-empty, trimmed sentences of the input, in order.
+pub fn split_sentences(input: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let ends = match c {
+            '\n' => true,
+            '.' | '!' | '?' => chars.peek().map_or(true, |(_, next)| next.is_whitespace()),
+            _ => false,
+        };
+        if ends {
+            let end = i + c.len_utf8();
+            sentences.push(input[start..end].trim());
+            start = end;
+        }
+    }
+    sentences.push(input[start..].trim());
+    sentences.retain(|sentence| !sentence.is_empty());
+
+    sentences
+}
+
+/// The header of a streamed mono 16-bit PCM WAV file.
+///
+/// The length of the audio is unknown when the header is sent, so the RIFF and data
+/// chunk sizes are set to their maximum, which players read as "until the end of the
+/// stream".
+pub fn wav_stream_header(sample_rate: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, mono
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    // Byte rate and block alignment of 16-bit mono samples
+    header.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+
+    header
+}
+
+/// Encodes mono samples as raw 16-bit little-endian PCM.
+pub fn encode_pcm(pcm: &[f32]) -> Vec<u8> {
+    pcm.iter().flat_map(|s| to_i16(*s).to_le_bytes()).collect()
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_sentence_ends() {
+        assert_eq!(
+            split_sentences("Hello there. It costs 3.50 today!\nWhy? Ok"),
+            vec!["Hello there.", "It costs 3.50 today!", "Why?", "Ok"]
+        );
+        assert!(split_sentences("  \n ").is_empty());
+    }
+
+    #[test]
+    fn streamed_wav_header_is_readable() {
+        let mut bytes = wav_stream_header(24_000);
+        bytes.extend_from_slice(&encode_pcm(&[0.0, 0.5, -0.5]));
+        let reader = hound::WavReader::new(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.spec().sample_rate, 24_000);
+        assert_eq!(reader.spec().channels, 1);
+        assert_eq!(reader.spec().bits_per_sample, 16);
+    }
+}
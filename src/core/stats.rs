@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Live counters of the generation engine, reported by the health endpoint.
+pub struct EngineStats {
+    started_at: Instant,
+    in_flight: AtomicUsize,
+    kv_cache_tokens: AtomicUsize,
+}
+
+impl Default for EngineStats {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            in_flight: AtomicUsize::new(0),
+            kv_cache_tokens: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl EngineStats {
+    /// Records the start of a generation, which lasts until the returned guard is dropped.
+    pub fn start_generation(self: &Arc<Self>) -> GenerationGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+
+        GenerationGuard {
+            stats: self.clone(),
+            kv_cache_tokens: 0,
+        }
+    }
+
+    /// The time since the engine was started.
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// The number of generations waiting for or holding the model.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// The number of tokens held in the KV caches of the running generations.
+    pub fn kv_cache_tokens(&self) -> usize {
+        self.kv_cache_tokens.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracks one running generation in the [`EngineStats`].
+pub struct GenerationGuard {
+    stats: Arc<EngineStats>,
+    kv_cache_tokens: usize,
+}
+
+impl GenerationGuard {
+    /// Updates the number of tokens held in the KV cache of this generation.
+    pub fn set_kv_cache_tokens(&mut self, tokens: usize) {
+        let counter = &self.stats.kv_cache_tokens;
+        if tokens >= self.kv_cache_tokens {
+            counter.fetch_add(tokens - self.kv_cache_tokens, Ordering::Relaxed);
+        } else {
+            counter.fetch_sub(self.kv_cache_tokens - tokens, Ordering::Relaxed);
+        }
+        self.kv_cache_tokens = tokens;
+    }
+}
+
+impl Drop for GenerationGuard {
+    fn drop(&mut self) {
+        self.set_kv_cache_tokens(0);
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{bail, Context};
+use candle_transformers::models::llama::LlamaEosToks;
+use serde::Deserialize;
+use tokenizers::Tokenizer;
+
+/// A token ending the generation, by id or by its text in the tokenizer vocabulary.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum StopToken {
+    Id(u32),
+    Text(String),
+}
+
+/// The end of turn and end of text tokens of the known model families, used as stop
+/// tokens when they are in the vocabulary and the model has no configured stop tokens.
+const KNOWN_END_TOKENS: [&str; 8] = [
+    // Llama 3
+    "<|eot_id|>",
+    "<|end_of_text|>",
+    // Llama 2, Mistral
+    "</s>",
+    // Qwen, Phi-3
+    "<|im_end|>",
+    "<|endoftext|>",
+    "<|end|>",
+    // Gemma
+    "<end_of_turn>",
+    "<eos>",
+];
+
+/// Resolves the tokens that end the generation of the served model.
+///
+/// The end of sequence tokens of the model configuration always end the generation.
+/// The configured stop tokens are added to them, or, when the model has none, the
+/// known end tokens found in the vocabulary: `config.json` of instruction-tuned models
+/// often only lists the end of text token, while the chat template ends every turn with
+/// another one, such as `<|eot_id|>` for Llama 3.
+///
+/// # Arguments
+///
+/// * `tokenizer` - The tokenizer of the served model.
+/// * `eos` - The end of sequence tokens of the model configuration.
+/// * `configured` - The stop tokens configured for the model, if any.
+///
+/// # Returns
+///
+/// The ids of the stop tokens, without duplicates.
+///
+/// # Errors
+///
+/// Returns an error if a configured token is not in the vocabulary, or if no stop token
+/// is found at all, since the generation could then only end at `max_tokens`.
+pub fn resolve_stop_tokens(
+    tokenizer: &Tokenizer,
+    eos: Option<&LlamaEosToks>,
+    configured: Option<&[StopToken]>,
+) -> anyhow::Result<Vec<u32>> {
+    let mut tokens = match eos {
+        Some(LlamaEosToks::Single(id)) => vec![*id],
+        Some(LlamaEosToks::Multiple(ids)) => ids.clone(),
+        None => Vec::new(),
+    };
+
+    match configured {
+        Some(configured) => {
+            let vocab_size = tokenizer.get_vocab_size(true) as u32;
+            for token in configured {
+                let id = match token {
+                    StopToken::Id(id) if *id < vocab_size => *id,
+                    StopToken::Id(id) => {
+                        bail!(
+                            "The stop token {id} is outside the vocabulary of {vocab_size} tokens"
+                        )
+                    }
+                    StopToken::Text(text) => tokenizer.token_to_id(text).with_context(|| {
+                        format!("The stop token {text} is not in the vocabulary")
+                    })?,
+                };
+                tokens.push(id);
+            }
+        }
+        None => tokens.extend(
+            KNOWN_END_TOKENS
+                .iter()
+                .filter_map(|text| tokenizer.token_to_id(text)),
+        ),
+    }
+
+    if tokens.is_empty() {
+        bail!("The model has no end of sequence token, configure its 'stop_tokens'");
+    }
+    tokens.sort_unstable();
+    tokens.dedup();
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// A word-level tokenizer whose vocabulary holds `words`, with ids in order.
+    fn tokenizer(words: &[&str]) -> Tokenizer {
+        let vocab: serde_json::Map<String, serde_json::Value> = words
+            .iter()
+            .enumerate()
+            .map(|(id, word)| (word.to_string(), id.into()))
+            .collect();
+        let json = serde_json::json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": null,
+            "post_processor": null,
+            "decoder": null,
+            "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "[UNK]" }
+        });
+        Tokenizer::from_str(&json.to_string()).expect("the tokenizer is valid")
+    }
+
+    #[test]
+    fn known_end_tokens_are_added_to_the_eos_token() {
+        let tokenizer = tokenizer(&["[UNK]", "hello", "<|eot_id|>", "<|end_of_text|>"]);
+        let tokens = resolve_stop_tokens(&tokenizer, Some(&LlamaEosToks::Single(3)), None).unwrap();
+        assert_eq!(tokens, vec![2, 3]);
+    }
+
+    #[test]
+    fn configured_stop_tokens_replace_the_known_end_tokens() {
+        let tokenizer = tokenizer(&["[UNK]", "hello", "<|eot_id|>", "<|end_of_text|>"]);
+        let configured = [StopToken::Text("hello".to_string()), StopToken::Id(0)];
+        let tokens = resolve_stop_tokens(
+            &tokenizer,
+            Some(&LlamaEosToks::Multiple(vec![3, 3])),
+            Some(&configured),
+        )
+        .unwrap();
+        assert_eq!(tokens, vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn unknown_configured_stop_tokens_are_rejected() {
+        let tokenizer = tokenizer(&["[UNK]", "hello"]);
+        let eos = LlamaEosToks::Single(1);
+        for configured in [StopToken::Text("<|im_end|>".to_string()), StopToken::Id(2)] {
+            assert!(resolve_stop_tokens(&tokenizer, Some(&eos), Some(&[configured])).is_err());
+        }
+    }
+
+    #[test]
+    fn a_model_without_stop_token_is_rejected() {
+        let tokenizer = tokenizer(&["[UNK]", "hello"]);
+        assert!(resolve_stop_tokens(&tokenizer, None, None).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{mpsc, Notify};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// An event of a stream.
+#[derive(Clone, Debug)]
+pub struct StreamEvent {
+    /// The SSE event name, for the APIs telling their events apart by name such as the
+    /// Assistants API.
+    pub name: Option<&'static str>,
+    pub data: String,
+}
+
+/// The events of one streamed generation, kept so that clients can resume it.
+///
+/// The producer appends serialized events while the generation runs; every
+/// subscriber replays the events from a sequence number and then follows new ones
+/// until the stream is finished.
+pub struct StreamBuffer {
+    /// The API key of the request that created the stream, the only one allowed to
+    /// resume it.
+    owner: Option<String>,
+    state: Mutex<BufferState>,
+    notify: Notify,
+}
+
+struct BufferState {
+    events: Vec<StreamEvent>,
+    finished_at: Option<Instant>,
+}
+
+impl StreamBuffer {
+    fn new(owner: Option<&str>) -> Self {
+        Self {
+            owner: owner.map(str::to_string),
+            state: Mutex::new(BufferState {
+                events: Vec::new(),
+                finished_at: None,
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Appends an event and wakes up the subscribers.
+    pub fn push(&self, event: String) {
+        self.push_event(StreamEvent {
+            name: None,
+            data: event,
+        });
+    }
+
+    /// Appends an event sent with an SSE event name.
+    pub fn push_named(&self, name: &'static str, data: String) {
+        self.push_event(StreamEvent {
+            name: Some(name),
+            data,
+        });
+    }
+
+    fn push_event(&self, event: StreamEvent) {
+        self.lock().events.push(event);
+        self.notify.notify_waiters();
+    }
+
+    /// Marks the stream as complete, no more events will be pushed.
+    pub fn finish(&self) {
+        self.lock().finished_at = Some(Instant::now());
+        self.notify.notify_waiters();
+    }
+
+    /// Streams the events starting at sequence number `from`, with their sequence numbers.
+    pub fn subscribe(self: &Arc<Self>, from: usize) -> ReceiverStream<(usize, StreamEvent)> {
+        let (tx, rx) = mpsc::channel(64);
+        let buffer = self.clone();
+
+        tokio::spawn(async move {
+            let mut next = from;
+            loop {
+                let notified = buffer.notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+
+                let (events, finished) = {
+                    let state = buffer.lock();
+                    let events = state.events.get(next..).unwrap_or_default().to_vec();
+                    (events, state.finished_at.is_some())
+                };
+                if events.is_empty() {
+                    if finished {
+                        return;
+                    }
+                    notified.await;
+                    continue;
+                }
+                for event in events {
+                    if tx.send((next, event)).await.is_err() {
+                        // The client went away, the buffer stays available for a resume
+                        return;
+                    }
+                    next += 1;
+                }
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+
+    fn finished_before(&self, deadline: Instant) -> bool {
+        self.lock().finished_at.is_some_and(|at| at < deadline)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BufferState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The streams that can currently be resumed, by stream id.
+///
+/// A stream stays resumable for `resume_window` after it finished, so a client that
+/// dropped the connection can reconnect with `Last-Event-ID` instead of regenerating.
+pub struct StreamRegistry {
+    streams: Mutex<HashMap<String, Arc<StreamBuffer>>>,
+    resume_window: Duration,
+}
+
+impl StreamRegistry {
+    /// Creates an empty registry.
+    ///
+    /// # Arguments
+    ///
+    /// * `resume_window` - How long a finished stream can still be resumed.
+    pub fn new(resume_window: Duration) -> Self {
+        Self {
+            streams: Mutex::new(HashMap::new()),
+            resume_window,
+        }
+    }
+
+    /// Registers a new stream and drops the streams whose resume window has passed.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the stream.
+    /// * `owner` - The API key of the request, the only one allowed to resume the stream.
+    pub fn create(&self, id: &str, owner: Option<&str>) -> Arc<StreamBuffer> {
+        let buffer = Arc::new(StreamBuffer::new(owner));
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(deadline) = Instant::now().checked_sub(self.resume_window) {
+            streams.retain(|_, stream| !stream.finished_before(deadline));
+        }
+        streams.insert(id.to_string(), buffer.clone());
+
+        buffer
+    }
+
+    /// Returns the stream with the given id, if it can still be resumed.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the stream.
+    /// * `key` - The API key of the request resuming the stream, which must be the one
+    ///   that created it.
+    pub fn get(&self, id: &str, key: Option<&str>) -> Option<Arc<StreamBuffer>> {
+        let streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        let stream = streams.get(id)?;
+        if stream.owner.as_deref() != key {
+            return None;
+        }
+        let expired = Instant::now()
+            .checked_sub(self.resume_window)
+            .is_some_and(|deadline| stream.finished_before(deadline));
+
+        (!expired).then(|| stream.clone())
+    }
+}
+
+/// Formats the SSE event id of an event.
+pub fn event_id(stream_id: &str, sequence: usize) -> String {
+    format!("{stream_id}:{sequence}")
+}
+
+/// Parses a `Last-Event-ID` value into the stream id and the sequence number.
+pub fn parse_event_id(event_id: &str) -> Option<(&str, usize)> {
+    let (stream_id, sequence) = event_id.rsplit_once(':')?;
+
+    Some((stream_id, sequence.parse().ok()?))
+}
@@ -0,0 +1,64 @@
+use candle_core::Tensor;
+use candle_transformers::models::llama::{Cache, Llama as Llama3};
+use candle_transformers::models::quantized_llama::ModelWeights as QuantizedLlama3;
+
+use crate::core::sharded_llama::ShardedLlama;
+
+/// The weights of the served language model.
+#[derive(Clone)]
+pub enum TextModel {
+    /// Full-precision weights.
+    Llama(Llama3),
+    /// Weights quantized at load time.
+    Quantized(QuantizedLlama3),
+    /// Full-precision weights spread over several devices.
+    Sharded(ShardedLlama),
+}
+
+impl TextModel {
+    /// Runs the model over `input` and returns the logits of the last position.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The token ids, of shape `(batch, sequence)`.
+    /// * `index_pos` - The position of the first token of `input` in the sequence.
+    /// * `cache` - The KV cache of the single-device full-precision model. The quantized
+    ///   model keeps its own cache, reset whenever `index_pos` is zero, and the sharded
+    ///   model has none.
+    pub fn forward(
+        &mut self,
+        input: &Tensor,
+        index_pos: usize,
+        cache: &mut Cache,
+    ) -> candle_core::Result<Tensor> {
+        match self {
+            Self::Llama(model) => model.forward(input, index_pos, cache),
+            Self::Quantized(model) => model.forward(input, index_pos),
+            Self::Sharded(model) => model.forward(input, index_pos),
+        }
+    }
+
+    /// Whether the model keeps its KV cache in the `Cache` passed to
+    /// [`TextModel::forward`], which can then be retained and resumed.
+    pub fn has_external_cache(&self) -> bool {
+        matches!(self, Self::Llama(_))
+    }
+
+    /// Runs the model over a single sequence and returns the logits of every position, of
+    /// shape `(sequence, vocab)`, which verifying several drafted tokens at once needs.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The token ids, of shape `(1, sequence)`.
+    ///
+    /// # Returns
+    ///
+    /// The logits, or `None` for the candle models which only return the logits of the
+    /// last position.
+    pub fn forward_all(&mut self, input: &Tensor) -> candle_core::Result<Option<Tensor>> {
+        match self {
+            Self::Llama(_) | Self::Quantized(_) => Ok(None),
+            Self::Sharded(model) => model.forward_all(input, 0)?.squeeze(0).map(Some),
+        }
+    }
+}
@@ -0,0 +1,851 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tracing::{info, warn};
+
+use crate::config::{McpSettings, ToolDefinition, ToolKind, ToolSettings};
+use crate::core::webhooks::is_valid_url;
+use crate::openai::http_entities::AppState;
+
+/// A tool call the model emitted, parsed from its output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedToolCall {
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// A server tool call that was run, with its result.
+#[derive(Clone, Debug)]
+pub struct ToolResult {
+    pub name: String,
+    /// The arguments of the call, as a JSON string.
+    pub arguments: String,
+    pub output: String,
+}
+
+/// Which server tools a request lets the model call.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ToolChoice {
+    /// The model may call any tool.
+    #[default]
+    Auto,
+    /// No server tool is offered.
+    None,
+    /// The model must call a tool.
+    Required,
+    /// Only this tool is offered, and the model must call it.
+    Named(String),
+}
+
+impl ToolChoice {
+    /// Reads the `tool_choice` of a request: `auto`, `none`, `required`,
+    /// `{"type": "function", "name": ...}` or, in chat completions,
+    /// `{"type": "function", "function": {"name": ...}}`.
+    pub fn from_value(choice: Option<&Value>) -> Self {
+        match choice {
+            Some(Value::String(choice)) if choice == "none" => Self::None,
+            Some(Value::String(choice)) if choice == "required" => Self::Required,
+            Some(Value::Object(choice)) => match choice
+                .get("name")
+                .or_else(|| {
+                    choice
+                        .get("function")
+                        .and_then(|function| function.get("name"))
+                })
+                .and_then(Value::as_str)
+            {
+                Some(name) => Self::Named(name.to_string()),
+                None => Self::Auto,
+            },
+            _ => Self::Auto,
+        }
+    }
+}
+
+/// The server tools offered to the model for a request: the registered tools and the
+/// tools of the MCP servers.
+#[derive(Default)]
+pub struct OfferedTools {
+    tools: Vec<(String, ToolDefinition)>,
+    /// The tool the model must call, `*` for any of them.
+    required: Option<String>,
+    max_iterations: usize,
+    max_output_bytes: usize,
+}
+
+impl OfferedTools {
+    /// The server tools an API key may use, filtered by the `tool_choice` of the request.
+    ///
+    /// A registered tool shadows an MCP tool of the same name, and a tool of the request
+    /// shadows both.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The application state, holding the tool settings and the MCP clients.
+    /// * `key` - The API key of the request, if it has one.
+    /// * `choice` - The `tool_choice` of the request.
+    /// * `shadowed` - The names of the tools of the request.
+    pub async fn new(
+        state: &AppState,
+        key: Option<&str>,
+        choice: &ToolChoice,
+        shadowed: &[String],
+    ) -> Self {
+        let (only, required) = match choice {
+            ToolChoice::None => return Self::default(),
+            ToolChoice::Auto => (None, None),
+            ToolChoice::Required => (None, Some("*".to_string())),
+            ToolChoice::Named(name) => (Some(name.as_str()), Some(name.clone())),
+        };
+        let settings = state.settings.current().tools.clone();
+
+        let mut tools: Vec<(String, ToolDefinition)> = settings.registry.into_iter().collect();
+        for (name, tool) in state.mcp.tools().await {
+            if !tools.iter().any(|(registered, _)| *registered == name) {
+                tools.push((name, tool));
+            }
+        }
+        tools.retain(|(name, _)| {
+            only.map_or(true, |only| only == name)
+                && settings.access.allows(key, name)
+                && !shadowed.contains(name)
+        });
+        tools.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Self {
+            tools,
+            required,
+            max_iterations: settings.max_iterations,
+            max_output_bytes: settings.max_output_bytes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// Whether a tool with this name is offered.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Keeps offering only the tools named in `names`.
+    pub fn retain(&mut self, names: &[String]) {
+        self.tools.retain(|(name, _)| names.contains(name));
+    }
+
+    /// Whether a model output calls server tools, and only offered ones.
+    pub fn calls_tools(&self, text: &str) -> bool {
+        let calls = parse_tool_calls(text);
+        !calls.is_empty() && calls.iter().all(|call| self.contains(&call.name))
+    }
+
+    /// The maximum number of rounds of tool calls of a request.
+    pub fn max_iterations(&self) -> usize {
+        self.max_iterations
+    }
+
+    /// The instructions describing the tools, `None` when none is offered.
+    pub fn prompt(&self) -> Option<String> {
+        (!self.is_empty()).then(|| tools_prompt(&self.tools, self.required.as_deref()))
+    }
+
+    fn get(&self, name: &str) -> Option<&ToolDefinition> {
+        self.tools
+            .iter()
+            .find(|(tool, _)| tool == name)
+            .map(|(_, tool)| tool)
+    }
+
+    /// Runs the server tools a model output calls.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The application state.
+    /// * `text` - The output of the model.
+    ///
+    /// # Returns
+    ///
+    /// The calls and their results, in order, or `None` when the output calls no tool or
+    /// calls a tool that is not offered, which is then left to the client.
+    pub async fn execute(&self, state: &AppState, text: &str) -> Option<Vec<ToolResult>> {
+        if !self.calls_tools(text) {
+            return None;
+        }
+
+        let calls = parse_tool_calls(text);
+        let mut results = Vec::with_capacity(calls.len());
+        for call in calls {
+            let tool = self.get(&call.name)?;
+            let output = run_tool(
+                state,
+                &call.name,
+                tool,
+                &call.arguments,
+                self.max_output_bytes,
+            )
+            .await;
+            results.push(ToolResult {
+                arguments: call.arguments.to_string(),
+                name: call.name,
+                output,
+            });
+        }
+
+        Some(results)
+    }
+}
+
+/// Checks that the registered tools can be run.
+///
+/// # Errors
+///
+/// Returns an error naming the first tool with an invalid URL, no command, a command
+/// neither sandboxed nor explicitly `unsandboxed`, an unsupported method or an unknown
+/// MCP server.
+pub fn check_registry(settings: &ToolSettings, mcp: &McpSettings) -> anyhow::Result<()> {
+    for (name, tool) in &settings.registry {
+        match tool.kind {
+            ToolKind::Http if !tool.url.as_deref().is_some_and(is_valid_url) => {
+                anyhow::bail!("The http tool '{name}' needs an http or https url")
+            }
+            ToolKind::Http if !matches!(tool.method.as_str(), "GET" | "POST") => {
+                anyhow::bail!(
+                    "The http tool '{name}' has method {}, expected GET or POST",
+                    tool.method
+                )
+            }
+            ToolKind::Command if tool.command.is_empty() => {
+                anyhow::bail!("The command tool '{name}' needs a command")
+            }
+            ToolKind::Command if tool.sandbox.is_empty() && !tool.unsandboxed => {
+                anyhow::bail!(
+                    "The command tool '{name}' needs a sandbox, or `unsandboxed: true` to run \
+                     with the rights of the server"
+                )
+            }
+            ToolKind::Mcp
+                if !tool
+                    .server
+                    .as_ref()
+                    .is_some_and(|server| mcp.servers.contains_key(server)) =>
+            {
+                anyhow::bail!("The mcp tool '{name}' needs a server of `mcp.servers`")
+            }
+            _ => {}
+        }
+    }
+    if let Some((name, _)) = mcp.servers.iter().find(|(_, server)| {
+        server.command.is_empty() && !server.url.as_deref().is_some_and(is_valid_url)
+    }) {
+        anyhow::bail!("The MCP server '{name}' needs a command or an http or https url");
+    }
+
+    Ok(())
+}
+
+/// The system prompt describing the server tools to the model and how to call them.
+///
+/// # Arguments
+///
+/// * `tools` - The offered tools, by name.
+/// * `required` - The tool the model must call, or `*` for any of them.
+pub fn tools_prompt(tools: &[(String, ToolDefinition)], required: Option<&str>) -> String {
+    let mut prompt = String::from(
+        "You can call the following tools. To call a tool, answer with only \
+         <tool_call>{\"name\": \"<tool name>\", \"arguments\": {<arguments>}}</tool_call>, \
+         one per call, and wait for the results before answering.\n",
+    );
+    for (name, tool) in tools {
+        let schema = serde_json::json!({
+            "name": name,
+            "description": tool.description,
+            "parameters": tool.parameters,
+        });
+        prompt.push_str(&schema.to_string());
+        prompt.push('\n');
+    }
+    match required {
+        Some("*") => prompt.push_str("You must call at least one tool.\n"),
+        Some(name) => prompt.push_str(&format!("You must call the tool {name}.\n")),
+        None => {}
+    }
+
+    prompt
+}
+
+/// Parses the tool calls of a model output.
+///
+/// The calls are read from `<tool_call>` blocks, as the tools prompt asks, or from an
+/// output that is a single JSON object with a `name` and its `arguments` or `parameters`,
+/// as Llama 3.1 emits them.
+///
+/// # Arguments
+///
+/// * `text` - The generated text.
+///
+/// # Returns
+///
+/// The calls in order, empty when the output is an answer.
+pub fn parse_tool_calls(text: &str) -> Vec<ParsedToolCall> {
+    let mut calls = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<tool_call>") {
+        rest = &rest[start + "<tool_call>".len()..];
+        let end = rest.find("</tool_call>").unwrap_or(rest.len());
+        calls.extend(parse_call(&rest[..end]));
+        rest = &rest[end..];
+    }
+    if calls.is_empty() {
+        let text = text.trim().trim_start_matches("<|python_tag|>");
+        calls.extend(parse_call(text));
+    }
+
+    calls
+}
+
+fn parse_call(json: &str) -> Option<ParsedToolCall> {
+    let Value::Object(mut call) = serde_json::from_str(json.trim()).ok()? else {
+        return None;
+    };
+    let name = call.get("name")?.as_str()?.to_string();
+    let arguments = call
+        .remove("arguments")
+        .or_else(|| call.remove("parameters"))
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    // Some models encode the arguments as a JSON string
+    let arguments = match arguments {
+        Value::String(encoded) => serde_json::from_str(&encoded).ok()?,
+        arguments => arguments,
+    };
+
+    Some(ParsedToolCall { name, arguments })
+}
+
+/// Runs a server tool.
+///
+/// A failing tool does not fail the request: its error is returned as the result, so
+/// that the model can answer without it or try again.
+///
+/// # Arguments
+///
+/// * `state` - The application state, holding the stores searched by `search` tools.
+/// * `name` - The name of the tool.
+/// * `tool` - The definition of the tool.
+/// * `arguments` - The arguments of the call.
+/// * `max_output_bytes` - The size the result is truncated to.
+///
+/// # Returns
+///
+/// The result fed back to the model.
+pub async fn run_tool(
+    state: &AppState,
+    name: &str,
+    tool: &ToolDefinition,
+    arguments: &Value,
+    max_output_bytes: usize,
+) -> String {
+    info!("Running the tool {name}");
+    let result = match tool.kind {
+        ToolKind::Http => run_http(tool, arguments).await,
+        ToolKind::Command => run_command(tool, arguments, max_output_bytes).await,
+        ToolKind::Calculator => match arguments.get("expression").and_then(Value::as_str) {
+            Some(expression) => evaluate(expression).map(|value| value.to_string()),
+            None => Err("missing the expression argument".to_string()),
+        },
+        ToolKind::Search => run_search(state, tool, arguments).await,
+        ToolKind::Mcp => {
+            let server = tool.server.as_deref().unwrap_or_default();
+            state
+                .mcp
+                .call(server, tool.tool.as_deref().unwrap_or(name), arguments)
+                .await
+                .map_err(|err| format!("{err:#}"))
+        }
+    };
+
+    let output = result.unwrap_or_else(|err| {
+        warn!("The tool {name} failed: {err}");
+        format!("Error: {err}")
+    });
+    truncate(output, max_output_bytes)
+}
+
+/// Sends the arguments of a call to the endpoint of an `http` tool.
+async fn run_http(tool: &ToolDefinition, arguments: &Value) -> Result<String, String> {
+    let tool = tool.clone();
+    let arguments = arguments.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let url = tool.url.as_deref().ok_or("the tool has no url")?;
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(tool.timeout_secs))
+            .build();
+        let mut request = agent.request(&tool.method, url);
+        for (name, value) in &tool.headers {
+            request = request.set(name, value);
+        }
+
+        let response = if tool.method == "GET" {
+            if let Value::Object(arguments) = &arguments {
+                for (name, value) in arguments {
+                    let value = match value {
+                        Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    };
+                    request = request.query(name, &value);
+                }
+            }
+            request.call()
+        } else {
+            request
+                .set("Content-Type", "application/json")
+                .send_string(&arguments.to_string())
+        };
+        response
+            .map_err(|err| err.to_string())?
+            .into_string()
+            .map_err(|err| err.to_string())
+    })
+    .await
+    .map_err(|err| err.to_string())?
+}
+
+/// Runs the command of a `command` tool with the arguments on its standard input.
+///
+/// The input is written and the output read within the timeout of the tool. The output
+/// is read up to one byte past `max_output_bytes`, which tells that it is truncated, and
+/// the command is then killed rather than left to fill the pipe.
+async fn run_command(
+    tool: &ToolDefinition,
+    arguments: &Value,
+    max_output_bytes: usize,
+) -> Result<String, String> {
+    let mut argv = tool.sandbox.iter().chain(&tool.command);
+    let program = argv.next().ok_or("the tool has no command")?;
+    let mut command = tokio::process::Command::new(program);
+    command
+        .args(argv)
+        .env_clear()
+        .envs(&tool.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(directory) = &tool.working_dir {
+        command.current_dir(directory);
+    }
+
+    let mut child = command.spawn().map_err(|err| err.to_string())?;
+    let (stdin, stdout, stderr) = (child.stdin.take(), child.stdout.take(), child.stderr.take());
+    let input = arguments.to_string();
+    let limit = max_output_bytes as u64 + 1;
+    let run = async {
+        let write = async move {
+            if let Some(mut stdin) = stdin {
+                // A command that does not read its input closes the pipe, which is not an error
+                let _ = stdin.write_all(input.as_bytes()).await;
+            }
+        };
+        let read = async {
+            let stdout = read_pipe(stdout, limit).await?;
+            if stdout.len() as u64 == limit {
+                child.start_kill()?;
+            }
+            Ok::<_, std::io::Error>(stdout)
+        };
+        let ((), stdout, stderr) = tokio::join!(write, read, read_pipe(stderr, limit));
+        Ok::<_, std::io::Error>((child.wait().await?, stdout?, stderr?))
+    };
+    // The child is killed when it is dropped at the timeout
+    let (status, stdout, stderr) =
+        tokio::time::timeout(Duration::from_secs(tool.timeout_secs), run)
+            .await
+            .map_err(|_| format!("timed out after {} seconds", tool.timeout_secs))?
+            .map_err(|err| err.to_string())?;
+
+    if stdout.len() as u64 == limit {
+        // The output is truncated by the caller, the exit status is the one of the kill
+        return Ok(String::from_utf8_lossy(&stdout).into_owned());
+    }
+    if !status.success() {
+        return Err(format!(
+            "the command exited with {status}: {}",
+            String::from_utf8_lossy(&stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&stdout).into_owned())
+}
+
+/// Reads a pipe of a child process up to `limit` bytes, and closes it.
+async fn read_pipe(pipe: Option<impl AsyncRead + Unpin>, limit: u64) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    if let Some(pipe) = pipe {
+        pipe.take(limit).read_to_end(&mut bytes).await?;
+    }
+    Ok(bytes)
+}
+
+/// Searches the vector store or the RAG documents of a `search` tool.
+async fn run_search(
+    state: &AppState,
+    tool: &ToolDefinition,
+    arguments: &Value,
+) -> Result<String, String> {
+    let query = arguments
+        .get("query")
+        .and_then(Value::as_str)
+        .ok_or("missing the query argument")?
+        .to_string();
+    let max_results = tool.max_results;
+
+    let results = match tool.vector_store_id.clone() {
+        Some(id) => {
+            let stores = state
+                .vector_stores
+                .clone()
+                .ok_or("vector stores are not enabled")?;
+            tokio::task::spawn_blocking(move || {
+                let results = stores
+                    .search(&id, &[query], max_results, 0.0, None)
+                    .map_err(|err| err.to_string())?;
+                serde_json::to_string(&results).map_err(|err| err.to_string())
+            })
+            .await
+        }
+        None => {
+            let rag = state.rag.clone().ok_or("RAG is not enabled")?;
+            tokio::task::spawn_blocking(move || {
+                let (chunks, _) = rag
+                    .retrieve(&query, max_results)
+                    .map_err(|err| err.to_string())?;
+                serde_json::to_string(&chunks).map_err(|err| err.to_string())
+            })
+            .await
+        }
+    };
+
+    results.map_err(|err| err.to_string())?
+}
+
+/// Truncates a result to a number of bytes, on a character boundary.
+fn truncate(mut output: String, max_bytes: usize) -> String {
+    if output.len() > max_bytes {
+        let mut end = max_bytes;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+        output.push_str(" [truncated]");
+    }
+    output
+}
+
+/// Evaluates an arithmetic expression with `+ - * / % ^`, parentheses, the constants
+/// `pi` and `e` and the functions `sqrt`, `abs`, `ln`, `log10`, `exp`, `sin`, `cos`,
+/// `tan`, `floor`, `ceil`, `round`, `min` and `max`.
+///
+/// # Errors
+///
+/// Returns a description of the first syntax error, of an expression longer than
+/// `MAX_EXPRESSION_CHARS` or nested deeper than `MAX_EXPRESSION_DEPTH`, or of a result
+/// that is not finite.
+pub fn evaluate(expression: &str) -> Result<f64, String> {
+    if expression.chars().count() > MAX_EXPRESSION_CHARS {
+        return Err(format!(
+            "the expression is longer than {MAX_EXPRESSION_CHARS} characters"
+        ));
+    }
+    let mut parser = Calculator {
+        chars: expression.chars().filter(|c| !c.is_whitespace()).collect(),
+        position: 0,
+        depth: 0,
+    };
+    let value = parser.expression()?;
+    if parser.position < parser.chars.len() {
+        return Err(format!(
+            "unexpected '{}' in the expression",
+            parser.chars[parser.position]
+        ));
+    }
+    if !value.is_finite() {
+        return Err("the result is not a finite number".to_string());
+    }
+
+    Ok(value)
+}
+
+/// The longest expression the calculator evaluates.
+const MAX_EXPRESSION_CHARS: usize = 4096;
+
+/// How deep the calculator nests parentheses, signs and powers, so that the model cannot
+/// overflow the stack of the server.
+const MAX_EXPRESSION_DEPTH: usize = 64;
+
+/// A recursive descent parser evaluating an expression as it reads it.
+struct Calculator {
+    chars: Vec<char>,
+    position: usize,
+    /// The number of nested `unary` being parsed, which every recursion goes through.
+    depth: usize,
+}
+
+impl Calculator {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value += self.term()?;
+            } else if self.eat('-') {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                value /= self.unary()?;
+            } else if self.eat('%') {
+                value %= self.unary()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// A signed power, so that `-2^2` is `-4`.
+    fn unary(&mut self) -> Result<f64, String> {
+        if self.depth == MAX_EXPRESSION_DEPTH {
+            return Err(format!(
+                "the expression is nested deeper than {MAX_EXPRESSION_DEPTH} levels"
+            ));
+        }
+        self.depth += 1;
+        let value = self.signed_power();
+        self.depth -= 1;
+        value
+    }
+
+    fn signed_power(&mut self) -> Result<f64, String> {
+        if self.eat('-') {
+            return Ok(-self.unary()?);
+        }
+        if self.eat('+') {
+            return self.unary();
+        }
+        let base = self.atom()?;
+        if self.eat('^') {
+            return Ok(base.powf(self.unary()?));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<f64, String> {
+        if self.eat('(') {
+            let value = self.expression()?;
+            if !self.eat(')') {
+                return Err("missing ')' in the expression".to_string());
+            }
+            return Ok(value);
+        }
+
+        let start = self.position;
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                    self.position += 1;
+                }
+                let number: String = self.chars[start..self.position].iter().collect();
+                number
+                    .parse()
+                    .map_err(|_| format!("invalid number '{number}'"))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+                    self.position += 1;
+                }
+                let name: String = self.chars[start..self.position].iter().collect();
+                self.function(&name)
+            }
+            Some(c) => Err(format!("unexpected '{c}' in the expression")),
+            None => Err("the expression ends too early".to_string()),
+        }
+    }
+
+    fn function(&mut self, name: &str) -> Result<f64, String> {
+        match name {
+            "pi" => return Ok(std::f64::consts::PI),
+            "e" => return Ok(std::f64::consts::E),
+            _ => {}
+        }
+        if !self.eat('(') {
+            return Err(format!("unknown constant '{name}'"));
+        }
+        let mut arguments = vec![self.expression()?];
+        while self.eat(',') {
+            arguments.push(self.expression()?);
+        }
+        if !self.eat(')') {
+            return Err(format!("missing ')' after the arguments of {name}"));
+        }
+
+        match (name, arguments.as_slice()) {
+            ("sqrt", [x]) => Ok(x.sqrt()),
+            ("abs", [x]) => Ok(x.abs()),
+            ("ln", [x]) => Ok(x.ln()),
+            ("log10", [x]) => Ok(x.log10()),
+            ("exp", [x]) => Ok(x.exp()),
+            ("sin", [x]) => Ok(x.sin()),
+            ("cos", [x]) => Ok(x.cos()),
+            ("tan", [x]) => Ok(x.tan()),
+            ("floor", [x]) => Ok(x.floor()),
+            ("ceil", [x]) => Ok(x.ceil()),
+            ("round", [x]) => Ok(x.round()),
+            ("min", [x, rest @ ..]) => Ok(rest.iter().fold(*x, |a, b| a.min(*b))),
+            ("max", [x, rest @ ..]) => Ok(rest.iter().fold(*x, |a, b| a.max(*b))),
+            _ => Err(format!(
+                "unknown function {name} with {} arguments",
+                arguments.len()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn evaluates_with_precedence() {
+        assert_eq!(evaluate("1 + 2 * 3"), Ok(7.0));
+        assert_eq!(evaluate("(1 + 2) * 3"), Ok(9.0));
+        assert_eq!(evaluate("-2^2"), Ok(-4.0));
+        assert_eq!(evaluate("2^3^2"), Ok(512.0));
+        assert_eq!(evaluate("max(1, sqrt(16), 3) % 3"), Ok(1.0));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(evaluate("1 +").is_err());
+        assert!(evaluate("(1 + 2").is_err());
+        assert!(evaluate("1 / 0").is_err());
+        assert!(evaluate("system(1)").is_err());
+    }
+
+    #[test]
+    fn rejects_deep_or_long_expressions() {
+        let nested = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert!(evaluate(&nested).is_err());
+        assert!(evaluate(&"-".repeat(1_000)).is_err());
+        assert!(evaluate(&"2^".repeat(1_000)).is_err());
+        assert!(evaluate(&"1+".repeat(MAX_EXPRESSION_CHARS)).is_err());
+
+        let shallow = format!("{}1{}", "(".repeat(32), ")".repeat(32));
+        assert_eq!(evaluate(&shallow), Ok(1.0));
+    }
+
+    #[test]
+    fn parses_tagged_and_bare_calls() {
+        let text = "<tool_call>{\"name\": \"calc\", \"arguments\": {\"expression\": \"1+1\"}}</tool_call>\n\
+                    <tool_call>{\"name\": \"search\", \"arguments\": \"{\\\"query\\\": \\\"rust\\\"}\"}</tool_call>";
+        assert_eq!(
+            parse_tool_calls(text),
+            vec![
+                ParsedToolCall {
+                    name: "calc".to_string(),
+                    arguments: json!({ "expression": "1+1" }),
+                },
+                ParsedToolCall {
+                    name: "search".to_string(),
+                    arguments: json!({ "query": "rust" }),
+                },
+            ]
+        );
+
+        let bare = "<|python_tag|>{\"name\": \"calc\", \"parameters\": {\"expression\": \"2\"}}";
+        assert_eq!(
+            parse_tool_calls(bare)[0].arguments,
+            json!({ "expression": "2" })
+        );
+        assert!(parse_tool_calls("The answer is {\"name\": 1}").is_empty());
+    }
+
+    #[test]
+    fn reads_response_and_chat_tool_choices() {
+        assert_eq!(ToolChoice::from_value(None), ToolChoice::Auto);
+        assert_eq!(
+            ToolChoice::from_value(Some(&json!("none"))),
+            ToolChoice::None
+        );
+        assert_eq!(
+            ToolChoice::from_value(Some(&json!({ "type": "function", "name": "calculator" }))),
+            ToolChoice::Named("calculator".to_string())
+        );
+        assert_eq!(
+            ToolChoice::from_value(Some(
+                &json!({ "type": "function", "function": { "name": "calculator" } })
+            )),
+            ToolChoice::Named("calculator".to_string())
+        );
+    }
+
+    #[cfg(unix)]
+    fn shell(script: &str, timeout_secs: u64) -> ToolDefinition {
+        ToolDefinition {
+            kind: ToolKind::Command,
+            command: vec!["/bin/sh".to_string(), "-c".to_string(), script.to_string()],
+            timeout_secs,
+            ..ToolDefinition::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_output_is_capped() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let tool = shell("while :; do echo cat; done", 10);
+
+        let output = runtime
+            .block_on(run_command(&tool, &json!({}), 16))
+            .unwrap();
+
+        assert_eq!(output.len(), 17);
+        assert_eq!(truncate(output, 16), "cat\ncat\ncat\ncat\n [truncated]");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_not_reading_their_input_time_out() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let tool = shell("while :; do :; done", 1);
+        // More than a pipe holds, so that writing it blocks
+        let arguments = json!({ "text": "cat ".repeat(1 << 20) });
+
+        let started = std::time::Instant::now();
+        let result = runtime.block_on(run_command(&tool, &arguments, 16));
+
+        assert_eq!(result, Err("timed out after 1 seconds".to_string()));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
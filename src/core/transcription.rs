@@ -0,0 +1,384 @@
+use std::io::Cursor;
+use std::sync::Mutex;
+
+use anyhow::{bail, Error as E};
+use candle_core::{DType, Device, IndexOp, Tensor, D};
+use candle_nn::VarBuilder;
+use candle_transformers::models::whisper::{self as m, audio, Config};
+use hf_hub::api::sync::Api;
+use hf_hub::{Repo, RepoType};
+use tokenizers::Tokenizer;
+use tracing::info;
+
+use crate::config::HubSettings;
+use crate::core::hub_fetch::fetch_with_retry;
+
+/// Duration in seconds of one timestamp token step.
+const TIMESTAMP_STEP: f64 = 0.02;
+
+/// A transcribed span of audio.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    pub tokens: Vec<u32>,
+}
+
+/// The result of a transcription.
+#[derive(Debug, Clone)]
+pub struct Transcription {
+    pub language: Option<String>,
+    pub duration: f64,
+    pub segments: Vec<Segment>,
+}
+
+impl Transcription {
+    /// Returns the full transcribed text.
+    pub fn text(&self) -> String {
+        self.segments
+            .iter()
+            .map(|s| s.text.trim())
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Speech-to-text using a Whisper model.
+///
+/// The `Transcriber` owns the Whisper encoder/decoder, its tokenizer and the mel
+/// filter bank. Audio is processed in 30 second windows, each window is decoded
+/// greedily with timestamp tokens enabled so the output can be split into segments.
+pub struct Transcriber {
+    model: Mutex<m::model::Whisper>,
+    tokenizer: Tokenizer,
+    config: Config,
+    mel_filters: Vec<f32>,
+    device: Device,
+}
+
+impl Transcriber {
+    /// Loads a Whisper model from the Hugging Face Hub.
+    ///
+    /// # Arguments
+    ///
+    /// * `api` - The Hub API client.
+    /// * `hub` - The Hub settings holding the download retry policy.
+    /// * `model_id` - The Hub id of the Whisper model, e.g. `openai/whisper-base`.
+    /// * `device` - The device to run the model on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the model files cannot be fetched or loaded.
+    pub fn load(
+        api: &Api,
+        hub: &HubSettings,
+        model_id: &str,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        let repo = api.repo(Repo::new(model_id.to_string(), RepoType::Model));
+
+        let config: Config = serde_json::from_slice(&std::fs::read(fetch_with_retry(
+            &repo,
+            "config.json",
+            hub,
+        )?)?)?;
+        let tokenizer = Tokenizer::from_file(fetch_with_retry(&repo, "tokenizer.json", hub)?)
+            .map_err(E::msg)?;
+        let weights = fetch_with_retry(&repo, "model.safetensors", hub)?;
+
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], m::DTYPE, device)? };
+        let model = m::model::Whisper::load(&vb, config.clone())?;
+        let mel_filters = mel_filters(config.num_mel_bins, m::N_FFT, m::SAMPLE_RATE);
+
+        info!("Transcription model {} loaded", model_id);
+
+        Ok(Self {
+            model: Mutex::new(model),
+            tokenizer,
+            config,
+            mel_filters,
+            device: device.clone(),
+        })
+    }
+
+    /// Transcribes audio samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `pcm` - Mono samples at 16kHz, as returned by [`decode_wav`].
+    /// * `language` - Optional ISO-639-1 language of the audio, e.g. `en`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the language is unknown or the model fails.
+    pub fn transcribe(&self, pcm: &[f32], language: Option<&str>) -> anyhow::Result<Transcription> {
+        let duration = pcm.len() as f64 / m::SAMPLE_RATE as f64;
+
+        let mel = audio::pcm_to_mel(&self.config, pcm, &self.mel_filters);
+        let mel_len = mel.len();
+        let n_mels = self.config.num_mel_bins;
+        let mel = Tensor::from_vec(mel, (1, n_mels, mel_len / n_mels), &self.device)?;
+
+        let sot = self.token_id(m::SOT_TOKEN)?;
+        let transcribe = self.token_id(m::TRANSCRIBE_TOKEN)?;
+        let eot = self.token_id(m::EOT_TOKEN)?;
+        let no_timestamps = self.token_id(m::NO_TIMESTAMPS_TOKEN)?;
+        let language_token = match language {
+            Some(language) => Some(self.token_id(&format!("<|{language}|>"))?),
+            None => None,
+        };
+
+        let suppress = {
+            let mut mask = vec![0f32; self.config.vocab_size];
+            for &token in self
+                .config
+                .suppress_tokens
+                .iter()
+                .chain(std::iter::once(&no_timestamps))
+            {
+                if let Some(value) = mask.get_mut(token as usize) {
+                    *value = f32::NEG_INFINITY;
+                }
+            }
+            Tensor::new(mask.as_slice(), &self.device)?
+        };
+
+        let mut model = self.model.lock().unwrap_or_else(|e| e.into_inner());
+        let (_, _, content_frames) = mel.dims3()?;
+        let mut seek = 0;
+        let mut segments = Vec::new();
+
+        while seek < content_frames {
+            let window_start = seek as f64 * m::HOP_LENGTH as f64 / m::SAMPLE_RATE as f64;
+            let segment_size = usize::min(content_frames - seek, m::N_FRAMES);
+            let mel_segment = mel.narrow(2, seek, segment_size)?;
+            seek += segment_size;
+
+            let audio_features = model.encoder.forward(&mel_segment, true)?;
+
+            let mut tokens = vec![sot];
+            tokens.extend(language_token);
+            tokens.push(transcribe);
+            let prompt_len = tokens.len();
+
+            for i in 0..self.config.max_target_positions / 2 {
+                let tokens_t = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
+                let ys = model.decoder.forward(&tokens_t, &audio_features, i == 0)?;
+                let (_, seq_len, _) = ys.dims3()?;
+                let logits = model
+                    .decoder
+                    .final_linear(&ys.i((..1, seq_len - 1..))?)?
+                    .i(0)?
+                    .i(0)?
+                    .to_dtype(DType::F32)?
+                    .broadcast_add(&suppress)?;
+                let next_token = logits.argmax(D::Minus1)?.to_scalar::<u32>()?;
+                tokens.push(next_token);
+                if next_token == eot || tokens.len() > self.config.max_target_positions {
+                    break;
+                }
+            }
+
+            segments.extend(self.split_segments(
+                &tokens[prompt_len..],
+                window_start,
+                segment_size as f64 * m::HOP_LENGTH as f64 / m::SAMPLE_RATE as f64,
+                no_timestamps,
+                eot,
+            )?);
+        }
+
+        Ok(Transcription {
+            language: language.map(str::to_string),
+            duration,
+            segments,
+        })
+    }
+
+    /// Splits the decoded tokens of one window into segments on timestamp tokens.
+    fn split_segments(
+        &self,
+        tokens: &[u32],
+        window_start: f64,
+        window_duration: f64,
+        no_timestamps: u32,
+        eot: u32,
+    ) -> anyhow::Result<Vec<Segment>> {
+        let mut segments = Vec::new();
+        let mut start = None;
+        let mut text_tokens = Vec::new();
+
+        for &token in tokens {
+            if token == eot {
+                break;
+            }
+            if token <= no_timestamps {
+                text_tokens.push(token);
+                continue;
+            }
+
+            let time = (token - no_timestamps - 1) as f64 * TIMESTAMP_STEP;
+            match start {
+                Some(segment_start) if !text_tokens.is_empty() => {
+                    segments.push(self.segment(window_start, segment_start, time, &text_tokens)?);
+                    text_tokens.clear();
+                    start = None;
+                }
+                _ => start = Some(time),
+            }
+        }
+
+        if !text_tokens.is_empty() {
+            let segment_start = start.unwrap_or(0.0);
+            segments.push(self.segment(
+                window_start,
+                segment_start,
+                window_duration,
+                &text_tokens,
+            )?);
+        }
+
+        Ok(segments)
+    }
+
+    fn segment(
+        &self,
+        window_start: f64,
+        start: f64,
+        end: f64,
+        tokens: &[u32],
+    ) -> anyhow::Result<Segment> {
+        Ok(Segment {
+            start: window_start + start,
+            end: window_start + end,
+            text: self.tokenizer.decode(tokens, true).map_err(E::msg)?,
+            tokens: tokens.to_vec(),
+        })
+    }
+
+    /// Whether the model transcribes a language, given as an ISO-639-1 code such as `en`.
+    pub fn supports_language(&self, language: &str) -> bool {
+        // Unlike the other special tokens, the language tokens are short lowercase codes
+        (2..=3).contains(&language.len())
+            && language.bytes().all(|byte| byte.is_ascii_lowercase())
+            && self
+                .tokenizer
+                .token_to_id(&format!("<|{language}|>"))
+                .is_some()
+    }
+
+    fn token_id(&self, token: &str) -> anyhow::Result<u32> {
+        match self.tokenizer.token_to_id(token) {
+            Some(id) => Ok(id),
+            None => bail!("No token id for {token}"),
+        }
+    }
+}
+
+/// Decodes a WAV file into mono `f32` samples at Whisper's 16kHz sample rate.
+///
+/// Multi-channel audio is mixed down and other sample rates are linearly resampled.
+///
+/// # Errors
+///
+/// Returns an error if the bytes are not a supported WAV file.
+pub fn decode_wav(bytes: &[u8]) -> anyhow::Result<Vec<f32>> {
+    let mut reader = hound::WavReader::new(Cursor::new(bytes))?;
+    let spec = reader.spec();
+
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+
+    let channels = spec.channels.max(1) as usize;
+    let mono: Vec<f32> = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect();
+
+    Ok(resample(&mono, spec.sample_rate, m::SAMPLE_RATE as u32))
+}
+
+/// Linearly resamples `samples` from `from` Hz to `to` Hz.
+pub(crate) fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = from as f64 / to as f64;
+    let len = (samples.len() as f64 / ratio).floor() as usize;
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let index = position.floor() as usize;
+            let frac = (position - index as f64) as f32;
+            let current = samples[index];
+            let next = samples.get(index + 1).copied().unwrap_or(current);
+            current + (next - current) * frac
+        })
+        .collect()
+}
+
+/// Computes the Slaney-style mel filter bank used by Whisper (librosa defaults).
+///
+/// # Returns
+///
+/// A row-major `n_mels x (n_fft / 2 + 1)` matrix of filter weights.
+fn mel_filters(n_mels: usize, n_fft: usize, sample_rate: usize) -> Vec<f32> {
+    fn hz_to_mel(hz: f64) -> f64 {
+        let f_sp = 200.0 / 3.0;
+        let min_log_hz = 1000.0;
+        let min_log_mel = min_log_hz / f_sp;
+        let log_step = 6.4f64.ln() / 27.0;
+        if hz >= min_log_hz {
+            min_log_mel + (hz / min_log_hz).ln() / log_step
+        } else {
+            hz / f_sp
+        }
+    }
+
+    fn mel_to_hz(mel: f64) -> f64 {
+        let f_sp = 200.0 / 3.0;
+        let min_log_hz = 1000.0;
+        let min_log_mel = min_log_hz / f_sp;
+        let log_step = 6.4f64.ln() / 27.0;
+        if mel >= min_log_mel {
+            min_log_hz * (log_step * (mel - min_log_mel)).exp()
+        } else {
+            mel * f_sp
+        }
+    }
+
+    let n_freqs = n_fft / 2 + 1;
+    let nyquist = sample_rate as f64 / 2.0;
+    let fft_freqs: Vec<f64> = (0..n_freqs)
+        .map(|i| i as f64 * nyquist / (n_freqs - 1) as f64)
+        .collect();
+    let max_mel = hz_to_mel(nyquist);
+    let mel_freqs: Vec<f64> = (0..n_mels + 2)
+        .map(|i| mel_to_hz(i as f64 * max_mel / (n_mels + 1) as f64))
+        .collect();
+
+    let mut filters = vec![0f32; n_mels * n_freqs];
+    for i in 0..n_mels {
+        let lower_width = mel_freqs[i + 1] - mel_freqs[i];
+        let upper_width = mel_freqs[i + 2] - mel_freqs[i + 1];
+        let norm = 2.0 / (mel_freqs[i + 2] - mel_freqs[i]);
+        for (j, &freq) in fft_freqs.iter().enumerate() {
+            let lower = (freq - mel_freqs[i]) / lower_width;
+            let upper = (mel_freqs[i + 2] - freq) / upper_width;
+            filters[i * n_freqs + j] = (lower.min(upper).max(0.0) * norm) as f32;
+        }
+    }
+
+    filters
+}
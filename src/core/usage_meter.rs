@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use tokio::sync::{watch, Notify};
+
+use crate::core::generation_queue::QueuePosition;
+
+/// The usage of a request so far, reported in the response headers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MeteredUsage {
+    pub prompt_tokens: usize,
+    /// The tokens generated for the request, `None` while a stream is still generating.
+    pub completion_tokens: Option<usize>,
+    /// How long the request waited for a thread or the model before its prompt was
+    /// processed, in milliseconds.
+    pub queue_time_ms: u64,
+    /// The number of tokens the generations may produce when the server lowered the
+    /// `max_tokens` of the request, to its limit or to the room left in the context.
+    pub max_tokens_applied: Option<usize>,
+}
+
+/// Counts the tokens and the queue time of a request.
+///
+/// The generations of all the choices of a request share one meter. The choices share
+/// their prompt, so it is counted once, and the queue time is the one of the choice
+/// that waited the longest.
+pub struct UsageMeter {
+    received_at: Instant,
+    prompt_tokens: AtomicUsize,
+    completion_tokens: AtomicUsize,
+    queue_time_ms: AtomicU64,
+    /// The lowered `max_tokens`, `usize::MAX` while the request got what it asked for.
+    max_tokens_applied: AtomicUsize,
+    started: AtomicBool,
+    /// Woken once the first generation started, or all of them ended without starting.
+    on_start: Notify,
+    /// The position of a generation waiting in the queue, `None` once it started.
+    queue: watch::Sender<Option<QueuePosition>>,
+}
+
+impl Default for UsageMeter {
+    fn default() -> Self {
+        Self {
+            received_at: Instant::now(),
+            prompt_tokens: AtomicUsize::new(0),
+            completion_tokens: AtomicUsize::new(0),
+            queue_time_ms: AtomicU64::new(0),
+            max_tokens_applied: AtomicUsize::new(usize::MAX),
+            started: AtomicBool::new(false),
+            on_start: Notify::new(),
+            queue: watch::Sender::new(None),
+        }
+    }
+}
+
+impl UsageMeter {
+    /// Records that a generation starts processing its prompt, ending its wait.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt_tokens` - The number of tokens of the prompt.
+    pub fn record_start(&self, prompt_tokens: usize) {
+        let waited = self.received_at.elapsed().as_millis() as u64;
+        self.prompt_tokens
+            .fetch_max(prompt_tokens, Ordering::Relaxed);
+        self.queue_time_ms.fetch_max(waited, Ordering::Relaxed);
+        self.release();
+    }
+
+    /// Records the tokens read by a model that generates nothing, such as an embedding
+    /// model, once it ran.
+    pub fn record_prompt_tokens(&self, prompt_tokens: usize) {
+        self.prompt_tokens
+            .fetch_add(prompt_tokens, Ordering::Relaxed);
+    }
+
+    /// Records the tokens generated by one generation.
+    pub fn record_completion(&self, completion_tokens: usize) {
+        self.completion_tokens
+            .fetch_add(completion_tokens, Ordering::Relaxed);
+    }
+
+    /// Records that the server lowered the `max_tokens` of a generation.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_tokens` - The number of tokens the generation may produce.
+    pub fn record_max_tokens_applied(&self, max_tokens: usize) {
+        self.max_tokens_applied
+            .fetch_min(max_tokens, Ordering::Relaxed);
+    }
+
+    /// Records that a generation waits for its turn in the queue.
+    pub fn record_queued(&self, position: QueuePosition) {
+        self.queue.send_replace(Some(position));
+    }
+
+    /// Wakes the tasks waiting for the start, also called when the generations end
+    /// without starting, e.g. on an error.
+    pub fn release(&self) {
+        self.started.store(true, Ordering::Release);
+        self.queue.send_replace(None);
+        self.on_start.notify_waiters();
+    }
+
+    /// Follows the position of the request in the queue, `None` while it is not queued.
+    pub fn queue_position(&self) -> watch::Receiver<Option<QueuePosition>> {
+        self.queue.subscribe()
+    }
+
+    /// Waits until a generation started or the generations ended.
+    pub async fn started(&self) {
+        let notified = self.on_start.notified();
+        if self.started.load(Ordering::Acquire) {
+            return;
+        }
+        notified.await;
+    }
+
+    /// The usage of the request while it is still generating, without completion tokens.
+    pub fn partial(&self) -> MeteredUsage {
+        MeteredUsage {
+            completion_tokens: None,
+            ..self.usage()
+        }
+    }
+
+    /// The usage of the request.
+    pub fn usage(&self) -> MeteredUsage {
+        MeteredUsage {
+            prompt_tokens: self.prompt_tokens.load(Ordering::Relaxed),
+            completion_tokens: Some(self.completion_tokens.load(Ordering::Relaxed)),
+            queue_time_ms: self.queue_time_ms.load(Ordering::Relaxed),
+            max_tokens_applied: Some(self.max_tokens_applied.load(Ordering::Relaxed))
+                .filter(|&max_tokens| max_tokens != usize::MAX),
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::hash_map::RandomState;
+use std::collections::{BinaryHeap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+
+/// The number of neighbours a node keeps on the upper layers.
+const MAX_NEIGHBOURS: usize = 16;
+/// The number of neighbours a node keeps on the bottom layer.
+const MAX_NEIGHBOURS_BOTTOM: usize = 2 * MAX_NEIGHBOURS;
+/// The number of candidates considered while linking a new node.
+const EF_CONSTRUCTION: usize = 100;
+/// The minimum number of candidates considered while searching.
+const EF_SEARCH: usize = 50;
+
+/// A vector found by [`VectorIndex::search`].
+#[derive(Clone, Copy, Debug)]
+pub struct Neighbour {
+    /// The id returned by [`VectorIndex::insert`].
+    pub id: usize,
+    /// The cosine similarity with the query, between -1 and 1.
+    pub score: f32,
+}
+
+/// A node of the graph, with its neighbours on each of its layers.
+struct Node {
+    vector: Vec<f32>,
+    neighbours: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+/// A distance to a node, ordered by distance then id.
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    id: usize,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.id.cmp(&other.id))
+    }
+}
+
+/// An in-process approximate nearest neighbour index over unit vectors.
+///
+/// The index is a Hierarchical Navigable Small World graph: every vector is a node
+/// linked to its nearest neighbours on the bottom layer, and to a shrinking random
+/// subset of the nodes on each layer above, so that a search walks down from coarse
+/// to fine layers in logarithmic time. Vectors must be normalized, which makes the
+/// dot product their cosine similarity.
+///
+/// Removed vectors stay in the graph to keep it navigable and are only left out of
+/// the results.
+pub struct VectorIndex {
+    dims: usize,
+    nodes: Vec<Node>,
+    entry_point: Option<usize>,
+    live: usize,
+}
+
+impl VectorIndex {
+    /// Creates an empty index.
+    ///
+    /// # Arguments
+    ///
+    /// * `dims` - The size of the vectors.
+    pub fn new(dims: usize) -> Self {
+        Self {
+            dims,
+            nodes: Vec::new(),
+            entry_point: None,
+            live: 0,
+        }
+    }
+
+    /// The size of the vectors.
+    pub fn dims(&self) -> usize {
+        self.dims
+    }
+
+    /// The number of vectors that were not removed.
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    /// Whether the index holds no vector.
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    /// Adds a vector to the index.
+    ///
+    /// # Arguments
+    ///
+    /// * `vector` - The unit vector, of size `dims`.
+    ///
+    /// # Returns
+    ///
+    /// The id of the vector, which ids are assigned in insertion order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the vector does not have `dims` components.
+    pub fn insert(&mut self, vector: Vec<f32>) -> usize {
+        assert_eq!(vector.len(), self.dims, "vector size mismatch");
+
+        let id = self.nodes.len();
+        let level = random_level();
+        self.nodes.push(Node {
+            vector,
+            neighbours: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.live += 1;
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(id);
+            return id;
+        };
+        let top = self.nodes[entry_point].neighbours.len() - 1;
+        let query = self.nodes[id].vector.clone();
+
+        // Greedy descent through the layers above the new node
+        let mut nearest = entry_point;
+        for layer in (level + 1..=top).rev() {
+            nearest = self.greedy_closest(&query, nearest, layer);
+        }
+
+        // Link the node on each of its layers, starting from its top one
+        let mut entry_points = vec![nearest];
+        for layer in (0..=level.min(top)).rev() {
+            let candidates = self.search_layer(&query, &entry_points, EF_CONSTRUCTION, layer);
+            let max = max_neighbours(layer);
+            let neighbours = self.select_neighbours(&candidates, max);
+            self.nodes[id].neighbours[layer] = neighbours.clone();
+
+            for neighbour in neighbours {
+                self.nodes[neighbour].neighbours[layer].push(id);
+                if self.nodes[neighbour].neighbours[layer].len() > max {
+                    self.prune(neighbour, layer, max);
+                }
+            }
+            entry_points = candidates.iter().map(|c| c.id).collect();
+        }
+
+        if level > top {
+            self.entry_point = Some(id);
+        }
+
+        id
+    }
+
+    /// Removes a vector from the search results.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id returned by [`insert`](Self::insert).
+    ///
+    /// # Returns
+    ///
+    /// Whether the vector was in the index.
+    pub fn remove(&mut self, id: usize) -> bool {
+        match self.nodes.get_mut(id) {
+            Some(node) if !node.deleted => {
+                node.deleted = true;
+                self.live -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Finds the vectors most similar to a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The unit query vector, of size `dims`.
+    /// * `k` - The maximum number of vectors to return.
+    ///
+    /// # Returns
+    ///
+    /// The nearest vectors that were not removed, most similar first.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<Neighbour> {
+        let Some(entry_point) = self.entry_point else {
+            return Vec::new();
+        };
+        if k == 0 || query.len() != self.dims {
+            return Vec::new();
+        }
+
+        let top = self.nodes[entry_point].neighbours.len() - 1;
+        let mut nearest = entry_point;
+        for layer in (1..=top).rev() {
+            nearest = self.greedy_closest(query, nearest, layer);
+        }
+
+        // Removed nodes take up candidate slots, so the beam grows with them
+        let removed = self.nodes.len() - self.live;
+        let ef = EF_SEARCH.max(k) + removed.min(EF_SEARCH * 4);
+        self.search_layer(query, &[nearest], ef, 0)
+            .into_iter()
+            .filter(|c| !self.nodes[c.id].deleted)
+            .take(k)
+            .map(|c| Neighbour {
+                id: c.id,
+                score: 1.0 - c.distance,
+            })
+            .collect()
+    }
+
+    fn distance(&self, query: &[f32], id: usize) -> f32 {
+        let vector = &self.nodes[id].vector;
+        1.0 - query.iter().zip(vector).map(|(a, b)| a * b).sum::<f32>()
+    }
+
+    /// Walks a layer towards the query from `start`, one closer neighbour at a time.
+    fn greedy_closest(&self, query: &[f32], start: usize, layer: usize) -> usize {
+        let mut current = start;
+        let mut distance = self.distance(query, current);
+        loop {
+            let closer = self.nodes[current].neighbours[layer]
+                .iter()
+                .map(|&id| (id, self.distance(query, id)))
+                .filter(|&(_, d)| d < distance)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            match closer {
+                Some((id, d)) => {
+                    current = id;
+                    distance = d;
+                }
+                None => return current,
+            }
+        }
+    }
+
+    /// Beam search of a layer, returning up to `ef` nodes closest first.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = entry_points.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+        for &id in entry_points {
+            let candidate = Candidate {
+                distance: self.distance(query, id),
+                id,
+            };
+            candidates.push(Reverse(candidate));
+            found.push(candidate);
+        }
+        while found.len() > ef {
+            found.pop();
+        }
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let furthest = found
+                .peek()
+                .map_or(f32::INFINITY, |c: &Candidate| c.distance);
+            if candidate.distance > furthest && found.len() >= ef {
+                break;
+            }
+
+            for &id in &self.nodes[candidate.id].neighbours[layer] {
+                if !visited.insert(id) {
+                    continue;
+                }
+                let neighbour = Candidate {
+                    distance: self.distance(query, id),
+                    id,
+                };
+                let furthest = found.peek().map_or(f32::INFINITY, |c| c.distance);
+                if found.len() < ef || neighbour.distance < furthest {
+                    candidates.push(Reverse(neighbour));
+                    found.push(neighbour);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    /// Picks up to `max` neighbours among candidates sorted closest first.
+    ///
+    /// A candidate is skipped when it is closer to an already picked neighbour than to
+    /// the new node, which keeps links spread in all directions instead of clustered.
+    fn select_neighbours(&self, candidates: &[Candidate], max: usize) -> Vec<usize> {
+        let mut selected: Vec<usize> = Vec::with_capacity(max);
+        for candidate in candidates {
+            if selected.len() == max {
+                break;
+            }
+            let vector = &self.nodes[candidate.id].vector;
+            let diverse = selected
+                .iter()
+                .all(|&id| self.distance(vector, id) > candidate.distance);
+            if diverse {
+                selected.push(candidate.id);
+            }
+        }
+        // Fill up with the closest skipped candidates rather than leaving slots empty
+        for candidate in candidates {
+            if selected.len() == max {
+                break;
+            }
+            if !selected.contains(&candidate.id) {
+                selected.push(candidate.id);
+            }
+        }
+        selected
+    }
+
+    /// Trims the neighbours of a node on a layer back to `max`.
+    fn prune(&mut self, id: usize, layer: usize, max: usize) {
+        let vector = self.nodes[id].vector.clone();
+        let mut candidates: Vec<Candidate> = self.nodes[id].neighbours[layer]
+            .iter()
+            .map(|&neighbour| Candidate {
+                distance: self.distance(&vector, neighbour),
+                id: neighbour,
+            })
+            .collect();
+        candidates.sort();
+        self.nodes[id].neighbours[layer] = self.select_neighbours(&candidates, max);
+    }
+}
+
+fn max_neighbours(layer: usize) -> usize {
+    if layer == 0 {
+        MAX_NEIGHBOURS_BOTTOM
+    } else {
+        MAX_NEIGHBOURS
+    }
+}
+
+/// Draws the top layer of a new node, each layer being `MAX_NEIGHBOURS` times sparser.
+fn random_level() -> usize {
+    let random = RandomState::new().build_hasher().finish();
+    let uniform = (random >> 11) as f64 / (1u64 << 53) as f64;
+    let level = -(1.0 - uniform).ln() / (MAX_NEIGHBOURS as f64).ln();
+
+    (level as usize).min(16)
+}
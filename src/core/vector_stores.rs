@@ -0,0 +1,789 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::config::VectorStoreSettings;
+use crate::core::embeddings::Embedder;
+use crate::core::vector_index::VectorIndex;
+use crate::openai::models::{
+    AttributeFilter, ChunkingStrategy, FileObject, VectorStoreExpiration,
+    VectorStoreFileBatchObject, VectorStoreFileCounts, VectorStoreFileError, VectorStoreFileObject,
+    VectorStoreObject, VectorStoreSearchContent, VectorStoreSearchResult,
+};
+
+/// Seconds in a day, for the expiration of vector stores.
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Errors returned by the [`VectorStores`].
+#[derive(Debug)]
+pub enum VectorStoreError {
+    /// No vector store has this id.
+    NotFound(String),
+    /// The file is not part of the vector store.
+    FileNotFound(String),
+    /// No file batch of the vector store has this id.
+    BatchNotFound(String),
+    /// The vector store id is not a valid id issued by this store.
+    InvalidId(String),
+    Io(std::io::Error),
+    Metadata(serde_json::Error),
+    Embedding(anyhow::Error),
+}
+
+impl fmt::Display for VectorStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "No vector store found with id '{id}'"),
+            Self::FileNotFound(id) => write!(f, "No file found with id '{id}' in the vector store"),
+            Self::BatchNotFound(id) => write!(f, "No file batch found with id '{id}'"),
+            Self::InvalidId(id) => write!(f, "Invalid vector store id '{id}'"),
+            Self::Io(err) => write!(f, "Vector store I/O error: {err}"),
+            Self::Metadata(err) => write!(f, "Vector store metadata error: {err}"),
+            Self::Embedding(err) => write!(f, "Error embedding text: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for VectorStoreError {}
+
+impl From<std::io::Error> for VectorStoreError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for VectorStoreError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Metadata(err)
+    }
+}
+
+/// A vector store as kept on disk: its files with their chunks and vectors.
+#[derive(Serialize, Deserialize)]
+struct StoredVectorStore {
+    store: VectorStoreObject,
+    files: Vec<StoredFile>,
+    batches: Vec<VectorStoreFileBatchObject>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredFile {
+    file: VectorStoreFileObject,
+    filename: String,
+    chunks: Vec<StoredChunk>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredChunk {
+    text: String,
+    vector: Vec<f32>,
+}
+
+/// A vector store in memory, with the index of the vectors of its chunks.
+struct LoadedStore {
+    stored: StoredVectorStore,
+    index: VectorIndex,
+    /// The file id and chunk number of each vector of the index.
+    vectors: HashMap<usize, (String, usize)>,
+}
+
+impl LoadedStore {
+    fn new(stored: StoredVectorStore, dims: usize) -> Self {
+        let mut loaded = Self {
+            stored,
+            index: VectorIndex::new(dims),
+            vectors: HashMap::new(),
+        };
+        for position in 0..loaded.stored.files.len() {
+            loaded.index_file(position);
+        }
+        loaded
+    }
+
+    fn index_file(&mut self, position: usize) {
+        let file = &self.stored.files[position];
+        for (number, chunk) in file.chunks.iter().enumerate() {
+            let id = self.index.insert(chunk.vector.clone());
+            self.vectors.insert(id, (file.file.id.clone(), number));
+        }
+    }
+
+    fn file(&self, file_id: &str) -> Option<&StoredFile> {
+        self.stored.files.iter().find(|f| f.file.id == file_id)
+    }
+
+    /// Recomputes the file counts and usage, and marks the store active.
+    fn touch(&mut self) {
+        let mut counts = VectorStoreFileCounts::default();
+        for file in &self.stored.files {
+            match file.file.status.as_str() {
+                "in_progress" => counts.in_progress += 1,
+                "failed" => counts.failed += 1,
+                "cancelled" => counts.cancelled += 1,
+                _ => counts.completed += 1,
+            }
+            counts.total += 1;
+        }
+
+        let store = &mut self.stored.store;
+        let now = Utc::now().timestamp();
+        store.file_counts = counts;
+        store.usage_bytes = self.stored.files.iter().map(|f| f.file.usage_bytes).sum();
+        store.last_active_at = Some(now);
+        store.expires_at = store
+            .expires_after
+            .as_ref()
+            .map(|expiration| now + expiration.days as i64 * DAY_SECS);
+    }
+
+    /// The vector store object, `expired` once its expiration time has passed.
+    fn object(&self) -> VectorStoreObject {
+        let mut store = self.stored.store.clone();
+        if store
+            .expires_at
+            .is_some_and(|at| at <= Utc::now().timestamp())
+        {
+            store.status = "expired".to_string();
+        }
+        store
+    }
+
+    fn result(&self, vector_id: usize, score: f32) -> Option<VectorStoreSearchResult> {
+        let (file_id, number) = self.vectors.get(&vector_id)?;
+        let file = self.file(file_id)?;
+
+        Some(VectorStoreSearchResult {
+            file_id: file_id.clone(),
+            filename: file.filename.clone(),
+            score,
+            attributes: file.file.attributes.clone(),
+            content: vec![VectorStoreSearchContent {
+                kind: "text".to_string(),
+                text: file.chunks.get(*number)?.text.clone(),
+            }],
+        })
+    }
+}
+
+/// The chunks of a file being added, or the reason it cannot be indexed.
+type Ingestion = Result<Vec<StoredChunk>, VectorStoreFileError>;
+
+/// Disk-backed vector stores behind the `/v1/vector_stores` endpoints.
+///
+/// Files uploaded through `/v1/files` are split into chunks of tokens, embedded and
+/// indexed in the HNSW index of their vector store. Every vector store is kept as
+/// `<id>.json` in the configured directory, with the text and vector of each chunk, and
+/// its index is rebuilt from it when the server starts.
+pub struct VectorStores {
+    settings: VectorStoreSettings,
+    embedder: Arc<Embedder>,
+    stores: RwLock<HashMap<String, LoadedStore>>,
+}
+
+impl VectorStores {
+    /// Opens the vector stores, creating the storage directory if needed.
+    ///
+    /// Vector stores embedded by a model with another vector size are skipped with a
+    /// warning, as they cannot be searched with the current model.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The storage directory.
+    /// * `embedder` - The model embedding the chunks and the queries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory or a vector store cannot be read.
+    pub fn open(
+        settings: VectorStoreSettings,
+        embedder: Arc<Embedder>,
+    ) -> Result<Self, VectorStoreError> {
+        fs::create_dir_all(&settings.directory)?;
+
+        let mut stores = HashMap::new();
+        for entry in fs::read_dir(&settings.directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let stored: StoredVectorStore = serde_json::from_slice(&fs::read(&path)?)?;
+            let dims = stored
+                .files
+                .iter()
+                .flat_map(|f| f.chunks.first())
+                .map(|c| c.vector.len())
+                .next();
+            if dims.is_some_and(|dims| dims != embedder.dims()) {
+                warn!(
+                    "Skipping vector store {}, its vectors were not made by {}",
+                    stored.store.id,
+                    embedder.model_id()
+                );
+                continue;
+            }
+            stores.insert(
+                stored.store.id.clone(),
+                LoadedStore::new(stored, embedder.dims()),
+            );
+        }
+
+        Ok(Self {
+            settings,
+            embedder,
+            stores: RwLock::new(stores),
+        })
+    }
+
+    /// Creates an empty vector store.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the vector store.
+    /// * `metadata` - Client key-value pairs.
+    /// * `expires_after` - When the vector store expires after its last activity.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vector store cannot be written.
+    pub fn create(
+        &self,
+        name: Option<String>,
+        metadata: HashMap<String, String>,
+        expires_after: Option<VectorStoreExpiration>,
+    ) -> Result<VectorStoreObject, VectorStoreError> {
+        let stored = StoredVectorStore {
+            store: VectorStoreObject {
+                id: format!("vs_{}", Uuid::new_v4().simple()),
+                object: "vector_store".to_string(),
+                created_at: Utc::now().timestamp(),
+                name,
+                usage_bytes: 0,
+                file_counts: VectorStoreFileCounts::default(),
+                status: "completed".to_string(),
+                expires_after,
+                expires_at: None,
+                last_active_at: None,
+                metadata,
+            },
+            files: Vec::new(),
+            batches: Vec::new(),
+        };
+        let mut loaded = LoadedStore::new(stored, self.embedder.dims());
+        loaded.touch();
+
+        let id = loaded.stored.store.id.clone();
+        self.save(&loaded)?;
+        let store = loaded.object();
+        self.write().insert(id, loaded);
+
+        Ok(store)
+    }
+
+    /// Lists the vector stores, oldest first.
+    pub fn list(&self) -> Vec<VectorStoreObject> {
+        let mut stores: Vec<VectorStoreObject> =
+            self.read().values().map(LoadedStore::object).collect();
+        stores.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+        stores
+    }
+
+    /// Returns a vector store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no vector store has this id.
+    pub fn get(&self, id: &str) -> Result<VectorStoreObject, VectorStoreError> {
+        self.read()
+            .get(id)
+            .map(LoadedStore::object)
+            .ok_or_else(|| VectorStoreError::NotFound(id.to_string()))
+    }
+
+    /// Changes the name, metadata or expiration of a vector store.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the vector store.
+    /// * `name` - The new name, unchanged when `None`.
+    /// * `metadata` - The new metadata, unchanged when `None`.
+    /// * `expires_after` - The new expiration, unchanged when `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no vector store has this id or it cannot be written.
+    pub fn modify(
+        &self,
+        id: &str,
+        name: Option<String>,
+        metadata: Option<HashMap<String, String>>,
+        expires_after: Option<VectorStoreExpiration>,
+    ) -> Result<VectorStoreObject, VectorStoreError> {
+        let mut stores = self.write();
+        let loaded = stores
+            .get_mut(id)
+            .ok_or_else(|| VectorStoreError::NotFound(id.to_string()))?;
+
+        let store = &mut loaded.stored.store;
+        if name.is_some() {
+            store.name = name;
+        }
+        if let Some(metadata) = metadata {
+            store.metadata = metadata;
+        }
+        if expires_after.is_some() {
+            store.expires_after = expires_after;
+        }
+        loaded.touch();
+        self.save(loaded)?;
+
+        Ok(loaded.object())
+    }
+
+    /// Deletes a vector store with its chunks. The files themselves are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no vector store has this id or it cannot be removed.
+    pub fn delete(&self, id: &str) -> Result<(), VectorStoreError> {
+        let path = self.path(id)?;
+        let mut stores = self.write();
+        if stores.remove(id).is_none() {
+            return Err(VectorStoreError::NotFound(id.to_string()));
+        }
+        fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    /// Chunks, embeds and indexes a file in a vector store.
+    ///
+    /// A file that is not UTF-8 text is recorded with the `failed` status and the
+    /// reason in `last_error`. Adding a file twice returns the existing entry.
+    /// Embedding is compute bound and should run on a blocking thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the vector store.
+    /// * `file` - The metadata of the file in the file store.
+    /// * `content` - The content of the file.
+    /// * `chunking` - How the file is split into chunks.
+    /// * `attributes` - Key-value pairs the search results can be filtered on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no vector store has this id, embedding fails or the vector
+    /// store cannot be written.
+    pub fn add_file(
+        &self,
+        id: &str,
+        file: &FileObject,
+        content: &[u8],
+        chunking: &ChunkingStrategy,
+        attributes: HashMap<String, serde_json::Value>,
+    ) -> Result<VectorStoreFileObject, VectorStoreError> {
+        if let Some(existing) = self.read().get(id).and_then(|s| s.file(&file.id)) {
+            return Ok(existing.file.clone());
+        }
+        self.get(id)?;
+
+        let chunking = ChunkingStrategy::Static {
+            r#static: chunking.resolve(),
+        };
+        let ingestion = self.ingest(content, &chunking)?;
+
+        let mut stores = self.write();
+        let loaded = stores
+            .get_mut(id)
+            .ok_or_else(|| VectorStoreError::NotFound(id.to_string()))?;
+        // Another request may have added the file while this one was embedding
+        if let Some(existing) = loaded.file(&file.id) {
+            return Ok(existing.file.clone());
+        }
+
+        let dims = self.embedder.dims() as u64;
+        let (chunks, status, last_error) = match ingestion {
+            Ok(chunks) => (chunks, "completed", None),
+            Err(error) => (Vec::new(), "failed", Some(error)),
+        };
+        let stored = StoredFile {
+            file: VectorStoreFileObject {
+                id: file.id.clone(),
+                object: "vector_store.file".to_string(),
+                usage_bytes: chunks.iter().map(|c| c.text.len() as u64 + dims * 4).sum(),
+                created_at: Utc::now().timestamp(),
+                vector_store_id: id.to_string(),
+                status: status.to_string(),
+                last_error,
+                chunking_strategy: chunking,
+                attributes,
+            },
+            filename: file.filename.clone(),
+            chunks,
+        };
+        let object = stored.file.clone();
+
+        loaded.stored.files.push(stored);
+        loaded.index_file(loaded.stored.files.len() - 1);
+        loaded.touch();
+        self.save(loaded)?;
+
+        Ok(object)
+    }
+
+    /// Lists the files of a vector store, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no vector store has this id.
+    pub fn list_files(&self, id: &str) -> Result<Vec<VectorStoreFileObject>, VectorStoreError> {
+        let stores = self.read();
+        let loaded = stores
+            .get(id)
+            .ok_or_else(|| VectorStoreError::NotFound(id.to_string()))?;
+
+        Ok(loaded.stored.files.iter().map(|f| f.file.clone()).collect())
+    }
+
+    /// Returns a file of a vector store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vector store or the file does not exist.
+    pub fn get_file(
+        &self,
+        id: &str,
+        file_id: &str,
+    ) -> Result<VectorStoreFileObject, VectorStoreError> {
+        let stores = self.read();
+        let loaded = stores
+            .get(id)
+            .ok_or_else(|| VectorStoreError::NotFound(id.to_string()))?;
+
+        loaded
+            .file(file_id)
+            .map(|f| f.file.clone())
+            .ok_or_else(|| VectorStoreError::FileNotFound(file_id.to_string()))
+    }
+
+    /// Removes a file and its chunks from a vector store. The file itself is kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vector store or the file does not exist, or the vector
+    /// store cannot be written.
+    pub fn delete_file(&self, id: &str, file_id: &str) -> Result<(), VectorStoreError> {
+        let mut stores = self.write();
+        let loaded = stores
+            .get_mut(id)
+            .ok_or_else(|| VectorStoreError::NotFound(id.to_string()))?;
+        let Some(position) = loaded
+            .stored
+            .files
+            .iter()
+            .position(|f| f.file.id == file_id)
+        else {
+            return Err(VectorStoreError::FileNotFound(file_id.to_string()));
+        };
+
+        loaded.stored.files.remove(position);
+        let removed: Vec<usize> = loaded
+            .vectors
+            .iter()
+            .filter(|(_, (file, _))| file == file_id)
+            .map(|(&vector_id, _)| vector_id)
+            .collect();
+        for vector_id in removed {
+            loaded.index.remove(vector_id);
+            loaded.vectors.remove(&vector_id);
+        }
+        loaded.touch();
+        self.save(loaded)
+    }
+
+    /// Adds several files to a vector store as one batch.
+    ///
+    /// The files are ingested one after the other before the batch is returned, so the
+    /// batch is always `completed`; files that could not be ingested are counted as failed.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the vector store.
+    /// * `files` - The metadata and content of each file.
+    /// * `chunking` - How the files are split into chunks.
+    /// * `attributes` - Key-value pairs set on every file of the batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no vector store has this id, embedding fails or the vector
+    /// store cannot be written.
+    pub fn add_file_batch(
+        &self,
+        id: &str,
+        files: &[(FileObject, Vec<u8>)],
+        chunking: &ChunkingStrategy,
+        attributes: HashMap<String, serde_json::Value>,
+    ) -> Result<VectorStoreFileBatchObject, VectorStoreError> {
+        let mut counts = VectorStoreFileCounts::default();
+        for (file, content) in files {
+            let added = self.add_file(id, file, content, chunking, attributes.clone())?;
+            match added.status.as_str() {
+                "failed" => counts.failed += 1,
+                _ => counts.completed += 1,
+            }
+            counts.total += 1;
+        }
+
+        let batch = VectorStoreFileBatchObject {
+            id: format!("vsfb_{}", Uuid::new_v4().simple()),
+            object: "vector_store.files_batch".to_string(),
+            created_at: Utc::now().timestamp(),
+            vector_store_id: id.to_string(),
+            status: "completed".to_string(),
+            file_counts: counts,
+        };
+
+        let mut stores = self.write();
+        let loaded = stores
+            .get_mut(id)
+            .ok_or_else(|| VectorStoreError::NotFound(id.to_string()))?;
+        loaded.stored.batches.push(batch.clone());
+        self.save(loaded)?;
+
+        Ok(batch)
+    }
+
+    /// Returns a file batch of a vector store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vector store or the batch does not exist.
+    pub fn get_file_batch(
+        &self,
+        id: &str,
+        batch_id: &str,
+    ) -> Result<VectorStoreFileBatchObject, VectorStoreError> {
+        let stores = self.read();
+        let loaded = stores
+            .get(id)
+            .ok_or_else(|| VectorStoreError::NotFound(id.to_string()))?;
+
+        loaded
+            .stored
+            .batches
+            .iter()
+            .find(|b| b.id == batch_id)
+            .cloned()
+            .ok_or_else(|| VectorStoreError::BatchNotFound(batch_id.to_string()))
+    }
+
+    /// Searches the chunks of a vector store most similar to the queries.
+    ///
+    /// Without a filter the HNSW index is searched; with one, every chunk of the files
+    /// matching the filter is compared to the queries so that no match is missed.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the vector store.
+    /// * `queries` - The queries, a chunk scoring its best similarity with any of them.
+    /// * `max_results` - The maximum number of chunks to return.
+    /// * `score_threshold` - The minimum similarity of the returned chunks.
+    /// * `filter` - A filter on the attributes of the files.
+    ///
+    /// # Returns
+    ///
+    /// The matching chunks, most similar first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no vector store has this id or the queries cannot be embedded.
+    pub fn search(
+        &self,
+        id: &str,
+        queries: &[String],
+        max_results: usize,
+        score_threshold: f32,
+        filter: Option<&AttributeFilter>,
+    ) -> Result<Vec<VectorStoreSearchResult>, VectorStoreError> {
+        self.get(id)?;
+        let embeddings = self
+            .embedder
+            .embed(queries)
+            .map_err(VectorStoreError::Embedding)?;
+
+        let stores = self.read();
+        let loaded = stores
+            .get(id)
+            .ok_or_else(|| VectorStoreError::NotFound(id.to_string()))?;
+
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        for query in &embeddings.vectors {
+            let neighbours: Vec<(usize, f32)> = match filter {
+                None => loaded
+                    .index
+                    .search(query, max_results)
+                    .into_iter()
+                    .map(|n| (n.id, n.score))
+                    .collect(),
+                Some(filter) => loaded
+                    .vectors
+                    .iter()
+                    .filter(|(_, (file_id, _))| {
+                        loaded
+                            .file(file_id)
+                            .is_some_and(|f| matches_filter(filter, &f.file.attributes))
+                    })
+                    .filter_map(|(&vector_id, (file_id, number))| {
+                        let chunk = loaded.file(file_id)?.chunks.get(*number)?;
+                        let score = query
+                            .iter()
+                            .zip(&chunk.vector)
+                            .map(|(a, b)| a * b)
+                            .sum::<f32>();
+                        Some((vector_id, score))
+                    })
+                    .collect(),
+            };
+            for (vector_id, score) in neighbours {
+                let best = scores.entry(vector_id).or_insert(score);
+                *best = best.max(score);
+            }
+        }
+
+        let mut scores: Vec<(usize, f32)> = scores
+            .into_iter()
+            .filter(|&(_, score)| score >= score_threshold)
+            .collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        Ok(scores
+            .into_iter()
+            .take(max_results)
+            .filter_map(|(vector_id, score)| loaded.result(vector_id, score))
+            .collect())
+    }
+
+    /// Splits a file into chunks and embeds them.
+    fn ingest(
+        &self,
+        content: &[u8],
+        chunking: &ChunkingStrategy,
+    ) -> Result<Ingestion, VectorStoreError> {
+        let Ok(text) = std::str::from_utf8(content) else {
+            return Ok(Err(VectorStoreFileError {
+                code: "unsupported_file".to_string(),
+                message: "Only UTF-8 text files can be added to a vector store".to_string(),
+            }));
+        };
+
+        let strategy = chunking.resolve();
+        let texts = self
+            .embedder
+            .chunk(
+                text,
+                strategy.max_chunk_size_tokens,
+                strategy.chunk_overlap_tokens,
+            )
+            .map_err(VectorStoreError::Embedding)?;
+        if texts.is_empty() {
+            return Ok(Err(VectorStoreFileError {
+                code: "invalid_file".to_string(),
+                message: "The file has no text to index".to_string(),
+            }));
+        }
+        let embeddings = self
+            .embedder
+            .embed(&texts)
+            .map_err(VectorStoreError::Embedding)?;
+
+        Ok(Ok(texts
+            .into_iter()
+            .zip(embeddings.vectors)
+            .map(|(text, vector)| StoredChunk { text, vector })
+            .collect()))
+    }
+
+    /// Writes a vector store to disk, replacing the previous version atomically.
+    fn save(&self, loaded: &LoadedStore) -> Result<(), VectorStoreError> {
+        let path = self.path(&loaded.stored.store.id)?;
+        let partial = path.with_extension("json.partial");
+        fs::write(&partial, serde_json::to_vec(&loaded.stored)?)?;
+        fs::rename(partial, path)?;
+
+        Ok(())
+    }
+
+    /// Returns the path of a vector store.
+    ///
+    /// Ids come straight from request paths, so anything but the `vs_<hex>` form issued
+    /// by [`VectorStores::create`] is rejected to keep lookups inside the directory.
+    fn path(&self, id: &str) -> Result<PathBuf, VectorStoreError> {
+        let valid = id
+            .strip_prefix("vs_")
+            .is_some_and(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()));
+        if !valid {
+            return Err(VectorStoreError::InvalidId(id.to_string()));
+        }
+
+        Ok(self.settings.directory.join(format!("{id}.json")))
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<String, LoadedStore>> {
+        self.stores.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<String, LoadedStore>> {
+        self.stores.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether the attributes of a file match a filter.
+///
+/// Numbers are compared by value and strings lexicographically; a comparison on a
+/// missing attribute or between values of different types does not match.
+pub fn matches_filter(
+    filter: &AttributeFilter,
+    attributes: &HashMap<String, serde_json::Value>,
+) -> bool {
+    use serde_json::Value;
+    use std::cmp::Ordering;
+
+    let filters = filter.filters.as_deref().unwrap_or_default();
+    match filter.kind.as_str() {
+        "and" => return filters.iter().all(|f| matches_filter(f, attributes)),
+        "or" => return filters.iter().any(|f| matches_filter(f, attributes)),
+        _ => {}
+    }
+
+    let (Some(key), Some(expected)) = (&filter.key, &filter.value) else {
+        return false;
+    };
+    let Some(actual) = attributes.get(key) else {
+        return false;
+    };
+    let ordering = match (actual, expected) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .zip(b.as_f64())
+            .and_then(|(a, b)| a.partial_cmp(&b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+
+    match (filter.kind.as_str(), ordering) {
+        ("eq", Some(o)) => o == Ordering::Equal,
+        ("ne", Some(o)) => o != Ordering::Equal,
+        ("gt", Some(o)) => o == Ordering::Greater,
+        ("gte", Some(o)) => o != Ordering::Less,
+        ("lt", Some(o)) => o == Ordering::Less,
+        ("lte", Some(o)) => o != Ordering::Greater,
+        _ => false,
+    }
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::config::WebhookSettings;
+
+/// A job-completed or job-failed notification, in the layout of the OpenAI webhook events.
+#[derive(Clone, Debug, Serialize)]
+pub struct WebhookEvent {
+    pub id: String,
+    pub object: String,
+    /// The kind of event, e.g. `generation.completed`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub created_at: i64,
+    /// The job the event is about, as its endpoint returns it.
+    pub data: Value,
+}
+
+impl WebhookEvent {
+    /// Creates an event about a job.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of event, e.g. `generation.completed`.
+    /// * `data` - The job, as its endpoint returns it.
+    pub fn new(kind: impl Into<String>, data: &impl Serialize) -> Self {
+        Self {
+            id: format!("evt_{}", Uuid::new_v4().simple()),
+            object: "event".to_string(),
+            kind: kind.into(),
+            created_at: Utc::now().timestamp(),
+            data: serde_json::to_value(data).unwrap_or(Value::Null),
+        }
+    }
+}
+
+/// Sends an event to the registered webhooks subscribed to it, and to the webhook of
+/// the request of the job if it is still allowed, in the background.
+///
+/// # Arguments
+///
+/// * `settings` - The registered webhooks and how they are called.
+/// * `event` - The event to send.
+/// * `request_url` - The webhook named by the request of the job, if any.
+pub fn spawn_delivery(settings: WebhookSettings, event: WebhookEvent, request_url: Option<String>) {
+    let urls: Vec<String> = settings
+        .endpoints
+        .iter()
+        .filter(|endpoint| endpoint.subscribes_to(&event.kind))
+        .map(|endpoint| endpoint.url.clone())
+        .chain(request_url.filter(|url| settings.allows_request_url(url)))
+        .collect();
+    if urls.is_empty() {
+        return;
+    }
+
+    tokio::task::spawn_blocking(move || {
+        for url in urls {
+            match deliver(&settings, &url, &event) {
+                Ok(()) => debug!("Sent {} {} to {url}", event.kind, event.id),
+                Err(err) => warn!("Error sending {} {}: {err:#}", event.kind, event.id),
+            }
+        }
+    });
+}
+
+/// Posts an event to a webhook, signed with the configured secret, blocking the thread
+/// until it is accepted or every attempt failed.
+///
+/// Every attempt carries the same `webhook-id`, so the receiver can drop the duplicates
+/// of an attempt that timed out after it was processed.
+///
+/// # Arguments
+///
+/// * `settings` - The signing secret, the timeout and the number of attempts.
+/// * `url` - The URL of the webhook.
+/// * `event` - The event to post.
+///
+/// # Errors
+///
+/// Returns the error of the last attempt if the webhook cannot be reached or answers with
+/// an error status every time.
+pub fn deliver(settings: &WebhookSettings, url: &str, event: &WebhookEvent) -> anyhow::Result<()> {
+    let body = serde_json::to_string(event)?;
+    // A redirect could send the event to a host the URL was not allowed to name
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(settings.timeout_secs))
+        .redirects(0)
+        .build();
+
+    let mut attempt = 1;
+    loop {
+        let timestamp = Utc::now().timestamp();
+        let mut request = agent
+            .post(url)
+            .set("Content-Type", "application/json")
+            .set("webhook-id", &event.id)
+            .set("webhook-timestamp", &timestamp.to_string());
+        if let Some(secret) = &settings.secret {
+            request = request.set(
+                "webhook-signature",
+                &sign(secret, &event.id, timestamp, &body)?,
+            );
+        }
+
+        match request.send_string(&body) {
+            Ok(_) => return Ok(()),
+            Err(err) if attempt < settings.max_attempts => {
+                debug!("Attempt {attempt} to call the webhook {url} failed: {err}");
+                std::thread::sleep(Duration::from_secs(1 << (attempt - 1).min(6)));
+                attempt += 1;
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Error calling the webhook {url}"));
+            }
+        }
+    }
+}
+
+/// The `webhook-signature` of an event, in the Standard Webhooks scheme: the base64
+/// HMAC-SHA256 of `{id}.{timestamp}.{body}`, prefixed with `v1,`.
+///
+/// A secret prefixed with `whsec_` is the base64 of the key, as the Standard Webhooks
+/// libraries of the receivers expect, any other secret is used as the key as is.
+///
+/// # Arguments
+///
+/// * `secret` - The signing secret shared with the receivers.
+/// * `id` - The id of the event.
+/// * `timestamp` - When the event is sent, in seconds since the epoch.
+/// * `body` - The JSON body of the request.
+///
+/// # Errors
+///
+/// Returns an error if a `whsec_` secret is not valid base64.
+pub fn sign(secret: &str, id: &str, timestamp: i64, body: &str) -> anyhow::Result<String> {
+    let key = match secret.strip_prefix("whsec_") {
+        Some(encoded) => STANDARD
+            .decode(encoded)
+            .context("The webhook secret starts with 'whsec_' but is not valid base64")?,
+        None => secret.as_bytes().to_vec(),
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC accepts keys of any size");
+    mac.update(format!("{id}.{timestamp}.{body}").as_bytes());
+    Ok(format!(
+        "v1,{}",
+        STANDARD.encode(mac.finalize().into_bytes())
+    ))
+}
+
+/// Whether a webhook URL can be called, an absolute `http` or `https` URL.
+pub fn is_valid_url(url: &str) -> bool {
+    ["http://", "https://"]
+        .iter()
+        .any(|scheme| url.len() > scheme.len() && url.starts_with(scheme))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_the_standard_webhooks_example() {
+        // The example of the Standard Webhooks specification
+        let signature = sign(
+            "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw",
+            "msg_p5jXN8AQM9LWM0D4loKWxJek",
+            1614265330,
+            r#"{"test": 2432232314}"#,
+        )
+        .unwrap();
+        assert_eq!(signature, "v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE=");
+
+        assert!(sign("whsec_not base64!", "msg", 0, "{}").is_err());
+    }
+}
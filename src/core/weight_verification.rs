@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::{bail, Context};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use hf_hub::api::sync::ApiRepo;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::VerificationSettings;
+use crate::core::bundle::{hash_file, BundleManifest};
+
+/// Whether the served weights were checked against expected digests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationStatus {
+    /// Every weight file matched the digest of at least one source.
+    Verified,
+    /// Some weight files could not be checked against any digest.
+    Unverified,
+}
+
+/// How the served weights were verified, reported in the metadata of the model.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WeightVerification {
+    pub status: VerificationStatus,
+    /// Where the expected digests came from: `hub`, `manifest` or `bundle`.
+    pub sources: Vec<String>,
+    /// The number of weight files whose digest was checked.
+    pub verified_files: usize,
+    /// Whether the manifest signature was checked against the configured public key.
+    pub signed: bool,
+}
+
+/// Expected digests of weight files, by file name.
+pub struct DigestSource {
+    /// Where the digests come from, e.g. `hub`.
+    pub name: &'static str,
+    /// The hex SHA-256 digests, by file name.
+    pub digests: HashMap<String, String>,
+    /// Whether every weight file must be listed.
+    pub complete: bool,
+    /// Whether the digests were signed and the signature was checked.
+    pub signed: bool,
+}
+
+/// The files of a revision as the Hub lists them with `blobs=true`.
+#[derive(Deserialize)]
+struct HubRevision {
+    siblings: Vec<HubFile>,
+}
+
+#[derive(Deserialize)]
+struct HubFile {
+    rfilename: String,
+    /// Set for the files stored with Git LFS, such as the weights.
+    lfs: Option<HubLfs>,
+}
+
+#[derive(Deserialize)]
+struct HubLfs {
+    sha256: String,
+}
+
+/// A weight manifest, as configured in `model.verification.manifest`.
+#[derive(Deserialize)]
+struct WeightManifest {
+    files: HashMap<String, String>,
+}
+
+/// Reads the SHA-256 digests the Hub lists for the LFS files of a revision.
+///
+/// # Arguments
+///
+/// * `repo` - The model repository, at the revision of the weights.
+///
+/// # Errors
+///
+/// Returns an error if the Hub cannot be reached or answers an unexpected body.
+pub fn hub_digests(repo: &ApiRepo) -> anyhow::Result<DigestSource> {
+    let response = repo
+        .info_request()
+        .query("blobs", "true")
+        .call()
+        .context("Error reading the file digests from the Hub")?;
+    let revision: HubRevision = serde_json::from_reader(response.into_reader())?;
+
+    Ok(DigestSource {
+        name: "hub",
+        digests: revision
+            .siblings
+            .into_iter()
+            .filter_map(|file| {
+                let name = file.rfilename.rsplit('/').next()?.to_string();
+                Some((name, file.lfs?.sha256))
+            })
+            .collect(),
+        complete: false,
+        signed: false,
+    })
+}
+
+/// Reads the configured weight manifest, checking its signature when a public key is set.
+///
+/// # Arguments
+///
+/// * `settings` - The manifest, its signature and the public key.
+///
+/// # Returns
+///
+/// The digests of the manifest, `None` when no manifest is configured.
+///
+/// # Errors
+///
+/// Returns an error if the manifest cannot be read or parsed, or its signature is
+/// missing or invalid while a public key is set.
+pub fn manifest_digests(settings: &VerificationSettings) -> anyhow::Result<Option<DigestSource>> {
+    let Some(path) = &settings.manifest else {
+        return Ok(None);
+    };
+    let content =
+        std::fs::read(path).with_context(|| format!("Error reading {}", path.display()))?;
+
+    let signed = match &settings.public_key {
+        Some(public_key) => {
+            let signature_path = settings.manifest_signature.clone().unwrap_or_else(|| {
+                let mut signature = path.as_os_str().to_owned();
+                signature.push(".sig");
+                PathBuf::from(signature)
+            });
+            let signature = std::fs::read_to_string(&signature_path)
+                .with_context(|| format!("Error reading {}", signature_path.display()))?;
+            check_signature(public_key, signature.trim(), &content)
+                .with_context(|| format!("The signature of {} is invalid", path.display()))?;
+            true
+        }
+        None => false,
+    };
+    let manifest: WeightManifest = serde_json::from_slice(&content)
+        .with_context(|| format!("Invalid weight manifest {}", path.display()))?;
+
+    Ok(Some(DigestSource {
+        name: "manifest",
+        digests: manifest
+            .files
+            .into_iter()
+            .map(|(name, digest)| (name, digest.to_lowercase()))
+            .collect(),
+        complete: true,
+        signed,
+    }))
+}
+
+/// The digests of the files of a bundle, checked again when it is loaded since its
+/// unpacked directory is reused.
+pub fn bundle_digests(manifest: &BundleManifest) -> DigestSource {
+    DigestSource {
+        name: "bundle",
+        digests: manifest
+            .files
+            .iter()
+            .map(|file| (file.name.clone(), file.sha256.clone()))
+            .collect(),
+        complete: true,
+        signed: false,
+    }
+}
+
+/// Checks a base64 Ed25519 signature of a message.
+fn check_signature(public_key: &str, signature: &str, message: &[u8]) -> anyhow::Result<()> {
+    let public_key: [u8; 32] = STANDARD
+        .decode(public_key)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("The public key is not a 32-byte Ed25519 key"))?;
+    let signature: [u8; 64] = STANDARD
+        .decode(signature)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("The signature is not a 64-byte Ed25519 signature"))?;
+
+    VerifyingKey::from_bytes(&public_key)?.verify(message, &Signature::from_bytes(&signature))?;
+    Ok(())
+}
+
+/// Checks the weight files against the expected digests before they are loaded.
+///
+/// The files are hashed by `threads` threads at once, which also reads them into the page
+/// cache ahead of the load. A file whose digest differs from the one of any source fails
+/// the verification, as does a file missing from a source that must list them all.
+///
+/// # Arguments
+///
+/// * `filenames` - The weight files.
+/// * `sources` - The expected digests.
+/// * `settings` - Whether weights that cannot be checked are refused.
+/// * `threads` - The number of files hashed at once.
+///
+/// # Returns
+///
+/// How the weights were verified.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be read, does not match its digest, is missing from
+/// a complete source, or cannot be checked at all while `require` is set.
+pub fn verify_weights(
+    filenames: &[PathBuf],
+    sources: &[DigestSource],
+    settings: &VerificationSettings,
+    threads: usize,
+) -> anyhow::Result<WeightVerification> {
+    let names = filenames
+        .iter()
+        .map(|path| file_name(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for source in sources.iter().filter(|source| source.complete) {
+        if let Some(name) = names
+            .iter()
+            .find(|name| !source.digests.contains_key(**name))
+        {
+            bail!("{name} is not listed by the {} digests", source.name);
+        }
+    }
+
+    let checked: Vec<bool> = names
+        .iter()
+        .map(|name| {
+            sources
+                .iter()
+                .any(|source| source.digests.contains_key(*name))
+        })
+        .collect();
+    let verified_files = checked.iter().filter(|checked| **checked).count();
+    if verified_files < filenames.len() && settings.require {
+        let name = names
+            .iter()
+            .zip(&checked)
+            .find(|(_, checked)| !**checked)
+            .map_or("", |(name, _)| *name);
+        bail!("No digest to verify {name} against, and `model.verification.require` is set");
+    }
+
+    let started = Instant::now();
+    let digests = hash_files(filenames, &checked, threads)?;
+    for (name, digest) in names.iter().zip(&digests) {
+        let Some(digest) = digest else {
+            continue;
+        };
+        for source in sources {
+            match source.digests.get(*name) {
+                Some(expected) if expected != digest => bail!(
+                    "{name} does not match its {} digest, refusing to serve tampered or corrupted weights",
+                    source.name
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    let status = if verified_files == filenames.len() {
+        VerificationStatus::Verified
+    } else {
+        warn!(
+            "{} of {} weight files have no digest to verify them against",
+            filenames.len() - verified_files,
+            filenames.len()
+        );
+        VerificationStatus::Unverified
+    };
+    if verified_files > 0 {
+        info!(
+            "Verified the digests of {verified_files} weight files in {:.2?}",
+            started.elapsed()
+        );
+    }
+
+    Ok(WeightVerification {
+        status,
+        sources: sources
+            .iter()
+            .map(|source| source.name.to_string())
+            .collect(),
+        verified_files,
+        signed: sources.iter().any(|source| source.signed),
+    })
+}
+
+/// Hashes the files marked in `selected`, `threads` at a time.
+fn hash_files(
+    filenames: &[PathBuf],
+    selected: &[bool],
+    threads: usize,
+) -> anyhow::Result<Vec<Option<String>>> {
+    let digests = Mutex::new(vec![None; filenames.len()]);
+    let next = AtomicUsize::new(0);
+    let failure = Mutex::new(None);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, filenames.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= filenames.len() {
+                    return;
+                }
+                if !selected[index] {
+                    continue;
+                }
+                match hash_file(&filenames[index]) {
+                    Ok((_, digest)) => {
+                        digests.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(digest)
+                    }
+                    Err(err) => {
+                        *failure.lock().unwrap_or_else(|e| e.into_inner()) = Some(err);
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(err) = failure.into_inner().unwrap_or_else(|e| e.into_inner()) {
+        return Err(err);
+    }
+    Ok(digests.into_inner().unwrap_or_else(|e| e.into_inner()))
+}
+
+fn file_name(path: &Path) -> anyhow::Result<&str> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid weight file {}", path.display()))
+}
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::config::WorkerSettings;
+use crate::openai::proxy::{Upstream, WORKER_HEALTH_PATH};
+
+/// The command line argument making a process serve as a worker on a loopback port.
+pub const WORKER_PORT_ARG: &str = "--worker-port";
+
+/// Spawns the worker processes and restarts the ones that exit.
+///
+/// Every worker is this executable started again with [`WORKER_PORT_ARG`], so it loads
+/// the model and serves the API on its own loopback port, with the same configuration
+/// and environment. Worker `i` only sees the GPU `devices[i]`, or GPU `i` when no
+/// devices are configured, through `CUDA_VISIBLE_DEVICES`. A worker that crashes or
+/// runs out of memory is restarted after `restart_delay_secs`, and is not sent requests
+/// until its health check passes again.
+///
+/// # Arguments
+///
+/// * `settings` - The settings of the workers.
+///
+/// # Returns
+///
+/// The upstream of every worker, and the handles of the tasks supervising them.
+///
+/// # Errors
+///
+/// Returns an error if the path of the executable cannot be found.
+pub fn spawn_workers(
+    settings: &WorkerSettings,
+) -> anyhow::Result<(Vec<Arc<Upstream>>, Vec<JoinHandle<()>>)> {
+    let executable = std::env::current_exe()?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let restart_delay = Duration::from_secs(settings.restart_delay_secs);
+
+    let mut upstreams = Vec::with_capacity(settings.count);
+    let mut tasks = Vec::with_capacity(settings.count);
+    for index in 0..settings.count {
+        let port = settings.base_port + index as u16;
+        let device = settings.devices.get(index).copied().unwrap_or(index);
+        let upstream = Arc::new(Upstream::new(
+            format!("http://127.0.0.1:{port}"),
+            WORKER_HEALTH_PATH,
+        ));
+        upstreams.push(upstream.clone());
+
+        let mut command = Command::new(&executable);
+        command
+            .args(&args)
+            .arg(format!("{WORKER_PORT_ARG}={port}"))
+            .env("CUDA_VISIBLE_DEVICES", device.to_string())
+            .kill_on_drop(true);
+
+        tasks.push(tokio::spawn(async move {
+            loop {
+                info!("Starting worker {index} on port {port} with GPU {device}");
+                let status = match command.spawn() {
+                    Ok(mut child) => child.wait().await,
+                    Err(err) => Err(err),
+                };
+                upstream.set_healthy(false);
+                match status {
+                    Ok(status) => error!("Worker {index} exited with {status}"),
+                    Err(err) => error!("Worker {index} could not run: {err}"),
+                }
+                tokio::time::sleep(restart_delay).await;
+            }
+        }));
+    }
+
+    Ok((upstreams, tasks))
+}
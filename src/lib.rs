@@ -1,2 +1,3 @@
+pub mod config;
+pub mod core;
 pub mod openai;
-pub mod core;
\ No newline at end of file
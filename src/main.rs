@@ -1,64 +1,756 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use axum::{
     body::Bytes,
-    extract::MatchedPath,
+    extract::{DefaultBodyLimit, MatchedPath},
+    handler::Handler,
     http::{HeaderMap, Request},
+    middleware,
     response::Response,
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 
+use synap_forge_llm::config::{NetworkSettings, ServerConfig};
+use synap_forge_llm::core::access_log::AccessLog;
+use synap_forge_llm::core::bench::{compare_reports, read_report, run_bench};
+use synap_forge_llm::core::bundle::{create_bundle, open_bundle};
+use synap_forge_llm::core::evaluation::evaluate;
 use synap_forge_llm::core::load_model::initialise_model;
+use synap_forge_llm::core::logging::LogFilter;
+use synap_forge_llm::core::quantization_check::compare_quantization;
+use synap_forge_llm::core::quantize::Quantization;
+use synap_forge_llm::core::self_test::run_self_test;
+use synap_forge_llm::core::workers::{spawn_workers, WORKER_PORT_ARG};
+use synap_forge_llm::openai::access_log::{log_access, AccessLogState};
+use synap_forge_llm::openai::admin_service::{
+    get_log_level, list_audit, list_cache, purge_cache, reload_config, require_admin, set_log_level,
+};
+use synap_forge_llm::openai::agents_service::run_agent;
+use synap_forge_llm::openai::assistants_service::{
+    create_assistant, create_message, create_run, create_thread, delete_assistant, delete_thread,
+    list_assistants, list_messages, list_runs, modify_assistant, retrieve_assistant, retrieve_run,
+    retrieve_thread,
+};
+use synap_forge_llm::openai::audio_service::{create_speech, create_transcription};
+use synap_forge_llm::openai::azure_service::{
+    azure_chat_completion, azure_completion, azure_embedding,
+};
+use synap_forge_llm::openai::classification_service::create_classification;
+use synap_forge_llm::openai::conversations_service::{
+    delete_conversation, list_conversations, retrieve_conversation,
+};
+use synap_forge_llm::openai::files_service::{
+    delete_file, list_files, retrieve_file, retrieve_file_content, upload_file,
+};
+use synap_forge_llm::openai::generations_service::{create_generation, retrieve_generation};
 use synap_forge_llm::openai::http_service::{
-    create_chat_completion, create_completion, create_embedding, delete_model, health, list_models,
-    retrieve_model,
+    create_chat_completion, create_completion, create_embedding, delete_model, health,
+    health_dependencies, list_models, retrieve_model,
+};
+use synap_forge_llm::openai::metrics_service::metrics;
+use synap_forge_llm::openai::network::check_client_address;
+use synap_forge_llm::openai::proxy::{
+    list_split_usage, list_upstream_models, proxy_request, spawn_health_checks, ProxyState,
+    Upstream,
+};
+use synap_forge_llm::openai::rag_service::{
+    create_rag_document, delete_rag_document, list_rag_documents, query_rag,
+};
+use synap_forge_llm::openai::realtime_service::realtime;
+use synap_forge_llm::openai::responses_service::create_response;
+use synap_forge_llm::openai::scoring_service::create_score;
+use synap_forge_llm::openai::traffic_split::TrafficSplit;
+use synap_forge_llm::openai::vector_stores_service::{
+    create_vector_store, create_vector_store_file, create_vector_store_file_batch,
+    delete_vector_store, delete_vector_store_file, list_vector_store_files, list_vector_stores,
+    modify_vector_store, retrieve_vector_store, retrieve_vector_store_file,
+    retrieve_vector_store_file_batch, search_vector_store,
 };
 use tower_http::classify::ServerErrorsFailureClass;
 use tower_http::trace::TraceLayer;
 use tracing::log::error;
 use tracing::{info, info_span, Span};
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
+
+/// Maximum size of an audio upload, matching the OpenAI API limit of 25 MB.
+const AUDIO_UPLOAD_LIMIT: usize = 25 * 1024 * 1024;
+
+/// The flags that apply to every command, each taking a value.
+const GLOBAL_ARGS: [&str; 4] = ["--revision", "--cache-dir", "--model-uri", "--bundle"];
+
+/// The command line arguments without the global flags and their values, so that a
+/// subcommand such as `bench` can be given before or after `--revision <commit>`.
+fn command_args() -> Vec<String> {
+    let mut args = std::env::args().skip(1);
+    let mut command = Vec::new();
+    while let Some(arg) = args.next() {
+        let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+        if !GLOBAL_ARGS.contains(&name) {
+            command.push(arg);
+        } else if !arg.contains('=') {
+            args.next();
+        }
+    }
+
+    command
+}
+
+/// Reads the `--cache-dir <path>` command line argument, which overrides `hub.cache_dir`.
+fn cache_dir_arg() -> Result<Option<PathBuf>> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(path) = arg.strip_prefix("--cache-dir=") {
+            return Ok(Some(PathBuf::from(path)));
+        }
+        if arg == "--cache-dir" {
+            let path = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("--cache-dir expects a directory"))?;
+            return Ok(Some(PathBuf::from(path)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the `--revision <commit, branch or tag>` command line argument, which overrides
+/// `model.revision`.
+fn revision_arg() -> Result<Option<String>> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(revision) = arg.strip_prefix("--revision=") {
+            return Ok(Some(revision.to_string()));
+        }
+        if arg == "--revision" {
+            let revision = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("--revision expects a commit, branch or tag"))?;
+            return Ok(Some(revision));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the `--model-uri <s3://bucket/path>` command line argument, which overrides
+/// `model.uri`.
+fn model_uri_arg() -> Result<Option<String>> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(uri) = arg.strip_prefix("--model-uri=") {
+            return Ok(Some(uri.to_string()));
+        }
+        if arg == "--model-uri" {
+            let uri = args.next().ok_or_else(|| {
+                anyhow::anyhow!("--model-uri expects an s3://bucket/path or gs://bucket/path URI")
+            })?;
+            return Ok(Some(uri));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the `--bundle <path>` command line argument, which overrides `model.bundle`.
+fn bundle_arg() -> Result<Option<PathBuf>> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(path) = arg.strip_prefix("--bundle=") {
+            return Ok(Some(PathBuf::from(path)));
+        }
+        if arg == "--bundle" {
+            let path = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("--bundle expects a bundle file"))?;
+            return Ok(Some(PathBuf::from(path)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The `bundle` subcommands.
+enum BundleCommand {
+    /// Packages the configured model into a bundle.
+    Create {
+        output: PathBuf,
+        quantize: Option<Quantization>,
+    },
+    /// Verifies a bundle and unpacks it to the local cache.
+    Load { input: PathBuf },
+}
+
+/// Reads the `bundle create --output <path> [--quantize int8|int4]` and
+/// `bundle load --input <path>` subcommands, which create or unpack an air-gapped bundle
+/// instead of serving the model.
+fn bundle_command_arg() -> Result<Option<BundleCommand>> {
+    let mut args = command_args().into_iter();
+    if args.next().as_deref() != Some("bundle") {
+        return Ok(None);
+    }
+    let command = args
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("bundle expects create or load"))?;
+    let mut path = None;
+    let mut quantize = None;
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("{arg} expects a value"))?;
+                (arg, value)
+            }
+        };
+        match (command.as_str(), name.as_str()) {
+            ("create", "--output") | ("load", "--input") => path = Some(PathBuf::from(value)),
+            ("create", "--quantize") => {
+                quantize = Some(
+                    serde_json::from_value(serde_json::Value::String(value))
+                        .map_err(|_| anyhow::anyhow!("--quantize expects int8 or int4"))?,
+                )
+            }
+            _ => bail!("Unknown bundle {command} argument {name}"),
+        }
+    }
+
+    match command.as_str() {
+        "create" => Ok(Some(BundleCommand::Create {
+            output: path.ok_or_else(|| anyhow::anyhow!("bundle create expects --output <file>"))?,
+            quantize,
+        })),
+        "load" => Ok(Some(BundleCommand::Load {
+            input: path.ok_or_else(|| anyhow::anyhow!("bundle load expects --input <file>"))?,
+        })),
+        _ => bail!("Unknown bundle command {command}, expected create or load"),
+    }
+}
+
+/// Reads the `eval --dataset <path>` subcommand, which evaluates the model instead of serving it.
+fn eval_dataset_arg() -> Result<Option<PathBuf>> {
+    let mut args = command_args().into_iter();
+    if args.next().as_deref() != Some("eval") {
+        return Ok(None);
+    }
+    while let Some(arg) = args.next() {
+        if let Some(path) = arg.strip_prefix("--dataset=") {
+            return Ok(Some(PathBuf::from(path)));
+        }
+        if arg == "--dataset" {
+            let path = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("--dataset expects a JSONL file"))?;
+            return Ok(Some(PathBuf::from(path)));
+        }
+    }
+
+    Err(anyhow::anyhow!("eval expects --dataset <file.jsonl>"))
+}
+
+/// The `compare-quantization` subcommand.
+struct QuantizationCheckArgs {
+    prompts: PathBuf,
+    quantization: Quantization,
+    max_tokens: i32,
+}
+
+/// Reads the `compare-quantization --prompts <path> [--quantize int8|int4]
+/// [--max-tokens <n>]` subcommand, which compares the model with a quantized version of
+/// it instead of serving it.
+fn quantization_check_arg() -> Result<Option<QuantizationCheckArgs>> {
+    let mut args = command_args().into_iter();
+    if args.next().as_deref() != Some("compare-quantization") {
+        return Ok(None);
+    }
+    let mut prompts = None;
+    let mut quantization = Quantization::Int4;
+    let mut max_tokens = 64;
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("{arg} expects a value"))?;
+                (arg, value)
+            }
+        };
+        match name.as_str() {
+            "--prompts" => prompts = Some(PathBuf::from(value)),
+            "--quantize" => {
+                quantization = serde_json::from_value(serde_json::Value::String(value))
+                    .map_err(|_| anyhow::anyhow!("--quantize expects int8 or int4"))?
+            }
+            "--max-tokens" => max_tokens = value.parse()?,
+            _ => bail!("Unknown compare-quantization argument {name}"),
+        }
+    }
+
+    let prompts = prompts
+        .ok_or_else(|| anyhow::anyhow!("compare-quantization expects --prompts <file.jsonl>"))?;
+    Ok(Some(QuantizationCheckArgs {
+        prompts,
+        quantization,
+        max_tokens,
+    }))
+}
+
+/// The `bench` subcommand.
+struct BenchArgs {
+    prompts: Option<PathBuf>,
+    runs: usize,
+    max_tokens: i32,
+    /// Where the JSON report is written, printed when `None`.
+    output: Option<PathBuf>,
+    /// The report to compare with, for `bench compare`.
+    baseline: Option<PathBuf>,
+    threshold_percent: f64,
+}
+
+/// Reads the `bench [--prompts <path>] [--runs <n>] [--max-tokens <n>] [--output <path>]`
+/// subcommand, which benchmarks the model instead of serving it, and
+/// `bench compare <baseline.json> [--threshold <percent>]` with the same options, which
+/// also compares the results with an earlier report.
+fn bench_arg() -> Result<Option<BenchArgs>> {
+    let mut args = command_args().into_iter().peekable();
+    if args.next().as_deref() != Some("bench") {
+        return Ok(None);
+    }
+    let mut bench = BenchArgs {
+        prompts: None,
+        runs: 3,
+        max_tokens: 128,
+        output: None,
+        baseline: None,
+        threshold_percent: 10.,
+    };
+    if args.peek().map(String::as_str) == Some("compare") {
+        args.next();
+        let baseline = args
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("bench compare expects a baseline report"))?;
+        bench.baseline = Some(PathBuf::from(baseline));
+    }
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("{arg} expects a value"))?;
+                (arg, value)
+            }
+        };
+        match name.as_str() {
+            "--prompts" => bench.prompts = Some(PathBuf::from(value)),
+            "--runs" => bench.runs = value.parse()?,
+            "--max-tokens" => bench.max_tokens = value.parse()?,
+            "--output" => bench.output = Some(PathBuf::from(value)),
+            "--threshold" if bench.baseline.is_some() => bench.threshold_percent = value.parse()?,
+            _ => bail!("Unknown bench argument {name}"),
+        }
+    }
+
+    Ok(Some(bench))
+}
+
+/// Whether the `--self-test` flag asks to check the loaded models and exit instead of serving.
+fn self_test_arg() -> bool {
+    std::env::args().skip(1).any(|arg| arg == "--self-test")
+}
+
+/// Reads the `--worker-port <port>` argument the router starts its worker processes with.
+fn worker_port_arg() -> Result<Option<u16>> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(port) = arg.strip_prefix(&format!("{WORKER_PORT_ARG}=")) {
+            return Ok(Some(port.parse()?));
+        }
+        if arg == WORKER_PORT_ARG {
+            let port = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("{WORKER_PORT_ARG} expects a port"))?;
+            return Ok(Some(port.parse()?));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Serves the API by forwarding every request to worker processes.
+async fn serve_workers(settings: ServerConfig) -> Result<()> {
+    let (upstreams, _workers) = spawn_workers(&settings.workers)?;
+    // A request failing on a crashed worker is retried once on another one
+    let proxy = ProxyState::new(upstreams, 1, settings.limits.max_body_bytes)?;
+    let health_check = Duration::from_secs(settings.workers.health_check_secs.max(1));
+
+    info!(
+        "Routing requests to {} worker processes",
+        settings.workers.count
+    );
+    serve_proxy(Router::new(), proxy, health_check, &settings.network).await
+}
+
+/// Serves the API by forwarding every request to the configured upstream servers.
+async fn serve_gateway(settings: ServerConfig) -> Result<()> {
+    let upstreams = settings
+        .gateway
+        .upstreams
+        .iter()
+        .map(|upstream| Arc::new(Upstream::from_settings(upstream)))
+        .collect();
+    let splits = settings
+        .gateway
+        .splits
+        .iter()
+        .map(|(name, targets)| TrafficSplit::new(name.as_str(), targets))
+        .collect::<Result<Vec<_>>>()?;
+    let proxy = ProxyState::new(
+        upstreams,
+        settings.gateway.retries,
+        settings.limits.max_body_bytes,
+    )?
+    .with_splits(splits);
+    let proxy = if settings.gateway.shadows.is_empty() {
+        proxy
+    } else {
+        let Some(path) = &settings.gateway.shadow_log.path else {
+            bail!("gateway.shadow_log.path must be set to shadow models");
+        };
+        let log = AccessLog::open(path, &settings.gateway.shadow_log)?;
+        proxy.with_shadows(settings.gateway.shadows.clone(), Arc::new(log))
+    };
+    let health_check = Duration::from_secs(settings.gateway.health_check_secs.max(1));
+
+    info!(
+        "Routing requests to {} upstreams",
+        settings.gateway.upstreams.len()
+    );
+    // The models of the whole fleet are listed by the gateway itself
+    let router = Router::new()
+        .route("/v1/models", get(list_upstream_models))
+        .route("/v1/gateway/splits", get(list_split_usage));
+    serve_proxy(router, proxy, health_check, &settings.network).await
+}
+
+/// Serves a router forwarding the requests it does not handle itself to upstreams.
+async fn serve_proxy(
+    router: Router<ProxyState>,
+    proxy: ProxyState,
+    health_check: Duration,
+    network: &NetworkSettings,
+) -> Result<()> {
+    spawn_health_checks(proxy.clone(), health_check);
+
+    let router = router
+        .fallback(proxy_request)
+        .with_state(proxy)
+        .layer(TraceLayer::new_for_http());
+    let router = restrict_network(router, network);
+
+    let tcp_listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await?;
+    axum::serve(
+        tcp_listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Rejects the clients the `network` rules do not allow, if any rule is configured.
+fn restrict_network(router: Router, network: &NetworkSettings) -> Router {
+    if network.is_empty() {
+        return router;
+    }
+
+    router.layer(middleware::from_fn_with_state(
+        Arc::new(network.clone()),
+        check_client_address,
+    ))
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                // axum logs rejections from built-in extractors with the `axum::rejection`
-                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
-                "synap_forge_llm=debug,tower_http=debug,axum::rejection=trace".into()
-            }),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    let Ok(api_token) = std::env::var("HF_TOKEN") else {
-        return Err(anyhow::anyhow!("Error getting HF_TOKEN env var"));
+    let mut settings = ServerConfig::load()?;
+    let log_filter = LogFilter::init(
+        settings.log_level.as_deref(),
+        // axum logs rejections from built-in extractors with the `axum::rejection`
+        // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
+        "synap_forge_llm=debug,tower_http=debug,axum::rejection=trace",
+    )?;
+
+    if let Some(cache_dir) = cache_dir_arg()? {
+        settings.hub.cache_dir = Some(cache_dir);
+    }
+    if let Some(revision) = revision_arg()? {
+        settings.model.revision = revision;
+    }
+    if let Some(uri) = model_uri_arg()? {
+        settings.model.uri = Some(uri);
+    }
+    if let Some(bundle) = bundle_arg()? {
+        settings.model.bundle = Some(bundle);
+    }
+    let bundle_command = bundle_command_arg()?;
+    // Unpacking a bundle loads no model
+    if let Some(BundleCommand::Load { input }) = &bundle_command {
+        let (directory, manifest) = open_bundle(input, &settings.hub)?;
+        info!(
+            "The bundle of {} at revision {} is verified and unpacked to {}",
+            manifest.model_id,
+            manifest.revision,
+            directory.display()
+        );
+        return Ok(());
+    }
+    let eval_dataset = eval_dataset_arg()?;
+    let self_test = self_test_arg();
+    let quantization_check = quantization_check_arg()?;
+    let bench = bench_arg()?;
+    let worker_port = worker_port_arg()?;
+    // Evaluations and self-tests run on a model loaded in this process
+    let serves = worker_port.is_none()
+        && bundle_command.is_none()
+        && eval_dataset.is_none()
+        && !self_test
+        && quantization_check.is_none()
+        && bench.is_none();
+    if serves && settings.workers.count > 0 {
+        return serve_workers(settings).await;
+    }
+    // A gateway loads no model and needs no Hugging Face token
+    if serves && !settings.gateway.upstreams.is_empty() {
+        return serve_gateway(settings).await;
+    }
+
+    // A model loaded from a local directory, object storage or a bundle needs no Hugging
+    // Face token either
+    let api_token = match std::env::var("HF_TOKEN") {
+        Ok(api_token) => api_token,
+        Err(_)
+            if settings.model.path.is_some()
+                || settings.model.uri.is_some()
+                || settings.model.bundle.is_some() =>
+        {
+            String::new()
+        }
+        Err(_) => return Err(anyhow::anyhow!("Error getting HF_TOKEN env var")),
     };
+    if let Some(BundleCommand::Create { output, quantize }) = bundle_command {
+        let manifest = create_bundle(api_token, settings, quantize, &output)?;
+        info!(
+            "Wrote the bundle of {} at revision {} with {} files to {}",
+            manifest.model_id,
+            manifest.revision,
+            manifest.files.len(),
+            output.display()
+        );
+        return Ok(());
+    }
+    // Leave room for the multipart framing around the file content
+    let upload_limit = settings.files.max_file_bytes as usize + 64 * 1024;
+    let body_limit = settings.limits.max_body_bytes;
+    // Workers are only reached through the router, which applies the network rules
+    let network = match worker_port {
+        Some(_) => NetworkSettings::default(),
+        None => settings.network.clone(),
+    };
+    let access_log = match &settings.access_log.path {
+        Some(path) => Some(AccessLogState {
+            log: Arc::new(AccessLog::open(path, &settings.access_log)?),
+            max_body_bytes: body_limit,
+        }),
+        None => None,
+    };
+
+    if let Some(check) = quantization_check {
+        let mut reference = settings.clone();
+        reference.model.quantize = None;
+        let reference = initialise_model(api_token.clone(), reference)?;
+        settings.model.quantize = Some(check.quantization);
+        let quantized = initialise_model(api_token, settings)?;
+
+        info!(
+            "Comparing the {} model on {}",
+            check.quantization.as_str(),
+            check.prompts.display()
+        );
+        let report =
+            compare_quantization(&reference, &quantized, &check.prompts, check.max_tokens)?;
+        print!("{report}");
+        return Ok(());
+    }
 
     let before = Instant::now();
     info!("Model is loading in memory");
 
-    let state = initialise_model(api_token)?;
+    let state = initialise_model(api_token, settings)?.with_log_filter(log_filter);
+    if let Some(dataset) = eval_dataset {
+        info!("Evaluating the model on {}", dataset.display());
+        let report = evaluate(&state, &dataset)?;
+        print!("{report}");
+        return Ok(());
+    }
+    if let Some(bench) = bench {
+        // Read first, so that a wrong path fails before the benchmark runs
+        let baseline = bench.baseline.as_deref().map(read_report).transpose()?;
+        info!("Benchmarking the model");
+        let report = run_bench(
+            &state,
+            bench.prompts.as_deref(),
+            bench.runs,
+            bench.max_tokens,
+        )?;
+        let json = serde_json::to_string_pretty(&report)?;
+        match &bench.output {
+            Some(output) => std::fs::write(output, json)?,
+            None => println!("{json}"),
+        }
+        if let Some(baseline) = baseline {
+            let comparison = compare_reports(&baseline, &report, bench.threshold_percent);
+            eprint!("{comparison}");
+            if !comparison.passed() {
+                return Err(anyhow::anyhow!(
+                    "The benchmark regressed by more than {}%",
+                    bench.threshold_percent
+                ));
+            }
+        }
+        return Ok(());
+    }
+    if self_test {
+        let report = run_self_test(&state);
+        print!("{report}");
+        if !report.passed() {
+            return Err(anyhow::anyhow!("The self-test failed"));
+        }
+        return Ok(());
+    }
+    state.spawn_idle_unloader();
+    state.spawn_update_checker();
+    state.spawn_recovery();
+    #[cfg(unix)]
+    state.spawn_reload_on_hangup()?;
 
     info!(
         "Model loaded and is ready now with Elapsed time: {:.2?}",
         before.elapsed()
     );
 
+    let admin_router = Router::new()
+        .route("/cache", get(list_cache).delete(purge_cache))
+        .route("/reload", post(reload_config))
+        .route("/log-level", get(get_log_level).put(set_log_level))
+        .route("/audit", get(list_audit))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_admin))
+        .with_state(state.clone());
+
+    let azure_router = Router::new()
+        .route(
+            "/deployments/:deployment/chat/completions",
+            post(azure_chat_completion),
+        )
+        .route(
+            "/deployments/:deployment/completions",
+            post(azure_completion),
+        )
+        .route("/deployments/:deployment/embeddings", post(azure_embedding))
+        .with_state(state.clone());
+
     let openai_router = Router::new()
         .route("/health", get(health))
+        .route("/health/dependencies", get(health_dependencies))
         .route("/chat/completions", post(create_chat_completion))
         .route("/completions", post(create_completion))
+        .route("/responses", post(create_response))
+        .route("/agents/run", post(run_agent))
+        .route("/realtime", get(realtime))
+        .route("/assistants", get(list_assistants).post(create_assistant))
+        .route(
+            "/assistants/:assistant_id",
+            get(retrieve_assistant)
+                .post(modify_assistant)
+                .delete(delete_assistant),
+        )
+        .route("/threads", post(create_thread))
+        .route(
+            "/threads/:thread_id",
+            get(retrieve_thread).delete(delete_thread),
+        )
+        .route(
+            "/threads/:thread_id/messages",
+            get(list_messages).post(create_message),
+        )
+        .route("/threads/:thread_id/runs", get(list_runs).post(create_run))
+        .route("/threads/:thread_id/runs/:run_id", get(retrieve_run))
+        .route("/generations", post(create_generation))
+        .route("/generations/:generation_id", get(retrieve_generation))
         .route("/embeddings", post(create_embedding))
+        .route("/classifications", post(create_classification))
+        .route("/score", post(create_score))
         .route("/models", get(list_models))
         .route(
-            "/models/:model_id",
+            "/models/*model_id",
             get(retrieve_model).delete(delete_model),
         )
+        .route(
+            "/files",
+            get(list_files).post(upload_file.layer(DefaultBodyLimit::max(upload_limit))),
+        )
+        .route("/files/:file_id", get(retrieve_file).delete(delete_file))
+        .route("/files/:file_id/content", get(retrieve_file_content))
+        .route("/conversations", get(list_conversations))
+        .route(
+            "/rag/documents",
+            get(list_rag_documents).post(create_rag_document),
+        )
+        .route("/rag/documents/:document_id", delete(delete_rag_document))
+        .route("/rag/query", post(query_rag))
+        .route(
+            "/vector_stores",
+            get(list_vector_stores).post(create_vector_store),
+        )
+        .route(
+            "/vector_stores/:vector_store_id",
+            get(retrieve_vector_store)
+                .post(modify_vector_store)
+                .delete(delete_vector_store),
+        )
+        .route(
+            "/vector_stores/:vector_store_id/files",
+            get(list_vector_store_files).post(create_vector_store_file),
+        )
+        .route(
+            "/vector_stores/:vector_store_id/files/:file_id",
+            get(retrieve_vector_store_file).delete(delete_vector_store_file),
+        )
+        .route(
+            "/vector_stores/:vector_store_id/file_batches",
+            post(create_vector_store_file_batch),
+        )
+        .route(
+            "/vector_stores/:vector_store_id/file_batches/:batch_id",
+            get(retrieve_vector_store_file_batch),
+        )
+        .route(
+            "/vector_stores/:vector_store_id/search",
+            post(search_vector_store),
+        )
+        .route(
+            "/conversations/:conversation_id",
+            get(retrieve_conversation).delete(delete_conversation),
+        )
+        .route("/audio/speech", post(create_speech))
+        .route(
+            "/audio/transcriptions",
+            post(create_transcription.layer(DefaultBodyLimit::max(AUDIO_UPLOAD_LIMIT))),
+        )
         .with_state(state)
         .layer(
             TraceLayer::new_for_http()
@@ -75,23 +767,16 @@ async fn main() -> Result<()> {
                         uri = %request.uri(),
                         matched_path = matched_path,
                         version = ?request.version(),
-                        headers = ?request.headers(),
                     )
                 })
                 .on_request(|request: &Request<_>, _span: &Span| {
                     // Log when request starts
-                    info!(
-                        "Started {} request to {} body {:?}",
-                        request.method(),
-                        request.uri(),
-                        request.body()
-                    );
+                    info!("Started {} request to {}", request.method(), request.uri());
                 })
                 .on_response(|response: &Response, latency: Duration, _span: &Span| {
                     // Log response details
                     info!(
-                        "Response completed with body {:?} status {} in {:?}",
-                        response.body(),
+                        "Response completed with status {} in {:?}",
                         response.status(),
                         latency
                     );
@@ -121,11 +806,31 @@ async fn main() -> Result<()> {
                 ),
         );
 
-    let main_router = Router::new().nest("/v1", openai_router);
+    let mut main_router = Router::new()
+        .nest("/v1", openai_router)
+        .nest("/admin", admin_router)
+        .nest("/openai", azure_router)
+        .route("/metrics", get(metrics))
+        // Uploads set their own limit on their handler, which takes precedence
+        .layer(DefaultBodyLimit::max(body_limit));
+    main_router = restrict_network(main_router, &network);
+    if let Some(access_log) = access_log {
+        main_router = main_router.layer(middleware::from_fn_with_state(access_log, log_access));
+    }
 
-    let tcp_listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
+    // Workers are only reached through the router
+    let address = match worker_port {
+        Some(port) => format!("127.0.0.1:{port}"),
+        None => "0.0.0.0:8000".to_string(),
+    };
+    let tcp_listener = tokio::net::TcpListener::bind(address).await.unwrap();
 
-    axum::serve(tcp_listener, main_router).await.unwrap();
+    axum::serve(
+        tcp_listener,
+        main_router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 
     Ok(())
 }
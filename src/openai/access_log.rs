@@ -0,0 +1,92 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::Utc;
+
+use crate::core::access_log::{digest, AccessLog, AccessLogEntry};
+use crate::openai::errors::ApiError;
+
+/// The state of the access log middleware.
+#[derive(Clone)]
+pub struct AccessLogState {
+    pub log: Arc<AccessLog>,
+    /// The size limit of the bodies read for the log, the limit of the request bodies.
+    pub max_body_bytes: usize,
+}
+
+/// Writes every request to the access log.
+///
+/// The API key is logged as a digest only. JSON bodies are read and logged according
+/// to the content policy, file and audio uploads are never read.
+///
+/// # Arguments
+///
+/// * `state` - The access log and the request body limit.
+/// * `request` - The incoming request.
+/// * `next` - The rest of the middleware stack.
+///
+/// # Returns
+///
+/// The response of the endpoint, or a `413` error if the body is over the size limit.
+pub async fn log_access(
+    State(state): State<AccessLogState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let timestamp = Utc::now().to_rfc3339();
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let key = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|key| digest(key.as_bytes()));
+    let is_json = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+
+    let (response, body) = if state.log.logs_content() && is_json {
+        let (parts, body) = request.into_parts();
+        match axum::body::to_bytes(body, state.max_body_bytes).await {
+            Ok(bytes) => {
+                let logged = state.log.redact(&bytes);
+                let request = Request::from_parts(parts, Body::from(bytes));
+                (next.run(request).await, logged)
+            }
+            Err(_) => {
+                let error = ApiError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "invalid_request_error",
+                    format!(
+                        "The request body is larger than the limit of {} bytes",
+                        state.max_body_bytes
+                    ),
+                );
+                (error.into_response(), None)
+            }
+        }
+    } else {
+        (next.run(request).await, None)
+    };
+
+    state.log.record(&AccessLogEntry {
+        timestamp,
+        method,
+        path,
+        status: response.status().as_u16(),
+        latency_ms: start.elapsed().as_millis() as u64,
+        key,
+        body,
+    });
+
+    response
+}
@@ -0,0 +1,317 @@
+use crate::config::RELOADABLE_SECTIONS;
+use crate::core::access_log::digest;
+use crate::core::hub_cache::{
+    list_cached_models, purge_unused_revisions, PurgeReport, RevisionInUse,
+};
+use crate::core::logging::LogFilter;
+use crate::core::settings_reload::reload_settings;
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::models::{
+    AuditQuery, ListAuditResponse, ListCachedModelsResponse, LogLevel, PurgeCacheQuery,
+    ReloadConfigResponse,
+};
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Json;
+use tracing::info;
+
+/// Rejects requests to the admin endpoints without the configured admin key.
+///
+/// The key is expected as a bearer token in the `Authorization` header. The admin
+/// endpoints are disabled entirely when `admin.api_key` is not configured.
+///
+/// Requests changing something, and every rejected request, are recorded in the audit
+/// log with the digest of their key and their outcome.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `request` - The incoming request.
+/// * `next` - The rest of the middleware stack.
+///
+/// # Returns
+///
+/// The response of the endpoint, or an `ApiError` if the admin endpoints are
+/// disabled or the key is missing or wrong.
+pub async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let settings = state.settings.current();
+    let Some(api_key) = &settings.admin.api_key else {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "invalid_request_error",
+            "The admin endpoints are disabled, set `admin.api_key` to enable them",
+        ));
+    };
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let authorized =
+        provided.is_some_and(|provided| constant_time_eq(provided.as_bytes(), api_key.as_bytes()));
+    let actor = actor(request.headers());
+    let action = format!("{} {}", request.method(), request.uri().path());
+    let detail = request.uri().query().map(str::to_string);
+    let changes = !matches!(*request.method(), Method::GET | Method::HEAD);
+
+    if !authorized {
+        if let Some(audit) = &state.audit {
+            audit.record(&actor, &action, "unauthorized", detail.as_deref());
+        }
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "invalid_request_error",
+            "Incorrect admin API key provided",
+        )
+        .with_code("invalid_api_key"));
+    }
+
+    let response = next.run(request).await;
+    if let (Some(audit), true) = (&state.audit, changes) {
+        let status = response.status();
+        let outcome = match status.is_success() {
+            true => "success".to_string(),
+            false => format!("failed with status {}", status.as_u16()),
+        };
+        audit.record(&actor, &action, &outcome, detail.as_deref());
+    }
+
+    Ok(response)
+}
+
+/// Identifies the caller of an admin endpoint in the audit log by the digest of its key.
+fn actor(headers: &HeaderMap) -> String {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map_or_else(|| "anonymous".to_string(), |key| digest(key.as_bytes()))
+}
+
+/// Lists the models in the Hugging Face Hub cache with the size of each revision.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+///
+/// # Returns
+///
+/// The cached models wrapped in `Json`, or an `ApiError` if the cache cannot be read.
+pub async fn list_cache(
+    State(state): State<AppState>,
+) -> Result<Json<ListCachedModelsResponse>, ApiError> {
+    let cache_dir = state.settings.current().hub.cache_dir();
+    let in_use = revisions_in_use(&state);
+
+    let dir = cache_dir.clone();
+    let models = tokio::task::spawn_blocking(move || list_cached_models(&dir, &in_use))
+        .await
+        .map_err(ApiError::internal)?
+        .map_err(ApiError::internal)?;
+
+    Ok(Json(ListCachedModelsResponse {
+        object: "list".to_string(),
+        cache_dir: cache_dir.display().to_string(),
+        data: models,
+    }))
+}
+
+/// Removes the cached revisions the server does not use.
+///
+/// The revision of the served model and the revisions of the audio models are kept.
+/// With `?dry_run=true` nothing is removed and the response lists what would be.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `query` - Whether to only report what would be removed.
+///
+/// # Returns
+///
+/// The removed revisions and freed bytes wrapped in `Json`, or an `ApiError` if the
+/// cache cannot be read or a file cannot be removed.
+pub async fn purge_cache(
+    State(state): State<AppState>,
+    Query(query): Query<PurgeCacheQuery>,
+) -> Result<Json<PurgeReport>, ApiError> {
+    let cache_dir = state.settings.current().hub.cache_dir();
+    let in_use = revisions_in_use(&state);
+
+    let report = tokio::task::spawn_blocking(move || {
+        purge_unused_revisions(&cache_dir, &in_use, query.dry_run)
+    })
+    .await
+    .map_err(ApiError::internal)?
+    .map_err(ApiError::internal)?;
+
+    info!(
+        "Purged {} revisions ({} bytes) from the Hub cache, dry run: {}",
+        report.removed.len(),
+        report.freed_bytes,
+        report.dry_run
+    );
+
+    Ok(Json(report))
+}
+
+/// Reloads the runtime-tunable sections of the configuration file.
+///
+/// The sampling defaults and limits, the request limits, the admin key, the prompt
+/// presets and the log level are applied to the requests received from now on. Changing
+/// the served model or the other sections still requires a restart. Sending `SIGHUP` to
+/// the process has the same effect.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, identifying the caller in the audit log.
+///
+/// # Returns
+///
+/// The reloaded sections wrapped in `Json`, or an `ApiError` if the configuration file
+/// cannot be read or is invalid, the previous configuration then stays in effect.
+pub async fn reload_config(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<ReloadConfigResponse>, ApiError> {
+    let reloaded = {
+        let state = state.clone();
+        let actor = actor(&headers);
+        tokio::task::spawn_blocking(move || reload_settings(&state, &actor))
+            .await
+            .map_err(ApiError::internal)?
+            .map_err(|err| ApiError::invalid_request(format!("{err:#}")))?
+    };
+
+    Ok(Json(ReloadConfigResponse {
+        object: "config.reload".to_string(),
+        reloaded: RELOADABLE_SECTIONS.iter().map(|s| s.to_string()).collect(),
+        log_level: match &state.log_filter {
+            Some(filter) => Some(filter.current()),
+            None => reloaded.log_level.clone(),
+        },
+    }))
+}
+
+/// Returns the current filter of the logs.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+///
+/// # Returns
+///
+/// The log filter wrapped in `Json`, or an `ApiError` if the server did not install a
+/// log filter that can be changed.
+pub async fn get_log_level(State(state): State<AppState>) -> Result<Json<LogLevel>, ApiError> {
+    let filter = log_filter(&state)?;
+
+    Ok(Json(LogLevel {
+        level: filter.current(),
+    }))
+}
+
+/// Replaces the filter of the logs without restarting the server.
+///
+/// The new filter lasts until it is replaced again, by this endpoint or by a reload of a
+/// configuration setting `log_level`, and is lost on restart.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `request` - The new log filter, a level such as `debug` or per-target directives.
+///
+/// # Returns
+///
+/// The log filter now in effect wrapped in `Json`, or an `ApiError` if the filter is
+/// invalid or the server did not install a log filter that can be changed.
+pub async fn set_log_level(
+    State(state): State<AppState>,
+    Json(request): Json<LogLevel>,
+) -> Result<Json<LogLevel>, ApiError> {
+    let filter = log_filter(&state)?;
+    filter
+        .set(&request.level)
+        .map_err(|err| ApiError::invalid_request(format!("{err:#}")).with_param("level"))?;
+    info!("Log filter set to {}", request.level);
+
+    Ok(Json(LogLevel {
+        level: filter.current(),
+    }))
+}
+
+fn log_filter(state: &AppState) -> Result<&LogFilter, ApiError> {
+    state.log_filter.as_deref().ok_or_else(|| {
+        ApiError::not_found("The log level of this server cannot be changed at runtime")
+    })
+}
+
+/// Lists the recorded admin actions, the most recent first.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `query` - Filters the actions by name and pages through the log.
+///
+/// # Returns
+///
+/// The actions wrapped in `Json`, or an `ApiError` if auditing is disabled or the
+/// database fails.
+pub async fn list_audit(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<ListAuditResponse>, ApiError> {
+    let audit = state.audit.as_ref().ok_or_else(|| {
+        ApiError::not_found("The audit log is disabled, set `audit.database` to enable it")
+    })?;
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+
+    let mut data = audit
+        .list(query.action.as_deref(), query.before, limit + 1)
+        .map_err(ApiError::internal)?;
+    let has_more = data.len() > limit;
+    data.truncate(limit);
+
+    Ok(Json(ListAuditResponse {
+        object: "list".to_string(),
+        data,
+        has_more,
+    }))
+}
+
+/// The revisions of the models loaded by the server.
+fn revisions_in_use(state: &AppState) -> Vec<RevisionInUse> {
+    let settings = state.settings.current();
+    let mut in_use = vec![RevisionInUse {
+        model_id: settings.model.id.clone(),
+        revision: Some(state.model.revision()),
+    }];
+    // The audio models are loaded from their main branch
+    in_use.extend(
+        [
+            &settings.audio.transcription_model,
+            &settings.audio.speech_model,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|model_id| RevisionInUse {
+            model_id: model_id.clone(),
+            revision: None,
+        }),
+    );
+
+    in_use
+}
+
+/// Compares two byte strings in a time that does not depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,270 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::core::chat_template::ChatTemplate;
+use crate::core::deadline::Deadline;
+use crate::core::events::FinishReason;
+use crate::core::generator::{PromptInput, TextGeneration};
+use crate::core::sampling::SamplingParams;
+use crate::core::tools::{OfferedTools, ToolChoice};
+use crate::core::usage_meter::UsageMeter;
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::http_service::template_tokens;
+use crate::openai::limits::{
+    api_key, check_memory_pressure, check_message_count, check_model_access, check_prompt_tokens,
+    prompt_limits,
+};
+use crate::openai::models::{AgentRun, AgentStep, AgentStreamEvent, CreateAgentRunRequest};
+use crate::openai::request_json::RequestJson;
+use crate::openai::streaming::{api_error_data, stream_task, DONE};
+use crate::openai::validation::Validate;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::Utc;
+use tracing::info;
+use uuid::Uuid;
+
+/// Runs an agent: the model continues the conversation, calling the server tools, until
+/// it answers or the run reaches its budget.
+///
+/// Every iteration renders the conversation with the chat template of the served model
+/// and generates; when the output calls server tools, they are run and the call and
+/// their results are added to the conversation as `assistant` and `tool` messages before
+/// the next iteration. The run ends `completed` when the model answers, or `incomplete`
+/// once it ran `max_iterations` generations, its `max_duration_secs` passed or a
+/// generation reached `max_tokens`. Both are capped by the `agents` settings.
+///
+/// With `stream: true` every step is sent as an `agent.step` server-sent event as soon as
+/// it ends, followed by an `agent.run.completed` event holding the run.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key selects the tools.
+/// * `request` - The `CreateAgentRunRequest` with the conversation and the budget.
+///
+/// # Returns
+///
+/// The `AgentRun` wrapped in `Json` with the usage headers of its generations, or the SSE
+/// stream of its steps, or an `ApiError` if agent runs are disabled, the request is
+/// invalid or names a tool the key may not use, or a generation fails.
+pub async fn run_agent(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateAgentRunRequest>,
+) -> Result<Response, ApiError> {
+    let settings = state.settings.current();
+    if !settings.agents.enabled {
+        return Err(ApiError::not_found(
+            "Agent runs are disabled, set `agents.enabled` to enable them",
+        ));
+    }
+    request.validate(&settings.model.id)?;
+    check_model_access(&state, &headers, &request.model)?;
+    let limits = prompt_limits(&state, &headers);
+    check_message_count(&limits, request.messages.len(), "messages")?;
+
+    let mut tools = OfferedTools::new(&state, api_key(&headers), &ToolChoice::Auto, &[]).await;
+    if let Some(names) = &request.tools {
+        if let Some((index, name)) = names
+            .iter()
+            .enumerate()
+            .find(|(_, name)| !tools.contains(name))
+        {
+            return Err(ApiError::invalid_request(format!(
+                "'{name}' is not a server tool available to this key - 'tools.{index}'"
+            ))
+            .with_param(format!("tools.{index}")));
+        }
+        tools.retain(names);
+    }
+
+    let max_iterations = request
+        .max_iterations
+        .unwrap_or(settings.agents.max_iterations)
+        .min(settings.agents.max_iterations)
+        .max(1);
+    let max_duration = request
+        .max_duration_secs
+        .unwrap_or(f64::MAX)
+        .min(settings.agents.max_duration_secs as f64);
+    let params = SamplingParams::default()
+        .with_temperature(request.temperature)
+        .with_top_p(request.top_p)
+        .with_max_tokens(request.max_tokens);
+    let chat_template = state.chat_template.clone().unwrap_or_default();
+
+    let turns: Vec<(String, String)> = tools
+        .prompt()
+        .into_iter()
+        .chain(request.instructions)
+        .map(|content| ("system".to_string(), content))
+        .chain(
+            request
+                .messages
+                .into_iter()
+                .map(|message| (message.role, message.content)),
+        )
+        .collect();
+    let prompt = PromptInput::Text(chat_template.render(&turns, &template_tokens(&state))?);
+    check_prompt_tokens(&limits, &state.tokenizer, &prompt, "messages")?;
+    check_memory_pressure(&state, &prompt, request.max_tokens)?;
+
+    let meter = Arc::new(UsageMeter::default());
+    let agent = Agent {
+        state: state.clone(),
+        tools,
+        params,
+        chat_template,
+        turns,
+        max_iterations,
+        deadline: Deadline::after(Duration::from_secs_f64(max_duration)),
+        meter: meter.clone(),
+        run: AgentRun {
+            id: format!("run_{}", Uuid::new_v4().simple()),
+            object: "agent.run".to_string(),
+            created_at: Utc::now().timestamp(),
+            model: settings.model.id.clone(),
+            status: "in_progress".to_string(),
+            incomplete_reason: None,
+            output: String::new(),
+            steps: Vec::new(),
+            iterations: 0,
+            usage: meter.usage().into(),
+        },
+    };
+
+    if request.stream.unwrap_or(false) {
+        let stream_id = agent.run.id.clone();
+        let released = meter.clone();
+        let owner = api_key(&headers);
+        let response = stream_task(&state, owner, stream_id, move |buffer| async move {
+            let result = agent
+                .run(|step| {
+                    let event = AgentStreamEvent::Step { step: step.clone() };
+                    buffer.push(serde_json::to_string(&event).unwrap_or_default());
+                })
+                .await;
+            match result {
+                Ok(run) => {
+                    let event = AgentStreamEvent::Completed { run };
+                    buffer.push(serde_json::to_string(&event).unwrap_or_default());
+                    buffer.push(DONE.to_string());
+                }
+                Err(err) => buffer.push(api_error_data(err)),
+            }
+            buffer.finish();
+            released.release();
+        });
+        return Ok((meter.partial(), response).into_response());
+    }
+
+    let run = agent.run(|_| {}).await?;
+    info!("run_agent is done");
+
+    Ok((StatusCode::OK, meter.usage(), Json(run)).into_response())
+}
+
+/// The state of an agent run.
+struct Agent {
+    state: AppState,
+    tools: OfferedTools,
+    params: SamplingParams,
+    chat_template: Arc<ChatTemplate>,
+    /// The roles and contents of the conversation, growing with every iteration.
+    turns: Vec<(String, String)>,
+    max_iterations: usize,
+    deadline: Deadline,
+    meter: Arc<UsageMeter>,
+    run: AgentRun,
+}
+
+impl Agent {
+    /// Runs the iterations of the agent until the model answers or the budget is spent.
+    ///
+    /// # Arguments
+    ///
+    /// * `on_step` - Called with every step once it ended.
+    ///
+    /// # Returns
+    ///
+    /// The ended run.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation cannot be rendered or a generation fails.
+    async fn run(mut self, mut on_step: impl FnMut(&AgentStep)) -> Result<AgentRun, ApiError> {
+        let mut incomplete_reason = None;
+        for iteration in 1..=self.max_iterations {
+            if self.deadline.remaining().is_zero() {
+                incomplete_reason = Some("max_duration");
+                break;
+            }
+            let prompt = self
+                .chat_template
+                .render(&self.turns, &template_tokens(&self.state))?;
+            let text_gen = TextGeneration::from_state(self.state.clone(), &self.params)?
+                .with_deadline(Some(self.deadline))
+                .with_meter(self.meter.clone());
+            let (text, finish_reason) =
+                tokio::task::spawn_blocking(move || text_gen.generate(prompt))
+                    .await
+                    .map_err(ApiError::internal)??;
+            self.run.iterations = iteration;
+
+            let calls_tools = finish_reason == FinishReason::Stop && self.tools.calls_tools(&text);
+            if !calls_tools || iteration == self.max_iterations {
+                incomplete_reason = match finish_reason {
+                    FinishReason::Stop if calls_tools => Some("max_iterations"),
+                    FinishReason::Stop => None,
+                    FinishReason::Length | FinishReason::LengthCapped => Some("max_tokens"),
+                    FinishReason::Timeout => Some("max_duration"),
+                    FinishReason::Repetition => Some("repetition"),
+                    FinishReason::ContentFilter => Some("content_filter"),
+                };
+                let step = AgentStep::Message {
+                    iteration,
+                    content: text.clone(),
+                };
+                on_step(&step);
+                self.run.steps.push(step);
+                self.run.output = text;
+                break;
+            }
+
+            let results = self
+                .tools
+                .execute(&self.state, &text)
+                .await
+                .unwrap_or_default();
+            self.turns.push(("assistant".to_string(), text));
+            for result in results {
+                self.turns.push((
+                    "tool".to_string(),
+                    format!("[{}] {}", result.name, result.output),
+                ));
+                let step = AgentStep::ToolCall {
+                    iteration,
+                    name: result.name,
+                    arguments: result.arguments,
+                    output: result.output,
+                };
+                on_step(&step);
+                self.run.steps.push(step);
+            }
+        }
+
+        self.run.status = match incomplete_reason {
+            Some(_) => "incomplete",
+            None => "completed",
+        }
+        .to_string();
+        self.run.incomplete_reason = incomplete_reason.map(str::to_string);
+        self.run.usage = self.meter.usage().into();
+
+        Ok(self.run)
+    }
+}
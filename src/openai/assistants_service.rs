@@ -0,0 +1,827 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::core::access_log::digest;
+use crate::core::assistants::{AssistantStore, AssistantStoreError};
+use crate::core::events::{FinishReason, GenerationEvent};
+use crate::core::generator::{PromptInput, TextGeneration};
+use crate::core::reasoning::ReasoningParser;
+use crate::core::sampling::SamplingParams;
+use crate::core::streams::StreamBuffer;
+use crate::core::usage_meter::UsageMeter;
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::http_service::template_tokens;
+use crate::openai::limits::{
+    api_key, check_memory_pressure, check_message_count, check_model_access, check_prompt_tokens,
+    prompt_limits,
+};
+use crate::openai::models::{
+    AssistantObject, CreateAssistantRequest, CreateMessageRequest, CreateRunRequest,
+    CreateThreadRequest, CursorPage, DeleteAssistantObjectResponse, IncompleteDetails,
+    MessageContent, MessageDeltaContent, MessageDeltaPart, MessageText, ModifyAssistantRequest,
+    RunError, RunObject, ThreadMessageDelta, ThreadMessageObject, ThreadObject,
+    VectorStoreListQuery,
+};
+use crate::openai::request_json::RequestJson;
+use crate::openai::streaming::{stream_task, DONE};
+use crate::openai::validation::{check_model, Validate};
+use crate::openai::vector_stores_service::paginate;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::Utc;
+use serde::Serialize;
+use tokio_stream::StreamExt;
+use tracing::{error, info};
+use uuid::Uuid;
+
+impl From<AssistantStoreError> for ApiError {
+    fn from(err: AssistantStoreError) -> Self {
+        match err {
+            AssistantStoreError::NotFound { .. } => ApiError::not_found(err.to_string()),
+            AssistantStoreError::Database(_) | AssistantStoreError::Serialization(_) => {
+                ApiError::internal(err)
+            }
+        }
+    }
+}
+
+/// Creates an assistant: the model, instructions and sampling parameters its runs use.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key owns the assistant.
+/// * `request` - The `CreateAssistantRequest` with the fields of the assistant.
+///
+/// # Returns
+///
+/// The `AssistantObject` wrapped in `Json`, or an `ApiError` if the Assistants API is
+/// disabled, the request is invalid or the database fails.
+pub async fn create_assistant(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateAssistantRequest>,
+) -> Result<Json<AssistantObject>, ApiError> {
+    request.validate(&state.settings.current().model.id)?;
+
+    let assistant = AssistantObject {
+        id: format!("asst_{}", Uuid::new_v4().simple()),
+        object: "assistant".to_string(),
+        created_at: Utc::now().timestamp(),
+        name: request.name,
+        description: request.description,
+        model: request.model,
+        instructions: request.instructions,
+        tools: request.tools,
+        metadata: request.metadata,
+        temperature: request.temperature,
+        top_p: request.top_p,
+    };
+    let owner = owner(&headers);
+    let assistant = query(&state, move |store| {
+        store.save_assistant(&assistant, owner.as_deref())?;
+        Ok::<_, AssistantStoreError>(assistant)
+    })
+    .await?;
+    info!("Assistant {} created", assistant.id);
+
+    Ok(Json(assistant))
+}
+
+/// Lists the assistants of the API key of the request.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key owns the assistants.
+/// * `page` - The `limit`, `order`, `after` and `before` pagination parameters.
+///
+/// # Returns
+///
+/// A page of `AssistantObject` wrapped in `Json`, or an `ApiError` if the Assistants API
+/// is disabled, the pagination parameters are invalid or the database fails.
+pub async fn list_assistants(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(page): Query<VectorStoreListQuery>,
+) -> Result<Json<CursorPage<AssistantObject>>, ApiError> {
+    let owner = owner(&headers);
+    let assistants = query(&state, move |store| store.assistants(owner.as_deref())).await?;
+
+    paginate(assistants, |assistant| &assistant.id, &page).map(Json)
+}
+
+/// Retrieves an assistant.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key must own the assistant.
+/// * `assistant_id` - The id of the assistant.
+///
+/// # Returns
+///
+/// The `AssistantObject` wrapped in `Json`, or an `ApiError` if the Assistants API is
+/// disabled or the assistant does not exist for this key.
+pub async fn retrieve_assistant(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(assistant_id): Path<String>,
+) -> Result<Json<AssistantObject>, ApiError> {
+    let owner = owner(&headers);
+    let assistant = query(&state, move |store| {
+        store.assistant(&assistant_id, owner.as_deref())
+    })
+    .await?;
+
+    Ok(Json(assistant))
+}
+
+/// Changes the fields of an assistant given in the request.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key must own the assistant.
+/// * `assistant_id` - The id of the assistant.
+/// * `request` - The `ModifyAssistantRequest` with the fields to change.
+///
+/// # Returns
+///
+/// The modified `AssistantObject` wrapped in `Json`, or an `ApiError` if the Assistants
+/// API is disabled, the assistant does not exist for this key or the request is invalid.
+pub async fn modify_assistant(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(assistant_id): Path<String>,
+    RequestJson(request): RequestJson<ModifyAssistantRequest>,
+) -> Result<Json<AssistantObject>, ApiError> {
+    request.validate(&state.settings.current().model.id)?;
+
+    let owner = owner(&headers);
+    let assistant = query(&state, move |store| {
+        let assistant = modify(store.assistant(&assistant_id, owner.as_deref())?, request);
+        store.save_assistant(&assistant, owner.as_deref())?;
+        Ok::<_, AssistantStoreError>(assistant)
+    })
+    .await?;
+
+    Ok(Json(assistant))
+}
+
+/// Deletes an assistant.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key must own the assistant.
+/// * `assistant_id` - The id of the assistant.
+///
+/// # Returns
+///
+/// The `DeleteAssistantObjectResponse` wrapped in `Json`, or an `ApiError` if the
+/// Assistants API is disabled or the assistant does not exist for this key.
+pub async fn delete_assistant(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(assistant_id): Path<String>,
+) -> Result<Json<DeleteAssistantObjectResponse>, ApiError> {
+    let owner = owner(&headers);
+    let id = assistant_id.clone();
+    query(&state, move |store| {
+        store.delete_assistant(&id, owner.as_deref())
+    })
+    .await?;
+    info!("Assistant {} deleted", assistant_id);
+
+    Ok(Json(DeleteAssistantObjectResponse {
+        id: assistant_id,
+        object: "assistant.deleted".to_string(),
+        deleted: true,
+    }))
+}
+
+/// Creates a thread, with the messages it starts with.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key owns the thread.
+/// * `request` - The `CreateThreadRequest` with the messages and metadata, `{}` for an
+///   empty thread.
+///
+/// # Returns
+///
+/// The `ThreadObject` wrapped in `Json`, or an `ApiError` if the Assistants API is
+/// disabled, a message is invalid or the database fails.
+pub async fn create_thread(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateThreadRequest>,
+) -> Result<Json<ThreadObject>, ApiError> {
+    request.validate(&state.settings.current().model.id)?;
+
+    let thread = ThreadObject {
+        id: format!("thread_{}", Uuid::new_v4().simple()),
+        object: "thread".to_string(),
+        created_at: Utc::now().timestamp(),
+        metadata: request.metadata,
+    };
+    let messages: Vec<_> = request
+        .messages
+        .into_iter()
+        .map(|message| new_message(&thread.id, message))
+        .collect();
+    let owner = owner(&headers);
+    let thread = query(&state, move |store| {
+        store.create_thread(&thread, &messages, owner.as_deref())?;
+        Ok::<_, AssistantStoreError>(thread)
+    })
+    .await?;
+    info!("Thread {} created", thread.id);
+
+    Ok(Json(thread))
+}
+
+/// Retrieves a thread.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key must own the thread.
+/// * `thread_id` - The id of the thread.
+///
+/// # Returns
+///
+/// The `ThreadObject` wrapped in `Json`, or an `ApiError` if the Assistants API is
+/// disabled or the thread does not exist for this key.
+pub async fn retrieve_thread(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(thread_id): Path<String>,
+) -> Result<Json<ThreadObject>, ApiError> {
+    let owner = owner(&headers);
+    let thread = query(&state, move |store| {
+        store.thread(&thread_id, owner.as_deref())
+    })
+    .await?;
+
+    Ok(Json(thread))
+}
+
+/// Deletes a thread with its messages and runs.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key must own the thread.
+/// * `thread_id` - The id of the thread.
+///
+/// # Returns
+///
+/// The `DeleteAssistantObjectResponse` wrapped in `Json`, or an `ApiError` if the
+/// Assistants API is disabled or the thread does not exist for this key.
+pub async fn delete_thread(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(thread_id): Path<String>,
+) -> Result<Json<DeleteAssistantObjectResponse>, ApiError> {
+    let owner = owner(&headers);
+    let id = thread_id.clone();
+    query(&state, move |store| {
+        store.delete_thread(&id, owner.as_deref())
+    })
+    .await?;
+    info!("Thread {} deleted", thread_id);
+
+    Ok(Json(DeleteAssistantObjectResponse {
+        id: thread_id,
+        object: "thread.deleted".to_string(),
+        deleted: true,
+    }))
+}
+
+/// Adds a message to a thread.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key must own the thread.
+/// * `thread_id` - The id of the thread.
+/// * `request` - The `CreateMessageRequest` with the role and content of the message.
+///
+/// # Returns
+///
+/// The `ThreadMessageObject` wrapped in `Json`, or an `ApiError` if the Assistants API is
+/// disabled, the thread does not exist for this key or has an active run, or the message
+/// is invalid.
+pub async fn create_message(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(thread_id): Path<String>,
+    RequestJson(request): RequestJson<CreateMessageRequest>,
+) -> Result<Json<ThreadMessageObject>, ApiError> {
+    request.validate(&state.settings.current().model.id)?;
+
+    let owner = owner(&headers);
+    let message = new_message(&thread_id, request);
+    let message = query(&state, move |store| {
+        check_no_active_run(store, &thread_id, owner.as_deref())?;
+        store.save_message(&message, owner.as_deref())?;
+        Ok::<_, ApiError>(message)
+    })
+    .await?;
+
+    Ok(Json(message))
+}
+
+/// Lists the messages of a thread, most recent first by default.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key must own the thread.
+/// * `thread_id` - The id of the thread.
+/// * `page` - The `limit`, `order`, `after` and `before` pagination parameters.
+///
+/// # Returns
+///
+/// A page of `ThreadMessageObject` wrapped in `Json`, or an `ApiError` if the Assistants
+/// API is disabled, the thread does not exist for this key or the pagination parameters
+/// are invalid.
+pub async fn list_messages(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(thread_id): Path<String>,
+    Query(page): Query<VectorStoreListQuery>,
+) -> Result<Json<CursorPage<ThreadMessageObject>>, ApiError> {
+    let owner = owner(&headers);
+    let messages = query(&state, move |store| {
+        store.messages(&thread_id, owner.as_deref())
+    })
+    .await?;
+
+    paginate(messages, |message| &message.id, &page).map(Json)
+}
+
+/// Runs an assistant on a thread: the model continues the conversation of the thread and
+/// its answer is added to it as an `assistant` message.
+///
+/// The thread is rendered with the chat template of the served model, after a system
+/// message holding the `instructions` of the run, or of the assistant when the run has
+/// none, followed by its `additional_instructions`. The temperature and top-p of the run
+/// take precedence over the ones of the assistant.
+///
+/// Without `stream`, the run is returned `queued` right away and generated in the
+/// background; clients poll it until it is `completed`, `incomplete` or `failed`. With
+/// `stream: true` its events are sent as server-sent events named as in the OpenAI API:
+/// `thread.run.created`, `thread.run.in_progress`, `thread.message.created`,
+/// `thread.message.delta` for every piece of text, `thread.message.completed`, then
+/// `thread.run.completed` (or `incomplete` or `failed`) and `done`.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+/// * `thread_id` - The id of the thread.
+/// * `request` - The `CreateRunRequest` with the assistant and the overrides of the run.
+///
+/// # Returns
+///
+/// The queued `RunObject` wrapped in `Json`, or the SSE stream of its events, or an
+/// `ApiError` if the Assistants API is disabled, the thread or the assistant does not
+/// exist, the thread has an active run or the request is invalid.
+pub async fn create_run(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(thread_id): Path<String>,
+    RequestJson(request): RequestJson<CreateRunRequest>,
+) -> Result<Response, ApiError> {
+    let store = store(&state)?.clone();
+    let settings = state.settings.current();
+    request.validate(&settings.model.id)?;
+    let owner = owner(&headers);
+    let (assistant, messages) = {
+        let (owner, thread_id, assistant_id) = (
+            owner.clone(),
+            thread_id.clone(),
+            request.assistant_id.clone(),
+        );
+        query(&state, move |store| {
+            check_no_active_run(store, &thread_id, owner.as_deref())?;
+            let assistant = store
+                .assistant(&assistant_id, owner.as_deref())
+                .map_err(|err| ApiError::from(err).with_param("assistant_id"))?;
+            let messages = store.messages(&thread_id, owner.as_deref())?;
+            Ok::<_, ApiError>((assistant, messages))
+        })
+        .await?
+    };
+    let model = request.model.clone().unwrap_or(assistant.model.clone());
+    check_model(&model, &settings.model.id)?;
+    check_model_access(&state, &headers, &model)?;
+
+    let limits = prompt_limits(&state, &headers);
+    check_message_count(&limits, messages.len(), "messages")?;
+    let instructions = request.instructions.clone().or(assistant.instructions);
+    let system = match (&instructions, &request.additional_instructions) {
+        (Some(instructions), Some(additional)) => Some(format!("{instructions}\n\n{additional}")),
+        (instructions, additional) => instructions.clone().or(additional.clone()),
+    };
+    let turns: Vec<(String, String)> = system
+        .map(|content| ("system".to_string(), content))
+        .into_iter()
+        .chain(messages.iter().map(|message| {
+            let text: Vec<_> = message
+                .content
+                .iter()
+                .map(|part| part.text.value.as_str())
+                .collect();
+            (message.role.clone(), text.join("\n"))
+        }))
+        .collect();
+    let chat_template = state.chat_template.clone().unwrap_or_default();
+    let prompt = chat_template.render(&turns, &template_tokens(&state))?;
+    let input = PromptInput::Text(prompt.clone());
+    check_prompt_tokens(&limits, &state.tokenizer, &input, "messages")?;
+    check_memory_pressure(&state, &input, request.max_completion_tokens)?;
+
+    let temperature = request.temperature.or(assistant.temperature);
+    let top_p = request.top_p.or(assistant.top_p);
+    let params = SamplingParams::default()
+        .with_temperature(temperature)
+        .with_top_p(top_p)
+        .with_max_tokens(request.max_completion_tokens);
+    let meter = Arc::new(UsageMeter::default());
+    let text_gen = TextGeneration::from_state(state.clone(), &params)?.with_meter(meter.clone());
+    let reasoning = ReasoningParser::new(&settings.model_settings(&model).reasoning, &prompt);
+
+    let run = RunObject {
+        id: format!("run_{}", Uuid::new_v4().simple()),
+        object: "thread.run".to_string(),
+        created_at: Utc::now().timestamp(),
+        thread_id,
+        assistant_id: assistant.id,
+        status: "queued".to_string(),
+        model: settings.model.id.clone(),
+        instructions,
+        started_at: None,
+        completed_at: None,
+        failed_at: None,
+        last_error: None,
+        incomplete_details: None,
+        usage: None,
+        temperature,
+        top_p,
+        max_completion_tokens: request.max_completion_tokens,
+        metadata: request.metadata,
+    };
+    let queued = run.clone();
+    let run_owner = owner.clone();
+    query(&state, move |store| {
+        store.save_run(&queued, run_owner.as_deref())
+    })
+    .await?;
+    info!("Run {} queued on thread {}", run.id, run.thread_id);
+
+    let execution = RunExecution {
+        store,
+        owner,
+        run: run.clone(),
+        meter: meter.clone(),
+        reasoning,
+        buffer: None,
+    };
+    if request.stream.unwrap_or(false) {
+        let response = stream_task(&state, api_key(&headers), run.id.clone(), move |buffer| {
+            RunExecution {
+                buffer: Some(buffer),
+                ..execution
+            }
+            .execute(text_gen, input)
+        });
+        return Ok((meter.partial(), response).into_response());
+    }
+    tokio::spawn(execution.execute(text_gen, input));
+
+    Ok((StatusCode::OK, Json(run)).into_response())
+}
+
+/// Lists the runs of a thread, most recent first by default.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key must own the thread.
+/// * `thread_id` - The id of the thread.
+/// * `page` - The `limit`, `order`, `after` and `before` pagination parameters.
+///
+/// # Returns
+///
+/// A page of `RunObject` wrapped in `Json`, or an `ApiError` if the Assistants API is
+/// disabled, the thread does not exist for this key or the pagination parameters are
+/// invalid.
+pub async fn list_runs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(thread_id): Path<String>,
+    Query(page): Query<VectorStoreListQuery>,
+) -> Result<Json<CursorPage<RunObject>>, ApiError> {
+    let owner = owner(&headers);
+    let runs = query(&state, move |store| {
+        store.runs(&thread_id, owner.as_deref())
+    })
+    .await?;
+
+    paginate(runs, |run| &run.id, &page).map(Json)
+}
+
+/// Retrieves a run, to poll it until it ends.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key must own the thread.
+/// * `thread_id` - The id of the thread.
+/// * `run_id` - The id of the run.
+///
+/// # Returns
+///
+/// The `RunObject` wrapped in `Json`, or an `ApiError` if the Assistants API is disabled
+/// or the run does not exist in this thread of the key.
+pub async fn retrieve_run(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((thread_id, run_id)): Path<(String, String)>,
+) -> Result<Json<RunObject>, ApiError> {
+    let owner = owner(&headers);
+    let run = query(&state, move |store| {
+        store.run(&thread_id, &run_id, owner.as_deref())
+    })
+    .await?;
+
+    Ok(Json(run))
+}
+
+/// The generation of a run, recording its progress in the store and, when streamed,
+/// sending it as events.
+struct RunExecution {
+    store: Arc<AssistantStore>,
+    /// The digest of the API key that owns the thread of the run.
+    owner: Option<String>,
+    run: RunObject,
+    meter: Arc<UsageMeter>,
+    /// Separates the reasoning of thinking models, which is left out of the answer.
+    reasoning: Option<ReasoningParser>,
+    buffer: Option<Arc<StreamBuffer>>,
+}
+
+impl RunExecution {
+    /// Generates the answer of the run and adds it to the thread.
+    async fn execute(mut self, text_gen: TextGeneration, prompt: PromptInput) {
+        self.emit("thread.run.created", &self.run);
+        let mut message = ThreadMessageObject {
+            id: format!("msg_{}", Uuid::new_v4().simple()),
+            object: "thread.message".to_string(),
+            created_at: Utc::now().timestamp(),
+            thread_id: self.run.thread_id.clone(),
+            status: "in_progress".to_string(),
+            role: "assistant".to_string(),
+            content: Vec::new(),
+            assistant_id: Some(self.run.assistant_id.clone()),
+            run_id: Some(self.run.id.clone()),
+            metadata: HashMap::new(),
+        };
+
+        let mut events = text_gen.stream(prompt);
+        let mut text = String::new();
+        let mut finish_reason = FinishReason::Stop;
+        let mut failure = None;
+        while let Some(event) = events.next().await {
+            if self.run.started_at.is_none() {
+                self.run.status = "in_progress".to_string();
+                self.run.started_at = Some(Utc::now().timestamp());
+                self.save().await;
+                self.emit("thread.run.in_progress", &self.run);
+                self.emit("thread.message.created", &message);
+                self.emit("thread.message.in_progress", &message);
+            }
+            match event {
+                GenerationEvent::TokenDelta(delta) => {
+                    let delta = match &mut self.reasoning {
+                        Some(parser) => parser.push(&delta).content,
+                        None => delta,
+                    };
+                    self.push_delta(&message.id, &mut text, delta);
+                }
+                GenerationEvent::Done {
+                    finish_reason: reason,
+                } => finish_reason = reason,
+                GenerationEvent::Error(err) => failure = Some(ApiError::from(err)),
+                GenerationEvent::UsageUpdate(_) | GenerationEvent::ToolCallDelta(_) => {}
+            }
+        }
+        if let Some(parser) = &mut self.reasoning {
+            let rest = parser.finish().content;
+            self.push_delta(&message.id, &mut text, rest);
+        }
+
+        let now = Utc::now().timestamp();
+        self.run.usage = Some(self.meter.usage().into());
+        let name = match failure {
+            Some(failure) => {
+                error!("Run {} failed: {}", self.run.id, failure.body().message);
+                self.run.status = "failed".to_string();
+                self.run.failed_at = Some(now);
+                let code = if failure.status() == StatusCode::TOO_MANY_REQUESTS {
+                    "rate_limit_exceeded"
+                } else {
+                    "server_error"
+                };
+                self.run.last_error = Some(RunError {
+                    code: code.to_string(),
+                    message: failure.body().message.clone(),
+                });
+                "thread.run.failed"
+            }
+            None => {
+                message.status = "completed".to_string();
+                message.content = vec![MessageContent::text(text)];
+                let (store, owner, answer) =
+                    (self.store.clone(), self.owner.clone(), message.clone());
+                let saved = tokio::task::spawn_blocking(move || {
+                    store.save_message(&answer, owner.as_deref())
+                })
+                .await;
+                if let Ok(Err(err)) = saved {
+                    error!("Failed to store the answer of run {}: {}", self.run.id, err);
+                }
+                self.emit("thread.message.completed", &message);
+
+                let incomplete_reason = match finish_reason {
+                    FinishReason::Stop => None,
+                    FinishReason::Length | FinishReason::LengthCapped => {
+                        Some("max_completion_tokens")
+                    }
+                    FinishReason::ContentFilter => Some("content_filter"),
+                    FinishReason::Timeout => Some("timeout"),
+                    FinishReason::Repetition => Some("repetition"),
+                };
+                match incomplete_reason {
+                    Some(reason) => {
+                        self.run.status = "incomplete".to_string();
+                        self.run.incomplete_details = Some(IncompleteDetails {
+                            reason: reason.to_string(),
+                        });
+                        "thread.run.incomplete"
+                    }
+                    None => {
+                        self.run.status = "completed".to_string();
+                        self.run.completed_at = Some(now);
+                        "thread.run.completed"
+                    }
+                }
+            }
+        };
+        self.save().await;
+        self.emit(name, &self.run);
+        info!("Run {} is {}", self.run.id, self.run.status);
+
+        if let Some(buffer) = &self.buffer {
+            buffer.push_named("done", DONE.to_string());
+            buffer.finish();
+        }
+        self.meter.release();
+    }
+
+    /// Appends a piece of the answer and sends it as a `thread.message.delta` event.
+    fn push_delta(&self, message_id: &str, text: &mut String, delta: String) {
+        if delta.is_empty() {
+            return;
+        }
+        text.push_str(&delta);
+        let event = ThreadMessageDelta {
+            id: message_id.to_string(),
+            object: "thread.message.delta".to_string(),
+            delta: MessageDeltaContent {
+                content: vec![MessageDeltaPart {
+                    index: 0,
+                    kind: "text".to_string(),
+                    text: MessageText {
+                        value: delta,
+                        annotations: Vec::new(),
+                    },
+                }],
+            },
+        };
+        self.emit("thread.message.delta", &event);
+    }
+
+    /// Sends an event when the run is streamed.
+    fn emit(&self, name: &'static str, data: &impl Serialize) {
+        if let Some(buffer) = &self.buffer {
+            buffer.push_named(name, serde_json::to_string(data).unwrap_or_default());
+        }
+    }
+
+    /// Stores the run on a blocking thread.
+    async fn save(&self) {
+        let (store, owner, run) = (self.store.clone(), self.owner.clone(), self.run.clone());
+        let saved =
+            tokio::task::spawn_blocking(move || store.save_run(&run, owner.as_deref())).await;
+        if let Ok(Err(err)) = saved {
+            error!("Failed to store run {}: {}", self.run.id, err);
+        }
+    }
+}
+
+/// A new message of a thread.
+fn new_message(thread_id: &str, request: CreateMessageRequest) -> ThreadMessageObject {
+    ThreadMessageObject {
+        id: format!("msg_{}", Uuid::new_v4().simple()),
+        object: "thread.message".to_string(),
+        created_at: Utc::now().timestamp(),
+        thread_id: thread_id.to_string(),
+        status: "completed".to_string(),
+        role: request.role,
+        content: vec![MessageContent::text(request.content.text())],
+        assistant_id: None,
+        run_id: None,
+        metadata: request.metadata,
+    }
+}
+
+/// An assistant with the fields given in a request changed.
+fn modify(mut assistant: AssistantObject, request: ModifyAssistantRequest) -> AssistantObject {
+    if let Some(model) = request.model {
+        assistant.model = model;
+    }
+    if let Some(tools) = request.tools {
+        assistant.tools = tools;
+    }
+    if let Some(metadata) = request.metadata {
+        assistant.metadata = metadata;
+    }
+    assistant.name = request.name.or(assistant.name);
+    assistant.description = request.description.or(assistant.description);
+    assistant.instructions = request.instructions.or(assistant.instructions);
+    assistant.temperature = request.temperature.or(assistant.temperature);
+    assistant.top_p = request.top_p.or(assistant.top_p);
+    assistant
+}
+
+/// Rejects changes to a thread while one of its runs has not ended.
+fn check_no_active_run(
+    store: &AssistantStore,
+    thread_id: &str,
+    owner: Option<&str>,
+) -> Result<(), ApiError> {
+    let runs = store.runs(thread_id, owner)?;
+    if let Some(run) = runs.iter().find(|run| run.is_active()) {
+        return Err(ApiError::invalid_request(format!(
+            "Thread {thread_id} already has an active run {}",
+            run.id
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the assistant store, or an error if the Assistants API is disabled.
+fn store(state: &AppState) -> Result<&Arc<AssistantStore>, ApiError> {
+    state.assistants.as_ref().ok_or_else(|| {
+        ApiError::not_found(
+            "The Assistants API is disabled, set `assistants.database` to enable it",
+        )
+    })
+}
+
+/// Runs queries of the assistant store on a blocking thread, since they wait for the
+/// database.
+///
+/// # Errors
+///
+/// Returns an error if the Assistants API is disabled or the queries fail.
+async fn query<T, E>(
+    state: &AppState,
+    queries: impl FnOnce(&AssistantStore) -> Result<T, E> + Send + 'static,
+) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+    ApiError: From<E>,
+{
+    let store = store(state)?.clone();
+    let result = tokio::task::spawn_blocking(move || queries(&store))
+        .await
+        .map_err(ApiError::internal)?;
+
+    Ok(result?)
+}
+
+/// The owner of the objects of a request, the digest of its API key.
+fn owner(headers: &HeaderMap) -> Option<String> {
+    api_key(headers).map(|key| digest(key.as_bytes()))
+}
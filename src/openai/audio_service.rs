@@ -0,0 +1,331 @@
+use crate::core::speech::{encode_pcm, split_sentences, wav_stream_header};
+use crate::core::transcription::{decode_wav, Transcription};
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::limits::check_model_access;
+use crate::openai::models::{
+    CreateSpeechRequest, CreateTranscriptionResponse, CreateTranscriptionVerboseResponse,
+    TranscriptionSegment,
+};
+use crate::openai::request_json::RequestJson;
+use axum::body::{Body, Bytes};
+use axum::extract::{Multipart, State};
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::{info, warn};
+
+/// The `response_format` of speech when the request sets none. OpenAI defaults to `mp3`,
+/// which is not supported, so clients relying on that default get a WAV file instead.
+const DEFAULT_SPEECH_FORMAT: &str = "wav";
+
+/// Speaker descriptions of the OpenAI voices, used unless overridden in `audio.voices`.
+const DEFAULT_VOICES: [(&str, &str); 6] = [
+    ("alloy", "A neutral voice speaks clearly at a moderate pace with very clear audio."),
+    ("echo", "A male speaker with a warm, resonant voice delivers his words calmly with very clear audio."),
+    ("fable", "A male speaker with a British accent tells the text expressively with very clear audio."),
+    ("onyx", "A male speaker with a deep, low-pitched voice speaks slowly with very clear audio."),
+    ("nova", "A female speaker with a bright, energetic voice speaks quickly with very clear audio."),
+    ("shimmer", "A female speaker with a soft, gentle voice speaks calmly with very clear audio."),
+];
+
+/// Transcribes audio into text.
+///
+/// This function accepts a `multipart/form-data` body with a `file` part holding a WAV
+/// file and the optional `model`, `language` and `response_format` parts. The audio is
+/// transcribed with the configured Whisper model on the server device.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+/// * `multipart` - The multipart form of the request.
+///
+/// # Returns
+///
+/// The transcription as `json`, `text`, `verbose_json`, `srt` or `vtt` depending on
+/// `response_format`, or an `ApiError` if the request is invalid, the `language` is not
+/// one of the model or transcription is not enabled.
+pub async fn create_transcription(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    let Some(transcriber) = state.transcriber.clone() else {
+        return Err(
+            ApiError::not_found("Audio transcription is not enabled on this server")
+                .with_param("model")
+                .with_code("model_not_found"),
+        );
+    };
+    if let Some(model_id) = &state.settings.current().audio.transcription_model {
+        check_model_access(&state, &headers, model_id)?;
+    }
+
+    let mut file = None;
+    let mut language = None;
+    let mut response_format = "json".to_string();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| ApiError::invalid_request(e.body_text()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "file" => {
+                let bytes = field
+                    .bytes()
+                    .await
+                    .map_err(|e| ApiError::invalid_request(e.body_text()))?;
+                file = Some(bytes);
+            }
+            "language" | "response_format" => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| ApiError::invalid_request(e.body_text()))?;
+                if name == "language" {
+                    language = Some(text);
+                } else {
+                    response_format = text;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let Some(file) = file else {
+        return Err(
+            ApiError::invalid_request("Missing required parameter: 'file'").with_param("file"),
+        );
+    };
+    if !["json", "text", "verbose_json", "srt", "vtt"].contains(&response_format.as_str()) {
+        return Err(ApiError::invalid_request(format!(
+            "'{response_format}' is not a supported response_format"
+        ))
+        .with_param("response_format"));
+    }
+    if let Some(language) = language
+        .as_deref()
+        .filter(|language| !transcriber.supports_language(language))
+    {
+        return Err(ApiError::invalid_request(format!(
+            "'{language}' is not a language of the transcription model"
+        ))
+        .with_param("language"));
+    }
+
+    let pcm = decode_wav(&file).map_err(|e| {
+        ApiError::invalid_request(format!("Unsupported audio file, expected WAV: {e}"))
+            .with_param("file")
+    })?;
+
+    // Decoding holds the transcriber for the whole audio, off the async runtime
+    let transcription =
+        tokio::task::spawn_blocking(move || transcriber.transcribe(&pcm, language.as_deref()))
+            .await
+            .map_err(ApiError::internal)??;
+    info!(
+        "Transcribed {:.1}s of audio into {} segments",
+        transcription.duration,
+        transcription.segments.len()
+    );
+
+    let response = match response_format.as_str() {
+        "text" => text_response(transcription.text(), "text/plain"),
+        "srt" => text_response(subtitles(&transcription, ","), "application/x-subrip"),
+        "vtt" => text_response(
+            format!("WEBVTT\n\n{}", subtitles(&transcription, ".")),
+            "text/vtt",
+        ),
+        "verbose_json" => Json(CreateTranscriptionVerboseResponse {
+            task: "transcribe".to_string(),
+            language: transcription.language.clone(),
+            duration: transcription.duration,
+            text: transcription.text(),
+            segments: transcription
+                .segments
+                .iter()
+                .enumerate()
+                .map(|(id, segment)| TranscriptionSegment {
+                    id: id as i64,
+                    seek: (segment.start * 100.0) as i64,
+                    start: segment.start,
+                    end: segment.end,
+                    text: segment.text.clone(),
+                    tokens: segment.tokens.clone(),
+                    temperature: 0.0,
+                })
+                .collect(),
+        })
+        .into_response(),
+        _ => Json(CreateTranscriptionResponse {
+            text: transcription.text(),
+        })
+        .into_response(),
+    };
+
+    Ok(response)
+}
+
+fn text_response(body: String, content_type: &'static str) -> Response {
+    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+/// Renders the segments as SRT/WebVTT cues, which only differ in the millisecond separator.
+fn subtitles(transcription: &Transcription, separator: &str) -> String {
+    transcription
+        .segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            format!(
+                "{}\n{} --> {}\n{}\n",
+                i + 1,
+                timestamp(segment.start, separator),
+                timestamp(segment.end, separator),
+                segment.text.trim()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn timestamp(seconds: f64, separator: &str) -> String {
+    let millis = (seconds * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        (millis / 60_000) % 60,
+        (millis / 1000) % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+/// Generates audio from the input text.
+///
+/// This function synthesizes the `input` with the configured Parler-TTS model, using the
+/// speaker description of the requested `voice`. The input is synthesized one sentence
+/// at a time off the async runtime, and the audio of each sentence is streamed as soon as
+/// it is generated. A `wav` response starts with a streaming header whose sizes are left
+/// open. Only `wav` and `pcm` are supported, any other `response_format`, such as `mp3`,
+/// is rejected with a 400 `invalid_request_error`, and `wav` is the default where OpenAI
+/// defaults to `mp3`. A `speed` other than `1.0` resamples the audio, which shifts its
+/// pitch along with its tempo.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+/// * `request` - The `CreateSpeechRequest` containing the input parameters.
+///
+/// # Returns
+///
+/// The audio as `wav` or raw 16-bit `pcm` depending on `response_format`, or an
+/// `ApiError` if the request is invalid, speech is not enabled or the first sentence
+/// fails to synthesize.
+pub async fn create_speech(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateSpeechRequest>,
+) -> Result<Response, ApiError> {
+    let Some(synthesizer) = state.synthesizer.clone() else {
+        return Err(
+            ApiError::not_found("Text-to-speech is not enabled on this server")
+                .with_param("model")
+                .with_code("model_not_found"),
+        );
+    };
+    if let Some(model_id) = &state.settings.current().audio.speech_model {
+        check_model_access(&state, &headers, model_id)?;
+    }
+
+    if request.input.trim().is_empty() {
+        return Err(ApiError::invalid_request("'input' must not be empty").with_param("input"));
+    }
+    let speed = request.speed.unwrap_or(1.0);
+    if !(0.25..=4.0).contains(&speed) {
+        return Err(ApiError::invalid_request(format!(
+            "{speed} is not in the supported range 0.25 - 4.0 - 'speed'"
+        ))
+        .with_param("speed"));
+    }
+    let response_format = request
+        .response_format
+        .as_deref()
+        .unwrap_or(DEFAULT_SPEECH_FORMAT);
+    if !["wav", "pcm"].contains(&response_format) {
+        return Err(ApiError::invalid_request(format!(
+            "'{response_format}' is not supported, use 'wav' or 'pcm' - 'response_format'"
+        ))
+        .with_param("response_format"));
+    }
+
+    let settings = state.settings.current();
+    let description = settings
+        .audio
+        .voices
+        .get(&request.voice)
+        .map(String::as_str)
+        .or_else(|| {
+            DEFAULT_VOICES
+                .iter()
+                .find(|(voice, _)| *voice == request.voice)
+                .map(|(_, description)| *description)
+        })
+        .ok_or_else(|| {
+            ApiError::invalid_request(format!("Unknown voice '{}'", request.voice))
+                .with_param("voice")
+        })?
+        .to_string();
+
+    let (tx, rx) = mpsc::channel::<anyhow::Result<Vec<u8>>>(4);
+    let sample_rate = synthesizer.sample_rate();
+    tokio::task::spawn_blocking(move || {
+        let mut seconds = 0.;
+        for sentence in split_sentences(&request.input) {
+            let chunk = synthesizer
+                .synthesize(sentence, &description, speed)
+                .map(|pcm| {
+                    seconds += pcm.len() as f64 / sample_rate as f64;
+                    encode_pcm(&pcm)
+                });
+            let failed = chunk.is_err();
+            // The client went away or the synthesis failed, the rest is not spoken
+            if tx.blocking_send(chunk).is_err() || failed {
+                return;
+            }
+        }
+        info!("Synthesized {seconds:.1}s of speech");
+    });
+
+    // The first sentence is awaited so that a failing synthesis is still an error status
+    let mut chunks = ReceiverStream::new(rx);
+    let first = match chunks.next().await {
+        Some(chunk) => chunk?,
+        None => Vec::new(),
+    };
+    let (content_type, header_bytes) = if response_format == "pcm" {
+        ("audio/pcm", Vec::new())
+    } else {
+        ("audio/wav", wav_stream_header(sample_rate))
+    };
+    let rest = chunks.map(|chunk| {
+        chunk.map(Bytes::from).map_err(|err| {
+            warn!("Speech synthesis failed mid-stream: {err:#}");
+            std::io::Error::other(err)
+        })
+    });
+    let body = tokio_stream::iter([Ok(Bytes::from([header_bytes, first].concat()))]).chain(rest);
+
+    Ok((
+        [(header::CONTENT_TYPE, content_type)],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
@@ -0,0 +1,142 @@
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::http_service::{create_chat_completion, create_completion, create_embedding};
+use crate::openai::request_json::{parse_request, RequestJson};
+use axum::extract::{Path, Query, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// The query string of the Azure OpenAI endpoints.
+#[derive(Deserialize, Debug)]
+pub struct AzureQuery {
+    #[serde(rename = "api-version")]
+    pub api_version: Option<String>,
+}
+
+/// Creates a chat completion through an Azure OpenAI deployment URL.
+///
+/// The request is the same as for `/v1/chat/completions`, except that the model is
+/// given by the deployment in the path instead of the `model` field.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `deployment` - The name of the deployment.
+/// * `query` - The query string holding the `api-version`.
+/// * `headers` - The request headers.
+/// * `body` - The JSON body of the chat completion request.
+///
+/// # Returns
+///
+/// The response of `/v1/chat/completions`, or an `ApiError` if the deployment does
+/// not exist, the `api-version` is missing or the request is invalid.
+pub async fn azure_chat_completion(
+    State(state): State<AppState>,
+    Path(deployment): Path<String>,
+    Query(query): Query<AzureQuery>,
+    headers: HeaderMap,
+    RequestJson(body): RequestJson<serde_json::Value>,
+) -> Result<Response, ApiError> {
+    let request = deployment_request(&state, &deployment, &query, body)?;
+
+    create_chat_completion(State(state), headers, RequestJson(request)).await
+}
+
+/// Creates a text completion through an Azure OpenAI deployment URL.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `deployment` - The name of the deployment.
+/// * `query` - The query string holding the `api-version`.
+/// * `headers` - The request headers.
+/// * `body` - The JSON body of the completion request.
+///
+/// # Returns
+///
+/// The response of `/v1/completions`, or an `ApiError` if the deployment does not
+/// exist, the `api-version` is missing or the request is invalid.
+pub async fn azure_completion(
+    State(state): State<AppState>,
+    Path(deployment): Path<String>,
+    Query(query): Query<AzureQuery>,
+    headers: HeaderMap,
+    RequestJson(body): RequestJson<serde_json::Value>,
+) -> Result<Response, ApiError> {
+    let request = deployment_request(&state, &deployment, &query, body)?;
+
+    create_completion(State(state), headers, RequestJson(request)).await
+}
+
+/// Creates an embedding through an Azure OpenAI deployment URL.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `deployment` - The name of the deployment.
+/// * `query` - The query string holding the `api-version`.
+/// * `headers` - The request headers.
+/// * `body` - The JSON body of the embedding request.
+///
+/// # Returns
+///
+/// The response of `/v1/embeddings`, or an `ApiError` if the deployment does not
+/// exist, the `api-version` is missing or the request is invalid.
+pub async fn azure_embedding(
+    State(state): State<AppState>,
+    Path(deployment): Path<String>,
+    Query(query): Query<AzureQuery>,
+    headers: HeaderMap,
+    RequestJson(body): RequestJson<serde_json::Value>,
+) -> Result<Response, ApiError> {
+    let request = deployment_request(&state, &deployment, &query, body)?;
+
+    Ok(
+        create_embedding(State(state), headers, RequestJson(request))
+            .await
+            .into_response(),
+    )
+}
+
+/// Resolves the model of a deployment and sets it on the request body.
+///
+/// Deployments are mapped to models by `azure.deployments`; a deployment named like
+/// the served model, by full id or repository name, needs no mapping.
+fn deployment_request<T: DeserializeOwned>(
+    state: &AppState,
+    deployment: &str,
+    query: &AzureQuery,
+    mut body: serde_json::Value,
+) -> Result<T, ApiError> {
+    if query.api_version.is_none() {
+        return Err(
+            ApiError::invalid_request("Missing required query parameter 'api-version'")
+                .with_param("api-version"),
+        );
+    }
+
+    let settings = state.settings.current();
+    let served_model = &settings.model.id;
+    let short_name = served_model.rsplit('/').next().unwrap_or(served_model);
+    let model = match settings.azure.deployments.get(deployment) {
+        Some(model) => settings.resolve_model(model).to_string(),
+        None if deployment == served_model || deployment == short_name => deployment.to_string(),
+        None => {
+            return Err(ApiError::not_found(format!(
+                "The API deployment '{deployment}' does not exist"
+            ))
+            .with_code("DeploymentNotFound"))
+        }
+    };
+
+    let Some(fields) = body.as_object_mut() else {
+        return Err(ApiError::invalid_request(
+            "The request body must be a JSON object",
+        ));
+    };
+    fields.insert("model".to_string(), serde_json::Value::String(model));
+
+    parse_request(body, settings.request_parsing)
+}
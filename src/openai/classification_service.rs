@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use crate::core::classification::{ClassificationError, LabelSet};
+use crate::core::generator::TextGeneration;
+use crate::core::sampling::SamplingParams;
+use crate::core::usage_meter::{MeteredUsage, UsageMeter};
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::limits::check_model_access;
+use crate::openai::models::{
+    ClassificationUsage, CreateClassificationRequest, CreateClassificationResponse,
+};
+use crate::openai::request_json::RequestJson;
+use crate::openai::validation::Validate;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::Json;
+use chrono::Utc;
+use tracing::info;
+use uuid::Uuid;
+
+impl From<ClassificationError> for ApiError {
+    fn from(err: ClassificationError) -> Self {
+        match err {
+            ClassificationError::EmptyLabel(index) => {
+                ApiError::invalid_request(err.to_string()).with_param(format!("labels.{index}"))
+            }
+            ClassificationError::AmbiguousLabels { .. } => {
+                ApiError::invalid_request(err.to_string()).with_param("labels")
+            }
+            ClassificationError::Tokenizer(_) => ApiError::internal(err),
+        }
+    }
+}
+
+/// Classifies a text into one of the candidate labels.
+///
+/// This is a zero-shot classifier built on constrained single-token decoding: the
+/// served model reads the text and the labels, and the logits of its next token are
+/// normalized over the first tokens of the labels. A single forward pass is made and
+/// no text is generated, so it is much cheaper than asking for the label in a chat.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+/// * `request` - The `CreateClassificationRequest` with the text and the labels.
+///
+/// # Returns
+///
+/// The `CreateClassificationResponse` with the probability of every label wrapped in
+/// `Json` with the usage headers, or an `ApiError` if the request is invalid, two labels start with the same
+/// token or a guardrail rejects the text.
+pub async fn create_classification(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateClassificationRequest>,
+) -> Result<(MeteredUsage, Json<CreateClassificationResponse>), ApiError> {
+    let meter = Arc::new(UsageMeter::default());
+    request.validate(&state.settings.current().model.id)?;
+    check_model_access(&state, &headers, &request.model)?;
+
+    let labels = LabelSet::new(&state.tokenizer, &request.labels)?;
+    let prompt = labels.prompt(request.instructions.as_deref(), &request.input);
+    let text_gen = TextGeneration::from_state(state.clone(), &SamplingParams::default())?
+        .with_meter(meter.clone());
+
+    let (logits, prompt_tokens) =
+        tokio::task::spawn_blocking(move || text_gen.next_token_logits(prompt))
+            .await
+            .map_err(ApiError::internal)??;
+    let scores = labels.probabilities(&logits);
+    let label = scores
+        .first()
+        .map(|score| score.label.clone())
+        .unwrap_or_default();
+    info!(
+        "Classified text as '{}' among {} labels",
+        label,
+        scores.len()
+    );
+
+    let response = CreateClassificationResponse {
+        id: format!("clf_{}", Uuid::new_v4().simple()),
+        object: "classification".to_string(),
+        created: Utc::now().timestamp(),
+        model: state.settings.current().model.id.clone(),
+        label,
+        scores,
+        usage: ClassificationUsage {
+            prompt_tokens,
+            total_tokens: prompt_tokens,
+        },
+    };
+
+    Ok((meter.usage(), Json(response)))
+}
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use crate::core::access_log::digest;
+use crate::core::conversations::ConversationStore;
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::limits::api_key;
+use crate::openai::models::{
+    ConversationObject, ConversationQuery, DeleteConversationResponse, ListConversationsResponse,
+};
+use axum::extract::{Path, Query, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use tracing::info;
+
+/// Lists the stored conversations of the API key of the request and of a user.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key the conversations belong to.
+/// * `query` - The `user` the conversations belong to; conversations created
+///   without a user are listed when it is omitted.
+///
+/// # Returns
+///
+/// The conversations wrapped in `Json`, most recently updated first, or an `ApiError`
+/// if persistence is disabled or the database fails.
+pub async fn list_conversations(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ConversationQuery>,
+) -> Result<Json<ListConversationsResponse>, ApiError> {
+    let store = store(&state)?.clone();
+    let owner = conversation_owner(&headers);
+    let conversations =
+        tokio::task::spawn_blocking(move || store.list(owner.as_deref(), query.user.as_deref()))
+            .await
+            .map_err(ApiError::internal)??;
+
+    Ok(Json(ListConversationsResponse {
+        object: "list".to_string(),
+        data: conversations,
+    }))
+}
+
+/// Retrieves the messages of a stored conversation.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key the conversation belongs to.
+/// * `conversation_id` - The id of the conversation.
+/// * `query` - The `user` the conversation belongs to.
+///
+/// # Returns
+///
+/// The `ConversationObject` wrapped in `Json`, or an `ApiError` if persistence is
+/// disabled or the conversation does not exist for this key and user.
+pub async fn retrieve_conversation(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<String>,
+    Query(query): Query<ConversationQuery>,
+) -> Result<Json<ConversationObject>, ApiError> {
+    let store = store(&state)?.clone();
+    let owner = conversation_owner(&headers);
+    let id = conversation_id.clone();
+    let messages = tokio::task::spawn_blocking(move || {
+        store.history(&id, owner.as_deref(), query.user.as_deref())
+    })
+    .await
+    .map_err(ApiError::internal)??;
+    if messages.is_empty() {
+        return Err(ApiError::not_found(format!(
+            "No conversation found with id '{conversation_id}'"
+        )));
+    }
+
+    Ok(Json(ConversationObject {
+        id: conversation_id,
+        object: "conversation".to_string(),
+        messages,
+    }))
+}
+
+/// Deletes a stored conversation and its messages.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key the conversation belongs to.
+/// * `conversation_id` - The id of the conversation.
+/// * `query` - The `user` the conversation belongs to.
+///
+/// # Returns
+///
+/// The `DeleteConversationResponse` wrapped in `Json`, or an `ApiError` if
+/// persistence is disabled or the conversation does not exist for this key and user.
+pub async fn delete_conversation(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(conversation_id): Path<String>,
+    Query(query): Query<ConversationQuery>,
+) -> Result<Json<DeleteConversationResponse>, ApiError> {
+    let store = store(&state)?.clone();
+    let owner = conversation_owner(&headers);
+    let id = conversation_id.clone();
+    tokio::task::spawn_blocking(move || store.delete(&id, owner.as_deref(), query.user.as_deref()))
+        .await
+        .map_err(ApiError::internal)??;
+    info!("Conversation {} deleted", conversation_id);
+
+    Ok(Json(DeleteConversationResponse {
+        id: conversation_id,
+        object: "conversation.deleted".to_string(),
+        deleted: true,
+    }))
+}
+
+/// Returns the conversation store, or an error if persistence is disabled.
+fn store(state: &AppState) -> Result<&Arc<ConversationStore>, ApiError> {
+    state.conversations.as_ref().ok_or_else(|| {
+        ApiError::not_found(
+            "Conversation persistence is disabled, set `conversations.database` to enable it",
+        )
+    })
+}
+
+/// The owner of the conversations of a request, the digest of its API key.
+pub(crate) fn conversation_owner(headers: &HeaderMap) -> Option<String> {
+    api_key(headers).map(|key| digest(key.as_bytes()))
+}
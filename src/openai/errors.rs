@@ -0,0 +1,324 @@
+use crate::core::circuit_breaker::CircuitOpen;
+use crate::core::deadline::DeadlineExceeded;
+use crate::core::embeddings::InputTooLong;
+use crate::core::guardrails::GuardrailViolation;
+use crate::core::image_embeddings::InvalidImage;
+use crate::core::sampling::NonFiniteLogits;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::error;
+
+/// The class of a failure, counted by `/metrics` and sent as the error `code` when no
+/// more specific code applies, so that dashboards tell client errors from server
+/// trouble.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// The request is invalid, such as a bad parameter or an unknown model.
+    Validation,
+    /// The server is too busy, such as a full queue, a rate limit or an exceeded deadline.
+    Overload,
+    /// The device ran out of memory while running the model.
+    DeviceOom,
+    /// The model files could not be fetched from the Hugging Face Hub.
+    HubFetch,
+    /// Any other failure of the server.
+    Internal,
+}
+
+impl ErrorCategory {
+    /// Every category, in the order of the metrics.
+    pub const ALL: [Self; 5] = [
+        Self::Validation,
+        Self::Overload,
+        Self::DeviceOom,
+        Self::HubFetch,
+        Self::Internal,
+    ];
+
+    /// The name of the category in the metrics and the error codes.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Validation => "validation",
+            Self::Overload => "overload",
+            Self::DeviceOom => "device_oom",
+            Self::HubFetch => "hub_fetch",
+            Self::Internal => "internal",
+        }
+    }
+
+    /// The category of the errors answered with a status, until a more specific one is
+    /// set.
+    fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::REQUEST_TIMEOUT
+            | StatusCode::SERVICE_UNAVAILABLE => Self::Overload,
+            status if status.is_client_error() => Self::Validation,
+            _ => Self::Internal,
+        }
+    }
+
+    /// The category of an unexpected error, from its causes.
+    fn of(err: &anyhow::Error) -> Self {
+        let from_hub = err.chain().any(|cause| {
+            cause
+                .downcast_ref::<hf_hub::api::sync::ApiError>()
+                .is_some()
+        });
+        if from_hub {
+            return Self::HubFetch;
+        }
+        let out_of_memory = err
+            .chain()
+            .any(|cause| cause.to_string().to_lowercase().contains("out of memory"));
+        if out_of_memory {
+            return Self::DeviceOom;
+        }
+        Self::Internal
+    }
+}
+
+/// The number of errors returned per category, in the order of `ErrorCategory::ALL`.
+static ERROR_COUNTS: [AtomicU64; 5] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// The number of errors returned since the start, per category.
+pub fn error_counts() -> Vec<(ErrorCategory, u64)> {
+    ErrorCategory::ALL
+        .iter()
+        .zip(&ERROR_COUNTS)
+        .map(|(category, count)| (*category, count.load(Ordering::Relaxed)))
+        .collect()
+}
+
+/// The error envelope returned by the OpenAI API.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+/// The details of an OpenAI API error.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorBody {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub param: Option<String>,
+    pub code: Option<String>,
+}
+
+/// An error returned by an HTTP handler.
+///
+/// `ApiError` pairs an HTTP status code with an OpenAI-style error body, so handlers
+/// can return `Result<_, ApiError>` and clients receive the same error shape the
+/// OpenAI API produces.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    category: ErrorCategory,
+    body: ErrorBody,
+    /// The delay in seconds sent in the `Retry-After` header.
+    retry_after: Option<u64>,
+}
+
+impl ApiError {
+    /// Creates a new `ApiError`, whose category and code follow from its status.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The HTTP status code of the response.
+    /// * `kind` - The OpenAI error type, e.g. `invalid_request_error`.
+    /// * `message` - A human readable description of the error.
+    pub fn new(status: StatusCode, kind: &str, message: impl Into<String>) -> Self {
+        let category = ErrorCategory::from_status(status);
+        Self {
+            status,
+            category,
+            body: ErrorBody {
+                message: message.into(),
+                kind: kind.to_string(),
+                param: None,
+                code: Some(category.as_str().to_string()),
+            },
+            retry_after: None,
+        }
+    }
+
+    /// Sets the category of the error, which is also its code unless a specific code
+    /// was set.
+    pub fn with_category(mut self, category: ErrorCategory) -> Self {
+        if self.body.code.as_deref() == Some(self.category.as_str()) {
+            self.body.code = Some(category.as_str().to_string());
+        }
+        self.category = category;
+        self
+    }
+
+    /// Sets the request parameter the error refers to.
+    pub fn with_param(mut self, param: impl Into<String>) -> Self {
+        self.body.param = Some(param.into());
+        self
+    }
+
+    /// Sets the machine readable error code.
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.body.code = Some(code.into());
+        self
+    }
+
+    /// Tells the client how many seconds to wait before retrying, in `Retry-After`.
+    pub fn with_retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    /// A `400 Bad Request` caused by an invalid request payload.
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request_error", message)
+    }
+
+    /// A `404 Not Found` for a resource that does not exist.
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "invalid_request_error", message)
+    }
+
+    /// A `413 Payload Too Large` for a request exceeding a configured size limit.
+    pub fn payload_too_large(message: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "invalid_request_error",
+            message,
+        )
+    }
+
+    /// A `500 Internal Server Error`. The cause is logged, not returned to the client.
+    pub fn internal(err: impl Display) -> Self {
+        error!("Internal error: {}", err);
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "server_error",
+            "The server had an error while processing your request",
+        )
+    }
+
+    /// Returns the HTTP status code of the error.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the OpenAI error body.
+    pub fn body(&self) -> &ErrorBody {
+        &self.body
+    }
+
+    /// Returns the category of the error.
+    pub fn category(&self) -> ErrorCategory {
+        self.category
+    }
+
+    /// Counts the error in the metrics, once it is returned to the client.
+    pub fn record(&self) {
+        ERROR_COUNTS[self.category as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        if let Some(exceeded) = err.downcast_ref::<DeadlineExceeded>() {
+            return Self::from(exceeded.clone());
+        }
+        if let Some(open) = err.downcast_ref::<CircuitOpen>() {
+            return Self::from(open.clone());
+        }
+        if let Some(logits) = err.downcast_ref::<NonFiniteLogits>() {
+            return Self::from(logits.clone());
+        }
+        if let Some(too_long) = err.downcast_ref::<InputTooLong>() {
+            return Self::from(too_long.clone());
+        }
+        if let Some(invalid) = err.downcast_ref::<InvalidImage>() {
+            return Self::from(invalid.clone());
+        }
+        match err.downcast_ref::<GuardrailViolation>() {
+            Some(violation) => {
+                Self::invalid_request(violation.to_string()).with_code("content_filter")
+            }
+            None => {
+                let category = ErrorCategory::of(&err);
+                Self::internal(err).with_category(category)
+            }
+        }
+    }
+}
+
+impl From<DeadlineExceeded> for ApiError {
+    fn from(err: DeadlineExceeded) -> Self {
+        Self::new(
+            StatusCode::REQUEST_TIMEOUT,
+            "timeout_error",
+            err.to_string(),
+        )
+        .with_code("deadline_exceeded")
+    }
+}
+
+impl From<CircuitOpen> for ApiError {
+    fn from(err: CircuitOpen) -> Self {
+        Self::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "server_error",
+            err.to_string(),
+        )
+        .with_code("model_recovering")
+        .with_category(ErrorCategory::Internal)
+    }
+}
+
+impl From<NonFiniteLogits> for ApiError {
+    fn from(err: NonFiniteLogits) -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "server_error",
+            err.to_string(),
+        )
+        .with_code("non_finite_logits")
+    }
+}
+
+impl From<InputTooLong> for ApiError {
+    fn from(err: InputTooLong) -> Self {
+        Self::invalid_request(err.to_string())
+            .with_param("input")
+            .with_code("input_too_long")
+    }
+}
+
+impl From<InvalidImage> for ApiError {
+    fn from(err: InvalidImage) -> Self {
+        Self::invalid_request(err.to_string())
+            .with_param("input")
+            .with_code("invalid_image")
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        self.record();
+        let mut response = (self.status, Json(ErrorResponse { error: self.body })).into_response();
+        if let Some(secs) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
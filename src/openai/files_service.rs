@@ -0,0 +1,213 @@
+use crate::core::files::{FileStore, FileStoreError};
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::models::{DeleteFileResponse, FileObject, ListFilesQuery, ListFilesResponse};
+use axum::extract::{Multipart, Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use tracing::info;
+
+/// The purposes accepted by the OpenAI files API.
+const FILE_PURPOSES: [&str; 7] = [
+    "assistants",
+    "batch",
+    "batch_output",
+    "fine-tune",
+    "vision",
+    "user_data",
+    "evals",
+];
+
+impl From<FileStoreError> for ApiError {
+    fn from(err: FileStoreError) -> Self {
+        match err {
+            FileStoreError::FileTooLarge { .. } | FileStoreError::StorageFull { .. } => {
+                ApiError::payload_too_large(err.to_string()).with_param("file")
+            }
+            FileStoreError::InvalidId(_) => ApiError::not_found(err.to_string()),
+            FileStoreError::Io(_) | FileStoreError::Metadata(_) => ApiError::internal(err),
+        }
+    }
+}
+
+/// Uploads a file.
+///
+/// This function accepts a `multipart/form-data` body with a `file` part holding the
+/// content and a `purpose` part, stores the file on disk and returns its metadata.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `multipart` - The multipart form of the upload.
+///
+/// # Returns
+///
+/// The `FileObject` of the stored file wrapped in `Json`, or an `ApiError` if the
+/// form is incomplete, the purpose is unknown or a size limit is exceeded.
+pub async fn upload_file(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Json<FileObject>, ApiError> {
+    let mut purpose = None;
+    let mut file = None;
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| ApiError::invalid_request(e.body_text()))?
+    {
+        match field.name() {
+            Some("purpose") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| ApiError::invalid_request(e.body_text()))?;
+                purpose = Some(text);
+            }
+            Some("file") => {
+                let filename = field.file_name().unwrap_or("upload").to_string();
+                let bytes = field
+                    .bytes()
+                    .await
+                    .map_err(|e| ApiError::invalid_request(e.body_text()))?;
+                file = Some((filename, bytes));
+            }
+            _ => {}
+        }
+    }
+
+    let Some(purpose) = purpose else {
+        return Err(ApiError::invalid_request("Missing required parameter: 'purpose'")
+            .with_param("purpose"));
+    };
+    if !FILE_PURPOSES.contains(&purpose.as_str()) {
+        return Err(ApiError::invalid_request(format!(
+            "'{purpose}' is not one of {FILE_PURPOSES:?} - 'purpose'"
+        ))
+        .with_param("purpose"));
+    }
+    let Some((filename, bytes)) = file else {
+        return Err(ApiError::invalid_request("Missing required parameter: 'file'")
+            .with_param("file"));
+    };
+
+    let file = with_store(&state, move |files| files.create(&filename, &purpose, &bytes)).await?;
+    info!("Stored file {} ({} bytes)", file.id, file.bytes);
+
+    Ok(Json(file))
+}
+
+/// Lists the uploaded files.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `query` - Optional `purpose` filter.
+///
+/// # Returns
+///
+/// The `ListFilesResponse` wrapped in `Json`.
+pub async fn list_files(
+    State(state): State<AppState>,
+    Query(query): Query<ListFilesQuery>,
+) -> Result<Json<ListFilesResponse>, ApiError> {
+    let purpose = query.purpose;
+    let data = with_store(&state, move |files| files.list(purpose.as_deref())).await?;
+
+    Ok(Json(ListFilesResponse {
+        object: "list".to_string(),
+        data,
+    }))
+}
+
+/// Retrieves the metadata of a file.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `file_id` - The ID of the file to retrieve.
+///
+/// # Returns
+///
+/// The `FileObject` wrapped in `Json`, or a `404` if the file does not exist.
+pub async fn retrieve_file(
+    State(state): State<AppState>,
+    Path(file_id): Path<String>,
+) -> Result<Json<FileObject>, ApiError> {
+    let id = file_id.clone();
+    with_store(&state, move |files| files.get(&id))
+        .await?
+        .map(Json)
+        .ok_or_else(|| file_not_found(&file_id))
+}
+
+/// Retrieves the content of a file.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `file_id` - The ID of the file to download.
+///
+/// # Returns
+///
+/// The raw file content, or a `404` if the file does not exist.
+pub async fn retrieve_file_content(
+    State(state): State<AppState>,
+    Path(file_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let id = file_id.clone();
+    let content = with_store(&state, move |files| files.content(&id))
+        .await?
+        .ok_or_else(|| file_not_found(&file_id))?;
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/octet-stream")],
+        content,
+    ))
+}
+
+/// Deletes a file.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `file_id` - The ID of the file to delete.
+///
+/// # Returns
+///
+/// The `DeleteFileResponse` wrapped in `Json`, or a `404` if the file does not exist.
+pub async fn delete_file(
+    State(state): State<AppState>,
+    Path(file_id): Path<String>,
+) -> Result<Json<DeleteFileResponse>, ApiError> {
+    let id = file_id.clone();
+    if !with_store(&state, move |files| files.delete(&id)).await? {
+        return Err(file_not_found(&file_id));
+    }
+
+    Ok(Json(DeleteFileResponse {
+        id: file_id,
+        object: "file".to_string(),
+        deleted: true,
+    }))
+}
+
+/// Runs an operation of the file store on a blocking thread, since the storage backend
+/// may be a remote bucket.
+async fn with_store<T: Send + 'static>(
+    state: &AppState,
+    operation: impl FnOnce(&FileStore) -> Result<T, FileStoreError> + Send + 'static,
+) -> Result<T, ApiError> {
+    let files = state.files.clone();
+    let result = tokio::task::spawn_blocking(move || operation(&files))
+        .await
+        .map_err(ApiError::internal)?;
+
+    Ok(result?)
+}
+
+pub(crate) fn file_not_found(file_id: &str) -> ApiError {
+    ApiError::not_found(format!("No such File object: {file_id}")).with_param("id")
+}
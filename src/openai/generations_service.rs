@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use crate::core::access_log::digest;
+use crate::core::generation_jobs::{GenerationJob, JobHandle, JobStatus};
+use crate::core::webhooks::{is_valid_url, spawn_delivery, WebhookEvent};
+use crate::openai::errors::{ApiError, ErrorResponse};
+use crate::openai::http_entities::AppState;
+use crate::openai::http_service::{create_chat_completion, create_completion};
+use crate::openai::limits::api_key;
+use crate::openai::models::{
+    CreateChatCompletionRequest, CreateCompletionRequest, CreateGenerationRequest, GenerationQuery,
+};
+use crate::openai::request_json::RequestJson;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tracing::info;
+
+/// The endpoint a generation runs on when the request names none.
+const DEFAULT_URL: &str = "/v1/chat/completions";
+
+/// A request of a generation, parsed for its endpoint.
+enum GenerationRequest {
+    Chat(CreateChatCompletionRequest),
+    Completion(CreateCompletionRequest),
+}
+
+/// Starts a generation in the background and returns its id right away.
+///
+/// The request is run by the endpoint it names as if it was sent to it, so it goes
+/// through the same validation, limits and queue, but the client polls
+/// `GET /v1/generations/{id}` for the result instead of holding the connection open
+/// for the whole generation. Once it ended, a `generation.completed` or
+/// `generation.failed` event is sent to the registered webhooks and to the `webhook_url`
+/// of the request.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key the generation runs with.
+/// * `request` - The `CreateGenerationRequest` with the endpoint and its request.
+///
+/// # Returns
+///
+/// The queued `GenerationJob` with a `202 Accepted` status, or an `ApiError` if the
+/// endpoint is not supported, the request cannot be parsed, is streamed or the webhook
+/// URL is invalid or not allowed by `webhooks.allowed_url_prefixes`.
+pub async fn create_generation(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateGenerationRequest>,
+) -> Result<(StatusCode, Json<GenerationJob>), ApiError> {
+    let url = request.url.as_deref().unwrap_or(DEFAULT_URL).to_string();
+    if request.body.get("stream").and_then(Value::as_bool) == Some(true) {
+        return Err(
+            ApiError::invalid_request("Generations cannot be streamed, poll them instead")
+                .with_param("body.stream"),
+        );
+    }
+    let generation = match url.as_str() {
+        "/v1/chat/completions" => GenerationRequest::Chat(parse_body(request.body)?),
+        "/v1/completions" => GenerationRequest::Completion(parse_body(request.body)?),
+        _ => {
+            return Err(ApiError::invalid_request(format!(
+                "Unsupported url '{url}', expected '/v1/chat/completions' or '/v1/completions'"
+            ))
+            .with_param("url"))
+        }
+    };
+    if let Some(webhook_url) = &request.webhook_url {
+        if !is_valid_url(webhook_url) {
+            return Err(ApiError::invalid_request(format!(
+                "Invalid webhook_url '{webhook_url}', expected an http or https URL"
+            ))
+            .with_param("webhook_url"));
+        }
+        if !state
+            .settings
+            .current()
+            .webhooks
+            .allows_request_url(webhook_url)
+        {
+            return Err(ApiError::invalid_request(format!(
+                "The webhook_url '{webhook_url}' is not under an allowed prefix of the server"
+            ))
+            .with_param("webhook_url"));
+        }
+    }
+
+    let owner = api_key(&headers).map(|key| digest(key.as_bytes()));
+    let job = state.generations.create(&url, owner);
+    let created = job.snapshot();
+    info!("Queued generation {} on {url}", created.id);
+
+    tokio::spawn(run_generation(
+        state,
+        headers,
+        generation,
+        job,
+        request.webhook_url,
+    ));
+
+    Ok((StatusCode::ACCEPTED, Json(created)))
+}
+
+/// Returns a generation, waiting up to `wait` seconds for it to end.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key must be the one of the generation.
+/// * `generation_id` - The id of the generation.
+/// * `query` - How long to wait, bounded by the `max_wait_secs` setting.
+///
+/// # Returns
+///
+/// The `GenerationJob` as it stands when it ended or the wait ran out, or a
+/// `not_found` `ApiError` if it does not exist, expired or belongs to another key.
+pub async fn retrieve_generation(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(generation_id): Path<String>,
+    Query(query): Query<GenerationQuery>,
+) -> Result<Json<GenerationJob>, ApiError> {
+    let max_wait = state.settings.current().generations.max_wait_secs;
+    let wait = Duration::from_secs(query.wait.unwrap_or(0).min(max_wait));
+    let owner = api_key(&headers).map(|key| digest(key.as_bytes()));
+
+    let job = state
+        .generations
+        .wait(&generation_id, owner.as_deref(), wait)
+        .await
+        .ok_or_else(|| {
+            ApiError::not_found(format!("No generation found with id '{generation_id}'"))
+                .with_param("generation_id")
+        })?;
+
+    Ok(Json(job))
+}
+
+/// Parses the request of a generation for its endpoint.
+fn parse_body<T: DeserializeOwned>(body: Value) -> Result<T, ApiError> {
+    serde_json::from_value(body)
+        .map_err(|err| ApiError::invalid_request(format!("Invalid body: {err}")).with_param("body"))
+}
+
+/// Runs a generation on its endpoint, records how it ended and notifies the webhooks.
+async fn run_generation(
+    state: AppState,
+    headers: HeaderMap,
+    request: GenerationRequest,
+    job: JobHandle,
+    webhook_url: Option<String>,
+) {
+    job.start();
+    let response = match request {
+        GenerationRequest::Chat(request) => {
+            create_chat_completion(State(state.clone()), headers, RequestJson(request)).await
+        }
+        GenerationRequest::Completion(request) => {
+            create_completion(State(state.clone()), headers, RequestJson(request)).await
+        }
+    };
+    record_response(&job, response.unwrap_or_else(IntoResponse::into_response)).await;
+
+    let generation = job.snapshot();
+    let kind = match generation.status {
+        JobStatus::Completed => "generation.completed",
+        _ => "generation.failed",
+    };
+    spawn_delivery(
+        state.settings.current().webhooks.clone(),
+        WebhookEvent::new(kind, &generation),
+        webhook_url,
+    );
+}
+
+/// Completes or fails a generation from the response of its endpoint.
+async fn record_response(job: &JobHandle, response: Response) {
+    let status = response.status();
+    let body = match axum::body::to_bytes(response.into_body(), usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            job.fail(ApiError::internal(err).body().clone());
+            return;
+        }
+    };
+
+    if status.is_success() {
+        let response = serde_json::from_slice(&body)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+        info!("Generation {} completed", job.snapshot().id);
+        job.complete(response);
+    } else {
+        let error = match serde_json::from_slice::<ErrorResponse>(&body) {
+            Ok(response) => response.error,
+            Err(_) => ApiError::new(status, "server_error", String::from_utf8_lossy(&body))
+                .body()
+                .clone(),
+        };
+        info!("Generation {} failed: {}", job.snapshot().id, error.message);
+        job.fail(error);
+    }
+}
@@ -1,8 +1,43 @@
-use candle_core::Device;
+use std::sync::Arc;
+use std::time::Duration;
 
-use candle_transformers::models::llama::{Config, Llama as Llama3};
+use crate::config::{ServerConfig, SettingsHandle};
+use crate::core::assistants::AssistantStore;
+use crate::core::audit::AuditLog;
+use crate::core::chat_template::{ChatTemplate, TemplateTokens};
+use crate::core::circuit_breaker::{spawn_recovery, CircuitBreaker};
+use crate::core::conversations::ConversationStore;
+use crate::core::device_memory::DeviceMemory;
+use crate::core::embedding_cache::EmbeddingCacheStats;
+use crate::core::embeddings::Embedder;
+use crate::core::files::FileStore;
+use crate::core::fim::FimTemplate;
+use crate::core::generation_jobs::GenerationJobs;
+use crate::core::generation_queue::GenerationQueue;
+use crate::core::guardrails::{Guardrail, GuardrailStage, Guardrails};
+use crate::core::image_embeddings::ImageEmbedder;
+use crate::core::logging::LogFilter;
+use crate::core::mcp::McpClients;
+use crate::core::model_handle::{spawn_idle_unloader, ModelHandle};
+use crate::core::model_updates::{spawn_update_checker, ModelUpdater};
+use crate::core::prefix_cache::PrefixCache;
+use crate::core::rag::RagStore;
+#[cfg(unix)]
+use crate::core::settings_reload::spawn_hangup_handler;
+use crate::core::speech::SpeechSynthesizer;
+use crate::core::stats::EngineStats;
+use crate::core::stop_tokens::resolve_stop_tokens;
+use crate::core::streams::StreamRegistry;
+use crate::core::tools::check_registry;
+use crate::core::transcription::Transcriber;
+use crate::core::vector_stores::VectorStores;
+use crate::core::webhooks::is_valid_url;
+use candle_core::{DType, Device};
+
+use candle_transformers::models::llama::Config;
 use serde::{Deserialize, Serialize};
 use tokenizers::Tokenizer;
+use tokio::task::JoinHandle;
 
 #[derive(Serialize, Deserialize)]
 pub struct CompletionsRequest {
@@ -110,6 +145,41 @@ impl Usage {
     }
 }
 
+/// The detailed status of the server returned by the health endpoint.
+#[derive(Serialize)]
+pub struct HealthResponse {
+    pub status: String,
+    /// Whether the model weights are in memory: `ready`, `cold` while reloading or `unloaded`.
+    pub readiness: String,
+    /// `open` while the model is recovering from repeated failures, `closed` otherwise.
+    pub circuit: String,
+    pub model: String,
+    pub revision: String,
+    /// The device the model runs on, such as `cuda:0`.
+    pub device: String,
+    /// The compute backends compiled into the server, such as `cpu` and `cuda`.
+    pub backends: Vec<&'static str>,
+    pub dtype: String,
+    /// The memory of the accelerator, absent on the CPU.
+    pub memory: Option<DeviceMemory>,
+    pub kv_cache: KvCacheStatus,
+    /// The hit counters of the embedding cache, absent when it is disabled.
+    pub embedding_cache: Option<EmbeddingCacheStats>,
+    /// The number of generations waiting for or holding the model.
+    pub queue_depth: usize,
+    pub uptime_secs: u64,
+}
+
+/// The usage of the KV caches of the running generations.
+#[derive(Serialize)]
+pub struct KvCacheStatus {
+    pub tokens: usize,
+    /// The number of tokens the running generations could hold at their maximum context length.
+    pub capacity_tokens: usize,
+    /// `tokens` relative to `capacity_tokens`, between 0 and 1.
+    pub utilization: f64,
+}
+
 // #[derive(Deserialize)]
 // pub struct Prompt {
 //     pub prompt: String,
@@ -117,19 +187,247 @@ impl Usage {
 
 #[derive(Clone)]
 pub struct AppState {
-    pub(crate) model: Llama3,
+    pub(crate) model: Arc<ModelHandle>,
     pub(crate) device: Device,
     pub(crate) tokenizer: Tokenizer,
     pub(crate) config: Config,
+    pub(crate) dtype: DType,
+    /// The configuration, whose runtime-tunable sections can be reloaded.
+    pub(crate) settings: Arc<SettingsHandle>,
+    pub(crate) files: Arc<FileStore>,
+    pub(crate) conversations: Option<Arc<ConversationStore>>,
+    pub(crate) assistants: Option<Arc<AssistantStore>>,
+    pub(crate) audit: Option<Arc<AuditLog>>,
+    pub(crate) guardrails: Arc<Guardrails>,
+    pub(crate) fim: Option<Arc<FimTemplate>>,
+    /// The chat template bundled with the served model, used when a request sets none.
+    pub(crate) chat_template: Option<Arc<ChatTemplate>>,
+    /// The special tokens of `tokenizer_config.json`, which the chat templates refer to.
+    pub(crate) special_tokens: TemplateTokens,
+    /// The tokens ending the generation of the served model.
+    pub(crate) stop_tokens: Arc<[u32]>,
+    pub(crate) streams: Arc<StreamRegistry>,
+    pub(crate) prefix_cache: Option<Arc<PrefixCache>>,
+    pub(crate) breaker: Option<Arc<CircuitBreaker>>,
+    /// Where generations wait when the number of running generations is limited.
+    pub(crate) queue: Option<Arc<GenerationQueue>>,
+    /// The generations run in the background for `/v1/generations`.
+    pub(crate) generations: Arc<GenerationJobs>,
+    pub(crate) stats: Arc<EngineStats>,
+    pub(crate) transcriber: Option<Arc<Transcriber>>,
+    pub(crate) synthesizer: Option<Arc<SpeechSynthesizer>>,
+    pub(crate) embedder: Option<Arc<Embedder>>,
+    pub(crate) image_embedder: Option<Arc<ImageEmbedder>>,
+    pub(crate) rag: Option<Arc<RagStore>>,
+    pub(crate) vector_stores: Option<Arc<VectorStores>>,
+    /// The clients of the MCP servers whose tools are offered to the model.
+    pub(crate) mcp: Arc<McpClients>,
+    pub(crate) updater: Option<Arc<ModelUpdater>>,
+    pub(crate) log_filter: Option<Arc<LogFilter>>,
 }
 
-impl From<(Llama3, Device, Tokenizer, Config)> for AppState {
-    fn from(e: (Llama3, Device, Tokenizer, Config)) -> Self {
-        Self {
-            model: e.0,
-            device: e.1,
-            tokenizer: e.2,
-            config: e.3,
+impl AppState {
+    /// Creates the application state from the loaded model and the server configuration.
+    ///
+    /// Optional model-backed subsystems start out disabled and are attached by
+    /// `initialise_model` when they are configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The handle of the loaded Llama model.
+    /// * `device` - The device the model runs on.
+    /// * `tokenizer` - The tokenizer of the model.
+    /// * `config` - The model configuration.
+    /// * `dtype` - The data type the weights were loaded in.
+    /// * `settings` - The server configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the configured subsystems cannot be initialised.
+    pub fn new(
+        model: Arc<ModelHandle>,
+        device: Device,
+        tokenizer: Tokenizer,
+        config: Config,
+        dtype: DType,
+        settings: ServerConfig,
+    ) -> anyhow::Result<Self> {
+        let files = FileStore::open(settings.files.clone(), &settings.storage)?;
+        let conversations = match &settings.conversations.database {
+            Some(path) => Some(Arc::new(ConversationStore::open(path)?)),
+            None => None,
+        };
+        let assistants = match &settings.assistants.database {
+            Some(path) => Some(Arc::new(AssistantStore::open(path)?)),
+            None => None,
+        };
+        let audit = match &settings.audit.database {
+            Some(path) => Some(Arc::new(AuditLog::open(path)?)),
+            None => None,
+        };
+        let guardrails = Guardrails::from_settings(&settings.guardrails)?;
+        let fim = match &settings.fim {
+            Some(template) => {
+                template.check(&tokenizer)?;
+                Some(template.clone())
+            }
+            None => FimTemplate::detect(&tokenizer),
+        };
+        let stop_tokens = resolve_stop_tokens(
+            &tokenizer,
+            config.eos_token_id.as_ref(),
+            settings
+                .model_settings(&settings.model.id)
+                .stop_tokens
+                .as_deref(),
+        )?;
+        if let Some(endpoint) = settings
+            .webhooks
+            .endpoints
+            .iter()
+            .find(|endpoint| !is_valid_url(&endpoint.url))
+        {
+            anyhow::bail!("Invalid webhook URL '{}'", endpoint.url);
         }
+        check_registry(&settings.tools, &settings.mcp)?;
+        let mcp = McpClients::new(&settings.mcp);
+        let streams =
+            StreamRegistry::new(Duration::from_secs(settings.streaming.resume_window_secs));
+        let prefix_cache = settings.prefix_cache.enabled.then(|| {
+            Arc::new(PrefixCache::new(
+                settings.prefix_cache.max_entries,
+                Duration::from_secs(settings.prefix_cache.ttl_secs),
+                settings.prefix_cache.max_resume_tokens,
+            ))
+        });
+
+        let queue = (settings.queue.max_running > 0)
+            .then(|| Arc::new(GenerationQueue::new(settings.queue.max_running)));
+        let generations =
+            GenerationJobs::new(Duration::from_secs(settings.generations.retention_secs));
+
+        let breaker = settings.circuit_breaker.enabled.then(|| {
+            Arc::new(CircuitBreaker::new(
+                settings.circuit_breaker.failure_threshold,
+            ))
+        });
+
+        Ok(Self {
+            model,
+            device,
+            tokenizer,
+            config,
+            dtype,
+            settings: Arc::new(SettingsHandle::new(settings)),
+            files: Arc::new(files),
+            conversations,
+            assistants,
+            audit,
+            guardrails: Arc::new(guardrails),
+            fim: fim.map(Arc::new),
+            chat_template: None,
+            special_tokens: TemplateTokens::default(),
+            stop_tokens: stop_tokens.into(),
+            streams: Arc::new(streams),
+            prefix_cache,
+            breaker,
+            queue,
+            generations: Arc::new(generations),
+            stats: Arc::new(EngineStats::default()),
+            transcriber: None,
+            synthesizer: None,
+            embedder: None,
+            image_embedder: None,
+            rag: None,
+            vector_stores: None,
+            mcp: Arc::new(mcp),
+            updater: None,
+            log_filter: None,
+        })
+    }
+
+    /// Registers a custom guardrail hook in addition to the configured ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - Whether the hook inspects prompts or outputs.
+    /// * `hook` - The hook to run after the already registered hooks of the stage.
+    pub fn with_guardrail(mut self, stage: GuardrailStage, hook: Arc<dyn Guardrail>) -> Self {
+        Arc::make_mut(&mut self.guardrails).register(stage, hook);
+        self
+    }
+
+    /// Lets the log level follow the configuration when it is reloaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `log_filter` - The filter of the logs installed at startup.
+    pub fn with_log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(Arc::new(log_filter));
+        self
+    }
+
+    /// Starts unloading the model after the configured idle time, if one is configured.
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Returns
+    ///
+    /// The handle of the background task, or `None` when idle unloading is disabled.
+    pub fn spawn_idle_unloader(&self) -> Option<JoinHandle<()>> {
+        let minutes = self.settings.current().model.idle_unload_minutes?;
+
+        Some(spawn_idle_unloader(
+            self.model.clone(),
+            self.stats.clone(),
+            Duration::from_secs(minutes * 60),
+        ))
+    }
+
+    /// Starts recovering the model whenever the circuit breaker opens, if it is enabled.
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Returns
+    ///
+    /// The handle of the background task, or `None` when the circuit breaker is disabled.
+    pub fn spawn_recovery(&self) -> Option<JoinHandle<()>> {
+        Some(spawn_recovery(
+            self.clone(),
+            self.breaker.clone()?,
+            Duration::from_secs(self.settings.current().circuit_breaker.retry_delay_secs),
+        ))
+    }
+
+    /// Starts checking the model repository for new revisions, if update checks are configured.
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Returns
+    ///
+    /// The handle of the background task, or `None` when update checks are disabled.
+    pub fn spawn_update_checker(&self) -> Option<JoinHandle<()>> {
+        let minutes = self.settings.current().model.update_check_minutes?;
+
+        Some(spawn_update_checker(
+            self.updater.clone()?,
+            Duration::from_secs(minutes.max(1) * 60),
+        ))
+    }
+
+    /// Starts reloading the configuration whenever the process receives `SIGHUP`.
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Returns
+    ///
+    /// The handle of the background task.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the signal handler cannot be registered.
+    #[cfg(unix)]
+    pub fn spawn_reload_on_hangup(&self) -> anyhow::Result<JoinHandle<()>> {
+        spawn_hangup_handler(self.clone())
     }
 }
@@ -1,23 +1,142 @@
-use crate::core::generator::TextGeneration;
-use crate::openai::http_entities::AppState;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::EmbeddingOutput;
+use crate::core::chat_template::{ChatTemplate, ChatTemplateError, TemplateTokens};
+use crate::core::circuit_breaker::CircuitState;
+use crate::core::conversations::{ConversationStore, ConversationStoreError, StoredMessage};
+use crate::core::deadline::Deadline;
+use crate::core::dependencies::{check_dependencies, DependencyReport};
+use crate::core::device_memory::{compiled_backends, device_memory, device_name};
+use crate::core::embeddings::{EmbeddingOptions, Embeddings};
+use crate::core::events::{FinishReason, TokenUsage};
+use crate::core::generator::{PromptInput, TextGeneration};
+use crate::core::image_embeddings::{EmbeddingItem, ImageEmbedder, InvalidImage};
+use crate::core::prompts::{render_template, PromptTemplateError};
+use crate::core::reasoning::ReasoningParser;
+use crate::core::sampling::SamplingParams;
+use crate::core::tools::{OfferedTools, ToolChoice};
+use crate::core::usage_meter::{MeteredUsage, UsageMeter};
+use crate::openai::conversations_service::conversation_owner;
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::{AppState, HealthResponse, KvCacheStatus};
+use crate::openai::limits::{
+    api_key, check_memory_pressure, check_message_count, check_model_access, check_prompt_tokens,
+    model_allowed, prompt_limits,
+};
 use crate::openai::models::{
-    ChatCompletionChoice, ChatCompletionResponseMessage, CompletionChoice,
-    CreateChatCompletionRequest, CreateChatCompletionResponse, CreateCompletionRequest,
-    CreateCompletionResponse, CreateEmbeddingRequest, CreateEmbeddingResponse, DeleteModelResponse,
-    Embedding, ListModelsResponse, Model, Stop,
+    ChatCompletionChoice, ChatCompletionRequestMessage, ChatCompletionResponseMessage,
+    ChatCompletionStreamChoice, ChatCompletionStreamDelta, CompletionChoice,
+    CompletionStreamChoice, CompletionUsage, CreateChatCompletionRequest,
+    CreateChatCompletionResponse, CreateChatCompletionStreamResponse, CreateCompletionRequest,
+    CreateCompletionResponse, CreateCompletionStreamResponse, CreateEmbeddingRequest,
+    CreateEmbeddingResponse, DeleteModelResponse, Embedding, EmbeddingInputItem, EmbeddingUsage,
+    ListModelsResponse, Model, ModelCapabilities, Prompt, PromptTemplateReference,
 };
+use crate::openai::request_json::RequestJson;
+use crate::openai::streaming::{resume_stream, stream_generation};
+use crate::openai::validation::{check_model, Validate};
 use axum::extract::{Path, State};
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::Json;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use chrono::Utc;
-use tracing::{debug, info, trace};
+use tracing::{debug, error, info, trace};
 use uuid::Uuid;
 
+/// The header carrying the time in milliseconds the client waits for a generation.
+const TIMEOUT_HEADER: &str = "x-timeout-ms";
+
+impl From<PromptTemplateError> for ApiError {
+    fn from(err: PromptTemplateError) -> Self {
+        ApiError::invalid_request(err.to_string()).with_param("prompt_template")
+    }
+}
+
+impl From<ChatTemplateError> for ApiError {
+    fn from(err: ChatTemplateError) -> Self {
+        ApiError::invalid_request(err.to_string()).with_param("chat_template")
+    }
+}
+
+impl From<ConversationStoreError> for ApiError {
+    fn from(err: ConversationStoreError) -> Self {
+        match err {
+            ConversationStoreError::NotFound(_) => {
+                ApiError::not_found(err.to_string()).with_param("conversation_id")
+            }
+            ConversationStoreError::Database(_) => ApiError::internal(err),
+        }
+    }
+}
+
+/// The messages of a chat completion to append to a stored conversation.
+#[derive(Clone)]
+struct ConversationTurn {
+    store: Arc<ConversationStore>,
+    id: String,
+    /// The digest of the API key of the request.
+    owner: Option<String>,
+    user: Option<String>,
+    /// The roles and contents of the request messages.
+    messages: Vec<(String, String)>,
+}
+
+impl ConversationTurn {
+    /// Stores the request messages followed by the assistant reply, blocking the thread
+    /// on the database.
+    fn save(&self, reply: &str) -> Result<(), ConversationStoreError> {
+        let mut messages = self.messages.clone();
+        messages.push(("assistant".to_string(), reply.to_string()));
+
+        self.store.append(
+            &self.id,
+            self.owner.as_deref(),
+            self.user.as_deref(),
+            &messages,
+        )
+    }
+
+    /// Returns the stored messages of the conversation, read on a blocking thread.
+    async fn history(&self) -> Result<Vec<StoredMessage>, ApiError> {
+        let turn = self.clone();
+        let history = tokio::task::spawn_blocking(move || {
+            turn.store
+                .history(&turn.id, turn.owner.as_deref(), turn.user.as_deref())
+        })
+        .await
+        .map_err(ApiError::internal)??;
+
+        Ok(history)
+    }
+}
+
+/// Renders the prompt template referenced by a request.
+///
+/// # Arguments
+///
+/// * `state` - The application state holding the configured templates.
+/// * `reference` - The template name and variables from the request.
+///
+/// # Returns
+///
+/// The rendered system prompt, or `None` if the request references no template.
+fn render_prompt_template(
+    state: &AppState,
+    reference: Option<&PromptTemplateReference>,
+) -> Result<Option<String>, PromptTemplateError> {
+    reference
+        .map(|r| render_template(&state.settings.current().prompts, &r.name, &r.variables))
+        .transpose()
+}
+
 /// Health check endpoint.
 ///
 /// This function is called to check the health status of the service.
-/// It logs some debug information about the model state and returns a static string indicating that the service is up.
+/// It reports the served model, the device and its memory, the KV cache usage, the
+/// embedding cache hits, the number of generations in flight and the uptime.
 ///
 /// # Arguments
 ///
@@ -25,241 +144,1219 @@ use uuid::Uuid;
 ///
 /// # Returns
 ///
-/// A static string indicating that the service is up.
-pub async fn health(State(state): State<AppState>) -> &'static str {
+/// The `HealthResponse` wrapped in `Json`.
+pub async fn health(State(state): State<AppState>) -> Json<HealthResponse> {
     trace!("Health endpoint called");
 
-    info!("Model state is {}", state.device.is_metal());
-
-    info!("Model state is {}", state.device.is_cpu());
+    let queue_depth = state.stats.in_flight();
+    let tokens = state.stats.kv_cache_tokens();
+    let capacity_tokens = queue_depth * state.config.max_position_embeddings;
+    let utilization = if capacity_tokens == 0 {
+        0.0
+    } else {
+        tokens as f64 / capacity_tokens as f64
+    };
 
-    info!("Model state is {}", state.device.is_cuda());
+    let circuit = match &state.breaker {
+        Some(breaker) => breaker.state(),
+        None => CircuitState::Closed,
+    };
 
-    "Service is up!"
+    Json(HealthResponse {
+        status: match circuit {
+            CircuitState::Closed => "ok",
+            CircuitState::Open => "recovering",
+        }
+        .to_string(),
+        readiness: state.model.readiness().as_str().to_string(),
+        circuit: circuit.as_str().to_string(),
+        model: state.settings.current().model.id.clone(),
+        revision: state.model.revision(),
+        device: device_name(&state.device),
+        backends: compiled_backends(),
+        dtype: match state.settings.current().model.quantize {
+            Some(quantization) => quantization.as_str().to_string(),
+            None => format!("{:?}", state.dtype).to_lowercase(),
+        },
+        memory: device_memory(&state.device),
+        kv_cache: KvCacheStatus {
+            tokens,
+            capacity_tokens,
+            utilization,
+        },
+        embedding_cache: state
+            .embedder
+            .as_ref()
+            .and_then(|embedder| embedder.cache_stats()),
+        queue_depth,
+        uptime_secs: state.stats.uptime().as_secs(),
+    })
 }
 
 /// Creates a chat completion.
 ///
 /// This function takes a `CreateChatCompletionRequest` as input and generates a chat completion response.
 /// It extracts the necessary information from the request, such as temperature, top_p, and messages.
+/// When the request references a prompt template, the rendered template is prepended as a system message.
 /// It then generates the chat completion using the `TextGeneration` struct and returns a `CreateChatCompletionResponse`.
 ///
+/// With `stream: true` the completion is streamed as `chat.completion.chunk` server-sent events;
+/// a request repeated with the `Last-Event-ID` header of such a stream resumes it instead.
+///
 /// # Arguments
 ///
 /// * `state` - The application state.
+/// * `headers` - The request headers.
 /// * `request` - The `CreateChatCompletionRequest` containing the input parameters.
 ///
 /// # Returns
 ///
-/// A tuple containing the HTTP status code and the `CreateChatCompletionResponse` wrapped in `Json`.
+/// A tuple containing the HTTP status code and the `CreateChatCompletionResponse` wrapped in `Json`,
+/// or the SSE stream of chunks, or an `ApiError` if the request is invalid, the prompt template
+/// cannot be rendered or a guardrail rejects the request.
 pub async fn create_chat_completion(
     State(state): State<AppState>,
-    Json(request): Json<CreateChatCompletionRequest>,
-) -> impl IntoResponse {
-    let request_tuple: (AppState, Option<f64>, Option<f64>, Option<usize>) =
-        (state, request.temperature, request.top_p, None);
-    let text_gen = TextGeneration::from(request_tuple);
-    let max_tokens = request.max_tokens;
-
-    let content_vec: Vec<_> = request
-        .messages
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateChatCompletionRequest>,
+) -> Result<Response, ApiError> {
+    let stream = request.stream.unwrap_or(false);
+    if stream {
+        if let Some(response) = resume_stream(&state, &headers) {
+            return Ok(response);
+        }
+    }
+    let deadline = request_deadline(&headers, request.timeout)?;
+    let meter = Arc::new(UsageMeter::default());
+
+    request.validate(&state.settings.current().model.id)?;
+    check_model_access(&state, &headers, &request.model)?;
+    let limits = prompt_limits(&state, &headers);
+    check_message_count(&limits, request.messages.len(), "messages")?;
+    let system_prompt = render_prompt_template(&state, request.prompt_template.as_ref())?;
+    let chat_template = match &request.chat_template {
+        Some(spec) => Arc::new(ChatTemplate::resolve(spec)?),
+        None => state.chat_template.clone().unwrap_or_default(),
+    };
+    let params = SamplingParams::default()
+        .with_temperature(request.temperature)
+        .with_top_p(request.top_p)
+        .with_max_tokens(request.max_tokens)
+        .with_seed(request.seed.map(|seed| seed as u64))
+        .with_top_k(request.top_k.filter(|&k| k > 0).map(|k| k as usize))
+        .with_min_p(request.min_p)
+        .with_repeat_penalty(request.repetition_penalty)
+        .with_frequency_penalty(request.frequency_penalty.map(|penalty| penalty as f32))
+        .with_presence_penalty(request.presence_penalty.map(|penalty| penalty as f32));
+    let n = request.n.unwrap_or(1) as usize;
+    let mut generations = with_stopping(
+        choice_generations(&state, &params, n, deadline)?,
+        request.ignore_eos,
+        request.stop_token_ids.as_deref(),
+    );
+    if let Some(prediction) = &request.prediction {
+        let prediction = prediction.text();
+        generations = generations
+            .into_iter()
+            .map(|text_gen| text_gen.with_prediction(&prediction))
+            .collect::<anyhow::Result<_>>()?;
+    }
+    let id = Uuid::new_v4().to_string();
+    let created = Utc::now().timestamp_millis();
+    let model = state.settings.current().model.id.clone();
+    let system_fingerprint = model_fingerprint(&state);
+
+    let conversation = match (&request.conversation_id, &state.conversations) {
+        (Some(id), Some(store)) => Some(ConversationTurn {
+            store: store.clone(),
+            id: id.clone(),
+            owner: conversation_owner(&headers),
+            user: request.user.clone(),
+            messages: request
+                .messages
+                .iter()
+                .map(|message| (message.role.clone(), message.content.clone()))
+                .collect(),
+        }),
+        (Some(_), None) => {
+            return Err(ApiError::invalid_request(
+                "Conversation persistence is disabled on this server",
+            )
+            .with_param("conversation_id"))
+        }
+        (None, _) => None,
+    };
+    let history = match &conversation {
+        Some(turn) => turn.history().await?,
+        None => Vec::new(),
+    };
+
+    let system_message = system_prompt.map(|content| ChatCompletionRequestMessage {
+        role: "system".to_string(),
+        content,
+    });
+    let history = history
+        .into_iter()
+        .map(|message| ChatCompletionRequestMessage {
+            role: message.role,
+            content: message.content,
+        });
+    let server_tools = if stream || n > 1 {
+        OfferedTools::default()
+    } else {
+        let shadowed: Vec<String> = request
+            .tools
+            .iter()
+            .flatten()
+            .map(|tool| tool.function.name.clone())
+            .collect();
+        OfferedTools::new(
+            &state,
+            api_key(&headers),
+            &ToolChoice::from_value(request.tool_choice.as_ref()),
+            &shadowed,
+        )
+        .await
+    };
+    let tools_message = server_tools
+        .prompt()
+        .map(|content| ("system".to_string(), content));
+    let content_vec: Vec<_> = tools_message
         .into_iter()
-        .map(|message| format!("{}:{}", message.role, message.content))
+        .chain(
+            system_message
+                .into_iter()
+                .chain(history)
+                .chain(request.messages)
+                .map(|message| (message.role, message.content)),
+        )
         .collect();
-    let messages = content_vec.join(" ");
-    info!("Messages {}", messages);
+    let prompt = chat_template.render(&content_vec, &template_tokens(&state))?;
+    let reasoning = state.settings.current().model_settings(&model).reasoning;
+    let new_parser = || ReasoningParser::new(&reasoning, &prompt);
+    let messages = PromptInput::Text(prompt.clone());
+    trace!("Messages {:?}", messages);
+    check_prompt_tokens(&limits, &state.tokenizer, &messages, "messages")?;
+    check_memory_pressure(&state, &messages, request.max_tokens)?;
+    let generations = prefill_choices(generations, &messages).await?;
 
-    let content_result = text_gen.generate(messages, max_tokens);
+    if stream {
+        let mut first = vec![true; n];
+        let mut parsers: Vec<_> = (0..n).map(|_| new_parser()).collect();
+        let mut reply = String::new();
+        let include_usage = request
+            .stream_options
+            .as_ref()
+            .is_some_and(|options| options.include_usage);
+        let chunk = move |index: usize,
+                          content: Option<&str>,
+                          finish_reason: Option<&str>,
+                          usage: Option<TokenUsage>| {
+            if let Some(usage) = usage {
+                let chunk = CreateChatCompletionStreamResponse {
+                    id: id.clone(),
+                    object: "chat.completion.chunk".to_string(),
+                    created,
+                    model: model.clone(),
+                    system_fingerprint: system_fingerprint.clone(),
+                    choices: Vec::new(),
+                    usage: Some(usage.into()),
+                };
+                return serde_json::to_string(&chunk).unwrap_or_default();
+            }
+            let (content, reasoning_content) = match &mut parsers[index] {
+                Some(parser) => {
+                    let mut split = parser.push(content.unwrap_or_default());
+                    if finish_reason.is_some() {
+                        let rest = parser.finish();
+                        split.reasoning.push_str(&rest.reasoning);
+                        split.content.push_str(&rest.content);
+                    }
+                    (
+                        (content.is_some() || !split.content.is_empty()).then_some(split.content),
+                        (!split.reasoning.is_empty()).then_some(split.reasoning),
+                    )
+                }
+                None => (content.map(str::to_string), None),
+            };
+            // The conversation continues with the first choice
+            if index == 0 {
+                reply.push_str(content.as_deref().unwrap_or_default());
+                if let (Some(turn), Some(_)) = (&conversation, finish_reason) {
+                    let (turn, reply) = (turn.clone(), reply.clone());
+                    tokio::task::spawn_blocking(move || {
+                        if let Err(err) = turn.save(&reply) {
+                            error!("Failed to store conversation {}: {}", turn.id, err);
+                        }
+                    });
+                }
+            }
+            let delta = ChatCompletionStreamDelta {
+                role: first[index].then(|| "assistant".to_string()),
+                content,
+                reasoning_content,
+            };
+            first[index] = false;
+            let chunk = CreateChatCompletionStreamResponse {
+                id: id.clone(),
+                object: "chat.completion.chunk".to_string(),
+                created,
+                model: model.clone(),
+                system_fingerprint: system_fingerprint.clone(),
+                choices: vec![ChatCompletionStreamChoice {
+                    index: index as i64,
+                    delta,
+                    finish_reason: finish_reason.map(str::to_string),
+                }],
+                usage: None,
+            };
+            serde_json::to_string(&chunk).unwrap_or_default()
+        };
+        let stream_id = Uuid::new_v4().to_string();
+        return Ok(stream_generation(
+            &state,
+            api_key(&headers),
+            stream_id,
+            generations,
+            meter,
+            messages,
+            include_usage,
+            chunk,
+        )
+        .await);
+    }
+
+    let results = if server_tools.is_empty() {
+        generate_choices(generations, &meter, messages).await?
+    } else {
+        let next_generation = || {
+            Ok(with_stopping(
+                choice_generations(&state, &params, 1, deadline)?,
+                request.ignore_eos,
+                request.stop_token_ids.as_deref(),
+            ))
+        };
+        vec![
+            chat_with_tools(
+                &state,
+                &server_tools,
+                generations,
+                next_generation,
+                &meter,
+                content_vec,
+                &chat_template,
+                messages,
+            )
+            .await?,
+        ]
+    };
+    let results: Vec<_> = results
+        .into_iter()
+        .map(|(text, finish_reason)| match new_parser() {
+            Some(parser) => (parser.split(&text), finish_reason),
+            None => ((None, text), finish_reason),
+        })
+        .collect();
+    if let (Some(turn), Some(((_, content), _))) = (conversation, results.first()) {
+        let content = content.clone();
+        tokio::task::spawn_blocking(move || turn.save(&content))
+            .await
+            .map_err(ApiError::internal)??;
+    }
 
     let response = CreateChatCompletionResponse {
-        id: Uuid::new_v4().to_string(),
-        object: "text_completion".to_string(),
-        created: Utc::now().timestamp_millis(),
-        model: "Llama-3.2-3B-Instruct".parse().unwrap(),
-        choices: vec![ChatCompletionChoice {
-            index: 0,
-            message: ChatCompletionResponseMessage {
-                role: "assistant".to_string(),
-                content: content_result.to_string(),
-            },
-            finish_reason: "stop".to_string(),
-        }],
+        id,
+        object: "chat.completion".to_string(),
+        created,
+        model,
+        system_fingerprint,
+        choices: results
+            .into_iter()
+            .enumerate()
+            .map(
+                |(index, ((reasoning_content, content), finish_reason))| ChatCompletionChoice {
+                    index: index as i64,
+                    message: ChatCompletionResponseMessage {
+                        role: "assistant".to_string(),
+                        content,
+                        reasoning_content,
+                    },
+                    finish_reason: finish_reason.to_string(),
+                },
+            )
+            .collect(),
+        usage: meter.usage().into(),
+        max_tokens_applied: meter.usage().max_tokens_applied,
     };
 
     info!("create_chat_completion is done");
 
-    (StatusCode::OK, Json(response))
+    Ok((StatusCode::OK, meter.usage(), Json(response)).into_response())
 }
 
 /// Creates a text completion.
 ///
 /// This function takes a `CreateCompletionRequest` as input and generates a text completion response.
 /// It extracts the necessary information from the request, such as temperature, top_p, prompt, and max_tokens.
+/// When the request references a prompt template, the rendered template is prepended to the prompt.
 /// It then generates the text completion using the `TextGeneration` struct and returns a `CreateCompletionResponse`.
 ///
+/// With `stream: true` the completion is streamed as server-sent events; a request repeated with
+/// the `Last-Event-ID` header of such a stream resumes it instead.
+///
 /// # Arguments
 ///
 /// * `state` - The application state.
+/// * `headers` - The request headers.
 /// * `request` - The `CreateCompletionRequest` containing the input parameters.
 ///
 /// # Returns
 ///
-/// A tuple containing the HTTP status code and the `CreateCompletionResponse` wrapped in `Json`.
+/// A tuple containing the HTTP status code and the `CreateCompletionResponse` wrapped in `Json`,
+/// or the SSE stream of chunks, or an `ApiError` if the request is invalid, the prompt template
+/// cannot be rendered or a guardrail rejects the request.
 pub async fn create_completion(
     State(state): State<AppState>,
-    Json(request): Json<CreateCompletionRequest>,
-) -> impl IntoResponse {
-    let request_tuple: (AppState, Option<f64>, Option<f64>, Option<usize>) =
-        (state, request.temperature, request.top_p, None);
-    let text_gen = TextGeneration::from(request_tuple);
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateCompletionRequest>,
+) -> Result<Response, ApiError> {
+    let stream = request.stream.unwrap_or(false);
+    if stream {
+        if let Some(response) = resume_stream(&state, &headers) {
+            return Ok(response);
+        }
+    }
+    let deadline = request_deadline(&headers, request.timeout)?;
+    let meter = Arc::new(UsageMeter::default());
+
+    request.validate(&state.settings.current().model.id)?;
+    check_model_access(&state, &headers, &request.model)?;
+    let limits = prompt_limits(&state, &headers);
+    let system_prompt = render_prompt_template(&state, request.prompt_template.as_ref())?;
+    let params = SamplingParams::default()
+        .with_temperature(request.temperature)
+        .with_top_p(request.top_p)
+        .with_max_tokens(request.max_tokens)
+        .with_seed(request.seed.map(|seed| seed as u64))
+        .with_top_k(request.top_k.filter(|&k| k > 0).map(|k| k as usize))
+        .with_min_p(request.min_p)
+        .with_repeat_penalty(request.repetition_penalty)
+        .with_frequency_penalty(request.frequency_penalty)
+        .with_presence_penalty(request.presence_penalty);
+    let n = request.n.unwrap_or(1) as usize;
+    let mut generations = with_stopping(
+        choice_generations(&state, &params, n, deadline)?,
+        request.ignore_eos,
+        request.stop_token_ids.as_deref(),
+    );
+    let id = Uuid::new_v4().to_string();
+    let created = Utc::now().timestamp_millis();
+    let model = state.settings.current().model.id.clone();
+    let system_fingerprint = model_fingerprint(&state);
 
-    let prompt = String::from(request.prompt.unwrap());
-    let max_tokens = request.max_tokens;
+    let mut prompt = completion_prompt(&state, request.prompt, system_prompt)?;
+    if let Some(suffix) = &request.suffix {
+        let Some(fim) = &state.fim else {
+            return Err(ApiError::invalid_request(
+                "The served model does not support fill-in-the-middle completions",
+            )
+            .with_param("suffix"));
+        };
+        if let PromptInput::Text(prefix) = &prompt {
+            prompt = PromptInput::Text(fim.format(prefix, suffix));
+        }
+        let stop_tokens = fim.end_token(&state.tokenizer);
+        generations = generations
+            .into_iter()
+            .map(|text_gen| text_gen.with_stop_tokens(stop_tokens.clone()))
+            .collect();
+    }
+    check_prompt_tokens(&limits, &state.tokenizer, &prompt, "prompt")?;
+    check_memory_pressure(&state, &prompt, request.max_tokens)?;
+    let generations = prefill_choices(generations, &prompt).await?;
 
-    let result = text_gen.generate(prompt, max_tokens);
+    if stream {
+        let include_usage = request
+            .stream_options
+            .as_ref()
+            .is_some_and(|options| options.include_usage);
+        let chunk = move |index: usize,
+                          text: Option<&str>,
+                          finish_reason: Option<&str>,
+                          usage: Option<TokenUsage>| {
+            let choices = match usage {
+                Some(_) => Vec::new(),
+                None => vec![CompletionStreamChoice {
+                    text: text.unwrap_or_default().to_string(),
+                    index: index as i64,
+                    logprobs: None,
+                    finish_reason: finish_reason.map(str::to_string),
+                }],
+            };
+            let chunk = CreateCompletionStreamResponse {
+                id: id.clone(),
+                object: "text_completion".to_string(),
+                created,
+                model: model.clone(),
+                system_fingerprint: system_fingerprint.clone(),
+                choices,
+                usage: usage.map(CompletionUsage::from),
+            };
+            serde_json::to_string(&chunk).unwrap_or_default()
+        };
+        let stream_id = Uuid::new_v4().to_string();
+        return Ok(stream_generation(
+            &state,
+            api_key(&headers),
+            stream_id,
+            generations,
+            meter,
+            prompt,
+            include_usage,
+            chunk,
+        )
+        .await);
+    }
+
+    let results = generate_choices(generations, &meter, prompt).await?;
 
     let response = CreateCompletionResponse {
-        id: Uuid::new_v4().to_string(),
+        id,
         object: "text_completion".to_string(),
-        created: Utc::now().timestamp_millis(),
-        model: "Llama-3.2-3B-Instruct".parse().unwrap(),
-        choices: vec![CompletionChoice {
-            text: result.to_string(),
-            index: 0,
-            logprobs: None,
-            finish_reason: "stop".to_string(),
-        }],
+        created,
+        model,
+        system_fingerprint,
+        choices: results
+            .into_iter()
+            .enumerate()
+            .map(|(index, (text, finish_reason))| CompletionChoice {
+                text,
+                index: index as i64,
+                logprobs: None,
+                finish_reason: finish_reason.to_string(),
+            })
+            .collect(),
+        usage: meter.usage().into(),
+        max_tokens_applied: meter.usage().max_tokens_applied,
+    };
+
+    Ok((StatusCode::OK, meter.usage(), Json(response)).into_response())
+}
+
+/// Reads the deadline of a generation request.
+///
+/// Clients set the time they wait for the answer with the `X-Timeout-Ms` header, or with
+/// the `timeout` field of the body in seconds, like the `timeout` option of the OpenAI
+/// clients. The header takes precedence.
+///
+/// # Arguments
+///
+/// * `headers` - The headers of the request.
+/// * `timeout` - The `timeout` field of the request, in seconds.
+///
+/// # Returns
+///
+/// The deadline counted from now, or `None` when the client sent no timeout.
+///
+/// # Errors
+///
+/// Returns an `invalid_request_error` if the header or the field is not a positive duration.
+pub(crate) fn request_deadline(
+    headers: &HeaderMap,
+    timeout: Option<f64>,
+) -> Result<Option<Deadline>, ApiError> {
+    let timeout = match headers.get(TIMEOUT_HEADER) {
+        Some(value) => {
+            let millis = value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .filter(|&millis| millis > 0)
+                .ok_or_else(|| {
+                    ApiError::invalid_request(format!(
+                        "The {TIMEOUT_HEADER} header must be a positive number of milliseconds"
+                    ))
+                })?;
+            Duration::from_millis(millis)
+        }
+        None => match timeout {
+            // Also rejects values too large for a `Duration`
+            Some(seconds) if seconds > 0.0 && seconds < 1e9 => Duration::from_secs_f64(seconds),
+            Some(_) => {
+                return Err(ApiError::invalid_request(
+                    "'timeout' must be a positive number of seconds",
+                )
+                .with_param("timeout"))
+            }
+            None => return Ok(None),
+        },
     };
 
-    (StatusCode::OK, Json(response))
+    Ok(Some(Deadline::after(timeout)))
 }
 
-/// Creates an embedding.
+/// Creates the generations of the `n` choices of a request.
 ///
-/// This function takes a `CreateEmbeddingRequest` as input and generates an embedding response.
-/// It currently returns a placeholder response with hardcoded values.
+/// Every choice samples with its own seed, derived from the seed of the request, so that
+/// the choices differ while a seeded request stays reproducible.
 ///
 /// # Arguments
 ///
 /// * `state` - The application state.
-/// * `req` - The `CreateEmbeddingRequest` containing the input parameters.
+/// * `params` - The sampling parameters of the request.
+/// * `n` - The number of choices to generate.
+/// * `deadline` - The deadline of the request, if the client set one.
+///
+/// # Errors
+///
+/// Returns an error if the model weights cannot be reloaded or the deadline passed while
+/// they were.
+fn choice_generations(
+    state: &AppState,
+    params: &SamplingParams,
+    n: usize,
+    deadline: Option<Deadline>,
+) -> Result<Vec<TextGeneration>, ApiError> {
+    let settings = state.settings.current();
+    let seed = params
+        .seed
+        .unwrap_or(settings.model_settings(&settings.model.id).defaults.seed);
+    let generations = (0..n)
+        .map(|index| {
+            let params = params
+                .clone()
+                .with_seed(Some(seed.wrapping_add(index as u64)));
+            TextGeneration::from_state(state.clone(), &params)
+                .map(|text_gen| text_gen.with_deadline(deadline))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if let Some(deadline) = &deadline {
+        deadline.check()?;
+    }
+    Ok(generations)
+}
+
+/// The `system_fingerprint` of the responses: `fp_` followed by the first characters of
+/// the commit of the served model, so that clients can tell when the weights changed.
+fn model_fingerprint(state: &AppState) -> String {
+    let revision = state.model.revision();
+    format!("fp_{}", revision.chars().take(10).collect::<String>())
+}
+
+/// The special tokens of the served model that chat templates refer to.
+///
+/// Models without an eos token in `tokenizer_config.json` use their first stop token.
+pub(crate) fn template_tokens(state: &AppState) -> TemplateTokens {
+    let mut tokens = state.special_tokens.clone();
+    if tokens.eos_token.is_empty() {
+        tokens.eos_token = state
+            .stop_tokens
+            .first()
+            .and_then(|&id| state.tokenizer.id_to_token(id))
+            .unwrap_or_default();
+    }
+    tokens
+}
+
+/// Applies the vLLM stopping parameters of a request to the generations of its choices.
+///
+/// # Arguments
+///
+/// * `generations` - The generations of the choices.
+/// * `ignore_eos` - Whether the generations continue past the end-of-sequence tokens.
+/// * `stop_token_ids` - Extra token ids that end the generations.
+fn with_stopping(
+    generations: Vec<TextGeneration>,
+    ignore_eos: Option<bool>,
+    stop_token_ids: Option<&[u32]>,
+) -> Vec<TextGeneration> {
+    generations
+        .into_iter()
+        .map(|text_gen| {
+            let text_gen = match ignore_eos {
+                Some(true) => text_gen.ignoring_eos(),
+                _ => text_gen,
+            };
+            text_gen.with_stop_tokens(stop_token_ids.unwrap_or_default().iter().copied())
+        })
+        .collect()
+}
+
+/// Runs the model over the prompt once for all the choices of a request.
+///
+/// The KV cache of the prompt is retained in the prefix cache, so the generations of
+/// the choices resume from it instead of each processing the whole prompt.
+///
+/// # Returns
+///
+/// The generations of the choices, untouched.
+///
+/// # Errors
+///
+/// Returns an error if a prompt guardrail rejects the prompt or the model fails.
+async fn prefill_choices(
+    mut generations: Vec<TextGeneration>,
+    prompt: &PromptInput,
+) -> Result<Vec<TextGeneration>, ApiError> {
+    if generations.len() < 2 {
+        return Ok(generations);
+    }
+    let prompt = prompt.clone();
+
+    tokio::task::spawn_blocking(move || {
+        generations[0].prefill(prompt)?;
+        Ok::<_, anyhow::Error>(generations)
+    })
+    .await
+    .map_err(ApiError::internal)?
+    .map_err(ApiError::from)
+}
+
+/// Runs the generations of the choices of a request concurrently.
+///
+/// # Arguments
+///
+/// * `generations` - The configured generation of every choice.
+/// * `meter` - The usage meter the generations record into.
+/// * `prompt` - The prompt text or tokens to generate from.
 ///
 /// # Returns
 ///
-/// A tuple containing the HTTP status code and the `CreateEmbeddingResponse` wrapped in `Json`.
+/// The text and finish reason of every choice, in the order of the generations.
+///
+/// # Errors
+///
+/// Returns an error if any of the generations fails.
+async fn generate_choices(
+    generations: Vec<TextGeneration>,
+    meter: &Arc<UsageMeter>,
+    prompt: PromptInput,
+) -> Result<Vec<(String, FinishReason)>, ApiError> {
+    let tasks: Vec<_> = generations
+        .into_iter()
+        .map(|text_gen| {
+            let text_gen = text_gen.with_meter(meter.clone());
+            let prompt = prompt.clone();
+            tokio::task::spawn_blocking(move || text_gen.generate(prompt))
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        results.push(task.await.map_err(ApiError::internal)??);
+    }
+    Ok(results)
+}
+
+/// Generates the reply of a chat completion with a single choice, running the server
+/// tools the model calls and generating again with their results, added to the
+/// conversation as `tool` messages, until it answers or `tools.max_iterations` rounds of
+/// calls were run.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `server_tools` - The server tools offered to the model.
+/// * `generations` - The generation of the first round.
+/// * `next_generation` - Creates the generation of the next round.
+/// * `meter` - The usage meter every round records into.
+/// * `turns` - The roles and contents of the messages of the prompt.
+/// * `chat_template` - The template turning the messages into the prompt.
+/// * `prompt` - The rendered prompt of the first round.
+///
+/// # Returns
+///
+/// The text and finish reason of the answer.
+///
+/// # Errors
+///
+/// Returns an error if a generation fails or the conversation cannot be rendered.
+#[allow(clippy::too_many_arguments)]
+async fn chat_with_tools(
+    state: &AppState,
+    server_tools: &OfferedTools,
+    mut generations: Vec<TextGeneration>,
+    next_generation: impl Fn() -> Result<Vec<TextGeneration>, ApiError>,
+    meter: &Arc<UsageMeter>,
+    mut turns: Vec<(String, String)>,
+    chat_template: &ChatTemplate,
+    mut prompt: PromptInput,
+) -> Result<(String, FinishReason), ApiError> {
+    let mut round = 0;
+    loop {
+        let (text, finish_reason) = generate_choices(generations, meter, prompt)
+            .await?
+            .remove(0);
+        let results = if round == server_tools.max_iterations() {
+            None
+        } else {
+            server_tools.execute(state, &text).await
+        };
+        let Some(results) = results else {
+            return Ok((text, finish_reason));
+        };
+
+        turns.push(("assistant".to_string(), text));
+        for result in results {
+            turns.push((
+                "tool".to_string(),
+                format!("[{}] {}", result.name, result.output),
+            ));
+        }
+        prompt = PromptInput::Text(chat_template.render(&turns, &template_tokens(state))?);
+        generations = next_generation()?;
+        round += 1;
+    }
+}
+
+/// Turns the prompt of a validated completion request into the input of the generation.
+///
+/// Text prompts are prefixed with the rendered system prompt, token prompts are passed
+/// through untouched once their ids are checked against the vocabulary of the model.
+///
+/// # Errors
+///
+/// Returns an `invalid_request_error` if a token id is outside the vocabulary.
+pub(crate) fn completion_prompt(
+    state: &AppState,
+    prompt: Option<Prompt>,
+    system_prompt: Option<String>,
+) -> Result<PromptInput, ApiError> {
+    let text = match prompt {
+        Some(Prompt::Single(text)) => text,
+        Some(Prompt::ArrayOfStrings(prompts)) => prompts.into_iter().next().unwrap_or_default(),
+        Some(Prompt::ArrayOfTokens(tokens)) => return prompt_tokens(state, tokens),
+        Some(Prompt::ArrayOfTokenArrays(prompts)) => {
+            return prompt_tokens(state, prompts.into_iter().next().unwrap_or_default())
+        }
+        None => {
+            return Err(ApiError::invalid_request(
+                "Missing required parameter: 'prompt'",
+            ))
+        }
+    };
+
+    Ok(PromptInput::Text(match system_prompt {
+        Some(system_prompt) => format!("{}\n\n{}", system_prompt, text),
+        None => text,
+    }))
+}
+
+/// Checks the ids of a token prompt against the vocabulary of the served model.
+fn prompt_tokens(state: &AppState, tokens: Vec<i32>) -> Result<PromptInput, ApiError> {
+    let vocab_size = state.config.vocab_size;
+    if let Some(index) = tokens
+        .iter()
+        .position(|&token| token < 0 || token as usize >= vocab_size)
+    {
+        return Err(ApiError::invalid_request(format!(
+            "Token id {} is outside the vocabulary of {} tokens - 'prompt.{}'",
+            tokens[index], vocab_size, index
+        ))
+        .with_param("prompt"));
+    }
+    Ok(PromptInput::Tokens(
+        tokens.into_iter().map(|token| token as u32).collect(),
+    ))
+}
+
+/// Creates embeddings.
+///
+/// This function embeds the `input` texts with the configured embedding model and returns
+/// one vector per text, in the order of the input. The pooling and normalization of the
+/// model in `models` apply unless the request overrides them. Requests for the image
+/// embedding model may mix texts and base64 images.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+/// * `request` - The `CreateEmbeddingRequest` containing the input parameters.
+///
+/// # Returns
+///
+/// The `CreateEmbeddingResponse` wrapped in `Json` with the usage headers, or an
+/// `ApiError` if embeddings are not enabled, the request is invalid or the model fails.
 pub async fn create_embedding(
     State(state): State<AppState>,
-    Json(req): Json<CreateEmbeddingRequest>,
-) -> impl IntoResponse {
-    // TODO: Process request and return response
-    let response = CreateEmbeddingResponse {
-        object: "list".to_string(),
-        data: vec![Embedding {
-            object: "embedding".to_string(),
-            embedding: vec![0.1, 0.2, 0.3],
-            index: 0,
-        }],
-        model: req.model,
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateEmbeddingRequest>,
+) -> Result<(MeteredUsage, Json<CreateEmbeddingResponse>), ApiError> {
+    let meter = Arc::new(UsageMeter::default());
+    if let Some(image_embedder) = state.image_embedder.clone() {
+        if check_model(&request.model, image_embedder.model_id()).is_ok() {
+            return create_image_embedding(&state, &headers, image_embedder, meter, request).await;
+        }
+    }
+    let Some(embedder) = state.embedder.clone() else {
+        return Err(
+            ApiError::not_found("Embeddings are not enabled on this server")
+                .with_param("model")
+                .with_code("model_not_found"),
+        );
+    };
+    request.validate(embedder.model_id())?;
+    check_model_access(&state, &headers, embedder.model_id())?;
+
+    let settings = state.settings.current();
+    let output = settings.model_settings(embedder.model_id()).embedding;
+    let options = EmbeddingOptions {
+        output: EmbeddingOutput {
+            pooling: request.pooling.unwrap_or(output.pooling),
+            normalize: request.normalize.unwrap_or(output.normalize),
+        },
+        long_inputs: request
+            .long_inputs
+            .unwrap_or(settings.embeddings.long_inputs),
+        sparse: request.sparse.unwrap_or(false),
+        multi_vector: request.multi_vector.unwrap_or(false),
     };
+    if options.sparse && !embedder.supports_sparse() {
+        return Err(ApiError::invalid_request(
+            "The embedding model has no sparse head, see `embeddings.heads.sparse`",
+        )
+        .with_param("sparse"));
+    }
+    if options.multi_vector && !embedder.supports_multi_vector() {
+        return Err(ApiError::invalid_request(
+            "The embedding model has no multi-vector head, see `embeddings.heads.multi_vector`",
+        )
+        .with_param("multi_vector"));
+    }
+    let Some(texts) = request.input.into_texts() else {
+        return Err(ApiError::invalid_request(format!(
+            "The model '{}' does not embed images",
+            request.model
+        ))
+        .with_param("input"));
+    };
+    let metered = meter.clone();
+    let embeddings = tokio::task::spawn_blocking(move || {
+        metered.record_start(0);
+        embedder.embed_with(&texts, options)
+    })
+    .await
+    .map_err(ApiError::internal)??;
+    meter.record_prompt_tokens(embeddings.prompt_tokens);
+
+    Ok((
+        meter.usage(),
+        Json(embedding_response(request.model, embeddings)),
+    ))
+}
+
+/// Creates embeddings of texts and images with the image embedding model.
+///
+/// # Errors
+///
+/// Returns a `400` `ApiError` if the request is invalid or an image cannot be decoded.
+async fn create_image_embedding(
+    state: &AppState,
+    headers: &HeaderMap,
+    embedder: Arc<ImageEmbedder>,
+    meter: Arc<UsageMeter>,
+    request: CreateEmbeddingRequest,
+) -> Result<(MeteredUsage, Json<CreateEmbeddingResponse>), ApiError> {
+    request.validate(embedder.model_id())?;
+    check_model_access(state, headers, embedder.model_id())?;
+    if request.sparse.unwrap_or(false) || request.multi_vector.unwrap_or(false) {
+        return Err(ApiError::invalid_request(format!(
+            "The model '{}' only returns dense embeddings",
+            request.model
+        ))
+        .with_param("model"));
+    }
+
+    let normalize = request.normalize.unwrap_or(
+        state
+            .settings
+            .current()
+            .model_settings(embedder.model_id())
+            .embedding
+            .normalize,
+    );
+    let items = request
+        .input
+        .into_items()
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| match item {
+            EmbeddingInputItem::Text(text) => Ok(EmbeddingItem::Text(text)),
+            EmbeddingInputItem::Image { image } => decode_image(&image)
+                .map(EmbeddingItem::Image)
+                .map_err(|err| {
+                    ApiError::from(InvalidImage {
+                        index,
+                        reason: err.to_string(),
+                    })
+                }),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let metered = meter.clone();
+    let embeddings = tokio::task::spawn_blocking(move || {
+        metered.record_start(0);
+        embedder.embed(&items, normalize)
+    })
+    .await
+    .map_err(ApiError::internal)??;
+    meter.record_prompt_tokens(embeddings.prompt_tokens);
 
-    (StatusCode::OK, Json(response))
+    Ok((
+        meter.usage(),
+        Json(embedding_response(request.model, embeddings)),
+    ))
+}
+
+/// Decodes a base64 image, with or without its `data:image/...;base64,` prefix.
+fn decode_image(image: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    let encoded = match image.split_once(";base64,") {
+        Some((scheme, data)) if scheme.starts_with("data:") => data,
+        _ => image,
+    };
+    STANDARD.decode(encoded.trim())
+}
+
+/// Builds the response of `/v1/embeddings` from the vectors of the model.
+fn embedding_response(model: String, embeddings: Embeddings) -> CreateEmbeddingResponse {
+    let mut sparse = embeddings.sparse.map(Vec::into_iter);
+    let mut multi_vectors = embeddings.multi_vectors.map(Vec::into_iter);
+    CreateEmbeddingResponse {
+        object: "list".to_string(),
+        data: embeddings
+            .vectors
+            .into_iter()
+            .zip(embeddings.truncated)
+            .enumerate()
+            .map(|(index, (embedding, truncated))| Embedding {
+                object: "embedding".to_string(),
+                embedding,
+                index: index as i64,
+                truncated: truncated.then_some(true),
+                sparse_embedding: sparse.as_mut().and_then(Iterator::next),
+                multi_vector: multi_vectors.as_mut().and_then(Iterator::next),
+            })
+            .collect(),
+        model,
+        usage: EmbeddingUsage {
+            prompt_tokens: embeddings.prompt_tokens,
+            total_tokens: embeddings.prompt_tokens,
+        },
+    }
+}
+
+/// Dependencies health check endpoint.
+///
+/// Reports whether the Hugging Face Hub answers, whether the Hub cache is writable and
+/// the space left on its disk. A `degraded` server keeps serving the loaded models, but
+/// cannot download new ones, so a reload or a model update would fail.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+///
+/// # Returns
+///
+/// The `DependencyReport` wrapped in `Json`, or an `ApiError` if the checks panic.
+pub async fn health_dependencies(
+    State(state): State<AppState>,
+) -> Result<Json<DependencyReport>, ApiError> {
+    let settings = state.settings.current();
+    let report =
+        tokio::task::spawn_blocking(move || check_dependencies(&settings.hub, &settings.model.id))
+            .await
+            .map_err(ApiError::internal)?;
+    if report.status != "ok" {
+        info!(
+            "Dependencies degraded: hub reachable {}, cache writable {}, disk sufficient {}",
+            report.hub.reachable, report.cache.writable, report.disk.sufficient
+        );
+    }
+
+    Ok(Json(report))
 }
 
 /// Lists available models.
 ///
-/// This function returns a list of available models.
-/// It currently returns a placeholder response with hardcoded values.
+/// This function returns the served language model and the configured audio models,
+/// each with its capabilities so that clients can route requests across servers.
+/// Only the models the API key of the request may use are listed.
 ///
 /// # Arguments
 ///
 /// * `state` - The application state.
+/// * `headers` - The request headers.
 ///
 /// # Returns
 ///
-/// A tuple containing the HTTP status code and the `ListModelsResponse` wrapped in `Json`.
-pub async fn list_models(State(state): State<AppState>) -> impl IntoResponse {
-    // TODO: Fetch list of models and return response
-    let response = ListModelsResponse {
+/// The `ListModelsResponse` wrapped in `Json`.
+pub async fn list_models(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Json<ListModelsResponse> {
+    Json(ListModelsResponse {
         object: "list".to_string(),
-        data: vec![
-            Model {
-                id: "model-1".to_string(),
-                object: "model".to_string(),
-                created: 1677652288,
-                owned_by: "organization-1".to_string(),
-            },
-            Model {
-                id: "model-2".to_string(),
-                object: "model".to_string(),
-                created: 1677652288,
-                owned_by: "organization-1".to_string(),
-            },
-        ],
-    };
-
-    (StatusCode::OK, Json(response))
+        data: served_models(&state)
+            .into_iter()
+            .filter(|model| {
+                model_allowed(&state, &headers, model.root.as_ref().unwrap_or(&model.id))
+            })
+            .collect(),
+    })
 }
 
 /// Retrieves a specific model.
 ///
 /// This function retrieves details of a specific model identified by the `model_id` parameter.
-/// It currently returns a placeholder response with hardcoded values.
 ///
 /// # Arguments
 ///
 /// * `state` - The application state.
-/// * `model_id` - The ID of the model to retrieve.
+/// * `headers` - The request headers.
+/// * `model_id` - The ID of the model to retrieve, which may contain slashes.
 ///
 /// # Returns
 ///
-/// A tuple containing the HTTP status code and the `Model` wrapped in `Json`.
+/// The `Model` wrapped in `Json`, or an `ApiError` if the server does not serve it.
 pub async fn retrieve_model(
     State(state): State<AppState>,
-    Path(model_id): Path<Stop>,
-) -> impl IntoResponse {
-    // TODO: Fetch model details and return response
+    headers: HeaderMap,
+    Path(model_id): Path<String>,
+) -> Result<Json<Model>, ApiError> {
+    find_model(&state, &headers, &model_id).map(Json)
+}
 
-    let option_model_id = match model_id {
-        Stop::String(s) => Some(s), // Extract String value
-        Stop::Array(_) => None,     // Return None or handle as needed
-    };
-    let response = Model {
-        id: option_model_id.unwrap(),
+/// Finds a served model by id.
+///
+/// # Errors
+///
+/// Returns a `not_found` error with the `model_not_found` code if the server does not
+/// serve the model or the API key of the request may not use it.
+fn find_model(state: &AppState, headers: &HeaderMap, model_id: &str) -> Result<Model, ApiError> {
+    let model = served_models(state)
+        .into_iter()
+        .find(|model| model.id == model_id)
+        .ok_or_else(|| {
+            ApiError::not_found(format!("The model '{model_id}' does not exist"))
+                .with_param("model")
+                .with_code("model_not_found")
+        })?;
+    check_model_access(state, headers, model.root.as_ref().unwrap_or(&model.id))?;
+
+    Ok(model)
+}
+
+/// Describes the models served by this server.
+fn served_models(state: &AppState) -> Vec<Model> {
+    let created = Utc::now().timestamp() - state.stats.uptime().as_secs() as i64;
+    let model = |id: &str, capabilities| Model {
+        id: id.to_string(),
         object: "model".to_string(),
-        created: 1677652288,
-        owned_by: "organization-1".to_string(),
+        created,
+        owned_by: id
+            .split_once('/')
+            .map_or("system", |(owner, _)| owner)
+            .to_string(),
+        root: None,
+        capabilities,
+        verification: None,
     };
 
-    (StatusCode::OK, Json(response))
+    let settings = state.settings.current();
+    let mut models = vec![Model {
+        verification: Some(state.model.verification()),
+        ..model(
+            &settings.model.id,
+            ModelCapabilities {
+                supports_chat: true,
+                supports_fill_in_the_middle: state.fim.is_some(),
+                max_context: Some(state.config.max_position_embeddings),
+                ..ModelCapabilities::default()
+            },
+        )
+    }];
+    if let Some(id) = &settings.audio.transcription_model {
+        models.push(model(
+            id,
+            ModelCapabilities {
+                supports_transcription: state.transcriber.is_some(),
+                ..ModelCapabilities::default()
+            },
+        ));
+    }
+    if let Some(id) = &settings.audio.speech_model {
+        models.push(model(
+            id,
+            ModelCapabilities {
+                supports_speech: state.synthesizer.is_some(),
+                ..ModelCapabilities::default()
+            },
+        ));
+    }
+    if let Some(embedder) = &state.embedder {
+        models.push(model(
+            embedder.model_id(),
+            ModelCapabilities {
+                embedding_dims: Some(embedder.dims()),
+                supports_sparse_embeddings: embedder.supports_sparse(),
+                supports_multi_vector_embeddings: embedder.supports_multi_vector(),
+                ..ModelCapabilities::default()
+            },
+        ));
+    }
+    if let Some(embedder) = &state.image_embedder {
+        models.push(model(
+            embedder.model_id(),
+            ModelCapabilities {
+                embedding_dims: Some(embedder.dims()),
+                supports_image_embeddings: true,
+                ..ModelCapabilities::default()
+            },
+        ));
+    }
+
+    let mut aliases: Vec<_> = settings
+        .model_aliases
+        .iter()
+        .filter_map(|(alias, target)| {
+            let served = models.iter().find(|model| &model.id == target)?;
+            Some(Model {
+                id: alias.clone(),
+                owned_by: "system".to_string(),
+                root: Some(target.clone()),
+                ..served.clone()
+            })
+        })
+        .collect();
+    aliases.sort_by(|a, b| a.id.cmp(&b.id));
+    models.extend(aliases);
+
+    models
 }
 
 /// Deletes a specific model.
 ///
-/// This function deletes a specific model identified by the `model_id` parameter.
-/// It currently returns a placeholder response with hardcoded values.
+/// Only fine-tuned models can be deleted in the OpenAI API, and this server serves the
+/// models it was started with, so every deletion is refused.
 ///
 /// # Arguments
 ///
 /// * `state` - The application state.
-/// * `model_id` - The ID of the model to delete.
+/// * `headers` - The request headers.
+/// * `model_id` - The ID of the model to delete, which may contain slashes.
 ///
 /// # Returns
 ///
-/// A tuple containing the HTTP status code and the `DeleteModelResponse` wrapped in `Json`.
+/// An `ApiError`: `not_found` if the server does not serve the model, and an
+/// `invalid_request_error` with the `model_not_deletable` code otherwise.
 pub async fn delete_model(
     State(state): State<AppState>,
-    Path(model_id): Path<Stop>,
-) -> impl IntoResponse {
-    // TODO: Delete model and return response
-    let option_model_id = match model_id {
-        Stop::String(s) => Some(s), // Extract String value
-        Stop::Array(_) => None,     // Return None or handle as needed
-    };
-    let response = DeleteModelResponse {
-        id: option_model_id.unwrap(),
-        object: "model".to_string(),
-        deleted: true,
-    };
-
-    (StatusCode::OK, Json(response))
+    headers: HeaderMap,
+    Path(model_id): Path<String>,
+) -> Result<Json<DeleteModelResponse>, ApiError> {
+    let model = find_model(&state, &headers, &model_id)?;
+    Err(ApiError::invalid_request(format!(
+        "The model '{}' is served by this server and cannot be deleted",
+        model.id
+    ))
+    .with_param("model")
+    .with_code("model_not_deletable"))
 }
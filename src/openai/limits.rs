@@ -0,0 +1,203 @@
+use crate::config::PromptLimits;
+use crate::core::device_memory::device_memory;
+use crate::core::generator::PromptInput;
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use axum::http::{header, HeaderMap, StatusCode};
+use tokenizers::Tokenizer;
+use tracing::warn;
+
+/// Returns the prompt limits of a request, from the API key of its `Authorization` header.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+pub(crate) fn prompt_limits(state: &AppState, headers: &HeaderMap) -> PromptLimits {
+    state.settings.current().limits.for_key(api_key(headers))
+}
+
+/// Checks that the API key of a request may use a model.
+///
+/// This runs before the request is scheduled on the model.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+/// * `model_id` - The id of the model the request is for.
+///
+/// # Errors
+///
+/// Returns a `not_found` error with the `model_not_found` code if the key may not use
+/// the model, so the models of other keys are not disclosed.
+pub(crate) fn check_model_access(
+    state: &AppState,
+    headers: &HeaderMap,
+    model_id: &str,
+) -> Result<(), ApiError> {
+    if model_allowed(state, headers, model_id) {
+        return Ok(());
+    }
+
+    Err(ApiError::not_found(format!(
+        "The model '{model_id}' does not exist or you do not have access to it"
+    ))
+    .with_param("model")
+    .with_code("model_not_found"))
+}
+
+/// Whether the API key of a request may use a model.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+/// * `model_id` - The id of the model.
+pub(crate) fn model_allowed(state: &AppState, headers: &HeaderMap, model_id: &str) -> bool {
+    state
+        .settings
+        .current()
+        .model_access
+        .allows(api_key(headers), model_id)
+}
+
+/// The API key of a request, the bearer token of its `Authorization` header.
+pub(crate) fn api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Checks the number of messages of a request against the limits of its key.
+///
+/// # Arguments
+///
+/// * `limits` - The limits of the request.
+/// * `count` - The number of messages or input items of the request.
+/// * `param` - The name of the parameter holding the messages.
+///
+/// # Errors
+///
+/// Returns an `invalid_request_error` if the request has too many messages.
+pub(crate) fn check_message_count(
+    limits: &PromptLimits,
+    count: usize,
+    param: &str,
+) -> Result<(), ApiError> {
+    match limits.max_messages {
+        Some(max) if count > max => Err(ApiError::invalid_request(format!(
+            "'{param}' has {count} items, more than the limit of {max} on this server"
+        ))
+        .with_param(param)
+        .with_code("too_many_messages")),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the number of tokens of a prompt against the limits of its key.
+///
+/// This runs before the prompt reaches the generator. Text prompts that are shorter in
+/// bytes than the limit are not tokenized, as they cannot have more tokens than bytes
+/// besides the special tokens the tokenizer adds.
+///
+/// # Arguments
+///
+/// * `limits` - The limits of the request.
+/// * `tokenizer` - The tokenizer of the served model.
+/// * `prompt` - The complete prompt, system prompt and history included.
+/// * `param` - The name of the parameter holding the prompt.
+///
+/// # Errors
+///
+/// Returns an `invalid_request_error` if the prompt has too many tokens.
+pub(crate) fn check_prompt_tokens(
+    limits: &PromptLimits,
+    tokenizer: &Tokenizer,
+    prompt: &PromptInput,
+    param: &str,
+) -> Result<(), ApiError> {
+    let Some(max) = limits.max_prompt_tokens else {
+        return Ok(());
+    };
+
+    let tokens = match prompt {
+        PromptInput::Tokens(tokens) => tokens.len(),
+        PromptInput::Text(text) if text.len() < max => return Ok(()),
+        PromptInput::Text(text) => tokenizer
+            .encode(text.as_str(), true)
+            .map_err(ApiError::internal)?
+            .len(),
+    };
+    if tokens > max {
+        return Err(ApiError::invalid_request(format!(
+            "The prompt has {tokens} tokens, more than the limit of {max} on this server"
+        ))
+        .with_param(param)
+        .with_code("prompt_too_long"));
+    }
+    Ok(())
+}
+
+/// Refuses long requests while the device of the model is short of memory.
+///
+/// The memory is only queried when `limits.memory_pressure.min_free_mb` is set, and the
+/// prompt only tokenized when the free memory is below it.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `prompt` - The complete prompt of the request.
+/// * `max_tokens` - The number of tokens the request asks to generate, if it sets one.
+///
+/// # Errors
+///
+/// Returns a `503 Service Unavailable` with the `memory_pressure` code and a
+/// `Retry-After` header if the free memory is below the threshold and the request is
+/// long.
+pub(crate) fn check_memory_pressure(
+    state: &AppState,
+    prompt: &PromptInput,
+    max_tokens: Option<i32>,
+) -> Result<(), ApiError> {
+    let settings = state.settings.current().limits.memory_pressure.clone();
+    let Some(min_free_mb) = settings.min_free_mb else {
+        return Ok(());
+    };
+    let Some(memory) = device_memory(&state.device) else {
+        return Ok(());
+    };
+    let min_free_bytes = min_free_mb.saturating_mul(1024 * 1024);
+    if memory.free_bytes >= min_free_bytes {
+        return Ok(());
+    }
+
+    let prompt_tokens = match prompt {
+        PromptInput::Tokens(tokens) => tokens.len(),
+        PromptInput::Text(text) => state
+            .tokenizer
+            .encode(text.as_str(), true)
+            .map_err(ApiError::internal)?
+            .len(),
+    };
+    let tokens = prompt_tokens + max_tokens.unwrap_or(0).max(0) as usize;
+    if tokens <= settings.long_request_tokens {
+        return Ok(());
+    }
+
+    warn!(
+        "Refusing a request of {tokens} tokens, {} MiB of device memory free",
+        memory.free_bytes / (1024 * 1024)
+    );
+    Err(ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "server_error",
+        format!(
+            "The server is short of memory and only accepts requests of up to {} prompt and output tokens, retry later",
+            settings.long_request_tokens
+        ),
+    )
+    .with_code("memory_pressure")
+    .with_retry_after(settings.retry_after_secs))
+}
@@ -0,0 +1,45 @@
+use std::fmt::Write;
+
+use axum::http::header;
+use axum::response::IntoResponse;
+
+use crate::core::circuit_breaker::step_retries;
+use crate::openai::errors::error_counts;
+
+/// Serves the metrics of the server in the Prometheus text format.
+///
+/// # Returns
+///
+/// The number of errors returned since the start, labeled by category, and the number
+/// of generation steps retried after a transient device error.
+pub async fn metrics() -> impl IntoResponse {
+    let mut body = String::new();
+    let _ = writeln!(
+        body,
+        "# HELP synap_forge_errors_total Errors returned by the API, by category."
+    );
+    let _ = writeln!(body, "# TYPE synap_forge_errors_total counter");
+    for (category, count) in error_counts() {
+        let _ = writeln!(
+            body,
+            "synap_forge_errors_total{{category=\"{}\"}} {}",
+            category.as_str(),
+            count
+        );
+    }
+    let _ = writeln!(
+        body,
+        "# HELP synap_forge_generation_step_retries_total Generation steps retried after a transient device error."
+    );
+    let _ = writeln!(
+        body,
+        "# TYPE synap_forge_generation_step_retries_total counter"
+    );
+    let _ = writeln!(
+        body,
+        "synap_forge_generation_step_retries_total {}",
+        step_retries()
+    );
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
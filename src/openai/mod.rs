@@ -1,3 +1,29 @@
+pub mod access_log;
+pub mod admin_service;
+pub mod agents_service;
+pub mod assistants_service;
+pub mod audio_service;
+pub mod azure_service;
+pub mod classification_service;
+pub mod conversations_service;
+pub mod errors;
+pub mod files_service;
+pub mod generations_service;
 pub mod http_entities;
 pub mod http_service;
+pub mod limits;
+pub mod metrics_service;
 pub mod models;
+pub mod network;
+pub mod proxy;
+pub mod rag_service;
+pub mod realtime_service;
+pub mod request_json;
+pub mod responses_service;
+pub mod scoring_service;
+pub mod shadow;
+pub mod streaming;
+pub mod traffic_split;
+pub mod usage_headers;
+pub mod validation;
+pub mod vector_stores_service;
@@ -1,3 +1,14 @@
+use crate::config::{LongInputPolicy, Pooling};
+use crate::core::audit::AuditEntry;
+use crate::core::classification::LabelScore;
+use crate::core::conversations::{ConversationSummary, StoredMessage};
+use crate::core::embeddings::SparseVector;
+use crate::core::events::TokenUsage;
+use crate::core::hub_cache::CachedModel;
+use crate::core::rag::{RagDocument, RetrievedChunk};
+use crate::core::usage_meter::MeteredUsage;
+use crate::core::weight_verification::WeightVerification;
+use crate::openai::errors::ErrorBody;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -40,7 +51,8 @@ pub struct CreateChatCompletionRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<ChatCompletionTool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_choice: Option<ChatCompletionToolChoiceOption>,
+    /// `auto`, `none`, `required` or `{"type": "function", "function": {"name": ...}}`.
+    pub tool_choice: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub parallel_tool_calls: Option<ParallelToolCalls>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -51,6 +63,88 @@ pub struct CreateChatCompletionRequest {
     pub functions: Option<Vec<ChatCompletionFunctions>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<i32>,
+    /// Extension: a named system prompt preset configured on the server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_template: Option<PromptTemplateReference>,
+    /// Extension: the chat template turning the messages into the prompt, the name of a
+    /// built-in preset such as `chatml` or `llama3`, or a Jinja template in the format of
+    /// the `chat_template` of Hugging Face tokenizers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_template: Option<String>,
+    /// Extension: a conversation stored on the server, whose history is prepended to
+    /// `messages` and which the new messages and the reply are appended to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<String>,
+    /// The output the reply is expected to be close to, such as the file being edited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prediction: Option<PredictionContent>,
+    /// Extension: the seconds the client waits for the reply, see `X-Timeout-Ms`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<f64>,
+    /// Extension: the multiplicative penalty of recently generated tokens, `1` disables it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repetition_penalty: Option<f32>,
+    /// vLLM extension: the number of most likely tokens sampled from, `-1` disables it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i64>,
+    /// vLLM extension: tokens less likely than `min_p` times the most likely one are never
+    /// sampled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f64>,
+    /// vLLM extension: token ids ending the generation, in addition to the end-of-sequence ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_token_ids: Option<Vec<u32>>,
+    /// vLLM extension: keeps generating past the end-of-sequence tokens, up to `max_tokens`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignore_eos: Option<bool>,
+    /// vLLM extension: the number of candidates the `n` choices are picked from. Only
+    /// `best_of` equal to `n` is supported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best_of: Option<i64>,
+    /// vLLM extension: beam search, which is not supported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_beam_search: Option<bool>,
+}
+
+/// A predicted output, whose matching parts are verified several tokens at a time.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PredictionContent {
+    Content { content: PredictionText },
+}
+
+/// The text of a predicted output, as a string or as text parts.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum PredictionText {
+    Text(String),
+    Parts(Vec<PredictionTextPart>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PredictionTextPart {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: String,
+}
+
+impl PredictionContent {
+    /// Returns the predicted text, with the text parts concatenated.
+    pub fn text(&self) -> String {
+        let Self::Content { content } = self;
+        match content {
+            PredictionText::Text(text) => text.clone(),
+            PredictionText::Parts(parts) => parts.iter().map(|part| part.text.as_str()).collect(),
+        }
+    }
+}
+
+/// A reference to a prompt template configured on the server, with the values of its variables.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PromptTemplateReference {
+    pub name: String,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -81,20 +175,53 @@ pub struct ResponseFormatJsonSchema {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ChatCompletionStreamOptions {
-    // Implement the fields based on the OpenAPI spec
-    // ...
+    /// Whether a last chunk with empty `choices` carries the token usage of the request.
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+/// The token usage of a completion, sent in the last chunk of a stream with `include_usage`
+/// and in the response of a completion that is not streamed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CompletionUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl From<TokenUsage> for CompletionUsage {
+    fn from(usage: TokenUsage) -> Self {
+        Self {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total_tokens(),
+        }
+    }
+}
+
+impl From<MeteredUsage> for CompletionUsage {
+    fn from(usage: MeteredUsage) -> Self {
+        Self::from(TokenUsage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens.unwrap_or(0),
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ChatCompletionTool {
-    // Implement the fields based on the OpenAPI spec
-    // ...
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: ChatCompletionToolFunction,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct ChatCompletionToolChoiceOption {
-    // Implement the fields based on the OpenAPI spec
-    // ...
+pub struct ChatCompletionToolFunction {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -142,7 +269,15 @@ pub(crate) struct CreateChatCompletionResponse {
     pub(crate) object: String,
     pub(crate) created: i64,
     pub(crate) model: String,
+    /// `fp_` followed by the start of the commit of the served model, which changes when
+    /// the weights do.
+    pub(crate) system_fingerprint: String,
     pub(crate) choices: Vec<ChatCompletionChoice>,
+    pub(crate) usage: CompletionUsage,
+    /// Extension: the number of tokens each choice could generate, present when the
+    /// server lowered `max_tokens` to its limit or to the room left in the context.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_tokens_applied: Option<usize>,
     // ... other fields
 }
 
@@ -157,12 +292,48 @@ pub(crate) struct ChatCompletionChoice {
 pub(crate) struct ChatCompletionResponseMessage {
     pub(crate) role: String,
     pub(crate) content: String,
+    /// The reasoning trace of a thinking model, out of `content`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reasoning_content: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CreateChatCompletionStreamResponse {
+    pub(crate) id: String,
+    pub(crate) object: String,
+    pub(crate) created: i64,
+    pub(crate) model: String,
+    /// `fp_` followed by the start of the commit of the served model, which changes when
+    /// the weights do.
+    pub(crate) system_fingerprint: String,
+    pub(crate) choices: Vec<ChatCompletionStreamChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) usage: Option<CompletionUsage>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ChatCompletionStreamChoice {
+    pub(crate) index: i64,
+    pub(crate) delta: ChatCompletionStreamDelta,
+    pub(crate) finish_reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct ChatCompletionStreamDelta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reasoning_content: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CreateCompletionRequest {
     pub model: String,
-    pub prompt: Option<String>,
+    pub prompt: Option<Prompt>,
+    /// The number of candidates the `n` choices are picked from. Only `best_of` equal to
+    /// `n` is supported.
     pub best_of: Option<i32>,
     pub echo: Option<bool>,
     pub frequency_penalty: Option<f32>,
@@ -174,12 +345,34 @@ pub struct CreateCompletionRequest {
     pub seed: Option<i64>,
     pub stop: Option<StopSequence>,
     pub stream: Option<bool>,
+    pub stream_options: Option<ChatCompletionStreamOptions>,
     pub suffix: Option<String>,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
     pub user: Option<String>,
+    /// Extension: a named system prompt preset prepended to the prompt.
+    pub prompt_template: Option<PromptTemplateReference>,
+    /// Extension: the seconds the client waits for the completion, see `X-Timeout-Ms`.
+    pub timeout: Option<f64>,
+    /// Extension: the multiplicative penalty of recently generated tokens, `1` disables it.
+    pub repetition_penalty: Option<f32>,
+    /// vLLM extension: the number of most likely tokens sampled from, `-1` disables it.
+    pub top_k: Option<i64>,
+    /// vLLM extension: tokens less likely than `min_p` times the most likely one are never
+    /// sampled.
+    pub min_p: Option<f64>,
+    /// vLLM extension: token ids ending the generation, in addition to the end-of-sequence ones.
+    pub stop_token_ids: Option<Vec<u32>>,
+    /// vLLM extension: keeps generating past the end-of-sequence tokens, up to `max_tokens`.
+    pub ignore_eos: Option<bool>,
+    /// vLLM extension: beam search, which is not supported.
+    pub use_beam_search: Option<bool>,
 }
 
+/// The prompt of a completion, as text or as token ids of the served model's tokenizer.
+///
+/// Token prompts are fed to the model without being tokenized again. A single prompt is
+/// served per request, so the array variants must hold exactly one prompt.
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum Prompt {
@@ -202,7 +395,15 @@ pub(crate) struct CreateCompletionResponse {
     pub(crate) object: String,
     pub(crate) created: i64,
     pub(crate) model: String,
+    /// `fp_` followed by the start of the commit of the served model, which changes when
+    /// the weights do.
+    pub(crate) system_fingerprint: String,
     pub(crate) choices: Vec<CompletionChoice>,
+    pub(crate) usage: CompletionUsage,
+    /// Extension: the number of tokens each choice could generate, present when the
+    /// server lowered `max_tokens` to its limit or to the room left in the context.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_tokens_applied: Option<usize>,
     // ... other fields
 }
 
@@ -214,11 +415,98 @@ pub struct CompletionChoice {
     pub finish_reason: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CreateCompletionStreamResponse {
+    pub(crate) id: String,
+    pub(crate) object: String,
+    pub(crate) created: i64,
+    pub(crate) model: String,
+    /// `fp_` followed by the start of the commit of the served model, which changes when
+    /// the weights do.
+    pub(crate) system_fingerprint: String,
+    pub(crate) choices: Vec<CompletionStreamChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) usage: Option<CompletionUsage>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CompletionStreamChoice {
+    pub text: String,
+    pub index: i64,
+    pub logprobs: Option<i64>,
+    pub finish_reason: Option<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CreateEmbeddingRequest {
     pub model: String,
-    input: String,
-    // ... other fields
+    pub input: EmbeddingInput,
+    pub user: Option<String>,
+    /// Extension: what to do with the inputs longer than the context of the model,
+    /// `embeddings.long_inputs` by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub long_inputs: Option<LongInputPolicy>,
+    /// Extension: which token states make the vectors, by default the `pooling` of the
+    /// model in `models`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pooling: Option<Pooling>,
+    /// Extension: whether the vectors are normalized to unit length, by default the
+    /// `normalize` of the model in `models`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalize: Option<bool>,
+    /// Extension: also return the lexical weights of every input as `sparse_embedding`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparse: Option<bool>,
+    /// Extension: also return the vectors of the tokens of every input as `multi_vector`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multi_vector: Option<bool>,
+}
+
+/// The text or texts to embed.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Text(String),
+    Texts(Vec<String>),
+    /// Extension: texts and images, for the image embedding model.
+    Items(Vec<EmbeddingInputItem>),
+}
+
+/// A text or an image to embed.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum EmbeddingInputItem {
+    Text(String),
+    /// An image as base64, or as a `data:image/...;base64,` URL.
+    Image {
+        image: String,
+    },
+}
+
+impl EmbeddingInput {
+    /// The texts to embed, or `None` if the input contains images.
+    pub fn into_texts(self) -> Option<Vec<String>> {
+        match self {
+            Self::Text(text) => Some(vec![text]),
+            Self::Texts(texts) => Some(texts),
+            Self::Items(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    EmbeddingInputItem::Text(text) => Some(text),
+                    EmbeddingInputItem::Image { .. } => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// The texts and images to embed.
+    pub fn into_items(self) -> Vec<EmbeddingInputItem> {
+        match self {
+            Self::Text(text) => vec![EmbeddingInputItem::Text(text)],
+            Self::Texts(texts) => texts.into_iter().map(EmbeddingInputItem::Text).collect(),
+            Self::Items(items) => items,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -226,14 +514,31 @@ pub struct CreateEmbeddingResponse {
     pub object: String,
     pub data: Vec<Embedding>,
     pub model: String,
-    // ... other fields
+    pub usage: EmbeddingUsage,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EmbeddingUsage {
+    pub prompt_tokens: usize,
+    pub total_tokens: usize,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Embedding {
     pub object: String,
-    pub embedding: Vec<f64>,
+    pub embedding: Vec<f32>,
     pub index: i64,
+    /// Extension: `true` when the input was longer than the context of the model and only
+    /// its first tokens were embedded, absent otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<bool>,
+    /// Extension: the weights of the tokens of the input by token id, when `sparse` was
+    /// requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparse_embedding: Option<SparseVector>,
+    /// Extension: one unit vector per token of the input, when `multi_vector` was requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multi_vector: Option<Vec<Vec<f32>>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -242,12 +547,49 @@ pub struct ListModelsResponse {
     pub data: Vec<Model>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Model {
     pub id: String,
     pub object: String,
     pub created: i64,
     pub owned_by: String,
+    /// The served model an alias stands for, absent for the served models themselves.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    pub capabilities: ModelCapabilities,
+    /// How the weights of the served model were checked against their digests, absent
+    /// for the other models.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<WeightVerification>,
+}
+
+/// What a served model supports, for clients routing requests across servers.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ModelCapabilities {
+    /// Whether the model serves `/v1/chat/completions` and `/v1/completions`.
+    pub supports_chat: bool,
+    /// Whether `/v1/completions` fills in the code between `prompt` and `suffix`.
+    pub supports_fill_in_the_middle: bool,
+    /// Whether requests with `tools` are answered with tool calls.
+    pub supports_tools: bool,
+    /// Whether messages can contain images.
+    pub supports_vision: bool,
+    /// Whether `response_format` constrains the output to JSON.
+    pub supports_json_mode: bool,
+    /// Whether the model serves `/v1/audio/transcriptions`.
+    pub supports_transcription: bool,
+    /// Whether the model serves `/v1/audio/speech`.
+    pub supports_speech: bool,
+    /// The maximum number of prompt and generated tokens.
+    pub max_context: Option<usize>,
+    /// The size of the vectors returned by `/v1/embeddings`.
+    pub embedding_dims: Option<usize>,
+    /// Whether `/v1/embeddings` returns sparse lexical weights.
+    pub supports_sparse_embeddings: bool,
+    /// Whether `/v1/embeddings` returns the vectors of the tokens.
+    pub supports_multi_vector_embeddings: bool,
+    /// Whether `/v1/embeddings` embeds images along with texts.
+    pub supports_image_embeddings: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -256,3 +598,1243 @@ pub struct DeleteModelResponse {
     pub object: String,
     pub deleted: bool,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileObject {
+    pub id: String,
+    pub object: String,
+    pub bytes: u64,
+    pub created_at: i64,
+    pub filename: String,
+    pub purpose: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListFilesResponse {
+    pub object: String,
+    pub data: Vec<FileObject>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteFileResponse {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListFilesQuery {
+    pub purpose: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateTranscriptionResponse {
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateTranscriptionVerboseResponse {
+    pub task: String,
+    pub language: Option<String>,
+    pub duration: f64,
+    pub text: String,
+    pub segments: Vec<TranscriptionSegment>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TranscriptionSegment {
+    pub id: i64,
+    pub seek: i64,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    pub tokens: Vec<u32>,
+    pub temperature: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateSpeechRequest {
+    pub model: String,
+    pub input: String,
+    pub voice: String,
+    pub response_format: Option<String>,
+    pub speed: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct ListCachedModelsResponse {
+    pub object: String,
+    pub cache_dir: String,
+    pub data: Vec<CachedModel>,
+}
+
+#[derive(Serialize)]
+pub struct ReloadConfigResponse {
+    pub object: String,
+    /// The configuration sections taken from the file, the others need a restart.
+    pub reloaded: Vec<String>,
+    /// The log filter in effect after the reload.
+    pub log_level: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LogLevel {
+    /// The tracing filter of the logs, e.g. `debug` or `synap_forge_llm=trace,tower_http=info`.
+    pub level: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuditQuery {
+    /// Only lists the actions with this name, e.g. `POST /admin/reload`.
+    pub action: Option<String>,
+    /// Only lists the actions older than the one with this id, to page through the log.
+    pub before: Option<i64>,
+    /// The maximum number of actions listed, 100 by default and at most 1000.
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct ListAuditResponse {
+    pub object: String,
+    pub data: Vec<AuditEntry>,
+    /// Whether older actions match the query, listed with `before` set to the last id.
+    pub has_more: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PurgeCacheQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateResponseRequest {
+    pub model: String,
+    pub input: ResponseInput,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ResponseTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_response_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Extension: a named system prompt preset configured on the server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_template: Option<PromptTemplateReference>,
+}
+
+/// The input of a response: a single user message or a list of conversation items.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ResponseInput {
+    Text(String),
+    Items(Vec<ResponseInputItem>),
+}
+
+/// An item of the conversation sent to `/v1/responses`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ResponseInputItem {
+    Typed(TypedResponseInputItem),
+    /// A message without the `type` field, as sent by most SDKs.
+    Message(ResponseInputMessage),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TypedResponseInputItem {
+    Message(ResponseInputMessage),
+    /// A tool call previously returned by the server.
+    FunctionCall {
+        call_id: String,
+        name: String,
+        arguments: String,
+    },
+    /// The result of a tool call, computed by the client.
+    FunctionCallOutput {
+        call_id: String,
+        output: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseInputMessage {
+    pub role: String,
+    pub content: ResponseMessageContent,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ResponseMessageContent {
+    Text(String),
+    Parts(Vec<ResponseContentPart>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseContentPart {
+    /// `input_text` and `output_text` parts are supported.
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResponseTool {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ResponseObject {
+    pub id: String,
+    pub object: String,
+    pub created_at: i64,
+    /// `in_progress`, `completed`, `incomplete` or `failed`.
+    pub status: String,
+    pub model: String,
+    pub output: Vec<ResponseOutputItem>,
+    pub usage: Option<ResponseUsage>,
+    pub incomplete_details: Option<ResponseIncompleteDetails>,
+    pub error: Option<ErrorBody>,
+    pub instructions: Option<String>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_output_tokens: Option<i32>,
+    pub tools: Vec<ResponseTool>,
+    pub tool_choice: serde_json::Value,
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseOutputItem {
+    Message {
+        id: String,
+        status: String,
+        role: String,
+        content: Vec<ResponseOutputContent>,
+    },
+    FunctionCall {
+        id: String,
+        call_id: String,
+        name: String,
+        arguments: String,
+        status: String,
+    },
+    /// Extension: the result of a tool the server ran itself for the preceding call.
+    FunctionCallOutput {
+        id: String,
+        call_id: String,
+        output: String,
+        status: String,
+    },
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseOutputContent {
+    OutputText {
+        text: String,
+        annotations: Vec<serde_json::Value>,
+    },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ResponseUsage {
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    pub total_tokens: usize,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ResponseIncompleteDetails {
+    /// `max_output_tokens` or `content_filter`.
+    pub reason: String,
+}
+
+/// A server-sent event of a streamed response, numbered in emission order.
+#[derive(Serialize, Debug)]
+pub struct ResponseStreamEvent {
+    #[serde(flatten)]
+    pub event: ResponseStreamEventKind,
+    pub sequence_number: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+pub enum ResponseStreamEventKind {
+    #[serde(rename = "response.created")]
+    Created { response: ResponseObject },
+    #[serde(rename = "response.in_progress")]
+    InProgress { response: ResponseObject },
+    #[serde(rename = "response.output_item.added")]
+    OutputItemAdded {
+        output_index: usize,
+        item: ResponseOutputItem,
+    },
+    #[serde(rename = "response.content_part.added")]
+    ContentPartAdded {
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        part: ResponseOutputContent,
+    },
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta {
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        delta: String,
+    },
+    #[serde(rename = "response.output_text.done")]
+    OutputTextDone {
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        text: String,
+    },
+    #[serde(rename = "response.content_part.done")]
+    ContentPartDone {
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        part: ResponseOutputContent,
+    },
+    #[serde(rename = "response.function_call_arguments.delta")]
+    FunctionCallArgumentsDelta {
+        item_id: String,
+        output_index: usize,
+        delta: String,
+    },
+    #[serde(rename = "response.function_call_arguments.done")]
+    FunctionCallArgumentsDone {
+        item_id: String,
+        output_index: usize,
+        arguments: String,
+    },
+    #[serde(rename = "response.output_item.done")]
+    OutputItemDone {
+        output_index: usize,
+        item: ResponseOutputItem,
+    },
+    #[serde(rename = "response.completed")]
+    Completed { response: ResponseObject },
+    #[serde(rename = "response.incomplete")]
+    Incomplete { response: ResponseObject },
+    #[serde(rename = "response.failed")]
+    Failed { response: ResponseObject },
+    #[serde(rename = "error")]
+    Error {
+        code: Option<String>,
+        message: String,
+        param: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+pub struct ListConversationsResponse {
+    pub object: String,
+    pub data: Vec<ConversationSummary>,
+}
+
+#[derive(Serialize)]
+pub struct ConversationObject {
+    pub id: String,
+    pub object: String,
+    pub messages: Vec<StoredMessage>,
+}
+
+#[derive(Serialize)]
+pub struct DeleteConversationResponse {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ConversationQuery {
+    pub user: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateRagDocumentRequest {
+    /// The id of the document, generated when omitted.
+    pub id: Option<String>,
+    pub text: String,
+    /// Arbitrary data returned with the chunks retrieved from the document.
+    pub metadata: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub struct ListRagDocumentsResponse {
+    pub object: String,
+    pub data: Vec<RagDocument>,
+}
+
+#[derive(Serialize)]
+pub struct DeleteRagDocumentResponse {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RagQueryRequest {
+    pub model: String,
+    pub query: String,
+    /// The number of chunks to retrieve, `rag.top_k` when omitted.
+    pub top_k: Option<usize>,
+    /// Instructions placed before the retrieved context.
+    pub instructions: Option<String>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct RagQueryResponse {
+    pub id: String,
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    pub answer: String,
+    /// The chunks the answer was generated from, most similar first.
+    pub sources: Vec<RetrievedChunk>,
+    pub usage: RagUsage,
+}
+
+#[derive(Serialize, Default)]
+pub struct RagUsage {
+    /// The tokens of the query passed through the embedding model.
+    pub embedding_tokens: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorStoreObject {
+    pub id: String,
+    pub object: String,
+    pub created_at: i64,
+    pub name: Option<String>,
+    pub usage_bytes: u64,
+    pub file_counts: VectorStoreFileCounts,
+    pub status: String,
+    pub expires_after: Option<VectorStoreExpiration>,
+    pub expires_at: Option<i64>,
+    pub last_active_at: Option<i64>,
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VectorStoreFileCounts {
+    pub in_progress: u64,
+    pub completed: u64,
+    pub failed: u64,
+    pub cancelled: u64,
+    pub total: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorStoreExpiration {
+    /// The timestamp the expiration counts from, always `last_active_at`.
+    pub anchor: String,
+    pub days: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorStoreFileObject {
+    pub id: String,
+    pub object: String,
+    pub usage_bytes: u64,
+    pub created_at: i64,
+    pub vector_store_id: String,
+    pub status: String,
+    pub last_error: Option<VectorStoreFileError>,
+    pub chunking_strategy: ChunkingStrategy,
+    pub attributes: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorStoreFileError {
+    pub code: String,
+    pub message: String,
+}
+
+/// How files are split into chunks, `auto` being resolved to the default static strategy.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChunkingStrategy {
+    Auto,
+    Static { r#static: StaticChunkingStrategy },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct StaticChunkingStrategy {
+    pub max_chunk_size_tokens: usize,
+    pub chunk_overlap_tokens: usize,
+}
+
+impl Default for StaticChunkingStrategy {
+    fn default() -> Self {
+        Self {
+            max_chunk_size_tokens: 800,
+            chunk_overlap_tokens: 400,
+        }
+    }
+}
+
+impl ChunkingStrategy {
+    /// The static strategy the chunks are made with.
+    pub fn resolve(&self) -> StaticChunkingStrategy {
+        match self {
+            Self::Auto => StaticChunkingStrategy::default(),
+            Self::Static { r#static } => *r#static,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorStoreFileBatchObject {
+    pub id: String,
+    pub object: String,
+    pub created_at: i64,
+    pub vector_store_id: String,
+    pub status: String,
+    pub file_counts: VectorStoreFileCounts,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateVectorStoreRequest {
+    pub name: Option<String>,
+    pub file_ids: Option<Vec<String>>,
+    pub expires_after: Option<VectorStoreExpiration>,
+    pub chunking_strategy: Option<ChunkingStrategy>,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ModifyVectorStoreRequest {
+    pub name: Option<String>,
+    pub expires_after: Option<VectorStoreExpiration>,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateVectorStoreFileRequest {
+    pub file_id: String,
+    pub chunking_strategy: Option<ChunkingStrategy>,
+    pub attributes: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateVectorStoreFileBatchRequest {
+    pub file_ids: Vec<String>,
+    pub chunking_strategy: Option<ChunkingStrategy>,
+    pub attributes: Option<HashMap<String, serde_json::Value>>,
+}
+
+/// The cursor pagination of the vector store list endpoints.
+#[derive(Deserialize, Debug)]
+pub struct VectorStoreListQuery {
+    pub limit: Option<usize>,
+    /// `asc` or `desc` by creation time, `desc` by default.
+    pub order: Option<String>,
+    pub after: Option<String>,
+    pub before: Option<String>,
+    /// The status of the files to list: `in_progress`, `completed`, `failed` or `cancelled`.
+    pub filter: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CursorPage<T> {
+    pub object: String,
+    pub data: Vec<T>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+    pub has_more: bool,
+}
+
+#[derive(Serialize)]
+pub struct DeleteVectorStoreResponse {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VectorStoreSearchRequest {
+    pub query: EmbeddingInput,
+    pub max_num_results: Option<usize>,
+    pub filters: Option<AttributeFilter>,
+    pub ranking_options: Option<VectorStoreRankingOptions>,
+    /// Accepted for compatibility, queries are searched as sent.
+    pub rewrite_query: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VectorStoreRankingOptions {
+    pub ranker: Option<String>,
+    pub score_threshold: Option<f32>,
+}
+
+/// A filter on the attributes of the files, either a comparison (`eq`, `ne`, `gt`, `gte`,
+/// `lt`, `lte`) of the attribute `key` with `value`, or a compound (`and`, `or`) of `filters`.
+#[derive(Deserialize, Debug)]
+pub struct AttributeFilter {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub key: Option<String>,
+    pub value: Option<serde_json::Value>,
+    pub filters: Option<Vec<AttributeFilter>>,
+}
+
+#[derive(Serialize)]
+pub struct VectorStoreSearchResponse {
+    pub object: String,
+    pub search_query: Vec<String>,
+    pub data: Vec<VectorStoreSearchResult>,
+    pub has_more: bool,
+    pub next_page: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VectorStoreSearchResult {
+    pub file_id: String,
+    pub filename: String,
+    pub score: f32,
+    pub attributes: HashMap<String, serde_json::Value>,
+    pub content: Vec<VectorStoreSearchContent>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VectorStoreSearchContent {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateClassificationRequest {
+    pub model: String,
+    pub input: String,
+    /// The candidate labels, which must start with different tokens.
+    pub labels: Vec<String>,
+    /// What the labels mean, replacing the default instructions.
+    pub instructions: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreateClassificationResponse {
+    pub id: String,
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    /// The most likely label.
+    pub label: String,
+    /// The probability of every label, most likely first.
+    pub scores: Vec<LabelScore>,
+    pub usage: ClassificationUsage,
+}
+
+#[derive(Serialize)]
+pub struct ClassificationUsage {
+    pub prompt_tokens: usize,
+    pub total_tokens: usize,
+}
+
+/// Request of `/v1/score`, which returns the log-probabilities of the tokens of a text.
+#[derive(Deserialize, Debug)]
+pub struct CreateScoreRequest {
+    pub model: String,
+    /// The text or tokens to score, or the context of `continuation`.
+    pub prompt: Option<Prompt>,
+    /// Text appended to the prompt whose tokens alone are scored, e.g. an answer of a
+    /// multiple-choice question.
+    pub continuation: Option<String>,
+    /// The number of most likely tokens returned at every position, at most 20.
+    pub top_logprobs: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct CreateScoreResponse {
+    pub id: String,
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    pub logprobs: ScoreLogprobs,
+    /// The log-probability of the scored tokens together, the continuation if there is one.
+    pub total_logprob: f32,
+    /// The perplexity of the model over the scored tokens.
+    pub perplexity: f32,
+    /// The number of scored tokens.
+    pub scored_tokens: usize,
+    pub usage: ScoreUsage,
+}
+
+#[derive(Serialize)]
+pub struct ScoreUsage {
+    pub prompt_tokens: usize,
+    pub total_tokens: usize,
+}
+
+/// The log-probabilities of every token, in the layout of the legacy completions `logprobs`.
+#[derive(Serialize)]
+pub struct ScoreLogprobs {
+    pub tokens: Vec<String>,
+    /// `null` for the first token, which has no context to be predicted from.
+    pub token_logprobs: Vec<Option<f32>>,
+    pub top_logprobs: Vec<Option<HashMap<String, f32>>>,
+    /// The byte offset of every token in the scored text.
+    pub text_offset: Vec<usize>,
+}
+
+/// A request run in the background by `/v1/generations`, in the layout of a batch line.
+#[derive(Deserialize, Debug)]
+pub struct CreateGenerationRequest {
+    /// The endpoint the body is for, `/v1/chat/completions` by default or
+    /// `/v1/completions`.
+    pub url: Option<String>,
+    /// The request of the endpoint, which may not be streamed.
+    pub body: serde_json::Value,
+    /// The URL the generation is posted to once it ended.
+    pub webhook_url: Option<String>,
+}
+
+/// The long polling of `GET /v1/generations/{id}`.
+#[derive(Deserialize, Debug)]
+pub struct GenerationQuery {
+    /// How long in seconds to wait for the generation to end before answering.
+    pub wait: Option<u64>,
+}
+
+/// A run of `/v1/agents/run`: the conversation the model continues, calling the server
+/// tools until it answers.
+#[derive(Deserialize)]
+pub struct CreateAgentRunRequest {
+    pub model: String,
+    pub messages: Vec<ChatCompletionRequestMessage>,
+    /// Instructions placed in a system message ahead of the conversation.
+    pub instructions: Option<String>,
+    /// The server tools the model may call, every tool the API key may use when omitted.
+    pub tools: Option<Vec<String>>,
+    /// The maximum number of generations, `agents.max_iterations` when omitted.
+    pub max_iterations: Option<usize>,
+    /// The maximum duration of the run in seconds, `agents.max_duration_secs` when omitted.
+    pub max_duration_secs: Option<f64>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    /// The maximum number of tokens of each generation.
+    pub max_tokens: Option<i32>,
+    pub stream: Option<bool>,
+}
+
+/// A step of an agent run.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentStep {
+    /// A server tool the model called, with its result.
+    ToolCall {
+        iteration: usize,
+        name: String,
+        arguments: String,
+        output: String,
+    },
+    /// The text the model generated, the answer when it is the last step.
+    Message { iteration: usize, content: String },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AgentRun {
+    pub id: String,
+    pub object: String,
+    pub created_at: i64,
+    pub model: String,
+    /// `completed` when the model answered, `incomplete` when the run was stopped.
+    pub status: String,
+    /// Why the run was stopped: `max_iterations`, `max_duration` or `max_tokens`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub incomplete_reason: Option<String>,
+    /// The answer of the model, the text of the last generation.
+    pub output: String,
+    pub steps: Vec<AgentStep>,
+    pub iterations: usize,
+    pub usage: CompletionUsage,
+}
+
+/// An event of a streamed agent run.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+pub enum AgentStreamEvent {
+    #[serde(rename = "agent.step")]
+    Step { step: AgentStep },
+    #[serde(rename = "agent.run.completed")]
+    Completed { run: AgentRun },
+}
+
+// Assistants API
+
+#[derive(Deserialize, Debug)]
+pub struct CreateAssistantRequest {
+    pub model: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub instructions: Option<String>,
+    /// Kept and returned as sent, runs do not call them.
+    #[serde(default)]
+    pub tools: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+}
+
+/// The fields of an assistant to change, the others are kept.
+#[derive(Deserialize, Debug)]
+pub struct ModifyAssistantRequest {
+    pub model: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub instructions: Option<String>,
+    pub tools: Option<Vec<serde_json::Value>>,
+    pub metadata: Option<HashMap<String, String>>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AssistantObject {
+    pub id: String,
+    pub object: String,
+    pub created_at: i64,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub model: String,
+    pub instructions: Option<String>,
+    pub tools: Vec<serde_json::Value>,
+    pub metadata: HashMap<String, String>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateThreadRequest {
+    /// The messages the thread starts with.
+    #[serde(default)]
+    pub messages: Vec<CreateMessageRequest>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThreadObject {
+    pub id: String,
+    pub object: String,
+    pub created_at: i64,
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateMessageRequest {
+    /// `user` or `assistant`.
+    pub role: String,
+    pub content: MessageContentInput,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+/// The content of a new message: a string, or an array of `text` parts.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum MessageContentInput {
+    Text(String),
+    Parts(Vec<MessageContentInputPart>),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MessageContentInputPart {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: Option<String>,
+}
+
+impl MessageContentInput {
+    /// The text of the content, its `text` parts joined.
+    pub fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| part.text.as_deref())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThreadMessageObject {
+    pub id: String,
+    pub object: String,
+    pub created_at: i64,
+    pub thread_id: String,
+    /// `completed`, or `in_progress` while a streamed run writes it.
+    pub status: String,
+    pub role: String,
+    pub content: Vec<MessageContent>,
+    pub assistant_id: Option<String>,
+    pub run_id: Option<String>,
+    pub metadata: HashMap<String, String>,
+}
+
+/// A `text` part of the content of a message.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MessageContent {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: MessageText,
+}
+
+impl MessageContent {
+    pub fn text(value: impl Into<String>) -> Self {
+        Self {
+            kind: "text".to_string(),
+            text: MessageText {
+                value: value.into(),
+                annotations: Vec::new(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MessageText {
+    pub value: String,
+    pub annotations: Vec<serde_json::Value>,
+}
+
+/// The text generated by a streamed run since the last `thread.message.delta` event.
+#[derive(Serialize, Debug)]
+pub struct ThreadMessageDelta {
+    pub id: String,
+    pub object: String,
+    pub delta: MessageDeltaContent,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MessageDeltaContent {
+    pub content: Vec<MessageDeltaPart>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MessageDeltaPart {
+    pub index: usize,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: MessageText,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateRunRequest {
+    pub assistant_id: String,
+    /// Replaces the model of the assistant, which must still be the served model.
+    pub model: Option<String>,
+    /// Replaces the instructions of the assistant.
+    pub instructions: Option<String>,
+    /// Appended to the instructions of the assistant or of the run.
+    pub additional_instructions: Option<String>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_completion_tokens: Option<i32>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    pub stream: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunObject {
+    pub id: String,
+    pub object: String,
+    pub created_at: i64,
+    pub thread_id: String,
+    pub assistant_id: String,
+    /// `queued`, `in_progress`, `completed`, `incomplete` or `failed`.
+    pub status: String,
+    pub model: String,
+    pub instructions: Option<String>,
+    pub started_at: Option<i64>,
+    pub completed_at: Option<i64>,
+    pub failed_at: Option<i64>,
+    pub last_error: Option<RunError>,
+    pub incomplete_details: Option<IncompleteDetails>,
+    pub usage: Option<CompletionUsage>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_completion_tokens: Option<i32>,
+    pub metadata: HashMap<String, String>,
+}
+
+impl RunObject {
+    /// Whether the run is still to end.
+    pub fn is_active(&self) -> bool {
+        matches!(self.status.as_str(), "queued" | "in_progress")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunError {
+    /// `server_error`, or `rate_limit_exceeded` when the server was too busy.
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IncompleteDetails {
+    /// `max_completion_tokens`.
+    pub reason: String,
+}
+
+/// The deletion of an assistant or a thread.
+#[derive(Serialize, Debug)]
+pub struct DeleteAssistantObjectResponse {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
+
+// Realtime API
+
+/// The query of `/v1/realtime`.
+#[derive(Deserialize, Debug)]
+pub struct RealtimeQuery {
+    /// The served model when omitted.
+    pub model: Option<String>,
+}
+
+/// A maximum number of output tokens, or `inf` for none.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum RealtimeMaxTokens {
+    Count(i32),
+    Infinite(String),
+}
+
+/// An event a realtime client sends over the WebSocket.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum RealtimeClientEvent {
+    #[serde(rename = "session.update")]
+    SessionUpdate {
+        event_id: Option<String>,
+        session: RealtimeSessionUpdate,
+    },
+    #[serde(rename = "conversation.item.create")]
+    ItemCreate {
+        event_id: Option<String>,
+        /// The item the new one follows, `root` for the start of the conversation, the
+        /// end when omitted.
+        previous_item_id: Option<String>,
+        item: RealtimeItemInput,
+    },
+    #[serde(rename = "conversation.item.delete")]
+    ItemDelete {
+        event_id: Option<String>,
+        item_id: String,
+    },
+    #[serde(rename = "response.create")]
+    ResponseCreate {
+        event_id: Option<String>,
+        response: Option<RealtimeResponseConfig>,
+    },
+    #[serde(rename = "response.cancel")]
+    ResponseCancel { event_id: Option<String> },
+}
+
+/// The fields of the session to change. The audio fields are ignored.
+#[derive(Deserialize, Debug)]
+pub struct RealtimeSessionUpdate {
+    pub instructions: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_response_output_tokens: Option<RealtimeMaxTokens>,
+    /// The name of `max_response_output_tokens` in the GA protocol.
+    pub max_output_tokens: Option<RealtimeMaxTokens>,
+    pub modalities: Option<Vec<String>>,
+    /// The name of `modalities` in the GA protocol.
+    pub output_modalities: Option<Vec<String>>,
+}
+
+/// The settings of one response, overriding the ones of the session.
+#[derive(Deserialize, Debug)]
+pub struct RealtimeResponseConfig {
+    pub instructions: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_response_output_tokens: Option<RealtimeMaxTokens>,
+    pub max_output_tokens: Option<RealtimeMaxTokens>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RealtimeItemInput {
+    pub id: Option<String>,
+    /// `message`, the only supported item type.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub role: Option<String>,
+    #[serde(default)]
+    pub content: Vec<RealtimeContent>,
+}
+
+/// A text part of the content of a conversation item: `input_text` for the items of the
+/// client, `text` or `output_text` for the answers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RealtimeContent {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: Option<String>,
+}
+
+/// An event the server sends over the WebSocket.
+#[derive(Serialize, Debug)]
+pub struct RealtimeServerEvent {
+    pub event_id: String,
+    #[serde(flatten)]
+    pub event: RealtimeServerEventKind,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+pub enum RealtimeServerEventKind {
+    #[serde(rename = "session.created")]
+    SessionCreated { session: RealtimeSession },
+    #[serde(rename = "session.updated")]
+    SessionUpdated { session: RealtimeSession },
+    #[serde(rename = "conversation.item.created")]
+    ItemCreated {
+        previous_item_id: Option<String>,
+        item: RealtimeItem,
+    },
+    #[serde(rename = "conversation.item.deleted")]
+    ItemDeleted { item_id: String },
+    #[serde(rename = "response.created")]
+    ResponseCreated { response: RealtimeResponse },
+    #[serde(rename = "response.output_item.added")]
+    OutputItemAdded {
+        response_id: String,
+        output_index: usize,
+        item: RealtimeItem,
+    },
+    #[serde(rename = "response.content_part.added")]
+    ContentPartAdded {
+        response_id: String,
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        part: RealtimeContent,
+    },
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta {
+        response_id: String,
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        delta: String,
+    },
+    #[serde(rename = "response.output_text.done")]
+    OutputTextDone {
+        response_id: String,
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        text: String,
+    },
+    #[serde(rename = "response.content_part.done")]
+    ContentPartDone {
+        response_id: String,
+        item_id: String,
+        output_index: usize,
+        content_index: usize,
+        part: RealtimeContent,
+    },
+    #[serde(rename = "response.output_item.done")]
+    OutputItemDone {
+        response_id: String,
+        output_index: usize,
+        item: RealtimeItem,
+    },
+    #[serde(rename = "response.done")]
+    ResponseDone { response: RealtimeResponse },
+    #[serde(rename = "error")]
+    Error { error: RealtimeError },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RealtimeSession {
+    pub id: String,
+    pub object: String,
+    pub model: String,
+    /// Always `["text"]`.
+    pub modalities: Vec<String>,
+    pub instructions: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_response_output_tokens: RealtimeMaxTokens,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RealtimeItem {
+    pub id: String,
+    pub object: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// `completed`, `in_progress` while it is generated, or `incomplete`.
+    pub status: String,
+    pub role: String,
+    pub content: Vec<RealtimeContent>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RealtimeResponse {
+    pub id: String,
+    pub object: String,
+    /// `in_progress`, `completed`, `incomplete`, `cancelled` or `failed`.
+    pub status: String,
+    /// Why the response is not `completed`, e.g.
+    /// `{"type": "incomplete", "reason": "max_output_tokens"}`.
+    pub status_details: Option<serde_json::Value>,
+    pub output: Vec<RealtimeItem>,
+    pub usage: Option<ResponseUsage>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RealtimeError {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub code: Option<String>,
+    pub message: String,
+    pub param: Option<String>,
+    /// The client event that caused the error.
+    pub event_id: Option<String>,
+}
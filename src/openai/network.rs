@@ -0,0 +1,50 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::warn;
+
+use crate::config::NetworkSettings;
+use crate::openai::errors::ApiError;
+
+/// Rejects the requests of clients whose address the network rules do not allow.
+///
+/// The address is the one of the TCP connection, a reverse proxy in front of the
+/// server must apply the rules itself.
+///
+/// # Arguments
+///
+/// * `rules` - The configured network rules.
+/// * `client` - The address of the client.
+/// * `request` - The incoming request.
+/// * `next` - The rest of the middleware stack.
+///
+/// # Returns
+///
+/// The response of the endpoint, or a `403` `ApiError` if the address is not allowed.
+pub async fn check_client_address(
+    State(rules): State<Arc<NetworkSettings>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if !rules.allows(client.ip(), request.uri().path()) {
+        warn!(
+            "Rejected {} {} from {}",
+            request.method(),
+            request.uri().path(),
+            client.ip()
+        );
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "invalid_request_error",
+            "Requests from this address are not allowed",
+        )
+        .with_code("address_not_allowed"));
+    }
+
+    Ok(next.run(request).await)
+}
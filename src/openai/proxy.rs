@@ -0,0 +1,619 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::config::{ShadowSettings, UpstreamSettings};
+use crate::core::access_log::AccessLog;
+use crate::openai::errors::ApiError;
+use crate::openai::shadow::{
+    capture_output, is_sampled, shadow_body, ShadowLogEntry, ShadowOutput, SHADOW_BODY_LIMIT,
+};
+use crate::openai::traffic_split::TrafficSplit;
+use axum::body::{Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::uri::PathAndQuery;
+use axum::http::{header, HeaderValue, StatusCode, Uri};
+use axum::response::Response;
+use axum::Json;
+use chrono::Utc;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tracing::{info, warn};
+
+/// The path probed by the health checks of the worker processes.
+pub const WORKER_HEALTH_PATH: &str = "/v1/health";
+
+/// The path listing the models of an OpenAI-compatible server.
+const MODELS_PATH: &str = "/v1/models";
+
+/// The largest model list read from an upstream.
+const MODELS_BODY_LIMIT: usize = 4 * 1024 * 1024;
+
+/// A server requests are forwarded to.
+pub struct Upstream {
+    /// The base URL of the server, such as `http://127.0.0.1:8100`.
+    pub url: String,
+    /// The key replacing the `Authorization` header of the forwarded requests.
+    api_key: Option<String>,
+    health_path: String,
+    /// Whether the models are read from the model list of the upstream.
+    discover_models: bool,
+    models: RwLock<Vec<String>>,
+    healthy: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+/// The model list of an OpenAI-compatible server.
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+/// The `model` field of a request body.
+#[derive(Deserialize)]
+struct RequestModel {
+    model: Option<String>,
+}
+
+impl Upstream {
+    /// Creates an upstream serving any model, unhealthy until its first successful
+    /// health check.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The base URL of the server, without a trailing slash.
+    /// * `health_path` - The path probed by the health checks.
+    pub fn new(url: impl Into<String>, health_path: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            api_key: None,
+            health_path: health_path.into(),
+            discover_models: false,
+            models: RwLock::new(Vec::new()),
+            healthy: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Creates a configured upstream of a gateway.
+    ///
+    /// Without configured models, the upstream is probed on its model list, which also
+    /// tells the models it serves.
+    pub fn from_settings(settings: &UpstreamSettings) -> Self {
+        let discover_models = settings.models.is_empty();
+        let health_path = match (&settings.health_path, discover_models) {
+            (Some(path), _) => path.clone(),
+            (None, true) => MODELS_PATH.to_string(),
+            (None, false) => WORKER_HEALTH_PATH.to_string(),
+        };
+        Self {
+            url: settings.url.trim_end_matches('/').to_string(),
+            api_key: settings.api_key.clone(),
+            health_path,
+            discover_models,
+            models: RwLock::new(settings.models.clone()),
+            healthy: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether the upstream passed its last health check.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Marks the upstream as healthy or not, logging the changes.
+    pub fn set_healthy(&self, healthy: bool) {
+        if self.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            match healthy {
+                true => info!("Upstream {} is healthy", self.url),
+                false => warn!("Upstream {} is unhealthy", self.url),
+            }
+        }
+    }
+
+    /// The number of requests currently forwarded to the upstream.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// The models the upstream serves, empty when it serves any model.
+    pub fn models(&self) -> Vec<String> {
+        self.models
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Whether the upstream serves a model, by full id or repository name.
+    fn serves(&self, model: Option<&str>) -> bool {
+        let Some(model) = model else {
+            return true;
+        };
+        let models = self.models.read().unwrap_or_else(|e| e.into_inner());
+        models.is_empty()
+            || models
+                .iter()
+                .any(|id| id == model || id.rsplit('/').next() == Some(model))
+    }
+}
+
+/// Counts a forwarded request until its response body is dropped.
+struct InFlight(Arc<Upstream>);
+
+impl InFlight {
+    fn start(upstream: Arc<Upstream>) -> Self {
+        upstream.in_flight.fetch_add(1, Ordering::Relaxed);
+        Self(upstream)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The state of a server forwarding the requests it receives to other servers.
+#[derive(Clone)]
+pub struct ProxyState {
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+    upstreams: Arc<Vec<Arc<Upstream>>>,
+    splits: Arc<HashMap<String, TrafficSplit>>,
+    shadows: Arc<HashMap<String, ShadowSettings>>,
+    shadow_log: Option<Arc<AccessLog>>,
+    retries: usize,
+    body_limit: usize,
+}
+
+impl ProxyState {
+    /// Creates the state of a proxy over a set of upstreams.
+    ///
+    /// # Arguments
+    ///
+    /// * `upstreams` - The servers the requests are forwarded to.
+    /// * `retries` - How many other upstreams a request is sent to when an upstream
+    ///   cannot be reached or is overloaded.
+    /// * `body_limit` - The largest request body forwarded, in bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TLS root certificates cannot be loaded.
+    pub fn new(
+        upstreams: Vec<Arc<Upstream>>,
+        retries: usize,
+        body_limit: usize,
+    ) -> anyhow::Result<Self> {
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            client: Client::builder(TokioExecutor::new()).build(connector),
+            upstreams: Arc::new(upstreams),
+            splits: Arc::new(HashMap::new()),
+            shadows: Arc::new(HashMap::new()),
+            shadow_log: None,
+            retries,
+            body_limit,
+        })
+    }
+
+    /// Spreads the requests for the names of the splits over their target models.
+    pub fn with_splits(mut self, splits: Vec<TrafficSplit>) -> Self {
+        self.splits = Arc::new(
+            splits
+                .into_iter()
+                .map(|split| (split.name.clone(), split))
+                .collect(),
+        );
+        self
+    }
+
+    /// Copies a sample of the requests for some models to their shadow models, and
+    /// writes the outputs of both to a log.
+    pub fn with_shadows(
+        mut self,
+        shadows: HashMap<String, ShadowSettings>,
+        log: Arc<AccessLog>,
+    ) -> Self {
+        self.shadows = Arc::new(shadows);
+        self.shadow_log = Some(log);
+        self
+    }
+
+    /// The servers the requests are forwarded to.
+    pub fn upstreams(&self) -> &[Arc<Upstream>] {
+        &self.upstreams
+    }
+
+    /// Whether a healthy upstream serves a model.
+    fn is_available(&self, model: &str) -> bool {
+        self.upstreams
+            .iter()
+            .any(|upstream| upstream.is_healthy() && upstream.serves(Some(model)))
+    }
+
+    /// Picks the healthy upstream serving a model with the fewest requests in flight,
+    /// among the upstreams not tried yet.
+    fn pick(&self, model: Option<&str>, tried: &[usize]) -> Option<(usize, Arc<Upstream>)> {
+        self.upstreams
+            .iter()
+            .enumerate()
+            .filter(|(index, upstream)| {
+                !tried.contains(index) && upstream.is_healthy() && upstream.serves(model)
+            })
+            .min_by_key(|(_, upstream)| upstream.in_flight())
+            .map(|(index, upstream)| (index, upstream.clone()))
+    }
+
+    /// Sends a request to an upstream and streams its response back.
+    async fn send(
+        &self,
+        upstream: Arc<Upstream>,
+        parts: &axum::http::request::Parts,
+        body: Bytes,
+    ) -> anyhow::Result<Response> {
+        let path = parts.uri.path_and_query().map_or("/", PathAndQuery::as_str);
+        let mut request = Request::new(Body::from(body));
+        *request.method_mut() = parts.method.clone();
+        *request.uri_mut() = format!("{}{}", upstream.url, path).parse::<Uri>()?;
+        *request.headers_mut() = parts.headers.clone();
+        // The upstream may be another host
+        request.headers_mut().remove(header::HOST);
+        if let Some(key) = &upstream.api_key {
+            request.headers_mut().insert(
+                header::AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {key}"))?,
+            );
+        }
+
+        let in_flight = InFlight::start(upstream);
+        let response = self.client.request(request).await?;
+
+        // The upstream stays busy until the whole body, possibly a stream, is relayed
+        let (parts, body) = response.into_parts();
+        let body = Body::new(body).into_data_stream().map(move |chunk| {
+            let _ = &in_flight;
+            chunk
+        });
+        Ok(Response::from_parts(parts, Body::from_stream(body)))
+    }
+
+    /// Sends a copy of a request to a shadow model in the background, and logs its
+    /// output along with the output of the requested model once both are known.
+    ///
+    /// # Arguments
+    ///
+    /// * `shadow` - The shadow model of the requested model.
+    /// * `parts` - The head of the client request.
+    /// * `body` - The body of the client request.
+    ///
+    /// # Returns
+    ///
+    /// Where the output of the requested model is sent, `None` if the request cannot be
+    /// copied, such as a body that is not JSON.
+    fn spawn_shadow(
+        &self,
+        shadow: &ShadowSettings,
+        parts: &axum::http::request::Parts,
+        body: &Bytes,
+    ) -> Option<oneshot::Sender<ShadowOutput>> {
+        let log = self.shadow_log.clone()?;
+        let shadow_body = shadow_body(body, &shadow.model)?;
+        let (mut shadow_parts, ()) = Request::new(()).into_parts();
+        shadow_parts.method = parts.method.clone();
+        shadow_parts.uri = parts.uri.clone();
+        shadow_parts.headers = parts.headers.clone();
+        shadow_parts
+            .headers
+            .insert(header::CONTENT_LENGTH, HeaderValue::from(shadow_body.len()));
+
+        let timestamp = Utc::now().to_rfc3339();
+        let request = log.redact(body);
+        let (sender, primary) = oneshot::channel();
+        let proxy = self.clone();
+        let model = shadow.model.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let output = match proxy.pick(Some(&model), &[]) {
+                Some((_, upstream)) => match proxy.send(upstream, &shadow_parts, shadow_body).await
+                {
+                    Ok(response) => {
+                        let status = response.status().as_u16();
+                        match axum::body::to_bytes(response.into_body(), SHADOW_BODY_LIMIT).await {
+                            Ok(bytes) => {
+                                ShadowOutput::new(model, status, &bytes, false, started.elapsed())
+                            }
+                            Err(err) => {
+                                ShadowOutput::failed(model, err.to_string(), started.elapsed())
+                            }
+                        }
+                    }
+                    Err(err) => ShadowOutput::failed(model, err.to_string(), started.elapsed()),
+                },
+                None => ShadowOutput::failed(
+                    model,
+                    "No upstream is available to serve the model".to_string(),
+                    started.elapsed(),
+                ),
+            };
+            log.record(&ShadowLogEntry {
+                timestamp,
+                path: shadow_parts.uri.path().to_string(),
+                request,
+                primary: primary.await.ok(),
+                shadow: output,
+            });
+        });
+        Some(sender)
+    }
+
+    /// Probes the health endpoint of an upstream, and reads its models when they are
+    /// discovered.
+    async fn check(&self, upstream: &Upstream, timeout: Duration) -> bool {
+        let Ok(uri) = format!("{}{}", upstream.url, upstream.health_path).parse::<Uri>() else {
+            return false;
+        };
+        let mut request = Request::new(Body::empty());
+        *request.uri_mut() = uri;
+        if let Some(key) = &upstream.api_key {
+            if let Ok(value) = HeaderValue::from_str(&format!("Bearer {key}")) {
+                request.headers_mut().insert(header::AUTHORIZATION, value);
+            }
+        }
+
+        let response = match tokio::time::timeout(timeout, self.client.request(request)).await {
+            Ok(Ok(response)) if response.status().is_success() => response,
+            _ => return false,
+        };
+        if upstream.discover_models && upstream.health_path == MODELS_PATH {
+            let body = axum::body::to_bytes(Body::new(response.into_body()), MODELS_BODY_LIMIT);
+            let Ok(Ok(body)) = tokio::time::timeout(timeout, body).await else {
+                return false;
+            };
+            let Ok(list) = serde_json::from_slice::<ModelList>(&body) else {
+                warn!("Upstream {} returned an invalid model list", upstream.url);
+                return false;
+            };
+            *upstream.models.write().unwrap_or_else(|e| e.into_inner()) =
+                list.data.into_iter().map(|model| model.id).collect();
+        }
+        true
+    }
+}
+
+/// Forwards a request to the least busy healthy upstream serving its model.
+///
+/// The model is read from the `model` field of JSON bodies. A request for the name of
+/// a split is forwarded for one of its target models, picked by weight, and its outcome
+/// is recorded in the usage of that target. A sample of the requests for a model with
+/// a shadow is also sent to the shadow model in the background. The request is sent to
+/// another upstream, up to the configured number of retries, when the upstream cannot be
+/// reached or answers `429`, `502`, `503` or `504`. The response is relayed as it is, so
+/// streamed responses reach the client as the upstream produces them.
+///
+/// # Arguments
+///
+/// * `proxy` - The state of the proxy.
+/// * `request` - The request to forward.
+///
+/// # Returns
+///
+/// The response of the upstream, a `404 Not Found` if no upstream serves the model, or
+/// a `503 Service Unavailable` if none of the upstreams serving it is available.
+pub async fn proxy_request(
+    State(proxy): State<ProxyState>,
+    request: Request,
+) -> Result<Response, ApiError> {
+    let (mut parts, body) = request.into_parts();
+    let mut body = axum::body::to_bytes(body, proxy.body_limit)
+        .await
+        .map_err(|_| {
+            ApiError::payload_too_large(format!(
+                "The request body is larger than {} bytes",
+                proxy.body_limit
+            ))
+        })?;
+    let model = serde_json::from_slice::<RequestModel>(&body)
+        .ok()
+        .and_then(|request| request.model);
+    let mut model = model.as_deref();
+
+    let split_target = match model.and_then(|model| proxy.splits.get(model)) {
+        Some(split) => {
+            let Some(target) = split.pick(|model| proxy.is_available(model)) else {
+                return Err(ApiError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "server_error",
+                    format!("No model of the split '{}' is available", split.name),
+                ));
+            };
+            body = route_to_model(&body, &target.model);
+            parts
+                .headers
+                .insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+            model = Some(target.model.as_str());
+            Some(target)
+        }
+        None => None,
+    };
+
+    if !proxy
+        .upstreams()
+        .iter()
+        .any(|upstream| upstream.serves(model))
+    {
+        return Err(ApiError::not_found(format!(
+            "The model '{}' does not exist",
+            model.unwrap_or("")
+        ))
+        .with_param("model")
+        .with_code("model_not_found"));
+    }
+
+    let started = Instant::now();
+    let mut shadow = model
+        .and_then(|model| proxy.shadows.get(model))
+        .filter(|shadow| is_sampled(shadow.sample_rate))
+        .and_then(|shadow| proxy.spawn_shadow(shadow, &parts, &body));
+
+    let mut tried = Vec::new();
+    while let Some((index, upstream)) = proxy.pick(model, &tried) {
+        tried.push(index);
+        let last_attempt = tried.len() > proxy.retries;
+        match proxy.send(upstream.clone(), &parts, body.clone()).await {
+            Ok(response) if last_attempt || !is_retryable(response.status()) => {
+                if let Some(target) = split_target {
+                    target.record_response(
+                        response.status(),
+                        response.headers(),
+                        started.elapsed(),
+                    );
+                }
+                return Ok(match shadow.take() {
+                    Some(sender) => capture_output(
+                        response,
+                        model.unwrap_or_default().to_string(),
+                        started,
+                        sender,
+                    ),
+                    None => response,
+                });
+            }
+            Ok(response) => warn!(
+                "Upstream {} answered {}, retrying",
+                upstream.url,
+                response.status()
+            ),
+            Err(err) => {
+                warn!("Error forwarding to {}: {}", upstream.url, err);
+                upstream.set_healthy(false);
+            }
+        }
+        if last_attempt {
+            break;
+        }
+    }
+
+    if let Some(target) = split_target {
+        target.record_failure();
+    }
+    Err(ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "server_error",
+        "No upstream is available to serve the request",
+    ))
+}
+
+/// Replaces the `model` field of a JSON body.
+fn route_to_model(body: &Bytes, model: &str) -> Bytes {
+    let Ok(mut request) = serde_json::from_slice::<Value>(body) else {
+        return body.clone();
+    };
+    request["model"] = Value::String(model.to_string());
+    serde_json::to_vec(&request).map_or_else(|_| body.clone(), Bytes::from)
+}
+
+/// Whether a response status means another upstream may succeed.
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Lists the models served by the healthy upstreams of a gateway, and the splits with
+/// an available target.
+///
+/// # Arguments
+///
+/// * `proxy` - The state of the proxy.
+///
+/// # Returns
+///
+/// The model list in the format of the OpenAI API.
+pub async fn list_upstream_models(State(proxy): State<ProxyState>) -> Json<Value> {
+    let mut models: Vec<String> = proxy
+        .upstreams()
+        .iter()
+        .filter(|upstream| upstream.is_healthy())
+        .flat_map(|upstream| upstream.models())
+        .collect();
+    models.extend(
+        proxy
+            .splits
+            .values()
+            .filter(|split| split.pick(|model| proxy.is_available(model)).is_some())
+            .map(|split| split.name.clone()),
+    );
+    models.sort();
+    models.dedup();
+
+    let data: Vec<Value> = models
+        .into_iter()
+        .map(|id| {
+            let owned_by = id.split_once('/').map_or("system", |(owner, _)| owner);
+            json!({ "id": id, "object": "model", "created": 0, "owned_by": owned_by })
+        })
+        .collect();
+    Json(json!({ "object": "list", "data": data }))
+}
+
+/// Reports the usage of every target of the splits of a gateway, to compare a candidate
+/// model with the production one.
+///
+/// # Arguments
+///
+/// * `proxy` - The state of the proxy.
+///
+/// # Returns
+///
+/// The requests, failures, tokens and mean latency of every target, per split.
+pub async fn list_split_usage(State(proxy): State<ProxyState>) -> Json<Value> {
+    let mut splits: Vec<_> = proxy.splits.values().map(TrafficSplit::usage).collect();
+    splits.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(json!({ "object": "list", "data": splits }))
+}
+
+/// Periodically probes the health endpoint of every upstream, in the background.
+///
+/// Requests are only forwarded to the upstreams whose last probe succeeded.
+///
+/// # Arguments
+///
+/// * `proxy` - The state of the proxy.
+/// * `period` - The time between two probes of an upstream.
+///
+/// # Returns
+///
+/// The handle of the background task.
+pub fn spawn_health_checks(proxy: ProxyState, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            for upstream in proxy.upstreams() {
+                let healthy = proxy.check(upstream, period).await;
+                upstream.set_healthy(healthy);
+            }
+        }
+    })
+}
@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use crate::core::events::GenerationEvent;
+use crate::core::generator::TextGeneration;
+use crate::core::rag::{RagDocument, RagError, RagStore, RetrievedChunk};
+use crate::core::sampling::SamplingParams;
+use crate::core::usage_meter::{MeteredUsage, UsageMeter};
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::limits::check_model_access;
+use crate::openai::models::{
+    CreateRagDocumentRequest, DeleteRagDocumentResponse, ListRagDocumentsResponse, RagQueryRequest,
+    RagQueryResponse, RagUsage,
+};
+use crate::openai::request_json::RequestJson;
+use crate::openai::validation::Validate;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use chrono::Utc;
+use tracing::info;
+use uuid::Uuid;
+
+/// The instructions placed before the retrieved chunks when the request has none.
+const DEFAULT_INSTRUCTIONS: &str = "Answer the question using only the context below. \
+If the context does not contain the answer, say that you do not know.";
+
+impl From<RagError> for ApiError {
+    fn from(err: RagError) -> Self {
+        match err {
+            RagError::NotFound(_) => ApiError::not_found(err.to_string()),
+            RagError::Duplicate(_) => ApiError::new(
+                StatusCode::CONFLICT,
+                "invalid_request_error",
+                err.to_string(),
+            )
+            .with_param("id"),
+            RagError::EmptyDocument => {
+                ApiError::invalid_request(err.to_string()).with_param("text")
+            }
+            RagError::Embedding(err) => ApiError::internal(err),
+        }
+    }
+}
+
+/// Ingests a document into the RAG index.
+///
+/// The document is split into overlapping chunks, which are embedded and indexed so
+/// that `/v1/rag/query` can retrieve them.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `request` - The `CreateRagDocumentRequest` holding the text of the document.
+///
+/// # Returns
+///
+/// The `RagDocument` wrapped in `Json`, or an `ApiError` if RAG is disabled, the id is
+/// taken or the text is empty.
+pub async fn create_rag_document(
+    State(state): State<AppState>,
+    RequestJson(request): RequestJson<CreateRagDocumentRequest>,
+) -> Result<Json<RagDocument>, ApiError> {
+    let rag = store(&state)?.clone();
+
+    let document = tokio::task::spawn_blocking(move || {
+        rag.add_document(request.id, &request.text, request.metadata)
+    })
+    .await
+    .map_err(ApiError::internal)??;
+    info!(
+        "RAG document {} indexed in {} chunks",
+        document.id, document.chunks
+    );
+
+    Ok(Json(document))
+}
+
+/// Lists the documents of the RAG index.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+///
+/// # Returns
+///
+/// The documents wrapped in `Json`, most recent first, or an `ApiError` if RAG is disabled.
+pub async fn list_rag_documents(
+    State(state): State<AppState>,
+) -> Result<Json<ListRagDocumentsResponse>, ApiError> {
+    Ok(Json(ListRagDocumentsResponse {
+        object: "list".to_string(),
+        data: store(&state)?.documents(),
+    }))
+}
+
+/// Removes a document and its chunks from the RAG index.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `document_id` - The id of the document.
+///
+/// # Returns
+///
+/// The `DeleteRagDocumentResponse` wrapped in `Json`, or an `ApiError` if RAG is
+/// disabled or the document does not exist.
+pub async fn delete_rag_document(
+    State(state): State<AppState>,
+    Path(document_id): Path<String>,
+) -> Result<Json<DeleteRagDocumentResponse>, ApiError> {
+    store(&state)?.delete_document(&document_id)?;
+    info!("RAG document {} deleted", document_id);
+
+    Ok(Json(DeleteRagDocumentResponse {
+        id: document_id,
+        object: "rag.document.deleted".to_string(),
+        deleted: true,
+    }))
+}
+
+/// Answers a question from the documents of the RAG index.
+///
+/// The query is embedded, the `top_k` most similar chunks are retrieved and placed in
+/// a system message ahead of the question, and the served model generates the answer.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+/// * `request` - The `RagQueryRequest` holding the question and the sampling parameters.
+///
+/// # Returns
+///
+/// The `RagQueryResponse` with the answer and its sources wrapped in `Json`, with the
+/// usage headers of the generation, or an `ApiError` if RAG is disabled, the request is invalid or a guardrail rejects it.
+pub async fn query_rag(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<RagQueryRequest>,
+) -> Result<(MeteredUsage, Json<RagQueryResponse>), ApiError> {
+    let meter = Arc::new(UsageMeter::default());
+    let rag = store(&state)?.clone();
+    request.validate(&state.settings.current().model.id)?;
+    check_model_access(&state, &headers, &request.model)?;
+
+    let top_k = request.top_k.unwrap_or_else(|| rag.default_top_k());
+    let query = request.query.clone();
+    let (sources, embedding_tokens) =
+        tokio::task::spawn_blocking(move || rag.retrieve(&query, top_k))
+            .await
+            .map_err(ApiError::internal)??;
+
+    let params = SamplingParams::default()
+        .with_temperature(request.temperature)
+        .with_top_p(request.top_p)
+        .with_max_tokens(request.max_tokens);
+    let text_gen = TextGeneration::from_state(state.clone(), &params)?.with_meter(meter.clone());
+
+    let instructions = request
+        .instructions
+        .as_deref()
+        .unwrap_or(DEFAULT_INSTRUCTIONS);
+    let prompt = format!(
+        "system:{} user:{}",
+        context_message(instructions, &sources),
+        request.query
+    );
+    info!(
+        "RAG query with {} retrieved chunks: {}",
+        sources.len(),
+        request.query
+    );
+
+    let mut usage = RagUsage {
+        embedding_tokens,
+        ..RagUsage::default()
+    };
+    let answer = text_gen.generate_streaming(prompt, |event| {
+        if let GenerationEvent::UsageUpdate(tokens) = event {
+            usage.prompt_tokens = tokens.prompt_tokens;
+            usage.completion_tokens = tokens.completion_tokens;
+            usage.total_tokens = embedding_tokens + tokens.total_tokens();
+        }
+        Ok(())
+    })?;
+
+    let response = RagQueryResponse {
+        id: format!("rag_{}", Uuid::new_v4().simple()),
+        object: "rag.answer".to_string(),
+        created: Utc::now().timestamp(),
+        model: state.settings.current().model.id.clone(),
+        answer,
+        sources,
+        usage,
+    };
+
+    Ok((meter.usage(), Json(response)))
+}
+
+/// The system message holding the instructions and the numbered retrieved chunks.
+fn context_message(instructions: &str, sources: &[RetrievedChunk]) -> String {
+    let context = sources
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| format!("[{}] {}", index + 1, chunk.text))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    format!("{instructions}\n\nContext:\n{context}")
+}
+
+/// Returns the RAG store, or an error if RAG is disabled.
+fn store(state: &AppState) -> Result<&Arc<RagStore>, ApiError> {
+    state.rag.as_ref().ok_or_else(|| {
+        ApiError::not_found(
+            "RAG is disabled, set `rag.enabled` and `embeddings.model` to enable it",
+        )
+    })
+}
@@ -0,0 +1,636 @@
+use std::sync::Arc;
+
+use crate::core::events::{FinishReason, GenerationEvent, GenerationStream};
+use crate::core::generator::{PromptInput, TextGeneration};
+use crate::core::reasoning::ReasoningParser;
+use crate::core::sampling::SamplingParams;
+use crate::core::usage_meter::UsageMeter;
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::http_service::template_tokens;
+use crate::openai::limits::{
+    check_memory_pressure, check_message_count, check_model_access, check_prompt_tokens,
+    prompt_limits,
+};
+use crate::openai::models::{
+    RealtimeClientEvent, RealtimeContent, RealtimeError, RealtimeItem, RealtimeItemInput,
+    RealtimeMaxTokens, RealtimeQuery, RealtimeResponse, RealtimeResponseConfig,
+    RealtimeServerEvent, RealtimeServerEventKind, RealtimeSession, RealtimeSessionUpdate,
+    ResponseUsage,
+};
+use crate::openai::validation::check_model;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use serde_json::{json, Value};
+use tokio_stream::StreamExt;
+use tracing::info;
+use uuid::Uuid;
+
+/// The roles of the conversation items a client may create.
+const ITEM_ROLES: [&str; 3] = ["system", "user", "assistant"];
+
+/// Opens a text-only session of the Realtime API over a WebSocket.
+///
+/// The session follows the event protocol of the OpenAI Realtime API for text: the client
+/// configures it with `session.update`, adds messages with `conversation.item.create` and
+/// asks for an answer with `response.create`, which is generated from the whole
+/// conversation and streamed as `response.output_text.delta` events, framed by the
+/// `response.created`, `response.output_item.added`, `response.content_part.added` and
+/// the matching `.done` events, up to `response.done` with the token usage. The answer
+/// joins the conversation, so the session holds a multi-turn chat without resending it.
+/// `response.cancel` stops the generation, which ends `cancelled`.
+///
+/// Audio events are answered with an `error` event, as are invalid events; the session
+/// stays open. The conversation lives as long as the connection.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers, whose API key sets the limits of the session.
+/// * `query` - The `model` of the session, the served model when omitted.
+/// * `ws` - The WebSocket upgrade of the request.
+///
+/// # Returns
+///
+/// The upgrade response, with the `realtime` subprotocol when the client offers it, or an
+/// `ApiError` if the model is not served or the API key may not use it.
+pub async fn realtime(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<RealtimeQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let settings = state.settings.current();
+    let served_model = settings.model.id.clone();
+    let model = query.model.unwrap_or_else(|| served_model.clone());
+    let model = settings.model_aliases.get(&model).cloned().unwrap_or(model);
+    check_model(&model, &served_model)?;
+    check_model_access(&state, &headers, &model)?;
+
+    let session = Session {
+        state,
+        headers,
+        config: RealtimeSession {
+            id: format!("sess_{}", Uuid::new_v4().simple()),
+            object: "realtime.session".to_string(),
+            model,
+            modalities: vec!["text".to_string()],
+            instructions: None,
+            temperature: None,
+            max_response_output_tokens: RealtimeMaxTokens::Infinite("inf".to_string()),
+        },
+        items: Vec::new(),
+        active: None,
+    };
+
+    Ok(ws
+        .protocols(["realtime"])
+        .on_upgrade(move |socket| session.run(socket)))
+}
+
+/// The state of a realtime session.
+struct Session {
+    state: AppState,
+    headers: HeaderMap,
+    config: RealtimeSession,
+    /// The conversation, in order.
+    items: Vec<RealtimeItem>,
+    active: Option<ActiveResponse>,
+}
+
+/// The response being generated.
+struct ActiveResponse {
+    response: RealtimeResponse,
+    item: RealtimeItem,
+    text: String,
+    events: GenerationStream,
+    meter: Arc<UsageMeter>,
+    reasoning: Option<ReasoningParser>,
+    finish_reason: FinishReason,
+}
+
+/// What woke the session up.
+enum Input {
+    Client(Option<Result<Message, axum::Error>>),
+    Generation(Option<GenerationEvent>),
+}
+
+impl Session {
+    /// Answers the events of the client until it disconnects.
+    async fn run(mut self, mut socket: WebSocket) {
+        info!("Realtime session {} opened", self.config.id);
+        let created = RealtimeServerEventKind::SessionCreated {
+            session: self.config.clone(),
+        };
+        if send(&mut socket, vec![created]).await.is_err() {
+            return;
+        }
+
+        loop {
+            let input = match &mut self.active {
+                Some(active) => tokio::select! {
+                    message = socket.recv() => Input::Client(message),
+                    event = active.events.next() => Input::Generation(event),
+                },
+                None => Input::Client(socket.recv().await),
+            };
+            let events = match input {
+                Input::Client(Some(Ok(Message::Text(text)))) => self.handle(&text),
+                Input::Client(Some(Ok(Message::Binary(_)))) => vec![error_event(
+                    ApiError::invalid_request("Binary messages are not supported, send JSON text"),
+                    None,
+                )],
+                Input::Client(Some(Ok(Message::Close(_)))) | Input::Client(None) => break,
+                Input::Client(Some(Ok(_))) => continue,
+                Input::Client(Some(Err(_))) => break,
+                Input::Generation(Some(event)) => self.on_generation(event),
+                Input::Generation(None) => self.finish(None),
+            };
+            if send(&mut socket, events).await.is_err() {
+                break;
+            }
+        }
+
+        // Dropping the events of an active response stops its generation
+        if let Some(active) = self.active.take() {
+            active.meter.release();
+        }
+        info!("Realtime session {} closed", self.config.id);
+    }
+
+    /// Handles an event of the client.
+    fn handle(&mut self, text: &str) -> Vec<RealtimeServerEventKind> {
+        let value: Value = match serde_json::from_str(text) {
+            Ok(value) => value,
+            Err(err) => {
+                let error = ApiError::invalid_request(format!("Invalid JSON event: {err}"));
+                return vec![error_event(error, None)];
+            }
+        };
+        let event_id = value
+            .get("event_id")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let kind = value
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let event = match serde_json::from_value(value) {
+            Ok(event) => event,
+            Err(err) => {
+                let message = if kind.starts_with("input_audio_buffer.") {
+                    format!("'{kind}' is not supported, realtime sessions are text-only")
+                } else {
+                    format!("Invalid '{kind}' event: {err}")
+                };
+                let error = ApiError::invalid_request(message);
+                return vec![error_event(error, event_id)];
+            }
+        };
+
+        let result = match event {
+            RealtimeClientEvent::SessionUpdate { session, .. } => self.update(session),
+            RealtimeClientEvent::ItemCreate {
+                previous_item_id,
+                item,
+                ..
+            } => self.create_item(previous_item_id, item),
+            RealtimeClientEvent::ItemDelete { item_id, .. } => self.delete_item(item_id),
+            RealtimeClientEvent::ResponseCreate { response, .. } => self.create_response(response),
+            RealtimeClientEvent::ResponseCancel { .. } if self.active.is_some() => {
+                Ok(self.finish(Some(json!({
+                    "type": "cancelled",
+                    "reason": "client_cancelled"
+                }))))
+            }
+            RealtimeClientEvent::ResponseCancel { .. } => Err(ApiError::invalid_request(
+                "There is no active response to cancel",
+            )
+            .with_code("response_cancel_not_active")),
+        };
+
+        result.unwrap_or_else(|error| vec![error_event(error, event_id)])
+    }
+
+    /// Applies a `session.update` event.
+    fn update(
+        &mut self,
+        update: RealtimeSessionUpdate,
+    ) -> Result<Vec<RealtimeServerEventKind>, ApiError> {
+        if let Some(modalities) = update.modalities.or(update.output_modalities) {
+            if modalities.iter().any(|modality| modality != "text") {
+                return Err(ApiError::invalid_request(
+                    "Only the 'text' modality is supported - 'session.modalities'",
+                )
+                .with_param("session.modalities"));
+            }
+        }
+        check_temperature(update.temperature, "session.temperature")?;
+        let max_tokens = update
+            .max_response_output_tokens
+            .or(update.max_output_tokens);
+        if let Some(max_tokens) = max_tokens {
+            max_output_tokens(&max_tokens, "session.max_response_output_tokens")?;
+            self.config.max_response_output_tokens = max_tokens;
+        }
+        if let Some(instructions) = update.instructions {
+            self.config.instructions = Some(instructions);
+        }
+        if let Some(temperature) = update.temperature {
+            self.config.temperature = Some(temperature);
+        }
+
+        Ok(vec![RealtimeServerEventKind::SessionUpdated {
+            session: self.config.clone(),
+        }])
+    }
+
+    /// Applies a `conversation.item.create` event.
+    fn create_item(
+        &mut self,
+        previous_item_id: Option<String>,
+        input: RealtimeItemInput,
+    ) -> Result<Vec<RealtimeServerEventKind>, ApiError> {
+        if input.kind != "message" {
+            return Err(ApiError::invalid_request(format!(
+                "'{}' items are not supported, only 'message' - 'item.type'",
+                input.kind
+            ))
+            .with_param("item.type"));
+        }
+        let role = input.role.unwrap_or_default();
+        if !ITEM_ROLES.contains(&role.as_str()) {
+            return Err(ApiError::invalid_request(format!(
+                "'{role}' is not one of {ITEM_ROLES:?} - 'item.role'"
+            ))
+            .with_param("item.role"));
+        }
+        for (index, part) in input.content.iter().enumerate() {
+            if !matches!(part.kind.as_str(), "input_text" | "text" | "output_text")
+                || part.text.is_none()
+            {
+                return Err(ApiError::invalid_request(format!(
+                    "Only text content is supported - 'item.content.{index}'"
+                ))
+                .with_param(format!("item.content.{index}")));
+            }
+        }
+
+        let position = match previous_item_id.as_deref() {
+            None => self.items.len(),
+            Some("root") => 0,
+            Some(id) => self.position(id, "previous_item_id")? + 1,
+        };
+        let item = RealtimeItem {
+            id: input
+                .id
+                .unwrap_or_else(|| format!("item_{}", Uuid::new_v4().simple())),
+            object: "realtime.item".to_string(),
+            kind: input.kind,
+            status: "completed".to_string(),
+            role,
+            content: input.content,
+        };
+        let previous_item_id = position
+            .checked_sub(1)
+            .map(|index| self.items[index].id.clone());
+        self.items.insert(position, item.clone());
+
+        Ok(vec![RealtimeServerEventKind::ItemCreated {
+            previous_item_id,
+            item,
+        }])
+    }
+
+    /// Applies a `conversation.item.delete` event.
+    fn delete_item(&mut self, item_id: String) -> Result<Vec<RealtimeServerEventKind>, ApiError> {
+        let position = self.position(&item_id, "item_id")?;
+        self.items.remove(position);
+
+        Ok(vec![RealtimeServerEventKind::ItemDeleted { item_id }])
+    }
+
+    /// Starts generating a response from the conversation.
+    fn create_response(
+        &mut self,
+        config: Option<RealtimeResponseConfig>,
+    ) -> Result<Vec<RealtimeServerEventKind>, ApiError> {
+        if self.active.is_some() {
+            return Err(ApiError::invalid_request(
+                "The conversation already has an active response",
+            )
+            .with_code("conversation_already_has_active_response"));
+        }
+        let (instructions, temperature, max_tokens) = match config {
+            Some(config) => (
+                config.instructions,
+                config.temperature,
+                config
+                    .max_response_output_tokens
+                    .or(config.max_output_tokens),
+            ),
+            None => (None, None, None),
+        };
+        check_temperature(temperature, "response.temperature")?;
+        let max_tokens = match &max_tokens {
+            Some(max_tokens) => {
+                max_output_tokens(max_tokens, "response.max_response_output_tokens")?
+            }
+            None => max_output_tokens(&self.config.max_response_output_tokens, "max_tokens")?,
+        };
+
+        let limits = prompt_limits(&self.state, &self.headers);
+        check_message_count(&limits, self.items.len(), "items")?;
+        let turns: Vec<(String, String)> = instructions
+            .or(self.config.instructions.clone())
+            .map(|content| ("system".to_string(), content))
+            .into_iter()
+            .chain(self.items.iter().map(|item| {
+                let text: Vec<_> = item
+                    .content
+                    .iter()
+                    .filter_map(|part| part.text.as_deref())
+                    .collect();
+                (item.role.clone(), text.join("\n"))
+            }))
+            .collect();
+        let chat_template = self.state.chat_template.clone().unwrap_or_default();
+        let prompt = chat_template.render(&turns, &template_tokens(&self.state))?;
+        let input = PromptInput::Text(prompt.clone());
+        check_prompt_tokens(&limits, &self.state.tokenizer, &input, "items")?;
+        check_memory_pressure(&self.state, &input, max_tokens)?;
+
+        let params = SamplingParams::default()
+            .with_temperature(temperature.or(self.config.temperature))
+            .with_max_tokens(max_tokens);
+        let meter = Arc::new(UsageMeter::default());
+        let events = TextGeneration::from_state(self.state.clone(), &params)?
+            .with_meter(meter.clone())
+            .stream(input);
+        let reasoning = ReasoningParser::new(
+            &self
+                .state
+                .settings
+                .current()
+                .model_settings(&self.config.model)
+                .reasoning,
+            &prompt,
+        );
+
+        let response = RealtimeResponse {
+            id: format!("resp_{}", Uuid::new_v4().simple()),
+            object: "realtime.response".to_string(),
+            status: "in_progress".to_string(),
+            status_details: None,
+            output: Vec::new(),
+            usage: None,
+        };
+        let item = RealtimeItem {
+            id: format!("item_{}", Uuid::new_v4().simple()),
+            object: "realtime.item".to_string(),
+            kind: "message".to_string(),
+            status: "in_progress".to_string(),
+            role: "assistant".to_string(),
+            content: Vec::new(),
+        };
+        let created = vec![
+            RealtimeServerEventKind::ResponseCreated {
+                response: response.clone(),
+            },
+            RealtimeServerEventKind::OutputItemAdded {
+                response_id: response.id.clone(),
+                output_index: 0,
+                item: item.clone(),
+            },
+            RealtimeServerEventKind::ContentPartAdded {
+                response_id: response.id.clone(),
+                item_id: item.id.clone(),
+                output_index: 0,
+                content_index: 0,
+                part: text_part(String::new()),
+            },
+        ];
+        self.active = Some(ActiveResponse {
+            response,
+            item,
+            text: String::new(),
+            events,
+            meter,
+            reasoning,
+            finish_reason: FinishReason::Stop,
+        });
+
+        Ok(created)
+    }
+
+    /// Handles an event of the generation of the active response.
+    fn on_generation(&mut self, event: GenerationEvent) -> Vec<RealtimeServerEventKind> {
+        let Some(active) = &mut self.active else {
+            return Vec::new();
+        };
+        match event {
+            GenerationEvent::TokenDelta(delta) => {
+                let delta = match &mut active.reasoning {
+                    Some(parser) => parser.push(&delta).content,
+                    None => delta,
+                };
+                active.delta(delta).into_iter().collect()
+            }
+            GenerationEvent::Done { finish_reason } => {
+                active.finish_reason = finish_reason;
+                Vec::new()
+            }
+            GenerationEvent::Error(err) => {
+                let error = ApiError::from(err);
+                let body = error.body();
+                self.finish(Some(json!({
+                    "type": "failed",
+                    "error": { "type": body.kind, "code": body.code, "message": body.message }
+                })))
+            }
+            GenerationEvent::UsageUpdate(_) | GenerationEvent::ToolCallDelta(_) => Vec::new(),
+        }
+    }
+
+    /// Ends the active response and adds its answer to the conversation.
+    ///
+    /// # Arguments
+    ///
+    /// * `status_details` - Why the response ended early, `None` when its generation ended.
+    fn finish(&mut self, status_details: Option<Value>) -> Vec<RealtimeServerEventKind> {
+        let Some(mut active) = self.active.take() else {
+            return Vec::new();
+        };
+        // The generation stops once its events are dropped
+        let finished = status_details.is_none();
+        let mut events = Vec::new();
+        if finished {
+            if let Some(rest) = active
+                .reasoning
+                .as_mut()
+                .map(|parser| parser.finish().content)
+            {
+                events.extend(active.delta(rest));
+            }
+        }
+
+        let status_details = status_details.or_else(|| {
+            let reason = match active.finish_reason {
+                FinishReason::Stop => return None,
+                FinishReason::Length | FinishReason::LengthCapped => "max_output_tokens",
+                FinishReason::ContentFilter => "content_filter",
+                FinishReason::Timeout => "timeout",
+                FinishReason::Repetition => "repetition",
+            };
+            Some(json!({ "type": "incomplete", "reason": reason }))
+        });
+        let status = status_details
+            .as_ref()
+            .and_then(|details| details.get("type"))
+            .and_then(Value::as_str)
+            .unwrap_or("completed")
+            .to_string();
+        let part = text_part(active.text.clone());
+        active.item.status = if finished && status == "completed" {
+            "completed"
+        } else {
+            "incomplete"
+        }
+        .to_string();
+        active.item.content = vec![part.clone()];
+        let usage = active.meter.usage();
+        active.meter.release();
+        let completion_tokens = usage.completion_tokens.unwrap_or(0);
+        active.response.status = status;
+        active.response.status_details = status_details;
+        active.response.output = vec![active.item.clone()];
+        active.response.usage = Some(ResponseUsage {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: completion_tokens,
+            total_tokens: usage.prompt_tokens + completion_tokens,
+        });
+
+        let response_id = active.response.id.clone();
+        let item_id = active.item.id.clone();
+        events.extend([
+            RealtimeServerEventKind::OutputTextDone {
+                response_id: response_id.clone(),
+                item_id: item_id.clone(),
+                output_index: 0,
+                content_index: 0,
+                text: active.text,
+            },
+            RealtimeServerEventKind::ContentPartDone {
+                response_id: response_id.clone(),
+                item_id,
+                output_index: 0,
+                content_index: 0,
+                part,
+            },
+            RealtimeServerEventKind::OutputItemDone {
+                response_id,
+                output_index: 0,
+                item: active.item.clone(),
+            },
+            RealtimeServerEventKind::ResponseDone {
+                response: active.response,
+            },
+        ]);
+        self.items.push(active.item);
+
+        events
+    }
+
+    /// The position of an item in the conversation.
+    fn position(&self, id: &str, param: &str) -> Result<usize, ApiError> {
+        self.items
+            .iter()
+            .position(|item| item.id == id)
+            .ok_or_else(|| {
+                ApiError::invalid_request(format!("No conversation item found with id '{id}'"))
+                    .with_param(param)
+            })
+    }
+}
+
+impl ActiveResponse {
+    /// Appends a piece of the answer, returning its `response.output_text.delta` event.
+    fn delta(&mut self, delta: String) -> Option<RealtimeServerEventKind> {
+        if delta.is_empty() {
+            return None;
+        }
+        self.text.push_str(&delta);
+
+        Some(RealtimeServerEventKind::OutputTextDelta {
+            response_id: self.response.id.clone(),
+            item_id: self.item.id.clone(),
+            output_index: 0,
+            content_index: 0,
+            delta,
+        })
+    }
+}
+
+/// Sends events to the client, each with a new `event_id`.
+async fn send(
+    socket: &mut WebSocket,
+    events: Vec<RealtimeServerEventKind>,
+) -> Result<(), axum::Error> {
+    for event in events {
+        let event = RealtimeServerEvent {
+            event_id: format!("event_{}", Uuid::new_v4().simple()),
+            event,
+        };
+        let text = serde_json::to_string(&event).unwrap_or_default();
+        socket.send(Message::Text(text)).await?;
+    }
+    Ok(())
+}
+
+/// The `error` event of an invalid client event.
+fn error_event(error: ApiError, event_id: Option<String>) -> RealtimeServerEventKind {
+    error.record();
+    let body = error.body().clone();
+    RealtimeServerEventKind::Error {
+        error: RealtimeError {
+            kind: body.kind,
+            code: body.code,
+            message: body.message,
+            param: body.param,
+            event_id,
+        },
+    }
+}
+
+fn text_part(text: String) -> RealtimeContent {
+    RealtimeContent {
+        kind: "text".to_string(),
+        text: Some(text),
+    }
+}
+
+/// Checks that a temperature lies within `[0, 2]`.
+fn check_temperature(temperature: Option<f64>, param: &str) -> Result<(), ApiError> {
+    match temperature {
+        Some(value) if !(0.0..=2.0).contains(&value) => Err(ApiError::invalid_request(format!(
+            "{value} is not in the range [0, 2] - '{param}'"
+        ))
+        .with_param(param)),
+        _ => Ok(()),
+    }
+}
+
+/// The maximum number of output tokens, `None` for `inf`.
+fn max_output_tokens(max_tokens: &RealtimeMaxTokens, param: &str) -> Result<Option<i32>, ApiError> {
+    match max_tokens {
+        RealtimeMaxTokens::Count(count) if *count > 0 => Ok(Some(*count)),
+        RealtimeMaxTokens::Infinite(value) if value == "inf" => Ok(None),
+        _ => Err(ApiError::invalid_request(format!(
+            "Expected a positive number of tokens or 'inf' - '{param}'"
+        ))
+        .with_param(param)),
+    }
+}
@@ -0,0 +1,135 @@
+use axum::body::Bytes;
+use axum::extract::{FromRequest, Request};
+use axum::http::{header, HeaderMap, StatusCode};
+use serde::de::{DeserializeOwned, Deserializer};
+
+use crate::config::{RequestParsing, ServerConfig};
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+
+/// A JSON request body, parsed according to the `request_parsing` mode of the server.
+///
+/// Unlike `axum::Json`, the body is parsed through a wrapper of the deserializer that
+/// collects the fields the request type ignored. In `strict` mode they are rejected with
+/// an OpenAI error naming them, and every parsing error is returned in the OpenAI error
+/// shape rather than as plain text.
+pub struct RequestJson<T>(pub T);
+
+#[axum::async_trait]
+impl<T: DeserializeOwned> FromRequest<AppState> for RequestJson<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &AppState) -> Result<Self, Self::Rejection> {
+        if !is_json(req.headers()) {
+            return Err(ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "invalid_request_error",
+                "Expected a request with `Content-Type: application/json`",
+            ));
+        }
+        let body = Bytes::from_request(req, state).await.map_err(|rejection| {
+            match rejection.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => ApiError::payload_too_large(rejection.body_text()),
+                _ => ApiError::invalid_request(rejection.body_text()),
+            }
+        })?;
+
+        let settings = state.settings.current();
+        if !settings.model_aliases.is_empty() {
+            let mut body: serde_json::Value = serde_json::from_slice(&body)
+                .map_err(|e| ApiError::invalid_request(format!("Invalid JSON body: {e}")))?;
+            resolve_model_alias(&mut body, &settings);
+            return Ok(Self(parse_request(body, settings.request_parsing)?));
+        }
+
+        let mut deserializer = serde_json::Deserializer::from_slice(&body);
+        let request = parse_request(&mut deserializer, settings.request_parsing)?;
+        deserializer
+            .end()
+            .map_err(|e| ApiError::invalid_request(format!("Invalid JSON body: {e}")))?;
+
+        Ok(Self(request))
+    }
+}
+
+/// Replaces a model alias in the `model` field of a request body by the model it names.
+///
+/// The responses then report the aliased model, as OpenAI reports the dated snapshot of
+/// a model requested by its alias.
+///
+/// # Arguments
+///
+/// * `body` - The JSON body of the request.
+/// * `settings` - The configuration holding the aliases.
+pub fn resolve_model_alias(body: &mut serde_json::Value, settings: &ServerConfig) {
+    if let Some(serde_json::Value::String(model)) = body.get_mut("model") {
+        if let Some(target) = settings.model_aliases.get(model.as_str()) {
+            *model = target.clone();
+        }
+    }
+}
+
+/// Deserializes a request, rejecting its unknown fields in strict mode.
+///
+/// # Arguments
+///
+/// * `deserializer` - The deserializer of the body, e.g. of the raw bytes or of a
+///   `serde_json::Value`.
+/// * `mode` - The parsing mode of the server.
+///
+/// # Returns
+///
+/// The request, whose unknown fields are dropped in permissive mode.
+///
+/// # Errors
+///
+/// Returns a `400` if the body does not match the request type, or if it holds unknown
+/// fields in strict mode. The error names every unknown field by its path, e.g.
+/// `messages.0.nmae`.
+pub fn parse_request<'de, T, D>(deserializer: D, mode: RequestParsing) -> Result<T, ApiError>
+where
+    T: serde::Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    let mut unknown = Vec::new();
+    let request = serde_ignored::deserialize(deserializer, |path| unknown.push(path.to_string()))
+        .map_err(|e| ApiError::invalid_request(format!("Invalid request body: {e}")))?;
+
+    if mode == RequestParsing::Strict && !unknown.is_empty() {
+        let message = match unknown.as_slice() {
+            [field] => format!("Unrecognized request argument supplied: {field}"),
+            fields => format!(
+                "Unrecognized request arguments supplied: {}",
+                fields.join(", ")
+            ),
+        };
+        return Err(ApiError::invalid_request(format!(
+            "{message}. Check the spelling of the field against the API reference; the \
+             server is configured to reject unknown fields"
+        ))
+        .with_param(unknown.swap_remove(0))
+        .with_code("unknown_parameter"));
+    }
+
+    Ok(request)
+}
+
+/// Whether the content type is JSON, e.g. `application/json` or `application/merge-patch+json`.
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    essence
+        .strip_prefix("application/")
+        .is_some_and(|subtype| subtype == "json" || subtype.ends_with("+json"))
+}
@@ -0,0 +1,573 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::core::events::{FinishReason, GenerationEvent, TokenUsage, ToolCallDelta};
+use crate::core::generator::{PromptInput, TextGeneration};
+use crate::core::prompts::render_template;
+use crate::core::sampling::SamplingParams;
+use crate::core::tools::{OfferedTools, ToolChoice, ToolResult};
+use crate::core::usage_meter::UsageMeter;
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::limits::{
+    api_key, check_memory_pressure, check_message_count, check_model_access, check_prompt_tokens,
+    prompt_limits,
+};
+use crate::openai::models::{
+    CreateResponseRequest, ResponseIncompleteDetails, ResponseInput, ResponseInputItem,
+    ResponseInputMessage, ResponseMessageContent, ResponseObject, ResponseOutputContent,
+    ResponseOutputItem, ResponseStreamEvent, ResponseStreamEventKind, ResponseUsage,
+    TypedResponseInputItem,
+};
+use crate::openai::request_json::RequestJson;
+use crate::openai::streaming::{resume_stream, stream_events};
+use crate::openai::validation::Validate;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::Utc;
+use tracing::{info, trace};
+use uuid::Uuid;
+
+/// Creates a model response.
+///
+/// This function implements the OpenAI Responses API on top of the text generation
+/// engine. The `instructions`, the rendered prompt template and the `input` items,
+/// including the tool calls and tool outputs of previous turns, form the prompt.
+/// Tool calls emitted by the engine are returned as `function_call` output items.
+///
+/// The tools registered in the `tools` settings are offered to the model as well, unless
+/// `tool_choice` is `none` or names another tool. When the model calls them, the server
+/// runs them, feeds their results back into the prompt and generates again, up to
+/// `tools.max_iterations` times; each call is returned as a `function_call` item followed
+/// by a `function_call_output` item. Streamed responses do not run server tools.
+///
+/// With `stream: true` the response is streamed as typed server-sent events
+/// (`response.created`, `response.output_text.delta`, ..., `response.completed`);
+/// a request repeated with the `Last-Event-ID` header of such a stream resumes it instead.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+/// * `request` - The `CreateResponseRequest` containing the input parameters.
+///
+/// # Returns
+///
+/// The `ResponseObject` wrapped in `Json`, or the SSE stream of response events, or an
+/// `ApiError` if the request is invalid, the prompt template cannot be rendered or a
+/// guardrail rejects the request.
+pub async fn create_response(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateResponseRequest>,
+) -> Result<Response, ApiError> {
+    let stream = request.stream.unwrap_or(false);
+    if stream {
+        if let Some(response) = resume_stream(&state, &headers) {
+            return Ok(response);
+        }
+    }
+
+    let meter = Arc::new(UsageMeter::default());
+    request.validate(&state.settings.current().model.id)?;
+    check_model_access(&state, &headers, &request.model)?;
+    let limits = prompt_limits(&state, &headers);
+    if let ResponseInput::Items(items) = &request.input {
+        check_message_count(&limits, items.len(), "input")?;
+    }
+    let template = request
+        .prompt_template
+        .as_ref()
+        .map(|r| render_template(&state.settings.current().prompts, &r.name, &r.variables))
+        .transpose()?;
+    let params = SamplingParams::default()
+        .with_temperature(request.temperature)
+        .with_top_p(request.top_p)
+        .with_max_tokens(request.max_output_tokens);
+    let text_gen = TextGeneration::from_state(state.clone(), &params)?;
+
+    let server_tools = if stream {
+        OfferedTools::default()
+    } else {
+        let shadowed: Vec<String> = request
+            .tools
+            .iter()
+            .flatten()
+            .filter_map(|tool| tool.name.clone())
+            .collect();
+        OfferedTools::new(
+            &state,
+            api_key(&headers),
+            &ToolChoice::from_value(request.tool_choice.as_ref()),
+            &shadowed,
+        )
+        .await
+    };
+    let mut prompt_text = build_prompt(&request, template);
+    if let Some(instructions) = server_tools.prompt() {
+        prompt_text = format!("system:{instructions} {prompt_text}");
+    }
+    let prompt = PromptInput::Text(prompt_text.clone());
+    trace!("Response prompt {:?}", prompt);
+    check_prompt_tokens(&limits, &state.tokenizer, &prompt, "input")?;
+    check_memory_pressure(&state, &prompt, request.max_output_tokens)?;
+
+    let mut builder = ResponseBuilder::new(&state, request);
+
+    if stream {
+        let stream_id = builder.response.id.clone();
+        let preamble = builder.preamble();
+        let preamble = builder.serialize(preamble);
+        return Ok(stream_events(
+            &state,
+            api_key(&headers),
+            stream_id,
+            text_gen,
+            meter,
+            prompt,
+            preamble,
+            move |event| {
+                let events = builder.on_event(event);
+                builder.serialize(events)
+            },
+        )
+        .await);
+    }
+
+    if server_tools.is_empty() {
+        text_gen
+            .with_meter(meter.clone())
+            .generate_streaming(prompt, |event| {
+                builder.on_event(event);
+                Ok(())
+            })?;
+    } else {
+        run_with_tools(
+            &state,
+            &params,
+            text_gen,
+            &meter,
+            &server_tools,
+            prompt_text,
+            &mut builder,
+        )
+        .await?;
+    }
+
+    info!("create_response is done");
+
+    Ok((StatusCode::OK, meter.usage(), Json(builder.response)).into_response())
+}
+
+/// Generates a response, running the server tools the model calls and generating again
+/// with their results until it answers or `max_iterations` rounds of calls were run.
+///
+/// The events of a round are buffered: a round calling server tools is recorded as its
+/// calls and their results, the last round is applied to the response, with the usage of
+/// every round.
+async fn run_with_tools(
+    state: &AppState,
+    params: &SamplingParams,
+    mut text_gen: TextGeneration,
+    meter: &Arc<UsageMeter>,
+    server_tools: &OfferedTools,
+    mut prompt: String,
+    builder: &mut ResponseBuilder,
+) -> Result<(), ApiError> {
+    let mut usage = TokenUsage::default();
+    for round in 0..=server_tools.max_iterations() {
+        let mut events = Vec::new();
+        let text =
+            text_gen
+                .with_meter(meter.clone())
+                .generate_streaming(prompt.clone(), |event| {
+                    events.push(event);
+                    Ok(())
+                })?;
+
+        let failed = events
+            .iter()
+            .any(|event| matches!(event, GenerationEvent::Error(_)));
+        let results = if failed || round == server_tools.max_iterations() {
+            None
+        } else {
+            server_tools.execute(state, &text).await
+        };
+        let Some(results) = results else {
+            for event in events {
+                builder.on_event(match event {
+                    GenerationEvent::UsageUpdate(last) => {
+                        GenerationEvent::UsageUpdate(TokenUsage {
+                            prompt_tokens: usage.prompt_tokens + last.prompt_tokens,
+                            completion_tokens: usage.completion_tokens + last.completion_tokens,
+                        })
+                    }
+                    event => event,
+                });
+            }
+            return Ok(());
+        };
+
+        for event in &events {
+            if let GenerationEvent::UsageUpdate(round_usage) = event {
+                usage.prompt_tokens += round_usage.prompt_tokens;
+                usage.completion_tokens += round_usage.completion_tokens;
+            }
+        }
+        for result in &results {
+            let call_id = builder.add_tool_result(result);
+            prompt.push_str(&format!(
+                " assistant:[{call_id}] {}({}) tool:[{call_id}] {}",
+                result.name, result.arguments, result.output
+            ));
+        }
+        text_gen = TextGeneration::from_state(state.clone(), params)?;
+    }
+
+    Ok(())
+}
+
+/// Flattens the instructions and the input items of a request into a prompt.
+fn build_prompt(request: &CreateResponseRequest, template: Option<String>) -> String {
+    let mut turns: Vec<String> = template
+        .into_iter()
+        .chain(request.instructions.clone())
+        .map(|instructions| format!("system:{instructions}"))
+        .collect();
+
+    match &request.input {
+        ResponseInput::Text(text) => turns.push(format!("user:{text}")),
+        ResponseInput::Items(items) => {
+            for item in items {
+                turns.push(match item {
+                    ResponseInputItem::Message(message)
+                    | ResponseInputItem::Typed(TypedResponseInputItem::Message(message)) => {
+                        format!("{}:{}", message.role, message_text(message))
+                    }
+                    ResponseInputItem::Typed(TypedResponseInputItem::FunctionCall {
+                        call_id,
+                        name,
+                        arguments,
+                    }) => format!("assistant:[{call_id}] {name}({arguments})"),
+                    ResponseInputItem::Typed(TypedResponseInputItem::FunctionCallOutput {
+                        call_id,
+                        output,
+                    }) => format!("tool:[{call_id}] {output}"),
+                });
+            }
+        }
+    }
+
+    turns.join(" ")
+}
+
+/// The text of a message, its content parts concatenated.
+fn message_text(message: &ResponseInputMessage) -> String {
+    match &message.content {
+        ResponseMessageContent::Text(text) => text.clone(),
+        ResponseMessageContent::Parts(parts) => parts
+            .iter()
+            .filter_map(|part| part.text.as_deref())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Builds a response object from the generation events, and the stream events
+/// describing each change of its state.
+struct ResponseBuilder {
+    response: ResponseObject,
+    /// The assistant message, always the first output item, then the tool calls.
+    items: Vec<ResponseOutputItem>,
+    text: String,
+    /// The output index of each tool call, by tool call index.
+    tool_calls: HashMap<usize, usize>,
+    sequence_number: u64,
+}
+
+impl ResponseBuilder {
+    fn new(state: &AppState, request: CreateResponseRequest) -> Self {
+        let response = ResponseObject {
+            id: format!("resp_{}", Uuid::new_v4().simple()),
+            object: "response".to_string(),
+            created_at: Utc::now().timestamp(),
+            status: "in_progress".to_string(),
+            model: state.settings.current().model.id.clone(),
+            output: Vec::new(),
+            usage: None,
+            incomplete_details: None,
+            error: None,
+            instructions: request.instructions,
+            temperature: request.temperature,
+            top_p: request.top_p,
+            max_output_tokens: request.max_output_tokens,
+            tools: request.tools.unwrap_or_default(),
+            tool_choice: request
+                .tool_choice
+                .unwrap_or_else(|| serde_json::Value::String("auto".to_string())),
+            metadata: request.metadata.unwrap_or_default(),
+        };
+        let message = ResponseOutputItem::Message {
+            id: format!("msg_{}", Uuid::new_v4().simple()),
+            status: "in_progress".to_string(),
+            role: "assistant".to_string(),
+            content: Vec::new(),
+        };
+
+        Self {
+            response,
+            items: vec![message],
+            text: String::new(),
+            tool_calls: HashMap::new(),
+            sequence_number: 0,
+        }
+    }
+
+    /// Records a call of a server tool and its result, as output items following the
+    /// message.
+    ///
+    /// # Returns
+    ///
+    /// The id of the call.
+    fn add_tool_result(&mut self, result: &ToolResult) -> String {
+        let call_id = format!("call_{}", Uuid::new_v4().simple());
+        self.items.push(ResponseOutputItem::FunctionCall {
+            id: format!("fc_{}", Uuid::new_v4().simple()),
+            call_id: call_id.clone(),
+            name: result.name.clone(),
+            arguments: result.arguments.clone(),
+            status: "completed".to_string(),
+        });
+        self.items.push(ResponseOutputItem::FunctionCallOutput {
+            id: format!("fco_{}", Uuid::new_v4().simple()),
+            call_id: call_id.clone(),
+            output: result.output.clone(),
+            status: "completed".to_string(),
+        });
+
+        call_id
+    }
+
+    /// The events announcing the response and its message before the first token.
+    fn preamble(&mut self) -> Vec<ResponseStreamEventKind> {
+        vec![
+            ResponseStreamEventKind::Created {
+                response: self.response.clone(),
+            },
+            ResponseStreamEventKind::InProgress {
+                response: self.response.clone(),
+            },
+            ResponseStreamEventKind::OutputItemAdded {
+                output_index: 0,
+                item: self.items[0].clone(),
+            },
+            ResponseStreamEventKind::ContentPartAdded {
+                item_id: self.message_id(),
+                output_index: 0,
+                content_index: 0,
+                part: self.text_part(),
+            },
+        ]
+    }
+
+    /// Applies a generation event to the response.
+    ///
+    /// # Returns
+    ///
+    /// The stream events describing the change.
+    fn on_event(&mut self, event: GenerationEvent) -> Vec<ResponseStreamEventKind> {
+        match event {
+            GenerationEvent::TokenDelta(delta) => {
+                self.text.push_str(&delta);
+                vec![ResponseStreamEventKind::OutputTextDelta {
+                    item_id: self.message_id(),
+                    output_index: 0,
+                    content_index: 0,
+                    delta,
+                }]
+            }
+            GenerationEvent::ToolCallDelta(delta) => self.on_tool_call(delta),
+            GenerationEvent::UsageUpdate(usage) => {
+                self.response.usage = Some(ResponseUsage {
+                    input_tokens: usage.prompt_tokens,
+                    output_tokens: usage.completion_tokens,
+                    total_tokens: usage.total_tokens(),
+                });
+                Vec::new()
+            }
+            GenerationEvent::Done { finish_reason } => self.on_done(finish_reason),
+            GenerationEvent::Error(err) => {
+                let error = ApiError::from(err);
+                error.record();
+                let error = error.body().clone();
+                self.response.status = "failed".to_string();
+                self.response.error = Some(error.clone());
+                vec![
+                    ResponseStreamEventKind::Error {
+                        code: error.code,
+                        message: error.message,
+                        param: error.param,
+                    },
+                    ResponseStreamEventKind::Failed {
+                        response: self.response.clone(),
+                    },
+                ]
+            }
+        }
+    }
+
+    fn on_tool_call(&mut self, delta: ToolCallDelta) -> Vec<ResponseStreamEventKind> {
+        let mut events = Vec::new();
+        let output_index = match self.tool_calls.get(&delta.index) {
+            Some(&output_index) => output_index,
+            None => {
+                let output_index = self.items.len();
+                self.tool_calls.insert(delta.index, output_index);
+                self.items.push(ResponseOutputItem::FunctionCall {
+                    id: format!("fc_{}", Uuid::new_v4().simple()),
+                    call_id: delta
+                        .id
+                        .clone()
+                        .unwrap_or_else(|| format!("call_{}", Uuid::new_v4().simple())),
+                    name: String::new(),
+                    arguments: String::new(),
+                    status: "in_progress".to_string(),
+                });
+                events.push(ResponseStreamEventKind::OutputItemAdded {
+                    output_index,
+                    item: self.items[output_index].clone(),
+                });
+                output_index
+            }
+        };
+
+        if let ResponseOutputItem::FunctionCall {
+            id,
+            name,
+            arguments,
+            ..
+        } = &mut self.items[output_index]
+        {
+            if let Some(delta_name) = delta.name {
+                name.push_str(&delta_name);
+            }
+            arguments.push_str(&delta.arguments);
+            if !delta.arguments.is_empty() {
+                events.push(ResponseStreamEventKind::FunctionCallArgumentsDelta {
+                    item_id: id.clone(),
+                    output_index,
+                    delta: delta.arguments,
+                });
+            }
+        }
+
+        events
+    }
+
+    fn on_done(&mut self, finish_reason: FinishReason) -> Vec<ResponseStreamEventKind> {
+        let mut events = vec![
+            ResponseStreamEventKind::OutputTextDone {
+                item_id: self.message_id(),
+                output_index: 0,
+                content_index: 0,
+                text: self.text.clone(),
+            },
+            ResponseStreamEventKind::ContentPartDone {
+                item_id: self.message_id(),
+                output_index: 0,
+                content_index: 0,
+                part: self.text_part(),
+            },
+        ];
+
+        let part = self.text_part();
+        for (output_index, item) in self.items.iter_mut().enumerate() {
+            match item {
+                ResponseOutputItem::Message {
+                    status, content, ..
+                } => {
+                    *status = "completed".to_string();
+                    *content = vec![part.clone()];
+                }
+                ResponseOutputItem::FunctionCall {
+                    id,
+                    arguments,
+                    status,
+                    ..
+                } => {
+                    *status = "completed".to_string();
+                    events.push(ResponseStreamEventKind::FunctionCallArgumentsDone {
+                        item_id: id.clone(),
+                        output_index,
+                        arguments: arguments.clone(),
+                    });
+                }
+                ResponseOutputItem::FunctionCallOutput { .. } => {}
+            }
+            events.push(ResponseStreamEventKind::OutputItemDone {
+                output_index,
+                item: item.clone(),
+            });
+        }
+        self.response.output = self.items.clone();
+
+        let incomplete_reason = match finish_reason {
+            FinishReason::Stop => None,
+            FinishReason::Length | FinishReason::LengthCapped => Some("max_output_tokens"),
+            FinishReason::ContentFilter => Some("content_filter"),
+            FinishReason::Timeout => Some("timeout"),
+            FinishReason::Repetition => Some("repetition"),
+        };
+        match incomplete_reason {
+            None => {
+                self.response.status = "completed".to_string();
+                events.push(ResponseStreamEventKind::Completed {
+                    response: self.response.clone(),
+                });
+            }
+            Some(reason) => {
+                self.response.status = "incomplete".to_string();
+                self.response.incomplete_details = Some(ResponseIncompleteDetails {
+                    reason: reason.to_string(),
+                });
+                events.push(ResponseStreamEventKind::Incomplete {
+                    response: self.response.clone(),
+                });
+            }
+        }
+
+        events
+    }
+
+    /// Numbers the events and serializes them as the data of SSE events.
+    fn serialize(&mut self, events: Vec<ResponseStreamEventKind>) -> Vec<String> {
+        events
+            .into_iter()
+            .map(|event| {
+                let event = ResponseStreamEvent {
+                    event,
+                    sequence_number: self.sequence_number,
+                };
+                self.sequence_number += 1;
+                serde_json::to_string(&event).unwrap_or_default()
+            })
+            .collect()
+    }
+
+    fn message_id(&self) -> String {
+        match &self.items[0] {
+            ResponseOutputItem::Message { id, .. }
+            | ResponseOutputItem::FunctionCall { id, .. }
+            | ResponseOutputItem::FunctionCallOutput { id, .. } => id.clone(),
+        }
+    }
+
+    fn text_part(&self) -> ResponseOutputContent {
+        ResponseOutputContent::OutputText {
+            text: self.text.clone(),
+            annotations: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::core::generator::TextGeneration;
+use crate::core::sampling::SamplingParams;
+use crate::core::usage_meter::{MeteredUsage, UsageMeter};
+use crate::openai::errors::ApiError;
+use crate::openai::http_entities::AppState;
+use crate::openai::http_service::completion_prompt;
+use crate::openai::limits::{
+    check_memory_pressure, check_model_access, check_prompt_tokens, prompt_limits,
+};
+use crate::openai::models::{CreateScoreRequest, CreateScoreResponse, ScoreLogprobs, ScoreUsage};
+use crate::openai::request_json::RequestJson;
+use crate::openai::validation::Validate;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::Json;
+use chrono::Utc;
+use tokenizers::Tokenizer;
+use tracing::info;
+use uuid::Uuid;
+
+/// Scores a text with the served model.
+///
+/// A single forward pass is made over the prompt, and the continuation if there is one,
+/// without generating anything: this is the `echo` with `logprobs` and no output of the
+/// legacy completions API that evaluation harnesses use to compute the perplexity of a
+/// text or the likelihood of the answers of a multiple-choice question.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+/// * `request` - The `CreateScoreRequest` with the text to score.
+///
+/// # Returns
+///
+/// The `CreateScoreResponse` with the log-probability of every token wrapped in `Json`,
+/// with the usage headers, or an `ApiError` if the request is invalid or a guardrail rejects the prompt.
+pub async fn create_score(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    RequestJson(request): RequestJson<CreateScoreRequest>,
+) -> Result<(MeteredUsage, Json<CreateScoreResponse>), ApiError> {
+    let meter = Arc::new(UsageMeter::default());
+    request.validate(&state.settings.current().model.id)?;
+    check_model_access(&state, &headers, &request.model)?;
+    let limits = prompt_limits(&state, &headers);
+
+    let prompt = completion_prompt(&state, request.prompt, None)?;
+    check_prompt_tokens(&limits, &state.tokenizer, &prompt, "prompt")?;
+    check_memory_pressure(&state, &prompt, None)?;
+    let top_logprobs = request.top_logprobs.unwrap_or(0);
+    let continuation = request.continuation;
+    let text_gen = TextGeneration::from_state(state.clone(), &SamplingParams::default())?
+        .with_meter(meter.clone());
+
+    let score = tokio::task::spawn_blocking(move || {
+        text_gen.score(prompt, continuation.as_deref(), top_logprobs)
+    })
+    .await
+    .map_err(ApiError::internal)??;
+    info!(
+        "Scored {} tokens, perplexity {:.3}",
+        score.scored().len(),
+        score.perplexity()
+    );
+
+    let tokens: Vec<String> = score
+        .tokens
+        .iter()
+        .map(|&token| token_text(&state.tokenizer, token))
+        .collect();
+    let text_offset = tokens
+        .iter()
+        .scan(0, |offset, token| {
+            let start = *offset;
+            *offset += token.len();
+            Some(start)
+        })
+        .collect();
+    let token_logprobs = std::iter::once(None)
+        .chain(
+            score
+                .positions
+                .iter()
+                .map(|position| Some(position.logprob)),
+        )
+        .collect();
+    let top = std::iter::once(None)
+        .chain(score.positions.iter().map(|position| {
+            (top_logprobs > 0).then(|| {
+                position
+                    .top_logprobs
+                    .iter()
+                    .map(|&(token, logprob)| (token_text(&state.tokenizer, token), logprob))
+                    .collect::<HashMap<_, _>>()
+            })
+        }))
+        .collect();
+
+    let response = CreateScoreResponse {
+        id: format!("score_{}", Uuid::new_v4().simple()),
+        object: "score".to_string(),
+        created: Utc::now().timestamp(),
+        model: state.settings.current().model.id.clone(),
+        total_logprob: score.total_logprob(),
+        perplexity: score.perplexity(),
+        scored_tokens: score.scored().len(),
+        usage: ScoreUsage {
+            prompt_tokens: score.tokens.len(),
+            total_tokens: score.tokens.len(),
+        },
+        logprobs: ScoreLogprobs {
+            tokens,
+            token_logprobs,
+            top_logprobs: top,
+            text_offset,
+        },
+    };
+
+    Ok((meter.usage(), Json(response)))
+}
+
+/// Decodes a single token, keeping special tokens.
+fn token_text(tokenizer: &Tokenizer, token: u32) -> String {
+    tokenizer.decode(&[token], false).unwrap_or_default()
+}
@@ -0,0 +1,189 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use axum::body::{Body, Bytes};
+use axum::response::Response;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::oneshot;
+use tokio_stream::StreamExt;
+
+/// The largest output of a model kept for the shadow log, the rest is dropped.
+pub const SHADOW_BODY_LIMIT: usize = 1024 * 1024;
+
+/// A request sent to both the requested model and its shadow, written to the shadow log.
+#[derive(Serialize)]
+pub struct ShadowLogEntry {
+    /// When the request was received, in RFC 3339.
+    pub timestamp: String,
+    /// The path of the request, without the query string.
+    pub path: String,
+    /// The body of the request, or its digest, depending on the content policy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+    /// The output returned to the client, `None` when no upstream served the request.
+    pub primary: Option<ShadowOutput>,
+    /// The output of the shadow model, which the client never sees.
+    pub shadow: ShadowOutput,
+}
+
+/// The output of a model for a shadowed request.
+#[derive(Serialize)]
+pub struct ShadowOutput {
+    pub model: String,
+    /// The status of the response, `None` when the model could not be reached.
+    pub status: Option<u16>,
+    /// The time until the whole output was received.
+    pub latency_ms: u64,
+    /// The JSON body of the response, or its text for streams.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    /// Whether the output was cut at `SHADOW_BODY_LIMIT` bytes.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ShadowOutput {
+    /// The output of a model from the body of its response.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model that answered.
+    /// * `status` - The status of the response.
+    /// * `body` - The body of the response, at most `SHADOW_BODY_LIMIT` bytes of it.
+    /// * `truncated` - Whether the body was longer.
+    /// * `latency` - The time until the whole body was received.
+    pub fn new(
+        model: String,
+        status: u16,
+        body: &[u8],
+        truncated: bool,
+        latency: Duration,
+    ) -> Self {
+        let output = serde_json::from_slice(body)
+            .unwrap_or_else(|_| String::from_utf8_lossy(body).into_owned().into());
+        Self {
+            model,
+            status: Some(status),
+            latency_ms: latency.as_millis() as u64,
+            output: Some(output),
+            truncated,
+            error: None,
+        }
+    }
+
+    /// The outcome of a model that could not answer.
+    pub fn failed(model: String, error: String, latency: Duration) -> Self {
+        Self {
+            model,
+            status: None,
+            latency_ms: latency.as_millis() as u64,
+            output: None,
+            truncated: false,
+            error: Some(error),
+        }
+    }
+}
+
+/// Whether a request is picked for its shadow.
+///
+/// # Arguments
+///
+/// * `sample_rate` - The fraction of the requests picked, between `0` and `1`.
+pub fn is_sampled(sample_rate: f64) -> bool {
+    let random = RandomState::new().build_hasher().finish();
+    ((random >> 11) as f64 / (1u64 << 53) as f64) < sample_rate
+}
+
+/// The body of the request sent to the shadow model: the same request for another
+/// model, never streamed since nobody reads it as it is produced.
+///
+/// # Arguments
+///
+/// * `body` - The body of the client request.
+/// * `model` - The shadow model.
+///
+/// # Returns
+///
+/// The body, `None` if the request is not a JSON object.
+pub fn shadow_body(body: &[u8], model: &str) -> Option<Bytes> {
+    let mut request = serde_json::from_slice::<Value>(body).ok()?;
+    let fields = request.as_object_mut()?;
+    fields.insert("model".to_string(), Value::String(model.to_string()));
+    fields.insert("stream".to_string(), Value::Bool(false));
+    fields.remove("stream_options");
+    serde_json::to_vec(&request).ok().map(Bytes::from)
+}
+
+/// Copies the body of a response as it is relayed to the client, and sends the copy
+/// once the body ends or the client goes away.
+///
+/// # Arguments
+///
+/// * `response` - The response of the requested model.
+/// * `model` - The requested model.
+/// * `started` - When the request was received.
+/// * `sender` - Where the output is sent.
+///
+/// # Returns
+///
+/// The response, relayed unchanged.
+pub fn capture_output(
+    response: Response,
+    model: String,
+    started: Instant,
+    sender: oneshot::Sender<ShadowOutput>,
+) -> Response {
+    let (parts, body) = response.into_parts();
+    let mut capture = Capture {
+        model,
+        status: parts.status.as_u16(),
+        started,
+        bytes: Vec::new(),
+        truncated: false,
+        sender: Some(sender),
+    };
+    let body = body.into_data_stream().map(move |chunk| {
+        if let Ok(chunk) = &chunk {
+            capture.push(chunk);
+        }
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// The output of a response copied so far.
+struct Capture {
+    model: String,
+    status: u16,
+    started: Instant,
+    bytes: Vec<u8>,
+    truncated: bool,
+    sender: Option<oneshot::Sender<ShadowOutput>>,
+}
+
+impl Capture {
+    fn push(&mut self, chunk: &[u8]) {
+        let room = SHADOW_BODY_LIMIT.saturating_sub(self.bytes.len());
+        self.truncated |= chunk.len() > room;
+        self.bytes
+            .extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.send(ShadowOutput::new(
+                std::mem::take(&mut self.model),
+                self.status,
+                &self.bytes,
+                self.truncated,
+                self.started.elapsed(),
+            ));
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::core::events::{GenerationEvent, TokenUsage};
+use crate::core::generation_queue::QueuePosition;
+use crate::core::generator::{PromptInput, TextGeneration};
+use crate::core::streams::{event_id, parse_event_id, StreamBuffer};
+use crate::core::usage_meter::UsageMeter;
+use crate::openai::errors::{ApiError, ErrorResponse};
+use crate::openai::http_entities::AppState;
+use crate::openai::limits::api_key;
+use crate::openai::usage_headers::{QUEUE_ETA_HEADER, QUEUE_POSITION_HEADER};
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use tokio::sync::watch;
+use tokio_stream::wrappers::WatchStream;
+use tokio_stream::{StreamExt, StreamMap};
+use tracing::info;
+
+/// The data of the event terminating a successful stream.
+pub(crate) const DONE: &str = "[DONE]";
+
+/// Resumes a stream if the request carries the `Last-Event-ID` of a resumable stream
+/// created with the same API key.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `headers` - The request headers.
+///
+/// # Returns
+///
+/// The SSE response replaying the events after the last received one, or `None`
+/// when the request is not a resume or the stream has expired.
+pub(crate) fn resume_stream(state: &AppState, headers: &HeaderMap) -> Option<Response> {
+    let last_event_id = headers.get("last-event-id")?.to_str().ok()?;
+    let (stream_id, sequence) = parse_event_id(last_event_id)?;
+    let buffer = state.streams.get(stream_id, api_key(headers))?;
+
+    info!("Resuming stream {} after event {}", stream_id, sequence);
+
+    Some(sse_response(
+        state,
+        stream_id.to_string(),
+        buffer,
+        sequence + 1,
+        None,
+    ))
+}
+
+/// Runs the generations of the choices of a completion in the background and streams
+/// their chunks as server-sent events.
+///
+/// The choices are generated concurrently and their chunks are interleaved as they are
+/// produced, each carrying the index of its choice. The stream ends once every choice is
+/// done, or at the first error. The generation keeps running if the client disconnects,
+/// so the stream can be resumed with `Last-Event-ID` within the configured window.
+///
+/// The response is returned once the generation starts, so that its headers carry the
+/// number of prompt tokens and the queue time of the request, or as soon as it waits in
+/// the queue when `queue.report_position` is set.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `owner` - The API key of the request, the only one allowed to resume the stream.
+/// * `stream_id` - The id of the completion, used as the prefix of the event ids.
+/// * `generations` - The configured generation of every choice, in the order of the choices.
+/// * `meter` - The usage meter the generations record into.
+/// * `prompt` - The prompt text or tokens to generate from.
+/// * `include_usage` - Whether a last chunk with the token usage of all the choices follows
+///   the finish reasons, as asked by `stream_options.include_usage`.
+/// * `chunk` - Serializes a chunk of a choice from a text delta and/or a finish reason, or
+///   the usage chunk.
+///
+/// # Returns
+///
+/// The SSE response.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn stream_generation<F>(
+    state: &AppState,
+    owner: Option<&str>,
+    stream_id: String,
+    generations: Vec<TextGeneration>,
+    meter: Arc<UsageMeter>,
+    prompt: impl Into<PromptInput>,
+    include_usage: bool,
+    mut chunk: F,
+) -> Response
+where
+    F: FnMut(usize, Option<&str>, Option<&str>, Option<TokenUsage>) -> String + Send + 'static,
+{
+    let buffer = state.streams.create(&stream_id, owner);
+    let producer = buffer.clone();
+    let prompt = prompt.into();
+    let mut events = StreamMap::new();
+    for (index, text_gen) in generations.into_iter().enumerate() {
+        let text_gen = text_gen.with_meter(meter.clone());
+        events.insert(index, text_gen.stream(prompt.clone()));
+    }
+
+    let released = meter.clone();
+    tokio::spawn(async move {
+        let mut usage: Option<TokenUsage> = None;
+        while let Some((index, event)) = events.next().await {
+            match event {
+                GenerationEvent::TokenDelta(text) => {
+                    producer.push(chunk(index, Some(&text), None, None))
+                }
+                GenerationEvent::UsageUpdate(update) => {
+                    // The prompt is shared by the choices
+                    let total = usage.get_or_insert(TokenUsage {
+                        prompt_tokens: update.prompt_tokens,
+                        completion_tokens: 0,
+                    });
+                    total.completion_tokens += update.completion_tokens;
+                }
+                GenerationEvent::Done { finish_reason } => {
+                    producer.push(chunk(index, None, Some(finish_reason.as_str()), None))
+                }
+                GenerationEvent::Error(err) => {
+                    producer.push(error_data(err));
+                    producer.finish();
+                    released.release();
+                    return;
+                }
+                GenerationEvent::ToolCallDelta(_) => {}
+            }
+        }
+        if include_usage {
+            producer.push(chunk(0, None, None, Some(usage.unwrap_or_default())));
+        }
+        producer.push(DONE.to_string());
+        producer.finish();
+        released.release();
+    });
+
+    let queue = wait_for_start(state, &meter).await;
+    (
+        meter.partial(),
+        sse_response(state, stream_id, buffer, 0, queue),
+    )
+        .into_response()
+}
+
+/// Runs a generation in the background and streams the events it is mapped to.
+///
+/// This is the building block of the endpoints whose stream is not a plain sequence
+/// of chunks, such as `/v1/responses` whose events describe the state of the response.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `owner` - The API key of the request, the only one allowed to resume the stream.
+/// * `stream_id` - The id of the stream, used as the prefix of the event ids.
+/// * `text_gen` - The configured generation.
+/// * `meter` - The usage meter the generation records into.
+/// * `prompt` - The prompt text or tokens to generate from.
+/// * `preamble` - The data of the events sent before the generation starts.
+/// * `on_event` - Maps a generation event to the data of zero or more SSE events.
+///
+/// # Returns
+///
+/// The SSE response, once the generation started or is queued.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn stream_events<F>(
+    state: &AppState,
+    owner: Option<&str>,
+    stream_id: String,
+    text_gen: TextGeneration,
+    meter: Arc<UsageMeter>,
+    prompt: impl Into<PromptInput>,
+    preamble: Vec<String>,
+    mut on_event: F,
+) -> Response
+where
+    F: FnMut(GenerationEvent) -> Vec<String> + Send + 'static,
+{
+    let buffer = state.streams.create(&stream_id, owner);
+    let producer = buffer.clone();
+    for data in preamble {
+        producer.push(data);
+    }
+    let mut events = text_gen.with_meter(meter.clone()).stream(prompt);
+
+    let released = meter.clone();
+    tokio::spawn(async move {
+        while let Some(event) = events.next().await {
+            for data in on_event(event) {
+                producer.push(data);
+            }
+        }
+        producer.finish();
+        released.release();
+    });
+
+    let queue = wait_for_start(state, &meter).await;
+    (
+        meter.partial(),
+        sse_response(state, stream_id, buffer, 0, queue),
+    )
+        .into_response()
+}
+
+/// Streams the events a background task pushes to its buffer, for the endpoints running
+/// more than one generation per request such as `/v1/agents/run`.
+///
+/// The task must finish the buffer once it pushed its last event.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `owner` - The API key of the request, the only one allowed to resume the stream.
+/// * `stream_id` - The id of the stream, used as the prefix of the event ids.
+/// * `task` - Runs the request, pushing the data of its events to the buffer.
+///
+/// # Returns
+///
+/// The SSE response, returned right away.
+pub(crate) fn stream_task<F, Fut>(
+    state: &AppState,
+    owner: Option<&str>,
+    stream_id: String,
+    task: F,
+) -> Response
+where
+    F: FnOnce(Arc<StreamBuffer>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let buffer = state.streams.create(&stream_id, owner);
+    tokio::spawn(task(buffer.clone()));
+
+    sse_response(state, stream_id, buffer, 0, None)
+}
+
+/// Serializes a generation error as the OpenAI error envelope.
+pub(crate) fn error_data(err: anyhow::Error) -> String {
+    api_error_data(ApiError::from(err))
+}
+
+/// Serializes an error as the OpenAI error envelope.
+pub(crate) fn api_error_data(error: ApiError) -> String {
+    error.record();
+    let body = ErrorResponse {
+        error: error.body().clone(),
+    };
+
+    serde_json::to_string(&body).unwrap_or_default()
+}
+
+/// Waits until the generation starts or, when queue positions are reported, until it
+/// waits in the queue.
+///
+/// # Returns
+///
+/// The position of the request in the queue, `None` once the generation started.
+async fn wait_for_start(
+    state: &AppState,
+    meter: &UsageMeter,
+) -> Option<watch::Receiver<Option<QueuePosition>>> {
+    if !state.settings.current().queue.report_position {
+        meter.started().await;
+        return None;
+    }
+    let mut queue = meter.queue_position();
+    let queued = tokio::select! {
+        _ = meter.started() => false,
+        queued = queue.wait_for(Option::is_some) => queued.is_ok(),
+    };
+    queued.then_some(queue)
+}
+
+/// Streams the events of a buffer from `from`, with periodic keep-alive comments.
+///
+/// A queued request is told its position and estimated wait in the
+/// `x-queue-position` and `x-queue-eta-ms` headers, then in a comment such as
+/// `: queue position=3 eta_ms=1200` whenever they change, until its generation starts.
+fn sse_response(
+    state: &AppState,
+    stream_id: String,
+    buffer: Arc<StreamBuffer>,
+    from: usize,
+    queue: Option<watch::Receiver<Option<QueuePosition>>>,
+) -> Response {
+    let position = queue.as_ref().and_then(|queue| *queue.borrow());
+    // Without a queue, the watch stream ends right away on the `None` of its channel
+    let queue = queue.unwrap_or_else(|| watch::channel(None).1);
+    let queue_events = WatchStream::new(queue)
+        .take_while(Option::is_some)
+        .filter_map(|position| {
+            position.map(|position| {
+                Ok::<_, Infallible>(Event::default().comment(format!(
+                    "queue position={} eta_ms={}",
+                    position.ahead,
+                    position.eta.as_millis()
+                )))
+            })
+        });
+    let events = buffer.subscribe(from).map(move |(sequence, event)| {
+        let sse = Event::default()
+            .id(event_id(&stream_id, sequence))
+            .data(event.data);
+        Ok::<_, Infallible>(match event.name {
+            Some(name) => sse.event(name),
+            None => sse,
+        })
+    });
+    let keep_alive = KeepAlive::new()
+        .interval(Duration::from_secs(
+            state.settings.current().streaming.keep_alive_secs,
+        ))
+        .text("keep-alive");
+
+    let mut response = Sse::new(queue_events.chain(events))
+        .keep_alive(keep_alive)
+        .into_response();
+    if let Some(position) = position {
+        let headers = response.headers_mut();
+        headers.insert(QUEUE_POSITION_HEADER, HeaderValue::from(position.ahead));
+        headers.insert(
+            QUEUE_ETA_HEADER,
+            HeaderValue::from(position.eta.as_millis() as u64),
+        );
+    }
+    response
+}
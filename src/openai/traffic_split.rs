@@ -0,0 +1,197 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::bail;
+use axum::http::{HeaderMap, StatusCode};
+use serde::Serialize;
+
+use crate::config::SplitTargetSettings;
+use crate::openai::usage_headers::{COMPLETION_TOKENS_HEADER, PROMPT_TOKENS_HEADER};
+
+/// A model name whose requests are spread over several models by weight, such as the
+/// production checkpoint and a candidate quantization receiving a fraction of its
+/// traffic.
+pub struct TrafficSplit {
+    pub name: String,
+    targets: Vec<SplitTarget>,
+}
+
+/// A model receiving part of the traffic of a split, with its usage since the start.
+pub struct SplitTarget {
+    pub model: String,
+    pub weight: f64,
+    requests: AtomicU64,
+    failures: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+    latency_ms: AtomicU64,
+}
+
+/// The usage of a split, returned by `/v1/gateway/splits`.
+#[derive(Debug, Serialize)]
+pub struct SplitUsage {
+    pub name: String,
+    pub targets: Vec<SplitTargetUsage>,
+}
+
+/// The usage of a target of a split.
+#[derive(Debug, Serialize)]
+pub struct SplitTargetUsage {
+    pub model: String,
+    pub weight: f64,
+    /// The requests routed to the target.
+    pub requests: u64,
+    /// The requests answered with an error status or that no upstream could serve.
+    pub failures: u64,
+    /// The tokens reported by the `x-usage-*` headers of the upstreams.
+    pub prompt_tokens: u64,
+    /// The generated tokens, without the ones of streams, whose headers are sent before
+    /// the generation ends.
+    pub completion_tokens: u64,
+    /// The mean time until the upstream answered the successful requests with its
+    /// headers.
+    pub mean_latency_ms: Option<f64>,
+}
+
+impl TrafficSplit {
+    /// Creates a split from its configured targets.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The model name clients send.
+    /// * `targets` - The models the requests are routed to, with their weights.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a weight is negative or not finite, or if no weight is
+    /// positive.
+    pub fn new(name: impl Into<String>, targets: &[SplitTargetSettings]) -> anyhow::Result<Self> {
+        let name = name.into();
+        if let Some(target) = targets
+            .iter()
+            .find(|target| !target.weight.is_finite() || target.weight < 0.0)
+        {
+            bail!(
+                "The weight of {} in the split {} must be a non-negative number",
+                target.model,
+                name
+            );
+        }
+        if !targets.iter().any(|target| target.weight > 0.0) {
+            bail!("The split {name} needs a target with a positive weight");
+        }
+
+        let targets = targets
+            .iter()
+            .map(|target| SplitTarget {
+                model: target.model.clone(),
+                weight: target.weight,
+                requests: AtomicU64::new(0),
+                failures: AtomicU64::new(0),
+                prompt_tokens: AtomicU64::new(0),
+                completion_tokens: AtomicU64::new(0),
+                latency_ms: AtomicU64::new(0),
+            })
+            .collect();
+        Ok(Self { name, targets })
+    }
+
+    /// Picks a target at random in proportion to the weights, among the targets that
+    /// are available.
+    ///
+    /// The share of a target that is not available goes to the others, so requests keep
+    /// being served while a candidate is down.
+    ///
+    /// # Arguments
+    ///
+    /// * `available` - Whether a model has a healthy upstream serving it.
+    ///
+    /// # Returns
+    ///
+    /// The picked target, `None` if no target with a positive weight is available.
+    pub fn pick(&self, available: impl Fn(&str) -> bool) -> Option<&SplitTarget> {
+        let candidates: Vec<&SplitTarget> = self
+            .targets
+            .iter()
+            .filter(|target| target.weight > 0.0 && available(&target.model))
+            .collect();
+        let total: f64 = candidates.iter().map(|target| target.weight).sum();
+
+        let random = RandomState::new().build_hasher().finish();
+        let mut point = (random >> 11) as f64 / (1u64 << 53) as f64 * total;
+        for target in &candidates {
+            if point < target.weight {
+                return Some(target);
+            }
+            point -= target.weight;
+        }
+        candidates.last().copied()
+    }
+
+    /// The usage of every target of the split.
+    pub fn usage(&self) -> SplitUsage {
+        SplitUsage {
+            name: self.name.clone(),
+            targets: self.targets.iter().map(SplitTarget::usage).collect(),
+        }
+    }
+}
+
+impl SplitTarget {
+    /// Records the response of an upstream to a request routed to the target.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The status of the response.
+    /// * `headers` - The headers of the response, holding its usage.
+    /// * `latency` - The time until the upstream answered.
+    pub fn record_response(&self, status: StatusCode, headers: &HeaderMap, latency: Duration) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if status.is_success() {
+            self.latency_ms
+                .fetch_add(latency.as_millis() as u64, Ordering::Relaxed);
+        } else {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.prompt_tokens.fetch_add(
+            header_count(headers, PROMPT_TOKENS_HEADER),
+            Ordering::Relaxed,
+        );
+        self.completion_tokens.fetch_add(
+            header_count(headers, COMPLETION_TOKENS_HEADER),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Records a request routed to the target that no upstream could serve.
+    pub fn record_failure(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn usage(&self) -> SplitTargetUsage {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let answered = requests.saturating_sub(self.failures.load(Ordering::Relaxed));
+        SplitTargetUsage {
+            model: self.model.clone(),
+            weight: self.weight,
+            requests,
+            failures: self.failures.load(Ordering::Relaxed),
+            prompt_tokens: self.prompt_tokens.load(Ordering::Relaxed),
+            completion_tokens: self.completion_tokens.load(Ordering::Relaxed),
+            mean_latency_ms: (answered > 0)
+                .then(|| self.latency_ms.load(Ordering::Relaxed) as f64 / answered as f64),
+        }
+    }
+}
+
+/// Reads a count from a response header, `0` when it is absent or invalid.
+fn header_count(headers: &HeaderMap, name: &str) -> u64 {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0)
+}
@@ -0,0 +1,46 @@
+use axum::http::HeaderValue;
+use axum::response::{IntoResponseParts, ResponseParts};
+
+use crate::core::usage_meter::MeteredUsage;
+
+/// The number of prompt tokens of the request.
+pub const PROMPT_TOKENS_HEADER: &str = "x-usage-prompt-tokens";
+
+/// The number of generated tokens, absent from streams whose generation is still running
+/// when the headers are sent.
+pub const COMPLETION_TOKENS_HEADER: &str = "x-usage-completion-tokens";
+
+/// How long the request waited for the model, in milliseconds.
+pub const QUEUE_TIME_HEADER: &str = "x-queue-time-ms";
+
+/// The number of requests ahead of a queued stream, sent when its generation waits.
+pub const QUEUE_POSITION_HEADER: &str = "x-queue-position";
+
+/// The estimated wait of a queued stream before its generation starts, in milliseconds.
+pub const QUEUE_ETA_HEADER: &str = "x-queue-eta-ms";
+
+/// The number of tokens the generation may produce, sent when the server lowered the
+/// `max_tokens` of the request.
+pub const MAX_TOKENS_APPLIED_HEADER: &str = "x-max-tokens-applied";
+
+/// Adds the usage headers to a response, so that proxies and gateways can meter the
+/// traffic without parsing the bodies or the streams.
+impl IntoResponseParts for MeteredUsage {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        let headers = res.headers_mut();
+        headers.insert(PROMPT_TOKENS_HEADER, HeaderValue::from(self.prompt_tokens));
+        if let Some(completion_tokens) = self.completion_tokens {
+            headers.insert(
+                COMPLETION_TOKENS_HEADER,
+                HeaderValue::from(completion_tokens),
+            );
+        }
+        headers.insert(QUEUE_TIME_HEADER, HeaderValue::from(self.queue_time_ms));
+        if let Some(max_tokens) = self.max_tokens_applied {
+            headers.insert(MAX_TOKENS_APPLIED_HEADER, HeaderValue::from(max_tokens));
+        }
+        Ok(res)
+    }
+}
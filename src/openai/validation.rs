@@ -0,0 +1,658 @@
+use crate::openai::errors::ApiError;
+use crate::openai::models::{
+    CreateAgentRunRequest, CreateAssistantRequest, CreateChatCompletionRequest,
+    CreateClassificationRequest, CreateCompletionRequest, CreateEmbeddingRequest,
+    CreateMessageRequest, CreateResponseRequest, CreateRunRequest, CreateScoreRequest,
+    CreateThreadRequest, EmbeddingInput, EmbeddingInputItem, ModifyAssistantRequest,
+    PredictionContent, PredictionText, Prompt, RagQueryRequest, ResponseInput, ResponseInputItem,
+    ResponseInputMessage, ResponseMessageContent, TypedResponseInputItem,
+};
+
+/// The message roles accepted in chat completion requests.
+const MESSAGE_ROLES: [&str; 5] = ["system", "developer", "user", "assistant", "tool"];
+
+/// The message roles accepted in the threads of the Assistants API.
+const THREAD_MESSAGE_ROLES: [&str; 2] = ["user", "assistant"];
+
+/// The maximum number of candidate labels of a classification request.
+const MAX_LABELS: usize = 100;
+
+/// The maximum number of most likely tokens returned at every position of a score.
+const MAX_TOP_LOGPROBS: usize = 20;
+
+/// The maximum number of choices of a generation request, which are generated concurrently.
+const MAX_CHOICES: i64 = 8;
+
+/// Validation of a request payload before it reaches the sampler.
+pub trait Validate {
+    /// Checks the request parameters against the ranges of the OpenAI API.
+    ///
+    /// # Arguments
+    ///
+    /// * `served_model` - The id of the model served by this instance.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `invalid_request_error` naming the offending parameter.
+    fn validate(&self, served_model: &str) -> Result<(), ApiError>;
+}
+
+impl Validate for CreateChatCompletionRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        check_model(&self.model, served_model)?;
+
+        if self.messages.is_empty() {
+            return Err(
+                ApiError::invalid_request("'messages' must contain at least one message")
+                    .with_param("messages"),
+            );
+        }
+        for (index, message) in self.messages.iter().enumerate() {
+            if !MESSAGE_ROLES.contains(&message.role.as_str()) {
+                return Err(ApiError::invalid_request(format!(
+                    "'{}' is not one of {:?} - 'messages.{}.role'",
+                    message.role, MESSAGE_ROLES, index
+                ))
+                .with_param(format!("messages.{index}.role")));
+            }
+        }
+        if let Some(PredictionContent::Content {
+            content: PredictionText::Parts(parts),
+        }) = &self.prediction
+        {
+            if let Some(index) = parts.iter().position(|part| part.kind != "text") {
+                return Err(ApiError::invalid_request(format!(
+                    "'{}' is not one of ['text'] - 'prediction.content.{}.type'",
+                    parts[index].kind, index
+                ))
+                .with_param(format!("prediction.content.{index}.type")));
+            }
+        }
+
+        check_range(self.temperature, "temperature", 0.0, 2.0)?;
+        check_top_p(self.top_p)?;
+        check_range(self.frequency_penalty, "frequency_penalty", -2.0, 2.0)?;
+        check_range(self.presence_penalty, "presence_penalty", -2.0, 2.0)?;
+        check_repetition_penalty(self.repetition_penalty)?;
+        check_positive(self.max_tokens.map(i64::from), "max_tokens")?;
+        check_choices(self.n)?;
+        check_top_k(self.top_k)?;
+        check_range(self.min_p, "min_p", 0.0, 1.0)?;
+        check_best_of(self.best_of, self.n)?;
+        check_beam_search(self.use_beam_search)
+    }
+}
+
+impl Validate for CreateCompletionRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        check_model(&self.model, served_model)?;
+        check_prompt(self.prompt.as_ref())?;
+        if self.prompt_template.is_some()
+            && matches!(
+                self.prompt,
+                Some(Prompt::ArrayOfTokens(_) | Prompt::ArrayOfTokenArrays(_))
+            )
+        {
+            return Err(ApiError::invalid_request(
+                "'prompt_template' cannot be combined with a token prompt",
+            )
+            .with_param("prompt_template"));
+        }
+        if self.suffix.is_some() {
+            if matches!(
+                self.prompt,
+                Some(Prompt::ArrayOfTokens(_) | Prompt::ArrayOfTokenArrays(_))
+            ) {
+                return Err(ApiError::invalid_request(
+                    "'suffix' requires a text prompt, the code before the cursor",
+                )
+                .with_param("suffix"));
+            }
+            if self.prompt_template.is_some() {
+                return Err(ApiError::invalid_request(
+                    "'prompt_template' cannot be combined with 'suffix'",
+                )
+                .with_param("prompt_template"));
+            }
+        }
+
+        check_range(self.temperature, "temperature", 0.0, 2.0)?;
+        check_top_p(self.top_p)?;
+        check_range(
+            self.frequency_penalty.map(f64::from),
+            "frequency_penalty",
+            -2.0,
+            2.0,
+        )?;
+        check_range(
+            self.presence_penalty.map(f64::from),
+            "presence_penalty",
+            -2.0,
+            2.0,
+        )?;
+        check_repetition_penalty(self.repetition_penalty)?;
+        check_positive(self.max_tokens.map(i64::from), "max_tokens")?;
+        check_choices(self.n.map(i64::from))?;
+        check_top_k(self.top_k)?;
+        check_range(self.min_p, "min_p", 0.0, 1.0)?;
+        check_best_of(self.best_of.map(i64::from), self.n.map(i64::from))?;
+        check_beam_search(self.use_beam_search)
+    }
+}
+
+impl Validate for CreateResponseRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        check_model(&self.model, served_model)?;
+
+        if let ResponseInput::Items(items) = &self.input {
+            if items.is_empty() {
+                return Err(
+                    ApiError::invalid_request("'input' must contain at least one item")
+                        .with_param("input"),
+                );
+            }
+            for (index, item) in items.iter().enumerate() {
+                match item {
+                    ResponseInputItem::Message(message)
+                    | ResponseInputItem::Typed(TypedResponseInputItem::Message(message)) => {
+                        check_input_message(message, index)?
+                    }
+                    ResponseInputItem::Typed(_) => {}
+                }
+            }
+        }
+        if self.previous_response_id.is_some() {
+            return Err(ApiError::invalid_request(
+                "Responses are not stored, send the whole conversation in 'input'",
+            )
+            .with_param("previous_response_id"));
+        }
+
+        check_range(self.temperature, "temperature", 0.0, 2.0)?;
+        check_top_p(self.top_p)?;
+        check_positive(self.max_output_tokens.map(i64::from), "max_output_tokens")
+    }
+}
+
+impl Validate for CreateEmbeddingRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        check_model(&self.model, served_model)?;
+
+        let empty = match &self.input {
+            EmbeddingInput::Text(text) => text.is_empty(),
+            EmbeddingInput::Texts(texts) => texts.is_empty() || texts.iter().any(String::is_empty),
+            EmbeddingInput::Items(items) => {
+                items.is_empty()
+                    || items.iter().any(|item| match item {
+                        EmbeddingInputItem::Text(text) => text.is_empty(),
+                        EmbeddingInputItem::Image { image } => image.is_empty(),
+                    })
+            }
+        };
+        if empty {
+            return Err(ApiError::invalid_request(
+                "'input' must not be empty or contain empty strings",
+            )
+            .with_param("input"));
+        }
+
+        Ok(())
+    }
+}
+
+impl Validate for RagQueryRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        check_model(&self.model, served_model)?;
+
+        if self.query.trim().is_empty() {
+            return Err(ApiError::invalid_request("'query' must not be empty").with_param("query"));
+        }
+
+        check_range(self.temperature, "temperature", 0.0, 2.0)?;
+        check_top_p(self.top_p)?;
+        check_positive(self.top_k.map(|k| k as i64), "top_k")?;
+        check_positive(self.max_tokens.map(i64::from), "max_tokens")
+    }
+}
+
+impl Validate for CreateAgentRunRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        check_model(&self.model, served_model)?;
+
+        if self.messages.is_empty() {
+            return Err(
+                ApiError::invalid_request("'messages' must contain at least one message")
+                    .with_param("messages"),
+            );
+        }
+        for (index, message) in self.messages.iter().enumerate() {
+            if !MESSAGE_ROLES.contains(&message.role.as_str()) {
+                return Err(ApiError::invalid_request(format!(
+                    "'{}' is not one of {:?} - 'messages.{}.role'",
+                    message.role, MESSAGE_ROLES, index
+                ))
+                .with_param(format!("messages.{index}.role")));
+            }
+        }
+
+        check_range(self.temperature, "temperature", 0.0, 2.0)?;
+        check_top_p(self.top_p)?;
+        check_positive(self.max_iterations.map(|n| n as i64), "max_iterations")?;
+        if let Some(secs) = self
+            .max_duration_secs
+            .filter(|secs| secs.is_nan() || *secs <= 0.0)
+        {
+            return Err(ApiError::invalid_request(format!(
+                "{secs} is not greater than 0 - 'max_duration_secs'"
+            ))
+            .with_param("max_duration_secs"));
+        }
+        check_positive(self.max_tokens.map(i64::from), "max_tokens")
+    }
+}
+
+impl Validate for CreateAssistantRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        check_model(&self.model, served_model)?;
+        check_range(self.temperature, "temperature", 0.0, 2.0)?;
+        check_top_p(self.top_p)
+    }
+}
+
+impl Validate for ModifyAssistantRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        if let Some(model) = &self.model {
+            check_model(model, served_model)?;
+        }
+        check_range(self.temperature, "temperature", 0.0, 2.0)?;
+        check_top_p(self.top_p)
+    }
+}
+
+impl Validate for CreateThreadRequest {
+    fn validate(&self, _served_model: &str) -> Result<(), ApiError> {
+        for (index, message) in self.messages.iter().enumerate() {
+            check_thread_message(message, &format!("messages.{index}."))?;
+        }
+        Ok(())
+    }
+}
+
+impl Validate for CreateMessageRequest {
+    fn validate(&self, _served_model: &str) -> Result<(), ApiError> {
+        check_thread_message(self, "")
+    }
+}
+
+impl Validate for CreateRunRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        if let Some(model) = &self.model {
+            check_model(model, served_model)?;
+        }
+        check_range(self.temperature, "temperature", 0.0, 2.0)?;
+        check_top_p(self.top_p)?;
+        check_positive(
+            self.max_completion_tokens.map(i64::from),
+            "max_completion_tokens",
+        )
+    }
+}
+
+impl Validate for CreateClassificationRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        check_model(&self.model, served_model)?;
+
+        if self.input.trim().is_empty() {
+            return Err(ApiError::invalid_request("'input' must not be empty").with_param("input"));
+        }
+        if !(2..=MAX_LABELS).contains(&self.labels.len()) {
+            return Err(ApiError::invalid_request(format!(
+                "'labels' must contain between 2 and {MAX_LABELS} labels"
+            ))
+            .with_param("labels"));
+        }
+        for (index, label) in self.labels.iter().enumerate() {
+            if self.labels[..index].contains(label) {
+                return Err(ApiError::invalid_request(format!(
+                    "'{label}' is listed twice - 'labels.{index}'"
+                ))
+                .with_param(format!("labels.{index}")));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Validate for CreateScoreRequest {
+    fn validate(&self, served_model: &str) -> Result<(), ApiError> {
+        check_model(&self.model, served_model)?;
+        check_prompt(self.prompt.as_ref())?;
+
+        if matches!(&self.continuation, Some(continuation) if continuation.is_empty()) {
+            return Err(
+                ApiError::invalid_request("'continuation' must not be empty")
+                    .with_param("continuation"),
+            );
+        }
+        if self.top_logprobs.is_some_and(|top| top > MAX_TOP_LOGPROBS) {
+            return Err(ApiError::invalid_request(format!(
+                "'top_logprobs' must be at most {MAX_TOP_LOGPROBS}"
+            ))
+            .with_param("top_logprobs"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the role and the content parts of a message of the `/v1/responses` input.
+fn check_input_message(message: &ResponseInputMessage, index: usize) -> Result<(), ApiError> {
+    if !MESSAGE_ROLES.contains(&message.role.as_str()) {
+        return Err(ApiError::invalid_request(format!(
+            "'{}' is not one of {:?} - 'input.{}.role'",
+            message.role, MESSAGE_ROLES, index
+        ))
+        .with_param(format!("input.{index}.role")));
+    }
+    if let ResponseMessageContent::Parts(parts) = &message.content {
+        for (part_index, part) in parts.iter().enumerate() {
+            if !["input_text", "output_text"].contains(&part.kind.as_str()) {
+                return Err(ApiError::invalid_request(format!(
+                    "Content parts of type '{}' are not supported - 'input.{}.content.{}.type'",
+                    part.kind, index, part_index
+                ))
+                .with_param(format!("input.{index}.content.{part_index}.type")));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a completion has exactly one prompt, and that token prompts are not empty
+/// and hold no negative ids.
+fn check_prompt(prompt: Option<&Prompt>) -> Result<(), ApiError> {
+    let tokens = match prompt {
+        None => {
+            return Err(
+                ApiError::invalid_request("Missing required parameter: 'prompt'")
+                    .with_param("prompt"),
+            )
+        }
+        Some(Prompt::Single(_)) => return Ok(()),
+        Some(Prompt::ArrayOfStrings(prompts)) if prompts.len() == 1 => return Ok(()),
+        Some(Prompt::ArrayOfTokens(tokens)) => tokens,
+        Some(Prompt::ArrayOfTokenArrays(prompts)) if prompts.len() == 1 => &prompts[0],
+        Some(Prompt::ArrayOfStrings(_) | Prompt::ArrayOfTokenArrays(_)) => {
+            return Err(ApiError::invalid_request(
+                "Only one prompt per request is supported, send the prompts in separate requests",
+            )
+            .with_param("prompt"))
+        }
+    };
+
+    if tokens.is_empty() {
+        return Err(
+            ApiError::invalid_request("'prompt' must contain at least one token")
+                .with_param("prompt"),
+        );
+    }
+    if let Some(index) = tokens.iter().position(|&token| token < 0) {
+        return Err(ApiError::invalid_request(format!(
+            "{} is not a valid token id - 'prompt.{}'",
+            tokens[index], index
+        ))
+        .with_param("prompt"));
+    }
+    Ok(())
+}
+
+/// Checks that the requested model is the served model, by full id or repository name.
+pub fn check_model(model: &str, served_model: &str) -> Result<(), ApiError> {
+    let short_name = served_model.rsplit('/').next().unwrap_or(served_model);
+    if model == served_model || model == short_name {
+        return Ok(());
+    }
+
+    Err(
+        ApiError::invalid_request(format!("The model '{model}' does not exist"))
+            .with_param("model")
+            .with_code("model_not_found"),
+    )
+}
+
+/// Checks the role of a message added to a thread, its parameters starting with `prefix`.
+fn check_thread_message(message: &CreateMessageRequest, prefix: &str) -> Result<(), ApiError> {
+    if !THREAD_MESSAGE_ROLES.contains(&message.role.as_str()) {
+        return Err(ApiError::invalid_request(format!(
+            "'{}' is not one of {:?} - '{prefix}role'",
+            message.role, THREAD_MESSAGE_ROLES
+        ))
+        .with_param(format!("{prefix}role")));
+    }
+    Ok(())
+}
+
+/// Checks that `value` lies within `[min, max]`.
+fn check_range(value: Option<f64>, param: &str, min: f64, max: f64) -> Result<(), ApiError> {
+    match value {
+        Some(v) if !(min..=max).contains(&v) => Err(ApiError::invalid_request(format!(
+            "{v} is not in the range [{min}, {max}] - '{param}'"
+        ))
+        .with_param(param)),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the `repetition_penalty` extension is positive.
+fn check_repetition_penalty(value: Option<f32>) -> Result<(), ApiError> {
+    match value {
+        Some(v) if !(v > 0.0 && v.is_finite()) => Err(ApiError::invalid_request(format!(
+            "{v} is not greater than 0 - 'repetition_penalty'"
+        ))
+        .with_param("repetition_penalty")),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that `top_p` lies within `(0, 1]`.
+fn check_top_p(value: Option<f64>) -> Result<(), ApiError> {
+    match value {
+        Some(v) if !(v > 0.0 && v <= 1.0) => Err(ApiError::invalid_request(format!(
+            "{v} is not in the range (0, 1] - 'top_p'"
+        ))
+        .with_param("top_p")),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the number of choices `n` lies within `[1, MAX_CHOICES]`.
+fn check_choices(n: Option<i64>) -> Result<(), ApiError> {
+    check_positive(n, "n")?;
+    match n {
+        Some(n) if n > MAX_CHOICES => Err(ApiError::invalid_request(format!(
+            "{n} is greater than the maximum of {MAX_CHOICES} - 'n'"
+        ))
+        .with_param("n")),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that `top_k` is positive, or `-1` which disables it like in vLLM.
+fn check_top_k(value: Option<i64>) -> Result<(), ApiError> {
+    match value {
+        Some(v) if v == 0 || v < -1 => Err(ApiError::invalid_request(format!(
+            "{v} is not a positive number or -1 - 'top_k'"
+        ))
+        .with_param("top_k")),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that `best_of` equals the number of choices, the only supported value.
+fn check_best_of(best_of: Option<i64>, n: Option<i64>) -> Result<(), ApiError> {
+    check_positive(best_of, "best_of")?;
+    let n = n.unwrap_or(1);
+    match best_of {
+        Some(best_of) if best_of < n => Err(ApiError::invalid_request(format!(
+            "{best_of} is less than n, {n} - 'best_of'"
+        ))
+        .with_param("best_of")),
+        Some(best_of) if best_of > n => Err(ApiError::invalid_request(
+            "'best_of' greater than 'n' is not supported, request 'n' choices instead",
+        )
+        .with_param("best_of")),
+        _ => Ok(()),
+    }
+}
+
+/// Rejects beam search, which is not supported.
+fn check_beam_search(use_beam_search: Option<bool>) -> Result<(), ApiError> {
+    if use_beam_search == Some(true) {
+        return Err(ApiError::invalid_request(
+            "Beam search is not supported, sample with 'n' instead",
+        )
+        .with_param("use_beam_search"));
+    }
+    Ok(())
+}
+
+/// Checks that `value` is greater than zero.
+fn check_positive(value: Option<i64>, param: &str) -> Result<(), ApiError> {
+    match value {
+        Some(v) if v <= 0 => Err(ApiError::invalid_request(format!(
+            "{v} is less than the minimum of 1 - '{param}'"
+        ))
+        .with_param(param)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    const SERVED_MODEL: &str = "meta-llama/Llama-3.1-8B-Instruct";
+
+    fn completion(payload: serde_json::Value) -> Result<(), ApiError> {
+        let request: CreateCompletionRequest =
+            serde_json::from_value(payload).expect("the payload deserializes");
+        request.validate(SERVED_MODEL)
+    }
+
+    fn assert_invalid(result: Result<(), ApiError>, param: &str) {
+        let err = result.expect_err("the request is rejected");
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(err.body().kind, "invalid_request_error");
+        assert_eq!(err.body().param.as_deref(), Some(param));
+    }
+
+    #[test]
+    fn completion_without_prompt_is_rejected() {
+        assert_invalid(completion(json!({ "model": SERVED_MODEL })), "prompt");
+        assert_invalid(
+            completion(json!({ "model": SERVED_MODEL, "prompt": null })),
+            "prompt",
+        );
+    }
+
+    #[test]
+    fn completion_with_malformed_token_prompt_is_rejected() {
+        assert_invalid(
+            completion(json!({ "model": SERVED_MODEL, "prompt": [[]] })),
+            "prompt",
+        );
+        assert_invalid(
+            completion(json!({ "model": SERVED_MODEL, "prompt": [1, -2, 3] })),
+            "prompt",
+        );
+        assert_invalid(
+            completion(json!({ "model": SERVED_MODEL, "prompt": ["a", "b"] })),
+            "prompt",
+        );
+    }
+
+    #[test]
+    fn completion_with_valid_prompt_is_accepted() {
+        assert!(completion(json!({ "model": SERVED_MODEL, "prompt": "Hello" })).is_ok());
+        assert!(completion(json!({ "model": "Llama-3.1-8B-Instruct", "prompt": [1, 2] })).is_ok());
+    }
+
+    #[test]
+    fn completion_for_another_model_is_rejected() {
+        assert_invalid(
+            completion(json!({ "model": "gpt-4o", "prompt": "Hello" })),
+            "model",
+        );
+    }
+
+    #[test]
+    fn completion_with_vllm_parameters_is_accepted() {
+        let payload = json!({
+            "model": SERVED_MODEL,
+            "prompt": "Hello",
+            "n": 2,
+            "best_of": 2,
+            "top_k": -1,
+            "min_p": 0.05,
+            "stop_token_ids": [128009],
+            "ignore_eos": true,
+            "use_beam_search": false,
+        });
+        assert!(completion(payload).is_ok());
+    }
+
+    #[test]
+    fn completion_with_unsupported_vllm_parameters_is_rejected() {
+        assert_invalid(
+            completion(json!({ "model": SERVED_MODEL, "prompt": "Hello", "best_of": 3 })),
+            "best_of",
+        );
+        assert_invalid(
+            completion(
+                json!({ "model": SERVED_MODEL, "prompt": "Hello", "use_beam_search": true }),
+            ),
+            "use_beam_search",
+        );
+        assert_invalid(
+            completion(json!({ "model": SERVED_MODEL, "prompt": "Hello", "top_k": 0 })),
+            "top_k",
+        );
+    }
+
+    #[test]
+    fn completion_with_out_of_range_choices_is_rejected() {
+        assert_invalid(
+            completion(json!({ "model": SERVED_MODEL, "prompt": "Hello", "n": 0 })),
+            "n",
+        );
+        assert_invalid(
+            completion(json!({ "model": SERVED_MODEL, "prompt": "Hello", "n": MAX_CHOICES + 1 })),
+            "n",
+        );
+    }
+
+    #[test]
+    fn completion_sampling_bounds_are_inclusive_where_documented() {
+        for temperature in [0.0, 2.0] {
+            assert!(completion(
+                json!({ "model": SERVED_MODEL, "prompt": "Hello", "temperature": temperature })
+            )
+            .is_ok());
+        }
+        assert_invalid(
+            completion(json!({ "model": SERVED_MODEL, "prompt": "Hello", "temperature": 2.01 })),
+            "temperature",
+        );
+        assert!(
+            completion(json!({ "model": SERVED_MODEL, "prompt": "Hello", "top_p": 1 })).is_ok()
+        );
+        assert_invalid(
+            completion(json!({ "model": SERVED_MODEL, "prompt": "Hello", "top_p": 0 })),
+            "top_p",
+        );
+    }
+}
@@ -0,0 +1,587 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::core::vector_stores::{VectorStoreError, VectorStores};
+use crate::core::webhooks::{spawn_delivery, WebhookEvent};
+use crate::openai::errors::ApiError;
+use crate::openai::files_service::file_not_found;
+use crate::openai::http_entities::AppState;
+use crate::openai::models::{
+    AttributeFilter, ChunkingStrategy, CreateVectorStoreFileBatchRequest,
+    CreateVectorStoreFileRequest, CreateVectorStoreRequest, CursorPage, DeleteVectorStoreResponse,
+    FileObject, ModifyVectorStoreRequest, VectorStoreExpiration, VectorStoreFileBatchObject,
+    VectorStoreFileObject, VectorStoreListQuery, VectorStoreObject, VectorStoreSearchRequest,
+    VectorStoreSearchResponse,
+};
+use crate::openai::request_json::RequestJson;
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use tracing::info;
+
+/// The statuses a vector store file can be listed by.
+const FILE_STATUSES: [&str; 4] = ["in_progress", "completed", "failed", "cancelled"];
+/// The attribute filter types.
+const FILTER_TYPES: [&str; 8] = ["eq", "ne", "gt", "gte", "lt", "lte", "and", "or"];
+
+impl From<VectorStoreError> for ApiError {
+    fn from(err: VectorStoreError) -> Self {
+        match err {
+            VectorStoreError::NotFound(_)
+            | VectorStoreError::FileNotFound(_)
+            | VectorStoreError::BatchNotFound(_)
+            | VectorStoreError::InvalidId(_) => ApiError::not_found(err.to_string()),
+            VectorStoreError::Io(_)
+            | VectorStoreError::Metadata(_)
+            | VectorStoreError::Embedding(_) => ApiError::internal(err),
+        }
+    }
+}
+
+/// Creates a vector store, adding the `file_ids` files to it.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `request` - The `CreateVectorStoreRequest` with the name, files and expiration.
+///
+/// # Returns
+///
+/// The `VectorStoreObject` wrapped in `Json`, or an `ApiError` if vector stores are
+/// disabled, the request is invalid or a file does not exist.
+pub async fn create_vector_store(
+    State(state): State<AppState>,
+    RequestJson(request): RequestJson<CreateVectorStoreRequest>,
+) -> Result<Json<VectorStoreObject>, ApiError> {
+    let stores = vector_stores(&state)?.clone();
+    check_expiration(request.expires_after.as_ref())?;
+    let chunking = request.chunking_strategy.unwrap_or(ChunkingStrategy::Auto);
+    check_chunking(&chunking)?;
+    let files = read_files(&state, request.file_ids.as_deref().unwrap_or_default())?;
+
+    let store = tokio::task::spawn_blocking(move || {
+        let store = stores.create(
+            request.name,
+            request.metadata.unwrap_or_default(),
+            request.expires_after,
+        )?;
+        if files.is_empty() {
+            return Ok(store);
+        }
+        stores.add_file_batch(&store.id, &files, &chunking, HashMap::new())?;
+        stores.get(&store.id)
+    })
+    .await
+    .map_err(ApiError::internal)??;
+    info!(
+        "Created vector store {} with {} files",
+        store.id, store.file_counts.total
+    );
+
+    Ok(Json(store))
+}
+
+/// Lists the vector stores.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `query` - The `limit`, `order`, `after` and `before` pagination parameters.
+///
+/// # Returns
+///
+/// A page of `VectorStoreObject` wrapped in `Json`, or an `ApiError` if vector stores
+/// are disabled or the pagination parameters are invalid.
+pub async fn list_vector_stores(
+    State(state): State<AppState>,
+    Query(query): Query<VectorStoreListQuery>,
+) -> Result<Json<CursorPage<VectorStoreObject>>, ApiError> {
+    let stores = vector_stores(&state)?.list();
+
+    paginate(stores, |store| &store.id, &query).map(Json)
+}
+
+/// Retrieves a vector store.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `vector_store_id` - The id of the vector store.
+///
+/// # Returns
+///
+/// The `VectorStoreObject` wrapped in `Json`, or an `ApiError` if vector stores are
+/// disabled or the vector store does not exist.
+pub async fn retrieve_vector_store(
+    State(state): State<AppState>,
+    Path(vector_store_id): Path<String>,
+) -> Result<Json<VectorStoreObject>, ApiError> {
+    Ok(Json(vector_stores(&state)?.get(&vector_store_id)?))
+}
+
+/// Changes the name, metadata or expiration of a vector store.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `vector_store_id` - The id of the vector store.
+/// * `request` - The `ModifyVectorStoreRequest` with the fields to change.
+///
+/// # Returns
+///
+/// The updated `VectorStoreObject` wrapped in `Json`, or an `ApiError` if vector stores
+/// are disabled, the request is invalid or the vector store does not exist.
+pub async fn modify_vector_store(
+    State(state): State<AppState>,
+    Path(vector_store_id): Path<String>,
+    RequestJson(request): RequestJson<ModifyVectorStoreRequest>,
+) -> Result<Json<VectorStoreObject>, ApiError> {
+    check_expiration(request.expires_after.as_ref())?;
+    let store = vector_stores(&state)?.modify(
+        &vector_store_id,
+        request.name,
+        request.metadata,
+        request.expires_after,
+    )?;
+
+    Ok(Json(store))
+}
+
+/// Deletes a vector store and its chunks. The files stay in `/v1/files`.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `vector_store_id` - The id of the vector store.
+///
+/// # Returns
+///
+/// The `DeleteVectorStoreResponse` wrapped in `Json`, or an `ApiError` if vector stores
+/// are disabled or the vector store does not exist.
+pub async fn delete_vector_store(
+    State(state): State<AppState>,
+    Path(vector_store_id): Path<String>,
+) -> Result<Json<DeleteVectorStoreResponse>, ApiError> {
+    vector_stores(&state)?.delete(&vector_store_id)?;
+    info!("Deleted vector store {}", vector_store_id);
+
+    Ok(Json(DeleteVectorStoreResponse {
+        id: vector_store_id,
+        object: "vector_store.deleted".to_string(),
+        deleted: true,
+    }))
+}
+
+/// Adds a file of `/v1/files` to a vector store.
+///
+/// The file is chunked, embedded and indexed before the response is returned, so its
+/// status is `completed`, or `failed` if it is not a text file.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `vector_store_id` - The id of the vector store.
+/// * `request` - The `CreateVectorStoreFileRequest` with the file id and chunking strategy.
+///
+/// # Returns
+///
+/// The `VectorStoreFileObject` wrapped in `Json`, or an `ApiError` if vector stores are
+/// disabled, the request is invalid or the vector store or the file does not exist.
+pub async fn create_vector_store_file(
+    State(state): State<AppState>,
+    Path(vector_store_id): Path<String>,
+    RequestJson(request): RequestJson<CreateVectorStoreFileRequest>,
+) -> Result<Json<VectorStoreFileObject>, ApiError> {
+    let stores = vector_stores(&state)?.clone();
+    let chunking = request.chunking_strategy.unwrap_or(ChunkingStrategy::Auto);
+    check_chunking(&chunking)?;
+    let (file, content) = read_files(&state, &[request.file_id])?
+        .pop()
+        .ok_or_else(|| ApiError::internal("File was read but not returned"))?;
+
+    let added = tokio::task::spawn_blocking(move || {
+        stores.add_file(
+            &vector_store_id,
+            &file,
+            &content,
+            &chunking,
+            request.attributes.unwrap_or_default(),
+        )
+    })
+    .await
+    .map_err(ApiError::internal)??;
+    info!(
+        "Added file {} to vector store {}: {}",
+        added.id, added.vector_store_id, added.status
+    );
+
+    Ok(Json(added))
+}
+
+/// Lists the files of a vector store.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `vector_store_id` - The id of the vector store.
+/// * `query` - The pagination parameters and the optional status `filter`.
+///
+/// # Returns
+///
+/// A page of `VectorStoreFileObject` wrapped in `Json`, or an `ApiError` if vector
+/// stores are disabled, the parameters are invalid or the vector store does not exist.
+pub async fn list_vector_store_files(
+    State(state): State<AppState>,
+    Path(vector_store_id): Path<String>,
+    Query(query): Query<VectorStoreListQuery>,
+) -> Result<Json<CursorPage<VectorStoreFileObject>>, ApiError> {
+    if let Some(filter) = &query.filter {
+        if !FILE_STATUSES.contains(&filter.as_str()) {
+            return Err(ApiError::invalid_request(format!(
+                "'{filter}' is not one of {FILE_STATUSES:?} - 'filter'"
+            ))
+            .with_param("filter"));
+        }
+    }
+
+    let files = vector_stores(&state)?
+        .list_files(&vector_store_id)?
+        .into_iter()
+        .filter(|file| query.filter.as_ref().map_or(true, |s| *s == file.status))
+        .collect();
+
+    paginate(files, |file| &file.id, &query).map(Json)
+}
+
+/// Retrieves a file of a vector store.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `path` - The ids of the vector store and of the file.
+///
+/// # Returns
+///
+/// The `VectorStoreFileObject` wrapped in `Json`, or an `ApiError` if vector stores are
+/// disabled or the vector store or the file does not exist.
+pub async fn retrieve_vector_store_file(
+    State(state): State<AppState>,
+    Path((vector_store_id, file_id)): Path<(String, String)>,
+) -> Result<Json<VectorStoreFileObject>, ApiError> {
+    Ok(Json(
+        vector_stores(&state)?.get_file(&vector_store_id, &file_id)?,
+    ))
+}
+
+/// Removes a file from a vector store. The file stays in `/v1/files`.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `path` - The ids of the vector store and of the file.
+///
+/// # Returns
+///
+/// The `DeleteVectorStoreResponse` wrapped in `Json`, or an `ApiError` if vector stores
+/// are disabled or the vector store or the file does not exist.
+pub async fn delete_vector_store_file(
+    State(state): State<AppState>,
+    Path((vector_store_id, file_id)): Path<(String, String)>,
+) -> Result<Json<DeleteVectorStoreResponse>, ApiError> {
+    vector_stores(&state)?.delete_file(&vector_store_id, &file_id)?;
+
+    Ok(Json(DeleteVectorStoreResponse {
+        id: file_id,
+        object: "vector_store.file.deleted".to_string(),
+        deleted: true,
+    }))
+}
+
+/// Adds several files of `/v1/files` to a vector store.
+///
+/// Once the batch is ingested, a `vector_store.file_batch.completed` event is sent to the
+/// registered webhooks, or `vector_store.file_batch.failed` if no file could be ingested.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `vector_store_id` - The id of the vector store.
+/// * `request` - The `CreateVectorStoreFileBatchRequest` with the file ids.
+///
+/// # Returns
+///
+/// The completed `VectorStoreFileBatchObject` wrapped in `Json`, or an `ApiError` if
+/// vector stores are disabled, the request is invalid or a file does not exist.
+pub async fn create_vector_store_file_batch(
+    State(state): State<AppState>,
+    Path(vector_store_id): Path<String>,
+    RequestJson(request): RequestJson<CreateVectorStoreFileBatchRequest>,
+) -> Result<Json<VectorStoreFileBatchObject>, ApiError> {
+    let stores = vector_stores(&state)?.clone();
+    let chunking = request.chunking_strategy.unwrap_or(ChunkingStrategy::Auto);
+    check_chunking(&chunking)?;
+    if request.file_ids.is_empty() {
+        return Err(
+            ApiError::invalid_request("'file_ids' must contain at least one file id")
+                .with_param("file_ids"),
+        );
+    }
+    let files = read_files(&state, &request.file_ids)?;
+
+    let batch = tokio::task::spawn_blocking(move || {
+        stores.add_file_batch(
+            &vector_store_id,
+            &files,
+            &chunking,
+            request.attributes.unwrap_or_default(),
+        )
+    })
+    .await
+    .map_err(ApiError::internal)??;
+
+    let counts = &batch.file_counts;
+    let kind = if counts.total > 0 && counts.failed == counts.total {
+        "vector_store.file_batch.failed"
+    } else {
+        "vector_store.file_batch.completed"
+    };
+    spawn_delivery(
+        state.settings.current().webhooks.clone(),
+        WebhookEvent::new(kind, &batch),
+        None,
+    );
+
+    Ok(Json(batch))
+}
+
+/// Retrieves a file batch of a vector store.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `path` - The ids of the vector store and of the batch.
+///
+/// # Returns
+///
+/// The `VectorStoreFileBatchObject` wrapped in `Json`, or an `ApiError` if vector stores
+/// are disabled or the vector store or the batch does not exist.
+pub async fn retrieve_vector_store_file_batch(
+    State(state): State<AppState>,
+    Path((vector_store_id, batch_id)): Path<(String, String)>,
+) -> Result<Json<VectorStoreFileBatchObject>, ApiError> {
+    Ok(Json(
+        vector_stores(&state)?.get_file_batch(&vector_store_id, &batch_id)?,
+    ))
+}
+
+/// Searches a vector store for the chunks most similar to a query.
+///
+/// # Arguments
+///
+/// * `state` - The application state.
+/// * `vector_store_id` - The id of the vector store.
+/// * `request` - The `VectorStoreSearchRequest` with the query, filters and ranking options.
+///
+/// # Returns
+///
+/// The `VectorStoreSearchResponse` wrapped in `Json`, or an `ApiError` if vector stores
+/// are disabled, the request is invalid or the vector store does not exist.
+pub async fn search_vector_store(
+    State(state): State<AppState>,
+    Path(vector_store_id): Path<String>,
+    RequestJson(request): RequestJson<VectorStoreSearchRequest>,
+) -> Result<Json<VectorStoreSearchResponse>, ApiError> {
+    let stores = vector_stores(&state)?.clone();
+
+    let Some(queries) = request.query.into_texts() else {
+        return Err(
+            ApiError::invalid_request("'query' must be text, images are not searchable")
+                .with_param("query"),
+        );
+    };
+    if queries.is_empty() || queries.iter().any(|q| q.trim().is_empty()) {
+        return Err(ApiError::invalid_request("'query' must not be empty").with_param("query"));
+    }
+    let max_results = request.max_num_results.unwrap_or(10);
+    if !(1..=50).contains(&max_results) {
+        return Err(ApiError::invalid_request(format!(
+            "{max_results} is not in the range [1, 50] - 'max_num_results'"
+        ))
+        .with_param("max_num_results"));
+    }
+    if let Some(filter) = &request.filters {
+        check_filter(filter, "filters")?;
+    }
+    let score_threshold = request
+        .ranking_options
+        .and_then(|options| options.score_threshold)
+        .unwrap_or(0.0);
+
+    let search_query = queries.clone();
+    let data = tokio::task::spawn_blocking(move || {
+        stores.search(
+            &vector_store_id,
+            &queries,
+            max_results,
+            score_threshold,
+            request.filters.as_ref(),
+        )
+    })
+    .await
+    .map_err(ApiError::internal)??;
+
+    Ok(Json(VectorStoreSearchResponse {
+        object: "vector_store.search_results.page".to_string(),
+        search_query,
+        data,
+        has_more: false,
+        next_page: None,
+    }))
+}
+
+/// Returns the vector stores, or an error if they are disabled.
+fn vector_stores(state: &AppState) -> Result<&Arc<VectorStores>, ApiError> {
+    state.vector_stores.as_ref().ok_or_else(|| {
+        ApiError::not_found("Vector stores are disabled, set `embeddings.model` to enable them")
+    })
+}
+
+/// Reads the metadata and content of files of `/v1/files`.
+fn read_files(
+    state: &AppState,
+    file_ids: &[String],
+) -> Result<Vec<(FileObject, Vec<u8>)>, ApiError> {
+    file_ids
+        .iter()
+        .map(|file_id| {
+            let file = state.files.get(file_id)?;
+            let content = state.files.content(file_id)?;
+            match (file, content) {
+                (Some(file), Some(content)) => Ok((file, content)),
+                _ => Err(file_not_found(file_id)),
+            }
+        })
+        .collect()
+}
+
+/// Checks that a static chunking strategy is within the limits of the OpenAI API.
+fn check_chunking(chunking: &ChunkingStrategy) -> Result<(), ApiError> {
+    let ChunkingStrategy::Static { r#static } = chunking else {
+        return Ok(());
+    };
+
+    if !(100..=4096).contains(&r#static.max_chunk_size_tokens) {
+        return Err(ApiError::invalid_request(format!(
+            "{} is not in the range [100, 4096] - 'chunking_strategy.static.max_chunk_size_tokens'",
+            r#static.max_chunk_size_tokens
+        ))
+        .with_param("chunking_strategy.static.max_chunk_size_tokens"));
+    }
+    if r#static.chunk_overlap_tokens > r#static.max_chunk_size_tokens / 2 {
+        return Err(ApiError::invalid_request(
+            "'chunk_overlap_tokens' must not exceed half of 'max_chunk_size_tokens'",
+        )
+        .with_param("chunking_strategy.static.chunk_overlap_tokens"));
+    }
+
+    Ok(())
+}
+
+/// Checks the anchor and the number of days of an expiration.
+fn check_expiration(expiration: Option<&VectorStoreExpiration>) -> Result<(), ApiError> {
+    let Some(expiration) = expiration else {
+        return Ok(());
+    };
+
+    if expiration.anchor != "last_active_at" {
+        return Err(ApiError::invalid_request(format!(
+            "'{}' is not one of [\"last_active_at\"] - 'expires_after.anchor'",
+            expiration.anchor
+        ))
+        .with_param("expires_after.anchor"));
+    }
+    if !(1..=365).contains(&expiration.days) {
+        return Err(ApiError::invalid_request(format!(
+            "{} is not in the range [1, 365] - 'expires_after.days'",
+            expiration.days
+        ))
+        .with_param("expires_after.days"));
+    }
+
+    Ok(())
+}
+
+/// Checks the types of an attribute filter and of its nested filters.
+fn check_filter(filter: &AttributeFilter, param: &str) -> Result<(), ApiError> {
+    if !FILTER_TYPES.contains(&filter.kind.as_str()) {
+        return Err(ApiError::invalid_request(format!(
+            "'{}' is not one of {FILTER_TYPES:?} - '{param}.type'",
+            filter.kind
+        ))
+        .with_param(format!("{param}.type")));
+    }
+
+    match filter.kind.as_str() {
+        "and" | "or" => {
+            for (index, nested) in filter.filters.iter().flatten().enumerate() {
+                check_filter(nested, &format!("{param}.filters.{index}"))?;
+            }
+        }
+        _ if filter.key.is_none() || filter.value.is_none() => {
+            return Err(ApiError::invalid_request(format!(
+                "Comparison filters need a 'key' and a 'value' - '{param}'"
+            ))
+            .with_param(param));
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Returns a page of a list ordered by creation, using the OpenAI cursor pagination.
+pub(crate) fn paginate<T>(
+    mut items: Vec<T>,
+    id: impl Fn(&T) -> &String,
+    query: &VectorStoreListQuery,
+) -> Result<CursorPage<T>, ApiError> {
+    let limit = query.limit.unwrap_or(20);
+    if !(1..=100).contains(&limit) {
+        return Err(ApiError::invalid_request(format!(
+            "{limit} is not in the range [1, 100] - 'limit'"
+        ))
+        .with_param("limit"));
+    }
+    match query.order.as_deref() {
+        None | Some("desc") => items.reverse(),
+        Some("asc") => {}
+        Some(order) => {
+            return Err(ApiError::invalid_request(format!(
+                "'{order}' is not one of [\"asc\", \"desc\"] - 'order'"
+            ))
+            .with_param("order"))
+        }
+    }
+
+    if let Some(before) = &query.before {
+        if let Some(position) = items.iter().position(|item| id(item) == before) {
+            items.truncate(position);
+            let skip = items.len().saturating_sub(limit);
+            items.drain(..skip);
+        }
+    }
+    if let Some(after) = &query.after {
+        if let Some(position) = items.iter().position(|item| id(item) == after) {
+            items.drain(..=position);
+        }
+    }
+    let has_more = items.len() > limit;
+    items.truncate(limit);
+
+    Ok(CursorPage {
+        object: "list".to_string(),
+        first_id: items.first().map(|item| id(item).clone()),
+        last_id: items.last().map(|item| id(item).clone()),
+        data: items,
+        has_more,
+    })
+}
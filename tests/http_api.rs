@@ -0,0 +1,1382 @@
+//! End-to-end tests of the HTTP API, served by the server binary from a tiny
+//! Llama-architecture checkpoint with random weights generated in a temporary directory.
+//!
+//! Every route is exercised. The subsystems needing models from the Hugging Face Hub,
+//! embeddings, RAG, the vector stores and audio, are only checked to report that they
+//! are disabled.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::routing::get;
+use axum::Router;
+use candle_core::{DType, Device, Tensor};
+use serde_json::{json, Value};
+use synap_forge_llm::config::{SplitTargetSettings, UpstreamSettings};
+use synap_forge_llm::openai::proxy::{
+    list_split_usage, list_upstream_models, proxy_request, spawn_health_checks, ProxyState,
+    Upstream,
+};
+use synap_forge_llm::openai::traffic_split::TrafficSplit;
+
+const MODEL: &str = "tiny-llama";
+const HIDDEN_SIZE: usize = 16;
+const INTERMEDIATE_SIZE: usize = 32;
+const HEADS: usize = 2;
+const KV_HEADS: usize = 1;
+const LAYERS: usize = 2;
+const WORDS: [&str; 16] = [
+    "hello",
+    "world",
+    "the",
+    "a",
+    "cat",
+    "dog",
+    "sat",
+    "on",
+    "mat",
+    "user",
+    "assistant",
+    "system",
+    "is",
+    "it",
+    ":",
+    ".",
+];
+
+/// The server binary serving the fixture model, killed when dropped.
+struct Server {
+    child: Child,
+    url: String,
+    directory: PathBuf,
+}
+
+impl Server {
+    /// Writes the fixture model and starts the server on a free loopback port.
+    fn start(name: &str) -> Self {
+        Self::start_with(name, json!({}))
+    }
+
+    /// Starts the server like [`Server::start`], with some sections of the configuration
+    /// replaced.
+    fn start_with(name: &str, overrides: Value) -> Self {
+        let directory = test_directory(name);
+        let _ = std::fs::remove_dir_all(&directory);
+        let model_dir = directory.join("model");
+        std::fs::create_dir_all(&model_dir).unwrap();
+        write_model(&model_dir);
+
+        let mut config = json!({
+            "model": { "id": MODEL, "path": model_dir, "prefetch_threads": 0 },
+            "models": { MODEL: { "defaults": { "max_tokens": 8 } } },
+            "files": { "directory": directory.join("files") },
+        });
+        if let (Some(config), Value::Object(overrides)) = (config.as_object_mut(), overrides) {
+            config.extend(overrides);
+        }
+        let config_path = directory.join("config.json");
+        std::fs::write(&config_path, config.to_string()).unwrap();
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_synap-forge-llm"))
+            .arg(format!("--worker-port={port}"))
+            .env("SYNAP_CONFIG", &config_path)
+            .env_remove("HF_TOKEN")
+            .env("RUST_LOG", "warn")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("the server binary starts");
+
+        let server = Self {
+            child,
+            url: format!("http://127.0.0.1:{port}"),
+            directory,
+        };
+        server.wait_until_ready();
+        server
+    }
+
+    fn wait_until_ready(&self) {
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(60) {
+            if ureq::get(&self.url("/v1/health")).call().is_ok() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("the server did not become ready");
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.url)
+    }
+
+    /// Sends a request and returns its status and JSON body.
+    fn json(&self, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+        self.json_as(None, method, path, body)
+    }
+
+    /// Sends a request with an API key, if any, and returns its status and JSON body.
+    fn json_as(
+        &self,
+        key: Option<&str>,
+        method: &str,
+        path: &str,
+        body: Option<Value>,
+    ) -> (u16, Value) {
+        let mut request = ureq::request(method, &self.url(path));
+        if let Some(key) = key {
+            request = request.set("Authorization", &format!("Bearer {key}"));
+        }
+        let response = match body {
+            Some(body) => request.send_json(body),
+            None => request.call(),
+        };
+        let response = match response {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(err) => panic!("{method} {path} failed: {err}"),
+        };
+        let status = response.status();
+        (status, response.into_json().expect("the body is JSON"))
+    }
+
+    /// Sends a multipart upload of a single file field and returns its status and JSON
+    /// body.
+    fn upload(&self, path: &str, fields: &[(&str, &str)], file: (&str, &str)) -> (u16, Value) {
+        let boundary = "synap-forge-llm-boundary";
+        let mut body = String::new();
+        for (name, value) in fields {
+            body.push_str(&format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            ));
+        }
+        let (filename, content) = file;
+        body.push_str(&format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n{content}\r\n--{boundary}--\r\n"
+        ));
+        let response = ureq::post(&self.url(path))
+            .set(
+                "Content-Type",
+                &format!("multipart/form-data; boundary={boundary}"),
+            )
+            .send_string(&body);
+        let response = match response {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(err) => panic!("POST {path} failed: {err}"),
+        };
+        let status = response.status();
+        (status, response.into_json().expect("the body is JSON"))
+    }
+
+    /// Opens a WebSocket, returning the status line of the handshake and the connection.
+    fn websocket(&self, path: &str) -> (String, BufReader<TcpStream>) {
+        let stream = TcpStream::connect(self.url.trim_start_matches("http://")).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(60)))
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+        write!(
+            reader.get_mut(),
+            "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n\
+             Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+        )
+        .unwrap();
+
+        let mut status = String::new();
+        reader.read_line(&mut status).unwrap();
+        // The headers end with an empty line
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).unwrap() <= 2 {
+                break;
+            }
+        }
+        (status.trim_end().to_string(), reader)
+    }
+
+    /// Sends a streamed request and returns the data of its server-sent events.
+    fn events(&self, path: &str, body: Value) -> Vec<String> {
+        let response = ureq::post(&self.url(path))
+            .send_json(body)
+            .expect("the stream starts");
+        assert_eq!(response.header("content-type"), Some("text/event-stream"));
+
+        response
+            .into_string()
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.directory);
+    }
+}
+
+/// Writes the configuration, tokenizer and random weights of a tiny Llama model.
+fn write_model(directory: &Path) {
+    let mut vocab: Vec<String> = [
+        "[UNK]",
+        "<|begin_of_text|>",
+        "<|eot_id|>",
+        "<|end_of_text|>",
+    ]
+    .iter()
+    .chain(WORDS.iter())
+    .map(|word| word.to_string())
+    .collect();
+    while vocab.len() < 32 {
+        vocab.push(format!("w{}", vocab.len()));
+    }
+    let vocab_size = vocab.len();
+
+    let config = json!({
+        "architectures": ["LlamaForCausalLM"],
+        "hidden_size": HIDDEN_SIZE,
+        "intermediate_size": INTERMEDIATE_SIZE,
+        "vocab_size": vocab_size,
+        "num_hidden_layers": LAYERS,
+        "num_attention_heads": HEADS,
+        "num_key_value_heads": KV_HEADS,
+        "rms_norm_eps": 1e-5,
+        "rope_theta": 10000.0,
+        "bos_token_id": 1,
+        "eos_token_id": 3,
+        "max_position_embeddings": 256,
+        "tie_word_embeddings": false,
+    });
+    std::fs::write(directory.join("config.json"), config.to_string()).unwrap();
+
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": { "type": "Whitespace" },
+        "post_processor": null,
+        "decoder": null,
+        "model": {
+            "type": "WordLevel",
+            "vocab": vocab
+                .iter()
+                .enumerate()
+                .map(|(id, word)| (word.clone(), id))
+                .collect::<HashMap<_, _>>(),
+            "unk_token": "[UNK]",
+        },
+    });
+    std::fs::write(directory.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+
+    let head_dim = HIDDEN_SIZE / HEADS;
+    let mut shapes = vec![
+        (
+            "model.embed_tokens.weight".to_string(),
+            (vocab_size, HIDDEN_SIZE),
+        ),
+        ("lm_head.weight".to_string(), (vocab_size, HIDDEN_SIZE)),
+    ];
+    for layer in 0..LAYERS {
+        let prefix = format!("model.layers.{layer}");
+        shapes.extend([
+            (
+                format!("{prefix}.self_attn.q_proj.weight"),
+                (HIDDEN_SIZE, HIDDEN_SIZE),
+            ),
+            (
+                format!("{prefix}.self_attn.k_proj.weight"),
+                (KV_HEADS * head_dim, HIDDEN_SIZE),
+            ),
+            (
+                format!("{prefix}.self_attn.v_proj.weight"),
+                (KV_HEADS * head_dim, HIDDEN_SIZE),
+            ),
+            (
+                format!("{prefix}.self_attn.o_proj.weight"),
+                (HIDDEN_SIZE, HIDDEN_SIZE),
+            ),
+            (
+                format!("{prefix}.mlp.gate_proj.weight"),
+                (INTERMEDIATE_SIZE, HIDDEN_SIZE),
+            ),
+            (
+                format!("{prefix}.mlp.up_proj.weight"),
+                (INTERMEDIATE_SIZE, HIDDEN_SIZE),
+            ),
+            (
+                format!("{prefix}.mlp.down_proj.weight"),
+                (HIDDEN_SIZE, INTERMEDIATE_SIZE),
+            ),
+        ]);
+    }
+
+    let mut tensors: HashMap<String, Tensor> = shapes
+        .into_iter()
+        .map(|(name, shape)| {
+            let tensor = Tensor::randn(0f32, 0.5, shape, &Device::Cpu).unwrap();
+            (name, tensor)
+        })
+        .collect();
+    let mut norms = vec!["model.norm.weight".to_string()];
+    for layer in 0..LAYERS {
+        norms.push(format!("model.layers.{layer}.input_layernorm.weight"));
+        norms.push(format!(
+            "model.layers.{layer}.post_attention_layernorm.weight"
+        ));
+    }
+    for name in norms {
+        let ones = Tensor::ones(HIDDEN_SIZE, DType::F32, &Device::Cpu).unwrap();
+        tensors.insert(name, ones);
+    }
+    candle_core::safetensors::save(&tensors, directory.join("model.safetensors")).unwrap();
+}
+
+/// Sends a JSON event in a masked text frame, as WebSocket clients do.
+fn send_frame(stream: &mut TcpStream, event: Value) {
+    let payload = event.to_string().into_bytes();
+    let mut frame = vec![0x81];
+    match payload.len() {
+        len if len < 126 => frame.push(0x80 | len as u8),
+        len => {
+            frame.push(0x80 | 126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+    }
+    // A zero mask leaves the payload as it is
+    frame.extend([0; 4]);
+    frame.extend(payload);
+    stream.write_all(&frame).unwrap();
+}
+
+/// Reads a JSON event from an unmasked text frame of the server.
+fn read_frame(reader: &mut impl Read) -> Value {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header).unwrap();
+    assert_eq!(header[0], 0x81, "a final text frame");
+    let len = match header[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len).unwrap();
+            u16::from_be_bytes(len) as usize
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len).unwrap();
+            u64::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).unwrap();
+    serde_json::from_slice(&payload).unwrap()
+}
+
+/// The temporary directory of the server of a test.
+fn test_directory(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("synap-forge-llm-{}-{name}", std::process::id()))
+}
+
+fn assert_usage(body: &Value) {
+    let usage = &body["usage"];
+    let prompt = usage["prompt_tokens"].as_u64().expect("prompt tokens");
+    let completion = usage["completion_tokens"]
+        .as_u64()
+        .expect("completion tokens");
+    assert!(prompt > 0);
+    assert!(completion <= 8);
+    assert_eq!(usage["total_tokens"].as_u64(), Some(prompt + completion));
+}
+
+#[test]
+fn health_and_models() {
+    let server = Server::start("models");
+
+    let (status, health) = server.json("GET", "/v1/health", None);
+    assert_eq!(status, 200);
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["readiness"], "ready");
+    assert_eq!(health["model"], MODEL);
+    assert_eq!(health["revision"], "local");
+
+    let (status, models) = server.json("GET", "/v1/models", None);
+    assert_eq!(status, 200);
+    assert_eq!(models["data"][0]["id"], MODEL);
+
+    let (status, model) = server.json("GET", &format!("/v1/models/{MODEL}"), None);
+    assert_eq!(status, 200);
+    assert_eq!(model["id"], MODEL);
+
+    let (status, error) = server.json("GET", "/v1/models/unknown", None);
+    assert_eq!(status, 404);
+    assert_eq!(error["error"]["code"], "model_not_found");
+
+    let (status, error) = server.json("DELETE", &format!("/v1/models/{MODEL}"), None);
+    assert_eq!(status, 400);
+    assert_eq!(error["error"]["code"], "model_not_deletable");
+
+    let response = ureq::get(&server.url("/metrics"))
+        .call()
+        .expect("the metrics are served");
+    assert!(response
+        .header("content-type")
+        .is_some_and(|kind| kind.starts_with("text/plain")));
+    let metrics = response.into_string().unwrap();
+    assert!(metrics.contains("# TYPE synap_forge_errors_total counter"));
+    assert!(metrics.contains("synap_forge_generation_step_retries_total "));
+}
+
+#[test]
+fn completions() {
+    let server = Server::start("completions");
+
+    let (status, body) = server.json(
+        "POST",
+        "/v1/completions",
+        Some(json!({ "model": MODEL, "prompt": "the cat sat on", "max_tokens": 4 })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(body["object"], "text_completion");
+    assert!(body["choices"][0]["text"].is_string());
+
+    let (status, body) = server.json(
+        "POST",
+        "/v1/completions",
+        Some(json!({ "model": MODEL, "prompt": [8, 9, 10], "n": 2, "temperature": 1.0 })),
+    );
+    assert_eq!(status, 200);
+    let choices = body["choices"].as_array().unwrap();
+    assert_eq!(choices.len(), 2);
+    assert_eq!(
+        (choices[0]["index"].as_u64(), choices[1]["index"].as_u64()),
+        (Some(0), Some(1))
+    );
+
+    let (status, error) = server.json(
+        "POST",
+        "/v1/completions",
+        Some(json!({ "model": MODEL, "prompt": [1000] })),
+    );
+    assert_eq!(status, 400);
+    assert_eq!(error["error"]["param"], "prompt");
+
+    let events = server.events(
+        "/v1/completions",
+        json!({
+            "model": MODEL,
+            "prompt": "hello world",
+            "stream": true,
+            "stream_options": { "include_usage": true },
+        }),
+    );
+    assert_eq!(events.last().map(String::as_str), Some("[DONE]"));
+    let usage: Value = serde_json::from_str(&events[events.len() - 2]).unwrap();
+    assert_eq!(usage["choices"], json!([]));
+    assert_usage(&usage);
+}
+
+#[test]
+fn chat_completions() {
+    let server = Server::start("chat");
+    let messages = json!([
+        { "role": "system", "content": "it is a cat" },
+        { "role": "user", "content": "hello world" },
+    ]);
+
+    let (status, body) = server.json(
+        "POST",
+        "/v1/chat/completions",
+        Some(json!({ "model": MODEL, "messages": messages, "max_tokens": 4 })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(body["object"], "chat.completion");
+    assert_eq!(body["choices"][0]["message"]["role"], "assistant");
+    assert!(body["choices"][0]["message"]["content"].is_string());
+
+    let events = server.events(
+        "/v1/chat/completions",
+        json!({ "model": MODEL, "messages": messages, "stream": true }),
+    );
+    assert_eq!(events.last().map(String::as_str), Some("[DONE]"));
+    let chunks: Vec<Value> = events[..events.len() - 1]
+        .iter()
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect();
+    assert!(chunks
+        .iter()
+        .all(|chunk| chunk["object"] == "chat.completion.chunk"));
+    assert!(chunks
+        .last()
+        .is_some_and(|chunk| chunk["choices"][0]["finish_reason"].is_string()));
+
+    let (status, error) = server.json(
+        "POST",
+        "/v1/chat/completions",
+        Some(json!({ "model": "another-model", "messages": messages })),
+    );
+    assert_eq!(status, 400);
+    assert_eq!(error["error"]["param"], "model");
+    assert_eq!(error["error"]["code"], "model_not_found");
+}
+
+#[test]
+fn responses_and_scores() {
+    let server = Server::start("responses");
+
+    let (status, body) = server.json(
+        "POST",
+        "/v1/responses",
+        Some(json!({ "model": MODEL, "input": "hello world", "max_output_tokens": 4 })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(body["object"], "response");
+
+    let (status, body) = server.json(
+        "POST",
+        "/v1/score",
+        Some(json!({ "model": MODEL, "prompt": "the cat", "continuation": "sat on the mat" })),
+    );
+    assert_eq!(status, 200);
+    assert!(body["total_logprob"]
+        .as_f64()
+        .is_some_and(|logprob| logprob <= 0.0));
+    assert!(body["scored_tokens"]
+        .as_u64()
+        .is_some_and(|tokens| tokens > 0));
+}
+
+#[test]
+fn disabled_subsystems_are_reported() {
+    let server = Server::start("disabled");
+
+    let (status, error) = server.json(
+        "POST",
+        "/v1/embeddings",
+        Some(json!({ "model": MODEL, "input": "hello" })),
+    );
+    assert_eq!(status, 404);
+    assert_eq!(error["error"]["code"], "model_not_found");
+
+    let (status, files) = server.json("GET", "/v1/files", None);
+    assert_eq!(status, 200);
+    assert_eq!(files["data"], json!([]));
+
+    // RAG and the vector stores need an embedding model, audio its models from the Hub
+    let store = "/v1/vector_stores/vs_1";
+    let routes = [
+        (
+            "POST",
+            "/v1/rag/documents".to_string(),
+            json!({ "text": "the cat" }),
+        ),
+        ("GET", "/v1/rag/documents".to_string(), Value::Null),
+        ("DELETE", "/v1/rag/documents/doc-1".to_string(), Value::Null),
+        (
+            "POST",
+            "/v1/rag/query".to_string(),
+            json!({ "model": MODEL, "query": "the cat" }),
+        ),
+        ("POST", "/v1/vector_stores".to_string(), json!({})),
+        ("GET", "/v1/vector_stores".to_string(), Value::Null),
+        ("GET", store.to_string(), Value::Null),
+        ("POST", store.to_string(), json!({ "name": "cats" })),
+        ("DELETE", store.to_string(), Value::Null),
+        (
+            "POST",
+            format!("{store}/files"),
+            json!({ "file_id": "file-1" }),
+        ),
+        ("GET", format!("{store}/files"), Value::Null),
+        ("GET", format!("{store}/files/file-1"), Value::Null),
+        ("DELETE", format!("{store}/files/file-1"), Value::Null),
+        (
+            "POST",
+            format!("{store}/file_batches"),
+            json!({ "file_ids": ["file-1"] }),
+        ),
+        ("GET", format!("{store}/file_batches/vsfb_1"), Value::Null),
+        ("POST", format!("{store}/search"), json!({ "query": "cat" })),
+        (
+            "POST",
+            "/v1/audio/speech".to_string(),
+            json!({ "model": "tts-1", "input": "hello", "voice": "alloy" }),
+        ),
+        (
+            "POST",
+            "/v1/agents/run".to_string(),
+            json!({ "model": MODEL, "messages": [{ "role": "user", "content": "hello" }] }),
+        ),
+    ];
+    for (method, path, body) in routes {
+        let (status, error) = server.json(method, &path, (!body.is_null()).then_some(body));
+        assert_eq!(status, 404, "{method} {path}");
+        assert!(error["error"]["message"].is_string(), "{method} {path}");
+    }
+
+    let (status, error) = server.upload(
+        "/v1/audio/transcriptions",
+        &[("model", "whisper-1")],
+        ("speech.wav", "RIFF"),
+    );
+    assert_eq!(status, 404);
+    assert_eq!(error["error"]["code"], "model_not_found");
+}
+
+#[test]
+fn access_log_hashes_keys_and_bodies() {
+    let path = test_directory("access-log").join("logs/access.log");
+    let server = Server::start_with(
+        "access-log",
+        json!({ "access_log": { "path": path, "content": "hash" } }),
+    );
+
+    let response = ureq::post(&server.url("/v1/completions"))
+        .set("Authorization", "Bearer sk-secret-key")
+        .send_json(json!({ "model": MODEL, "prompt": "the secret cat", "max_tokens": 2 }))
+        .expect("the completion succeeds");
+    assert_eq!(response.status(), 200);
+
+    // The lines are written by a background thread
+    let started = Instant::now();
+    let line = loop {
+        let log = std::fs::read_to_string(&path).unwrap_or_default();
+        if let Some(line) = log.lines().find(|line| line.contains("/v1/completions")) {
+            break line.to_string();
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "nothing logged"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert!(!line.contains("sk-secret-key"));
+    assert!(!line.contains("secret cat"));
+
+    let entry: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(entry["method"], "POST");
+    assert_eq!(entry["status"], 200);
+    assert!(entry["key"]
+        .as_str()
+        .is_some_and(|key| key.starts_with("sha256:")));
+    assert!(entry["body"]
+        .as_str()
+        .is_some_and(|body| body.starts_with("sha256:")));
+}
+
+#[test]
+fn capped_max_tokens_are_reported() {
+    let server = Server::start_with(
+        "capped",
+        json!({
+            "models": { MODEL: { "defaults": { "max_tokens": 8 }, "limits": { "max_tokens": 3 } } },
+        }),
+    );
+    let request = |max_tokens: u32, stream: bool| {
+        ureq::post(&server.url("/v1/completions"))
+            .send_json(json!({
+                "model": MODEL,
+                "prompt": "the cat",
+                "max_tokens": max_tokens,
+                "ignore_eos": true,
+                "stream": stream,
+            }))
+            .expect("the completion succeeds")
+    };
+
+    let response = request(50, false);
+    assert_eq!(response.header("x-max-tokens-applied"), Some("3"));
+    let body: Value = response.into_json().unwrap();
+    assert_eq!(body["max_tokens_applied"], 3);
+    assert_eq!(body["choices"][0]["finish_reason"], "length_capped");
+
+    let response = request(2, false);
+    assert_eq!(response.header("x-max-tokens-applied"), None);
+    let body: Value = response.into_json().unwrap();
+    assert_eq!(body["choices"][0]["finish_reason"], "length");
+
+    // The headers of a stream are sent once the generation started, after the cap
+    let response = request(50, true);
+    assert_eq!(response.header("x-max-tokens-applied"), Some("3"));
+    let finish_reasons: Vec<Value> = response
+        .into_string()
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter_map(|data| serde_json::from_str::<Value>(data).ok())
+        .map(|chunk| chunk["choices"][0]["finish_reason"].clone())
+        .filter(|reason| !reason.is_null())
+        .collect();
+    assert_eq!(finish_reasons, vec![json!("length_capped")]);
+}
+
+#[test]
+fn files() {
+    let server = Server::start("files");
+    let content = "{\"prompt\": \"the cat\"}\n";
+    let (status, file) = server.upload(
+        "/v1/files",
+        &[("purpose", "batch")],
+        ("prompts.jsonl", content),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(file["object"], "file");
+    assert_eq!(file["filename"], "prompts.jsonl");
+    assert_eq!(file["purpose"], "batch");
+    assert_eq!(file["bytes"].as_u64(), Some(content.len() as u64));
+    let id = file["id"].as_str().unwrap();
+
+    let (status, files) = server.json("GET", "/v1/files", None);
+    assert_eq!(status, 200);
+    assert_eq!(files["data"][0]["id"], id);
+
+    let (status, retrieved) = server.json("GET", &format!("/v1/files/{id}"), None);
+    assert_eq!(status, 200);
+    assert_eq!(retrieved, file);
+
+    let downloaded = ureq::get(&server.url(&format!("/v1/files/{id}/content")))
+        .call()
+        .expect("the content is returned")
+        .into_string()
+        .unwrap();
+    assert_eq!(downloaded, content);
+
+    let (status, deleted) = server.json("DELETE", &format!("/v1/files/{id}"), None);
+    assert_eq!(status, 200);
+    assert_eq!(
+        deleted,
+        json!({ "id": id, "object": "file", "deleted": true })
+    );
+
+    let (status, error) = server.json("GET", &format!("/v1/files/{id}"), None);
+    assert_eq!(status, 404);
+    assert_eq!(error["error"]["param"], "id");
+}
+
+#[test]
+fn classifications() {
+    let server = Server::start("classifications");
+
+    let (status, body) = server.json(
+        "POST",
+        "/v1/classifications",
+        Some(
+            json!({ "model": MODEL, "input": "the cat sat on the mat", "labels": ["cat", "dog"] }),
+        ),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(body["object"], "classification");
+    let scores = body["scores"].as_array().unwrap();
+    assert_eq!(scores.len(), 2);
+    assert_eq!(body["label"], scores[0]["label"]);
+    let total: f64 = scores
+        .iter()
+        .map(|score| score["probability"].as_f64().unwrap())
+        .sum();
+    assert!((total - 1.0).abs() < 1e-4);
+    assert!(body["usage"]["prompt_tokens"]
+        .as_u64()
+        .is_some_and(|tokens| tokens > 0));
+
+    // Both labels are unknown words, so they start with the same token
+    let (status, error) = server.json(
+        "POST",
+        "/v1/classifications",
+        Some(json!({ "model": MODEL, "input": "hello", "labels": ["yes", "no"] })),
+    );
+    assert_eq!(status, 400);
+    assert_eq!(error["error"]["param"], "labels");
+}
+
+#[test]
+fn azure_deployments() {
+    let server = Server::start_with(
+        "azure",
+        json!({ "azure": { "deployments": { "chat-deployment": MODEL } } }),
+    );
+    let messages = json!([{ "role": "user", "content": "hello world" }]);
+    let body = json!({ "messages": messages, "max_tokens": 4 });
+
+    for deployment in [MODEL, "chat-deployment"] {
+        let (status, completion) = server.json(
+            "POST",
+            &format!("/openai/deployments/{deployment}/chat/completions?api-version=2024-06-01"),
+            Some(body.clone()),
+        );
+        assert_eq!(status, 200);
+        assert_eq!(completion["object"], "chat.completion");
+    }
+
+    let (status, completion) = server.json(
+        "POST",
+        "/openai/deployments/chat-deployment/completions?api-version=2024-06-01",
+        Some(json!({ "prompt": "the cat", "max_tokens": 4 })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(completion["object"], "text_completion");
+
+    // Deployments reach the embeddings endpoint, disabled without an embedding model
+    let (status, error) = server.json(
+        "POST",
+        "/openai/deployments/chat-deployment/embeddings?api-version=2024-06-01",
+        Some(json!({ "input": "the cat" })),
+    );
+    assert_eq!(status, 404);
+    assert_eq!(error["error"]["code"], "model_not_found");
+
+    let (status, error) = server.json(
+        "POST",
+        "/openai/deployments/chat-deployment/chat/completions",
+        Some(body.clone()),
+    );
+    assert_eq!(status, 400);
+    assert_eq!(error["error"]["param"], "api-version");
+
+    let (status, error) = server.json(
+        "POST",
+        "/openai/deployments/unknown/chat/completions?api-version=2024-06-01",
+        Some(body),
+    );
+    assert_eq!(status, 404);
+    assert_eq!(error["error"]["code"], "DeploymentNotFound");
+}
+
+#[test]
+fn admin_endpoints() {
+    let server = Server::start("admin-disabled");
+    let (status, _) = server.json("GET", "/admin/log-level", None);
+    assert_eq!(status, 403);
+    drop(server);
+
+    let audit = test_directory("admin").join("audit.sqlite");
+    let cache = test_directory("admin").join("hub");
+    let server = Server::start_with(
+        "admin",
+        json!({
+            "admin": { "api_key": "admin-key" },
+            "audit": { "database": audit },
+            "hub": { "cache_dir": cache, "probe_timeout_secs": 1, "min_free_disk_mb": 0 },
+        }),
+    );
+    let admin = Some("admin-key");
+
+    let (status, error) = server.json_as(Some("wrong-key"), "GET", "/admin/log-level", None);
+    assert_eq!(status, 401);
+    assert_eq!(error["error"]["code"], "invalid_api_key");
+
+    let (status, level) = server.json_as(admin, "GET", "/admin/log-level", None);
+    assert_eq!(status, 200);
+    assert!(level["level"].is_string());
+
+    let (status, level) = server.json_as(
+        admin,
+        "PUT",
+        "/admin/log-level",
+        Some(json!({ "level": "debug" })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(level["level"], "debug");
+
+    let (status, log) = server.json_as(admin, "GET", "/admin/audit", None);
+    assert_eq!(status, 200);
+    let actions: Vec<(&str, &str)> = log["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            (
+                entry["action"].as_str().unwrap(),
+                entry["outcome"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            ("PUT /admin/log-level", "success"),
+            ("GET /admin/log-level", "unauthorized"),
+        ]
+    );
+
+    let (status, cached) = server.json_as(admin, "GET", "/admin/cache", None);
+    assert_eq!(status, 200);
+    assert_eq!(cached["cache_dir"], cache.display().to_string());
+    assert_eq!(cached["data"], json!([]));
+
+    let (status, purged) = server.json_as(admin, "DELETE", "/admin/cache?dry_run=true", None);
+    assert_eq!(status, 200);
+    assert_eq!(
+        purged,
+        json!({ "removed": [], "freed_bytes": 0, "dry_run": true })
+    );
+
+    // The Hub may not be reachable from the test, the cache always is
+    let (status, dependencies) = server.json("GET", "/v1/health/dependencies", None);
+    assert_eq!(status, 200);
+    assert_eq!(dependencies["cache"]["writable"], true);
+    assert_eq!(dependencies["disk"]["sufficient"], true);
+    let expected = match dependencies["hub"]["reachable"].as_bool() {
+        Some(true) => "ok",
+        _ => "degraded",
+    };
+    assert_eq!(dependencies["status"], expected);
+
+    let config_path = server.directory.join("config.json");
+    let mut config: Value =
+        serde_json::from_str(&std::fs::read_to_string(&config_path).unwrap()).unwrap();
+    config["models"][MODEL]["defaults"]["max_tokens"] = json!(2);
+    std::fs::write(&config_path, config.to_string()).unwrap();
+    let (status, reloaded) = server.json_as(admin, "POST", "/admin/reload", None);
+    assert_eq!(status, 200);
+    assert_eq!(reloaded["object"], "config.reload");
+    assert!(reloaded["reloaded"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("models")));
+
+    let (status, completion) = server.json(
+        "POST",
+        "/v1/completions",
+        Some(json!({ "model": MODEL, "prompt": "the cat", "ignore_eos": true })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(completion["usage"]["completion_tokens"], 2);
+
+    // An invalid file leaves the configuration in effect
+    std::fs::write(&config_path, "{").unwrap();
+    let (status, _) = server.json_as(admin, "POST", "/admin/reload", None);
+    assert_eq!(status, 400);
+    let (status, _) = server.json_as(admin, "GET", "/admin/log-level", None);
+    assert_eq!(status, 200);
+}
+
+#[test]
+fn streams_resume_after_the_last_event_id() {
+    let server = Server::start("resume");
+    let request = json!({
+        "model": MODEL,
+        "messages": [{ "role": "user", "content": "hello world" }],
+        "stream": true,
+    });
+    let events = |key: &str, last_event_id: Option<&str>| {
+        let mut call = ureq::post(&server.url("/v1/chat/completions"))
+            .set("Authorization", &format!("Bearer {key}"));
+        if let Some(id) = last_event_id {
+            call = call.set("Last-Event-ID", id);
+        }
+        let body = call
+            .send_json(request.clone())
+            .expect("the stream starts")
+            .into_string()
+            .unwrap();
+        // Every event is an `id:` line followed by a `data:` line
+        let ids: Vec<String> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("id: "))
+            .map(str::to_string)
+            .collect();
+        let data: Vec<String> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(str::to_string)
+            .collect();
+        assert_eq!(ids.len(), data.len());
+        (ids, data)
+    };
+
+    let (ids, data) = events("sk-first", None);
+    assert!(data.len() > 2);
+    assert_eq!(data.last().map(String::as_str), Some("[DONE]"));
+
+    let (resumed_ids, resumed) = events("sk-first", Some(&ids[0]));
+    assert_eq!(resumed_ids, ids[1..]);
+    assert_eq!(resumed, data[1..]);
+
+    // Another key starts a new completion instead
+    let (other_ids, _) = events("sk-second", Some(&ids[0]));
+    let stream_id = |id: &str| id.rsplit_once(':').unwrap().0.to_string();
+    assert_ne!(stream_id(&other_ids[0]), stream_id(&ids[0]));
+}
+
+#[test]
+fn streams_are_filtered_by_the_output_guardrails() {
+    let server = Server::start_with(
+        "guardrails",
+        json!({ "guardrails": { "output": [{ "type": "blocklist", "patterns": ["(?s)."] }] } }),
+    );
+
+    let events = server.events(
+        "/v1/completions",
+        json!({
+            "model": MODEL,
+            "prompt": "the cat",
+            "max_tokens": 8,
+            "ignore_eos": true,
+            "stream": true,
+        }),
+    );
+    let chunks: Vec<Value> = events
+        .iter()
+        .filter_map(|data| serde_json::from_str(data).ok())
+        .collect();
+    // The rejected text is never streamed
+    assert!(chunks.iter().all(|chunk| chunk["choices"][0]["text"]
+        .as_str()
+        .unwrap_or_default()
+        .is_empty()));
+    assert_eq!(
+        chunks
+            .last()
+            .map(|chunk| &chunk["choices"][0]["finish_reason"]),
+        Some(&json!("content_filter"))
+    );
+}
+
+#[test]
+fn conversations() {
+    let database = test_directory("conversations").join("conversations.sqlite");
+    let server = Server::start_with(
+        "conversations",
+        json!({ "conversations": { "database": database } }),
+    );
+
+    let (status, _) = server.json(
+        "POST",
+        "/v1/chat/completions",
+        Some(json!({
+            "model": MODEL,
+            "messages": [{ "role": "user", "content": "hello world" }],
+            "conversation_id": "conv-cats",
+            "max_tokens": 4,
+        })),
+    );
+    assert_eq!(status, 200);
+
+    let (status, conversations) = server.json("GET", "/v1/conversations", None);
+    assert_eq!(status, 200);
+    assert_eq!(conversations["data"][0]["id"], "conv-cats");
+    assert_eq!(conversations["data"][0]["message_count"], 2);
+
+    let (status, conversation) = server.json("GET", "/v1/conversations/conv-cats", None);
+    assert_eq!(status, 200);
+    let roles: Vec<&str> = conversation["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|message| message["role"].as_str().unwrap())
+        .collect();
+    assert_eq!(roles, ["user", "assistant"]);
+
+    // Conversations belong to the key that created them
+    let (status, _) = server.json_as(Some("sk-other"), "GET", "/v1/conversations/conv-cats", None);
+    assert_eq!(status, 404);
+
+    let (status, deleted) = server.json("DELETE", "/v1/conversations/conv-cats", None);
+    assert_eq!(status, 200);
+    assert_eq!(deleted["deleted"], true);
+    let (status, _) = server.json("GET", "/v1/conversations/conv-cats", None);
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn generations() {
+    let server = Server::start("generations");
+
+    let (status, queued) = server.json(
+        "POST",
+        "/v1/generations",
+        Some(json!({
+            "url": "/v1/completions",
+            "body": { "model": MODEL, "prompt": "the cat", "max_tokens": 4 },
+        })),
+    );
+    assert_eq!(status, 202);
+    assert_eq!(queued["object"], "generation");
+    let id = queued["id"].as_str().unwrap();
+
+    let (status, generation) = server.json("GET", &format!("/v1/generations/{id}?wait=30"), None);
+    assert_eq!(status, 200);
+    assert_eq!(generation["status"], "completed");
+    assert_eq!(generation["response"]["object"], "text_completion");
+
+    let (status, _) = server.json_as(
+        Some("sk-other"),
+        "GET",
+        &format!("/v1/generations/{id}"),
+        None,
+    );
+    assert_eq!(status, 404);
+
+    let (status, error) = server.json(
+        "POST",
+        "/v1/generations",
+        Some(json!({ "url": "/v1/embeddings", "body": { "input": "the cat" } })),
+    );
+    assert_eq!(status, 400);
+    assert_eq!(error["error"]["param"], "url");
+}
+
+#[test]
+fn agent_runs() {
+    let server = Server::start_with("agents", json!({ "agents": { "enabled": true } }));
+
+    let (status, run) = server.json(
+        "POST",
+        "/v1/agents/run",
+        Some(json!({
+            "model": MODEL,
+            "messages": [{ "role": "user", "content": "hello world" }],
+            "max_iterations": 1,
+            "max_tokens": 4,
+        })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(run["object"], "agent.run");
+    assert!(["completed", "incomplete"].contains(&run["status"].as_str().unwrap()));
+    assert_eq!(run["iterations"], 1);
+
+    let (status, error) = server.json(
+        "POST",
+        "/v1/agents/run",
+        Some(json!({
+            "model": MODEL,
+            "messages": [{ "role": "user", "content": "hello world" }],
+            "tools": ["unknown_tool"],
+        })),
+    );
+    assert_eq!(status, 400);
+    assert_eq!(error["error"]["param"], "tools.0");
+}
+
+#[test]
+fn assistants_threads_and_runs() {
+    let database = test_directory("assistants").join("assistants.sqlite");
+    let server = Server::start_with(
+        "assistants",
+        json!({ "assistants": { "database": database } }),
+    );
+
+    let (status, assistant) = server.json(
+        "POST",
+        "/v1/assistants",
+        Some(json!({ "model": MODEL, "name": "cats", "instructions": "it is a cat" })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(assistant["object"], "assistant");
+    let assistant_id = assistant["id"].as_str().unwrap();
+    let assistant_path = format!("/v1/assistants/{assistant_id}");
+
+    let (status, assistants) = server.json("GET", "/v1/assistants", None);
+    assert_eq!(status, 200);
+    assert_eq!(assistants["data"][0]["id"], assistant_id);
+
+    let (status, modified) = server.json("POST", &assistant_path, Some(json!({ "name": "dogs" })));
+    assert_eq!(status, 200);
+    assert_eq!(modified["name"], "dogs");
+    let (status, retrieved) = server.json("GET", &assistant_path, None);
+    assert_eq!(status, 200);
+    assert_eq!(retrieved["name"], "dogs");
+
+    let (status, thread) = server.json(
+        "POST",
+        "/v1/threads",
+        Some(json!({ "messages": [{ "role": "user", "content": "hello world" }] })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(thread["object"], "thread");
+    let thread_path = format!("/v1/threads/{}", thread["id"].as_str().unwrap());
+    let (status, _) = server.json("GET", &thread_path, None);
+    assert_eq!(status, 200);
+
+    let (status, message) = server.json(
+        "POST",
+        &format!("{thread_path}/messages"),
+        Some(json!({ "role": "user", "content": "the cat sat on the mat" })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(message["object"], "thread.message");
+
+    let (status, run) = server.json(
+        "POST",
+        &format!("{thread_path}/runs"),
+        Some(json!({ "assistant_id": assistant_id, "max_completion_tokens": 4 })),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(run["object"], "thread.run");
+    let run_path = format!("{thread_path}/runs/{}", run["id"].as_str().unwrap());
+
+    // Runs are executed in the background and polled
+    let started = Instant::now();
+    let run = loop {
+        let (status, run) = server.json("GET", &run_path, None);
+        assert_eq!(status, 200);
+        if !["queued", "in_progress"].contains(&run["status"].as_str().unwrap()) {
+            break run;
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(30),
+            "the run did not end"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert!(["completed", "incomplete"].contains(&run["status"].as_str().unwrap()));
+
+    let (status, runs) = server.json("GET", &format!("{thread_path}/runs"), None);
+    assert_eq!(status, 200);
+    assert_eq!(runs["data"][0]["id"], run["id"]);
+
+    let (status, messages) = server.json("GET", &format!("{thread_path}/messages"), None);
+    assert_eq!(status, 200);
+    let messages = messages["data"].as_array().unwrap();
+    assert_eq!(messages.len(), 3);
+    assert!(messages
+        .iter()
+        .any(|message| message["role"] == "assistant"));
+
+    // Threads and assistants belong to the key that created them
+    let (status, _) = server.json_as(Some("sk-other"), "GET", &thread_path, None);
+    assert_eq!(status, 404);
+
+    let (status, deleted) = server.json("DELETE", &thread_path, None);
+    assert_eq!(status, 200);
+    assert_eq!(deleted["object"], "thread.deleted");
+    let (status, deleted) = server.json("DELETE", &assistant_path, None);
+    assert_eq!(status, 200);
+    assert_eq!(deleted["object"], "assistant.deleted");
+    let (status, _) = server.json("GET", &assistant_path, None);
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn realtime_sessions() {
+    let server = Server::start("realtime");
+
+    let (status, _) = server.websocket("/v1/realtime?model=another-model");
+    assert!(status.contains(" 400 "), "{status}");
+
+    let (status, mut socket) = server.websocket(&format!("/v1/realtime?model={MODEL}"));
+    assert!(status.contains(" 101 "), "{status}");
+    let created = read_frame(&mut socket);
+    assert_eq!(created["type"], "session.created");
+    assert_eq!(created["session"]["model"], MODEL);
+
+    send_frame(
+        socket.get_mut(),
+        json!({
+            "type": "conversation.item.create",
+            "item": {
+                "type": "message",
+                "role": "user",
+                "content": [{ "type": "input_text", "text": "hello world" }],
+            },
+        }),
+    );
+    send_frame(
+        socket.get_mut(),
+        json!({ "type": "response.create", "response": { "max_output_tokens": 4 } }),
+    );
+    let done = loop {
+        let event = read_frame(&mut socket);
+        assert_ne!(event["type"], "error", "{event}");
+        if event["type"] == "response.done" {
+            break event;
+        }
+    };
+    assert!(done["response"]["usage"]["output_tokens"]
+        .as_u64()
+        .is_some_and(|tokens| tokens <= 4));
+}
+
+#[test]
+fn gateway_splits() {
+    let upstream = Server::start("gateway");
+    // The gateway binds the port of the server, so it is served here as `main` does
+    let settings = UpstreamSettings {
+        url: upstream.url.clone(),
+        api_key: None,
+        models: vec![MODEL.to_string()],
+        health_path: None,
+    };
+    let targets = [SplitTargetSettings {
+        model: MODEL.to_string(),
+        weight: 1.0,
+    }];
+    let split = TrafficSplit::new("tiny-split", &targets).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let gateway = runtime.block_on(async {
+        let proxy = ProxyState::new(
+            vec![Arc::new(Upstream::from_settings(&settings))],
+            0,
+            1 << 20,
+        )
+        .unwrap()
+        .with_splits(vec![split]);
+        spawn_health_checks(proxy.clone(), Duration::from_secs(1));
+        let router = Router::new()
+            .route("/v1/models", get(list_upstream_models))
+            .route("/v1/gateway/splits", get(list_split_usage))
+            .fallback(proxy_request)
+            .with_state(proxy);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{address}")
+    });
+
+    // The split is listed once the upstream passed its first health check
+    let started = Instant::now();
+    loop {
+        let models: Value = ureq::get(&format!("{gateway}/v1/models"))
+            .call()
+            .expect("the gateway lists the models")
+            .into_json()
+            .unwrap();
+        if models["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|model| model["id"] == "tiny-split")
+        {
+            break;
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(30),
+            "the upstream never became healthy"
+        );
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let completion: Value = ureq::post(&format!("{gateway}/v1/completions"))
+        .send_json(json!({ "model": "tiny-split", "prompt": "the cat", "max_tokens": 4 }))
+        .expect("the completion is forwarded")
+        .into_json()
+        .unwrap();
+    assert_eq!(completion["object"], "text_completion");
+
+    let splits: Value = ureq::get(&format!("{gateway}/v1/gateway/splits"))
+        .call()
+        .expect("the splits are reported")
+        .into_json()
+        .unwrap();
+    assert_eq!(splits["data"][0]["name"], "tiny-split");
+    let target = &splits["data"][0]["targets"][0];
+    assert_eq!(target["model"], MODEL);
+    assert_eq!(target["requests"], 1);
+    assert_eq!(target["failures"], 0);
+}